@@ -0,0 +1,27 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Embeds the git commit and the build profile, read by the build_info module. Builds outside a git
+/// checkout, such as from a source archive, get "unknown" as their commit.
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    let profile = std::env::var("PROFILE").unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=NETCODE_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=NETCODE_BUILD_PROFILE={}", profile);
+
+    // A missing path would rerun the script on every build, so only files that exist are watched
+    for path in [".git/HEAD", ".git/index"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
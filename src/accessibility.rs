@@ -0,0 +1,278 @@
+use crate::client_events::ClientEvent;
+use crate::constants::{
+    STATUS_CONNECTION_RATE_LIMIT, STATUS_ERROR_RATE_LIMIT, STATUS_NETWORK_RATE_LIMIT, STATUS_TESTING_RATE_LIMIT,
+};
+use crate::warnings::WarningChange;
+
+use std::collections::HashMap;
+
+/// The parts of the client state that are announced to screen readers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientStatus {
+    pub is_connected: bool,
+    pub is_testing: bool,
+    pub delay_ms: i32,
+    pub jitter_ms: i32,
+    pub packet_loss: i32,
+    pub high_prediction_error: bool,
+}
+
+/// Fields of the ClientStatus that are announced and rate limited separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum StatusField {
+    Connection,
+    Testing,
+    Network,
+    PredictionError,
+}
+
+/// Implementation of the StatusField
+impl StatusField {
+    const ALL: [StatusField; 4] = [
+        StatusField::Connection,
+        StatusField::Testing,
+        StatusField::Network,
+        StatusField::PredictionError,
+    ];
+
+    /// Minimum number of seconds between two announcements of this field
+    fn rate_limit(self) -> f64 {
+        match self {
+            StatusField::Connection => STATUS_CONNECTION_RATE_LIMIT as f64,
+            StatusField::Testing => STATUS_TESTING_RATE_LIMIT as f64,
+            StatusField::Network => STATUS_NETWORK_RATE_LIMIT as f64,
+            StatusField::PredictionError => STATUS_ERROR_RATE_LIMIT as f64,
+        }
+    }
+
+    /// Checks if this field differs between two statuses
+    fn changed(self, old: &ClientStatus, new: &ClientStatus) -> bool {
+        match self {
+            StatusField::Connection => old.is_connected != new.is_connected,
+            StatusField::Testing => old.is_testing != new.is_testing,
+            StatusField::Network => {
+                old.delay_ms != new.delay_ms || old.jitter_ms != new.jitter_ms || old.packet_loss != new.packet_loss
+            }
+            StatusField::PredictionError => old.high_prediction_error != new.high_prediction_error,
+        }
+    }
+
+    /// Copies this field from one status into another
+    fn copy(self, from: &ClientStatus, to: &mut ClientStatus) {
+        match self {
+            StatusField::Connection => to.is_connected = from.is_connected,
+            StatusField::Testing => to.is_testing = from.is_testing,
+            StatusField::Network => {
+                to.delay_ms = from.delay_ms;
+                to.jitter_ms = from.jitter_ms;
+                to.packet_loss = from.packet_loss;
+            }
+            StatusField::PredictionError => to.high_prediction_error = from.high_prediction_error,
+        }
+    }
+
+    /// Describes the field as a plain sentence
+    fn describe(self, status: &ClientStatus) -> String {
+        match self {
+            StatusField::Connection if status.is_connected => "Connected to the server.".to_string(),
+            StatusField::Connection => "Disconnected from the server.".to_string(),
+            StatusField::Testing if status.is_testing => "Performance test started.".to_string(),
+            StatusField::Testing => "Performance test finished.".to_string(),
+            StatusField::Network => format!(
+                "Network delay is now {} milliseconds, jitter is {} milliseconds and packet loss is {} percent.",
+                status.delay_ms, status.jitter_ms, status.packet_loss
+            ),
+            StatusField::PredictionError if status.high_prediction_error => "Prediction error is high.".to_string(),
+            StatusField::PredictionError => "Prediction error is back to normal.".to_string(),
+        }
+    }
+}
+
+/// Turns client status changes into plain sentences for screen readers.
+/// Only transitions are announced, and each field has its own rate limit.
+pub struct StatusAnnouncer {
+    announced: Option<ClientStatus>,
+    last_announced_at: HashMap<StatusField, f64>,
+}
+
+/// Implementation of the StatusAnnouncer
+impl StatusAnnouncer {
+    /// Creates a new StatusAnnouncer with nothing announced yet
+    pub fn new() -> Self {
+        Self {
+            announced: None,
+            last_announced_at: HashMap::new(),
+        }
+    }
+
+    /// Compares the status with what was last announced and returns the sentences to print.
+    /// A change that is rate limited is announced later if it is still present.
+    pub fn update(&mut self, status: ClientStatus, current_time: f64) -> Vec<String> {
+        // The first status is only a baseline
+        let Some(announced) = self.announced.as_mut() else {
+            self.announced = Some(status);
+            return Vec::new();
+        };
+
+        let mut messages = Vec::new();
+        for field in StatusField::ALL {
+            if !field.changed(announced, &status) {
+                continue;
+            }
+
+            // Skip if the field was announced too recently
+            if let Some(last) = self.last_announced_at.get(&field) {
+                if current_time - last < field.rate_limit() {
+                    continue;
+                }
+            }
+
+            messages.push(field.describe(&status));
+            field.copy(&status, announced);
+            self.last_announced_at.insert(field, current_time);
+        }
+        messages
+    }
+}
+
+/// Default implementation for the StatusAnnouncer
+impl Default for StatusAnnouncer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Describes a client event as a plain sentence for screen readers, None for events that aren't announced.
+/// Connection changes are left to the StatusAnnouncer, which rate limits them.
+pub fn describe_event(event: &ClientEvent) -> Option<String> {
+    match event {
+        ClientEvent::PlayerJoined { .. } => Some("A player joined.".to_string()),
+        ClientEvent::PlayerLeft { .. } => Some("A player left.".to_string()),
+        ClientEvent::Warning(WarningChange::Raised { metric, value, .. }) => {
+            Some(format!("Warning: {}, {}.", metric.label(), metric.format_value(*value)))
+        }
+        ClientEvent::Warning(WarningChange::Cleared { metric, .. }) => Some(format!("Warning cleared: {}.", metric.label())),
+        ClientEvent::PredictionDisabled => Some("Prediction turned off, your player follows the server.".to_string()),
+        _ => None,
+    }
+}
+
+/// Tests for the StatusAnnouncer
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::warnings::Metric;
+
+    use uuid::Uuid;
+
+    // Helper function to create a connected, idle status
+    fn status() -> ClientStatus {
+        ClientStatus {
+            is_connected: true,
+            is_testing: false,
+            delay_ms: 0,
+            jitter_ms: 0,
+            packet_loss: 0,
+            high_prediction_error: false,
+        }
+    }
+
+    #[test]
+    fn test_first_update_is_baseline() {
+        let mut announcer = StatusAnnouncer::new();
+        assert!(announcer.update(status(), 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_unchanged_status_is_silent() {
+        let mut announcer = StatusAnnouncer::new();
+        announcer.update(status(), 0.0);
+
+        // Calling every frame with the same status prints nothing
+        for frame in 1..100 {
+            assert!(announcer.update(status(), frame as f64 / 60.0).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_transition_is_announced_once() {
+        let mut announcer = StatusAnnouncer::new();
+        announcer.update(status(), 0.0);
+
+        let disconnected = ClientStatus { is_connected: false, ..status() };
+        assert_eq!(announcer.update(disconnected, 1.0), vec!["Disconnected from the server.".to_string()]);
+        assert!(announcer.update(disconnected, 2.0).is_empty());
+
+        assert_eq!(announcer.update(status(), 3.0), vec!["Connected to the server.".to_string()]);
+    }
+
+    #[test]
+    fn test_rate_limit_delays_announcement() {
+        let mut announcer = StatusAnnouncer::new();
+        announcer.update(status(), 0.0);
+
+        let high_error = ClientStatus { high_prediction_error: true, ..status() };
+        assert_eq!(announcer.update(high_error, 1.0).len(), 1);
+
+        // Changing back within the rate limit is not announced yet
+        assert!(announcer.update(status(), 1.5).is_empty());
+
+        // Once the limit has passed, the pending change is announced
+        let later = 1.0 + STATUS_ERROR_RATE_LIMIT as f64;
+        assert_eq!(announcer.update(status(), later), vec!["Prediction error is back to normal.".to_string()]);
+    }
+
+    #[test]
+    fn test_flapping_within_rate_limit_is_silent() {
+        let mut announcer = StatusAnnouncer::new();
+        announcer.update(status(), 0.0);
+
+        let high_error = ClientStatus { high_prediction_error: true, ..status() };
+        announcer.update(high_error, 1.0);
+
+        // Flapping back and forth ends on the announced value, so nothing is printed
+        announcer.update(status(), 1.2);
+        announcer.update(high_error, 1.4);
+        assert!(announcer.update(high_error, 10.0).is_empty());
+    }
+
+    #[test]
+    fn test_fields_are_limited_independently() {
+        let mut announcer = StatusAnnouncer::new();
+        announcer.update(status(), 0.0);
+
+        let testing = ClientStatus { is_testing: true, ..status() };
+        assert_eq!(announcer.update(testing, 1.0), vec!["Performance test started.".to_string()]);
+
+        // A different field can be announced right away
+        let lossy = ClientStatus { packet_loss: 5, delay_ms: 100, jitter_ms: 20, ..testing };
+        assert_eq!(
+            announcer.update(lossy, 1.1),
+            vec!["Network delay is now 100 milliseconds, jitter is 20 milliseconds and packet loss is 5 percent.".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_multiple_changes_in_one_update() {
+        let mut announcer = StatusAnnouncer::new();
+        announcer.update(status(), 0.0);
+
+        let changed = ClientStatus { is_connected: false, is_testing: true, ..status() };
+        let messages = announcer.update(changed, 1.0);
+        assert_eq!(messages.len(), 2);
+        assert!(messages.contains(&"Disconnected from the server.".to_string()));
+        assert!(messages.contains(&"Performance test started.".to_string()));
+    }
+
+    #[test]
+    fn test_events_are_described() {
+        assert_eq!(describe_event(&ClientEvent::PlayerJoined { id: Uuid::from_u128(2) }), Some("A player joined.".to_string()));
+        let raised = WarningChange::Raised { metric: Metric::Loss, value: 0.09, threshold: 0.05 };
+        assert_eq!(describe_event(&ClientEvent::Warning(raised)), Some("Warning: Packet loss, 9%.".to_string()));
+        let cleared = WarningChange::Cleared { metric: Metric::Loss, value: 0.01, threshold: 0.05 };
+        assert_eq!(describe_event(&ClientEvent::Warning(cleared)), Some("Warning cleared: Packet loss.".to_string()));
+
+        // Corrections happen too often to be read out
+        assert_eq!(describe_event(&ClientEvent::SnapCorrection { magnitude: 40.0 }), None);
+    }
+}
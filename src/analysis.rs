@@ -1,54 +1,216 @@
-use crate::constants::{TEST_DURATION};
-use crate::types::NetworkCondition;
-
+use crate::build_info::BuildInfo;
+use crate::constants::{ANALYSIS_REPORT_BUCKET_MS, HEATMAP_COLUMNS, HEATMAP_ROWS, TEST_DURATION, TICK_CADENCE_TOLERANCE, TRAFFIC_RATE_WINDOW};
+use crate::heatmap::{ErrorHeatmap, HeatmapExport};
+use crate::latency::{LatencyBreakdown, LatencyBudget, LatencyStage};
+use crate::overlap::{OverlapCounter, OverlapPair};
+use crate::profiler::{FrameProfile, ProfileTotals, ScopeStats};
+use crate::streaming_stats::{Capture, StreamingStats};
+use crate::tick_cadence::CadenceStatus;
+use crate::traffic::{text_bar, TrafficRate};
+use crate::types::{NetworkCondition, Position};
+
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
 
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█']; // Sparkline bars from low to high
+
+/// A value recorded during a test, with the time since the test condition started
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedSample {
+    pub elapsed_ms: u64,
+    pub value: f32,
+}
+
+/// The series of values recorded during a test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Series {
+    PredictionError, // Distance between the predicted and the server position
+    Rtt,             // Round trip time measured by the server, in milliseconds
+    Starvation,      // Share of remote players whose interpolation buffer ran dry
+    TickCadence,     // Observed time per server tick as a share of the reported tick interval
+}
+
+/// Implementation of the Series
+impl Series {
+    pub const ALL: [Series; 4] = [Series::PredictionError, Series::Rtt, Series::Starvation, Series::TickCadence];
+
+    /// Name of the series in reports and exports
+    pub fn name(self) -> &'static str {
+        match self {
+            Series::PredictionError => "prediction_error",
+            Series::Rtt => "rtt_ms",
+            Series::Starvation => "starvation",
+            Series::TickCadence => "tick_cadence",
+        }
+    }
+
+    /// Top of the histogram range of the series, larger values only count towards the exact aggregates
+    pub fn histogram_max(self) -> f64 {
+        match self {
+            Series::PredictionError => 200.0,
+            Series::Rtt => 2000.0,
+            Series::Starvation => 1.0,
+            Series::TickCadence => 4.0,
+        }
+    }
+}
+
 /// Represents performance metrics for a network condition
 pub struct PerformanceMetrics {
     pub avg_prediction_error: f32,
+    pub p95_prediction_error: f32, // Estimated from the histogram
     pub max_prediction_error: f32,
     pub reconciliation_count: u32,
     pub input_lag_ms: i32,
+    pub jitter_ms: i32,
+    pub packet_loss_percent: i32,
+    pub duplicate_percent: i32,
+    pub series: HashMap<Series, StreamingStats>, // Aggregates without the warmup, and the kept samples including it
+    pub latency: LatencyBudget, // Where the time from input to screen went, without the warmup
+    pub traffic: Vec<TrafficRate>, // Bandwidth per message category over the last seconds of the test
+    pub heatmap: ErrorHeatmap, // Where on the board the prediction errors were measured, without the warmup
+    pub overlaps: OverlapCounter, // Frames each pair of players was drawn overlapping, without the warmup
+    pub profile: ProfileTotals, // Frame time by profiled scope, without the warmup
+}
+
+/// Metrics of one tested condition in the JSON export and the regression check
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConditionSummary {
+    pub condition: String,
+    pub latency_ms: i32,
+    pub jitter_ms: i32,
+    pub packet_loss_percent: i32,
+    #[serde(default)]
+    pub duplicate_percent: i32,        // Missing from summaries written before duplication was simulated
+    pub avg_prediction_error: f32,
+    pub p95_prediction_error: f32,
+    pub max_prediction_error: f32,
+    pub reconciliation_count: u32,
+    pub avg_rtt_ms: Option<f64>,       // None if no round trip time was measured
+    pub avg_starvation: Option<f64>,   // None without remote players
+    pub avg_latency_ms: Option<f64>,   // Average input to screen latency, None if no input was acknowledged
+    #[serde(default)]
+    pub avg_compensated_latency_ms: Option<f64>, // The average latency without the expected half tick of waiting for a tick
+    #[serde(default)]
+    pub avg_tick_cadence: Option<f64>, // Observed time per tick as a share of the reported interval, None if not measured
+    #[serde(default)]
+    pub traffic: Vec<TrafficRate>,     // Bandwidth per message category at the end of the test, empty if not measured
+    #[serde(default)]
+    pub heatmap: Option<HeatmapExport>, // Mean prediction error per cell of the board, None if no position was recorded
+    #[serde(default)]
+    pub overlap_frames: u64,           // Frames summed over every pair of players drawn overlapping
+    #[serde(default)]
+    pub overlapping_pairs: usize,      // Pairs of players drawn overlapping at least once
+    #[serde(default)]
+    pub max_pair_overlap_frames: u64,  // Frames of the pair that overlapped longest
+    #[serde(default)]
+    pub profile: Vec<ScopeStats>,      // Frame time by profiled scope, the most expensive first, empty if not profiled
+}
+
+/// The JSON export: the summaries of the tested conditions, in the order they ran
+#[derive(Debug, Serialize)]
+struct JsonExport<'a> {
+    instance: &'a str,
+    client_build: &'a BuildInfo,
+    server_build: Option<&'a BuildInfo>, // Null if the server didn't send its build
+    conditions: Vec<ConditionSummary>,
+}
+
+/// The network conditions of the performance tests, in the order they run
+pub fn default_conditions() -> Vec<NetworkCondition> {
+    vec![
+        NetworkCondition { latency_ms: 200, jitter_ms: 50, packet_loss_percent: 10, duplicate_percent: 0, name: "Very Poor".to_string() },
+        NetworkCondition { latency_ms: 100, jitter_ms: 30, packet_loss_percent: 5, duplicate_percent: 0, name: "Lossy".to_string() },
+        NetworkCondition { latency_ms: 200, jitter_ms: 40, packet_loss_percent: 0, duplicate_percent: 0, name: "Poor".to_string() },
+        NetworkCondition { latency_ms: 100, jitter_ms: 10, packet_loss_percent: 0, duplicate_percent: 0, name: "Average".to_string() },
+        NetworkCondition { latency_ms: 50, jitter_ms: 5, packet_loss_percent: 0, duplicate_percent: 0, name: "Good".to_string() },
+        NetworkCondition { latency_ms: 0, jitter_ms: 0, packet_loss_percent: 0, duplicate_percent: 0, name: "Ideal".to_string() },
+        // Same mean latency as Average with more jitter, to see how the error scales with jitter
+        NetworkCondition { latency_ms: 100, jitter_ms: 0, packet_loss_percent: 0, duplicate_percent: 0, name: "Steady".to_string() },
+        NetworkCondition { latency_ms: 100, jitter_ms: 40, packet_loss_percent: 0, duplicate_percent: 0, name: "Jittery".to_string() },
+        NetworkCondition { latency_ms: 100, jitter_ms: 80, packet_loss_percent: 0, duplicate_percent: 0, name: "Very Jittery".to_string() },
+        // Average with a fifth of the packets delivered twice, the dedupe should keep the error at Average's
+        NetworkCondition { latency_ms: 100, jitter_ms: 10, packet_loss_percent: 0, duplicate_percent: 20, name: "Duplicated".to_string() },
+    ]
 }
 
 /// Analyzes performance metrics under different network conditions
 pub struct PerformanceAnalyzer {
     conditions: Vec<NetworkCondition>,
+    test_duration: Duration, // How long each condition is tested
     results: HashMap<String, PerformanceMetrics>,
     current_condition: Option<NetworkCondition>,
     current_index: usize,
-    samples: Vec<f32>,
+    samples: HashMap<Series, StreamingStats>,
+    capture: Capture, // How many samples of each series are kept, a bounded sample unless every one is asked for
     start_time: Instant,
+    warmup: Duration, // Samples this soon after a condition change are left out of the metrics
+    latency: LatencyBudget,
+    traffic: Vec<TrafficRate>, // Latest bandwidth per message category of the current test
+    heatmap: ErrorHeatmap, // Where the prediction errors of the current test were measured
+    overlaps: OverlapCounter, // Frames each pair of players was drawn overlapping in the current test
+    profile: ProfileTotals, // Frame time by profiled scope in the current test
+    client_build: BuildInfo, // Build and simulation seed of the client that ran the tests
+    server_build: Option<BuildInfo>, // Build and spawn seed of the server it tested against, None if unknown
 }
 
 /// Implementation of the PerformanceAnalyzer
 impl PerformanceAnalyzer {
     /// Creates a new PerformanceAnalyzer with predefined network conditions for testing
     pub fn new(_sample_duration: Duration) -> Self {
+        Self::with_conditions(default_conditions(), TEST_DURATION)
+    }
+
+    /// Creates a PerformanceAnalyzer testing each of the conditions for the duration, in order
+    pub fn with_conditions(conditions: Vec<NetworkCondition>, test_duration: Duration) -> Self {
         Self {
-            conditions: vec![
-                NetworkCondition { latency_ms: 200, packet_loss_percent: 10, name: "Very Poor".to_string() },
-                NetworkCondition { latency_ms: 100, packet_loss_percent: 5, name: "Lossy".to_string() },
-                NetworkCondition { latency_ms: 200, packet_loss_percent: 0, name: "Poor".to_string() },
-                NetworkCondition { latency_ms: 100, packet_loss_percent: 0, name: "Average".to_string() },
-                NetworkCondition { latency_ms: 50, packet_loss_percent: 0, name: "Good".to_string() },
-                NetworkCondition { latency_ms: 0, packet_loss_percent: 0, name: "Ideal".to_string() },
-            ],
+            conditions,
+            test_duration,
             results: HashMap::new(),
             current_condition: None,
             current_index: 0,
-            samples: Vec::new(),
+            samples: HashMap::new(),
+            capture: Capture::default(),
             start_time: Instant::now(),
+            warmup: Duration::ZERO,
+            latency: LatencyBudget::new(),
+            traffic: Vec::new(),
+            heatmap: ErrorHeatmap::new(HEATMAP_COLUMNS, HEATMAP_ROWS),
+            overlaps: OverlapCounter::new(),
+            profile: ProfileTotals::new(),
+            client_build: BuildInfo::current(None),
+            server_build: None,
         }
     }
 
+    /// Sets the builds of both sides, written into the header of the report and of every export
+    pub fn set_builds(&mut self, client: BuildInfo, server: Option<BuildInfo>) {
+        self.client_build = client;
+        self.server_build = server;
+    }
+
+    /// Leaves samples recorded within the warmup after a condition change out of the metrics
+    pub fn set_warmup(&mut self, warmup: Duration) {
+        self.warmup = warmup;
+    }
+
+    /// Sets how many samples of each series are kept from the next test on. The aggregates in the
+    /// report are exact either way, Capture::Full keeps every sample for the CSV export of short tests.
+    pub fn set_capture(&mut self, capture: Capture) {
+        self.capture = capture;
+    }
+
     /// Creates a new PerformanceAnalyzer with a custom set of network conditions
     pub fn start_next_test(&mut self) -> Option<NetworkCondition> {
         if self.current_index < self.conditions.len() {
             let condition = self.conditions[self.current_index].clone();
             self.current_condition = Some(condition.clone());
             self.samples.clear();
+            self.latency = LatencyBudget::new();
+            self.traffic.clear();
+            self.heatmap.clear();
+            self.overlaps = OverlapCounter::new();
             self.start_time = Instant::now();
             self.current_index += 1;
             Some(condition)
@@ -59,8 +221,61 @@ impl PerformanceAnalyzer {
     
     /// Records a prediction error for the current network condition
     pub fn record_prediction_error(&mut self, error: f32) {
+        self.record(Series::PredictionError, error);
+    }
+
+    /// Records where on the board a prediction error was measured, errors during the warmup are left out
+    pub fn record_error_position(&mut self, position: Position, error: f32) {
+        if self.current_condition.is_some() && self.start_time.elapsed() >= self.warmup {
+            self.heatmap.insert(position, error);
+        }
+    }
+
+    /// Records a value of a series, timestamped with the time since the current test started
+    pub fn record(&mut self, series: Series, value: f32) {
+        let elapsed_ms = self.start_time.elapsed().as_millis() as u64;
+        self.record_at(series, elapsed_ms, value);
+    }
+
+    /// Records a value of a series at the given time since the current test started
+    pub fn record_at(&mut self, series: Series, elapsed_ms: u64, value: f32) {
         if self.current_condition.is_some() {
-            self.samples.push(error);
+            // The warmup is measured in time, so it doesn't depend on how often samples arrive
+            let sample = TimedSample { elapsed_ms, value };
+            let stats = self.samples.entry(series).or_insert_with(|| StreamingStats::new(self.capture, series.histogram_max()));
+            if elapsed_ms >= self.warmup.as_millis() as u64 {
+                stats.record(sample);
+            } else {
+                stats.record_uncounted(sample);
+            }
+        }
+    }
+
+    /// Records where the time of one input went, inputs during the warmup are left out
+    pub fn record_latency(&mut self, breakdown: &LatencyBreakdown) {
+        if self.current_condition.is_some() && self.start_time.elapsed() >= self.warmup {
+            self.latency.record(breakdown);
+        }
+    }
+
+    /// Counts a frame for every pair of players drawn overlapping in it, frames during the warmup are left out
+    pub fn record_overlaps(&mut self, pairs: &[OverlapPair]) {
+        if self.current_condition.is_some() && self.start_time.elapsed() >= self.warmup {
+            self.overlaps.record(pairs);
+        }
+    }
+
+    /// Adds the profiled scopes of a frame, frames during the warmup are left out
+    pub fn record_profile(&mut self, frame: &FrameProfile) {
+        if self.current_condition.is_some() && self.start_time.elapsed() >= self.warmup {
+            self.profile.add(frame);
+        }
+    }
+
+    /// Records the bandwidth per message category of the current test, the latest rates are kept
+    pub fn record_traffic(&mut self, rates: Vec<TrafficRate>) {
+        if self.current_condition.is_some() {
+            self.traffic = rates;
         }
     }
 
@@ -78,44 +293,282 @@ impl PerformanceAnalyzer {
             return false;
         }
 
-        self.start_time.elapsed() >= TEST_DURATION
+        self.start_time.elapsed() >= self.test_duration
     }
 
     /// Completes the current test and calculates performance metrics
     pub fn complete_current_test(&mut self) {
         if let Some(condition) = &self.current_condition {
-            let avg_error = if self.samples.is_empty() {
-                0.0
-            } else {
-                self.samples.iter().sum::<f32>() / self.samples.len() as f32
-            };
-            
-            let max_error = self.samples.iter().fold(0.0_f32, |max, &x| f32::max(max, x));
+            let errors = self.samples.get(&Series::PredictionError);
+            let stats = errors.map(StreamingStats::stats);
+            let avg_error = stats.and_then(|stats| stats.mean()).unwrap_or(0.0);
+            let p95_error = errors.and_then(|errors| errors.percentile(0.95)).unwrap_or(0.0);
+            let max_error = stats.and_then(|stats| stats.max()).unwrap_or(0.0).max(0.0);
 
             self.results.insert(condition.name.clone(), PerformanceMetrics {
-                avg_prediction_error: avg_error,
-                max_prediction_error: max_error,
-                reconciliation_count: self.samples.len() as u32,
+                avg_prediction_error: avg_error as f32,
+                p95_prediction_error: p95_error as f32,
+                max_prediction_error: max_error as f32,
+                reconciliation_count: stats.map_or(0, |stats| stats.count()) as u32,
                 input_lag_ms: condition.latency_ms,
+                jitter_ms: condition.jitter_ms,
+                packet_loss_percent: condition.packet_loss_percent,
+                duplicate_percent: condition.duplicate_percent,
+                series: std::mem::take(&mut self.samples),
+                latency: std::mem::take(&mut self.latency),
+                traffic: std::mem::take(&mut self.traffic),
+                heatmap: std::mem::replace(&mut self.heatmap, ErrorHeatmap::new(HEATMAP_COLUMNS, HEATMAP_ROWS)),
+                overlaps: std::mem::take(&mut self.overlaps),
+                profile: std::mem::take(&mut self.profile),
             });
         }
     }
 
+    /// Summaries of the tested conditions, in the order they ran
+    pub fn summaries(&self) -> Vec<ConditionSummary> {
+        let average = |metrics: &PerformanceMetrics, series: Series| metrics.series.get(&series).and_then(|stats| stats.stats().mean());
+        self.conditions.iter()
+            .filter_map(|condition| Some((condition, self.results.get(&condition.name)?)))
+            .map(|(condition, metrics)| ConditionSummary {
+                condition: condition.name.clone(),
+                latency_ms: metrics.input_lag_ms,
+                jitter_ms: metrics.jitter_ms,
+                packet_loss_percent: metrics.packet_loss_percent,
+                duplicate_percent: metrics.duplicate_percent,
+                avg_prediction_error: metrics.avg_prediction_error,
+                p95_prediction_error: metrics.p95_prediction_error,
+                max_prediction_error: metrics.max_prediction_error,
+                reconciliation_count: metrics.reconciliation_count,
+                avg_rtt_ms: average(metrics, Series::Rtt),
+                avg_starvation: average(metrics, Series::Starvation),
+                avg_latency_ms: (metrics.latency.inputs() > 0).then(|| metrics.latency.total_average()),
+                avg_compensated_latency_ms: metrics.latency.tick_compensated_average(),
+                avg_tick_cadence: average(metrics, Series::TickCadence),
+                traffic: metrics.traffic.clone(),
+                heatmap: (!metrics.heatmap.is_empty()).then(|| metrics.heatmap.export()),
+                overlap_frames: metrics.overlaps.total_frames(),
+                overlapping_pairs: metrics.overlaps.pair_count(),
+                max_pair_overlap_frames: metrics.overlaps.max_frames(),
+                profile: metrics.profile.stats(),
+            })
+            .collect()
+    }
+
+    /// Exports the summaries of the tested conditions as pretty-printed JSON, with the hashed client instance
+    pub fn export_json(&self, instance: &str) -> String {
+        let export = JsonExport { instance, client_build: &self.client_build, server_build: self.server_build.as_ref(), conditions: self.summaries() };
+        serde_json::to_string_pretty(&export).unwrap() + "\n"
+    }
+
+    /// Per-second averages of a series recorded under a condition, None for seconds without samples
+    pub fn per_second_averages(&self, condition: &str, series: Series) -> Option<Vec<Option<f32>>> {
+        let stats = self.results.get(condition)?.series.get(&series)?;
+        Some(bucket_averages(&stats.samples(), 1000))
+    }
+
     /// Returns the results of the performance tests
     pub fn generate_report(&self) -> String {
         let mut report = "# Performance Analysis Report\n\n".to_string();
-        report.push_str("| Network Condition | Avg Error | Max Error | Input Lag |\n");
-        report.push_str("|------------------|-----------|-----------|----------|\n");
+        report.push_str(&format!("Client build: `{}`\n", self.client_build.describe()));
+        report.push_str(&format!("Server build: `{}`\n\n", self.server_build.as_ref().map_or_else(|| "unknown".to_string(), BuildInfo::describe)));
+        report.push_str("| Network Condition | Avg Error | P95 Error | Max Error | Input Lag | Jitter |\n");
+        report.push_str("|------------------|-----------|-----------|-----------|----------|--------|\n");
 
         for (condition, metrics) in &self.results {
-            report.push_str(&format!("| {:<16} | {:>8.2} | {:>8.2} | {:>8} ms |\n",
+            report.push_str(&format!("| {:<16} | {:>8.2} | {:>8.2} | {:>8.2} | {:>8} ms | {:>4} ms |\n",
                      condition,
                      metrics.avg_prediction_error,
+                     metrics.p95_prediction_error,
                      metrics.max_prediction_error,
-                     metrics.input_lag_ms));
+                     metrics.input_lag_ms,
+                     metrics.jitter_ms));
+        }
+
+        for (latency_ms, rows) in self.jitter_series() {
+            report.push_str(&format!("\n## Prediction error by jitter at {} ms\n\n", latency_ms));
+            report.push_str("| Jitter | Avg Error | Max Error |\n");
+            report.push_str("|--------|-----------|-----------|\n");
+            for metrics in rows {
+                report.push_str(&format!("| {:>3} ms | {:>8.2} | {:>8.2} |\n",
+                         metrics.jitter_ms,
+                         metrics.avg_prediction_error,
+                         metrics.max_prediction_error));
+            }
+        }
+
+        // How the error developed during each test, in the order the conditions ran
+        if !self.results.is_empty() {
+            report.push_str(&format!("\n## Prediction error over time ({} ms per bar)\n\n", ANALYSIS_REPORT_BUCKET_MS));
+            for condition in &self.conditions {
+                let Some(metrics) = self.results.get(&condition.name) else {
+                    continue;
+                };
+                let samples = metrics.series.get(&Series::PredictionError).map_or_else(Vec::new, StreamingStats::samples);
+                report.push_str(&format!("{:<16} {}\n", condition.name, sparkline(&bucket_averages(&samples, ANALYSIS_REPORT_BUCKET_MS))));
+            }
+        }
+
+        // Where the time from input to screen went, stages that were never measured are shown as -. The compensated
+        // total leaves out the half tick an input waits on average, so servers with other tick rates compare,
+        // and the residual is how far the tick waits were off that half tick, as mean ± deviation.
+        if self.results.values().any(|metrics| metrics.latency.inputs() > 0) {
+            report.push_str("\n## Latency budget by stage (avg ms)\n\n");
+            let names: Vec<&str> = LatencyStage::ALL.iter().map(|stage| stage.name()).collect();
+            report.push_str(&format!("| Network Condition | {} | Total | Compensated | Tick residual | Inputs |\n", names.join(" | ")));
+            report.push_str(&format!("|------------------|{}-------|-------------|---------------|--------|\n", "------|".repeat(names.len())));
+            for condition in &self.conditions {
+                let Some(metrics) = self.results.get(&condition.name).filter(|metrics| metrics.latency.inputs() > 0) else {
+                    continue;
+                };
+                let stages: Vec<String> = LatencyStage::ALL.iter()
+                    .map(|stage| metrics.latency.average(*stage).map_or("-".to_string(), |ms| format!("{:.1}", ms)))
+                    .collect();
+                let compensated = metrics.latency.tick_compensated_average().map_or("-".to_string(), |ms| format!("{:.1}", ms));
+                let residuals = metrics.latency.tick_residuals();
+                let residual = match (residuals.mean(), residuals.std_dev()) {
+                    (Some(mean), Some(deviation)) => format!("{:+.1} ± {:.1}", mean, deviation),
+                    (Some(mean), None) => format!("{:+.1}", mean),
+                    _ => "-".to_string(),
+                };
+                report.push_str(&format!("| {:<16} | {} | {:.1} | {} | {} | {} |\n", condition.name, stages.join(" | "), metrics.latency.total_average(), compensated, residual, metrics.latency.inputs()));
+            }
+        }
+
+        // Whether the server ticked at the interval it reports, a slower cadence makes the compensation too small
+        if self.results.values().any(|metrics| metrics.series.contains_key(&Series::TickCadence)) {
+            report.push_str(&format!("\n## Server tick cadence (flagged beyond {:.0}% of the reported interval)\n\n", TICK_CADENCE_TOLERANCE * 100.0));
+            report.push_str("| Network Condition | Observed / reported | Status |\n");
+            report.push_str("|------------------|---------------------|--------|\n");
+            for condition in &self.conditions {
+                let Some(ratio) = self.results.get(&condition.name).and_then(|metrics| metrics.series.get(&Series::TickCadence)?.stats().mean()) else {
+                    continue;
+                };
+                report.push_str(&format!("| {:<16} | {:.2} | {} |\n", condition.name, ratio, CadenceStatus::of_ratio(ratio).describe()));
+            }
+        }
+
+        // Players drawn inside each other, which interpolation that lags or overshoots causes more often than play does
+        if self.results.values().any(|metrics| !metrics.overlaps.is_empty()) {
+            report.push_str("\n## Players drawn overlapping\n\n");
+            report.push_str("| Network Condition | Overlap frames | Pairs | Longest pair |\n");
+            report.push_str("|------------------|----------------|-------|--------------|\n");
+            for condition in &self.conditions {
+                let Some(overlaps) = self.results.get(&condition.name).map(|metrics| &metrics.overlaps) else {
+                    continue;
+                };
+                report.push_str(&format!("| {:<16} | {} | {} | {} |\n", condition.name, overlaps.total_frames(), overlaps.pair_count(), overlaps.max_frames()));
+            }
+        }
+
+        // Where the frame time of the client went, to see which scope gets more expensive under which condition
+        if self.results.values().any(|metrics| metrics.profile.frames() > 0) {
+            report.push_str("\n## Frame time by scope\n\n");
+            report.push_str("| Network Condition | Scope | Avg ms | Max ms | Frame % |\n");
+            report.push_str("|------------------|-------|--------|--------|---------|\n");
+            for condition in &self.conditions {
+                let Some(metrics) = self.results.get(&condition.name) else {
+                    continue;
+                };
+                for scope in metrics.profile.stats() {
+                    report.push_str(&format!("| {:<16} | {} | {:.2} | {:.2} | {:.1} |\n", condition.name, scope.name, scope.avg_ms, scope.max_ms, scope.frame_percent));
+                }
+            }
+        }
+
+        // Which messages used the bandwidth, the bar is the share of the busiest category of the condition
+        if self.results.values().any(|metrics| !metrics.traffic.is_empty()) {
+            report.push_str(&format!("\n## Traffic by message category (last {} s of each condition)\n\n", TRAFFIC_RATE_WINDOW));
+            report.push_str("| Network Condition | Category | Up B/s | Down B/s | Up pkt/s | Down pkt/s | Share |\n");
+            report.push_str("|------------------|----------|--------|----------|----------|------------|-------|\n");
+            for condition in &self.conditions {
+                let Some(metrics) = self.results.get(&condition.name) else {
+                    continue;
+                };
+                let busiest = metrics.traffic.iter().map(TrafficRate::total_bytes_per_s).fold(0.0, f64::max);
+                for rate in &metrics.traffic {
+                    report.push_str(&format!("| {:<16} | {} | {:.0} | {:.0} | {:.1} | {:.1} | {} |\n",
+                             condition.name,
+                             rate.category.name(),
+                             rate.up_bytes_per_s,
+                             rate.down_bytes_per_s,
+                             rate.up_packets_per_s,
+                             rate.down_packets_per_s,
+                             text_bar(rate.total_bytes_per_s(), busiest, 10)));
+                }
+            }
         }
         report
     }
+
+    /// Exports the kept samples as CSV, one row per sample, so the series can be plotted together.
+    /// Every row carries the hashed client instance, to join it with the server metrics of the session.
+    /// Comment lines with the builds of the client and the server come before the header.
+    pub fn export_csv(&self, instance: &str) -> String {
+        let mut csv = BuildInfo::csv_comment("client", Some(&self.client_build));
+        csv.push_str(&BuildInfo::csv_comment("server", self.server_build.as_ref()));
+        csv.push_str("instance,condition,series,elapsed_ms,value\n");
+        for condition in &self.conditions {
+            let Some(metrics) = self.results.get(&condition.name) else {
+                continue;
+            };
+            for series in Series::ALL {
+                for sample in metrics.series.get(&series).map_or_else(Vec::new, StreamingStats::samples) {
+                    csv.push_str(&format!("{},{},{},{},{}\n", instance, condition.name, series.name(), sample.elapsed_ms, sample.value));
+                }
+            }
+        }
+        csv
+    }
+
+    /// Groups the results without packet loss or duplication by latency, keeping the latencies tested with more
+    /// than one jitter value. Each group is sorted by jitter.
+    fn jitter_series(&self) -> Vec<(i32, Vec<&PerformanceMetrics>)> {
+        let mut by_latency: HashMap<i32, Vec<&PerformanceMetrics>> = HashMap::new();
+        for metrics in self.results.values().filter(|metrics| metrics.packet_loss_percent == 0 && metrics.duplicate_percent == 0) {
+            by_latency.entry(metrics.input_lag_ms).or_default().push(metrics);
+        }
+
+        let mut series: Vec<(i32, Vec<&PerformanceMetrics>)> = by_latency.into_iter()
+            .filter(|(_, rows)| rows.len() > 1)
+            .collect();
+        for (_, rows) in series.iter_mut() {
+            rows.sort_by_key(|metrics| metrics.jitter_ms);
+        }
+        series.sort_by_key(|(latency_ms, _)| *latency_ms);
+        series
+    }
+}
+
+/// Averages samples in buckets of bucket_ms, starting at zero. Buckets without samples are None.
+pub fn bucket_averages(samples: &[TimedSample], bucket_ms: u64) -> Vec<Option<f32>> {
+    let Some(last) = samples.iter().map(|sample| sample.elapsed_ms).max() else {
+        return Vec::new();
+    };
+    let mut sums = vec![(0.0_f32, 0_u32); (last / bucket_ms) as usize + 1];
+    for sample in samples {
+        let (sum, count) = &mut sums[(sample.elapsed_ms / bucket_ms) as usize];
+        *sum += sample.value;
+        *count += 1;
+    }
+    sums.into_iter()
+        .map(|(sum, count)| (count > 0).then(|| sum / count as f32))
+        .collect()
+}
+
+/// Draws values as a line of bars scaled between the lowest and highest value, gaps are spaces
+pub fn sparkline(values: &[Option<f32>]) -> String {
+    let present = values.iter().flatten();
+    let min = present.clone().fold(f32::INFINITY, |min, &value| min.min(value));
+    let max = present.fold(f32::NEG_INFINITY, |max, &value| max.max(value));
+    let top = SPARK_LEVELS.len() - 1;
+
+    values.iter()
+        .map(|value| match value {
+            None => ' ',
+            Some(_) if max <= min => SPARK_LEVELS[0],
+            Some(value) => SPARK_LEVELS[(((value - min) / (max - min)) * top as f32).round() as usize],
+        })
+        .collect()
 }
 
 /// Tests for the PerformanceAnalyzer
@@ -123,6 +576,7 @@ impl PerformanceAnalyzer {
 mod tests {
     use super::*;
     use std::time::Duration;
+    use uuid::Uuid;
 
     #[test]
     fn test_new_analyzer() {
@@ -130,7 +584,13 @@ mod tests {
         assert_eq!(analyzer.current_index, 0);
         assert!(analyzer.current_condition.is_none());
         assert!(analyzer.samples.is_empty());
-        assert_eq!(analyzer.conditions.len(), 6);
+        assert_eq!(analyzer.conditions.len(), 10);
+
+        // The duplicated condition is Average apart from the duplication, so their errors can be compared
+        let find = |name: &str| analyzer.conditions.iter().find(|condition| condition.name == name).unwrap();
+        let (average, duplicated) = (find("Average"), find("Duplicated"));
+        assert_eq!((duplicated.latency_ms, duplicated.jitter_ms, duplicated.packet_loss_percent), (average.latency_ms, average.jitter_ms, average.packet_loss_percent));
+        assert_eq!((average.duplicate_percent, duplicated.duplicate_percent), (0, 20));
     }
 
     #[test]
@@ -145,7 +605,8 @@ mod tests {
         analyzer.start_next_test();
         analyzer.record_prediction_error(1.0);
         analyzer.record_prediction_error(2.0);
-        assert_eq!(analyzer.samples, vec![1.0, 2.0]);
+        let values: Vec<f32> = analyzer.samples[&Series::PredictionError].samples().iter().map(|sample| sample.value).collect();
+        assert_eq!(values, vec![1.0, 2.0]);
     }
 
     #[test]
@@ -243,4 +704,320 @@ mod tests {
         assert!(report.contains("Very Poor"));
         assert!(report.contains("Lossy"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_report_shows_error_by_jitter() {
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+
+        // Run every condition, with the error growing with the jitter
+        while let Some(condition) = analyzer.start_next_test() {
+            analyzer.record_prediction_error(condition.jitter_ms as f32 / 10.0);
+            analyzer.complete_current_test();
+        }
+
+        let series = analyzer.jitter_series();
+        let latencies: Vec<i32> = series.iter().map(|(latency_ms, _)| *latency_ms).collect();
+        assert_eq!(latencies, vec![100]);
+
+        let jitters: Vec<i32> = series[0].1.iter().map(|metrics| metrics.jitter_ms).collect();
+        assert_eq!(jitters, vec![0, 10, 40, 80]);
+
+        let report = analyzer.generate_report();
+        assert!(report.contains("Prediction error by jitter at 100 ms"));
+        assert!(report.contains("|  80 ms |     8.00 |     8.00 |"));
+    }
+
+    #[test]
+    fn test_report_shows_latency_budget() {
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+        analyzer.start_next_test();
+        analyzer.complete_current_test();
+        assert!(!analyzer.generate_report().contains("Latency budget"));
+
+        // Two inputs under the second condition, one without the server clock. Conditions without inputs are left out.
+        let journey = crate::latency::InputJourney {
+            timing: crate::types::InputTiming { sequence: 1, client_timestamp: 1_000, received_ms: 540, applied_ms: 540 },
+            send_delay_ms: Some(100.0),
+            snapshot_tick_ms: 544.0,
+            snapshot_sent_ms: 544.0,
+            tick_interval_ms: 16.0,
+            received_at_ms: 1_150.0,
+            server_offset_ms: Some(600.0),
+        };
+        analyzer.start_next_test();
+        analyzer.record_latency(&journey.breakdown(1_160.0));
+        analyzer.record_latency(&crate::latency::InputJourney { server_offset_ms: None, ..journey }.breakdown(1_160.0));
+        analyzer.complete_current_test();
+
+        let report = analyzer.generate_report();
+        assert!(report.contains("| Network Condition | client queue | uplink | server queue | tick wait | send wait | downlink | render | Total | Compensated | Tick residual | Inputs |"));
+        assert!(report.contains("| Lossy            | 100.0 | 40.0 | 0.0 | 4.0 | 0.0 | 6.0 | 10.0 | 160.0 | 152.0 | -4.0 ± 0.0 | 2 |"), "{}", report);
+        assert!(!report.contains("| Very Poor        | -"));
+    }
+
+    #[test]
+    fn test_report_and_export_show_traffic() {
+        use crate::traffic::TrafficCategory;
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+        analyzer.record_traffic(vec![TrafficRate { category: TrafficCategory::Ping, up_bytes_per_s: 1.0, down_bytes_per_s: 1.0, up_packets_per_s: 1.0, down_packets_per_s: 1.0 }]);
+        analyzer.start_next_test();
+        analyzer.complete_current_test();
+        assert!(!analyzer.generate_report().contains("Traffic by message category"));
+
+        // The latest rates of the test are kept, the busiest category gets the full bar
+        let rate = |category, up_bytes_per_s, down_bytes_per_s| TrafficRate { category, up_bytes_per_s, down_bytes_per_s, up_packets_per_s: 10.0, down_packets_per_s: 20.0 };
+        analyzer.start_next_test();
+        analyzer.record_traffic(vec![rate(TrafficCategory::Snapshot, 0.0, 1.0)]);
+        analyzer.record_traffic(vec![rate(TrafficCategory::Snapshot, 0.0, 4000.0), rate(TrafficCategory::Input, 900.0, 100.0)]);
+        analyzer.complete_current_test();
+
+        let report = analyzer.generate_report();
+        assert!(report.contains("| Lossy            | Snapshot | 0 | 4000 | 10.0 | 20.0 | ########## |"), "{}", report);
+        assert!(report.contains("| Lossy            | Input | 900 | 100 | 10.0 | 20.0 | ### |"), "{}", report);
+        let summaries = analyzer.summaries();
+        assert!(summaries[0].traffic.is_empty());
+        assert_eq!(summaries[1].traffic[1], rate(TrafficCategory::Input, 900.0, 100.0));
+        let json: serde_json::Value = serde_json::from_str(&analyzer.export_json("00ff00ff00ff00ff")).unwrap();
+        assert_eq!(json["conditions"][1]["traffic"][0]["category"], "snapshot");
+    }
+
+    #[test]
+    fn test_error_positions_are_exported_per_condition() {
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+        analyzer.record_error_position(Position { x: 10, y: 10 }, 50.0);
+        analyzer.start_next_test();
+        analyzer.record_error_position(Position { x: 10, y: 10 }, 2.0);
+        analyzer.record_error_position(Position { x: 20, y: 15 }, 4.0);
+        analyzer.complete_current_test();
+
+        // The next condition starts with an empty grid
+        analyzer.start_next_test();
+        analyzer.complete_current_test();
+        analyzer.set_warmup(Duration::from_secs(60));
+        analyzer.start_next_test();
+        analyzer.record_error_position(Position { x: 10, y: 10 }, 9.0);
+        analyzer.complete_current_test();
+
+        let summaries = analyzer.summaries();
+        let heatmap = summaries[0].heatmap.as_ref().unwrap();
+        assert_eq!((heatmap.columns, heatmap.rows), (HEATMAP_COLUMNS, HEATMAP_ROWS));
+        assert_eq!((heatmap.means[0], heatmap.counts[0]), (Some(3.0), 2));
+        assert_eq!(summaries[1].heatmap, None);
+        assert_eq!(summaries[2].heatmap, None);
+        let json: serde_json::Value = serde_json::from_str(&analyzer.export_json("00ff00ff00ff00ff")).unwrap();
+        assert_eq!(json["conditions"][0]["heatmap"]["means"][0], 3.0);
+    }
+
+    // Helper function to create samples from (elapsed_ms, value) pairs
+    fn timed(samples: &[(u64, f32)]) -> Vec<TimedSample> {
+        samples.iter().map(|&(elapsed_ms, value)| TimedSample { elapsed_ms, value }).collect()
+    }
+
+    #[test]
+    fn test_bucket_averages() {
+        let samples = timed(&[(0, 1.0), (400, 3.0), (999, 2.0), (2100, 8.0), (2900, 4.0)]);
+
+        assert_eq!(bucket_averages(&samples, 1000), vec![Some(2.0), None, Some(6.0)]);
+        assert_eq!(bucket_averages(&samples, 500).len(), 6);
+        assert!(bucket_averages(&[], 1000).is_empty());
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[Some(0.0), Some(7.0), None, Some(3.5)]), "▁█ ▅");
+
+        // Flat series use the lowest bar, empty series give an empty line
+        assert_eq!(sparkline(&[Some(2.0), Some(2.0)]), "▁▁");
+        assert_eq!(sparkline(&[None, None]), "  ");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_warmup_excludes_samples_by_time() {
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+        analyzer.set_warmup(Duration::from_millis(200));
+        analyzer.start_next_test();
+
+        // Many early samples don't push later ones out of the metrics
+        for elapsed_ms in 0..10 {
+            analyzer.record_at(Series::PredictionError, elapsed_ms * 10, 50.0);
+        }
+        analyzer.record_at(Series::PredictionError, 200, 2.0);
+        analyzer.record_at(Series::PredictionError, 600, 4.0);
+        analyzer.complete_current_test();
+
+        let metrics = analyzer.results.get("Very Poor").unwrap();
+        assert_eq!(metrics.avg_prediction_error, 3.0);
+        assert_eq!(metrics.max_prediction_error, 4.0);
+        assert_eq!(metrics.reconciliation_count, 2);
+
+        // The raw series still holds the warmup
+        assert_eq!(metrics.series[&Series::PredictionError].samples().len(), 12);
+    }
+
+    #[test]
+    fn test_long_runs_keep_a_bounded_sample() {
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+        analyzer.start_next_test();
+
+        // An hour of samples at 60 per second
+        let count = 60 * 60 * 60;
+        for i in 0..count {
+            analyzer.record_at(Series::PredictionError, i as u64 * 1000 / 60, (i % 10) as f32);
+        }
+        analyzer.complete_current_test();
+
+        // The aggregates still cover every sample
+        let metrics = analyzer.results.get("Very Poor").unwrap();
+        assert_eq!(metrics.reconciliation_count, count);
+        assert!((metrics.avg_prediction_error - 4.5).abs() < 1e-3);
+        assert_eq!(metrics.max_prediction_error, 9.0);
+        assert!((metrics.p95_prediction_error - 9.5).abs() <= 0.5);
+        assert_eq!(metrics.series[&Series::PredictionError].samples().len(), crate::constants::ANALYSIS_RESERVOIR_SIZE);
+        assert!(analyzer.generate_report().contains("P95 Error"));
+
+        // Every sample is kept on request
+        analyzer.set_capture(Capture::Full);
+        analyzer.start_next_test();
+        for i in 0..10_000 {
+            analyzer.record_at(Series::Rtt, i, 100.0);
+        }
+        analyzer.complete_current_test();
+        assert_eq!(analyzer.results["Lossy"].series[&Series::Rtt].samples().len(), 10_000);
+    }
+
+    #[test]
+    fn test_custom_conditions_and_json_summaries() {
+        let conditions = vec![
+            NetworkCondition { latency_ms: 0, jitter_ms: 0, packet_loss_percent: 0, duplicate_percent: 0, name: "Ideal".to_string() },
+            NetworkCondition { latency_ms: 60, jitter_ms: 5, packet_loss_percent: 1, duplicate_percent: 0, name: "Good".to_string() },
+        ];
+        let mut analyzer = PerformanceAnalyzer::with_conditions(conditions, Duration::ZERO);
+        assert!(!analyzer.is_test_complete());
+
+        analyzer.start_next_test();
+        assert!(analyzer.is_test_complete());
+        analyzer.record_at(Series::PredictionError, 0, 2.0);
+        analyzer.record_at(Series::Rtt, 0, 80.0);
+        analyzer.record_at(Series::Rtt, 10, 100.0);
+        analyzer.complete_current_test();
+        analyzer.start_next_test();
+        analyzer.complete_current_test();
+        assert!(analyzer.start_next_test().is_none());
+
+        // In the order the conditions ran, series that weren't measured are null
+        let summaries = analyzer.summaries();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!((summaries[0].condition.as_str(), summaries[0].avg_prediction_error, summaries[0].avg_rtt_ms), ("Ideal", 2.0, Some(90.0)));
+        assert_eq!((summaries[1].condition.as_str(), summaries[1].reconciliation_count, summaries[1].avg_rtt_ms), ("Good", 0, None));
+
+        let json: serde_json::Value = serde_json::from_str(&analyzer.export_json("00ff00ff00ff00ff")).unwrap();
+        assert_eq!(json["instance"], "00ff00ff00ff00ff");
+        assert_eq!(serde_json::from_value::<BuildInfo>(json["client_build"].clone()).unwrap(), BuildInfo::current(None));
+        assert!(json["server_build"].is_null());
+        assert_eq!(json["conditions"][1]["packet_loss_percent"], 1);
+        assert!(json["conditions"][1]["avg_starvation"].is_null());
+        let parsed: Vec<ConditionSummary> = serde_json::from_value(json["conditions"].clone()).unwrap();
+        assert_eq!(parsed, summaries);
+    }
+
+    #[test]
+    fn test_secondary_series_and_export() {
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+        analyzer.start_next_test();
+        analyzer.record_at(Series::PredictionError, 100, 1.5);
+        analyzer.record_at(Series::Rtt, 100, 210.0);
+        analyzer.record_at(Series::Starvation, 1500, 0.5);
+        analyzer.complete_current_test();
+
+        assert_eq!(analyzer.per_second_averages("Very Poor", Series::Rtt), Some(vec![Some(210.0)]));
+        assert_eq!(analyzer.per_second_averages("Very Poor", Series::Starvation), Some(vec![None, Some(0.5)]));
+        assert_eq!(analyzer.per_second_averages("Ideal", Series::Rtt), None);
+
+        let server = BuildInfo { version: "0.1.0".to_string(), git_hash: "0123456789ab".to_string(), profile: "release".to_string(), seed: Some(31) };
+        analyzer.set_builds(BuildInfo::current(Some(5)), Some(server.clone()));
+        let csv = analyzer.export_csv("00ff00ff00ff00ff");
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(BuildInfo::parse_csv_comment(lines[0]), Some(("client".to_string(), Some(BuildInfo::current(Some(5))))));
+        assert_eq!(BuildInfo::parse_csv_comment(lines[1]), Some(("server".to_string(), Some(server.clone()))));
+        assert_eq!(lines[2..], vec![
+            "instance,condition,series,elapsed_ms,value",
+            "00ff00ff00ff00ff,Very Poor,prediction_error,100,1.5",
+            "00ff00ff00ff00ff,Very Poor,rtt_ms,100,210",
+            "00ff00ff00ff00ff,Very Poor,starvation,1500,0.5",
+        ]);
+
+        let report = analyzer.generate_report();
+        assert!(report.contains("Prediction error over time"));
+        assert!(report.contains(&format!("Server build: `{}`", server.describe())), "{}", report);
+    }
+
+    #[test]
+    fn test_report_flags_an_overloaded_server() {
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+        analyzer.start_next_test();
+        analyzer.record_at(Series::TickCadence, 0, 1.02);
+        analyzer.record_at(Series::TickCadence, 10, 0.98);
+        analyzer.complete_current_test();
+        analyzer.start_next_test();
+        analyzer.record_at(Series::TickCadence, 0, 1.6);
+        analyzer.complete_current_test();
+
+        let report = analyzer.generate_report();
+        assert!(report.contains("## Server tick cadence (flagged beyond 20% of the reported interval)"), "{}", report);
+        assert!(report.contains("| Very Poor        | 1.00 | steady |"), "{}", report);
+        assert!(report.contains("| Lossy            | 1.60 | server overloaded |"), "{}", report);
+        assert!(analyzer.summaries()[1].avg_tick_cadence.is_some_and(|ratio| (ratio - 1.6).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_overlaps_are_counted_per_test_and_pair() {
+        let (a, b, c) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+        analyzer.record_overlaps(&[(a, b)]); // Before any test, not counted
+        analyzer.start_next_test();
+        analyzer.record_overlaps(&[(a, b)]);
+        analyzer.record_overlaps(&[(a, b), (b, c)]);
+        analyzer.complete_current_test();
+        analyzer.start_next_test();
+        analyzer.complete_current_test();
+
+        let summaries = analyzer.summaries();
+        assert_eq!((summaries[0].overlap_frames, summaries[0].overlapping_pairs, summaries[0].max_pair_overlap_frames), (3, 2, 2));
+        assert_eq!((summaries[1].overlap_frames, summaries[1].overlapping_pairs), (0, 0));
+        let report = analyzer.generate_report();
+        assert!(report.contains("## Players drawn overlapping"), "{}", report);
+        assert!(report.contains("| Very Poor        | 3 | 2 | 2 |"), "{}", report);
+        assert!(report.contains("| Lossy            | 0 | 0 | 0 |"), "{}", report);
+    }
+
+    #[test]
+    fn test_profiled_scopes_are_summarized_per_condition() {
+        let mut analyzer = PerformanceAnalyzer::new(Duration::from_secs(1));
+        let frame = |reconcile: f64| FrameProfile { frame_ms: 10.0, scopes: vec![("drawing", 4.0), ("reconciliation", reconcile)] };
+        analyzer.record_profile(&frame(50.0)); // Before any test, not counted
+        analyzer.start_next_test();
+        analyzer.record_profile(&frame(1.0));
+        analyzer.record_profile(&frame(3.0));
+        analyzer.complete_current_test();
+        analyzer.start_next_test();
+        analyzer.complete_current_test();
+
+        let summaries = analyzer.summaries();
+        assert_eq!(summaries[0].profile, vec![
+            ScopeStats { name: "drawing".to_string(), avg_ms: 4.0, max_ms: 4.0, frame_percent: 40.0 },
+            ScopeStats { name: "reconciliation".to_string(), avg_ms: 2.0, max_ms: 3.0, frame_percent: 20.0 },
+        ]);
+        assert!(summaries[1].profile.is_empty());
+        let report = analyzer.generate_report();
+        assert!(report.contains("## Frame time by scope"), "{}", report);
+        assert!(report.contains("| Very Poor        | reconciliation | 2.00 | 3.00 | 20.0 |"), "{}", report);
+
+        // Summaries exported before the profiler read back without scopes
+        let json = serde_json::to_value(&summaries[1]).unwrap();
+        let mut object = json.as_object().unwrap().clone();
+        object.remove("profile");
+        let old: ConditionSummary = serde_json::from_value(serde_json::Value::Object(object)).unwrap();
+        assert!(old.profile.is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+use crate::types::MatchPhase;
+
+/// Screens and modes of the game client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    Menu,         // Not connected, waiting for the player to connect
+    Connecting,   // Connect sent, waiting for the Welcome
+    Playing,      // Connected and controlling the player
+    Testing,      // Running the performance tests while playing
+    Spectating,   // Connected but watching, the camera follows other players and no inputs are sent
+    Results,      // Showing the report of the finished performance tests
+    Disconnected, // Stopped pinging on purpose or removed by the server, waiting for a reconnect
+    Crashed,      // A panic was caught in the frame loop, waiting for a soft reset or quit
+    ShuttingDown, // Quitting, the frame loop ends and the client closes its files and network thread
+}
+
+/// Implementation of the AppState
+impl AppState {
+    /// Checks if the client keeps pinging and receiving from the server in this state
+    pub fn is_connected(&self) -> bool {
+        matches!(self, AppState::Connecting | AppState::Playing | AppState::Testing | AppState::Results | AppState::Spectating)
+    }
+
+    /// Checks if the state sends inputs and runs prediction
+    pub fn drives_input(&self) -> bool {
+        matches!(self, AppState::Playing | AppState::Testing)
+    }
+
+    /// Checks if inputs are sent and predicted this frame, players are frozen in some match phases
+    pub fn sends_input(&self, phase: MatchPhase) -> bool {
+        self.drives_input() && phase.allows_movement()
+    }
+
+    /// Checks if the game world is drawn in this state
+    pub fn shows_world(&self) -> bool {
+        !matches!(self, AppState::Menu | AppState::Crashed | AppState::ShuttingDown)
+    }
+}
+
+/// Something that can make the client change state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppEvent {
+    ToggleConnection, // R
+    StartTests,       // T
+    ToggleSpectate,   // G
+    Back,             // Esc
+    Confirm,          // Enter
+    Welcomed,         // The server accepted the connection
+    Rejected,         // The server refused the connection, such as for a wrong room password
+    TestsFinished,    // Every performance test condition has run
+    Removed,          // The server removed the local player, such as for timing out
+    Crash,            // A panic was caught while running a frame
+    Quit,             // The window was closed
+}
+
+/// Work the client has to do when a transition is taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Connect,          // Send a connect or reconnect and start pinging
+    Disconnect,       // Stop pinging so the server times the session out
+    ForgetPrediction, // Drop the inputs the server will never apply
+    StartTests,       // Reset the analyzer and start the first test condition
+    AbortTests,       // Stop testing and restore the network settings
+    FinishTests,      // Restore the network settings and print the report
+    SoftReset,        // Rebuild the network client and the game world
+    Leave,            // Tell the server we quit, so it removes the player without waiting for the timeout
+    Quit,             // Close the client
+}
+
+/// One row of the transition table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    pub from: AppState,
+    pub event: AppEvent,
+    pub to: AppState,
+    pub effects: &'static [Effect],
+}
+
+/// Every legal transition, anything not listed leaves the state unchanged
+pub const TRANSITIONS: &[Transition] = &[
+    Transition { from: AppState::Menu, event: AppEvent::Confirm, to: AppState::Connecting, effects: &[Effect::Connect] },
+    Transition { from: AppState::Menu, event: AppEvent::ToggleConnection, to: AppState::Connecting, effects: &[Effect::Connect] },
+    Transition { from: AppState::Connecting, event: AppEvent::Welcomed, to: AppState::Playing, effects: &[] },
+    Transition { from: AppState::Connecting, event: AppEvent::Rejected, to: AppState::Menu, effects: &[] },
+    Transition { from: AppState::Connecting, event: AppEvent::ToggleConnection, to: AppState::Disconnected, effects: &[Effect::Disconnect] },
+    Transition { from: AppState::Connecting, event: AppEvent::Back, to: AppState::Menu, effects: &[Effect::Disconnect] },
+    Transition { from: AppState::Playing, event: AppEvent::ToggleConnection, to: AppState::Disconnected, effects: &[Effect::Disconnect] },
+    Transition { from: AppState::Playing, event: AppEvent::StartTests, to: AppState::Testing, effects: &[Effect::StartTests] },
+    Transition { from: AppState::Playing, event: AppEvent::ToggleSpectate, to: AppState::Spectating, effects: &[] },
+    Transition { from: AppState::Spectating, event: AppEvent::ToggleSpectate, to: AppState::Playing, effects: &[] },
+    Transition { from: AppState::Spectating, event: AppEvent::Back, to: AppState::Playing, effects: &[] },
+    Transition { from: AppState::Spectating, event: AppEvent::ToggleConnection, to: AppState::Disconnected, effects: &[Effect::Disconnect] },
+    Transition { from: AppState::Testing, event: AppEvent::TestsFinished, to: AppState::Results, effects: &[Effect::FinishTests] },
+    Transition { from: AppState::Testing, event: AppEvent::Back, to: AppState::Playing, effects: &[Effect::AbortTests] },
+    Transition { from: AppState::Testing, event: AppEvent::ToggleConnection, to: AppState::Disconnected, effects: &[Effect::AbortTests, Effect::Disconnect] },
+    Transition { from: AppState::Results, event: AppEvent::Back, to: AppState::Playing, effects: &[] },
+    Transition { from: AppState::Results, event: AppEvent::StartTests, to: AppState::Testing, effects: &[Effect::StartTests] },
+    Transition { from: AppState::Results, event: AppEvent::ToggleConnection, to: AppState::Disconnected, effects: &[Effect::Disconnect] },
+    Transition { from: AppState::Playing, event: AppEvent::Removed, to: AppState::Disconnected, effects: &[Effect::ForgetPrediction] },
+    Transition { from: AppState::Testing, event: AppEvent::Removed, to: AppState::Disconnected, effects: &[Effect::AbortTests, Effect::ForgetPrediction] },
+    Transition { from: AppState::Spectating, event: AppEvent::Removed, to: AppState::Disconnected, effects: &[Effect::ForgetPrediction] },
+    Transition { from: AppState::Results, event: AppEvent::Removed, to: AppState::Disconnected, effects: &[Effect::ForgetPrediction] },
+    Transition { from: AppState::Disconnected, event: AppEvent::ToggleConnection, to: AppState::Connecting, effects: &[Effect::Connect] },
+    Transition { from: AppState::Disconnected, event: AppEvent::Back, to: AppState::Menu, effects: &[] },
+    Transition { from: AppState::Menu, event: AppEvent::Crash, to: AppState::Crashed, effects: &[] },
+    Transition { from: AppState::Connecting, event: AppEvent::Crash, to: AppState::Crashed, effects: &[] },
+    Transition { from: AppState::Playing, event: AppEvent::Crash, to: AppState::Crashed, effects: &[] },
+    Transition { from: AppState::Testing, event: AppEvent::Crash, to: AppState::Crashed, effects: &[Effect::AbortTests] },
+    Transition { from: AppState::Results, event: AppEvent::Crash, to: AppState::Crashed, effects: &[] },
+    Transition { from: AppState::Spectating, event: AppEvent::Crash, to: AppState::Crashed, effects: &[] },
+    Transition { from: AppState::Disconnected, event: AppEvent::Crash, to: AppState::Crashed, effects: &[] },
+    Transition { from: AppState::Crashed, event: AppEvent::ToggleConnection, to: AppState::Connecting, effects: &[Effect::SoftReset, Effect::Connect] },
+    Transition { from: AppState::Crashed, event: AppEvent::Back, to: AppState::ShuttingDown, effects: &[Effect::Quit] },
+    Transition { from: AppState::Menu, event: AppEvent::Quit, to: AppState::ShuttingDown, effects: &[Effect::Quit] },
+    Transition { from: AppState::Connecting, event: AppEvent::Quit, to: AppState::ShuttingDown, effects: &[Effect::Leave, Effect::Quit] },
+    Transition { from: AppState::Playing, event: AppEvent::Quit, to: AppState::ShuttingDown, effects: &[Effect::Leave, Effect::Quit] },
+    Transition { from: AppState::Testing, event: AppEvent::Quit, to: AppState::ShuttingDown, effects: &[Effect::AbortTests, Effect::Leave, Effect::Quit] },
+    Transition { from: AppState::Results, event: AppEvent::Quit, to: AppState::ShuttingDown, effects: &[Effect::Leave, Effect::Quit] },
+    Transition { from: AppState::Spectating, event: AppEvent::Quit, to: AppState::ShuttingDown, effects: &[Effect::Leave, Effect::Quit] },
+    Transition { from: AppState::Disconnected, event: AppEvent::Quit, to: AppState::ShuttingDown, effects: &[Effect::Quit] },
+    Transition { from: AppState::Crashed, event: AppEvent::Quit, to: AppState::ShuttingDown, effects: &[Effect::Quit] },
+];
+
+/// Finds the transition for an event in a state
+pub fn find_transition(from: AppState, event: AppEvent) -> Option<&'static Transition> {
+    TRANSITIONS.iter().find(|transition| transition.from == from && transition.event == event)
+}
+
+/// Current state of the client, only changed through the transition table
+#[derive(Debug)]
+pub struct AppStateMachine {
+    state: AppState,
+}
+
+/// Implementation of the AppStateMachine
+impl AppStateMachine {
+    /// Creates a state machine in the given state
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+
+    /// The current state
+    pub fn state(&self) -> AppState {
+        self.state
+    }
+
+    /// Handles an event, returning the effects to carry out if a transition was taken
+    pub fn handle(&mut self, event: AppEvent) -> Option<&'static [Effect]> {
+        let transition = find_transition(self.state, event)?;
+        self.state = transition.to;
+        Some(transition.effects)
+    }
+}
+
+/// Default implementation for the AppStateMachine, the client connects right away on start
+impl Default for AppStateMachine {
+    fn default() -> Self {
+        Self::new(AppState::Connecting)
+    }
+}
+
+/// Tests for the client state machine
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATES: [AppState; 9] = [
+        AppState::Menu,
+        AppState::Connecting,
+        AppState::Playing,
+        AppState::Testing,
+        AppState::Results,
+        AppState::Spectating,
+        AppState::Disconnected,
+        AppState::Crashed,
+        AppState::ShuttingDown,
+    ];
+
+    // Helper function to run events from a state, returning the final state and all effects
+    fn run(from: AppState, events: &[AppEvent]) -> (AppState, Vec<Effect>) {
+        let mut machine = AppStateMachine::new(from);
+        let mut effects = Vec::new();
+        for event in events {
+            if let Some(taken) = machine.handle(*event) {
+                effects.extend_from_slice(taken);
+            }
+        }
+        (machine.state(), effects)
+    }
+
+    #[test]
+    fn test_every_legal_transition() {
+        for transition in TRANSITIONS {
+            let mut machine = AppStateMachine::new(transition.from);
+            assert_eq!(machine.handle(transition.event), Some(transition.effects), "{:?}", transition);
+            assert_eq!(machine.state(), transition.to);
+        }
+    }
+
+    #[test]
+    fn test_table_has_one_row_per_state_and_event() {
+        for (index, transition) in TRANSITIONS.iter().enumerate() {
+            let duplicate = TRANSITIONS[index + 1..].iter()
+                .any(|other| other.from == transition.from && other.event == transition.event);
+            assert!(!duplicate, "{:?} is listed twice", transition);
+        }
+    }
+
+    #[test]
+    fn test_illegal_transitions_keep_state() {
+        let illegal = [
+            (AppState::Menu, AppEvent::StartTests),
+            (AppState::Menu, AppEvent::Back),
+            (AppState::Menu, AppEvent::Welcomed),
+            (AppState::Connecting, AppEvent::StartTests),
+            (AppState::Playing, AppEvent::Welcomed),
+            (AppState::Playing, AppEvent::TestsFinished),
+            (AppState::Testing, AppEvent::StartTests),
+            (AppState::Disconnected, AppEvent::StartTests),
+            (AppState::Disconnected, AppEvent::Welcomed),
+            (AppState::Crashed, AppEvent::Welcomed),
+            (AppState::Crashed, AppEvent::StartTests),
+            (AppState::Crashed, AppEvent::Crash),
+            (AppState::Playing, AppEvent::Rejected),
+            (AppState::Menu, AppEvent::Rejected),
+            (AppState::Spectating, AppEvent::StartTests),
+            (AppState::Testing, AppEvent::ToggleSpectate),
+            (AppState::Menu, AppEvent::ToggleSpectate),
+            (AppState::Connecting, AppEvent::Removed),
+            (AppState::Disconnected, AppEvent::Removed),
+        ];
+        for (state, event) in illegal {
+            let mut machine = AppStateMachine::new(state);
+            assert_eq!(machine.handle(event), None, "{:?} in {:?}", event, state);
+            assert_eq!(machine.state(), state);
+        }
+    }
+
+    #[test]
+    fn test_reconnect_cycle() {
+        let (state, effects) = run(AppState::Connecting, &[
+            AppEvent::Welcomed,
+            AppEvent::ToggleConnection,
+            AppEvent::ToggleConnection,
+            AppEvent::Welcomed,
+        ]);
+        assert_eq!(state, AppState::Playing);
+        assert_eq!(effects, vec![Effect::Disconnect, Effect::Connect]);
+    }
+
+    #[test]
+    fn test_rejected_connect_returns_to_menu() {
+        let (state, effects) = run(AppState::Menu, &[AppEvent::Confirm, AppEvent::Rejected, AppEvent::Confirm]);
+        assert_eq!(state, AppState::Connecting);
+        assert_eq!(effects, vec![Effect::Connect, Effect::Connect]);
+    }
+
+    #[test]
+    fn test_removed_by_the_server_waits_for_a_reconnect() {
+        let (state, effects) = run(AppState::Playing, &[AppEvent::StartTests, AppEvent::Removed, AppEvent::ToggleConnection]);
+        assert_eq!(state, AppState::Connecting);
+        assert_eq!(effects, vec![Effect::StartTests, Effect::AbortTests, Effect::ForgetPrediction, Effect::Connect]);
+
+        // Being removed is only known once the server has welcomed us
+        assert_eq!(run(AppState::Connecting, &[AppEvent::Removed]), (AppState::Connecting, vec![]));
+    }
+
+    #[test]
+    fn test_performance_test_cycle() {
+        let (state, effects) = run(AppState::Playing, &[AppEvent::StartTests, AppEvent::TestsFinished]);
+        assert_eq!(state, AppState::Results);
+        assert_eq!(effects, vec![Effect::StartTests, Effect::FinishTests]);
+
+        // Disconnecting during a test stops it first
+        let (state, effects) = run(AppState::Playing, &[AppEvent::StartTests, AppEvent::ToggleConnection]);
+        assert_eq!(state, AppState::Disconnected);
+        assert_eq!(effects, vec![Effect::StartTests, Effect::AbortTests, Effect::Disconnect]);
+    }
+
+    #[test]
+    fn test_spectate_cycle() {
+        let (state, effects) = run(AppState::Playing, &[AppEvent::ToggleSpectate, AppEvent::ToggleSpectate, AppEvent::ToggleSpectate]);
+        assert_eq!(state, AppState::Spectating);
+        assert!(effects.is_empty());
+        assert_eq!(run(AppState::Spectating, &[AppEvent::Back]).0, AppState::Playing);
+
+        // Spectators stay connected and see the world, but send no inputs
+        assert!(AppState::Spectating.is_connected());
+        assert!(AppState::Spectating.shows_world());
+        assert!(!AppState::Spectating.drives_input());
+    }
+
+    #[test]
+    fn test_only_playing_states_drive_input() {
+        for state in STATES {
+            let expected = matches!(state, AppState::Playing | AppState::Testing);
+            assert_eq!(state.drives_input(), expected, "{:?}", state);
+
+            // Input is only sent while connected
+            assert!(!state.drives_input() || state.is_connected());
+        }
+        assert!(!AppState::Menu.shows_world());
+        assert!(!AppState::Disconnected.is_connected());
+    }
+
+    #[test]
+    fn test_crash_and_soft_reset() {
+        // A crash can happen anywhere, a test that was running is stopped first
+        for state in STATES.iter().filter(|state| !matches!(state, AppState::Crashed | AppState::ShuttingDown)) {
+            let (crashed, _) = run(*state, &[AppEvent::Crash]);
+            assert_eq!(crashed, AppState::Crashed, "{:?}", state);
+        }
+        let (_, effects) = run(AppState::Testing, &[AppEvent::Crash]);
+        assert_eq!(effects, vec![Effect::AbortTests]);
+
+        // R resets and reconnects, Esc quits
+        let (state, effects) = run(AppState::Playing, &[AppEvent::Crash, AppEvent::ToggleConnection, AppEvent::Welcomed]);
+        assert_eq!(state, AppState::Playing);
+        assert_eq!(effects, vec![Effect::SoftReset, Effect::Connect]);
+        assert_eq!(run(AppState::Crashed, &[AppEvent::Back]), (AppState::ShuttingDown, vec![Effect::Quit]));
+        assert!(!AppState::Crashed.shows_world());
+        assert!(!AppState::Crashed.is_connected());
+    }
+
+    #[test]
+    fn test_input_is_gated_by_match_phase() {
+        let frozen = [MatchPhase::Countdown { remaining_ms: 5000 }, MatchPhase::Ended];
+        for state in STATES {
+            assert_eq!(state.sends_input(MatchPhase::Warmup), state.drives_input(), "{:?}", state);
+            assert_eq!(state.sends_input(MatchPhase::Active), state.drives_input(), "{:?}", state);
+            for phase in frozen {
+                assert!(!state.sends_input(phase), "{:?} in {:?}", state, phase);
+            }
+        }
+    }
+
+    #[test]
+    fn test_quit_leaves_the_server_from_every_connected_state() {
+        for state in STATES.iter().filter(|state| **state != AppState::ShuttingDown) {
+            let (shutting_down, effects) = run(*state, &[AppEvent::Quit]);
+            assert_eq!(shutting_down, AppState::ShuttingDown, "{:?}", state);
+            assert_eq!(effects.contains(&Effect::Leave), state.is_connected(), "{:?}", state);
+            assert_eq!(effects.last(), Some(&Effect::Quit), "{:?}", state);
+        }
+        let (_, effects) = run(AppState::Testing, &[AppEvent::Quit]);
+        assert_eq!(effects, vec![Effect::AbortTests, Effect::Leave, Effect::Quit]);
+
+        // Once shutting down nothing brings the client back
+        for event in [AppEvent::Quit, AppEvent::ToggleConnection, AppEvent::Back, AppEvent::Crash, AppEvent::Welcomed] {
+            assert_eq!(run(AppState::ShuttingDown, &[event]), (AppState::ShuttingDown, vec![]), "{:?}", event);
+        }
+        assert!(!AppState::ShuttingDown.is_connected() && !AppState::ShuttingDown.shows_world());
+    }
+
+    #[test]
+    fn test_every_state_can_be_left() {
+        // Shutting down is the only end, the frame loop stops there
+        for state in STATES.iter().copied().filter(|state| *state != AppState::ShuttingDown) {
+            assert!(TRANSITIONS.iter().any(|transition| transition.from == state), "{:?} is a dead end", state);
+        }
+        assert_eq!(AppStateMachine::default().state(), AppState::Connecting);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::client_events::ClientEvent;
+use crate::constants::AUDIO_CUE_MIN_INTERVAL;
+
+use std::time::Instant;
+
+/// Netcode events the client plays a sound for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cue {
+    Join,       // A remote player appeared in the snapshots
+    Leave,      // A remote player is no longer in the snapshots
+    Pickup,     // The local score went up in a mode with pellets
+    Correction, // The prediction was snapped to the server position
+}
+
+/// Implementation of the Cue
+impl Cue {
+    /// Every cue
+    pub const ALL: [Cue; 4] = [Cue::Join, Cue::Leave, Cue::Pickup, Cue::Correction];
+
+    /// Cue of a client event, None for events without a sound. A rising score is a pickup only while the
+    /// mode has pellets, so time scored in tag isn't heard as pickups.
+    pub fn for_event(event: &ClientEvent) -> Option<Cue> {
+        match event {
+            ClientEvent::PlayerJoined { .. } => Some(Cue::Join),
+            ClientEvent::PlayerLeft { .. } => Some(Cue::Leave),
+            ClientEvent::ScoreChanged { score, previous, pellets: true } if score > previous => Some(Cue::Pickup),
+            ClientEvent::SnapCorrection { .. } => Some(Cue::Correction),
+            _ => None,
+        }
+    }
+
+    /// Position of the cue in ALL
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Volume the cue is played at, corrections are kept subtle
+    pub fn volume(self) -> f32 {
+        match self {
+            Cue::Join | Cue::Leave | Cue::Pickup => 0.6,
+            Cue::Correction => 0.3,
+        }
+    }
+}
+
+/// Plays the sound of a cue. Kept thin so tests can record the cues instead of playing them.
+pub trait SoundSink {
+    fn play(&mut self, cue: Cue);
+}
+
+/// Plays the sounds embedded in the client through macroquad's audio
+#[cfg(feature = "client")]
+pub struct MacroquadSink {
+    sounds: Vec<(Cue, macroquad::audio::Sound)>,
+}
+
+/// Implementation of the MacroquadSink
+#[cfg(feature = "client")]
+impl MacroquadSink {
+    /// Loads the embedded sounds, a sound that fails to load is left out and its cue stays silent
+    pub async fn load() -> Self {
+        let mut sounds = Vec::new();
+        for cue in Cue::ALL {
+            let data: &[u8] = match cue {
+                Cue::Join => include_bytes!("assets/sounds/join.wav"),
+                Cue::Leave => include_bytes!("assets/sounds/leave.wav"),
+                Cue::Pickup => include_bytes!("assets/sounds/pickup.wav"),
+                Cue::Correction => include_bytes!("assets/sounds/correction.wav"),
+            };
+            match macroquad::audio::load_sound_from_bytes(data).await {
+                Ok(sound) => sounds.push((cue, sound)),
+                Err(e) => eprintln!("Failed to load the {:?} sound: {}", cue, e),
+            }
+        }
+        Self { sounds }
+    }
+}
+
+/// Implementation of the SoundSink for the MacroquadSink
+#[cfg(feature = "client")]
+impl SoundSink for MacroquadSink {
+    fn play(&mut self, cue: Cue) {
+        if let Some((_, sound)) = self.sounds.iter().find(|(loaded, _)| *loaded == cue) {
+            macroquad::audio::play_sound(sound, macroquad::audio::PlaySoundParams { looped: false, volume: cue.volume() });
+        }
+    }
+}
+
+/// Plays cues through a sink, at most once per AUDIO_CUE_MIN_INTERVAL for each cue, unless muted
+pub struct AudioCues {
+    sink: Option<Box<dyn SoundSink>>, // None until the sounds are loaded
+    muted: bool,
+    last_played: [Option<Instant>; Cue::ALL.len()], // When each cue was last played
+}
+
+/// Implementation of the AudioCues
+impl AudioCues {
+    /// Creates the cues without a sink, nothing is heard until one is set
+    pub fn new(muted: bool) -> Self {
+        Self {
+            sink: None,
+            muted,
+            last_played: [None; Cue::ALL.len()],
+        }
+    }
+
+    /// Sets the sink the cues are played through
+    pub fn set_sink(&mut self, sink: Box<dyn SoundSink>) {
+        self.sink = Some(sink);
+    }
+
+    /// Whether every cue is muted
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Mutes or unmutes every cue
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Flips the master mute, returns whether the cues are muted now
+    pub fn toggle_mute(&mut self) -> bool {
+        self.muted = !self.muted;
+        self.muted
+    }
+
+    /// Plays a cue unless muted or the same cue was played less than AUDIO_CUE_MIN_INTERVAL ago.
+    /// Returns whether it was played.
+    pub fn trigger(&mut self, cue: Cue, now: Instant) -> bool {
+        let last = &mut self.last_played[cue.index()];
+        let too_soon = last.is_some_and(|last| now.saturating_duration_since(last) < AUDIO_CUE_MIN_INTERVAL);
+        if self.muted || too_soon {
+            return false;
+        }
+        *last = Some(now);
+        if let Some(sink) = &mut self.sink {
+            sink.play(cue);
+        }
+        true
+    }
+}
+
+/// Tests for the audio cues
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    /// Sink that records the cues instead of playing them
+    struct FakeSink(Rc<RefCell<Vec<Cue>>>);
+
+    /// Implementation of the SoundSink for the FakeSink
+    impl SoundSink for FakeSink {
+        fn play(&mut self, cue: Cue) {
+            self.0.borrow_mut().push(cue);
+        }
+    }
+
+    // Helper function to create cues that play into a shared list
+    fn recorded_cues(muted: bool) -> (AudioCues, Rc<RefCell<Vec<Cue>>>) {
+        let played = Rc::new(RefCell::new(Vec::new()));
+        let mut cues = AudioCues::new(muted);
+        cues.set_sink(Box::new(FakeSink(played.clone())));
+        (cues, played)
+    }
+
+    #[test]
+    fn test_each_cue_is_rate_limited_on_its_own() {
+        let (mut cues, played) = recorded_cues(false);
+        let start = Instant::now();
+
+        assert!(cues.trigger(Cue::Join, start));
+        assert!(!cues.trigger(Cue::Join, start + Duration::from_millis(99)));
+        assert!(cues.trigger(Cue::Pickup, start + Duration::from_millis(50)));
+        assert!(cues.trigger(Cue::Join, start + AUDIO_CUE_MIN_INTERVAL));
+        assert_eq!(*played.borrow(), vec![Cue::Join, Cue::Pickup, Cue::Join]);
+    }
+
+    #[test]
+    fn test_mute_silences_every_cue() {
+        let (mut cues, played) = recorded_cues(true);
+        let now = Instant::now();
+        for cue in Cue::ALL {
+            assert!(!cues.trigger(cue, now));
+        }
+        assert!(played.borrow().is_empty());
+
+        // Cues skipped while muted don't hold back the first one after unmuting
+        assert!(!cues.toggle_mute());
+        assert!(cues.trigger(Cue::Correction, now));
+        assert_eq!(*played.borrow(), vec![Cue::Correction]);
+    }
+
+    #[test]
+    fn test_cues_of_events() {
+        let id = Uuid::from_u128(2);
+        assert_eq!(Cue::for_event(&ClientEvent::PlayerJoined { id }), Some(Cue::Join));
+        assert_eq!(Cue::for_event(&ClientEvent::PlayerLeft { id }), Some(Cue::Leave));
+        assert_eq!(Cue::for_event(&ClientEvent::SnapCorrection { magnitude: 300.0 }), Some(Cue::Correction));
+        assert_eq!(Cue::for_event(&ClientEvent::ScoreChanged { score: 3, previous: 2, pellets: true }), Some(Cue::Pickup));
+
+        // The score going back to zero in a new match is no pickup, without pellets a rising score is time scored in tag
+        assert_eq!(Cue::for_event(&ClientEvent::ScoreChanged { score: 0, previous: 3, pellets: true }), None);
+        assert_eq!(Cue::for_event(&ClientEvent::ScoreChanged { score: 1, previous: 0, pellets: false }), None);
+        assert_eq!(Cue::for_event(&ClientEvent::SnapshotGap { count: 4 }), None);
+    }
+}
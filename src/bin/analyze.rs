@@ -0,0 +1,139 @@
+use netcode_game::analysis::{default_conditions, PerformanceAnalyzer};
+use netcode_game::constants::{BROADCAST_INTERVAL, INTERPOLATION_COMPARE_TICKS};
+use netcode_game::headless::{run_sweep, SweepConfig};
+use netcode_game::interpolation_compare::{compare, comparison_report, RecordedPath};
+use netcode_game::regression::{Regression, RegressionThresholds};
+use netcode_game::scripted_input::ScriptedInputDriver;
+use netcode_game::streaming_stats::Capture;
+use netcode_game::util::format_instance;
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const EXIT_REGRESSED: i32 = 1; // A condition's metrics went past the regression thresholds
+const EXIT_FAILED: i32 = 2; // The sweep couldn't run or its results couldn't be written
+
+/// Runs the performance tests without a window against a running server, writes the report, the samples
+/// and the summary to the output directory and exits with 1 if any condition regressed
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let server = parse_string_flag(&args, "--server").unwrap_or_else(|| "127.0.0.1:9000".to_string());
+    let out_dir = parse_string_flag(&args, "--out").map_or_else(|| PathBuf::from("analysis"), PathBuf::from);
+
+    // The interpolation strategies are compared offline, no server is needed
+    if has_flag(&args, "--compare-interpolation") {
+        let seed = parse_string_flag(&args, "--seed").and_then(|seed| seed.parse().ok()).unwrap_or_else(rand::random);
+        if let Err(e) = write_interpolation_comparison(&out_dir, seed) {
+            eprintln!("Failed to write the interpolation comparison to {}: {}", out_dir.display(), e);
+            std::process::exit(EXIT_FAILED);
+        }
+        return;
+    }
+
+    // Limits every condition is checked against, the defaults without a file
+    let thresholds = match parse_string_flag(&args, "--thresholds") {
+        Some(path) => match load_thresholds(Path::new(&path)) {
+            Ok(thresholds) => thresholds,
+            Err(e) => {
+                eprintln!("Failed to load the regression thresholds from {}: {}", path, e);
+                std::process::exit(EXIT_FAILED);
+            }
+        },
+        None => RegressionThresholds::default(),
+    };
+
+    let defaults = SweepConfig::default();
+    let config = SweepConfig {
+        test_duration: parse_millis_flag(&args, "--duration-ms").unwrap_or(defaults.test_duration),
+        warmup: parse_millis_flag(&args, "--warmup-ms").unwrap_or(defaults.warmup),
+        capture: if has_flag(&args, "--full-capture") { Capture::Full } else { Capture::default() },
+        room: parse_string_flag(&args, "--room").unwrap_or_default(),
+        ..defaults
+    };
+
+    // Every run is its own instance, so its rows can be told apart in the server metrics
+    let instance: u64 = rand::random();
+    println!(
+        "Analyzing {} conditions of {} s each against {} as instance {}",
+        config.conditions.len(),
+        config.test_duration.as_secs_f64(),
+        server,
+        format_instance(instance),
+    );
+    let analyzer = match run_sweep(&server, &config, instance) {
+        Ok(analyzer) => analyzer,
+        Err(e) => {
+            eprintln!("Analysis failed: {}", e);
+            std::process::exit(EXIT_FAILED);
+        }
+    };
+
+    let regressions = thresholds.check(&analyzer.summaries());
+    if let Err(e) = write_results(&out_dir, &analyzer, &regressions, &format_instance(instance)) {
+        eprintln!("Failed to write the results to {}: {}", out_dir.display(), e);
+        std::process::exit(EXIT_FAILED);
+    }
+    println!("Wrote the report, samples and summary to {}", out_dir.display());
+
+    if regressions.is_empty() {
+        println!("No regressions");
+    } else {
+        for regression in &regressions {
+            eprintln!("Regression: {}", regression.describe());
+        }
+        std::process::exit(EXIT_REGRESSED);
+    }
+}
+
+/// Writes report.md, samples.csv and summary.json to the directory, creating it if needed
+fn write_results(dir: &Path, analyzer: &PerformanceAnalyzer, regressions: &[Regression], instance: &str) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut report = analyzer.generate_report();
+    report.push_str("\n## Regressions\n\n");
+    if regressions.is_empty() {
+        report.push_str("None\n");
+    }
+    for regression in regressions {
+        report.push_str(&format!("- {}\n", regression.describe()));
+    }
+    std::fs::write(dir.join("report.md"), report)?;
+    std::fs::write(dir.join("samples.csv"), analyzer.export_csv(instance))?;
+    std::fs::write(dir.join("summary.json"), analyzer.export_json(instance))?;
+    Ok(())
+}
+
+/// Plays the square walk back with every interpolation strategy under every default condition, prints the
+/// table and writes it to interpolation.md in the directory. The seed is printed so a run can be repeated.
+fn write_interpolation_comparison(dir: &Path, seed: u64) -> std::io::Result<()> {
+    let path = RecordedPath::scripted(ScriptedInputDriver::square(), INTERPOLATION_COMPARE_TICKS, BROADCAST_INTERVAL.as_millis() as u32);
+    let mut report = comparison_report(&compare(&path, &default_conditions(), seed));
+    report.push_str(&format!("\nSeed: {}\n", seed));
+    println!("{}", report);
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("interpolation.md"), report)?;
+    println!("Wrote the comparison to {}", dir.join("interpolation.md").display());
+    Ok(())
+}
+
+/// Reads the regression thresholds from a JSON file. Unlike the settings of the client a broken file is
+/// an error, a gate that silently fell back to the defaults would pass what it was set up to catch.
+fn load_thresholds(path: &Path) -> Result<RegressionThresholds, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&text).map_err(|e| e.to_string())
+}
+
+/// Checks if the flag was given on the command line
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag)
+}
+
+/// Parses an optional `<flag> <text>` argument
+fn parse_string_flag(args: &[String], flag: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    args.get(index + 1).cloned()
+}
+
+/// Parses an optional `<flag> <milliseconds>` argument, None if missing or invalid
+fn parse_millis_flag(args: &[String], flag: &str) -> Option<Duration> {
+    parse_string_flag(args, flag)?.parse::<u64>().ok().map(Duration::from_millis)
+}
@@ -0,0 +1,674 @@
+mod app;
+
+use macroquad::prelude::*;
+
+use netcode_game::app_state::AppEvent;
+use netcode_game::codec::CodecKind;
+use netcode_game::audio_cues::{AudioCues, MacroquadSink};
+use netcode_game::build_info::BuildInfo;
+use netcode_game::config::{config_dir, config_window, hash_instance_id, load_audio_muted, load_frame_cap, load_language, load_or_create_instance_id, load_session_log_severity, load_warning_thresholds, save_audio_muted, save_frame_cap, save_language};
+use netcode_game::constants::{CAPTURE_DIR, CLOCK_DEBUG_STEP, CRASH_LOG_PATH, DEMO_INTERVAL, NETWORK_CLOSE_TIMEOUT, OFFLINE_BOTS, OFFLINE_SERVER_LABEL, SESSION_LOG_DIR, SHUTDOWN_TIMEOUT};
+use netcode_game::demo::{default_scenarios, DemoDirector};
+use netcode_game::diagnostics::SelfTest;
+use netcode_game::frame_capture::{CaptureTriggers, FrameCapturer, ScreenSink};
+use netcode_game::frame_pacer::{FramePacer, SystemClock};
+use netcode_game::input::{Action, KeyBindings};
+use netcode_game::interpolation::InterpolationStrategy;
+use netcode_game::local_server::{LocalServer, LocalServerHandle};
+use netcode_game::network::NetworkClient;
+use netcode_game::prediction::PredictionConfig;
+use netcode_game::render::Renderer;
+use netcode_game::render_clock::{ClockDrift, RenderClock};
+use netcode_game::scoreboard::ScoreboardSort;
+use netcode_game::server_config::ServerConfig;
+use netcode_game::session_log::{SessionLog, Severity};
+use netcode_game::split_view::ViewLayout;
+use netcode_game::streaming_stats::Capture;
+use netcode_game::strings::Language;
+use netcode_game::text_field::TextFieldKey;
+use netcode_game::types::PlayerShape;
+use netcode_game::util::format_instance;
+
+use app::{ClientApp, FrameContext};
+
+use std::any::Any;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Client main function
+#[macroquad::main(config_window)]
+async fn main() {
+    // Read accessibility options from the command line
+    let args: Vec<String> = std::env::args().collect();
+
+    // Checks the platform and exits, for machines where the game fails in odd ways
+    if has_flag(&args, "--self-test") {
+        let report = SelfTest::with_standard_checks().run();
+        print!("{}", report.report());
+        std::process::exit(report.exit_code());
+    }
+
+    let high_contrast = has_flag(&args, "--high-contrast");
+    let screen_reader = has_flag(&args, "--screen-reader");
+
+    // Unattended demo mode for exhibitions, which needs a server started with --debug-teleports true for the teleport
+    let demo = has_flag(&args, "--demo");
+
+    // Without a name the client starts on the menu to enter one, the demo mode connects right away
+    let name = parse_string_flag(&args, "--name").or_else(|| demo.then(|| "Demo".to_string()));
+
+    // Shape the player is drawn as, squares unless another one is chosen
+    let shape = parse_shape_flag(&args);
+
+    // Room to join, picked on the menu without one. The password is only needed for locked rooms.
+    let room = parse_string_flag(&args, "--room");
+    let password = parse_string_flag(&args, "--password");
+
+    // Seed of the simulated network, printed so a run can be repeated with --sim-seed
+    let sim_seed = parse_string_flag(&args, "--sim-seed").and_then(|seed| seed.parse().ok()).unwrap_or_else(::rand::random);
+    let build = BuildInfo::current(Some(sim_seed));
+    println!("{}", build.describe());
+
+    // Panics are appended to the crash log with the build before the frame loop catches them
+    let crash_log = parse_string_flag(&args, "--crash-log").map_or_else(|| PathBuf::from(CRASH_LOG_PATH), PathBuf::from);
+    install_crash_log_hook(crash_log, build);
+
+    // Identifies this machine across runs, only a salted hash of it is ever sent or written
+    let instance = hash_instance_id(instance_id(has_flag(&args, "--anonymous")));
+    println!("Client instance {}", format_instance(instance));
+
+    // Simulated drift of the local clock, to test the server clock estimate
+    let drift_ppm = parse_f64_flag(&args, "--sim-clock-drift").unwrap_or(0.0);
+    let mut clock_drift = ClockDrift::new(drift_ppm);
+    if clock_drift.is_enabled() {
+        println!("Simulating clock drift: {} ppm", drift_ppm);
+    }
+
+    // Protocol spoken with the server, the JSON one is for poking at the traffic while debugging
+    let codec = parse_codec_flag(&args);
+    if codec != CodecKind::default() {
+        println!("Speaking the {} protocol", codec.name());
+    }
+
+    // Initialize the game window and connect to the server, or to one in this process with wandering bots
+    let offline = has_flag(&args, "--offline");
+    let builder = NetworkClient::builder(if offline { OFFLINE_SERVER_LABEL } else { "127.0.0.1:9000" }).codec(codec).jitter_seed(Some(sim_seed));
+    let net = if offline {
+        let server = LocalServerHandle::start(LocalServer::new(&ServerConfig::default(), OFFLINE_BOTS)).expect("Failed to start the local server");
+        println!("Offline: playing against {} bots on a server in this process", OFFLINE_BOTS);
+        builder.local_server(server)
+    } else {
+        builder
+    }
+    .build()
+    .expect("Failed to bind UDP socket");
+    if let Some(size) = net.stats().recv_buffer_size {
+        println!("Socket receive buffer: {} bytes", size);
+    }
+    // Language of the interface: the flag, else the one chosen in an earlier run, switched with L
+    let mut renderer = Renderer::with_high_contrast(high_contrast);
+    renderer.set_language(parse_language_flag(&args, config_dir().map(|dir| load_language(&dir)).unwrap_or_default()));
+    let mut app = ClientApp::new(net, screen_reader, name, shape, instance, room, password);
+    if let Some(path) = parse_string_flag(&args, "--analysis-csv") {
+        app.set_analysis_csv(path.into());
+    }
+    if has_flag(&args, "--full-capture") {
+        app.set_analysis_capture(Capture::Full);
+    }
+    if has_flag(&args, "--no-culling") {
+        app.set_culling(false);
+    }
+    if let Some(name) = parse_string_flag(&args, "--interpolation") {
+        match InterpolationStrategy::parse(&name) {
+            Some(strategy) => app.set_interpolation_strategy(strategy),
+            None => eprintln!("Unknown interpolation strategy {}, using {}", name, InterpolationStrategy::default().name()),
+        }
+    }
+    if let Some(configs) = parse_mirror_flags(&args) {
+        if let Err(e) = app.set_mirror(configs) {
+            eprintln!("{}, playing without a mirror", e);
+        }
+    }
+
+    // Every session is logged for reviewing it afterwards, the summary is written when the client closes
+    if !has_flag(&args, "--no-session-log") {
+        let dir = parse_string_flag(&args, "--session-log-dir").unwrap_or_else(|| SESSION_LOG_DIR.to_string());
+        let severity = parse_severity_flag(&args, config_dir().map(|dir| load_session_log_severity(&dir)).unwrap_or_default());
+        let started_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis() as u64);
+        app.set_session_log(SessionLog::new(Path::new(&dir), started_ms, severity));
+    }
+    if demo {
+        let interval = parse_f64_flag(&args, "--demo-interval").map_or(DEMO_INTERVAL, Duration::from_secs_f64);
+        app.set_demo(DemoDirector::new(default_scenarios(), interval, Instant::now()));
+        println!("Demo mode: a scenario every {} s, any key pauses it", interval.as_secs_f64());
+    }
+
+    // Frames are captured with F2, and by themselves at large prediction errors and snapshot gaps when asked to
+    let capture_dir = parse_string_flag(&args, "--capture-dir").unwrap_or_else(|| CAPTURE_DIR.to_string());
+    let mut capturer = FrameCapturer::new(PathBuf::from(capture_dir), parse_capture_triggers(&args));
+    capturer.set_sink(Box::new(ScreenSink));
+    app.set_frame_capturer(capturer);
+
+    // Thresholds of the on-screen metric warnings, the settings file is created with the defaults on the first run
+    if let Some(dir) = config_dir() {
+        match load_warning_thresholds(&dir) {
+            Ok(thresholds) => app.set_warning_thresholds(thresholds),
+            Err(e) => eprintln!("Failed to load the warning thresholds from {}: {}", dir.display(), e),
+        }
+    }
+    // Sounds for joins, pickups and corrections, muted with F10 and kept muted in later runs
+    let mut audio = AudioCues::new(config_dir().is_some_and(|dir| load_audio_muted(&dir)));
+    audio.set_sink(Box::new(MacroquadSink::load().await));
+    app.set_audio(audio);
+    let mut render_clock = RenderClock::new(clock_drift.apply(get_time()));
+    let mut frame_cost_ms = 0.0;
+    let mut show_latency = false;
+    let mut show_traffic = false;
+    let mut show_profile = false;
+    let mut show_heatmap = false;
+    let mut show_minimap = false;
+    let mut show_server_view = false;
+    let mut show_overlaps = false;
+    let mut view_layout = ViewLayout::default();
+
+    // Frame rate cap chosen in an earlier run, cycled with F5
+    let mut frame_pacer = FramePacer::new(config_dir().map(|dir| load_frame_cap(&dir)).unwrap_or_default());
+    let mut pacer_clock = SystemClock::new();
+
+    // Closing the window is handled like any other quit, so the server is told and the files are written
+    prevent_quit();
+
+    // Main game loop
+    loop {
+        // Make the local clock jump ahead to test how the server clock estimate recovers
+        if is_key_pressed(KeyCode::F7) {
+            clock_drift.step_at(get_time(), CLOCK_DEBUG_STEP);
+            println!("Clock stepped {} ms ahead", CLOCK_DEBUG_STEP * 1000.0);
+        }
+        let current_time = clock_drift.apply(get_time());
+
+        // Toggle slow motion for rendering, networking and input keep running at normal speed
+        if is_key_pressed(KeyCode::F6) {
+            render_clock.toggle_slow_motion();
+            println!("Slow motion {}", if render_clock.is_slow_motion() { "on" } else { "off" });
+        }
+        let render_time = render_clock.update(current_time);
+
+        // Toggle the latency budget overlay with Shift+F8, F8 alone opens the scrubber
+        let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        if shift && is_key_pressed(KeyCode::F8) {
+            show_latency = !show_latency;
+        }
+
+        // Toggle the traffic per message category overlay, or the frame time by scope with Shift+F11
+        if shift && is_key_pressed(KeyCode::F11) {
+            show_profile = !show_profile;
+        } else if is_key_pressed(KeyCode::F11) {
+            show_traffic = !show_traffic;
+        }
+
+        // Toggle the prediction error heatmap
+        if is_key_pressed(KeyCode::F12) {
+            show_heatmap = !show_heatmap;
+        }
+
+        // Toggle the mini-map of every player
+        if is_key_pressed(KeyCode::F4) {
+            show_minimap = !show_minimap;
+        }
+
+        // Toggle the raw snapshots beside the client view
+        if is_key_pressed(KeyCode::F1) {
+            view_layout = view_layout.toggled();
+        }
+
+        // Toggle the outline of where the server most likely has the local player
+        if is_key_pressed(KeyCode::F3) {
+            show_server_view = !show_server_view;
+        }
+
+        // Toggle the outlines around players drawn overlapping, O is a letter so it is ignored while typing
+        if !app.is_typing() && is_key_pressed(KeyCode::O) {
+            show_overlaps = !show_overlaps;
+        }
+
+        // Switch the interface to the next language and keep it for the next run, L is ignored while typing
+        if !app.is_typing() && is_key_pressed(KeyCode::L) {
+            let language = renderer.strings().language().next();
+            renderer.set_language(language);
+            println!("Language: {}", language.name());
+            if let Some(dir) = config_dir() {
+                if let Err(e) = save_language(&dir, language) {
+                    eprintln!("Failed to store the language in {}: {}", dir.display(), e);
+                }
+            }
+        }
+
+        // Cycle the frame rate cap and keep it for the next run
+        if is_key_pressed(KeyCode::F5) {
+            let cap = frame_pacer.cap().next();
+            frame_pacer.set_cap(cap);
+            println!("Frame rate cap: {}", cap.describe());
+            if let Some(dir) = config_dir() {
+                if let Err(e) = save_frame_cap(&dir, cap) {
+                    eprintln!("Failed to store the frame rate cap in {}: {}", dir.display(), e);
+                }
+            }
+        }
+
+        // Mute or unmute the sounds and keep the choice for the next run
+        if is_key_pressed(KeyCode::F10) {
+            let muted = app.toggle_audio_mute();
+            println!("Sounds {}", if muted { "muted" } else { "on" });
+            if let Some(dir) = config_dir() {
+                if let Err(e) = save_audio_muted(&dir, muted) {
+                    eprintln!("Failed to store the sound mute in {}: {}", dir.display(), e);
+                }
+            }
+        }
+
+        // While a text field has focus, keys are typed into it instead of triggering events.
+        // The char queue is drained every frame so chars don't pile up while nothing is typed.
+        let typing = app.is_typing();
+        let typed_chars: Vec<char> = std::iter::from_fn(get_char_pressed).collect();
+        let mut events = if typing { Vec::new() } else { key_events(app.key_bindings()) };
+        if is_quit_requested() {
+            events.push(AppEvent::Quit);
+        }
+        let ctx = FrameContext {
+            current_time,
+            render_time,
+            frame_time: get_frame_time(),
+            frame_cost_ms,
+            events,
+            typed_chars: if typing { typed_chars } else { Vec::new() },
+            text_keys: if typing { text_field_keys() } else { Vec::new() },
+            room_moves: is_key_pressed(KeyCode::Down) as i32 - is_key_pressed(KeyCode::Up) as i32,
+            show_scoreboard: is_key_down(KeyCode::Tab),
+            scoreboard_sort: scoreboard_sort_key(),
+            follow_next: !typing && is_key_pressed(KeyCode::Tab),
+            toggle_free_camera: !typing && is_key_pressed(KeyCode::F),
+            show_latency,
+            show_traffic,
+            show_profile,
+            show_heatmap,
+            show_minimap,
+            show_server_view,
+            show_overlaps,
+            view_layout,
+            toggle_scrubber: !shift && is_key_pressed(KeyCode::F8),
+            scrub_steps: is_key_pressed(KeyCode::Right) as i32 - is_key_pressed(KeyCode::Left) as i32,
+            capture_requested: is_key_pressed(KeyCode::F2),
+            enable_prediction: !typing && is_key_pressed(KeyCode::P),
+            keyboard_used: !get_keys_down().is_empty() || !get_keys_pressed().is_empty(),
+            slow_motion_lag: (render_clock.is_slow_motion() || render_clock.lag() > 0.0).then(|| render_clock.lag()),
+        };
+        // A panic in the frame is caught and shows the crash screen instead of closing the window.
+        // The app may be left half updated, which is why the crash screen only offers a full reset.
+        // The cost is measured without waiting for the next frame, so vsync doesn't count as work
+        let frame_start = Instant::now();
+        let frame = panic::catch_unwind(AssertUnwindSafe(|| {
+            app.update(&ctx);
+            app.render(&renderer, &ctx);
+        }));
+        frame_cost_ms = frame_start.elapsed().as_secs_f32() * 1000.0;
+        if let Err(payload) = frame {
+            app.crash(panic_message(payload.as_ref()));
+        }
+
+        // The frame is drawn but not shown yet, which is when it can be captured
+        app.capture_frame(&ctx);
+        if app.should_quit() {
+            break;
+        }
+
+        next_frame().await;
+
+        // Hold the frame until the cap allows the next one, the network thread keeps running meanwhile
+        frame_pacer.pace(&mut pacer_clock);
+    }
+
+    // The Leave and the files were handled by the quit, what is left is the network thread. Should
+    // anything hang from here on, the process is ended anyway so the client can always be closed.
+    std::thread::spawn(|| {
+        std::thread::sleep(SHUTDOWN_TIMEOUT);
+        eprintln!("Shutting down took longer than {} s, exiting", SHUTDOWN_TIMEOUT.as_secs());
+        std::process::exit(1);
+    });
+    if !app.close(NETWORK_CLOSE_TIMEOUT) {
+        eprintln!("The network thread didn't stop within {} ms, leaving it behind", NETWORK_CLOSE_TIMEOUT.as_millis());
+    }
+}
+
+/// Helper function to turn the keys pressed this frame into state machine events, the toolbar keys as they are bound
+fn key_events(keys: &KeyBindings) -> Vec<AppEvent> {
+    let bindings = [
+        (keys.key(Action::ToggleConnection), AppEvent::ToggleConnection),
+        (keys.key(Action::StartTests), AppEvent::StartTests),
+        (Some(KeyCode::G), AppEvent::ToggleSpectate),
+        (Some(KeyCode::Escape), AppEvent::Back),
+        (Some(KeyCode::Enter), AppEvent::Confirm),
+    ];
+    bindings.iter()
+        .filter(|(key, _)| key.is_some_and(is_key_pressed))
+        .map(|(_, event)| *event)
+        .collect()
+}
+
+/// Helper function to pick the scoreboard sort column with 1, 2 or 3 while Tab holds the scoreboard open
+fn scoreboard_sort_key() -> Option<ScoreboardSort> {
+    if !is_key_down(KeyCode::Tab) {
+        return None;
+    }
+    [(KeyCode::Key1, ScoreboardSort::Ping), (KeyCode::Key2, ScoreboardSort::Distance), (KeyCode::Key3, ScoreboardSort::Time)]
+        .into_iter()
+        .find(|(key, _)| is_key_pressed(*key))
+        .map(|(_, sort)| sort)
+}
+
+/// Helper function to turn the editing keys pressed this frame into text field keys
+fn text_field_keys() -> Vec<TextFieldKey> {
+    let bindings = [
+        (KeyCode::Left, TextFieldKey::Left),
+        (KeyCode::Right, TextFieldKey::Right),
+        (KeyCode::Home, TextFieldKey::Home),
+        (KeyCode::End, TextFieldKey::End),
+        (KeyCode::Backspace, TextFieldKey::Backspace),
+        (KeyCode::Delete, TextFieldKey::Delete),
+        (KeyCode::Enter, TextFieldKey::Submit),
+        (KeyCode::KpEnter, TextFieldKey::Submit),
+        (KeyCode::Escape, TextFieldKey::Cancel),
+    ];
+    bindings.iter()
+        .filter(|(key, _)| is_key_pressed(*key))
+        .map(|(_, key)| *key)
+        .collect()
+}
+
+/// Instance id of this client, stored in the config directory. Anonymous runs and runs where
+/// the id can't be stored get a random id for this run only.
+fn instance_id(anonymous: bool) -> Uuid {
+    if anonymous {
+        return Uuid::new_v4();
+    }
+    let Some(dir) = config_dir() else {
+        eprintln!("No config directory found, using a random instance id for this run");
+        return Uuid::new_v4();
+    };
+    load_or_create_instance_id(&dir).unwrap_or_else(|e| {
+        eprintln!("Failed to store the instance id in {}: {}, using a random one for this run", dir.display(), e);
+        Uuid::new_v4()
+    })
+}
+
+/// Installs a panic hook that appends every panic with the build to the crash log, then runs the default hook
+fn install_crash_log_hook(path: PathBuf, build: BuildInfo) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        let entry = format_crash_entry(timestamp, &build, &info.to_string());
+        if let Err(e) = append_to_file(&path, &entry) {
+            eprintln!("Failed to write crash log {}: {}", path.display(), e);
+        }
+        default_hook(info);
+    }));
+}
+
+/// Formats one crash log entry, the timestamp is in seconds since the Unix epoch and the build comes before
+/// the message
+fn format_crash_entry(timestamp: f64, build: &BuildInfo, message: &str) -> String {
+    format!("[{:.3}] {}: {}\n", timestamp, build.describe(), message)
+}
+
+/// Appends text to a file, creating it if it doesn't exist
+fn append_to_file(path: &Path, text: &str) -> std::io::Result<()> {
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(text.as_bytes())
+}
+
+/// Gets the message out of a caught panic, panics carry a &str or a String
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Helper function to check if a command line flag is present
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag)
+}
+
+/// Parses an optional `<flag> <text>` argument
+fn parse_string_flag(args: &[String], flag: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    args.get(index + 1).cloned()
+}
+
+/// Parses the optional `--shape <square|circle|triangle>` argument, a square if missing or unknown
+fn parse_shape_flag(args: &[String]) -> PlayerShape {
+    let Some(name) = parse_string_flag(args, "--shape") else {
+        return PlayerShape::default();
+    };
+    PlayerShape::from_name(&name).unwrap_or_else(|| {
+        eprintln!("Unknown shape {}, using a square", name);
+        PlayerShape::default()
+    })
+}
+
+/// Parses the optional `--proto <bincode|json>` argument, bincode unless another protocol is chosen
+fn parse_codec_flag(args: &[String]) -> CodecKind {
+    let Some(name) = parse_string_flag(args, "--proto") else {
+        return CodecKind::default();
+    };
+    CodecKind::parse(&name).unwrap_or_else(|| {
+        eprintln!("Unknown protocol {}, using bincode", name);
+        CodecKind::default()
+    })
+}
+
+/// Parses the optional `--lang <en|nb>` argument, the stored language if missing or unknown
+fn parse_language_flag(args: &[String], stored: Language) -> Language {
+    let Some(code) = parse_string_flag(args, "--lang") else {
+        return stored;
+    };
+    Language::parse(&code).unwrap_or_else(|| {
+        eprintln!("Unknown language {}, using {}", code, stored.name());
+        stored
+    })
+}
+
+/// Parses `--mirror-a <settings>` and `--mirror-b <settings>`, either turning on the mirroring mode with the
+/// prediction configs of the two sides. A side without its flag or with settings that don't parse uses the defaults.
+fn parse_mirror_flags(args: &[String]) -> Option<[PredictionConfig; 2]> {
+    let flags = ["--mirror-a", "--mirror-b"];
+    if !flags.iter().any(|flag| has_flag(args, flag)) {
+        return None;
+    }
+    Some(flags.map(|flag| {
+        let settings = parse_string_flag(args, flag).unwrap_or_default();
+        PredictionConfig::parse(&settings).unwrap_or_else(|e| {
+            eprintln!("Bad {} settings: {}, using the defaults", flag, e);
+            PredictionConfig::default()
+        })
+    }))
+}
+
+/// Parses the optional `--session-log-level <debug|info|warning|error>` argument, the stored severity if missing or unknown
+fn parse_severity_flag(args: &[String], stored: Severity) -> Severity {
+    let Some(name) = parse_string_flag(args, "--session-log-level") else {
+        return stored;
+    };
+    Severity::parse(&name).unwrap_or_else(|| {
+        eprintln!("Unknown session log level {}, using {}", name, stored.name());
+        stored
+    })
+}
+
+/// Parses the capture triggers: `--captures` turns both on at their default thresholds,
+/// `--capture-error <px>` and `--capture-gap <snapshots>` turn one on at the threshold given
+fn parse_capture_triggers(args: &[String]) -> CaptureTriggers {
+    let mut triggers = if has_flag(args, "--captures") { CaptureTriggers::standard() } else { CaptureTriggers::default() };
+    if let Some(error) = parse_f64_flag(args, "--capture-error") {
+        triggers.prediction_error = Some(error as f32);
+    }
+    if let Some(gap) = parse_f64_flag(args, "--capture-gap") {
+        triggers.snapshot_gap = Some(gap.max(0.0) as u64);
+    }
+    triggers
+}
+
+/// Parses an optional `<flag> <number>` argument
+fn parse_f64_flag(args: &[String], flag: &str) -> Option<f64> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    args.get(index + 1)?.parse().ok()
+}
+
+/// Tests for the client functionality
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netcode_game::types::Position;
+
+    #[test]
+    fn test_color_conversion() {
+        let color = 0xFF0000; // Red
+
+        let r = ((color >> 16) & 0xFF_u32) as u8;
+        let g = ((color >> 8) & 0xFF_u32) as u8;
+        let b = (color & 0xFF_u32) as u8;
+
+        assert_eq!(r, 255);
+        assert_eq!(g, 0);
+        assert_eq!(b, 0);
+
+        let color = 0x00FF00; // Green
+
+        let r = ((color >> 16) & 0xFF_u32) as u8;
+        let g = ((color >> 8) & 0xFF_u32) as u8;
+        let b = (color & 0xFF_u32) as u8;
+
+        assert_eq!(r, 0);
+        assert_eq!(g, 255);
+        assert_eq!(b, 0);
+    }
+
+    #[test]
+    fn test_has_flag() {
+        let args = vec!["client".to_string(), "--high-contrast".to_string()];
+        assert!(has_flag(&args, "--high-contrast"));
+        assert!(!has_flag(&args, "--screen-reader"));
+    }
+
+    #[test]
+    fn test_parse_shape_flag() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_shape_flag(&args(&["client", "--shape", "triangle"])), PlayerShape::Triangle);
+        assert_eq!(parse_shape_flag(&args(&["client", "--shape", "star"])), PlayerShape::Square);
+        assert_eq!(parse_shape_flag(&args(&["client"])), PlayerShape::Square);
+    }
+
+    #[test]
+    fn test_parse_codec_flag() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_codec_flag(&args(&["client", "--proto", "json"])), CodecKind::Json);
+        assert_eq!(parse_codec_flag(&args(&["client", "--proto", "xml"])), CodecKind::Bincode);
+        assert_eq!(parse_codec_flag(&args(&["client"])), CodecKind::Bincode);
+    }
+
+    #[test]
+    fn test_parse_language_flag() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_language_flag(&args(&["client", "--lang", "nb"]), Language::English), Language::Norwegian);
+        assert_eq!(parse_language_flag(&args(&["client", "--lang", "fr"]), Language::Norwegian), Language::Norwegian);
+        assert_eq!(parse_language_flag(&args(&["client"]), Language::Norwegian), Language::Norwegian);
+    }
+
+    #[test]
+    fn test_parse_severity_flag() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_severity_flag(&args(&["client", "--session-log-level", "debug"]), Severity::Info), Severity::Debug);
+        assert_eq!(parse_severity_flag(&args(&["client", "--session-log-level", "all"]), Severity::Warning), Severity::Warning);
+        assert_eq!(parse_severity_flag(&args(&["client"]), Severity::Error), Severity::Error);
+    }
+
+    #[test]
+    fn test_parse_capture_triggers() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_capture_triggers(&args(&["client"])), CaptureTriggers::default());
+        assert_eq!(parse_capture_triggers(&args(&["client", "--captures"])), CaptureTriggers::standard());
+        let triggers = parse_capture_triggers(&args(&["client", "--capture-gap", "10"]));
+        assert_eq!((triggers.prediction_error, triggers.snapshot_gap), (None, Some(10)));
+        let triggers = parse_capture_triggers(&args(&["client", "--captures", "--capture-error", "25"]));
+        assert_eq!(triggers.prediction_error, Some(25.0));
+    }
+
+    #[test]
+    fn test_parse_mirror_flags() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_mirror_flags(&args(&["client", "--interpolation", "hermite"])), None);
+        let [a, b] = parse_mirror_flags(&args(&["client", "--mirror-b", "snap=3,watchdog=off"])).unwrap();
+        assert_eq!(a, PredictionConfig::default());
+        assert_eq!((b.snap_factor, b.watchdog), (3.0, false));
+
+        // Settings that don't parse fall back to the defaults of that side only
+        let [a, b] = parse_mirror_flags(&args(&["client", "--mirror-a", "predict=off", "--mirror-b", "snap=-1"])).unwrap();
+        assert!(!a.predict);
+        assert_eq!(b, PredictionConfig::default());
+    }
+
+    #[test]
+    fn test_parse_string_flag() {
+        let args: Vec<String> = ["client", "--name", "Kari"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(parse_string_flag(&args, "--name"), Some("Kari".to_string()));
+        assert_eq!(parse_string_flag(&args[..2], "--name"), None);
+    }
+
+    #[test]
+    fn test_parse_f64_flag() {
+        let args: Vec<String> = ["client", "--sim-clock-drift", "-250.5"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(parse_f64_flag(&args, "--sim-clock-drift"), Some(-250.5));
+        assert_eq!(parse_f64_flag(&args, "--other"), None);
+        assert_eq!(parse_f64_flag(&args[..2], "--sim-clock-drift"), None);
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = panic::catch_unwind(|| panic!("static message")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "static message");
+        let payload = panic::catch_unwind(|| panic!("formatted {}", 42)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "formatted 42");
+        assert_eq!(panic_message(&7u32), "unknown panic");
+    }
+
+    #[test]
+    fn test_crash_log_appends_entries() {
+        let path = std::env::temp_dir().join(format!("crash-log-test-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let build = BuildInfo::current(Some(3));
+        append_to_file(&path, &format_crash_entry(1.5, &build, "first")).unwrap();
+        append_to_file(&path, &format_crash_entry(2.25, &build, "second")).unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        let entries: Vec<&str> = log.lines().collect();
+        assert_eq!(entries, vec![format!("[1.500] {}: first", build.describe()), format!("[2.250] {}: second", build.describe())]);
+
+        // The build of an entry reads back from between the timestamp and the message
+        let (_, rest) = entries[0].split_once("] ").unwrap();
+        assert_eq!(BuildInfo::parse(rest.split_once(": ").unwrap().0), Some(build));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_position_creation() {
+        // Test the Position struct
+        let pos = Position { x: 100, y: 200 };
+        assert_eq!(pos.x, 100);
+        assert_eq!(pos.y, 200);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,224 @@
+use netcode_game::build_info::BuildInfo;
+use netcode_game::constants::DEFAULT_TICK_RATE;
+use netcode_game::diagnostics::SelfTest;
+use netcode_game::leaderboard::Leaderboard;
+use netcode_game::rooms::RoomConfig;
+use netcode_game::server::{create_rooms, run_server, ServerTuning};
+use netcode_game::server_config::ServerConfig;
+use netcode_game::transport::Transport;
+
+use std::collections::HashMap;
+
+use tokio::net::UdpSocket;
+
+const USAGE: &str = "\
+Usage: server [run] [flags]     Run the server
+       server dump-config [flags] Print the configuration merged from the defaults, the file and the flags
+       server self-test [flags]   Check encoding, clocks, sockets, config parsing and the bind address, then exit
+       server help              Show this help
+
+Flags, which override the file given with --config:
+  --config <file>                 TOML file with the configuration, its keys are the flags with underscores
+  --bind <address:port>           Address to listen on
+  --tick-rate <ticks>             Broadcast ticks per second
+  --match-minutes <minutes>       Reset the match this often, 0 never resets
+  --teams <count>                 Split the players into teams, 0 turns team mode off
+  --mode <free|pellets|tag>       Game mode of every room
+  --proto <bincode|json>          Speak only this protocol
+  --leaderboard <file>            Keep best scores in this file between runs
+  --metrics-csv <file>            Append per-player metrics to this file
+  --rooms <file>                  JSON file listing rooms and their passwords
+  --debug-teleports <true|false>  Let clients place their own player anywhere, for the client's demo mode
+  --coalesce-inputs <true|false>  Apply inputs at one per client frame, smoothing bursts at the cost of latency
+  --console <true|false>          Read commands from standard input: history <id>, rewind <id> <ms>, dump and help
+  --reconnect-grace-ms <ms>       How long a timed out player can resume its session, at most 60000
+  --max-resumable-sessions <n>    Timed out sessions kept per room, the longest gone is forgotten first
+  --max-rewind-ms <ms>            Furthest back lag compensation rewinds a player, older times use its current position
+  --spawn-seed <seed>             Seed the player spawns to repeat a run, random by default
+  --stats-port <port>             Answer stats queries on this port instead of the game port
+  --stats-token <token>           Only answer stats queries that send this token
+  --sim-processing-delay <ms>     Simulated delay of every broadcast tick
+  --sim-processing-jitter <ms>    Random variation of the simulated tick delay
+  --sim-input-delay <ms>          Simulated delay before inputs are applied";
+
+/// What the command line asks the server binary to do
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run(ServerConfig),        // Run the server with the merged configuration
+    DumpConfig(ServerConfig), // Print the merged configuration as TOML
+    SelfTest(Vec<String>),    // Run the self-test, the flags are checked by it instead of before it
+    Help,
+}
+
+/// Server main function using Tokio for async I/O
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match parse_command(&args) {
+        Ok(Command::Run(config)) => run(config).await,
+        Ok(Command::DumpConfig(config)) => print!("{}", config.to_toml()),
+        Ok(Command::Help) => println!("{}", USAGE),
+        Ok(Command::SelfTest(flags)) => {
+            let report = self_test(flags).run();
+            print!("{}", report.report());
+            std::process::exit(report.exit_code());
+        }
+        Err(e) => {
+            eprintln!("Invalid server configuration: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads the subcommand and its configuration from the arguments after the program name.
+/// Flags without a subcommand run the server, like before there were subcommands.
+fn parse_command(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        Some("help" | "--help" | "-h") => Ok(Command::Help),
+        Some("run") => ServerConfig::from_args(&args[1..]).map(Command::Run),
+        Some("dump-config") => ServerConfig::from_args(&args[1..]).map(Command::DumpConfig),
+        Some("self-test" | "--self-test") => Ok(Command::SelfTest(args[1..].to_vec())),
+        None => ServerConfig::from_args(args).map(Command::Run),
+        Some(flag) if flag.starts_with("--") => ServerConfig::from_args(args).map(Command::Run),
+        Some(other) => Err(format!("unknown command {}, use run, dump-config, self-test or help", other)),
+    }
+}
+
+/// The standard self-test and a check of the server's own configuration: it has to parse, and the
+/// address has to be free to bind, which fails while another server is running on it
+fn self_test(flags: Vec<String>) -> SelfTest {
+    let mut self_test = SelfTest::with_standard_checks();
+    self_test.register("server configuration", move || {
+        let config = ServerConfig::from_args(&flags)?;
+        let socket = std::net::UdpSocket::bind(&config.bind).map_err(|e| format!("can't bind {}: {}", config.bind, e))?;
+        let addr = socket.local_addr().map_err(|e| e.to_string())?;
+        Ok(format!("{} mode, {} ticks per second, {} is free", config.mode.name(), config.tick_rate, addr))
+    });
+    self_test
+}
+
+/// Runs the server with the configuration until Ctrl+C
+async fn run(mut config: ServerConfig) {
+    // Every run is seeded, so the seed of a run to repeat is in its output, the Welcome and the metrics
+    let spawn_seed = *config.spawn_seed.get_or_insert_with(rand::random);
+    println!("{}", BuildInfo::current(Some(spawn_seed)).describe());
+    if let Some(duration) = config.match_duration {
+        println!("Matches reset every {} seconds", duration.as_secs());
+    }
+    if config.processing_delay.is_enabled() {
+        println!("Simulating processing delay: {}", config.processing_delay.describe());
+    }
+    if config.tick_rate != DEFAULT_TICK_RATE {
+        println!("Ticking {} times per second", config.tick_rate);
+    }
+
+    // Bind the UDP socket to the specified address and start the server
+    let socket = match UdpSocket::bind(&config.bind).await {
+        Ok(socket) => Transport::new(socket, config.codec),
+        Err(e) => {
+            eprintln!("Failed to bind {}: {}", config.bind, e);
+            std::process::exit(1);
+        }
+    };
+    println!("Server running on {}", socket.socket().local_addr().unwrap());
+    match config.codec {
+        Some(codec) => println!("Speaking only the {} protocol", codec.name()),
+        None => println!("Speaking the bincode and json protocols"),
+    }
+
+    // Load the best scores of earlier runs so returning players keep them
+    let mut saved_records = HashMap::new();
+    if let Some(path) = &config.leaderboard_path {
+        match Leaderboard::load(path) {
+            Ok(leaderboard) => {
+                println!("Loaded {} leaderboard entries from {}", leaderboard.len(), path.display());
+                saved_records = leaderboard.player_records();
+            }
+            Err(e) => {
+                eprintln!("Failed to load leaderboard {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Rooms and their passwords from the config file, without one every room is created on its first join
+    let room_config = match &config.rooms_config {
+        Some(path) => match RoomConfig::load(path) {
+            Ok(room_config) => {
+                println!("Loaded {} rooms from {}{}", room_config.rooms.len(), path.display(), if room_config.fixed { " (fixed list)" } else { "" });
+                room_config
+            }
+            Err(e) => {
+                eprintln!("Failed to load room config {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => RoomConfig::default(),
+    };
+
+    // Every room has its own game
+    if config.team_count > 0 {
+        println!("Team mode: {} teams", config.team_count);
+    }
+    println!("Game mode: {}", config.mode.name());
+    let tuning = ServerTuning::default();
+    let rooms = create_rooms(&config, room_config, saved_records, tuning);
+    println!("Default room: {}", rooms.default_room());
+
+    // Stop on Ctrl+C so the leaderboard can be written one last time
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    run_server(socket, rooms, config, tuning, shutdown).await;
+}
+
+/// Tests for the command line of the server
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netcode_game::game_mode::ModeKind;
+
+    // Helper function to turn a list of strings into the arguments after the program name
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_subcommands() {
+        assert_eq!(parse_command(&args(&[])), Ok(Command::Run(ServerConfig::default())));
+        assert_eq!(parse_command(&args(&["help"])), Ok(Command::Help));
+        assert_eq!(parse_command(&args(&["--help"])), Ok(Command::Help));
+        assert_eq!(parse_command(&args(&["--self-test", "--teams", "2"])), Ok(Command::SelfTest(args(&["--teams", "2"]))));
+
+        let Ok(Command::Run(config)) = parse_command(&args(&["run", "--mode", "tag"])) else { panic!("not a run") };
+        assert_eq!(config.mode, ModeKind::Tag);
+        let Ok(Command::DumpConfig(config)) = parse_command(&args(&["dump-config", "--teams", "2"])) else { panic!("not a dump") };
+        assert_eq!(config.team_count, 2);
+    }
+
+    #[test]
+    fn test_flags_without_a_subcommand_run_the_server() {
+        let Ok(Command::Run(config)) = parse_command(&args(&["--match-minutes", "5"])) else { panic!("not a run") };
+        assert_eq!(config.match_duration, Some(std::time::Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn test_bad_commands_and_configs_are_errors() {
+        assert_eq!(parse_command(&args(&["serve"])), Err("unknown command serve, use run, dump-config, self-test or help".to_string()));
+        assert!(parse_command(&args(&["run", "--tick-rate", "1000"])).unwrap_err().contains("out of range"));
+        assert!(parse_command(&args(&["dump-config", "--teams"])).is_err());
+    }
+
+    #[test]
+    fn test_self_test_checks_the_server_configuration() {
+        let report = self_test(args(&["--bind", "127.0.0.1:0", "--mode", "tag"])).run();
+        assert!(report.passed(), "{}", report.report());
+        let last = report.results.last().unwrap();
+        assert_eq!(last.name, "server configuration");
+        assert!(last.detail.starts_with("tag mode, "), "{}", last.detail);
+
+        let report = self_test(args(&["--teams"])).run();
+        assert_eq!(report.exit_code(), 1);
+        assert!(report.report().contains("FAIL server configuration"));
+    }
+}
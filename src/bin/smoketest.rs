@@ -0,0 +1,260 @@
+use netcode_game::bot_script::{BotCommand, BotRunner, BotScript};
+use netcode_game::constants::{BOARD_HEIGHT, BOARD_WIDTH, PLAYER_SIZE, PLAYER_SPEED, TOOL_BAR_HEIGHT};
+use netcode_game::network::NetworkClient;
+use netcode_game::rooms::RoomConfig;
+use netcode_game::server::{create_rooms, run_server, ServerTuning};
+use netcode_game::server_config::ServerConfig;
+use netcode_game::smoke::{expect_near, SmokeRun};
+use netcode_game::transport::Transport;
+use netcode_game::types::{ClientMessage, Direction, GameState, PlayerInput, Position};
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+const PLAYER_TIMEOUT: Duration = Duration::from_secs(1); // Shortened so the dropped player check doesn't wait the full timeout
+const SERVER_PING_INTERVAL: Duration = Duration::from_millis(200); // Shortened so the server measures a ping early
+const CLIENT_PING_INTERVAL: Duration = Duration::from_millis(250); // How often both players ping the server
+const POSITION_TOLERANCE: f64 = 30.0; // How far the end position may be off, a few lost inputs or a late snapshot
+const STEP_TIMEOUT: Duration = Duration::from_secs(2); // How long a step may wait for the server
+const SCRIPT: &str = include_str!("../../scripts/bots/smoketest.toml"); // What the mover and the observer do once they see each other
+
+/// A scripted player, a network client without rendering or input handling
+struct Peer {
+    net: NetworkClient,
+    id: Option<Uuid>,
+    spawn: Option<Position>,
+    latest: Option<GameState>,
+    rtt: Option<Duration>,
+    started: Instant,
+    sequence: u32,
+    silent: bool, // Disconnected by the script, nothing is sent or read
+}
+
+/// Implementation of the Peer
+impl Peer {
+    /// Connects to the server with the network conditions turned off
+    fn connect(server: SocketAddr) -> Self {
+        let net = NetworkClient::new(&server.to_string());
+        net.set_conditions(0, 0, 0);
+        net.send_connect();
+        Self { net, id: None, spawn: None, latest: None, rtt: None, started: Instant::now(), sequence: 0, silent: false }
+    }
+
+    /// Takes everything received since the last call, answers server pings and keeps the newest snapshot.
+    /// A silent peer reads nothing, so it doesn't answer the pings either.
+    fn pump(&mut self) {
+        if self.silent {
+            return;
+        }
+        let frame = self.net.receive_frame(usize::MAX);
+        for message in frame.messages {
+            match message {
+                ClientMessage::Welcome(welcome) => {
+                    self.id = Some(welcome.id);
+                    self.spawn = Some(welcome.position);
+                }
+                ClientMessage::PlayerId(id) => self.id = Some(id),
+                ClientMessage::Pong(timestamp) => {
+                    self.rtt = Some(self.started.elapsed().saturating_sub(Duration::from_millis(timestamp)));
+                }
+                ClientMessage::ServerPing(nonce) => self.net.send_server_pong(nonce),
+                _ => {}
+            }
+        }
+        if let Some(snapshot) = frame.snapshots.into_iter().max_by_key(|snapshot| snapshot.snapshot_id) {
+            self.latest = Some(snapshot);
+        }
+    }
+
+    /// Pings the server with the time since the peer connected, unless the peer is silent
+    fn ping(&self) {
+        if !self.silent {
+            self.net.send_ping(self.started.elapsed().as_millis() as u64);
+        }
+    }
+
+    /// Does what the script says
+    fn apply(&mut self, command: BotCommand) {
+        match command {
+            BotCommand::Input(dir) => self.step(dir),
+            BotCommand::Disconnect => self.silent = true,
+            BotCommand::Reconnect => {
+                self.silent = false;
+                self.net.send_connect();
+            }
+        }
+    }
+
+    /// Sends the next input in the direction
+    fn step(&mut self, dir: Direction) {
+        self.sequence += 1;
+        let timestamp = self.started.elapsed().as_millis() as u64;
+        self.net.send_input(PlayerInput { dir, sequence: self.sequence, timestamp });
+    }
+
+    /// Position of a player in the newest snapshot
+    fn position_of(&self, id: Uuid) -> Option<Position> {
+        let snapshot = self.latest.as_ref()?;
+        snapshot.players.iter().find(|(player, _, _)| *player == id).map(|(_, position, _)| *position)
+    }
+}
+
+/// Runs a server in process, connects two scripted players and checks that they see each other,
+/// that movement and pings work and that a silent player is dropped
+fn main() {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start the runtime");
+    let socket = runtime.block_on(UdpSocket::bind("127.0.0.1:0")).expect("Failed to bind the server socket");
+    let server_addr = socket.local_addr().unwrap();
+
+    // The server with shortened timeouts, stopped once the checks are done
+    let tuning = ServerTuning { player_timeout: PLAYER_TIMEOUT, server_ping_interval: SERVER_PING_INTERVAL, ..ServerTuning::default() };
+    let config = ServerConfig::default();
+    let rooms = create_rooms(&config, RoomConfig::default(), HashMap::new(), tuning);
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = runtime.spawn(run_server(Transport::new(socket, None), rooms, config, tuning, async {
+        let _ = stopped.await;
+    }));
+
+    let mut run = SmokeRun::new(&format!("two players against a server on {}", server_addr));
+    scenario(&mut run, server_addr);
+
+    let _ = stop.send(());
+    let _ = runtime.block_on(server);
+    print!("{}", run.report());
+    std::process::exit(run.exit_code());
+}
+
+/// The checks, in order. A failed step ends the scenario when the later ones depend on it.
+fn scenario(run: &mut SmokeRun, server_addr: SocketAddr) {
+    let script = match BotScript::from_toml(SCRIPT) {
+        Ok(script) => script,
+        Err(e) => {
+            run.record("scenario script parses", Err(e), Duration::ZERO);
+            return;
+        }
+    };
+    let mut mover = Peer::connect(server_addr);
+    let mut observer = Peer::connect(server_addr);
+
+    // Both players are welcomed
+    let connected = run.wait_for("both players connect", STEP_TIMEOUT, || {
+        mover.pump();
+        observer.pump();
+        mover.id.zip(observer.id)
+    });
+    let Some((mover_id, observer_id)) = connected else {
+        return;
+    };
+
+    // The observer sees the mover in its snapshots
+    let Some(start) = run.wait_for("observer sees the mover", STEP_TIMEOUT, || {
+        mover.pump();
+        observer.pump();
+        observer.position_of(mover_id).filter(|_| mover.position_of(observer_id).is_some())
+    }) else {
+        return;
+    };
+
+    // Play the script with the walk turned towards free space, both players ping until the script silences one
+    let observer_at = observer.position_of(observer_id).or(observer.spawn).unwrap_or(start);
+    let walk = script.bot("mover").map_or(0, |bot| bot.input_count(script.input_interval)) as i32 * PLAYER_SPEED;
+    let dir = pick_direction(start, observer_at, walk);
+    let mut runners = script.turned(dir).runners();
+    let began = Instant::now();
+    let mut last_ping = began - CLIENT_PING_INTERVAL;
+    while !runners.iter().all(BotRunner::finished) {
+        for runner in &mut runners {
+            let peer = match runner.name() {
+                "mover" => &mut mover,
+                "observer" => &mut observer,
+                _ => continue,
+            };
+            for command in runner.advance(began.elapsed()) {
+                peer.apply(command);
+            }
+        }
+        if last_ping.elapsed() >= CLIENT_PING_INTERVAL {
+            mover.ping();
+            observer.ping();
+            last_ping = Instant::now();
+        }
+        mover.pump();
+        observer.pump();
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    // The mover is where its inputs took it once the last one is applied, seen by itself as the observer is silent by now
+    let (dx, dy) = offset(dir, mover.sequence as i32 * PLAYER_SPEED);
+    let expected = Position { x: start.x + dx, y: start.y + dy };
+    let seen = run.wait_for("last input applied", STEP_TIMEOUT, || {
+        mover.pump();
+        let acked = mover.latest.as_ref()?.last_processed.get(&mover_id).copied()?;
+        (acked >= mover.sequence).then(|| mover.position_of(mover_id)).flatten()
+    });
+    let moved = seen.unwrap_or(start);
+    let (along, across, expected_along) = match dir {
+        Direction::Left | Direction::Right => (moved.x, moved.y, expected.x),
+        Direction::Up | Direction::Down => (moved.y, moved.x, expected.y),
+    };
+    let across_start = if matches!(dir, Direction::Left | Direction::Right) { start.y } else { start.x };
+    run.check(&format!("mover walked {:?}", dir), || expect_near("position", along as f64, expected_along as f64, POSITION_TOLERANCE));
+    run.check("mover stayed on its line", || expect_near("cross position", across as f64, across_start as f64, 0.0));
+
+    // Pings are answered and the server measured the mover's round trip time
+    run.check("client round trip time", || match mover.rtt {
+        Some(rtt) => Ok(format!("{} ms", rtt.as_millis())),
+        None => Err("no pong received".to_string()),
+    });
+    run.check("server round trip time", || {
+        let ping = mover.latest.as_ref().and_then(|snapshot| snapshot.ping_ms.get(&mover_id).copied());
+        ping.map(|ping| format!("{} ms in the snapshots", ping)).ok_or_else(|| "no ping of the mover in the snapshots".to_string())
+    });
+
+    // The observer the script silenced is dropped after the player timeout
+    let mut last_ping = Instant::now();
+    run.wait_for("silent player dropped", PLAYER_TIMEOUT + STEP_TIMEOUT, || {
+        if last_ping.elapsed() >= CLIENT_PING_INTERVAL {
+            mover.ping();
+            last_ping = Instant::now();
+        }
+        mover.pump();
+        let snapshot = mover.latest.as_ref()?;
+        snapshot.players.iter().all(|(id, _, _)| *id != observer_id).then_some(())
+    });
+}
+
+/// First direction with room for a walk of distance pixels whose path stays clear of the other player,
+/// players that touch are knocked back which would throw the end position off
+fn pick_direction(start: Position, other: Position, distance: i32) -> Direction {
+    let directions = [Direction::Right, Direction::Left, Direction::Down, Direction::Up];
+    let fits = |dir: &Direction| {
+        let (dx, dy) = offset(*dir, distance);
+        let (x, y) = (start.x + dx, start.y + dy);
+        (PLAYER_SIZE..=BOARD_WIDTH - PLAYER_SIZE).contains(&x) && (PLAYER_SIZE..=BOARD_HEIGHT - PLAYER_SIZE - TOOL_BAR_HEIGHT).contains(&y)
+    };
+    let clear = |dir: &Direction| {
+        let (dx, dy) = offset(*dir, distance);
+        let (min_x, max_x) = (start.x.min(start.x + dx) - 2 * PLAYER_SIZE, start.x.max(start.x + dx) + 2 * PLAYER_SIZE);
+        let (min_y, max_y) = (start.y.min(start.y + dy) - 2 * PLAYER_SIZE, start.y.max(start.y + dy) + 2 * PLAYER_SIZE);
+        !((min_x..=max_x).contains(&other.x) && (min_y..=max_y).contains(&other.y))
+    };
+    directions.iter().find(|dir| fits(dir) && clear(dir))
+        .or_else(|| directions.iter().find(|dir| fits(dir)))
+        .copied()
+        .unwrap_or(Direction::Right)
+}
+
+/// Offset of distance pixels in the direction
+fn offset(dir: Direction, distance: i32) -> (i32, i32) {
+    match dir {
+        Direction::Up => (0, -distance),
+        Direction::Down => (0, distance),
+        Direction::Left => (-distance, 0),
+        Direction::Right => (distance, 0),
+    }
+}
@@ -0,0 +1,424 @@
+use crate::constants::BOT_INPUT_INTERVAL;
+use crate::types::Direction;
+
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// Verbs a script action can use
+const VERBS: [&str; 3] = ["move", "disconnect", "reconnect"];
+
+/// What a bot does at a point of its timeline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BotAction {
+    Move { dir: Direction, duration: Duration }, // Holds the direction, one input every input interval
+    Disconnect,                                  // Goes silent, the server times the player out
+    Reconnect,                                   // Connects again after a disconnect
+}
+
+/// An action with when it starts, counted from the start of the script
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedAction {
+    pub at: Duration,
+    pub action: BotAction,
+}
+
+/// Implementation of the TimedAction
+impl TimedAction {
+    /// When the action is over, a move lasts its duration and the others are instant
+    pub fn end(&self) -> Duration {
+        match self.action {
+            BotAction::Move { duration, .. } => self.at + duration,
+            BotAction::Disconnect | BotAction::Reconnect => self.at,
+        }
+    }
+}
+
+/// The timeline of one bot, its actions ordered by start without overlapping
+#[derive(Debug, Clone, PartialEq)]
+pub struct BotTimeline {
+    pub name: String,
+    pub actions: Vec<TimedAction>,
+}
+
+/// Implementation of the BotTimeline
+impl BotTimeline {
+    /// Number of inputs the moves of the bot send at the interval
+    pub fn input_count(&self, input_interval: Duration) -> u32 {
+        self.actions.iter()
+            .map(|action| match action.action {
+                BotAction::Move { duration, .. } => inputs_in(duration, input_interval),
+                BotAction::Disconnect | BotAction::Reconnect => 0,
+            })
+            .sum()
+    }
+}
+
+/// Timelines of scripted bots, read from a TOML file like scripts/bots/square.toml:
+/// input_interval_ms = 100
+/// [[bot]]
+/// name = "walker"
+/// actions = [{ at = 0.0, verb = "move", dir = "Right", for = 1.5 }, { at = 5.0, verb = "disconnect" }]
+/// Times are in seconds from the start of the script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BotScript {
+    pub input_interval: Duration, // Time between two inputs of a move
+    pub bots: Vec<BotTimeline>,
+}
+
+/// A script file as written, checked by BotScript::from_toml
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptFile {
+    input_interval_ms: Option<u64>,
+    #[serde(default)]
+    bot: Vec<BotFile>,
+}
+
+/// A bot of a script file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BotFile {
+    name: String,
+    #[serde(default)]
+    actions: Vec<ActionFile>,
+}
+
+/// An action of a script file, the verb decides which of the other fields it needs
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ActionFile {
+    at: f64,
+    verb: String,
+    dir: Option<Direction>,
+    #[serde(rename = "for")]
+    duration: Option<f64>,
+}
+
+/// Implementation of the BotScript
+impl BotScript {
+    /// Reads a script from a TOML file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::from_toml(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Parses a script from TOML text. Unknown verbs and keys, moves without a direction or duration,
+    /// actions that overlap and actions that don't fit the connection state, like moving while
+    /// disconnected, are errors naming the bot and the action.
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let file: ScriptFile = toml::from_str(text).map_err(|e| e.to_string().trim_end().to_string())?;
+        let input_interval = Duration::from_millis(file.input_interval_ms.unwrap_or(BOT_INPUT_INTERVAL.as_millis() as u64));
+        if input_interval.is_zero() {
+            return Err("input_interval_ms must be above 0".to_string());
+        }
+        if file.bot.is_empty() {
+            return Err("the script has no bots".to_string());
+        }
+
+        let mut bots: Vec<BotTimeline> = Vec::new();
+        for bot in file.bot {
+            if bot.name.trim().is_empty() {
+                return Err("every bot needs a name".to_string());
+            }
+            if bots.iter().any(|other| other.name == bot.name) {
+                return Err(format!("bot {} is in the script twice", bot.name));
+            }
+            let actions = parse_timeline(&bot.actions).map_err(|e| format!("bot {}: {}", bot.name, e))?;
+            bots.push(BotTimeline { name: bot.name, actions });
+        }
+        Ok(Self { input_interval, bots })
+    }
+
+    /// The timeline of the bot with the name
+    pub fn bot(&self, name: &str) -> Option<&BotTimeline> {
+        self.bots.iter().find(|bot| bot.name == name)
+    }
+
+    /// When the last action of any bot is over
+    pub fn duration(&self) -> Duration {
+        self.bots.iter().flat_map(|bot| bot.actions.iter().map(TimedAction::end)).max().unwrap_or_default()
+    }
+
+    /// The script with every move turned so that Right becomes the given direction, for walking a script
+    /// written for one side of the board towards wherever there is room
+    pub fn turned(&self, right_becomes: Direction) -> Self {
+        let mut turned = self.clone();
+        for action in turned.bots.iter_mut().flat_map(|bot| bot.actions.iter_mut()) {
+            if let BotAction::Move { dir, .. } = &mut action.action {
+                *dir = turn(*dir, right_becomes);
+            }
+        }
+        turned
+    }
+
+    /// A runner for every bot, in the order of the script
+    pub fn runners(&self) -> Vec<BotRunner> {
+        self.bots.iter().map(|bot| BotRunner::new(bot, self.input_interval)).collect()
+    }
+}
+
+/// Checks the actions of a bot and orders them by start
+fn parse_timeline(actions: &[ActionFile]) -> Result<Vec<TimedAction>, String> {
+    let mut timeline = Vec::new();
+    for (index, action) in actions.iter().enumerate() {
+        let number = index + 1;
+        let at = seconds(action.at).ok_or_else(|| format!("action {} starts at {} s, use a time of 0 or later", number, action.at))?;
+        let parsed = match (action.verb.as_str(), action.dir, action.duration) {
+            ("move", Some(dir), Some(duration)) => {
+                let duration = seconds(duration).filter(|duration| !duration.is_zero())
+                    .ok_or_else(|| format!("action {} moves for {} s, use a duration above 0", number, duration))?;
+                BotAction::Move { dir, duration }
+            }
+            ("move", _, _) => return Err(format!("action {} moves without a dir and a for", number)),
+            ("disconnect" | "reconnect", None, None) => {
+                if action.verb == "disconnect" { BotAction::Disconnect } else { BotAction::Reconnect }
+            }
+            ("disconnect" | "reconnect", _, _) => return Err(format!("action {} is a {}, which takes no dir or for", number, action.verb)),
+            (verb, _, _) => return Err(format!("action {} has the unknown verb {}, use {}", number, verb, VERBS.join(", "))),
+        };
+        timeline.push(TimedAction { at, action: parsed });
+    }
+    timeline.sort_by_key(|action| action.at);
+
+    for (previous, action) in timeline.iter().zip(timeline.iter().skip(1)) {
+        if action.at < previous.end() {
+            return Err(format!("the action at {} s starts before the one at {} s is over at {} s", secs(action.at), secs(previous.at), secs(previous.end())));
+        }
+    }
+    let mut connected = true;
+    for action in &timeline {
+        connected = match (action.action, connected) {
+            (BotAction::Move { .. }, false) => return Err(format!("the move at {} s comes while disconnected", secs(action.at))),
+            (BotAction::Disconnect, false) => return Err(format!("the disconnect at {} s comes while disconnected", secs(action.at))),
+            (BotAction::Reconnect, true) => return Err(format!("the reconnect at {} s comes while connected", secs(action.at))),
+            (BotAction::Move { .. }, true) => true,
+            (BotAction::Disconnect, true) => false,
+            (BotAction::Reconnect, false) => true,
+        };
+    }
+    Ok(timeline)
+}
+
+/// Seconds of the file as a duration, None for negative or not finite times
+fn seconds(value: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(value).ok()
+}
+
+/// A duration in seconds for the error messages
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs_f64()
+}
+
+/// Inputs a move of the duration sends, the first right at its start
+fn inputs_in(duration: Duration, input_interval: Duration) -> u32 {
+    duration.as_nanos().div_ceil(input_interval.as_nanos()) as u32
+}
+
+/// Turns a direction by the turn that takes Right to right_becomes
+fn turn(dir: Direction, right_becomes: Direction) -> Direction {
+    let clockwise = [Direction::Up, Direction::Right, Direction::Down, Direction::Left];
+    let index = |dir: Direction| clockwise.iter().position(|other| *other == dir).unwrap_or(0);
+    clockwise[(index(dir) + index(right_becomes) + 3) % 4]
+}
+
+/// What a bot does now, for the code that drives its network client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BotCommand {
+    Input(Direction), // Send an input in the direction through the usual input path
+    Disconnect,
+    Reconnect,
+}
+
+/// Plays the timeline of one bot against a clock. The caller passes the time since the script started, and
+/// gets every command due by then: inputs of a move come at its start and then every input interval, so the
+/// commands and their order only depend on the timeline and the clock, not on how often the runner is asked.
+#[derive(Debug, Clone)]
+pub struct BotRunner {
+    name: String,
+    actions: Vec<TimedAction>,
+    input_interval: Duration,
+    next: usize,      // Action in progress or the next to start
+    inputs_sent: u32, // Inputs sent of the move in progress
+}
+
+/// Implementation of the BotRunner
+impl BotRunner {
+    /// A runner at the start of the timeline
+    pub fn new(timeline: &BotTimeline, input_interval: Duration) -> Self {
+        Self { name: timeline.name.clone(), actions: timeline.actions.clone(), input_interval, next: 0, inputs_sent: 0 }
+    }
+
+    /// Name of the bot
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The commands due by elapsed since the start of the script, in order
+    pub fn advance(&mut self, elapsed: Duration) -> Vec<BotCommand> {
+        let mut commands = Vec::new();
+        while let Some(action) = self.actions.get(self.next) {
+            match action.action {
+                BotAction::Move { dir, duration } => {
+                    let inputs = inputs_in(duration, self.input_interval);
+                    while self.inputs_sent < inputs && action.at + self.input_interval * self.inputs_sent <= elapsed {
+                        commands.push(BotCommand::Input(dir));
+                        self.inputs_sent += 1;
+                    }
+                    if self.inputs_sent < inputs {
+                        break;
+                    }
+                }
+                BotAction::Disconnect | BotAction::Reconnect if action.at > elapsed => break,
+                BotAction::Disconnect => commands.push(BotCommand::Disconnect),
+                BotAction::Reconnect => commands.push(BotCommand::Reconnect),
+            }
+            self.next += 1;
+            self.inputs_sent = 0;
+        }
+        commands
+    }
+
+    /// True once every command of the timeline was given out
+    pub fn finished(&self) -> bool {
+        self.next >= self.actions.len()
+    }
+}
+
+/// Tests for the bot scripts
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMOKETEST: &str = include_str!("../scripts/bots/smoketest.toml");
+    const SQUARE: &str = include_str!("../scripts/bots/square.toml");
+    const RECONNECT: &str = include_str!("../scripts/bots/reconnect.toml");
+
+    // Helper function to run a bot on a fake clock in steps of step_ms, with the time of every command in ms
+    fn simulate(runner: &mut BotRunner, until_ms: u64, step_ms: u64) -> Vec<(u64, BotCommand)> {
+        let mut emitted = Vec::new();
+        for now in (0..=until_ms).step_by(step_ms as usize) {
+            emitted.extend(runner.advance(Duration::from_millis(now)).into_iter().map(|command| (now, command)));
+        }
+        emitted
+    }
+
+    // Helper function to parse a script and check that the error mentions the text
+    fn rejects(text: &str, expected: &str) {
+        match BotScript::from_toml(text) {
+            Ok(script) => panic!("{:?} was accepted, expected an error with {}", script, expected),
+            Err(e) => assert!(e.contains(expected), "{} doesn't mention {}", e, expected),
+        }
+    }
+
+    #[test]
+    fn test_example_scripts_parse() {
+        let smoketest = BotScript::from_toml(SMOKETEST).unwrap();
+        assert_eq!(smoketest.input_interval, Duration::from_millis(100));
+        assert_eq!(smoketest.bot("mover").unwrap().input_count(smoketest.input_interval), 50);
+        assert_eq!(smoketest.bot("observer").unwrap().actions, [TimedAction { at: Duration::from_secs(6), action: BotAction::Disconnect }]);
+        assert_eq!(smoketest.duration(), Duration::from_secs(6));
+
+        let square = BotScript::from_toml(SQUARE).unwrap();
+        assert_eq!(square.bots.len(), 1);
+        assert_eq!(square.duration(), Duration::from_millis(5500));
+
+        let reconnect = BotScript::from_toml(RECONNECT).unwrap();
+        assert_eq!(reconnect.bots.iter().map(|bot| bot.name.as_str()).collect::<Vec<_>>(), ["flaky", "steady"]);
+        assert_eq!(reconnect.duration(), Duration::from_millis(11_500));
+    }
+
+    #[test]
+    fn test_square_emits_one_input_per_interval_and_returns() {
+        let script = BotScript::from_toml(SQUARE).unwrap();
+        let emitted = simulate(&mut script.runners()[0], 6000, 10);
+        assert_eq!(emitted.len(), 40);
+
+        // Ten inputs a side, the first at the start of the move and the rest 100 ms apart
+        for (side, dir) in [Direction::Right, Direction::Down, Direction::Left, Direction::Up].into_iter().enumerate() {
+            let times: Vec<u64> = emitted[side * 10..side * 10 + 10].iter()
+                .map(|(at, command)| {
+                    assert_eq!(*command, BotCommand::Input(dir));
+                    *at
+                })
+                .collect();
+            let start = side as u64 * 1500;
+            assert_eq!(times, (0..10).map(|input| start + input * 100).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_reconnect_script_sequence() {
+        let script = BotScript::from_toml(RECONNECT).unwrap();
+        let mut runners = script.runners();
+        let flaky = simulate(&mut runners[0], 12_000, 50);
+        assert!(runners[0].finished());
+
+        let inputs = |dir| (0..15).map(move |input| (BotCommand::Input(dir), input * 100));
+        let expected: Vec<(BotCommand, u64)> = inputs(Direction::Right).map(|(command, at)| (command, 2000 + at))
+            .chain([(BotCommand::Disconnect, 5000), (BotCommand::Reconnect, 8000)])
+            .chain(inputs(Direction::Left).map(|(command, at)| (command, 10_000 + at)))
+            .collect();
+        assert_eq!(flaky.into_iter().map(|(at, command)| (command, at)).collect::<Vec<_>>(), expected);
+
+        let steady = simulate(&mut runners[1], 12_000, 50);
+        assert_eq!(steady.len(), 30);
+        assert_eq!(runners[1].name(), "steady");
+    }
+
+    #[test]
+    fn test_a_late_clock_catches_up_in_order() {
+        let script = BotScript::from_toml(RECONNECT).unwrap();
+        let mut fine = script.runners().remove(0);
+        let mut coarse = script.runners().remove(0);
+        let fine: Vec<BotCommand> = simulate(&mut fine, 12_000, 10).into_iter().map(|(_, command)| command).collect();
+
+        // Asked seldom, the runner gives out everything due at once, nothing before its time
+        assert_eq!(coarse.advance(Duration::from_millis(1999)), []);
+        let mut late = coarse.advance(Duration::from_millis(9000));
+        assert!(!coarse.finished());
+        late.extend(coarse.advance(Duration::from_secs(60)));
+        assert!(coarse.finished() && coarse.advance(Duration::from_secs(61)).is_empty());
+        assert_eq!(late, fine);
+    }
+
+    #[test]
+    fn test_invalid_scripts_are_rejected() {
+        rejects("[[bot]]\nname = \"a\"\nactions = [{ at = 1.0, verb = \"dash\" }]", "action 1 has the unknown verb dash, use move, disconnect, reconnect");
+        rejects(
+            "[[bot]]\nname = \"a\"\nactions = [{ at = 0.0, verb = \"move\", dir = \"Up\", for = 2.0 }, { at = 1.5, verb = \"disconnect\" }]",
+            "bot a: the action at 1.5 s starts before the one at 0 s is over at 2 s",
+        );
+        rejects("[[bot]]\nname = \"a\"\nactions = [{ at = 1.0, verb = \"disconnect\" }, { at = 2.0, verb = \"move\", dir = \"Up\", for = 1.0 }]", "the move at 2 s comes while disconnected");
+        rejects("[[bot]]\nname = \"a\"\nactions = [{ at = 1.0, verb = \"reconnect\" }]", "the reconnect at 1 s comes while connected");
+        rejects("[[bot]]\nname = \"a\"\nactions = [{ at = 1.0, verb = \"move\", dir = \"Up\" }]", "moves without a dir and a for");
+        rejects("[[bot]]\nname = \"a\"\nactions = [{ at = 1.0, verb = \"move\", dir = \"Up\", for = 0.0 }]", "use a duration above 0");
+        rejects("[[bot]]\nname = \"a\"\nactions = [{ at = -1.0, verb = \"disconnect\" }]", "use a time of 0 or later");
+        rejects("[[bot]]\nname = \"a\"\nactions = [{ at = 1.0, verb = \"disconnect\", for = 1.0 }]", "takes no dir or for");
+        rejects("[[bot]]\nname = \"a\"\n[[bot]]\nname = \"a\"", "bot a is in the script twice");
+        rejects("[[bot]]\nname = \"a\"\nactions = [{ at = 1.0, verb = \"move\", dir = \"Sideways\", for = 1.0 }]", "Sideways");
+        rejects("input_interval_ms = 0\n[[bot]]\nname = \"a\"", "input_interval_ms must be above 0");
+        rejects("", "the script has no bots");
+
+        // Actions may be written out of order, they are sorted by their start
+        let script = BotScript::from_toml("[[bot]]\nname = \"a\"\nactions = [{ at = 3.0, verb = \"reconnect\" }, { at = 1.0, verb = \"disconnect\" }]").unwrap();
+        assert_eq!(script.bots[0].actions.iter().map(|action| action.action).collect::<Vec<_>>(), [BotAction::Disconnect, BotAction::Reconnect]);
+        assert_eq!(script.input_interval, BOT_INPUT_INTERVAL);
+    }
+
+    #[test]
+    fn test_turned_rotates_every_move() {
+        let script = BotScript::from_toml(SQUARE).unwrap();
+        let dirs = |script: &BotScript| -> Vec<Direction> {
+            script.bots[0].actions.iter()
+                .filter_map(|action| match action.action {
+                    BotAction::Move { dir, .. } => Some(dir),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(dirs(&script.turned(Direction::Right)), dirs(&script));
+        assert_eq!(dirs(&script.turned(Direction::Down)), [Direction::Down, Direction::Left, Direction::Up, Direction::Right]);
+        assert_eq!(dirs(&script.turned(Direction::Left)), [Direction::Left, Direction::Up, Direction::Right, Direction::Down]);
+    }
+}
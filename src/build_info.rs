@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+
+/// Version of the crate this was built from
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short hash of the git commit this was built from, "unknown" outside a git checkout
+pub const GIT_HASH: &str = env!("NETCODE_GIT_HASH");
+/// Cargo profile this was built with, such as debug or release
+pub const PROFILE: &str = env!("NETCODE_BUILD_PROFILE");
+
+/// Build and random seed of one side of a session, so a bug report can be matched with the exact build and
+/// replayed with the same randomness. The server sends its own in the Welcome, reports and exports carry both.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub version: String,
+    pub git_hash: String,
+    pub profile: String,
+    pub seed: Option<u64>, // Spawn seed of a server room or simulation seed of a client, None if not seeded
+}
+
+/// Implementation of the BuildInfo
+impl BuildInfo {
+    /// The build of this binary with the seed it runs with
+    pub fn current(seed: Option<u64>) -> Self {
+        Self { version: VERSION.to_string(), git_hash: GIT_HASH.to_string(), profile: PROFILE.to_string(), seed }
+    }
+
+    /// One line of space separated key=value pairs, as written into report and CSV headers
+    pub fn describe(&self) -> String {
+        let seed = self.seed.map_or_else(|| "none".to_string(), |seed| seed.to_string());
+        format!("version={} git={} profile={} seed={}", self.version, self.git_hash, self.profile, seed)
+    }
+
+    /// Reads a line written by describe back, None if a key is missing or the seed isn't a number
+    pub fn parse(line: &str) -> Option<Self> {
+        let value = |key: &str| {
+            line.split_whitespace()
+                .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+                .map(str::to_string)
+        };
+        let seed = match value("seed")?.as_str() {
+            "none" => None,
+            seed => Some(seed.parse().ok()?),
+        };
+        Some(Self { version: value("version")?, git_hash: value("git")?, profile: value("profile")?, seed })
+    }
+
+    /// Comment line naming a side and its build, for the top of CSV exports. A side whose build isn't
+    /// known, such as a server that hasn't welcomed the client, is written as unknown.
+    pub fn csv_comment(side: &str, build: Option<&BuildInfo>) -> String {
+        format!("# {} {}\n", side, build.map_or_else(|| "unknown".to_string(), BuildInfo::describe))
+    }
+
+    /// Reads a comment line written by csv_comment back into the side and its build
+    pub fn parse_csv_comment(line: &str) -> Option<(String, Option<BuildInfo>)> {
+        let (side, rest) = line.strip_prefix("# ")?.split_once(' ')?;
+        let build = if rest.trim() == "unknown" { None } else { Some(Self::parse(rest)?) };
+        Some((side.to_string(), build))
+    }
+}
+
+/// Tests for the build info
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_build_is_filled_in() {
+        let build = BuildInfo::current(Some(42));
+        assert_eq!(build.version, env!("CARGO_PKG_VERSION"));
+        assert!(!build.git_hash.is_empty() && !build.git_hash.contains(char::is_whitespace));
+        assert!(!build.profile.is_empty());
+        assert_eq!(build.seed, Some(42));
+    }
+
+    #[test]
+    fn test_described_build_parses_back() {
+        let build = BuildInfo { version: "1.2.3".to_string(), git_hash: "0123456789ab".to_string(), profile: "release".to_string(), seed: Some(7) };
+        assert_eq!(build.describe(), "version=1.2.3 git=0123456789ab profile=release seed=7");
+        assert_eq!(BuildInfo::parse(&build.describe()), Some(build.clone()));
+        let unseeded = BuildInfo { seed: None, ..build.clone() };
+        assert_eq!(BuildInfo::parse(&unseeded.describe()), Some(unseeded));
+
+        // Comment lines name the side, an unknown build parses as None
+        assert_eq!(BuildInfo::parse_csv_comment(&BuildInfo::csv_comment("server", Some(&build))), Some(("server".to_string(), Some(build))));
+        assert_eq!(BuildInfo::parse_csv_comment(&BuildInfo::csv_comment("server", None)), Some(("server".to_string(), None)));
+        assert_eq!(BuildInfo::parse("version=1.2.3 git=abc profile=debug seed=many"), None);
+        assert_eq!(BuildInfo::parse_csv_comment("unix_ms,instance"), None);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::client_world::SnapshotOutcome;
+use crate::movement::MovementTunables;
+use crate::network::SnapshotBurst;
+use crate::types::{ClientMessage, DisconnectReason, GameState, RejectReason};
+use crate::warnings::WarningChange;
+
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+/// Where the client is with the server, as far as the client can tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    #[default]
+    Disconnected, // Not connected, refused or removed by the server
+    Connecting,   // A connect or reconnect was sent and not answered yet
+    Connected,    // The server welcomed the local player
+}
+
+/// Something that happened to the client, queued by the client world and the game client as it happened
+/// and taken once per frame. Every consumer, the sounds, the frame captures, the warning log and the
+/// screen reader output, picks the events it cares about from the same queue, so they all agree on when
+/// something happened without each of them looking for the change on its own.
+#[derive(Debug, PartialEq)]
+pub enum ClientEvent {
+    Joined { id: Uuid, session_token: Option<Uuid> }, // The server took our connect, with the session token of a welcome
+    ConnectionStateChanged(ConnectionState),          // The connection moved on, only sent when it changed
+    MatchStarted { match_number: u32 },                // The local player respawned for a new match
+    Rejected(RejectReason),                            // The server refused the connect
+    Incompatible { server_digest: u64 },               // The server moves players by tunables this client can't learn, it wasn't joined
+    TunablesAdopted(MovementTunables),                 // The server moves players by other tunables, the prediction uses them now
+    Removed(Option<DisconnectReason>),                 // The server removed us, None when only the snapshots stopped listing us
+    BurstCovered(SnapshotBurst),                       // Too many snapshots arrived at once, the skipped ones are covered by extra delay
+    PlayerJoined { id: Uuid },                         // A remote player appeared in the snapshots
+    PlayerLeft { id: Uuid },                           // A remote player is no longer in the snapshots
+    ScoreChanged { score: u32, previous: u32, pellets: bool }, // The local score changed, pellets if the board had pellets
+    SnapCorrection { magnitude: f32 },                 // The prediction was snapped to the server, this many pixels off
+    SnapshotGap { count: u64 },                        // Snapshots went missing right before the one applied
+    SnapshotDiscarded { snapshot_id: u64, anomalies: usize, players: usize }, // Most players of a snapshot were off the board, it was probably of another room or session
+    SpikeDropped { id: Uuid, total: u32 },             // A remote player's position jumped too far and was left out of its interpolation, total spikes of the player so far
+    Snapshot(SnapshotOutcome),                         // A snapshot was applied, with its prediction error
+    Warning(WarningChange),                            // A live metric warning was raised or cleared
+    PredictionDisabled,                                // The prediction kept going wrong, the local player follows the snapshots until it is turned back on
+    Resynced { paused: f64 },                          // The client stood still for this many seconds, the timing started over
+    Message(ClientMessage),                            // Any other message, such as a room list
+}
+
+/// Finds the joins, leaves and score changes in a snapshot by comparing it with the one before
+#[derive(Debug, Default)]
+pub struct TransitionDetector {
+    players: Option<BTreeSet<Uuid>>, // Remote players in the last snapshot, None before the first one
+    score: Option<u32>,              // Local score in the last snapshot
+}
+
+/// Implementation of the TransitionDetector
+impl TransitionDetector {
+    /// Creates a detector that takes its first snapshot as the baseline
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Events of a snapshot: a join or leave for every remote player that appeared or disappeared, in id
+    /// order, and a score change when the local score differs from the last snapshot that had it.
+    /// The first snapshot after a reset only sets the baseline.
+    pub fn observe(&mut self, state: &GameState, my_id: Option<Uuid>) -> Vec<ClientEvent> {
+        let mut events = Vec::new();
+        let players: BTreeSet<Uuid> = state.players.iter()
+            .map(|(id, _, _)| *id)
+            .filter(|id| Some(*id) != my_id)
+            .collect();
+        if let Some(previous) = &self.players {
+            events.extend(players.difference(previous).map(|id| ClientEvent::PlayerJoined { id: *id }));
+            events.extend(previous.difference(&players).map(|id| ClientEvent::PlayerLeft { id: *id }));
+        }
+        self.players = Some(players);
+
+        let score = my_id.and_then(|id| state.scores.get(&id).copied());
+        if let Some((score, previous)) = score.zip(self.score).filter(|(score, previous)| score != previous) {
+            events.push(ClientEvent::ScoreChanged { score, previous, pellets: !state.pellets.is_empty() });
+        }
+        if score.is_some() {
+            self.score = score;
+        }
+        events
+    }
+
+    /// Forgets the last snapshot, e.g. after a reconnect
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Tests for the client events
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MatchPhase, Position};
+
+    use std::collections::HashMap;
+
+    // Helper function to create a snapshot with the players, the scores and optionally a pellet
+    fn snapshot(players: &[Uuid], scores: &[(Uuid, u32)], pellets: bool) -> GameState {
+        GameState {
+            players: players.iter().map(|id| (*id, Position { x: 100, y: 100 }, 0)).collect(),
+            scores: scores.iter().copied().collect(),
+            last_processed: HashMap::new(),
+            pellets: if pellets { vec![Position { x: 50, y: 50 }] } else { Vec::new() },
+            best_scores: HashMap::new(),
+            teams: HashMap::new(),
+            input_reorders: HashMap::new(),
+            shapes: HashMap::new(),
+            impulses: HashMap::new(),
+            input_timings: HashMap::new(),
+            match_phase: MatchPhase::default(),
+            server_timestamp: 0,
+            match_number: 0,
+            ping_ms: HashMap::new(),
+            snapshot_id: 0,
+            tick_interval_ms: 50,
+            tagged: None,
+            movement_stats: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_joins_and_leaves_of_remote_players() {
+        let (me, a, b, c) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3), Uuid::from_u128(4));
+        let mut detector = TransitionDetector::new();
+
+        // The first snapshot is the baseline, players already there don't count as joins
+        assert!(detector.observe(&snapshot(&[me, a], &[], false), Some(me)).is_empty());
+        assert_eq!(detector.observe(&snapshot(&[me, c, a, b], &[], false), Some(me)), vec![
+            ClientEvent::PlayerJoined { id: b },
+            ClientEvent::PlayerJoined { id: c },
+        ]);
+        assert_eq!(detector.observe(&snapshot(&[me, b], &[], false), Some(me)), vec![
+            ClientEvent::PlayerLeft { id: a },
+            ClientEvent::PlayerLeft { id: c },
+        ]);
+
+        // The local player coming and going isn't a join or leave
+        assert!(detector.observe(&snapshot(&[b], &[], false), Some(me)).is_empty());
+
+        detector.reset();
+        assert!(detector.observe(&snapshot(&[a], &[], false), Some(me)).is_empty());
+    }
+
+    #[test]
+    fn test_score_changes_of_the_local_player() {
+        let (me, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let mut detector = TransitionDetector::new();
+
+        assert!(detector.observe(&snapshot(&[me], &[(me, 2)], true), Some(me)).is_empty());
+        assert_eq!(detector.observe(&snapshot(&[me], &[(me, 3)], true), Some(me)), vec![ClientEvent::ScoreChanged { score: 3, previous: 2, pellets: true }]);
+
+        // Another player scoring is none of ours, the score going back to zero in a new match is a change
+        assert!(detector.observe(&snapshot(&[me], &[(me, 3), (other, 1)], true), Some(me)).is_empty());
+        assert_eq!(detector.observe(&snapshot(&[me], &[(me, 0)], true), Some(me)), vec![ClientEvent::ScoreChanged { score: 0, previous: 3, pellets: true }]);
+
+        // A snapshot without the score keeps the last one
+        assert!(detector.observe(&snapshot(&[me], &[], false), Some(me)).is_empty());
+        assert_eq!(detector.observe(&snapshot(&[me], &[(me, 1)], false), Some(me)), vec![ClientEvent::ScoreChanged { score: 1, previous: 0, pellets: false }]);
+    }
+}
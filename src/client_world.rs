@@ -0,0 +1,1084 @@
+use crate::build_info::BuildInfo;
+use crate::client_events::{ClientEvent, TransitionDetector};
+use crate::constants::{HEATMAP_COLUMNS, PLAYER_SIZE, HEATMAP_ROWS, LATENCY_OVERLAY_WINDOW, LOCAL_SNAPSHOT_HISTORY, MATCH_COUNTDOWN, REMOVED_AFTER_MISSING_SNAPSHOTS, SNAPSHOT_BOARD_MARGIN, SNAPSHOT_DEDUPE_WINDOW};
+use crate::heatmap::ErrorHeatmap;
+use crate::input_log::InputLog;
+use crate::interpolation::{DelayBump, InterpolationState, PositionAt, RemotePlayers, ServerClock};
+use crate::latency::{InputJourney, LatencyBreakdown, LatencyBudget, LatencyTracker};
+use crate::network::{burst_delay, NetworkClient, SnapshotBurst};
+use crate::overlap::{find_overlaps, OverlapPair};
+use crate::positions::{AuthoritativePos, DisplayPos};
+use crate::prediction::{classify_prediction_error, expected_error_baseline, ErrorClass, MovementSpeed, PredictionConfig, PredictionState};
+use crate::prediction_watchdog::PredictionWatchdog;
+use crate::profile_scope;
+use crate::send_rate::LossEstimator;
+use crate::split_view::PositionSource;
+use crate::tick_cadence::TickCadence;
+use crate::types::{GameState, MatchPhase, MovementStats, PlayerInput, PlayerShape, Position, Welcome};
+use crate::util::RingHistory;
+use crate::visuals::PlayerVisuals;
+use crate::warnings::MetricWindow;
+
+use std::collections::HashMap;
+use std::time::Instant;
+use uuid::Uuid;
+
+/// What a snapshot changed that the caller reacts to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotOutcome {
+    pub prediction_error: Option<f32>,         // Error of the local player, None if the snapshot didn't reconcile it
+    pub prediction_error_at: Option<Position>, // Server position of the local player the error was measured at
+    pub removed: bool,                         // The local player has been missing for long enough to have been removed, set once
+    pub anomalies: usize,                      // Players further off the board than SNAPSHOT_BOARD_MARGIN, their entries were dropped
+    pub discarded: bool,                       // The snapshot was dropped whole for its anomalies
+}
+
+/// How a player is placed on screen
+pub enum LocalControl {
+    Predicted,                             // Where the prediction puts it, moving with the inputs right away
+    Interpolated(Box<InterpolationState>), // Where the snapshots put it, like a remote player, while the prediction is off
+}
+
+/// Where the players were at a moment in the past, for experiments with hit detection on the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldAt {
+    pub remote_players: HashMap<Uuid, PositionAt>, // Every buffered remote player at the server time
+    pub local_player: PositionAt,                  // The local player right before the input was applied
+}
+
+/// Game world as the client sees it, rebuilt from scratch on a soft reset. Holds the snapshot processing
+/// and prediction of the client without anything that draws, so the headless analysis client runs the same logic.
+pub struct ClientWorld {
+    pub prediction: PredictionState,
+    pub all_players: HashMap<Uuid, (Position, u32)>,
+    pub interpolated_positions: RemotePlayers, // Forgotten when stale even if no snapshot says the player left
+    pub server_clock: ServerClock,
+    pub burst_delay_bump: DelayBump,
+    pub my_id: Option<Uuid>,
+    pub handshake_pending: bool, // A connect was sent and its id hasn't arrived yet
+    pub my_pos: AuthoritativePos, // Predicted position of the local player, drawn through DisplayPos::from_authoritative
+    pub prediction_errors: HashMap<Uuid, f32>,
+    pub movement_speed: MovementSpeed, // Speed of the local player, for the expected prediction error
+    pub player_visuals: PlayerVisuals,
+    pub match_number: u32,
+    pub match_phase: MatchPhase, // Phase of the latest snapshot, players are frozen during a countdown
+    pub player_pings: HashMap<Uuid, u32>,
+    pub player_scores: HashMap<Uuid, u32>,
+    pub player_best_scores: HashMap<Uuid, u32>,
+    pub player_teams: HashMap<Uuid, u8>, // Empty unless the server runs in team mode
+    pub player_reorders: HashMap<Uuid, u32>, // Inputs of each player the server applied out of order in the last minute
+    pub player_movement: HashMap<Uuid, MovementStats>, // How far and how long each player moved since it joined
+    pub player_shapes: HashMap<Uuid, PlayerShape>,
+    pub pellets: Vec<Position>,
+    pub tagged: Option<Uuid>, // The player that is "it" when the server plays tag
+    pub input_log: InputLog,           // Recently sent inputs, shown in step mode
+    pub snapshot_loss: LossEstimator,  // Loss estimated from missing snapshots, drives the input send rate
+    pub latency: LatencyTracker,       // Breakdown of the newest input acknowledged by a snapshot
+    pub latency_window: LatencyBudget, // Inputs averaged for the next overlay update
+    pub latency_shown: LatencyBudget,  // Last complete window, shown in the overlay
+    pub tick_cadence: TickCadence,     // How fast the server really ticks, judged from when snapshots arrive
+    pub server_build: Option<BuildInfo>, // Build and spawn seed the server sent in its Welcome, for reports
+    pub metric_window: MetricWindow,   // Prediction errors and starvation since the last warning evaluation
+    pub error_heatmap: ErrorHeatmap,   // Prediction errors by where they were measured, for the heatmap overlay
+    pub transitions: TransitionDetector, // Joins, leaves and score changes found by comparing each snapshot with the one before
+    pub events: Vec<ClientEvent>,        // Found by the snapshots since the last take_events
+    pub seen_snapshots: RingHistory<u64, u64>, // Ids of the recently applied snapshots, a copy of one of them is skipped
+    pub duplicate_snapshots: u64,              // Snapshots skipped because one with the same id was already applied
+    pub missing_snapshots: u32,                // Snapshots in a row without the local player since it was last listed
+    pub local_control: LocalControl,           // Predicted unless the watchdog gave up on the prediction
+    pub prediction_watchdog: PredictionWatchdog, // Turns the prediction off when its errors stay pathological
+    pub local_snapshots: RingHistory<(u64, Position, u32), u64>, // Recent snapshot positions of the local player with their tick and tick interval
+    pub config: PredictionConfig,              // When to snap, whether to predict at all and whether the watchdog may turn it off
+}
+
+/// Implementation of the ClientWorld
+impl ClientWorld {
+    /// Creates an empty world with the local player at the initial position
+    pub fn new() -> Self {
+        let initial_position = AuthoritativePos::new(Position { x: 320, y: 240 });
+        Self {
+            prediction: PredictionState::new(initial_position),
+            all_players: HashMap::new(),
+            interpolated_positions: RemotePlayers::new(),
+            server_clock: ServerClock::new(),
+            burst_delay_bump: DelayBump::default(),
+            my_id: None,
+            handshake_pending: false,
+            my_pos: initial_position,
+            prediction_errors: HashMap::new(),
+            movement_speed: MovementSpeed::default(),
+            player_visuals: PlayerVisuals::new(),
+            match_number: 0,
+            match_phase: MatchPhase::default(),
+            player_pings: HashMap::new(),
+            player_scores: HashMap::new(),
+            player_best_scores: HashMap::new(),
+            player_teams: HashMap::new(),
+            player_reorders: HashMap::new(),
+            player_movement: HashMap::new(),
+            player_shapes: HashMap::new(),
+            pellets: Vec::new(),
+            tagged: None,
+            input_log: InputLog::new(),
+            snapshot_loss: LossEstimator::new(),
+            latency: LatencyTracker::new(),
+            latency_window: LatencyBudget::new(),
+            metric_window: MetricWindow::new(),
+            error_heatmap: ErrorHeatmap::new(HEATMAP_COLUMNS, HEATMAP_ROWS),
+            latency_shown: LatencyBudget::new(),
+            tick_cadence: TickCadence::new(),
+            server_build: None,
+            transitions: TransitionDetector::new(),
+            events: Vec::new(),
+            seen_snapshots: RingHistory::new(SNAPSHOT_DEDUPE_WINDOW, |id| *id),
+            duplicate_snapshots: 0,
+            local_control: LocalControl::Predicted,
+            prediction_watchdog: PredictionWatchdog::new(),
+            local_snapshots: RingHistory::new(LOCAL_SNAPSHOT_HISTORY, |(snapshot_id, _, _)| *snapshot_id),
+            missing_snapshots: 0,
+            config: PredictionConfig::default(),
+        }
+    }
+
+    /// Round trip time of the local player the server measured, None before it is in a snapshot
+    pub fn my_ping(&self) -> Option<u32> {
+        self.my_id.and_then(|id| self.player_pings.get(&id).copied())
+    }
+
+    /// Prediction error the current round trip time and movement speed are expected to cause
+    pub fn error_baseline(&self) -> f32 {
+        expected_error_baseline(self.my_ping(), self.movement_speed.speed())
+    }
+
+    /// Class of the latest prediction error of the local player, None before the first snapshot
+    pub fn my_error_class(&self) -> Option<ErrorClass> {
+        let error = self.my_id.and_then(|id| self.prediction_errors.get(&id))?;
+        Some(classify_prediction_error(*error, self.error_baseline()))
+    }
+}
+
+/// Implementation of the ClientWorld id handling
+impl ClientWorld {
+    /// Takes the id the server assigned in response to our connect, returns false for an id nobody asked for.
+    /// A different id than before means the server forgot us, e.g. after a restart: the old id is dropped
+    /// and comes back as a remote player only if snapshots still list it, and prediction starts over.
+    pub fn adopt_id(&mut self, id: Uuid) -> bool {
+        if !self.handshake_pending {
+            return false;
+        }
+        self.handshake_pending = false;
+
+        if let Some(old) = self.my_id.filter(|old| *old != id) {
+            self.all_players.remove(&old);
+            self.prediction_errors.remove(&old);
+            self.prediction.resume(self.my_pos, None);
+            self.movement_speed.reset();
+            self.input_log.clear();
+            self.latency.reset();
+        }
+
+        // The new id may have been seen as a remote player before
+        self.interpolated_positions.remove(&id);
+        self.my_id = Some(id);
+        true
+    }
+
+    /// Starts the session the server welcomed us to, continuing input sequences where the server left off.
+    /// The local player is known with its color and shape right away, so it is drawn right before the first
+    /// snapshot. Call after adopt_id took the id of the welcome.
+    pub fn welcome(&mut self, welcome: &Welcome) {
+        self.my_pos = AuthoritativePos::new(welcome.position);
+        self.all_players.insert(welcome.id, (welcome.position, welcome.color));
+        self.player_shapes.insert(welcome.id, welcome.shape);
+        self.prediction.resume(self.my_pos, welcome.last_processed);
+        self.server_build = welcome.build.clone();
+        self.latency.reset();
+        self.input_log.clear();
+        self.snapshot_loss.reset();
+        self.movement_speed.reset();
+        // Snapshot ids start over in a new room
+        self.seen_snapshots.clear();
+        self.tick_cadence.reset();
+        self.missing_snapshots = 0;
+    }
+}
+
+/// Implementation of the ClientWorld input handling
+impl ClientWorld {
+    /// Queues the inputs made this frame for sending and logs them
+    pub fn queue_inputs(&mut self, inputs: Vec<PlayerInput>, net: &mut NetworkClient) {
+        for input in inputs {
+            net.queue_input(input, Instant::now());
+            self.input_log.record(input);
+        }
+    }
+
+    /// Sends the queued inputs the send rate says are due and marks the inputs the network dropped
+    pub fn flush_inputs(&mut self, net: &mut NetworkClient) {
+        let rtt_ms = self.my_ping().map(f64::from);
+        net.update_send_rate(rtt_ms, self.snapshot_loss.loss(), self.prediction.pending_inputs.len());
+        net.flush_due_inputs(Instant::now());
+        for sequence in net.drain_dropped_inputs() {
+            self.input_log.mark_dropped(sequence);
+        }
+    }
+}
+
+/// Implementation of the ClientWorld snapshot handling
+impl ClientWorld {
+    /// Covers the snapshots skipped in a burst with extra interpolation delay
+    pub fn cover_burst(&mut self, burst: &SnapshotBurst, tick_interval_ms: u32, current_time: f64) {
+        self.burst_delay_bump.bump(burst_delay(burst, tick_interval_ms), current_time);
+    }
+
+    /// Applies a snapshot from the server: reconciles the prediction of the local player and feeds the
+    /// interpolation of the remote players. Render time is the time the world is drawn at, behind the
+    /// current time in slow motion. Returns the prediction error it caused and whether the local player
+    /// has now been missing for long enough to count as removed, and queues the joins, leaves, score
+    /// changes, snapshot gaps and corrections it brought. Nothing for a snapshot that was already applied
+    /// or is older than every remembered one.
+    pub fn apply_snapshot(&mut self, mut game_state: GameState, net: &NetworkClient, current_time: f64, render_time: f64) -> SnapshotOutcome {
+        if !self.seen_snapshots.insert(game_state.snapshot_id) {
+            self.duplicate_snapshots += 1;
+            return SnapshotOutcome::default();
+        }
+
+        // Players off the board would reach the interpolation and the overlays, a snapshot mostly made of them is dropped
+        let players = game_state.players.len();
+        let (anomalies, discard) = self.sanitize_players(&mut game_state);
+        if discard {
+            self.events.push(ClientEvent::SnapshotDiscarded { snapshot_id: game_state.snapshot_id, anomalies, players });
+            return SnapshotOutcome { anomalies, discarded: true, ..Default::default() };
+        }
+        let transitions = self.transitions.observe(&game_state, self.my_id);
+        self.events.extend(transitions);
+        let mut outcome = SnapshotOutcome { anomalies, ..Default::default() };
+
+        // Snapshots that keep coming without us mean the server removed us, even if its notice was lost
+        if let Some(my_id) = self.my_id.filter(|_| !self.handshake_pending) {
+            if game_state.players.iter().any(|(id, _, _)| *id == my_id) {
+                self.missing_snapshots = 0;
+            } else {
+                self.missing_snapshots += 1;
+                outcome.removed = self.missing_snapshots == REMOVED_AFTER_MISSING_SNAPSHOTS;
+            }
+        }
+
+        // New players fade in, players that are no longer in the game state fade out where they are drawn right now
+        let left = self.player_visuals.sync(game_state.players.iter().map(|(id, _, color)| (*id, *color)), current_time);
+        for id in left {
+            self.freeze_departed(&id, render_time, current_time);
+        }
+        self.player_pings = game_state.ping_ms;
+        self.player_scores = game_state.scores;
+        self.player_best_scores = game_state.best_scores;
+        self.player_teams = game_state.teams;
+        self.player_reorders = game_state.input_reorders;
+        // Only some snapshots carry the movement stats, in between the last ones are kept for the players still here
+        if game_state.movement_stats.is_empty() {
+            self.player_movement.retain(|id, _| game_state.players.iter().any(|(player, _, _)| player == id));
+        } else {
+            self.player_movement = game_state.movement_stats;
+        }
+        self.player_shapes = game_state.shapes;
+        self.pellets = game_state.pellets;
+        self.tagged = game_state.tagged;
+        self.match_phase = game_state.match_phase;
+        self.prediction.set_match_phase(game_state.match_phase);
+        let gap = self.snapshot_loss.record_snapshot(game_state.snapshot_id);
+        if gap > 0 {
+            self.events.push(ClientEvent::SnapshotGap { count: gap });
+        }
+
+        // Update interpolation states for other players, keyed by server tick
+        self.server_clock.observe(game_state.snapshot_id, game_state.tick_interval_ms, current_time);
+        self.tick_cadence.observe(game_state.snapshot_id, game_state.tick_interval_ms, current_time * 1000.0);
+        for (id, pos, _color) in &game_state.players {
+            if Some(*id) != self.my_id {
+                let rejected = self.interpolated_positions.get(id).map_or(0, InterpolationState::rejected_samples);
+                if let Some(evicted) = self.interpolated_positions.update(*id, *pos, game_state.snapshot_id, game_state.tick_interval_ms, current_time) {
+                    self.forget_remote_player(&evicted);
+                }
+                let interpolation = self.interpolated_positions.get(id).unwrap();
+                if interpolation.rejected_samples() > rejected {
+                    self.events.push(ClientEvent::SpikeDropped { id: *id, total: interpolation.rejected_samples() });
+                }
+            }
+        }
+
+        // Update all players map and check for prediction errors
+        for (id, pos, color) in &game_state.players {
+            if Some(*id) == self.my_id && game_state.match_number != self.match_number {
+                // A new match started and the reset message was lost, respawn from the snapshot
+                self.match_number = game_state.match_number;
+                self.reset_for_new_match(*pos);
+            } else if Some(*id) == self.my_id {
+                // Reconcile prediction with server state, including pushes from other players
+                profile_scope!("reconciliation");
+                let server_sequence = game_state.last_processed.get(id).copied().unwrap_or(0);
+                self.input_log.acknowledge(server_sequence);
+                if let Some(timing) = game_state.input_timings.get(id) {
+                    self.latency.on_snapshot(InputJourney {
+                        timing: *timing,
+                        send_delay_ms: net.input_send_delay(timing.sequence).map(|delay| delay.as_secs_f64() * 1000.0),
+                        snapshot_tick_ms: game_state.snapshot_id as f64 * game_state.tick_interval_ms as f64,
+                        snapshot_sent_ms: game_state.server_timestamp as f64,
+                        tick_interval_ms: game_state.tick_interval_ms as f64,
+                        received_at_ms: current_time * 1000.0,
+                        server_offset_ms: self.server_clock.estimate(current_time).map(|server_time| (current_time - server_time) * 1000.0),
+                    });
+                }
+                let server_position = AuthoritativePos::new(*pos);
+                self.prediction.reconcile_with_impulse(server_position, server_sequence, game_state.impulses.get(id).copied(), current_time);
+
+                // Calculate prediction error
+                let error = self.prediction.get_prediction_error(server_position);
+                self.prediction_errors.insert(*id, error);
+                self.metric_window.record_prediction_error(error);
+                self.error_heatmap.insert(*pos, error);
+                outcome.prediction_error = Some(error);
+                outcome.prediction_error_at = Some(*pos);
+
+                // A bad error snaps to the server position, otherwise pending inputs are reapplied
+                self.prediction_watchdog.record_error(current_time, error);
+                if self.config.snaps(error, self.error_baseline()) {
+                    self.prediction.snap_to(server_position);
+                    self.my_pos = server_position;
+                    self.movement_speed.reset();
+                    self.prediction_watchdog.record_resync(current_time);
+                } else {
+                    self.prediction.reapply_pending_inputs(&mut self.my_pos);
+                }
+            }
+            if Some(*id) == self.my_id {
+                self.record_local_snapshot(*pos, game_state.snapshot_id, game_state.tick_interval_ms);
+            }
+            self.all_players.insert(*id, (*pos, *color));
+        }
+
+        if self.prediction.take_correction() {
+            self.events.push(ClientEvent::SnapCorrection { magnitude: outcome.prediction_error.unwrap_or_default() });
+        }
+        outcome
+    }
+
+    /// Checks the players of a snapshot against the play area of the tunables the server sent. Players off it
+    /// by at most SNAPSHOT_BOARD_MARGIN are moved onto its edge, players further off are anomalies: a remote
+    /// one keeps where the last snapshot had it, or is left out if no snapshot had it yet. Returns the number
+    /// of anomalies and whether to discard the snapshot, which is when more than half of its players are
+    /// anomalies or the local player is one, since there is nothing to reconcile the prediction with then.
+    fn sanitize_players(&self, game_state: &mut GameState) -> (usize, bool) {
+        let (width, height) = self.prediction.tunables.play_area();
+        let players = game_state.players.len();
+        let mut anomalies = 0;
+        let mut local_anomaly = false;
+        game_state.players.retain_mut(|(id, position, _)| {
+            let on_board = (-SNAPSHOT_BOARD_MARGIN..=width + SNAPSHOT_BOARD_MARGIN).contains(&position.x)
+                && (-SNAPSHOT_BOARD_MARGIN..=height + SNAPSHOT_BOARD_MARGIN).contains(&position.y);
+            if on_board {
+                *position = Position { x: position.x.clamp(0, width), y: position.y.clamp(0, height) };
+                return true;
+            }
+            anomalies += 1;
+            local_anomaly |= Some(*id) == self.my_id;
+            match self.all_players.get(id) {
+                Some((last, _)) => {
+                    *position = *last;
+                    true
+                }
+                None => false,
+            }
+        });
+        (anomalies, local_anomaly || anomalies * 2 > players)
+    }
+
+    /// Takes the events the snapshots brought since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<ClientEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Resets local state when the server starts a new match
+    pub fn reset_for_new_match(&mut self, spawn: Position) {
+        let spawn = AuthoritativePos::new(spawn);
+        self.my_pos = spawn;
+        self.prediction.reset_to(spawn);
+        self.movement_speed.reset();
+
+        // A new match counts down before it starts, freeze right away instead of waiting for a snapshot
+        self.match_phase = MatchPhase::Countdown { remaining_ms: MATCH_COUNTDOWN.as_millis() as u32 };
+        self.prediction.set_match_phase(self.match_phase);
+
+        // Remote players also respawned, so don't interpolate across the jump
+        self.interpolated_positions.clear();
+        self.prediction_errors.clear();
+        self.local_snapshots.clear();
+        if let LocalControl::Interpolated(interpolation) = &mut self.local_control {
+            **interpolation = self.interpolated_positions.new_state();
+        }
+    }
+}
+
+/// Implementation of the ClientWorld pause handling
+impl ClientWorld {
+    /// Starts the timing over after the client stood still for a long time, such as through a laptop
+    /// suspend: the server clock, the buffers of the players drawn from snapshots and what is estimated
+    /// from when snapshots arrive. Nothing is played back or extrapolated across the gap, the next
+    /// snapshots fill the buffers again and players stay at their snapshot positions until then.
+    pub fn resync_after_pause(&mut self) {
+        self.server_clock = ServerClock::new();
+        self.burst_delay_bump = DelayBump::default();
+        self.interpolated_positions.clear();
+        self.tick_cadence.reset();
+        self.snapshot_loss.reset();
+        self.missing_snapshots = 0;
+        self.movement_speed.reset();
+        self.local_snapshots.clear();
+        if let LocalControl::Interpolated(interpolation) = &mut self.local_control {
+            **interpolation = self.interpolated_positions.new_state();
+        }
+    }
+}
+
+/// Implementation of the ClientWorld prediction fallback
+impl ClientWorld {
+    /// True while the local player is drawn where the prediction puts it
+    pub fn is_predicting(&self) -> bool {
+        matches!(self.local_control, LocalControl::Predicted)
+    }
+
+    /// Asks the watchdog whether the prediction keeps going wrong and if so draws the local player from
+    /// the snapshots instead. Inputs are still sent and predicted, only the drawing changes. Returns true
+    /// when the prediction was turned off by this call.
+    pub fn check_prediction(&mut self, current_time: f64) -> bool {
+        if !self.config.watchdog || !self.is_predicting() || !self.prediction_watchdog.tripped(current_time) {
+            return false;
+        }
+        self.fall_back_to_interpolation();
+        true
+    }
+
+    /// Draws the local player from the snapshots, seeding its interpolation with the recent ones so it
+    /// doesn't wait for a buffer to fill
+    pub fn fall_back_to_interpolation(&mut self) {
+        let mut interpolation = self.interpolated_positions.new_state();
+        for (snapshot_id, position, tick_interval_ms) in self.local_snapshots.iter() {
+            interpolation.add_position(*position, *snapshot_id, *tick_interval_ms);
+        }
+        self.local_control = LocalControl::Interpolated(Box::new(interpolation));
+    }
+
+    /// Turns the prediction back on from the newest snapshot position, and gives it a whole window before
+    /// the watchdog judges it again. Stays off when the config doesn't predict.
+    pub fn enable_prediction(&mut self) {
+        if !self.config.predict {
+            return;
+        }
+        if let Some((_, position, _)) = self.local_snapshots.newest().filter(|_| !self.is_predicting()) {
+            let server_position = AuthoritativePos::new(*position);
+            self.prediction.snap_to(server_position);
+            self.my_pos = server_position;
+            self.movement_speed.reset();
+        }
+        self.local_control = LocalControl::Predicted;
+        self.prediction_watchdog.reset();
+    }
+
+    /// Where the local player is drawn at a server time: predicted, or interpolated from the snapshots while
+    /// the prediction is off, at its snapshot position until the interpolation has something to play
+    pub fn local_display_position(&self, server_time: Option<f64>) -> DisplayPos {
+        match &self.local_control {
+            LocalControl::Predicted => DisplayPos::from_authoritative(self.my_pos),
+            LocalControl::Interpolated(interpolation) => server_time
+                .and_then(|server_time| interpolation.get_interpolated_position(server_time))
+                .or_else(|| self.local_snapshots.newest().map(|(_, position, _)| DisplayPos::from_authoritative(AuthoritativePos::new(*position))))
+                .unwrap_or_else(|| DisplayPos::from_authoritative(self.my_pos)),
+        }
+    }
+
+    /// Keeps a snapshot position of the local player for seeding its interpolation, and feeds the
+    /// interpolation while the prediction is off
+    fn record_local_snapshot(&mut self, position: Position, snapshot_id: u64, tick_interval_ms: u32) {
+        self.local_snapshots.insert((snapshot_id, position, tick_interval_ms));
+        if let LocalControl::Interpolated(interpolation) = &mut self.local_control {
+            interpolation.add_position(position, snapshot_id, tick_interval_ms);
+        }
+    }
+}
+
+/// Implementation of the ClientWorld frame handling
+impl ClientWorld {
+    /// Goes on with a replay of pending inputs too long for the last frame, and completes the breakdown of an
+    /// input acknowledged by the last snapshot, now that it reaches the screen. Returns the breakdown, once, for
+    /// the analysis.
+    pub fn present_frame(&mut self, current_time: f64) -> Option<LatencyBreakdown> {
+        self.prediction.continue_replay(&mut self.my_pos);
+        let breakdown = self.latency.on_frame(current_time * 1000.0)?;
+        self.latency_window.record(&breakdown);
+        if self.latency_window.inputs() >= LATENCY_OVERLAY_WINDOW {
+            self.latency_shown = std::mem::take(&mut self.latency_window);
+        }
+        Some(breakdown)
+    }
+
+    /// Server time the world is drawn at, None before the clock is synced
+    pub fn render_server_time(&self, render_time: f64, current_time: f64) -> Option<f64> {
+        self.server_clock.estimate(render_time - self.burst_delay_bump.current(current_time))
+    }
+
+    /// Share of remote players whose interpolation buffer ran dry, None without remote players
+    /// or before the clock is synced
+    pub fn starvation_share(&self, render_time: f64, current_time: f64) -> Option<f32> {
+        let server_time = self.render_server_time(render_time, current_time)?;
+        let remote: Vec<&InterpolationState> = self.interpolated_positions.iter()
+            .filter(|(id, _)| Some(**id) != self.my_id)
+            .map(|(_, interpolation)| interpolation)
+            .collect();
+        if remote.is_empty() {
+            return None;
+        }
+        let starved = remote.iter().filter(|interpolation| interpolation.is_starved(server_time)).count();
+        Some(starved as f32 / remote.len() as f32)
+    }
+
+    /// Forgets remote players no snapshot updated for a while, also while snapshots don't arrive,
+    /// and removes players once they have faded out
+    pub fn expire_players(&mut self, current_time: f64) {
+        for id in self.interpolated_positions.evict_stale(current_time) {
+            self.forget_remote_player(&id);
+        }
+        for id in self.player_visuals.update(current_time) {
+            self.all_players.remove(&id);
+            self.interpolated_positions.remove(&id);
+            self.prediction_errors.remove(&id);
+        }
+    }
+}
+
+/// Implementation of the ClientWorld remote player handling
+impl ClientWorld {
+    /// Pins a player that left to where it is drawn at this moment, the interpolated position if it has one and
+    /// its last snapshot position otherwise, and drops its interpolation buffer: nothing moves the fade out after this.
+    fn freeze_departed(&mut self, id: &Uuid, render_time: f64, current_time: f64) {
+        let server_time = self.render_server_time(render_time, current_time);
+        let interpolated = self.interpolated_positions.get(id)
+            .zip(server_time)
+            .and_then(|(interpolation, server_time)| interpolation.get_interpolated_position(server_time));
+        let last_known = || self.all_players.get(id).map(|(position, _)| DisplayPos::from_authoritative(AuthoritativePos::new(*position)));
+        if let Some(position) = interpolated.or_else(last_known) {
+            self.player_visuals.freeze_at(id, position);
+        }
+        self.interpolated_positions.remove(id);
+    }
+
+    /// Looks up the world at a server time, the time of the ticks without the interpolation delay, with the
+    /// local player where the prediction had it when it made the input with the sequence
+    pub fn world_at(&self, server_time: f64, sequence: u32) -> WorldAt {
+        WorldAt {
+            remote_players: self.interpolated_positions.iter()
+                .filter(|(id, _)| Some(**id) != self.my_id)
+                .map(|(id, interpolation)| (*id, interpolation.position_at(server_time)))
+                .collect(),
+            local_player: self.prediction.position_before(sequence),
+        }
+    }
+
+    /// Every player with where it is drawn from the source and its packed color. Smoothed has the local player
+    /// predicted and remote players as remote_position draws them at the server time. RawSnapshot has every
+    /// player, the local one included, where the latest snapshot put it; players fading out after they left
+    /// are no longer in the snapshots and are left out.
+    pub fn positions(&self, source: PositionSource, server_time: Option<f64>) -> Vec<(Uuid, DisplayPos, u32)> {
+        match source {
+            PositionSource::Smoothed => self.all_players.iter()
+                .map(|(id, (position, color))| {
+                    let position = if Some(*id) == self.my_id {
+                        self.local_display_position(server_time)
+                    } else {
+                        self.remote_position(id, *position, server_time)
+                    };
+                    (*id, position, *color)
+                })
+                .collect(),
+            PositionSource::RawSnapshot => self.all_players.iter()
+                .filter(|(id, _)| self.player_visuals.frozen_position(id).is_none())
+                .map(|(id, (position, color))| (*id, DisplayPos::from_authoritative(AuthoritativePos::new(*position)), *color))
+                .collect(),
+        }
+    }
+
+    /// Pairs of players drawn overlapping in the client view at the server time, the local player included
+    pub fn overlaps(&self, server_time: Option<f64>) -> Vec<OverlapPair> {
+        let drawn: Vec<(Uuid, DisplayPos)> = self.positions(PositionSource::Smoothed, server_time).into_iter()
+            .map(|(id, position, _)| (id, position))
+            .collect();
+        find_overlaps(&drawn, PLAYER_SIZE)
+    }
+
+    /// Where a remote player is drawn: frozen where it left while fading out, interpolated if possible,
+    /// the snapshot position otherwise
+    pub fn remote_position(&self, id: &Uuid, snapshot_position: Position, server_time: Option<f64>) -> DisplayPos {
+        if let Some(frozen) = self.player_visuals.frozen_position(id) {
+            return frozen;
+        }
+        self.interpolated_positions
+            .get(id)
+            .zip(server_time)
+            .and_then(|(interpol, server_time)| interpol.get_interpolated_position(server_time))
+            .unwrap_or_else(|| DisplayPos::from_authoritative(AuthoritativePos::new(snapshot_position)))
+    }
+
+    /// Drops what is known about a remote player whose interpolation buffer was evicted.
+    /// It comes back with the next snapshot that lists it.
+    pub fn forget_remote_player(&mut self, id: &Uuid) {
+        self.all_players.remove(id);
+        self.prediction_errors.remove(id);
+    }
+}
+
+/// Default implementation for the ClientWorld
+impl Default for ClientWorld {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tests for the ClientWorld
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_cues::Cue;
+    use crate::constants::INTERPOLATION_DELAY;
+    use crate::frame_capture::FrameMetrics;
+    use crate::types::Direction;
+
+    // Helper function to create a snapshot with the players at their positions
+    fn snapshot(snapshot_id: u64, players: &[(Uuid, Position)], last_processed: &[(Uuid, u32)]) -> GameState {
+        GameState {
+            players: players.iter().map(|(id, position)| (*id, *position, 0xFF0000)).collect(),
+            scores: HashMap::new(),
+            last_processed: last_processed.iter().copied().collect(),
+            pellets: Vec::new(),
+            best_scores: HashMap::new(),
+            teams: HashMap::new(),
+            input_reorders: HashMap::new(),
+            shapes: HashMap::new(),
+            impulses: HashMap::new(),
+            input_timings: HashMap::new(),
+            match_phase: MatchPhase::default(),
+            server_timestamp: 0,
+            match_number: 0,
+            ping_ms: HashMap::new(),
+            snapshot_id,
+            tick_interval_ms: 16,
+            tagged: None,
+            movement_stats: HashMap::new(),
+        }
+    }
+
+    // Helper function to create a world welcomed as the player
+    fn welcomed_world(id: Uuid, position: Position) -> ClientWorld {
+        let mut world = ClientWorld::new();
+        world.handshake_pending = true;
+        assert!(world.adopt_id(id));
+        world.welcome(&Welcome::new(id, Uuid::new_v4(), position, Some(0)));
+        world
+    }
+
+    // Helper function to get the server time of a fractional tick of the test snapshots
+    fn tick_time_of(tick: f64) -> f64 {
+        tick * 16.0 / 1000.0
+    }
+
+    #[test]
+    fn test_players_off_the_board_are_sanitized() {
+        let (me, a, b, c) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3), Uuid::from_u128(4));
+        let start = Position { x: 100, y: 100 };
+        let mut world = welcomed_world(me, start);
+        let net = NetworkClient::new("127.0.0.1:9");
+        let (width, height) = world.prediction.tunables.play_area();
+        let far = Position { x: width * 10, y: 300 };
+
+        // A little off the board is moved onto its edge
+        let outcome = world.apply_snapshot(snapshot(1, &[(me, start), (a, Position { x: -10, y: height + 20 }), (b, Position { x: 300, y: 300 })], &[]), &net, 0.0, 0.0);
+        assert_eq!((outcome.anomalies, outcome.discarded), (0, false));
+        assert_eq!(world.all_players[&a].0, Position { x: 0, y: height });
+
+        // Far off, a known player stays where it was and an unknown one is left out
+        let outcome = world.apply_snapshot(snapshot(2, &[(me, start), (a, Position { x: 0, y: height }), (b, far), (c, Position { x: i32::MIN, y: i32::MAX })], &[]), &net, 0.0, 0.0);
+        assert_eq!((outcome.anomalies, outcome.discarded), (2, false));
+        assert_eq!(world.all_players[&b].0, Position { x: 300, y: 300 });
+        assert!(!world.all_players.contains_key(&c));
+        assert!(!world.take_events().contains(&ClientEvent::PlayerJoined { id: c }));
+
+        // Mostly off the board is probably another room, the snapshot is dropped whole with a warning
+        let outcome = world.apply_snapshot(snapshot(3, &[(me, start), (a, far), (b, far), (c, far)], &[]), &net, 0.0, 0.0);
+        assert_eq!(outcome, SnapshotOutcome { anomalies: 3, discarded: true, ..Default::default() });
+        assert_eq!(world.take_events(), vec![ClientEvent::SnapshotDiscarded { snapshot_id: 3, anomalies: 3, players: 4 }]);
+        assert_eq!(world.all_players[&a].0, Position { x: 0, y: height });
+
+        // The local player off the board leaves nothing to reconcile with, it alone drops the snapshot
+        let outcome = world.apply_snapshot(snapshot(4, &[(me, far), (a, start), (b, start), (c, start)], &[]), &net, 0.0, 0.0);
+        assert!(outcome.discarded);
+        assert!(!world.all_players.contains_key(&c));
+        assert_eq!(world.all_players[&me].0, start);
+    }
+
+    #[test]
+    fn test_position_spikes_are_reported_as_events() {
+        let (me, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let start = Position { x: 300, y: 300 };
+        let mut world = welcomed_world(me, start);
+        let net = NetworkClient::new("127.0.0.1:9");
+
+        // One sample of the other player lands across the board, then it carries on where it was
+        for (snapshot_id, x, y) in [(1, 100, 100), (2, 105, 100), (3, 500, 300), (4, 115, 100)] {
+            world.apply_snapshot(snapshot(snapshot_id, &[(me, start), (other, Position { x, y })], &[]), &net, 0.0, 0.0);
+        }
+        let spikes: Vec<ClientEvent> = world.take_events().into_iter().filter(|event| matches!(event, ClientEvent::SpikeDropped { .. })).collect();
+        assert_eq!(spikes, vec![ClientEvent::SpikeDropped { id: other, total: 1 }]);
+    }
+
+    #[test]
+    fn test_snapshot_reconciles_the_prediction() {
+        let (me, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let start = Position { x: 100, y: 100 };
+        let mut world = welcomed_world(me, start);
+        let net = NetworkClient::new("127.0.0.1:9");
+
+        // Two inputs predicted, the server has applied the first one
+        for sequence in 1..3 {
+            let input = PlayerInput { dir: Direction::Right, sequence, timestamp: 0 };
+            world.prediction.pending_inputs.push_back((sequence, input));
+            world.prediction.next_sequence += 1;
+            world.prediction.apply_prediction(input, &mut world.my_pos);
+        }
+        let predicted = world.my_pos;
+        let server = Position { x: start.x + (predicted.x - start.x) / 2, y: start.y };
+        let outcome = world.apply_snapshot(snapshot(1, &[(me, server), (other, Position { x: 400, y: 300 })], &[(me, 1)]), &net, 0.0, 0.0);
+
+        assert_eq!(outcome.prediction_error, Some(0.0));
+        assert!(world.take_events().is_empty());
+        assert_eq!(world.my_pos, predicted);
+        assert_eq!(world.prediction.pending_inputs.len(), 1);
+        assert!(world.interpolated_positions.get(&other).is_some());
+        assert!(world.interpolated_positions.get(&me).is_none());
+        assert_eq!(world.all_players.len(), 2);
+    }
+
+    #[test]
+    fn test_far_off_prediction_snaps_with_a_correction() {
+        let me = Uuid::from_u128(1);
+        let mut world = welcomed_world(me, Position { x: 100, y: 100 });
+        let net = NetworkClient::new("127.0.0.1:9");
+
+        let server = Position { x: 400, y: 100 };
+        let outcome = world.apply_snapshot(snapshot(1, &[(me, server)], &[]), &net, 0.0, 0.0);
+        assert_eq!(outcome.prediction_error, Some(300.0));
+        assert_eq!(outcome.prediction_error_at, Some(server));
+        assert_eq!(world.take_events(), vec![ClientEvent::SnapCorrection { magnitude: 300.0 }]);
+        assert_eq!(world.my_pos.position(), server);
+        assert_eq!(world.my_error_class(), Some(ErrorClass::Bad));
+    }
+
+    #[test]
+    fn test_scripted_session_events_reach_every_consumer_alike() {
+        let (me, a, b) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let start = Position { x: 100, y: 100 };
+        let mut world = welcomed_world(me, start);
+        let net = NetworkClient::new("127.0.0.1:9");
+        let scored = |mut state: GameState, score: u32| {
+            state.scores.insert(me, score);
+            state.pellets.push(Position { x: 50, y: 50 });
+            state
+        };
+
+        // The events of each snapshot come first, then the snapshot itself, as the game client queues them
+        let apply = |world: &mut ClientWorld, state: GameState| {
+            let outcome = world.apply_snapshot(state, &net, 0.0, 0.0);
+            let mut events = world.take_events();
+            events.push(ClientEvent::Snapshot(outcome));
+            events
+        };
+
+        // The first snapshot is the baseline, the second one brings a join and a pellet, after two lost
+        // snapshots the third one has a player gone and the local player far from where it was predicted
+        let mut stream = apply(&mut world, scored(snapshot(1, &[(me, start), (a, start)], &[]), 0));
+        stream.extend(apply(&mut world, scored(snapshot(2, &[(me, start), (a, start), (b, start)], &[]), 1)));
+        stream.extend(apply(&mut world, scored(snapshot(5, &[(me, Position { x: 400, y: 100 }), (b, start)], &[]), 1)));
+        let snapshot_outcome = |error: f32, at: Position| ClientEvent::Snapshot(SnapshotOutcome { prediction_error: Some(error), prediction_error_at: Some(at), ..Default::default() });
+        assert_eq!(stream, vec![
+            snapshot_outcome(0.0, start),
+            ClientEvent::PlayerJoined { id: b },
+            ClientEvent::ScoreChanged { score: 1, previous: 0, pellets: true },
+            snapshot_outcome(0.0, start),
+            ClientEvent::PlayerLeft { id: a },
+            ClientEvent::SnapshotGap { count: 2 },
+            ClientEvent::SnapCorrection { magnitude: 300.0 },
+            snapshot_outcome(300.0, Position { x: 400, y: 100 }),
+        ]);
+
+        // The sounds and the capture triggers read the same stream and agree on what happened
+        let cues: Vec<Cue> = stream.iter().filter_map(Cue::for_event).collect();
+        assert_eq!(cues, vec![Cue::Join, Cue::Pickup, Cue::Leave, Cue::Correction]);
+        let mut frame = FrameMetrics::default();
+        for event in &stream {
+            frame.record_event(event);
+        }
+        assert_eq!((frame.prediction_error, frame.snapshot_gap), (Some(300.0), 2));
+        assert!(world.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_that_arrives_twice_is_applied_once() {
+        let me = Uuid::from_u128(1);
+        let mut world = welcomed_world(me, Position { x: 100, y: 100 });
+        let net = NetworkClient::new("127.0.0.1:9");
+
+        // The copy neither reconciles again nor plays the correction twice
+        let server = Position { x: 400, y: 100 };
+        world.apply_snapshot(snapshot(1, &[(me, server)], &[]), &net, 0.0, 0.0);
+        assert_eq!(world.take_events(), vec![ClientEvent::SnapCorrection { magnitude: 300.0 }]);
+        world.my_pos = AuthoritativePos::new(Position { x: 100, y: 100 });
+        assert_eq!(world.apply_snapshot(snapshot(1, &[(me, server)], &[]), &net, 0.0, 0.0), SnapshotOutcome::default());
+        assert!(world.take_events().is_empty());
+        assert_eq!(world.my_pos.position(), Position { x: 100, y: 100 });
+        assert_eq!(world.duplicate_snapshots, 1);
+
+        // A new room counts snapshot ids from the start again
+        world.handshake_pending = true;
+        world.adopt_id(me);
+        world.welcome(&Welcome::new(me, Uuid::new_v4(), server, None));
+        assert_eq!(world.apply_snapshot(snapshot(1, &[(me, server)], &[]), &net, 0.0, 0.0).prediction_error, Some(0.0));
+        assert_eq!(world.duplicate_snapshots, 1);
+    }
+
+    #[test]
+    fn test_snapshots_without_the_local_player_mean_it_was_removed() {
+        let (me, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let mut world = welcomed_world(me, Position { x: 100, y: 100 });
+        let net = NetworkClient::new("127.0.0.1:9");
+        let others = [(other, Position { x: 400, y: 300 })];
+
+        // A few missing snapshots are forgotten once we are listed again
+        for snapshot_id in 1..REMOVED_AFTER_MISSING_SNAPSHOTS as u64 {
+            assert!(!world.apply_snapshot(snapshot(snapshot_id, &others, &[]), &net, 0.0, 0.0).removed);
+        }
+        world.apply_snapshot(snapshot(100, &[(me, Position { x: 100, y: 100 })], &[]), &net, 0.0, 0.0);
+        assert_eq!(world.missing_snapshots, 0);
+
+        // Removal is reported once, when the missing snapshots reach the limit
+        let removed: Vec<bool> = (0..REMOVED_AFTER_MISSING_SNAPSHOTS as u64 + 5)
+            .map(|offset| world.apply_snapshot(snapshot(101 + offset, &others, &[]), &net, 0.0, 0.0).removed)
+            .collect();
+        assert_eq!(removed.iter().filter(|removed| **removed).count(), 1);
+        assert!(removed[REMOVED_AFTER_MISSING_SNAPSHOTS as usize - 1]);
+
+        // Nothing is counted while the handshake is still pending
+        world.handshake_pending = true;
+        world.apply_snapshot(snapshot(200, &others, &[]), &net, 0.0, 0.0);
+        assert_eq!(world.missing_snapshots, REMOVED_AFTER_MISSING_SNAPSHOTS + 5);
+    }
+
+    #[test]
+    fn test_world_at_a_past_server_time() {
+        let (me, walker, idle) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let mut world = welcomed_world(me, Position { x: 100, y: 100 });
+        let net = NetworkClient::new("127.0.0.1:9");
+        assert_eq!(world.world_at(0.0, 1).remote_players, HashMap::new());
+
+        // The walker moves 3 px per tick, the idle player only shows up in the last snapshot
+        for snapshot_id in 1..=10 {
+            let mut players = vec![(me, Position { x: 100, y: 100 }), (walker, Position { x: 200 + 3 * snapshot_id as i32, y: 300 })];
+            if snapshot_id == 10 {
+                players.push((idle, Position { x: 50, y: 50 }));
+            }
+            world.apply_snapshot(snapshot(snapshot_id, &players, &[]), &net, 0.0, 0.0);
+        }
+        let input = PlayerInput { dir: Direction::Down, sequence: 1, timestamp: 0 };
+        world.prediction.apply_prediction(input, &mut world.my_pos);
+
+        // Halfway between ticks 4 and 5 the walker is found exactly, the idle player is clamped to its only sample
+        let at = world.world_at(tick_time_of(4.5), 1);
+        assert_eq!(at.remote_players.len(), 2);
+        assert_eq!(at.remote_players[&walker], PositionAt::Exact(Position { x: 214, y: 300 }));
+        assert_eq!(at.remote_players[&idle], PositionAt::Clamped(Position { x: 50, y: 50 }));
+        assert_eq!(at.local_player, PositionAt::Exact(Position { x: 100, y: 100 }));
+
+        // Past the newest tick the walker is clamped, an input that wasn't made yet is unavailable
+        let later = world.world_at(tick_time_of(20.0), 2);
+        assert_eq!(later.remote_players[&walker], PositionAt::Clamped(Position { x: 230, y: 300 }));
+        assert_eq!(later.local_player, PositionAt::Unavailable);
+    }
+
+    #[test]
+    fn test_new_match_in_a_snapshot_respawns() {
+        let me = Uuid::from_u128(1);
+        let mut world = welcomed_world(me, Position { x: 100, y: 100 });
+        let net = NetworkClient::new("127.0.0.1:9");
+
+        let spawn = Position { x: 500, y: 200 };
+        let outcome = world.apply_snapshot(GameState { match_number: 1, ..snapshot(1, &[(me, spawn)], &[]) }, &net, 0.0, 0.0);
+        assert_eq!(outcome.prediction_error, None);
+        assert_eq!(world.match_number, 1);
+        assert_eq!(world.my_pos.position(), spawn);
+        assert!(matches!(world.match_phase, MatchPhase::Countdown { .. }));
+    }
+
+    #[test]
+    fn test_player_that_left_is_frozen_where_it_was_drawn() {
+        let (me, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let mut world = welcomed_world(me, Position { x: 100, y: 100 });
+        let net = NetworkClient::new("127.0.0.1:9");
+        let tick = 0.016;
+
+        // The other player walks right for a while, drawn behind the snapshots by the interpolation delay
+        let mut last = Position { x: 0, y: 0 };
+        for snapshot_id in 1..=20 {
+            last = Position { x: 200 + 3 * snapshot_id as i32, y: 300 };
+            world.apply_snapshot(snapshot(snapshot_id, &[(me, Position { x: 100, y: 100 }), (other, last)], &[]), &net, snapshot_id as f64 * tick, snapshot_id as f64 * tick);
+        }
+        let now = 20.5 * tick;
+        let server_time = world.render_server_time(now, now).unwrap();
+        let drawn = world.interpolated_positions.get(&other).unwrap().get_interpolated_position(server_time).unwrap();
+        assert_ne!(drawn.layout_position(), last);
+
+        // It is missing from the next snapshot: frozen where it was drawn and its buffer dropped
+        world.apply_snapshot(snapshot(21, &[(me, Position { x: 100, y: 100 })], &[]), &net, now, now);
+        assert_eq!(world.player_visuals.frozen_position(&other), Some(drawn));
+        assert!(world.interpolated_positions.get(&other).is_none());
+
+        // Later snapshots and time don't move the fade out
+        for snapshot_id in 22..=24 {
+            world.apply_snapshot(snapshot(snapshot_id, &[(me, Position { x: 100, y: 100 })], &[]), &net, snapshot_id as f64 * tick, snapshot_id as f64 * tick);
+            world.expire_players(snapshot_id as f64 * tick);
+        }
+        assert_eq!(world.player_visuals.frozen_position(&other), Some(drawn));
+        assert!(world.interpolated_positions.get(&other).is_none());
+    }
+
+    #[test]
+    fn test_movement_stats_are_kept_between_the_snapshots_that_carry_them() {
+        let (me, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let mut world = welcomed_world(me, Position { x: 100, y: 100 });
+        let net = NetworkClient::new("127.0.0.1:9");
+        let stats = MovementStats { distance: 500, inputs: 100, connected_ms: 4_000 };
+        let both = [(me, Position { x: 100, y: 100 }), (other, Position { x: 300, y: 300 })];
+
+        let mut with_stats = snapshot(1, &both, &[]);
+        with_stats.movement_stats = HashMap::from([(me, stats), (other, stats)]);
+        world.apply_snapshot(with_stats, &net, 0.016, 0.016);
+        world.apply_snapshot(snapshot(2, &both, &[]), &net, 0.032, 0.032);
+        assert_eq!(world.player_movement.len(), 2);
+
+        // A player that left is dropped without waiting for the next stats
+        world.apply_snapshot(snapshot(3, &both[..1], &[]), &net, 0.048, 0.048);
+        assert_eq!(world.player_movement, HashMap::from([(me, stats)]));
+    }
+
+    #[test]
+    fn test_raw_and_smoothed_positions_come_from_their_own_sources() {
+        let (me, walker, leaver) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let mut world = welcomed_world(me, Position { x: 100, y: 100 });
+        let net = NetworkClient::new("127.0.0.1:9");
+        let tick = 0.016;
+
+        // One player walks right, another stands still and leaves after the last of these snapshots
+        let mut walked = Position { x: 0, y: 0 };
+        for snapshot_id in 1..=20 {
+            walked = Position { x: 200 + 3 * snapshot_id as i32, y: 300 };
+            let players = [(me, Position { x: 100, y: 100 }), (walker, walked), (leaver, Position { x: 500, y: 500 })];
+            world.apply_snapshot(snapshot(snapshot_id, &players, &[]), &net, snapshot_id as f64 * tick, snapshot_id as f64 * tick);
+        }
+        let mut my_pos = world.my_pos;
+        world.prediction.predict_input(Direction::Right, 0, &mut my_pos);
+        world.my_pos = my_pos;
+        let now = 20.5 * tick;
+        world.apply_snapshot(snapshot(21, &[(me, Position { x: 100, y: 100 }), (walker, walked)], &[]), &net, now, now);
+        let server_time = world.render_server_time(now, now);
+        let sorted = |mut positions: Vec<(Uuid, DisplayPos, u32)>| {
+            positions.sort_by_key(|(id, _, _)| *id);
+            positions.into_iter().map(|(id, position, _)| (id, position.layout_position())).collect::<Vec<_>>()
+        };
+
+        // The raw view has the latest snapshot as it is, without the player that left
+        let raw = sorted(world.positions(PositionSource::RawSnapshot, server_time));
+        assert_eq!(raw, vec![(me, Position { x: 100, y: 100 }), (walker, walked)]);
+
+        // The client view has the prediction, the interpolation and the player fading out where it was drawn
+        let smoothed = sorted(world.positions(PositionSource::Smoothed, server_time));
+        assert_eq!(smoothed[0], (me, my_pos.position()));
+        assert_ne!(my_pos.position(), Position { x: 100, y: 100 });
+        assert_eq!(smoothed[1], (walker, world.remote_position(&walker, walked, server_time).layout_position()));
+        assert_ne!(smoothed[1].1, walked);
+        assert_eq!(smoothed[2], (leaver, Position { x: 500, y: 500 }));
+    }
+
+    #[test]
+    fn test_welcome_draws_the_local_player_before_any_snapshot() {
+        let me = Uuid::from_u128(1);
+        let spawn = Position { x: 240, y: 180 };
+        let welcome = Welcome { color: 0x33CC66, shape: PlayerShape::Circle, ..Welcome::new(me, Uuid::new_v4(), spawn, None) };
+        let mut world = ClientWorld::new();
+        world.handshake_pending = true;
+        assert!(world.adopt_id(me));
+        world.welcome(&welcome);
+
+        // The first frame already has the local player where it spawned, in its own color and shape
+        let positions = world.positions(PositionSource::Smoothed, None);
+        assert_eq!(positions.len(), 1);
+        let (id, position, color) = positions[0];
+        assert_eq!((id, position.layout_position(), color), (me, spawn, 0x33CC66));
+        assert_eq!(world.player_shapes.get(&me), Some(&PlayerShape::Circle));
+
+        // The first snapshot takes over from the welcome
+        let net = NetworkClient::new("127.0.0.1:9");
+        world.apply_snapshot(snapshot(1, &[(me, spawn)], &[]), &net, 0.016, 0.016);
+        assert_eq!(world.positions(PositionSource::Smoothed, None), vec![(me, DisplayPos::from_authoritative(AuthoritativePos::new(spawn)), 0xFF0000)]);
+    }
+
+    #[test]
+    fn test_pathological_prediction_falls_back_to_the_snapshots() {
+        let me = Uuid::from_u128(1);
+        let mut world = welcomed_world(me, Position { x: 100, y: 100 });
+        let net = NetworkClient::new("127.0.0.1:9");
+        let tick = 0.016;
+
+        // The server walks the player right while the prediction has it somewhere else entirely
+        for snapshot_id in 1..=20 {
+            let now = snapshot_id as f64 * tick;
+            world.apply_snapshot(snapshot(snapshot_id, &[(me, Position { x: 100 + 2 * snapshot_id as i32, y: 100 })], &[]), &net, now, now);
+        }
+        world.my_pos = AuthoritativePos::new(Position { x: 600, y: 500 });
+        assert!(!world.check_prediction(1.0), "not watched for a whole window yet");
+
+        // A whole window of large errors with snaps that didn't help trips the watchdog, once
+        for step in 0..=210 {
+            let at = 0.32 + step as f64 * 0.05;
+            world.prediction_watchdog.record_error(at, 300.0);
+            if step % 20 == 0 {
+                world.prediction_watchdog.record_resync(at);
+            }
+        }
+        let mut unwatched = ClientWorld { config: PredictionConfig { watchdog: false, ..PredictionConfig::default() }, ..ClientWorld::new() };
+        unwatched.prediction_watchdog = world.prediction_watchdog.clone();
+        assert!(!unwatched.check_prediction(10.85), "the config keeps the watchdog out");
+        assert!(world.check_prediction(10.85));
+        assert!(!world.is_predicting());
+        assert!(!world.check_prediction(10.9));
+
+        // The interpolation was seeded from the recent snapshots, the local player is drawn from them
+        let server_time = tick_time_of(15.5) + INTERPOLATION_DELAY;
+        let drawn = world.positions(PositionSource::Smoothed, Some(server_time))[0].1;
+        let LocalControl::Interpolated(interpolation) = &world.local_control else { panic!("not interpolated") };
+        assert_eq!(Some(drawn), interpolation.get_interpolated_position(server_time));
+        assert_eq!(drawn.layout_position(), Position { x: 131, y: 100 });
+
+        // Later snapshots keep feeding it, and turning the prediction back on starts at the newest one
+        world.apply_snapshot(snapshot(21, &[(me, Position { x: 142, y: 100 })], &[]), &net, 21.0 * tick, 21.0 * tick);
+        let LocalControl::Interpolated(interpolation) = &world.local_control else { panic!("not interpolated") };
+        assert_eq!(interpolation.position_at(tick_time_of(21.0)).position(), Some(Position { x: 142, y: 100 }));
+        world.enable_prediction();
+        assert!(world.is_predicting());
+        assert_eq!(world.my_pos, AuthoritativePos::new(Position { x: 142, y: 100 }));
+        assert_eq!(world.prediction_watchdog.p95(11.0), None);
+    }
+}
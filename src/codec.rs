@@ -0,0 +1,350 @@
+use crate::constants::MAX_DATAGRAM_SIZE;
+use crate::snapshot::{decode_snapshot, encode_snapshot};
+use crate::types::{ClientMessage, GameState};
+
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+
+/// Why binary data didn't decode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,                      // The data ended before the value did
+    TrailingBytes { extra: usize }, // The value ended before the data did
+    TooLarge,                       // The value would be larger than MAX_DATAGRAM_SIZE
+    Invalid(String),                // The bytes aren't a value of the type, such as an unknown enum variant
+}
+
+/// Implementation of the DecodeError
+impl DecodeError {
+    /// Describes the error for logs
+    pub fn describe(&self) -> String {
+        match self {
+            DecodeError::Truncated => "truncated".to_string(),
+            DecodeError::TrailingBytes { extra } => format!("{} trailing bytes", extra),
+            DecodeError::TooLarge => format!("larger than {} bytes", MAX_DATAGRAM_SIZE),
+            DecodeError::Invalid(reason) => format!("invalid: {}", reason),
+        }
+    }
+}
+
+/// The bincode configuration of the binary protocol, spelled out so a changed crate default can't change
+/// the wire format: fixed width little endian integers, and decoding never reads more than a datagram holds
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .with_limit(MAX_DATAGRAM_SIZE as u64)
+}
+
+/// Encodes a value in the binary protocol. Values are never refused here, a datagram too large
+/// for the socket fails when it is sent.
+pub fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    bincode_options().with_no_limit().serialize(value).expect("protocol values always serialize")
+}
+
+/// Decodes a value of the binary protocol that has to use all of the data
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, DecodeError> {
+    let mut rest = data;
+    let value = bincode_options().deserialize_from(&mut rest).map_err(|error| match *error {
+        bincode::ErrorKind::Io(ref io) if io.kind() == ErrorKind::UnexpectedEof => DecodeError::Truncated,
+        bincode::ErrorKind::SizeLimit => DecodeError::TooLarge,
+        other => DecodeError::Invalid(other.to_string()),
+    })?;
+    match rest.len() {
+        0 => Ok(value),
+        extra => Err(DecodeError::TrailingBytes { extra }),
+    }
+}
+
+/// Something received from the server
+#[derive(Debug)]
+pub enum Incoming {
+    Snapshot(Box<GameState>),
+    Message(ClientMessage),
+}
+
+/// Turns messages and snapshots into datagrams and back. One datagram always holds one message or snapshot.
+pub trait Codec {
+    /// Encodes a message as one datagram
+    fn encode_message(&self, message: &ClientMessage) -> Vec<u8>;
+
+    /// Encodes a snapshot as one datagram
+    fn encode_snapshot(&self, state: &GameState) -> Vec<u8>;
+
+    /// Decodes a datagram from a client, None if it isn't a message in this codec
+    fn decode_message(&self, data: &[u8]) -> Option<ClientMessage>;
+
+    /// Decodes a datagram from the server, which is either a snapshot or a message
+    fn decode_incoming(&self, data: &[u8]) -> Option<Incoming>;
+}
+
+/// The binary protocol: bincode messages and snapshots with extension blocks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+/// Implementation of the Codec for the BincodeCodec
+impl Codec for BincodeCodec {
+    fn encode_message(&self, message: &ClientMessage) -> Vec<u8> {
+        encode(message)
+    }
+
+    fn encode_snapshot(&self, state: &GameState) -> Vec<u8> {
+        encode_snapshot(state)
+    }
+
+    fn decode_message(&self, data: &[u8]) -> Option<ClientMessage> {
+        decode(data).ok()
+    }
+
+    /// Snapshots are tried first since their framing can't be mistaken for a message
+    fn decode_incoming(&self, data: &[u8]) -> Option<Incoming> {
+        if let Some(snapshot) = decode_snapshot(data) {
+            return Some(Incoming::Snapshot(Box::new(snapshot)));
+        }
+        self.decode_message(data).map(Incoming::Message)
+    }
+}
+
+/// The text protocol for debugging with netcat or scripts. Every datagram is a JSON object
+/// with a single key saying what it holds, e.g. `{"Message":{"Ping":5}}` or `{"Snapshot":{...}}`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+/// A JSON datagram as it is written
+#[derive(Serialize)]
+enum JsonDatagramRef<'a> {
+    Message(&'a ClientMessage),
+    Snapshot(&'a GameState),
+}
+
+/// A JSON datagram as it is read
+#[derive(Deserialize)]
+enum JsonDatagram {
+    Message(ClientMessage),
+    Snapshot(Box<GameState>),
+}
+
+/// Implementation of the Codec for the JsonCodec
+impl Codec for JsonCodec {
+    fn encode_message(&self, message: &ClientMessage) -> Vec<u8> {
+        serde_json::to_vec(&JsonDatagramRef::Message(message)).unwrap()
+    }
+
+    /// Goes through a JSON value, whose objects keep their keys sorted, so the maps of a snapshot are
+    /// written in the same order every time like in the binary protocol
+    fn encode_snapshot(&self, state: &GameState) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::to_value(JsonDatagramRef::Snapshot(state)).unwrap()).unwrap()
+    }
+
+    fn decode_message(&self, data: &[u8]) -> Option<ClientMessage> {
+        match self.decode_incoming(data)? {
+            Incoming::Message(message) => Some(message),
+            Incoming::Snapshot(_) => None,
+        }
+    }
+
+    fn decode_incoming(&self, data: &[u8]) -> Option<Incoming> {
+        match serde_json::from_slice(data).ok()? {
+            JsonDatagram::Message(message) => Some(Incoming::Message(message)),
+            JsonDatagram::Snapshot(snapshot) => Some(Incoming::Snapshot(snapshot)),
+        }
+    }
+}
+
+/// The protocols a process can speak, picked with --proto or the proto of the server config file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodecKind {
+    #[default]
+    Bincode,
+    Json,
+}
+
+/// Implementation of the CodecKind
+impl CodecKind {
+    /// Looks up a protocol by its command line name, bincode or json
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "bincode" => Some(CodecKind::Bincode),
+            "json" => Some(CodecKind::Json),
+            _ => None,
+        }
+    }
+
+    /// Command line name of the protocol
+    pub fn name(self) -> &'static str {
+        match self {
+            CodecKind::Bincode => "bincode",
+            CodecKind::Json => "json",
+        }
+    }
+
+    /// Tells the protocol of a datagram from its first byte. JSON datagrams are objects and start with '{',
+    /// a bincode message starts with its variant index, which is far below it.
+    pub fn sniff(data: &[u8]) -> Self {
+        if data.first() == Some(&b'{') { CodecKind::Json } else { CodecKind::Bincode }
+    }
+
+    /// The codec speaking the protocol
+    pub fn codec(self) -> &'static dyn Codec {
+        match self {
+            CodecKind::Bincode => &BincodeCodec,
+            CodecKind::Json => &JsonCodec,
+        }
+    }
+}
+
+/// Tests for the codecs
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Direction, DisconnectReason, MatchPhase, PlayerInput, PlayerShape, Position, RejectReason, RoomInfo, Welcome};
+
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    // Helper function to create one of every message
+    fn every_message() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Connect,
+            ClientMessage::PlayerId(Uuid::new_v4()),
+            ClientMessage::Input(PlayerInput { dir: Direction::Up, sequence: 42, timestamp: 12345 }),
+            ClientMessage::Ping(54321),
+            ClientMessage::Pong(98765),
+            ClientMessage::MatchReset { match_number: 3, positions: vec![(Uuid::new_v4(), Position { x: 40, y: 60 })] },
+            ClientMessage::Welcome(Welcome::new(Uuid::new_v4(), Uuid::new_v4(), Position { x: 70, y: 80 }, Some(500))),
+            ClientMessage::Reconnect { session_token: Uuid::new_v4() },
+            ClientMessage::ServerPing(7),
+            ClientMessage::ServerPong(7),
+            ClientMessage::ConnectWithProfile {
+                name: "Kari".to_string(),
+                shape: PlayerShape::Circle.to_wire(),
+                instance: 0x0123_4567_89ab_cdef,
+                room: "arena".to_string(),
+                password: Some("secret".to_string()),
+            },
+            ClientMessage::ListRooms,
+            ClientMessage::RoomList(vec![RoomInfo { name: "lobby".to_string(), players: 3, password_required: false }]),
+            ClientMessage::ConnectRejected { reason: RejectReason::WrongPassword },
+            ClientMessage::InputBatch(vec![PlayerInput { dir: Direction::Right, sequence: 43, timestamp: 12_400 }]),
+            ClientMessage::WithSession { session_token: Uuid::new_v4(), message: Box::new(ClientMessage::Ping(54321)) },
+            ClientMessage::Disconnected { id: Uuid::new_v4(), reason: DisconnectReason::TimedOut },
+            ClientMessage::DebugSetPosition(Position { x: 400, y: 300 }),
+            ClientMessage::Leave,
+        ]
+    }
+
+    // Helper function to create a snapshot with two players
+    fn snapshot() -> GameState {
+        let (a, b) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        GameState {
+            players: vec![(a, Position { x: 10, y: 20 }, 0xFF0000), (b, Position { x: 30, y: 40 }, 0x00FF00)],
+            last_processed: HashMap::from([(a, 5), (b, 9)]),
+            pellets: vec![Position { x: 1, y: 2 }],
+            scores: HashMap::from([(a, 2)]),
+            best_scores: HashMap::from([(a, 4)]),
+            teams: HashMap::new(),
+            input_reorders: HashMap::new(),
+            shapes: HashMap::from([(b, PlayerShape::Triangle)]),
+            impulses: HashMap::new(),
+            input_timings: HashMap::new(),
+            match_phase: MatchPhase::Countdown { remaining_ms: 1500 },
+            server_timestamp: 16_016,
+            match_number: 2,
+            ping_ms: HashMap::from([(b, 40)]),
+            snapshot_id: 1001,
+            tick_interval_ms: 16,
+            tagged: None,
+            movement_stats: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_every_message_round_trips_in_both_codecs() {
+        for kind in [CodecKind::Bincode, CodecKind::Json] {
+            let codec = kind.codec();
+            for message in every_message() {
+                let data = codec.encode_message(&message);
+                assert_eq!(CodecKind::sniff(&data), kind, "{:?}", message);
+
+                // Compare the debug output since ClientMessage doesn't implement PartialEq
+                let decoded = codec.decode_message(&data).unwrap();
+                assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+                match codec.decode_incoming(&data) {
+                    Some(Incoming::Message(decoded)) => assert_eq!(format!("{:?}", decoded), format!("{:?}", message)),
+                    other => panic!("Expected {:?}, got {:?}", message, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot_round_trips_in_both_codecs() {
+        let state = snapshot();
+        for kind in [CodecKind::Bincode, CodecKind::Json] {
+            let codec = kind.codec();
+            let data = codec.encode_snapshot(&state);
+            match codec.decode_incoming(&data) {
+                Some(Incoming::Snapshot(decoded)) => assert_eq!(*decoded, state),
+                other => panic!("Expected a snapshot, got {:?}", other),
+            }
+            assert!(codec.decode_message(&data).is_none() || kind == CodecKind::Bincode);
+        }
+    }
+
+    #[test]
+    fn test_json_is_readable_and_codecs_dont_mix() {
+        let data = JsonCodec.encode_message(&ClientMessage::Ping(5));
+        assert_eq!(String::from_utf8(data.clone()).unwrap(), r#"{"Message":{"Ping":5}}"#);
+
+        // Typed by hand, e.g. with netcat
+        assert!(matches!(JsonCodec.decode_message(br#"{"Message":"ListRooms"}"#), Some(ClientMessage::ListRooms)));
+        assert!(JsonCodec.decode_message(b"{not json").is_none());
+
+        // Neither codec reads the other
+        assert!(BincodeCodec.decode_message(&data).is_none());
+        assert!(JsonCodec.decode_incoming(&BincodeCodec.encode_message(&ClientMessage::Ping(5))).is_none());
+    }
+
+    #[test]
+    fn test_integers_are_fixed_width_little_endian() {
+        assert_eq!(encode(&ClientMessage::Ping(5)), vec![3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encode(&1_u32).len(), 4);
+    }
+
+    #[test]
+    fn test_decode_errors_tell_truncation_from_trailing_bytes() {
+        let data = encode(&ClientMessage::Ping(5));
+        assert_eq!(decode::<ClientMessage>(&data[..data.len() - 1]), Err(DecodeError::Truncated));
+        let mut padded = data.clone();
+        padded.extend_from_slice(&[0, 0]);
+        assert_eq!(decode::<ClientMessage>(&padded), Err(DecodeError::TrailingBytes { extra: 2 }));
+        assert!(BincodeCodec.decode_message(&padded).is_none());
+        assert!(BincodeCodec.decode_message(&data).is_some());
+
+        // A length prefix past the limit fails before anything is allocated, an unknown variant is invalid
+        assert_eq!(decode::<String>(&u64::MAX.to_le_bytes()), Err(DecodeError::TooLarge));
+        assert!(matches!(decode::<ClientMessage>(&[200, 0, 0, 0]), Err(DecodeError::Invalid(_))));
+        assert_eq!(DecodeError::TrailingBytes { extra: 2 }.describe(), "2 trailing bytes");
+    }
+
+    #[test]
+    fn test_parse_codec_kind() {
+        assert_eq!(CodecKind::parse("json"), Some(CodecKind::Json));
+        assert_eq!(CodecKind::parse("Bincode"), Some(CodecKind::Bincode));
+        assert_eq!(CodecKind::parse("xml"), None);
+        assert_eq!(CodecKind::default().name(), "bincode");
+    }
+
+    #[test]
+    fn test_same_snapshot_is_the_same_bytes_in_both_codecs() {
+        let state = snapshot();
+        let mut rebuilt = snapshot();
+        rebuilt.last_processed = rebuilt.last_processed.into_iter().collect::<Vec<_>>().into_iter().rev().collect();
+        for kind in [CodecKind::Bincode, CodecKind::Json] {
+            assert_eq!(kind.codec().encode_snapshot(&state), kind.codec().encode_snapshot(&rebuilt), "{}", kind.name());
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::colors::{PlayerColor, Rgb};
+use crate::constants::{BOT_DIM_FACTOR, RECONNECT_GRACE, SPAWN_HUE_SATURATION, SPAWN_HUE_VALUE};
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Who a color is handed out to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claimant {
+    Human, // Gets a color no one else in the room has
+    Bot,   // Gets a dimmed copy of a human's color, so humans never run out because of bots
+}
+
+/// A color held by a player
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Claim {
+    color: PlayerColor,
+    base: PlayerColor, // The undimmed color, the human color a bot copies
+    claimant: Claimant,
+}
+
+/// Hands out player colors in one room. Humans get distinct colors, first from the given palette
+/// and then generated hues once it is used up, bots share dimmed human colors. The color of a
+/// disconnected human stays reserved for the reconnect grace period so a resumed session keeps it.
+#[derive(Debug)]
+pub struct ColorAllocator {
+    claims: HashMap<Uuid, Claim>,
+    reserved: HashMap<Uuid, (PlayerColor, Instant)>, // Colors of disconnected humans by id, with when they left
+    grace: Duration, // How long a reservation is held, the reconnect grace period of the room
+}
+
+/// Default implementation for the ColorAllocator
+impl Default for ColorAllocator {
+    fn default() -> Self {
+        Self {
+            claims: HashMap::new(),
+            reserved: HashMap::new(),
+            grace: RECONNECT_GRACE,
+        }
+    }
+}
+
+/// Implementation of the ColorAllocator
+impl ColorAllocator {
+    /// Creates an allocator with no colors handed out
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long the color of a disconnected human stays reserved, RECONNECT_GRACE by default
+    pub fn set_grace(&mut self, grace: Duration) {
+        self.grace = grace;
+    }
+
+    /// Hands a color to a player, picking from palette first. Reservations older than the
+    /// reconnect grace period are given up first. A player that already has a color keeps it.
+    pub fn allocate(&mut self, id: Uuid, claimant: Claimant, palette: &[Rgb], now: Instant) -> PlayerColor {
+        self.expire(now);
+        if let Some(claim) = self.claims.get(&id) {
+            return claim.color;
+        }
+
+        let claim = match claimant {
+            Claimant::Human => {
+                let color = self.free_human_color(palette);
+                Claim { color, base: color, claimant }
+            }
+            Claimant::Bot => {
+                let base = self.least_shared_human_color()
+                    .or_else(|| palette.first().map(|rgb| PlayerColor::from_rgb(*rgb)))
+                    .unwrap_or_else(|| PlayerColor::from_hsv(0.0, SPAWN_HUE_SATURATION, SPAWN_HUE_VALUE));
+                Claim { color: base.dimmed(BOT_DIM_FACTOR), base, claimant }
+            }
+        };
+        self.claims.insert(id, claim);
+        claim.color
+    }
+
+    /// Gives up the color of a player that left. Humans that can still resume their session keep it
+    /// reserved for the reconnect grace period, bots and players that left for good free it at once.
+    pub fn release(&mut self, id: Uuid, now: Instant, reserve: bool) {
+        if let Some(claim) = self.claims.remove(&id) {
+            if reserve && claim.claimant == Claimant::Human {
+                self.reserved.insert(id, (claim.color, now));
+            }
+        }
+    }
+
+    /// Hands a resumed player its color again, whether or not the reservation was still held
+    pub fn restore(&mut self, id: Uuid, color: PlayerColor) {
+        self.reserved.remove(&id);
+        self.claims.insert(id, Claim { color, base: color, claimant: Claimant::Human });
+    }
+
+    /// Gives up reservations older than the reconnect grace period
+    pub fn expire(&mut self, now: Instant) {
+        let grace = self.grace;
+        self.reserved.retain(|_, (_, released_at)| now.saturating_duration_since(*released_at) < grace);
+    }
+
+    /// Gives up the reservation of a player whose session can no longer be resumed before the grace period ends
+    pub fn forget(&mut self, id: &Uuid) {
+        self.reserved.remove(id);
+    }
+
+    /// Color a player holds, None if it has none
+    pub fn color_of(&self, id: &Uuid) -> Option<PlayerColor> {
+        self.claims.get(id).map(|claim| claim.color)
+    }
+
+    /// Whether a color is held by a reservation
+    pub fn is_reserved(&self, color: PlayerColor) -> bool {
+        self.reserved.values().any(|(reserved, _)| *reserved == color)
+    }
+
+    /// First palette color no human holds or has reserved, then the first free generated hue
+    fn free_human_color(&self, palette: &[Rgb]) -> PlayerColor {
+        let taken = |color: &PlayerColor| {
+            self.is_reserved(*color)
+                || self.claims.values().any(|claim| claim.claimant == Claimant::Human && claim.color == *color)
+        };
+
+        if let Some(color) = palette.iter().map(|rgb| PlayerColor::from_rgb(*rgb)).find(|color| !taken(color)) {
+            return color;
+        }
+
+        // Double the number of hues until one is free, which ends since only finitely many colors are taken
+        let mut count = palette.len().max(1) * 2;
+        loop {
+            if let Some(color) = spaced_hues(count).into_iter().find(|color| !taken(color)) {
+                return color;
+            }
+            count *= 2;
+        }
+    }
+
+    /// The human color the fewest bots copy, the first handed out on a tie. None without humans.
+    fn least_shared_human_color(&self) -> Option<PlayerColor> {
+        let mut humans: Vec<PlayerColor> = self.claims.values()
+            .filter(|claim| claim.claimant == Claimant::Human)
+            .map(|claim| claim.color)
+            .collect();
+        // Sort so the pick doesn't depend on the hash map order
+        humans.sort_by_key(|color| color.packed());
+        humans.into_iter().min_by_key(|color| {
+            self.claims.values().filter(|claim| claim.claimant == Claimant::Bot && claim.base == *color).count()
+        })
+    }
+}
+
+/// count colors with evenly spaced hues starting at red, at the brightness of the palette
+pub fn spaced_hues(count: usize) -> Vec<PlayerColor> {
+    (0..count)
+        .map(|i| PlayerColor::from_hsv(360.0 * i as f32 / count as f32, SPAWN_HUE_SATURATION, SPAWN_HUE_VALUE))
+        .collect()
+}
+
+/// Tests for the color allocator
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors::palette;
+
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    // Helper function to hand out colors to count new humans
+    fn allocate_humans(colors: &mut ColorAllocator, count: usize, now: Instant) -> Vec<PlayerColor> {
+        (0..count).map(|_| colors.allocate(Uuid::new_v4(), Claimant::Human, &palette::PLAYER_PALETTE, now)).collect()
+    }
+
+    #[test]
+    fn test_spaced_hues() {
+        let hues = spaced_hues(6);
+        let expected: Vec<Rgb> = vec![(0xff, 0x17, 0x17), (0xff, 0xff, 0x17), (0x17, 0xff, 0x17), (0x17, 0xff, 0xff), (0x17, 0x17, 0xff), (0xff, 0x17, 0xff)];
+        assert_eq!(hues.iter().map(|color| color.rgb()).collect::<Vec<_>>(), expected);
+        assert!(spaced_hues(0).is_empty());
+    }
+
+    #[test]
+    fn test_humans_get_distinct_colors_beyond_the_palette() {
+        let mut colors = ColorAllocator::new();
+        let now = Instant::now();
+        let handed_out = allocate_humans(&mut colors, 30, now);
+
+        // The palette is used in order first
+        let palette_colors: Vec<PlayerColor> = palette::PLAYER_PALETTE.iter().map(|rgb| PlayerColor::from_rgb(*rgb)).collect();
+        assert_eq!(handed_out[..9], palette_colors[..]);
+        assert_eq!(handed_out.iter().collect::<HashSet<_>>().len(), 30);
+    }
+
+    #[test]
+    fn test_bots_share_dimmed_human_colors() {
+        let mut colors = ColorAllocator::new();
+        let now = Instant::now();
+        let humans = allocate_humans(&mut colors, 2, now);
+
+        // Bots spread over the human colors before doubling up
+        let bots: Vec<PlayerColor> = (0..4).map(|_| colors.allocate(Uuid::new_v4(), Claimant::Bot, &palette::PLAYER_PALETTE, now)).collect();
+        let dimmed: Vec<PlayerColor> = humans.iter().map(|color| color.dimmed(BOT_DIM_FACTOR)).collect();
+        assert_eq!(bots[..2].iter().collect::<HashSet<_>>(), dimmed.iter().collect::<HashSet<_>>());
+        assert_eq!(bots[2..].iter().collect::<HashSet<_>>(), dimmed.iter().collect::<HashSet<_>>());
+
+        // Bots don't take colors from humans
+        assert_eq!(allocate_humans(&mut colors, 1, now)[0], PlayerColor::from_rgb(palette::BLUE));
+
+        // With no humans around a bot dims the first palette color
+        let mut empty = ColorAllocator::new();
+        let bot = empty.allocate(Uuid::new_v4(), Claimant::Bot, &palette::PLAYER_PALETTE, now);
+        assert_eq!(bot, PlayerColor::from_rgb(palette::RED).dimmed(BOT_DIM_FACTOR));
+    }
+
+    #[test]
+    fn test_reservation_lasts_for_the_reconnect_grace() {
+        let mut colors = ColorAllocator::new();
+        let start = Instant::now();
+        let id = Uuid::new_v4();
+        let red = colors.allocate(id, Claimant::Human, &palette::PLAYER_PALETTE, start);
+        colors.release(id, start, true);
+        assert_eq!(colors.color_of(&id), None);
+
+        // Still reserved just before the grace ends, so the next player gets the next color
+        let before = start + RECONNECT_GRACE - Duration::from_millis(1);
+        let other = Uuid::new_v4();
+        assert_eq!(colors.allocate(other, Claimant::Human, &palette::PLAYER_PALETTE, before), PlayerColor::from_rgb(palette::GREEN));
+        assert!(colors.is_reserved(red));
+
+        // A resumed session takes it back
+        colors.restore(id, red);
+        assert!(!colors.is_reserved(red));
+        assert_eq!(colors.color_of(&id), Some(red));
+
+        // Once the grace is over the color is free again
+        colors.release(id, start, true);
+        colors.release(other, start, false);
+        assert_eq!(colors.allocate(Uuid::new_v4(), Claimant::Human, &palette::PLAYER_PALETTE, start + RECONNECT_GRACE), red);
+    }
+}
@@ -1,6 +1,100 @@
+#[cfg(feature = "client")]
 use macroquad::prelude::*;
 
+/// A color as plain red, green and blue channels
+pub type Rgb = (u8, u8, u8);
+
+/// Player palette as plain RGB, so the server can pick colors without the client feature
+pub mod palette {
+    use super::Rgb;
+    pub const RED: Rgb = (0xff, 0x17, 0x17); // Bright red
+    pub const GREEN: Rgb = (0x17, 0xff, 0x17); // Bright green
+    pub const BLUE: Rgb = (0x17, 0x17, 0xff); // Bright blue
+    pub const YELLOW: Rgb = (0xff, 0xff, 0x17); // Bright yellow
+    pub const ORANGE: Rgb = (0xff, 0x7f, 0x17); // Bright orange
+    pub const PURPLE: Rgb = (0x7f, 0x17, 0xff); // Bright purple
+    pub const CYAN: Rgb = (0x17, 0xff, 0xff); // Bright cyan
+    pub const MAGENTA: Rgb = (0xff, 0x17, 0xff); // Bright magenta
+    pub const PINK: Rgb = (0xff, 0x7f, 0x7f); // Bright pink
+
+    /// All player colors, in palette order
+    pub const PLAYER_PALETTE: [Rgb; 9] = [RED, GREEN, BLUE, YELLOW, ORANGE, PURPLE, CYAN, MAGENTA, PINK];
+
+    /// Player colors of each team in team mode, warm colors for the first team and cool for the second
+    pub const TEAM_PALETTES: [&[Rgb]; 2] = [&[RED, ORANGE, YELLOW, PINK], &[BLUE, CYAN, PURPLE, GREEN]];
+
+    /// Packs a color as 0xRRGGBB for serialization
+    pub fn pack(rgb: Rgb) -> u32 {
+        (rgb.0 as u32) << 16 | (rgb.1 as u32) << 8 | rgb.2 as u32
+    }
+
+    /// Unpacks a color packed with pack
+    pub fn unpack(color: u32) -> Rgb {
+        ((color >> 16) as u8, (color >> 8) as u8, color as u8)
+    }
+}
+
+/// A player color, stored packed as 0xRRGGBB like on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlayerColor(u32);
+
+/// Implementation of the PlayerColor
+impl PlayerColor {
+    /// Creates a color from plain RGB channels
+    pub fn from_rgb(rgb: Rgb) -> Self {
+        Self(palette::pack(rgb))
+    }
+
+    /// Creates a color from a packed 0xRRGGBB value
+    pub fn from_packed(color: u32) -> Self {
+        Self(color & 0xFF_FFFF)
+    }
+
+    /// Creates a color from a hue in degrees and a saturation and value between 0 and 1
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let (saturation, value) = (saturation.clamp(0.0, 1.0), value.clamp(0.0, 1.0));
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let min = value - chroma;
+        let channel = |c: f32| ((c + min) * 255.0).round() as u8;
+        Self::from_rgb((channel(r), channel(g), channel(b)))
+    }
+
+    /// The color with every channel scaled by factor, which darkens it for factors below 1
+    pub fn dimmed(self, factor: f32) -> Self {
+        let (r, g, b) = self.rgb();
+        let channel = |c: u8| (c as f32 * factor.clamp(0.0, 1.0)).round() as u8;
+        Self::from_rgb((channel(r), channel(g), channel(b)))
+    }
+
+    /// The plain RGB channels
+    pub fn rgb(self) -> Rgb {
+        palette::unpack(self.0)
+    }
+
+    /// The color packed as 0xRRGGBB for serialization
+    pub fn packed(self) -> u32 {
+        self.0
+    }
+}
+
+/// Converts a plain RGB color into an opaque macroquad color
+#[cfg(feature = "client")]
+pub const fn to_color(rgb: Rgb) -> Color {
+    Color::from_rgba(rgb.0, rgb.1, rgb.2, 255)
+}
+
 /// Background/UI colors
+#[cfg(feature = "client")]
 pub mod bg_colors {
     use super::*;
     pub const BLACK: Color = Color::from_hex(0x171717); // Dark background
@@ -11,33 +105,133 @@ pub mod bg_colors {
     pub const ORANGE: Color = Color::from_hex(0xe65c07); // Bright orange for warnings or highlights
 }
 
+/// High-contrast background/UI colors for the accessibility mode
+#[cfg(feature = "client")]
+pub mod high_contrast_bg_colors {
+    use super::*;
+    pub const BLACK: Color = Color::from_hex(0x000000); // Pure black background
+    pub const NAVY: Color = Color::from_hex(0x000080); // Navy for UI elements, keeps white text readable
+    pub const WHITE: Color = Color::from_hex(0xffffff); // Pure white for text and highlights
+    pub const RED: Color = Color::from_hex(0xff4040); // Light red for errors, readable on black
+    pub const GREEN: Color = Color::from_hex(0x40ff40); // Light green for success
+    pub const YELLOW: Color = Color::from_hex(0xffff00); // Yellow for warnings
+}
+
+/// Colors used by the renderer for each UI role, so the whole UI can switch color set at once
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub background: Color,
+    pub toolbar: Color,
+    pub text: Color,
+    pub error: Color,
+    pub success: Color,
+    pub warning: Color,
+    pub high_contrast: bool,
+}
+
+/// Implementation of the Theme
+#[cfg(feature = "client")]
+impl Theme {
+    /// The default color set
+    pub fn standard() -> Self {
+        Self {
+            background: bg_colors::BLACK,
+            toolbar: bg_colors::DARK_GRAY,
+            text: bg_colors::WHITE,
+            error: bg_colors::RED,
+            success: bg_colors::GREEN,
+            warning: bg_colors::ORANGE,
+            high_contrast: false,
+        }
+    }
+
+    /// The high-contrast color set
+    pub fn high_contrast() -> Self {
+        Self {
+            background: high_contrast_bg_colors::BLACK,
+            toolbar: high_contrast_bg_colors::NAVY,
+            text: high_contrast_bg_colors::WHITE,
+            error: high_contrast_bg_colors::RED,
+            success: high_contrast_bg_colors::GREEN,
+            warning: high_contrast_bg_colors::YELLOW,
+            high_contrast: true,
+        }
+    }
+
+    /// Selects the theme for the given accessibility setting
+    pub fn select(high_contrast: bool) -> Self {
+        if high_contrast {
+            Self::high_contrast()
+        } else {
+            Self::standard()
+        }
+    }
+
+    /// Maps a player color from the server palette into this theme
+    pub fn player_color(&self, color: Color) -> Color {
+        if self.high_contrast {
+            player_colors::to_high_contrast(color)
+        } else {
+            color
+        }
+    }
+}
+
 /// Player colors
+#[cfg(feature = "client")]
 pub mod player_colors {
     use super::*;
-    pub const RED: Color = Color::from_hex(0xff1717); // Bright red
-    pub const GREEN: Color = Color::from_hex(0x17ff17); // Bright green
-    pub const BLUE: Color = Color::from_hex(0x1717ff); // Bright blue
-    pub const YELLOW: Color = Color::from_hex(0xffff17); // Bright yellow
-    pub const ORANGE: Color = Color::from_hex(0xff7f17); // Bright orange
-    pub const PURPLE: Color = Color::from_hex(0x7f17ff); // Bright purple
-    pub const CYAN: Color = Color::from_hex(0x17ffff); // Bright cyan
-    pub const MAGENTA: Color = Color::from_hex(0xff17ff); // Bright magenta
-    pub const PINK: Color = Color::from_hex(0xff7f7f); // Bright pink
-    
+    pub const RED: Color = to_color(palette::RED); // Bright red
+    pub const GREEN: Color = to_color(palette::GREEN); // Bright green
+    pub const BLUE: Color = to_color(palette::BLUE); // Bright blue
+    pub const YELLOW: Color = to_color(palette::YELLOW); // Bright yellow
+    pub const ORANGE: Color = to_color(palette::ORANGE); // Bright orange
+    pub const PURPLE: Color = to_color(palette::PURPLE); // Bright purple
+    pub const CYAN: Color = to_color(palette::CYAN); // Bright cyan
+    pub const MAGENTA: Color = to_color(palette::MAGENTA); // Bright magenta
+    pub const PINK: Color = to_color(palette::PINK); // Bright pink
+
     /// Returns a vector of all player colors as a palette
     pub fn get_palette() -> Vec<Color> {
+        palette::PLAYER_PALETTE.iter().map(|rgb| to_color(*rgb)).collect()
+    }
+
+    /// Returns the high-contrast palette, with the same order as the normal palette
+    pub fn get_high_contrast_palette() -> Vec<Color> {
         vec![
-            RED,
-            GREEN,
-            BLUE,
-            YELLOW,
-            ORANGE,
-            PURPLE,
-            CYAN,
-            MAGENTA,
-            PINK,
+            Color::from_hex(0xff3030), // Red
+            Color::from_hex(0x00ff00), // Green
+            Color::from_hex(0x40a0ff), // Light blue, plain blue is too dark on black
+            Color::from_hex(0xffff00), // Yellow
+            Color::from_hex(0xff8000), // Orange
+            Color::from_hex(0xc080ff), // Light purple
+            Color::from_hex(0x00ffff), // Cyan
+            Color::from_hex(0xff00ff), // Magenta
+            Color::from_hex(0xffffff), // White instead of pink
         ]
     }
+
+    /// Returns the palette for the given accessibility setting
+    pub fn select_palette(high_contrast: bool) -> Vec<Color> {
+        if high_contrast {
+            get_high_contrast_palette()
+        } else {
+            get_palette()
+        }
+    }
+
+    /// Maps a palette color to its high-contrast counterpart, unknown colors are returned unchanged
+    pub fn to_high_contrast(color: Color) -> Color {
+        // Compare as 8-bit channels, since colors sent over the network lose float precision
+        let to_bytes = |c: Color| -> [u8; 3] { let [r, g, b, _] = c.into(); [r, g, b] };
+
+        get_palette()
+            .iter()
+            .position(|c| to_bytes(*c) == to_bytes(color))
+            .map(|index| get_high_contrast_palette()[index])
+            .unwrap_or(color)
+    }
 }
 
 /// Tests for the color module
@@ -45,6 +239,17 @@ pub mod player_colors {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_pack_and_unpack() {
+        assert_eq!(palette::pack(palette::RED), 0xff1717);
+        assert_eq!(palette::pack(palette::PURPLE), 0x7f17ff);
+
+        for rgb in palette::PLAYER_PALETTE {
+            assert_eq!(palette::unpack(palette::pack(rgb)), rgb);
+        }
+    }
+
+    #[cfg(feature = "client")]
     #[test]
     fn test_player_color_palette() {
         // Test that the palette returns the correct number of colors
@@ -56,4 +261,78 @@ mod tests {
         assert_eq!(palette[1], player_colors::GREEN);
         assert_eq!(palette[8], player_colors::PINK);
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_high_contrast_palette_matches_normal_palette() {
+        let normal = player_colors::select_palette(false);
+        let high_contrast = player_colors::select_palette(true);
+
+        // Same number of colors so palette indices map one to one
+        assert_eq!(normal.len(), high_contrast.len());
+        assert_eq!(normal, player_colors::get_palette());
+
+        // All high-contrast colors are distinct
+        for (i, a) in high_contrast.iter().enumerate() {
+            for b in high_contrast.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_to_high_contrast() {
+        let high_contrast = player_colors::get_high_contrast_palette();
+        assert_eq!(player_colors::to_high_contrast(player_colors::RED), high_contrast[0]);
+        assert_eq!(player_colors::to_high_contrast(player_colors::PINK), high_contrast[8]);
+
+        // A color that went through the u32 network packing still maps
+        let packed = Color::from_rgba(0x17, 0x17, 0xff, 255);
+        assert_eq!(player_colors::to_high_contrast(packed), high_contrast[2]);
+
+        // Unknown colors are returned unchanged
+        let unknown = Color::from_rgba(1, 2, 3, 255);
+        assert_eq!(player_colors::to_high_contrast(unknown), unknown);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_theme_selection() {
+        let standard = Theme::select(false);
+        assert_eq!(standard, Theme::standard());
+        assert_eq!(standard.background, bg_colors::BLACK);
+        assert_eq!(standard.player_color(player_colors::RED), player_colors::RED);
+
+        let high_contrast = Theme::select(true);
+        assert_eq!(high_contrast, Theme::high_contrast());
+        assert_eq!(high_contrast.background, high_contrast_bg_colors::BLACK);
+        assert_ne!(high_contrast.player_color(player_colors::RED), player_colors::RED);
+    }
+
+    #[test]
+    fn test_player_color_from_hsv() {
+        assert_eq!(PlayerColor::from_hsv(0.0, 1.0, 1.0).rgb(), (0xff, 0, 0));
+        assert_eq!(PlayerColor::from_hsv(120.0, 1.0, 1.0).rgb(), (0, 0xff, 0));
+        assert_eq!(PlayerColor::from_hsv(240.0, 1.0, 1.0).rgb(), (0, 0, 0xff));
+        assert_eq!(PlayerColor::from_hsv(60.0, 1.0, 1.0).rgb(), (0xff, 0xff, 0));
+        assert_eq!(PlayerColor::from_hsv(30.0, 1.0, 1.0).rgb(), (0xff, 0x80, 0));
+
+        // Hues wrap around, no saturation is grey
+        assert_eq!(PlayerColor::from_hsv(-120.0, 1.0, 1.0), PlayerColor::from_hsv(240.0, 1.0, 1.0));
+        assert_eq!(PlayerColor::from_hsv(200.0, 0.0, 0.5).rgb(), (0x80, 0x80, 0x80));
+
+        // The palette's saturation reproduces its colors
+        assert_eq!(PlayerColor::from_hsv(0.0, 1.0 - 0x17 as f32 / 255.0, 1.0).rgb(), palette::RED);
+    }
+
+    #[test]
+    fn test_player_color_dimmed_and_packed() {
+        let red = PlayerColor::from_rgb(palette::RED);
+        assert_eq!(red.packed(), 0xff1717);
+        assert_eq!(PlayerColor::from_packed(red.packed()), red);
+        assert_eq!(red.dimmed(0.5).rgb(), (0x80, 0x0c, 0x0c));
+        assert_eq!(red.dimmed(1.0), red);
+        assert_eq!(red.dimmed(0.0).rgb(), (0, 0, 0));
+    }
+}
@@ -1,7 +1,16 @@
-use crate::constants::{WINDOW_HEIGHT, WINDOW_RESIZABLE, WINDOW_TITLE, WINDOW_WIDTH};
+use crate::constants::{AUDIO_MUTED_FILE, CONFIG_DIR_NAME, FRAME_CAP_FILE, INSTANCE_ID_FILE, INSTANCE_ID_SALT, LANGUAGE_FILE, SESSION_LOG_SEVERITY_FILE, WARNING_THRESHOLDS_FILE, WINDOW_HEIGHT, WINDOW_RESIZABLE, WINDOW_TITLE, WINDOW_WIDTH};
+
+use crate::frame_pacer::FrameCap;
+use crate::session_log::Severity;
+use crate::strings::Language;
+use crate::warnings::WarningThresholds;
 
 use image::imageops::FilterType;
 use miniquad::conf::{Conf, Icon};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
 
 /// Configuration for the game window
 pub fn config_window() -> Conf {
@@ -30,6 +39,114 @@ pub fn config_window() -> Conf {
     }
 }
 
+/// Directory the client keeps its files in: the user's config directory on Windows (APPDATA),
+/// otherwise XDG_CONFIG_HOME or ~/.config. None if none of them is set.
+pub fn config_dir() -> Option<PathBuf> {
+    let env_dir = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let base = env_dir("APPDATA")
+        .or_else(|| env_dir("XDG_CONFIG_HOME"))
+        .or_else(|| env_dir("HOME").map(|home| home.join(".config")))?;
+    Some(base.join(CONFIG_DIR_NAME))
+}
+
+/// Loads the instance id of this client from the directory, creating it on the first run.
+/// A file that can't be parsed is replaced by a new id.
+pub fn load_or_create_instance_id(dir: &Path) -> io::Result<Uuid> {
+    let path = dir.join(INSTANCE_ID_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => match Uuid::parse_str(text.trim()) {
+            Ok(id) => return Ok(id),
+            Err(_) => eprintln!("Instance id in {} is corrupt, creating a new one", path.display()),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let id = Uuid::new_v4();
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(&path, format!("{}\n", id))?;
+    Ok(id)
+}
+
+/// Loads the frame rate cap chosen in an earlier run, uncapped if none was chosen or the file can't be read
+pub fn load_frame_cap(dir: &Path) -> FrameCap {
+    std::fs::read_to_string(dir.join(FRAME_CAP_FILE))
+        .ok()
+        .and_then(|text| FrameCap::parse(&text))
+        .unwrap_or_default()
+}
+
+/// Stores the frame rate cap for later runs
+pub fn save_frame_cap(dir: &Path, cap: FrameCap) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(FRAME_CAP_FILE), format!("{}\n", cap.name()))
+}
+
+/// Loads whether the sounds were muted in an earlier run, not muted if the file is missing or can't be read
+pub fn load_audio_muted(dir: &Path) -> bool {
+    std::fs::read_to_string(dir.join(AUDIO_MUTED_FILE))
+        .is_ok_and(|text| text.trim() == "muted")
+}
+
+/// Stores whether the sounds are muted for later runs
+pub fn save_audio_muted(dir: &Path, muted: bool) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(AUDIO_MUTED_FILE), if muted { "muted\n" } else { "unmuted\n" })
+}
+
+/// Loads the language chosen in an earlier run, English if none was chosen or the file can't be read
+pub fn load_language(dir: &Path) -> Language {
+    std::fs::read_to_string(dir.join(LANGUAGE_FILE))
+        .ok()
+        .and_then(|text| Language::parse(&text))
+        .unwrap_or_default()
+}
+
+/// Stores the language for later runs
+pub fn save_language(dir: &Path, language: Language) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(LANGUAGE_FILE), format!("{}\n", language.code()))
+}
+
+/// Loads the lowest severity the session log keeps, info if none was set or the file can't be read
+pub fn load_session_log_severity(dir: &Path) -> Severity {
+    std::fs::read_to_string(dir.join(SESSION_LOG_SEVERITY_FILE))
+        .ok()
+        .and_then(|text| Severity::parse(&text))
+        .unwrap_or_default()
+}
+
+/// Loads the metric warning thresholds from the directory. A missing file is created with the defaults so they
+/// can be edited, a file that can't be parsed is left alone and the defaults are used.
+pub fn load_warning_thresholds(dir: &Path) -> io::Result<WarningThresholds> {
+    let path = dir.join(WARNING_THRESHOLDS_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => match serde_json::from_str(&text) {
+            Ok(thresholds) => return Ok(thresholds),
+            Err(e) => {
+                eprintln!("Warning thresholds in {} are invalid, using the defaults: {}", path.display(), e);
+                return Ok(WarningThresholds::default());
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let thresholds = WarningThresholds::default();
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(&path, serde_json::to_string_pretty(&thresholds).unwrap() + "\n")?;
+    Ok(thresholds)
+}
+
+/// Salted hash of an instance id, the only form of it that leaves the machine
+pub fn hash_instance_id(id: Uuid) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(INSTANCE_ID_SALT)
+        .chain_update(id.as_bytes())
+        .finalize();
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
 /// Unit test to ensure the configuration is correct
 #[cfg(test)]
 mod tests {
@@ -49,4 +166,117 @@ mod tests {
         // Verify icon was created
         assert!(conf.icon.is_some());
     }
-}
\ No newline at end of file
+
+    // Helper function to get an empty directory for a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("netcode-config-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_instance_id_is_created_then_loaded() {
+        let dir = temp_dir("create");
+        let id = load_or_create_instance_id(&dir).unwrap();
+        assert!(dir.join(INSTANCE_ID_FILE).exists());
+
+        // Later runs get the same id
+        assert_eq!(load_or_create_instance_id(&dir).unwrap(), id);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_instance_id_is_replaced() {
+        let dir = temp_dir("corrupt");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(INSTANCE_ID_FILE), "not an id").unwrap();
+
+        let id = load_or_create_instance_id(&dir).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join(INSTANCE_ID_FILE)).unwrap().trim(), id.to_string());
+        assert_eq!(load_or_create_instance_id(&dir).unwrap(), id);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_frame_cap_is_saved_and_loaded() {
+        let dir = temp_dir("frame-cap");
+        assert_eq!(load_frame_cap(&dir), FrameCap::Uncapped);
+
+        save_frame_cap(&dir, FrameCap::Fps60).unwrap();
+        assert_eq!(load_frame_cap(&dir), FrameCap::Fps60);
+
+        // An unknown value falls back to uncapped
+        std::fs::write(dir.join(FRAME_CAP_FILE), "fast").unwrap();
+        assert_eq!(load_frame_cap(&dir), FrameCap::Uncapped);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_audio_mute_is_saved_and_loaded() {
+        let dir = temp_dir("audio-muted");
+        assert!(!load_audio_muted(&dir));
+
+        save_audio_muted(&dir, true).unwrap();
+        assert!(load_audio_muted(&dir));
+        save_audio_muted(&dir, false).unwrap();
+        assert!(!load_audio_muted(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_warning_thresholds_are_created_then_loaded() {
+        let dir = temp_dir("warnings");
+        assert_eq!(load_warning_thresholds(&dir).unwrap(), WarningThresholds::default());
+        assert!(dir.join(WARNING_THRESHOLDS_FILE).exists());
+
+        // Edited values are used, a broken file falls back to the defaults and is kept
+        std::fs::write(dir.join(WARNING_THRESHOLDS_FILE), r#"{ "loss": 0.2 }"#).unwrap();
+        assert_eq!(load_warning_thresholds(&dir).unwrap().loss, 0.2);
+        std::fs::write(dir.join(WARNING_THRESHOLDS_FILE), "{ broken").unwrap();
+        assert_eq!(load_warning_thresholds(&dir).unwrap(), WarningThresholds::default());
+        assert_eq!(std::fs::read_to_string(dir.join(WARNING_THRESHOLDS_FILE)).unwrap(), "{ broken");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_instance_hash() {
+        let id = Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+
+        // Stable across runs so sessions can be joined, different for different ids
+        assert_eq!(hash_instance_id(id), hash_instance_id(id));
+        assert_ne!(hash_instance_id(id), hash_instance_id(Uuid::from_u128(1)));
+
+        // The salt is part of the hash, it isn't a plain hash of the id
+        let unsalted = Sha256::digest(id.as_bytes());
+        assert_ne!(hash_instance_id(id), u64::from_be_bytes(unsalted[..8].try_into().unwrap()));
+    }
+
+    #[test]
+    fn test_language_is_saved_and_loaded() {
+        let dir = temp_dir("language");
+        assert_eq!(load_language(&dir), Language::English);
+
+        save_language(&dir, Language::Norwegian).unwrap();
+        assert_eq!(load_language(&dir), Language::Norwegian);
+
+        // An unknown language falls back to English
+        std::fs::write(dir.join(LANGUAGE_FILE), "klingon").unwrap();
+        assert_eq!(load_language(&dir), Language::English);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_session_log_severity_is_loaded() {
+        let dir = temp_dir("session-log");
+        assert_eq!(load_session_log_severity(&dir), Severity::Info);
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(SESSION_LOG_SEVERITY_FILE), "Warning\n").unwrap();
+        assert_eq!(load_session_log_severity(&dir), Severity::Warning);
+
+        // An unknown severity falls back to info
+        std::fs::write(dir.join(SESSION_LOG_SEVERITY_FILE), "everything").unwrap();
+        assert_eq!(load_session_log_severity(&dir), Severity::Info);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,9 +2,19 @@ use std::time::Duration;
 
 /// Constants for the game state
 pub const TIMEOUT: Duration = Duration::from_secs(5); // Timeout for player inactivity
+pub const RECONNECT_GRACE: Duration = Duration::from_secs(30); // How long a timed out player can resume with its session token
+pub const LAG_COMP_MAX_REWIND: Duration = Duration::from_millis(1000); // Furthest back lag compensation rewinds a player, older times use the current position
+pub const MAX_RESUMABLE_SESSIONS: usize = 256; // Most timed out sessions a room keeps for resuming, the one that timed out longest ago is forgotten first
 
 /// Constants for the game state
-pub const INTERPOLATION_DELAY: f32 = 0.016; // 16ms for 60fps interpolation
+pub const INTERPOLATION_DELAY: f64 = 0.05; // Remote players are drawn this many seconds behind the estimated server time
+pub const SERVER_CLOCK_RESYNC: f64 = 0.25; // Snapshots arriving this many seconds later than expected reset the server clock
+pub const CLOCK_DRIFT_WINDOW: f64 = 1.0; // Seconds of snapshots whose fastest delivery is one sample for the drift estimate
+pub const CLOCK_DRIFT_WINDOWS: usize = 30; // Number of recent windows the drift rate is fitted over
+pub const CLOCK_DRIFT_MIN_WINDOWS: usize = 3; // Windows needed before the drift rate is estimated
+pub const CLIENT_PAUSE_RESYNC: f64 = 2.0; // A client update this many seconds after the one before, as after a suspend, starts the timing over
+pub const CLOCK_MAX_DRIFT_RATE: f64 = 0.001; // Largest drift rate that is tracked, in seconds per second (1000 ppm)
+pub const CLOCK_STEP_THRESHOLD: f64 = 0.05; // A window this many seconds off the drift line is treated as a clock step
 
 /// Constants for window size
 pub const WINDOW_TITLE: &str = "Netcode Game"; // Title of the game window
@@ -12,6 +22,8 @@ pub const WINDOW_RESIZABLE: bool = false; // Whether the window is resizable
 pub const WINDOW_WIDTH: i32 = 1024; // Width of the game window
 pub const WINDOW_HEIGHT: i32 = 768; // Height of the game window
 pub const TOOL_BAR_HEIGHT: i32 = 40; // Height of the toolbar at the bottom of the window
+pub const TOOL_BAR_TEXT_SIZE: f32 = 20.0; // Text size in the toolbar
+pub const HIGH_CONTRAST_TEXT_SIZE: f32 = 26.0; // Enlarged toolbar text size in high-contrast mode
 
 /// Constants for the board
 pub const BOARD_WIDTH: i32 = 1024; // Width of the game board (usually the same as window width)
@@ -19,30 +31,278 @@ pub const BOARD_HEIGHT: i32 = 768; // Height of the game board (usually the same
 
 /// Constants for the player
 pub const PLAYER_SIZE: i32 = 20; // Size of the player character square
-pub const PLAYER_SPEED: i32 = 5; // Speed of the player character movement in pixels per frame
+pub const PLAYER_SPEED: i32 = 5; // Default speed of the player character movement in pixels per input, a player's own speed may be fractional
+pub const KNOCKBACK_DISTANCE: i32 = 3; // Pixels overlapping players are pushed apart per server tick
+pub const IMPULSE_RESEND_TICKS: u64 = 30; // Ticks an impulse is repeated in snapshots, so a lost snapshot doesn't lose it
+pub const MOVEMENT_STATS_TICKS: u64 = 60; // Snapshots carry the movement stats every this many ticks, the scoreboard doesn't need them more often
+
+/// Constants for player colors
+pub const SPAWN_HUE_SATURATION: f32 = 0.91; // Saturation of generated colors once the palette is used up, like the palette colors
+pub const SPAWN_HUE_VALUE: f32 = 1.0; // Brightness of generated colors once the palette is used up
+pub const BOT_DIM_FACTOR: f32 = 0.6; // Bots get the color of a human player with every channel scaled by this
+
+/// Constants for pellets
+pub const PELLET_COUNT: usize = 10; // Number of pellets on the board at any time
+pub const PELLET_SIZE: i32 = 10; // Size of a pellet square
+
+/// Constants for tag mode
+pub const TAG_COOLDOWN: Duration = Duration::from_secs(2); // A player that was just tagged can't tag anyone for this long
 
 /// Constants for server
 pub const BROADCAST_INTERVAL: Duration = Duration::from_millis(16); // 60fps game state updates
+pub const SERVER_STATS_INTERVAL: Duration = Duration::from_secs(10); // How often the server prints its stats line
+pub const LEADERBOARD_SAVE_INTERVAL: Duration = Duration::from_secs(30); // How often the server writes the leaderboard file
+pub const REORDER_RATE_WINDOW: Duration = Duration::from_secs(60); // Window the per-player rate of reordered inputs is counted over
+pub const COALESCED_INPUT_INTERVAL: Duration = Duration::from_micros(16_667); // Coalesced inputs are applied one per client frame at 60 fps, the rate a held key repeats at
+pub const CONSOLE_HISTORY_WINDOW: Duration = Duration::from_secs(5); // The history console command lists the moves of a player this far back
+pub const MAX_COALESCED_INPUTS: usize = 6; // Queued inputs beyond this are applied at once, so a client sending faster than expected doesn't fall further and further behind
+
+/// Constants for the server configuration
+pub const DEFAULT_BIND_ADDR: &str = "0.0.0.0:9000"; // Address the server binds unless the config or --bind says otherwise
+pub const DEFAULT_TICK_RATE: u32 = 60; // Broadcast ticks per second, a tick of BROADCAST_INTERVAL
+pub const MIN_TICK_RATE: u32 = 10; // Slower ticks make interpolation delay and input latency unplayable
+pub const MAX_TICK_RATE: u32 = 120; // Ticks are whole milliseconds, faster ones would round far off the rate
+
+/// Constants for the stats query
+pub const STATS_QUERY_MAGIC: &[u8] = b"STAT"; // A datagram starting with this asks for the server stats as JSON
+pub const STATS_QUERY_MAX_BYTES: usize = 128; // Longer datagrams are not stats queries, the magic and a token fit easily
+pub const STATS_QUERY_INTERVAL: Duration = Duration::from_secs(1); // A source address is answered at most once this often
+pub const STATS_QUERY_MAX_SOURCES: usize = 1024; // Most source addresses the rate limiter remembers, queries from more are dropped
+pub const STATS_REPLY_MAX_BYTES: usize = 1200; // Replies fit one unfragmented datagram, rooms beyond it are left out
+pub const STATS_TICK_WINDOW: usize = 600; // Ticks the timing percentiles are taken over, 10 seconds at the default tick rate
+
+/// Constants for the load shedding of the server receive loop
+pub const RECV_SOURCE_RATE: f64 = 300.0; // Datagrams per second a source address may send, a client sends an input per frame and its pings
+pub const RECV_SOURCE_BURST: f64 = 200.0; // Datagrams a source address may send at once after sending nothing for a while
+pub const RECV_MAX_SOURCES: usize = 4096; // Most source addresses that get a token bucket, datagrams from more are dropped
+pub const RECV_TICK_BUDGET: usize = 512; // Datagrams handled per tick, the rest of the tick's datagrams are shed
+pub const RECV_UNKNOWN_TICK_BUDGET: usize = 128; // Part of the tick budget datagrams from addresses without a player may use
+pub const RECV_BATCH_MAX: usize = 256; // Datagrams read off the socket before the batch is handled
+
+/// Constants for the offline mode
+pub const OFFLINE_SERVER_LABEL: &str = "offline"; // Server address shown for the in-process server of the offline mode
+pub const OFFLINE_BOTS: usize = 2; // Wandering bots the in-process server spawns, so the board isn't empty
+pub const OFFLINE_BOT_TURN_TICKS: std::ops::Range<u32> = 30..120; // Ticks a bot walks in one direction before it turns, unless it reaches an edge first
+
+/// Constants for match phases
+pub const MATCH_COUNTDOWN: Duration = Duration::from_secs(5); // Players are frozen at their spawn this long before a match starts
+pub const MATCH_END_PAUSE: Duration = Duration::from_secs(3); // Players are frozen this long after a match ends, before the next countdown
 
 /// Constants for performance testing
 pub const TEST_DURATION: Duration = Duration::from_millis(1000); // 1 second for performance tests
 pub const PERFORMANCE_TEST_FREQUENCY: Duration = Duration::from_secs(10); // Frequency of performance tests
+pub const ANALYSIS_REPORT_BUCKET_MS: u64 = 100; // Length of one sparkline bar in the report, in milliseconds of a test
+pub const ANALYSIS_RESERVOIR_SIZE: usize = 4096; // Samples kept of each series per condition unless every sample is captured
+pub const ANALYSIS_HISTOGRAM_BUCKETS: usize = 400; // Buckets of the histogram each series' percentiles are estimated from
+pub const HEATMAP_COLUMNS: usize = 32; // Cells across the board in the prediction error heatmap
+pub const HEATMAP_ROWS: usize = 24; // Cells down the board in the prediction error heatmap
+
+/// Constants for the headless analysis
+pub const ANALYZE_TEST_DURATION: Duration = Duration::from_secs(6); // How long the headless client tests each condition, one pass of the scripted walk
+pub const ANALYZE_WARMUP: Duration = Duration::from_millis(1000); // Samples this soon after a condition change are left out, while the old condition drains
+pub const ANALYZE_CONNECT_TIMEOUT: Duration = Duration::from_secs(3); // How long the headless client waits for the server to welcome it
+pub const REGRESSION_AVG_PREDICTION_ERROR: f32 = 20.0; // Average prediction error in pixels above which a condition regressed
+pub const REGRESSION_P95_PREDICTION_ERROR: f32 = 60.0; // 95th percentile of the prediction error in pixels above which a condition regressed
+pub const REGRESSION_MIN_RECONCILIATIONS: u32 = 1; // Fewer reconciled snapshots than this mean the condition wasn't measured
+
+/// Constants for the interpolation comparison
+pub const INTERPOLATION_COMPARE_TICKS: usize = 1800; // Ticks of the recorded path every strategy plays back, 30 s at 60 Hz
+pub const INTERPOLATION_COMPARE_FRAME: f64 = 1.0 / 60.0; // Seconds between two frames of the playback
+pub const INTERPOLATION_COMPARE_WARMUP: f64 = 1.0; // Seconds of playback left out while the server clock estimate settles
+
+/// Constants for the protocol
+pub const PROTOCOL_VERSION: u32 = 19; // Bumped on every intentional wire format change, the golden tests enforce it
+pub const MAX_DATAGRAM_SIZE: usize = 65_507; // Largest UDP payload, decoding a value never reads or allocates more
 
 /// Constants for network
 pub const DELAY_MS: i32 = 0; // Network delay in milliseconds
 pub const PACKET_LOSS: i32 = 0; // Packet loss percentage (0-100)
+pub const JITTER_MS: i32 = 0; // Random variation of the network delay in milliseconds
+pub const DUPLICATE_PERCENT: i32 = 0; // Percentage of packets the simulated network delivers twice (0-100)
+pub const MAX_REORDER_MS: u64 = 20; // Largest time a delayed packet may arrive ahead of one sent before it
+pub const SEND_PACING: Duration = Duration::from_millis(1); // Least time between delayed packets the client sends, so matured packets don't leave in one burst
+pub const JITTER_NORMAL_CLAMP: f64 = 3.0; // Normally distributed jitter is clamped to this many standard deviations
 pub const PING_INTERVAL: Duration = Duration::from_secs(1); // Interval for pinging the server
+pub const CLIENT_RECV_BUFFER_SIZE: usize = 1 << 20; // Requested client socket receive buffer, so a hitch doesn't overflow it
+pub const NETWORK_THREAD_POLL: Duration = Duration::from_millis(1); // Longest the client network thread waits for a datagram before checking its queues again
+pub const INCOMING_SNAPSHOT_QUEUE: usize = 64; // Received snapshots kept for a stalled render thread, the oldest are dropped beyond this
+pub const INCOMING_MESSAGE_QUEUE: usize = 256; // Received control messages kept for a stalled render thread, the oldest are dropped beyond this
+pub const SNAPSHOT_BURST_THRESHOLD: usize = 5; // More snapshots than this in one frame are treated as a burst
+pub const MAX_BURST_DELAY: f64 = 0.25; // Maximum extra interpolation delay after a burst (in seconds)
+pub const BURST_RECOVERY_TIME: f64 = 1.0; // Time for the extra interpolation delay to decay back to zero (in seconds)
+pub const INPUT_SEND_DELAY_HISTORY: usize = 256; // Recent inputs whose simulated send delay the client remembers for the latency breakdown
+pub const REMOVED_AFTER_MISSING_SNAPSHOTS: u32 = 30; // Snapshots in a row without the local player before the client takes it as removed by the server
+pub const SNAPSHOT_DEDUPE_WINDOW: usize = 128; // Recent snapshot ids the client remembers to skip a snapshot that arrives twice
+pub const SNAPSHOT_BOARD_MARGIN: i32 = 64; // Pixels a player in a snapshot may be off the board before the entry counts as an anomaly
+pub const TRAFFIC_RATE_WINDOW: u64 = 5; // Seconds of traffic the per category rates are averaged over
+pub const LATENCY_OVERLAY_WINDOW: u32 = 60; // Inputs averaged for each update of the latency overlay
+pub const TICK_CADENCE_WINDOW_MS: f64 = 2000.0; // Snapshot arrivals the observed tick interval is measured over
+pub const TICK_CADENCE_MIN_TICKS: u64 = 10; // Ticks the window has to span before the observed tick interval is trusted
+pub const TICK_CADENCE_TOLERANCE: f64 = 0.2; // Share the observed tick interval may be off the reported one before it is flagged
+
+/// Constants for round trip time estimation
+pub const SERVER_PING_INTERVAL: Duration = Duration::from_millis(500); // Interval for the server pinging each client
+pub const RTT_PING_EXPIRY: Duration = Duration::from_secs(5); // Pings without an echo after this long are considered lost
+pub const RTT_MAX_PENDING_PINGS: usize = 16; // Maximum number of pings waiting for an echo
+pub const RTT_SMOOTHING: f64 = 0.125; // Weight of a new sample in the smoothed RTT
+pub const RTT_OUTLIER_FACTOR: f64 = 3.0; // Samples this many times above the estimate are outliers
+pub const RTT_OUTLIERS_TO_ACCEPT: u32 = 3; // Consecutive outliers before the estimate jumps to the new value
 
 /// Constants for inputs from players
 pub const INITIAL_DELAY: f32 = 0.15; // Initial delay before input starts repeating
 pub const REPEAT_START: f32 = 0.1; // Delay before input starts repeating
 pub const REPEAT_MIN: f32 = 0.0; // Minimum delay between repeated inputs while key is held down
 pub const REPEAT_ACCEL: f32 = 0.3; // Acceleration factor for repeat input delay
+pub const INPUT_LOG_ROWS: usize = 8; // Sent inputs shown in the step mode log
+
+/// Constants for the input send rate
+pub const INPUT_SEND_BASE_INTERVAL: Duration = Duration::from_micros(33_333); // Inputs are batched and sent at 30 Hz normally
+pub const INPUT_SEND_REDUCED_INTERVAL: Duration = Duration::from_micros(66_667); // 15 Hz while the unacked backlog or the loss is high
+pub const MAX_INPUT_BATCH: usize = 8; // A batch this full is sent at once, so it fits the server's receive buffer in either protocol
+pub const IMMEDIATE_ENTER_RTT_MS: f64 = 50.0; // Inputs are sent one by one once the round trip time is at most this
+pub const IMMEDIATE_EXIT_RTT_MS: f64 = 80.0; // and back to batching once it is above this
+pub const IMMEDIATE_ENTER_UNACKED: usize = 2; // Inputs waiting for an ack to start sending one by one
+pub const IMMEDIATE_EXIT_UNACKED: usize = 6; // Inputs waiting for an ack to go back to batching
+pub const IMMEDIATE_MAX_LOSS: f64 = 0.02; // Loss above which inputs are never sent one by one
+pub const REDUCED_ENTER_UNACKED: usize = 16; // Inputs waiting for an ack to slow down to the reduced rate
+pub const REDUCED_EXIT_UNACKED: usize = 8; // Inputs waiting for an ack to speed up again
+pub const REDUCED_ENTER_LOSS: f64 = 0.10; // Loss to slow down to the reduced rate
+pub const REDUCED_EXIT_LOSS: f64 = 0.05; // Loss to speed up again
+pub const LOSS_SMOOTHING: f64 = 0.05; // Weight of one expected snapshot in the loss estimate
 
 /// New constants for improved interpolation
-pub const MAX_POSITION_HISTORY: usize = 30; // Maximum number of position snapshots to keep for interpolation
-pub const PREDICTION_ERROR_THRESHOLD: f32 = 5.0; // Maximum allowed prediction error before triggering reconciliation
+pub const MAX_POSITION_HISTORY: usize = 600; // Maximum number of position snapshots to keep for interpolation, 10 seconds so slow motion can play them back
+pub const MAX_PREDICTION_HISTORY: usize = 256; // Maximum number of unconfirmed predicted positions, about 4 seconds of inputs
+pub const MAX_REPLAY_RUNS_PER_FRAME: usize = 64; // Runs of pending inputs a reconciliation replays per frame, the rest waits for the next frames
+pub const PREDICTION_ERROR_THRESHOLD: f32 = 5.0; // Smallest expected prediction error, also used until the round trip time is measured
+pub const PREDICTION_ERROR_GOOD_FACTOR: f32 = 0.5; // Errors up to this fraction of the expected error are good
+pub const PREDICTION_ERROR_BAD_FACTOR: f32 = 1.5; // Errors above this multiple of the expected error are bad and snap the player
+pub const MOVEMENT_SPEED_TIME_CONSTANT: f32 = 0.25; // Seconds the measured movement speed takes to follow a change
+pub const INTERPOLATION_MAX_SPEED: f64 = PLAYER_SPEED as f64 * 60.0 * 5.0; // Samples implying a faster movement in px/s are spikes, 5 times one input per frame so input bursts pass
+pub const MAX_EXTRAPOLATION: f64 = 0.1; // Seconds the extrapolating strategy carries a remote player on past its newest position before holding it
 pub const MAX_INTERPOLATION_TIME: f32 = 0.1; // Maximum time to interpolate positions (in seconds)
+pub const REMOTE_PLAYER_STALE_AFTER: f64 = 5.0; // Remote players without a snapshot update for this many seconds are forgotten
+pub const MAX_REMOTE_PLAYERS: usize = 128; // Remote players buffered for interpolation, the least recently updated is forgotten beyond this
+
+/// Constants for the render clock
+pub const SLOW_MOTION_SCALE: f64 = 0.25; // Speed of the render clock in slow motion
+pub const RENDER_CLOCK_CATCH_UP_SCALE: f64 = 2.0; // Speed of the render clock while catching up after slow motion
+pub const CLOCK_DEBUG_STEP: f64 = 0.2; // Seconds the client clock jumps ahead when a step is triggered with F7
+
+/// Constants for team mode
+pub const MAX_TEAMS: u8 = 2; // Largest number of teams, each team needs its own palette
+
+/// Constants for the player name
+pub const MAX_NAME_CHARS: usize = 16; // Longest player name, in chars
+
+/// Constants for rooms
+pub const DEFAULT_ROOM: &str = "lobby"; // Room players join when they don't name one
+pub const MAX_ROOM_NAME_CHARS: usize = 24; // Longest room name, in chars
+pub const MAX_PASSWORD_CHARS: usize = 64; // Longest room password, connects with a longer one are refused
+pub const MAX_ROOMS: usize = 32; // Most rooms at once, configured ones included, joins that would create another are refused
+pub const EMPTY_ROOM_GRACE: Duration = Duration::from_secs(60); // Rooms created by a join are removed after being empty this long, longer than RECONNECT_GRACE
+pub const ROOM_LIST_INTERVAL: Duration = Duration::from_secs(1); // How often the client asks for the room list while on the menu
+
+/// Constants for the client instance id
+pub const CONFIG_DIR_NAME: &str = "netcode_game"; // Directory of the client in the user's config directory
+pub const INSTANCE_ID_FILE: &str = "instance_id"; // File in the config directory holding the client instance id
+pub const INSTANCE_ID_SALT: &[u8] = b"netcode_game instance id v1"; // Salt of the hash sent instead of the raw instance id
+
+/// Constants for crash handling
+pub const CRASH_LOG_PATH: &str = "client_crash.log"; // Default file panics of the client are appended to
+
+/// Constants for shutting down the client
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2); // The process is ended if quitting takes longer than this
+pub const NETWORK_CLOSE_TIMEOUT: Duration = Duration::from_millis(500); // Longest the client waits for its network thread to send what is queued and stop
+
+/// Constants for player visuals
+pub const PLAYER_FADE_TIME: f64 = 0.3; // Seconds players take to fade in after joining and fade out after leaving
+pub const COLOR_BLEND_TIME: f64 = 0.3; // Seconds a player's color takes to blend to a new color
+
+/// Constants for the frame budget watchdog
+pub const FRAME_BUDGET_MS: f32 = 12.0; // Average cost of update and render above which optional layers are shed, leaves room to present within 16 ms
+pub const FRAME_BUDGET_RESTORE_FACTOR: f32 = 0.6; // A shed layer comes back once the average cost is below this fraction of the budget
+pub const FRAME_BUDGET_WINDOW: usize = 60; // Frames averaged for each shed or restore decision
+
+/// Constants for accessibility status output
+pub const STATUS_CONNECTION_RATE_LIMIT: f32 = 0.5; // Minimum seconds between connection announcements
+pub const STATUS_TESTING_RATE_LIMIT: f32 = 0.5; // Minimum seconds between performance test announcements
+pub const STATUS_NETWORK_RATE_LIMIT: f32 = 1.0; // Minimum seconds between delay/packet loss announcements
+pub const STATUS_ERROR_RATE_LIMIT: f32 = 3.0; // Minimum seconds between prediction error announcements
+
+/// Constants for spectating
+pub const SPECTATOR_ZOOM: f32 = 2.0; // Screen pixels per board pixel while spectating, so the followed player is seen up close
+pub const SPECTATOR_PAN_SPEED: f32 = 600.0; // Screen pixels per second the free camera pans while a pan key is held
+
+/// Constants for frame pacing
+pub const FRAME_CAP_FILE: &str = "frame_cap"; // File in the config directory holding the chosen frame rate cap
+pub const FRAME_PACER_SPIN: Duration = Duration::from_millis(2); // End of a frame wait that is spun instead of slept, sleeps overshoot by about this much
+
+/// Constants for audio cues
+pub const AUDIO_CUE_MIN_INTERVAL: Duration = Duration::from_millis(100); // Shortest time between two plays of the same cue
+pub const AUDIO_MUTED_FILE: &str = "audio_muted"; // File in the config directory holding whether sounds are muted
+
+/// Constants for the interface language
+pub const LANGUAGE_FILE: &str = "language"; // File in the config directory holding the chosen language
+
+/// Constants for metric warnings
+pub const WARNING_EVALUATION_INTERVAL: Duration = Duration::from_secs(1); // How often the live metrics are checked against the thresholds
+pub const WARNING_BREACH_EVALUATIONS: u32 = 3; // Evaluations in a row a metric must be over its threshold before it is warned about
+pub const WARNING_RECOVERY_EVALUATIONS: u32 = 5; // Evaluations in a row a metric must be back under its threshold before the warning clears
+pub const WARNING_CRITICAL_FACTOR: f64 = 2.0; // A metric this many times over its threshold is shown in red instead of orange
+pub const WARNING_THRESHOLDS_FILE: &str = "warnings.json"; // File in the config directory holding the warning thresholds
+pub const WARNING_RTT_MS: f64 = 250.0; // Default round trip time threshold
+pub const WARNING_LOSS: f64 = 0.08; // Default threshold of the estimated packet loss, from 0 to 1
+pub const WARNING_PREDICTION_ERROR_P95: f64 = 20.0; // Default threshold of the 95th percentile prediction error in pixels
+pub const WARNING_STARVATION: f64 = 0.10; // Default threshold of the share of remote players whose interpolation buffer ran dry
+
+/// Constants for the demo mode
+pub const DEMO_INTERVAL: Duration = Duration::from_secs(20); // Default time between two demo scenarios
+pub const DEMO_PAUSE: Duration = Duration::from_secs(60); // The demo director stays out of the way this long after the last key
+
+/// Constants for the self-test
+pub const SELF_TEST_CLOCK_SAMPLES: usize = 100; // Readings of the monotonic clock checked for steps back
+pub const SELF_TEST_MAX_CLOCK_RESOLUTION: Duration = Duration::from_millis(1); // Coarsest clock step that still times frames and inputs
+pub const SELF_TEST_SOCKET_TIMEOUT: Duration = Duration::from_secs(1); // How long the loopback check waits for its own datagram
+
+/// Constants for the mini-map
+pub const MINIMAP_WIDTH: f32 = 160.0; // Width of the mini-map in screen pixels, the height follows the board's aspect
+pub const MINIMAP_MARGIN: f32 = 8.0; // Gap between the mini-map and the screen edge and toolbar
+pub const MINIMAP_DOT_SIZE: f32 = 3.0; // Radius of a player's dot, the local player's is drawn larger
+
+/// Constants for frame captures
+pub const CAPTURE_DIR: &str = "captures"; // Directory frame captures are saved in unless another one is given
+pub const CAPTURE_INDEX_FILE: &str = "index.json"; // File in the captures directory listing every capture with its metrics
+pub const CAPTURE_COOLDOWN: Duration = Duration::from_secs(2); // Shortest time between two captures by a condition, so one event is saved once
+pub const CAPTURE_PREDICTION_ERROR: f32 = 40.0; // Default prediction error in pixels above which a frame is captured
+pub const CAPTURE_SNAPSHOT_GAP: u64 = 5; // Default number of snapshots missing in a row above which a frame is captured
+
+/// Constants for bot scripts
+pub const BOT_INPUT_INTERVAL: Duration = Duration::from_millis(100); // Time between two inputs of a scripted move unless the script sets one
+
+/// Constants for viewport culling
+pub const CULL_MARGIN: f32 = PLAYER_SIZE as f32 * 2.0; // Distance outside the visible area within which players and pellets are still drawn, covers outlines and tag markers
+
+/// Constants for the overlap highlight
+pub const OVERLAP_GRID_MIN_PLAYERS: usize = 32; // From this many players overlaps are found through grid buckets instead of checking every pair
+pub const OVERLAP_HIGHLIGHT_MARGIN: f32 = 3.0; // Gap between an overlapping pair and its highlight outline in board pixels
+
+/// Constants for the prediction watchdog
+pub const PREDICTION_WATCHDOG_WINDOW: f64 = 10.0; // Seconds of prediction errors the watchdog decides on
+pub const PREDICTION_WATCHDOG_P95: f32 = 150.0; // 95th percentile prediction error in pixels over the window that turns the prediction off
+pub const PREDICTION_WATCHDOG_MIN_RESYNCS: usize = 3; // Snaps to the server within the window before the prediction is given up on
+pub const PREDICTION_WATCHDOG_MIN_SAMPLES: usize = 30; // Errors measured within the window before it is judged
+pub const LOCAL_SNAPSHOT_HISTORY: usize = 32; // Snapshot positions of the local player kept to seed its interpolation when the prediction is turned off
 
+/// Constants for the time travel scrubber
+pub const SCRUB_CAPTURE_INTERVAL: f64 = 0.1; // Seconds between two captures of the client state
+pub const SCRUB_HISTORY: usize = 100; // Captures kept for scrubbing, 10 seconds at the capture interval
 
+/// Constants for the profiler
+pub const PROFILE_WINDOW: usize = 120; // Frames the profile overlay averages over, two seconds at 60 FPS
 
+/// Constants for the session log
+pub const SESSION_LOG_DIR: &str = "sessions"; // Directory session logs are written to unless another one is given
+pub const SESSION_LOG_SEVERITY_FILE: &str = "session_log_severity"; // File in the config directory holding the lowest severity the session log keeps
+pub const SESSION_LOG_FLUSH_LINES: usize = 64; // Buffered lines that are written out right away
+pub const SESSION_LOG_FLUSH_INTERVAL_MS: u64 = 5_000; // Longest a line stays buffered before it is written
@@ -0,0 +1,113 @@
+use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH, TOOL_BAR_HEIGHT};
+use crate::minimap::ScreenRect;
+use crate::positions::DisplayPos;
+use crate::spectator::View;
+use crate::types::Position;
+
+/// Width and height of the play area in board pixels, the part of the window above the toolbar. Board
+/// space is where the server and the prediction have the players, in whole pixels: the origin is the top
+/// left corner of the board, x grows to the right and y grows down.
+pub const PLAY_AREA: (i32, i32) = (BOARD_WIDTH, BOARD_HEIGHT - TOOL_BAR_HEIGHT);
+
+/// Width and height of the window in board pixels at zoom 1, the play area with the toolbar below it
+pub const WINDOW: (i32, i32) = (BOARD_WIDTH, BOARD_HEIGHT);
+
+/// The play area as a float size, for scaling
+pub fn play_area_size() -> (f32, f32) {
+    (PLAY_AREA.0 as f32, PLAY_AREA.1 as f32)
+}
+
+/// The window as a float size, for scaling
+pub fn window_size() -> (f32, f32) {
+    (WINDOW.0 as f32, WINDOW.1 as f32)
+}
+
+/// A board position as a float point of the same space
+pub fn board_point(position: Position) -> (f32, f32) {
+    (position.x as f32, position.y as f32)
+}
+
+/// The board position nearest to a float point of board space
+pub fn board_position(point: (f32, f32)) -> Position {
+    Position { x: point.0.round() as i32, y: point.1.round() as i32 }
+}
+
+/// Point of board space a position is drawn at, before the view
+pub fn render_point(position: DisplayPos) -> (f32, f32) {
+    board_point(position.layout_position())
+}
+
+/// The position moved onto the play area, its edges included
+pub fn clamp_to_play_area(position: Position) -> Position {
+    Position { x: position.x.clamp(0, PLAY_AREA.0), y: position.y.clamp(0, PLAY_AREA.1) }
+}
+
+/// Point in a screen rect of a board point, with the play area scaled to fill the rect
+pub fn to_rect(board: (f32, f32), rect: ScreenRect) -> (f32, f32) {
+    let (width, height) = play_area_size();
+    (rect.0 + board.0 * rect.2 / width, rect.1 + board.1 * rect.3 / height)
+}
+
+/// Board point of a point in a screen rect the play area is scaled into, the inverse of to_rect
+pub fn from_rect(point: (f32, f32), rect: ScreenRect) -> (f32, f32) {
+    let (width, height) = play_area_size();
+    ((point.0 - rect.0) * width / rect.2, (point.1 - rect.1) * height / rect.3)
+}
+
+/// Screen position of a board point through a view on a screen of the given size. Render space is screen
+/// pixels as floats, without a view it is board space at zoom 1 with the origin in the top left corner.
+pub fn to_screen(view: &View, board: (f32, f32), screen: (f32, f32)) -> (f32, f32) {
+    let (left, top, _, _) = view.visible_rect(screen);
+    ((board.0 - left) * view.zoom, (board.1 - top) * view.zoom)
+}
+
+/// Board point under a screen position through a view, the inverse of to_screen
+pub fn to_board(view: &View, point: (f32, f32), screen: (f32, f32)) -> (f32, f32) {
+    let (left, top, _, _) = view.visible_rect(screen);
+    (left + point.0 / view.zoom, top + point.1 / view.zoom)
+}
+
+/// Tests for the coordinate conversions
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to compare points up to float rounding
+    fn assert_close(actual: (f32, f32), expected: (f32, f32)) {
+        assert!((actual.0 - expected.0).abs() < 1e-3 && (actual.1 - expected.1).abs() < 1e-3, "{:?} is not {:?}", actual, expected);
+    }
+
+    #[test]
+    fn test_play_area_is_the_window_above_the_toolbar() {
+        assert_eq!(PLAY_AREA, (BOARD_WIDTH, BOARD_HEIGHT - TOOL_BAR_HEIGHT));
+        assert_eq!(WINDOW, (BOARD_WIDTH, BOARD_HEIGHT));
+        assert_eq!(clamp_to_play_area(Position { x: -5, y: BOARD_HEIGHT }), Position { x: 0, y: PLAY_AREA.1 });
+        assert_eq!(clamp_to_play_area(Position { x: 40, y: 30 }), Position { x: 40, y: 30 });
+
+        // The origin is the top left corner, y grows down
+        let view = View::whole_board();
+        assert_eq!(to_screen(&view, (0.0, 0.0), window_size()), (0.0, 0.0));
+        assert_eq!(to_screen(&view, (10.0, 20.0), window_size()), (10.0, 20.0));
+    }
+
+    #[test]
+    fn test_conversions_round_trip() {
+        let positions = [Position { x: 0, y: 0 }, Position { x: 123, y: 456 }, Position { x: PLAY_AREA.0, y: PLAY_AREA.1 }, Position { x: -7, y: 900 }];
+        let minimap = (700.0, 20.0, 256.0, 180.0);
+        let screen = (800.0, 600.0);
+        let views = [View::whole_board(), View::centered_on(Position { x: 300, y: 200 }, 2.5, screen), View { center: (10.0, 700.0), zoom: 0.5 }];
+        for position in positions {
+            let point = board_point(position);
+            assert_eq!(board_position(point), position);
+            assert_eq!(render_point(DisplayPos::smoothed(position)), point);
+            assert_close(from_rect(to_rect(point, minimap), minimap), point);
+            for view in &views {
+                assert_close(to_board(view, to_screen(view, point, screen), screen), point);
+            }
+        }
+
+        // The corners of the play area are the corners of a rect it is scaled into
+        assert_eq!(to_rect((0.0, 0.0), minimap), (700.0, 20.0));
+        assert_close(to_rect(play_area_size(), minimap), (956.0, 200.0));
+    }
+}
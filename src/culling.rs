@@ -0,0 +1,117 @@
+use crate::minimap::ScreenRect;
+use crate::spectator::View;
+
+/// Board area shown on a screen of the given size. Without a view the board is drawn at its own size from
+/// the top left corner, so the area is the screen itself.
+pub fn visible_board_rect(view: Option<&View>, screen: (f32, f32)) -> ScreenRect {
+    match view {
+        Some(view) => view.visible_rect(screen),
+        None => (0.0, 0.0, screen.0, screen.1),
+    }
+}
+
+/// The rect grown by margin on every side
+pub fn expand(rect: ScreenRect, margin: f32) -> ScreenRect {
+    let (x, y, width, height) = rect;
+    (x - margin, y - margin, width + margin * 2.0, height + margin * 2.0)
+}
+
+/// True if the point is in the rect, its edges included
+pub fn contains_point(rect: ScreenRect, point: (f32, f32)) -> bool {
+    let (x, y, width, height) = rect;
+    (x..=x + width).contains(&point.0) && (y..=y + height).contains(&point.1)
+}
+
+/// True if the rects share any area or touch
+pub fn intersects(a: ScreenRect, b: ScreenRect) -> bool {
+    a.0 <= b.0 + b.2 && b.0 <= a.0 + a.2 && a.1 <= b.1 + b.3 && b.1 <= a.1 + a.3
+}
+
+/// Decides what is worth drawing this frame: anything entirely outside the visible board area grown by a
+/// margin is skipped. Only drawing is skipped, the caller still updates everything it culls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Culler {
+    bounds: Option<ScreenRect>, // Visible area with the margin, None draws everything
+}
+
+/// Implementation of the Culler
+impl Culler {
+    /// Culls what is further than margin outside the visible area
+    pub fn new(visible: ScreenRect, margin: f32) -> Self {
+        Self { bounds: Some(expand(visible, margin)) }
+    }
+
+    /// Draws everything, for comparing frame costs without culling
+    pub fn disabled() -> Self {
+        Self { bounds: None }
+    }
+
+    /// True if something drawn around the point, no larger than the margin, may be seen
+    pub fn shows_point(&self, point: (f32, f32)) -> bool {
+        self.bounds.is_none_or(|bounds| contains_point(bounds, point))
+    }
+
+    /// True if a circle drawn around the center may be seen, for circles that can outgrow the margin
+    pub fn shows_circle(&self, center: (f32, f32), radius: f32) -> bool {
+        self.shows_rect((center.0 - radius, center.1 - radius, radius * 2.0, radius * 2.0))
+    }
+
+    /// True if any part of the rect may be seen
+    pub fn shows_rect(&self, rect: ScreenRect) -> bool {
+        self.bounds.is_none_or(|bounds| intersects(bounds, rect))
+    }
+}
+
+/// Tests for the viewport culling
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH};
+    use crate::types::Position;
+
+    #[test]
+    fn test_visible_rect_follows_the_view() {
+        let screen = (800.0, 600.0);
+        assert_eq!(visible_board_rect(None, screen), (0.0, 0.0, 800.0, 600.0));
+
+        // Zoomed in twice on the top left corner a quarter of the screen is seen
+        let view = View::centered_on(Position { x: 0, y: 0 }, 2.0, screen);
+        assert_eq!(visible_board_rect(Some(&view), screen), (0.0, 0.0, 400.0, 300.0));
+        let view = View::centered_on(Position { x: BOARD_WIDTH, y: BOARD_HEIGHT }, 4.0, screen);
+        let (x, y, width, height) = visible_board_rect(Some(&view), screen);
+        assert_eq!((width, height), (200.0, 150.0));
+        assert!(x + width <= BOARD_WIDTH as f32 + 1e-3 && y + height <= BOARD_HEIGHT as f32 + 1e-3);
+    }
+
+    #[test]
+    fn test_points_and_rects_against_a_rect() {
+        let rect = (10.0, 20.0, 100.0, 50.0);
+        assert!(contains_point(rect, (10.0, 20.0)) && contains_point(rect, (110.0, 70.0)) && contains_point(rect, (60.0, 45.0)));
+        assert!(!contains_point(rect, (9.9, 45.0)) && !contains_point(rect, (60.0, 70.1)));
+
+        // Overlapping, touching and containing rects intersect, rects beside or above don't
+        assert!(intersects(rect, (100.0, 60.0, 50.0, 50.0)));
+        assert!(intersects(rect, (110.0, 20.0, 5.0, 5.0)));
+        assert!(intersects(rect, (0.0, 0.0, 500.0, 500.0)) && intersects((0.0, 0.0, 500.0, 500.0), rect));
+        assert!(!intersects(rect, (111.0, 20.0, 5.0, 5.0)));
+        assert!(!intersects(rect, (10.0, 0.0, 100.0, 19.0)));
+        assert_eq!(expand(rect, 5.0), (5.0, 15.0, 110.0, 60.0));
+    }
+
+    #[test]
+    fn test_culler_keeps_the_margin_and_can_be_disabled() {
+        let culler = Culler::new((0.0, 0.0, 400.0, 300.0), 20.0);
+        assert!(culler.shows_point((200.0, 150.0)));
+        assert!(culler.shows_point((415.0, -20.0)));
+        assert!(!culler.shows_point((421.0, 150.0)));
+        assert!(!culler.shows_point((700.0, 500.0)));
+
+        // A large circle reaches in from further out, an outline across the corner shows
+        assert!(culler.shows_circle((500.0, 150.0), 90.0));
+        assert!(!culler.shows_circle((500.0, 150.0), 70.0));
+        assert!(culler.shows_rect((410.0, 310.0, 50.0, 50.0)));
+
+        let everything = Culler::disabled();
+        assert!(everything.shows_point((1e6, -1e6)) && everything.shows_rect((-1e6, 0.0, 1.0, 1.0)));
+    }
+}
@@ -0,0 +1,262 @@
+use crate::constants::DEMO_PAUSE;
+use crate::coords::PLAY_AREA;
+use crate::types::Position;
+
+use std::time::{Duration, Instant};
+
+/// Something the demo director makes the client do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DemoAction {
+    SetConditions { delay_ms: i32, jitter_ms: i32, packet_loss: i32 }, // Simulated network conditions, as if picked on the toolbar
+    RestoreConditions, // Back to the network conditions from before the demo changed them
+    Disconnect,        // Go silent like pressing R, so the server times the player out
+    Reconnect,         // Resume the session like pressing R again
+    Teleport(Position), // Ask the server to place the local player, needs a server with debug teleports
+}
+
+/// Carries out the actions of the demo director: the client app in the game, a recorder in the tests
+pub trait DemoActionSink {
+    fn perform(&mut self, action: DemoAction);
+}
+
+/// One scripted demonstration: actions when it starts, a caption while it runs and actions when it ends
+#[derive(Debug, Clone, PartialEq)]
+pub struct DemoScenario {
+    pub title: &'static str,
+    pub caption: &'static str, // What the visitor is looking at, shown while the scenario runs
+    pub duration: Duration,
+    pub start: Vec<DemoAction>,
+    pub end: Vec<DemoAction>, // Also performed when a visitor interrupts the scenario, so nothing is left broken
+}
+
+/// The scenarios the demo mode cycles through
+pub fn default_scenarios() -> Vec<DemoScenario> {
+    let center = Position { x: PLAY_AREA.0 / 2, y: PLAY_AREA.1 / 2 };
+    vec![
+        DemoScenario {
+            title: "Latency spike",
+            caption: "Packets now take 300 ms each way. Your square still moves at once because the client predicts it.",
+            duration: Duration::from_secs(5),
+            start: vec![DemoAction::SetConditions { delay_ms: 300, jitter_ms: 40, packet_loss: 0 }],
+            end: vec![DemoAction::RestoreConditions],
+        },
+        DemoScenario {
+            title: "Burst loss",
+            caption: "Most packets are lost. Interpolation covers the missing snapshots of the other players.",
+            duration: Duration::from_secs(3),
+            start: vec![DemoAction::SetConditions { delay_ms: 0, jitter_ms: 0, packet_loss: 60 }],
+            end: vec![DemoAction::RestoreConditions],
+        },
+        DemoScenario {
+            title: "Connection drop",
+            caption: "The client went silent and the server times it out. Reconnecting resumes the session with its token.",
+            duration: Duration::from_secs(6),
+            start: vec![DemoAction::Disconnect],
+            end: vec![DemoAction::Reconnect],
+        },
+        DemoScenario {
+            title: "Debug teleport",
+            caption: "The server moved the player to the middle. The prediction is corrected to the server's position.",
+            duration: Duration::from_secs(3),
+            start: vec![DemoAction::Teleport(center)],
+            end: Vec::new(),
+        },
+    ]
+}
+
+/// What the director is doing
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DemoPhase {
+    Waiting { next_at: Instant },                  // Until the next scenario starts
+    Running { scenario: usize, started: Instant }, // A scenario is running
+    Paused { until: Instant },                     // A visitor is playing, the director stays out of the way
+}
+
+/// Text the client shows for the demo
+#[derive(Debug, Clone, PartialEq)]
+pub struct DemoCaption {
+    pub heading: String,
+    pub text: &'static str,
+}
+
+/// Unattended demo mode for exhibitions: every interval the director runs the next scenario, such as a
+/// latency spike or a dropped connection, and captions what is happening. Any keyboard input pauses it for
+/// DEMO_PAUSE so a visitor can play, ending a running scenario first.
+#[derive(Debug, Clone)]
+pub struct DemoDirector {
+    scenarios: Vec<DemoScenario>,
+    interval: Duration, // Time from the end of one scenario to the start of the next
+    next: usize,        // Scenario that runs next, they are run in order and over again
+    phase: DemoPhase,
+}
+
+/// Implementation of the DemoDirector
+impl DemoDirector {
+    /// Creates a director that runs the first scenario an interval after now
+    pub fn new(scenarios: Vec<DemoScenario>, interval: Duration, now: Instant) -> Self {
+        Self { scenarios, interval, next: 0, phase: DemoPhase::Waiting { next_at: now + interval } }
+    }
+
+    /// Starts and ends scenarios when they are due
+    pub fn update(&mut self, now: Instant, sink: &mut impl DemoActionSink) {
+        match self.phase {
+            DemoPhase::Waiting { next_at } if now >= next_at && !self.scenarios.is_empty() => {
+                let scenario = self.next;
+                self.next = (self.next + 1) % self.scenarios.len();
+                self.phase = DemoPhase::Running { scenario, started: now };
+                for action in &self.scenarios[scenario].start {
+                    sink.perform(*action);
+                }
+            }
+            DemoPhase::Running { scenario, started } if now.saturating_duration_since(started) >= self.scenarios[scenario].duration => {
+                self.end_scenario(scenario, sink);
+                self.phase = DemoPhase::Waiting { next_at: now + self.interval };
+            }
+            DemoPhase::Paused { until } if now >= until => {
+                self.phase = DemoPhase::Waiting { next_at: now };
+            }
+            _ => {}
+        }
+    }
+
+    /// A visitor used the keyboard: a running scenario is ended right away and the director pauses
+    /// until DEMO_PAUSE after the last input
+    pub fn user_input(&mut self, now: Instant, sink: &mut impl DemoActionSink) {
+        if let DemoPhase::Running { scenario, .. } = self.phase {
+            self.end_scenario(scenario, sink);
+        }
+        self.phase = DemoPhase::Paused { until: now + DEMO_PAUSE };
+    }
+
+    /// Checks if a visitor is playing
+    pub fn is_paused(&self) -> bool {
+        matches!(self.phase, DemoPhase::Paused { .. })
+    }
+
+    /// Caption of the running scenario, or when the next one starts. Nothing while a visitor plays.
+    pub fn caption(&self, now: Instant) -> Option<DemoCaption> {
+        let seconds_until = |at: Instant| at.saturating_duration_since(now).as_secs_f64().ceil() as u64;
+        match self.phase {
+            DemoPhase::Running { scenario, started } => {
+                let scenario = &self.scenarios[scenario];
+                Some(DemoCaption {
+                    heading: format!("Demo: {} ({} s)", scenario.title, seconds_until(started + scenario.duration)),
+                    text: scenario.caption,
+                })
+            }
+            DemoPhase::Waiting { next_at } => self.scenarios.get(self.next).map(|scenario| DemoCaption {
+                heading: format!("Demo mode: {} in {} s", scenario.title, seconds_until(next_at)),
+                text: "Press any key to play.",
+            }),
+            DemoPhase::Paused { .. } => None,
+        }
+    }
+
+    /// Performs the actions that end a scenario
+    fn end_scenario(&self, scenario: usize, sink: &mut impl DemoActionSink) {
+        for action in &self.scenarios[scenario].end {
+            sink.perform(*action);
+        }
+    }
+}
+
+/// Tests for the demo director
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the actions instead of performing them
+    #[derive(Default)]
+    struct RecordingSink {
+        actions: Vec<DemoAction>,
+    }
+
+    /// Implementation of the DemoActionSink for the RecordingSink
+    impl DemoActionSink for RecordingSink {
+        fn perform(&mut self, action: DemoAction) {
+            self.actions.push(action);
+        }
+    }
+
+    // Helper function to create a director over two short scenarios with a 10 second interval
+    fn director(now: Instant) -> DemoDirector {
+        let scenarios = vec![
+            DemoScenario {
+                title: "Spike",
+                caption: "Slow",
+                duration: Duration::from_secs(5),
+                start: vec![DemoAction::SetConditions { delay_ms: 300, jitter_ms: 0, packet_loss: 0 }],
+                end: vec![DemoAction::RestoreConditions],
+            },
+            DemoScenario {
+                title: "Drop",
+                caption: "Gone",
+                duration: Duration::from_secs(3),
+                start: vec![DemoAction::Disconnect],
+                end: vec![DemoAction::Reconnect],
+            },
+        ];
+        DemoDirector::new(scenarios, Duration::from_secs(10), now)
+    }
+
+    #[test]
+    fn test_scenarios_run_in_order_with_the_interval_between() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let mut demo = director(start);
+        let mut sink = RecordingSink::default();
+
+        demo.update(at(9), &mut sink);
+        assert!(sink.actions.is_empty());
+        assert_eq!(demo.caption(at(9)).unwrap().heading, "Demo mode: Spike in 1 s");
+
+        // Spike from 10 to 15 s, then 10 s of waiting before the drop from 25 to 28 s
+        let mut seen = Vec::new();
+        for second in 10..=40 {
+            demo.update(at(second), &mut sink);
+            seen.extend(sink.actions.drain(..).map(|action| (second, action)));
+        }
+        assert_eq!(seen, vec![
+            (10, DemoAction::SetConditions { delay_ms: 300, jitter_ms: 0, packet_loss: 0 }),
+            (15, DemoAction::RestoreConditions),
+            (25, DemoAction::Disconnect),
+            (28, DemoAction::Reconnect),
+            (38, DemoAction::SetConditions { delay_ms: 300, jitter_ms: 0, packet_loss: 0 }),
+        ]);
+        assert_eq!(demo.caption(at(40)), Some(DemoCaption { heading: "Demo: Spike (3 s)".to_string(), text: "Slow" }));
+    }
+
+    #[test]
+    fn test_keyboard_input_ends_the_scenario_and_pauses() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let mut demo = director(start);
+        let mut sink = RecordingSink::default();
+        demo.update(at(10), &mut sink);
+        sink.actions.clear();
+
+        // The visitor gets a normal connection back and no captions while playing
+        demo.user_input(at(12), &mut sink);
+        assert_eq!(sink.actions, vec![DemoAction::RestoreConditions]);
+        assert!(demo.is_paused());
+        assert_eq!(demo.caption(at(12)), None);
+
+        // Every input pushes the pause out, the next scenario runs once it is over
+        demo.user_input(at(40), &mut sink);
+        demo.update(at(99), &mut sink);
+        assert!(demo.is_paused());
+        demo.update(at(100), &mut sink);
+        demo.update(at(100), &mut sink);
+        assert_eq!(sink.actions, vec![DemoAction::RestoreConditions, DemoAction::Disconnect]);
+    }
+
+    #[test]
+    fn test_without_scenarios_nothing_happens() {
+        let start = Instant::now();
+        let mut demo = DemoDirector::new(Vec::new(), Duration::ZERO, start);
+        let mut sink = RecordingSink::default();
+        demo.update(start + Duration::from_secs(1), &mut sink);
+        assert!(sink.actions.is_empty());
+        assert_eq!(demo.caption(start), None);
+    }
+}
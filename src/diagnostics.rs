@@ -0,0 +1,362 @@
+use crate::codec::{decode, encode};
+use crate::constants::{SELF_TEST_CLOCK_SAMPLES, SELF_TEST_MAX_CLOCK_RESOLUTION, SELF_TEST_SOCKET_TIMEOUT};
+use crate::regression::RegressionThresholds;
+use crate::server_config::ServerConfig;
+use crate::smoke::CheckResult;
+use crate::snapshot::{decode_snapshot, encode_snapshot};
+use crate::types::{AppliedImpulse, ClientMessage, Direction, DisconnectReason, GameState, InputTiming, MatchPhase, MovementStats, PlayerInput, PlayerShape, Position, RejectReason, RoomInfo, Welcome};
+use crate::warnings::WarningThresholds;
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// A runtime check of the self-test, returns what it measured when it passes and why it failed otherwise
+pub type DiagnosticCheck = Box<dyn Fn() -> Result<String, String>>;
+
+/// Runtime checks for platforms where the game fails in odd ways, run by --self-test of the binaries.
+/// Every check is a named closure, so a subsystem can register its own next to the standard ones.
+#[derive(Default)]
+pub struct SelfTest {
+    checks: Vec<(String, DiagnosticCheck)>,
+}
+
+/// Outcome of every check of a self-test run, in the order they were registered
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestReport {
+    pub results: Vec<CheckResult>,
+}
+
+/// Implementation of the SelfTest
+impl SelfTest {
+    /// A self-test with the checks every binary runs: encoding, clocks, sockets and config files
+    pub fn with_standard_checks() -> Self {
+        let mut self_test = Self::default();
+        self_test.register("message round trip", check_message_round_trip);
+        self_test.register("snapshot round trip", check_snapshot_round_trip);
+        self_test.register("monotonic clock", || check_monotonic_clock(SELF_TEST_CLOCK_SAMPLES));
+        self_test.register("clock resolution", check_clock_resolution);
+        self_test.register("udp loopback", || check_udp_loopback(SELF_TEST_SOCKET_TIMEOUT));
+        self_test.register("config parsing", check_config_parsing);
+        self_test
+    }
+
+    /// Adds a check that runs after the ones registered before it
+    pub fn register(&mut self, name: &str, check: impl Fn() -> Result<String, String> + 'static) {
+        self.checks.push((name.to_string(), Box::new(check)));
+    }
+
+    /// Runs every check, a check that panics fails instead of ending the run
+    pub fn run(&self) -> SelfTestReport {
+        let results = self.checks.iter()
+            .map(|(name, check)| {
+                let started = Instant::now();
+                let outcome = panic::catch_unwind(AssertUnwindSafe(check))
+                    .unwrap_or_else(|payload| Err(format!("panicked: {}", panic_text(payload.as_ref()))));
+                let elapsed = started.elapsed();
+                let passed = outcome.is_ok();
+                let detail = outcome.unwrap_or_else(|reason| reason);
+                CheckResult { name: name.clone(), passed, detail, elapsed }
+            })
+            .collect();
+        SelfTestReport { results }
+    }
+}
+
+/// Implementation of the SelfTestReport
+impl SelfTestReport {
+    /// Whether every check passed. A run without checks didn't verify anything and doesn't pass.
+    pub fn passed(&self) -> bool {
+        !self.results.is_empty() && self.results.iter().all(|result| result.passed)
+    }
+
+    /// Process exit code for the run, 0 if it passed and 1 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.passed() { 0 } else { 1 }
+    }
+
+    /// One PASS or FAIL line per check followed by a summary
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for result in &self.results {
+            let verdict = if result.passed { "PASS" } else { "FAIL" };
+            report.push_str(&format!("{} {} ({} ms): {}\n", verdict, result.name, result.elapsed.as_millis(), result.detail));
+        }
+        let failed = self.results.iter().filter(|result| !result.passed).count();
+        if self.passed() {
+            report.push_str(&format!("Self-test passed: {} checks\n", self.results.len()));
+        } else {
+            report.push_str(&format!("Self-test failed: {} of {} checks\n", failed, self.results.len()));
+        }
+        report
+    }
+}
+
+/// Text of a panic payload, for the panicked checks
+fn panic_text(payload: &(dyn std::any::Any + Send)) -> String {
+    payload.downcast_ref::<&str>().map(|text| text.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "no message".to_string())
+}
+
+/// One message of every kind, with fields set so a broken encoding of any of them shows up
+pub fn sample_messages() -> Vec<ClientMessage> {
+    let id = Uuid::from_u128(0x1234_5678_9abc_def0_1234_5678_9abc_def0);
+    let input = PlayerInput { dir: Direction::Left, sequence: 70_000, timestamp: 1_700_000_000_123 };
+    vec![
+        ClientMessage::Connect,
+        ClientMessage::PlayerId(id),
+        ClientMessage::Input(input),
+        ClientMessage::Ping(1_700_000_000_123),
+        ClientMessage::Pong(1_700_000_000_123),
+        ClientMessage::MatchReset { match_number: 4, positions: vec![(id, Position { x: 50, y: 60 })] },
+        ClientMessage::Welcome(Welcome::new(id, Uuid::from_u128(7), Position { x: 320, y: 240 }, Some(77))),
+        ClientMessage::Reconnect { session_token: id },
+        ClientMessage::ServerPing(0xdead_beef),
+        ClientMessage::ServerPong(0xdead_beef),
+        ClientMessage::ConnectWithProfile {
+            name: "Kari ✓".to_string(),
+            shape: PlayerShape::Triangle.to_wire(),
+            instance: u64::MAX,
+            room: "lobby".to_string(),
+            password: Some("secret".to_string()),
+        },
+        ClientMessage::ListRooms,
+        ClientMessage::RoomList(vec![RoomInfo { name: "lobby".to_string(), players: 3, password_required: true }]),
+        ClientMessage::ConnectRejected { reason: RejectReason::WrongPassword },
+        ClientMessage::InputBatch(vec![input, PlayerInput { dir: Direction::Down, sequence: 70_001, ..input }]),
+        ClientMessage::WithSession { session_token: id, message: Box::new(ClientMessage::Input(input)) },
+        ClientMessage::Disconnected { id, reason: DisconnectReason::TimedOut },
+        ClientMessage::DebugSetPosition(Position { x: -5, y: 900 }),
+        ClientMessage::Leave,
+    ]
+}
+
+/// A snapshot with every extension block filled in
+fn sample_snapshot() -> GameState {
+    let id = Uuid::from_u128(42);
+    GameState {
+        players: vec![(id, Position { x: 120, y: 340 }, 0xff1717)],
+        last_processed: HashMap::from([(id, 512)]),
+        pellets: vec![Position { x: 30, y: 40 }],
+        scores: HashMap::from([(id, 4)]),
+        best_scores: HashMap::from([(id, 9)]),
+        teams: HashMap::from([(id, 1)]),
+        input_reorders: HashMap::from([(id, 2)]),
+        shapes: HashMap::from([(id, PlayerShape::Circle)]),
+        impulses: HashMap::from([(id, AppliedImpulse { dx: -3, dy: 6, after_sequence: 510, tick: 8_990 })]),
+        input_timings: HashMap::from([(id, InputTiming { sequence: 512, client_timestamp: 98_765, received_ms: 143_980, applied_ms: 144_000 })]),
+        match_phase: MatchPhase::Countdown { remaining_ms: 4200 },
+        server_timestamp: 123_456,
+        match_number: 3,
+        ping_ms: HashMap::from([(id, 35)]),
+        snapshot_id: 9_001,
+        tick_interval_ms: 16,
+        tagged: Some(id),
+        movement_stats: HashMap::from([(id, MovementStats { distance: 12_345, inputs: 2_048, connected_ms: 61_000 })]),
+    }
+}
+
+/// Every sample message decodes to itself in the binary protocol
+pub fn check_message_round_trip() -> Result<String, String> {
+    let messages = sample_messages();
+    for message in &messages {
+        let bytes = encode(message);
+        match decode::<ClientMessage>(&bytes) {
+            Ok(decoded) if decoded == *message => {}
+            Ok(decoded) => return Err(format!("{:?} came back as {:?}", message, decoded)),
+            Err(e) => return Err(format!("{:?} didn't decode: {}", message, e.describe())),
+        }
+    }
+    Ok(format!("{} message kinds", messages.len()))
+}
+
+/// A snapshot with every extension block decodes to itself
+pub fn check_snapshot_round_trip() -> Result<String, String> {
+    let snapshot = sample_snapshot();
+    let bytes = encode_snapshot(&snapshot);
+    match decode_snapshot(&bytes) {
+        Some(decoded) if decoded == snapshot => Ok(format!("{} bytes", bytes.len())),
+        Some(_) => Err("the snapshot came back different".to_string()),
+        None => Err("the snapshot didn't decode".to_string()),
+    }
+}
+
+/// The monotonic clock never steps back over the samples
+pub fn check_monotonic_clock(samples: usize) -> Result<String, String> {
+    let readings: Vec<Instant> = (0..samples).map(|_| Instant::now()).collect();
+    match readings.windows(2).position(|pair| pair[1] < pair[0]) {
+        Some(index) => Err(format!("stepped back between samples {} and {}", index, index + 1)),
+        None => Ok(format!("{} samples", samples)),
+    }
+}
+
+/// The monotonic clock ticks finely enough to time frames and inputs, and how long a 1 ms sleep takes
+pub fn check_clock_resolution() -> Result<String, String> {
+    let resolution = clock_resolution().ok_or("the clock didn't tick within a second")?;
+    let started = Instant::now();
+    std::thread::sleep(Duration::from_millis(1));
+    let slept = started.elapsed();
+    let measured = format!("resolution {:?}, a 1 ms sleep took {:.2} ms", resolution, slept.as_secs_f64() * 1000.0);
+    if resolution > SELF_TEST_MAX_CLOCK_RESOLUTION {
+        return Err(format!("{}, coarser than {:?}", measured, SELF_TEST_MAX_CLOCK_RESOLUTION));
+    }
+    Ok(measured)
+}
+
+/// Smallest step of the monotonic clock seen over a few ticks, None if it doesn't tick at all
+fn clock_resolution() -> Option<Duration> {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut smallest: Option<Duration> = None;
+    for _ in 0..10 {
+        let start = Instant::now();
+        let mut now = Instant::now();
+        while now == start {
+            if now >= deadline {
+                return smallest;
+            }
+            now = Instant::now();
+        }
+        let step = now - start;
+        smallest = Some(smallest.map_or(step, |smallest| smallest.min(step)));
+    }
+    smallest
+}
+
+/// A non-blocking UDP socket on an ephemeral loopback port receives a datagram it sent to itself
+/// within the timeout, and reports an empty queue as WouldBlock rather than blocking or failing
+pub fn check_udp_loopback(timeout: Duration) -> Result<String, String> {
+    let socket = UdpSocket::bind("127.0.0.1:0").map_err(|e| format!("bind failed: {}", e))?;
+    socket.set_nonblocking(true).map_err(|e| format!("couldn't make the socket non-blocking: {}", e))?;
+    let addr = socket.local_addr().map_err(|e| format!("no local address: {}", e))?;
+
+    let mut buf = [0u8; 64];
+    match socket.recv_from(&mut buf) {
+        Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+        Err(e) => return Err(format!("an empty socket failed with {} instead of WouldBlock", e)),
+        Ok((size, from)) => return Err(format!("an empty socket received {} bytes from {}", size, from)),
+    }
+
+    let sent = encode(&ClientMessage::Ping(1_700_000_000_123));
+    socket.send_to(&sent, addr).map_err(|e| format!("send failed: {}", e))?;
+    let started = Instant::now();
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((size, from)) if from == addr && buf[..size] == sent[..] => {
+                return Ok(format!("{} bytes on port {} after {} µs", size, addr.port(), started.elapsed().as_micros()));
+            }
+            Ok((size, from)) => return Err(format!("received {} unexpected bytes from {}", size, from)),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(format!("receive failed: {}", e)),
+        }
+        if started.elapsed() >= timeout {
+            return Err(format!("nothing arrived within {} ms", timeout.as_millis()));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// The config and threshold files are written and read back the way the binaries do it
+pub fn check_config_parsing() -> Result<String, String> {
+    let config = ServerConfig::default();
+    let parsed = ServerConfig::from_toml(&config.to_toml())?;
+    if parsed != config {
+        return Err("the server config changed when read back from TOML".to_string());
+    }
+
+    let warnings = WarningThresholds::default();
+    let text = serde_json::to_string(&warnings).map_err(|e| e.to_string())?;
+    let parsed: WarningThresholds = serde_json::from_str(&text).map_err(|e| format!("warning thresholds: {}", e))?;
+    if parsed != warnings {
+        return Err("the warning thresholds changed when read back from JSON".to_string());
+    }
+
+    let regression = RegressionThresholds::default();
+    let text = serde_json::to_string(&regression).map_err(|e| e.to_string())?;
+    let parsed: RegressionThresholds = serde_json::from_str(&text).map_err(|e| format!("regression thresholds: {}", e))?;
+    if parsed != regression {
+        return Err("the regression thresholds changed when read back from JSON".to_string());
+    }
+    Ok("server config, warning and regression thresholds".to_string())
+}
+
+/// Tests for the self-test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to number the message kinds, so a message added without a sample fails to compile here
+    fn kind_index(message: &ClientMessage) -> usize {
+        match message {
+            ClientMessage::Connect => 0,
+            ClientMessage::PlayerId(_) => 1,
+            ClientMessage::Input(_) => 2,
+            ClientMessage::Ping(_) => 3,
+            ClientMessage::Pong(_) => 4,
+            ClientMessage::MatchReset { .. } => 5,
+            ClientMessage::Welcome(_) => 6,
+            ClientMessage::Reconnect { .. } => 7,
+            ClientMessage::ServerPing(_) => 8,
+            ClientMessage::ServerPong(_) => 9,
+            ClientMessage::ConnectWithProfile { .. } => 10,
+            ClientMessage::ListRooms => 11,
+            ClientMessage::RoomList(_) => 12,
+            ClientMessage::ConnectRejected { .. } => 13,
+            ClientMessage::InputBatch(_) => 14,
+            ClientMessage::WithSession { .. } => 15,
+            ClientMessage::Disconnected { .. } => 16,
+            ClientMessage::DebugSetPosition(_) => 17,
+            ClientMessage::Leave => 18,
+        }
+    }
+
+    #[test]
+    fn test_samples_cover_every_message_kind() {
+        let indices: Vec<usize> = sample_messages().iter().map(kind_index).collect();
+        assert_eq!(indices, (0..19).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_standard_checks_pass_here() {
+        let report = SelfTest::with_standard_checks().run();
+        assert!(report.passed(), "{}", report.report());
+        let names: Vec<&str> = report.results.iter().map(|result| result.name.as_str()).collect();
+        assert_eq!(names, vec!["message round trip", "snapshot round trip", "monotonic clock", "clock resolution", "udp loopback", "config parsing"]);
+        assert_eq!(report.results[0].detail, "19 message kinds");
+    }
+
+    #[test]
+    fn test_registered_checks_report_pass_and_fail() {
+        let mut self_test = SelfTest::default();
+        assert!(!self_test.run().passed());
+
+        self_test.register("fine", || Ok("all good".to_string()));
+        self_test.register("broken", || Err("no luck".to_string()));
+        self_test.register("exploding", || panic!("boom"));
+        let report = self_test.run();
+        assert_eq!(report.exit_code(), 1);
+        assert_eq!(report.results.iter().map(|result| result.passed).collect::<Vec<bool>>(), vec![true, false, false]);
+        assert_eq!(report.results[2].detail, "panicked: boom");
+
+        let text = report.report();
+        assert!(text.starts_with("PASS fine ("));
+        assert!(text.contains("FAIL broken ("));
+        assert!(text.trim_end().ends_with("Self-test failed: 2 of 3 checks"));
+    }
+
+    #[test]
+    fn test_udp_loopback_round_trip() {
+        // Runs the real check on this machine's loopback interface
+        let detail = check_udp_loopback(Duration::from_secs(2)).unwrap();
+        assert!(detail.starts_with(&format!("{} bytes on port", encode(&ClientMessage::Ping(1_700_000_000_123)).len())), "{}", detail);
+    }
+
+    #[test]
+    fn test_monotonic_clock_check() {
+        assert_eq!(check_monotonic_clock(100), Ok("100 samples".to_string()));
+        assert!(clock_resolution().is_some_and(|resolution| resolution > Duration::ZERO));
+    }
+}
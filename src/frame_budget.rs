@@ -0,0 +1,193 @@
+use crate::constants::{FRAME_BUDGET_MS, FRAME_BUDGET_RESTORE_FACTOR, FRAME_BUDGET_WINDOW};
+
+/// Optional visual layers, in the order they are shed when frames take too long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionalLayer {
+    LatencyBars,    // Latency budget overlay, only drawn while it is toggled on
+    Minimap,        // Mini-map of every player in the toolbar corner, only drawn while it is toggled on
+    ErrorIndicator, // Circle around the local player showing the prediction error
+    PlayerFades,    // Fading players in and out and blending their colors
+}
+
+/// Implementation of the OptionalLayer
+impl OptionalLayer {
+    /// Every layer, first shed first
+    pub const ALL: [OptionalLayer; 4] = [OptionalLayer::LatencyBars, OptionalLayer::Minimap, OptionalLayer::ErrorIndicator, OptionalLayer::PlayerFades];
+
+    /// Shed level from which the layer is no longer drawn
+    fn shed_at(self) -> usize {
+        self as usize + 1
+    }
+}
+
+/// Next shed level for an average frame cost. One more layer is shed while the cost is over the budget,
+/// one comes back once it is well below, in between the level is kept so it doesn't flap.
+pub fn next_shed_level(average_ms: f32, level: usize) -> usize {
+    if average_ms > FRAME_BUDGET_MS {
+        (level + 1).min(OptionalLayer::ALL.len())
+    } else if average_ms < FRAME_BUDGET_MS * FRAME_BUDGET_RESTORE_FACTOR {
+        level.saturating_sub(1)
+    } else {
+        level
+    }
+}
+
+/// A layer shed or restored, with the average frame cost of the window that decided it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShedChange {
+    pub average_ms: f32,    // Average cost of the frames in the window
+    pub shed_level: usize,  // Number of layers not drawn from now on
+}
+
+/// Implementation of the ShedChange
+impl ShedChange {
+    /// Describes the change for the log, so frame costs can be compared between runs
+    pub fn describe(&self) -> String {
+        let drawn = OptionalLayer::ALL.len() - self.shed_level;
+        format!("Frames took {:.1} ms on average, drawing {} of {} optional layers", self.average_ms, drawn, OptionalLayer::ALL.len())
+    }
+}
+
+/// Measures the cost of recent frames and decides which optional layers are drawn.
+/// Decides once per window of frames, so the effect of a change is measured before the next one.
+#[derive(Debug, Clone, Default)]
+pub struct FrameWatchdog {
+    costs: Vec<f32>,              // Cost of the frames in the current window in milliseconds
+    shed_level: usize,            // Number of layers not drawn, from the start of OptionalLayer::ALL
+    last_average_ms: Option<f32>, // Average cost of the last full window
+}
+
+/// Implementation of the FrameWatchdog
+impl FrameWatchdog {
+    /// Creates a watchdog that draws every layer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the cost of the last frame in milliseconds, shedding or restoring a layer once a window is measured.
+    /// Returns the change if a layer was shed or restored.
+    pub fn record(&mut self, cost_ms: f32) -> Option<ShedChange> {
+        self.costs.push(cost_ms);
+        if self.costs.len() < FRAME_BUDGET_WINDOW {
+            return None;
+        }
+
+        let average = self.costs.iter().sum::<f32>() / self.costs.len() as f32;
+        let previous = self.shed_level;
+        self.shed_level = next_shed_level(average, self.shed_level);
+        self.last_average_ms = Some(average);
+        self.costs.clear();
+        (self.shed_level != previous).then_some(ShedChange { average_ms: average, shed_level: self.shed_level })
+    }
+
+    /// Average frame cost of the last full window in milliseconds, None before the first one
+    pub fn last_average_ms(&self) -> Option<f32> {
+        self.last_average_ms
+    }
+
+    /// Checks if a layer is drawn
+    pub fn draws(&self, layer: OptionalLayer) -> bool {
+        self.shed_level < layer.shed_at()
+    }
+
+    /// Checks if any layer is shed
+    pub fn is_shedding(&self) -> bool {
+        self.shed_level > 0
+    }
+}
+
+/// Tests for the frame budget watchdog
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to record a number of frames with the same cost
+    fn frames(watchdog: &mut FrameWatchdog, cost_ms: f32, count: usize) {
+        for _ in 0..count {
+            watchdog.record(cost_ms);
+        }
+    }
+
+    #[test]
+    fn test_next_shed_level() {
+        let over = FRAME_BUDGET_MS + 1.0;
+        let between = FRAME_BUDGET_MS * (1.0 + FRAME_BUDGET_RESTORE_FACTOR) / 2.0;
+        let under = FRAME_BUDGET_MS * FRAME_BUDGET_RESTORE_FACTOR - 1.0;
+        let max = OptionalLayer::ALL.len();
+
+        // Shed one layer at a time, never more than there are
+        assert_eq!(next_shed_level(over, 0), 1);
+        assert_eq!(next_shed_level(over, max - 1), max);
+        assert_eq!(next_shed_level(over, max), max);
+
+        // Between the thresholds nothing changes
+        assert_eq!(next_shed_level(between, 0), 0);
+        assert_eq!(next_shed_level(between, 1), 1);
+        assert_eq!(next_shed_level(FRAME_BUDGET_MS, 1), 1);
+
+        // Restore one layer at a time once there is headroom
+        assert_eq!(next_shed_level(under, max), max - 1);
+        assert_eq!(next_shed_level(under, 0), 0);
+    }
+
+    #[test]
+    fn test_watchdog_sheds_in_order_and_restores() {
+        let mut watchdog = FrameWatchdog::new();
+        assert!(OptionalLayer::ALL.iter().all(|layer| watchdog.draws(*layer)));
+
+        // A single slow frame doesn't shed anything
+        frames(&mut watchdog, FRAME_BUDGET_MS * 10.0, 1);
+        frames(&mut watchdog, 1.0, FRAME_BUDGET_WINDOW - 1);
+        assert!(!watchdog.is_shedding());
+
+        // Slow frames shed the first layer, a new window is measured before the next one goes
+        frames(&mut watchdog, FRAME_BUDGET_MS * 2.0, FRAME_BUDGET_WINDOW);
+        assert!(!watchdog.draws(OptionalLayer::LatencyBars));
+        assert!(watchdog.draws(OptionalLayer::Minimap));
+        frames(&mut watchdog, FRAME_BUDGET_MS * 2.0, FRAME_BUDGET_WINDOW);
+        assert!(!watchdog.draws(OptionalLayer::Minimap));
+        assert!(watchdog.draws(OptionalLayer::ErrorIndicator));
+        frames(&mut watchdog, FRAME_BUDGET_MS * 2.0, FRAME_BUDGET_WINDOW);
+        assert!(!watchdog.draws(OptionalLayer::ErrorIndicator));
+        assert!(watchdog.draws(OptionalLayer::PlayerFades));
+        frames(&mut watchdog, FRAME_BUDGET_MS * 2.0, FRAME_BUDGET_WINDOW - 1);
+        assert!(watchdog.draws(OptionalLayer::PlayerFades));
+        frames(&mut watchdog, FRAME_BUDGET_MS * 2.0, 1);
+        assert!(!watchdog.draws(OptionalLayer::PlayerFades));
+
+        // Just under the budget is not enough headroom to bring a layer back
+        frames(&mut watchdog, FRAME_BUDGET_MS * 0.9, FRAME_BUDGET_WINDOW * 3);
+        assert!(!watchdog.draws(OptionalLayer::PlayerFades));
+
+        // With headroom the last shed layer comes back first
+        frames(&mut watchdog, 1.0, FRAME_BUDGET_WINDOW);
+        assert!(watchdog.draws(OptionalLayer::PlayerFades));
+        assert!(!watchdog.draws(OptionalLayer::ErrorIndicator));
+        frames(&mut watchdog, 1.0, FRAME_BUDGET_WINDOW);
+        assert!(watchdog.draws(OptionalLayer::ErrorIndicator));
+        assert!(!watchdog.draws(OptionalLayer::Minimap));
+        frames(&mut watchdog, 1.0, FRAME_BUDGET_WINDOW);
+        assert!(watchdog.draws(OptionalLayer::Minimap));
+        assert!(!watchdog.draws(OptionalLayer::LatencyBars));
+        frames(&mut watchdog, 1.0, FRAME_BUDGET_WINDOW);
+        assert!(!watchdog.is_shedding());
+    }
+
+    #[test]
+    fn test_changes_are_reported_with_the_window_cost() {
+        let mut watchdog = FrameWatchdog::new();
+        assert_eq!(watchdog.last_average_ms(), None);
+        for _ in 0..FRAME_BUDGET_WINDOW - 1 {
+            assert_eq!(watchdog.record(FRAME_BUDGET_MS * 2.0), None);
+        }
+        let change = watchdog.record(FRAME_BUDGET_MS * 2.0).unwrap();
+        assert_eq!(change, ShedChange { average_ms: FRAME_BUDGET_MS * 2.0, shed_level: 1 });
+        assert_eq!(change.describe(), format!("Frames took {:.1} ms on average, drawing 3 of 4 optional layers", FRAME_BUDGET_MS * 2.0));
+
+        // A window that keeps the level reports nothing, but its cost is kept
+        let between = FRAME_BUDGET_MS * (1.0 + FRAME_BUDGET_RESTORE_FACTOR) / 2.0;
+        let changes: Vec<ShedChange> = (0..FRAME_BUDGET_WINDOW).filter_map(|_| watchdog.record(between)).collect();
+        assert!(changes.is_empty());
+        assert!((watchdog.last_average_ms().unwrap() - between).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::client_events::ClientEvent;
+use crate::constants::{CAPTURE_COOLDOWN, CAPTURE_INDEX_FILE, CAPTURE_PREDICTION_ERROR, CAPTURE_SNAPSHOT_GAP};
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Why a frame was captured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureTrigger {
+    PredictionError, // The prediction error of the local player went over its threshold
+    SnapshotGap,     // More snapshots than the threshold went missing in a row
+    Manual,          // The capture key was pressed
+}
+
+/// Implementation of the CaptureTrigger
+impl CaptureTrigger {
+    /// Name of the trigger in capture file names
+    pub fn name(self) -> &'static str {
+        match self {
+            CaptureTrigger::PredictionError => "prediction-error",
+            CaptureTrigger::SnapshotGap => "snapshot-gap",
+            CaptureTrigger::Manual => "manual",
+        }
+    }
+}
+
+/// Metrics of one frame the triggers are evaluated on
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameMetrics {
+    pub prediction_error: Option<f32>, // Largest prediction error measured this frame, in pixels
+    pub snapshot_gap: u64,             // Most snapshots missing right before one applied this frame
+    pub rtt_ms: Option<u32>,           // Round trip time measured by the server
+    pub manual: bool,                  // The capture key was pressed
+}
+
+/// Implementation of the FrameMetrics
+impl FrameMetrics {
+    /// Adds what an applied snapshot measured, keeping the largest error and gap of the frame
+    pub fn record_snapshot(&mut self, prediction_error: Option<f32>, snapshot_gap: u64) {
+        self.prediction_error = match (self.prediction_error, prediction_error) {
+            (Some(largest), Some(error)) => Some(largest.max(error)),
+            (largest, error) => largest.or(error),
+        };
+        self.snapshot_gap = self.snapshot_gap.max(snapshot_gap);
+    }
+
+    /// Adds what a client event measured: the prediction error of an applied snapshot or a snapshot gap
+    pub fn record_event(&mut self, event: &ClientEvent) {
+        match event {
+            ClientEvent::Snapshot(outcome) => self.record_snapshot(outcome.prediction_error, 0),
+            ClientEvent::SnapshotGap { count } => self.record_snapshot(None, *count),
+            _ => {}
+        }
+    }
+}
+
+/// Conditions that capture a frame by themselves, a condition without a threshold is off.
+/// The capture key works either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CaptureTriggers {
+    pub prediction_error: Option<f32>, // Capture when the prediction error is above this many pixels
+    pub snapshot_gap: Option<u64>,     // Capture when more than this many snapshots in a row went missing
+}
+
+/// Implementation of the CaptureTriggers
+impl CaptureTriggers {
+    /// Both conditions at their default thresholds
+    pub fn standard() -> Self {
+        Self { prediction_error: Some(CAPTURE_PREDICTION_ERROR), snapshot_gap: Some(CAPTURE_SNAPSHOT_GAP) }
+    }
+
+    /// The trigger that fires for the metrics of a frame: the capture key, then the prediction error, then the gap
+    pub fn evaluate(&self, metrics: &FrameMetrics) -> Option<CaptureTrigger> {
+        if metrics.manual {
+            return Some(CaptureTrigger::Manual);
+        }
+        if self.prediction_error.zip(metrics.prediction_error).is_some_and(|(threshold, error)| error > threshold) {
+            return Some(CaptureTrigger::PredictionError);
+        }
+        if self.snapshot_gap.is_some_and(|threshold| metrics.snapshot_gap > threshold) {
+            return Some(CaptureTrigger::SnapshotGap);
+        }
+        None
+    }
+}
+
+/// Keeps one event from capturing a frame every frame it lasts. The capture key isn't held back.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CaptureCooldown {
+    last_capture: Option<Instant>, // Last frame captured by a condition
+}
+
+/// Implementation of the CaptureCooldown
+impl CaptureCooldown {
+    /// Whether a frame may be captured for the trigger now, and starts the cooldown if a condition fired
+    pub fn allow(&mut self, trigger: CaptureTrigger, now: Instant) -> bool {
+        if trigger == CaptureTrigger::Manual {
+            return true;
+        }
+        if self.last_capture.is_some_and(|last| now.saturating_duration_since(last) < CAPTURE_COOLDOWN) {
+            return false;
+        }
+        self.last_capture = Some(now);
+        true
+    }
+}
+
+/// File name of a capture: when it was taken in Unix milliseconds, so names sort by time, then the trigger
+/// and the metrics of the frame, like 1760620212345_prediction-error_error-42px_gap-0_rtt-120ms.png
+pub fn capture_file_name(trigger: CaptureTrigger, metrics: &FrameMetrics, unix_ms: u64) -> String {
+    let mut name = format!("{}_{}", unix_ms, trigger.name());
+    if let Some(error) = metrics.prediction_error {
+        name.push_str(&format!("_error-{:.0}px", error));
+    }
+    name.push_str(&format!("_gap-{}", metrics.snapshot_gap));
+    if let Some(rtt) = metrics.rtt_ms {
+        name.push_str(&format!("_rtt-{}ms", rtt));
+    }
+    name + ".png"
+}
+
+/// A saved capture in the index, with the metrics of its frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    pub file: String, // Name of the PNG in the captures directory
+    pub trigger: CaptureTrigger,
+    pub unix_ms: u64,
+    pub prediction_error: Option<f32>,
+    pub snapshot_gap: u64,
+    pub rtt_ms: Option<u32>,
+}
+
+/// Saves the current frame to a file. Kept thin so tests can record the captures without a window.
+pub trait FrameSink {
+    fn save(&mut self, path: &Path) -> io::Result<()>;
+}
+
+/// Saves the frame drawn on the screen as a PNG through macroquad
+#[cfg(feature = "client")]
+pub struct ScreenSink;
+
+/// Implementation of the FrameSink for the ScreenSink
+#[cfg(feature = "client")]
+impl FrameSink for ScreenSink {
+    fn save(&mut self, path: &Path) -> io::Result<()> {
+        // The screen is read bottom row first
+        let screen = macroquad::texture::get_screen_data();
+        let row = screen.width as usize * 4;
+        let bytes: Vec<u8> = screen.bytes.chunks(row).rev().flatten().copied().collect();
+        image::save_buffer(path, &bytes, screen.width as u32, screen.height as u32, image::ExtendedColorType::Rgba8)
+            .map_err(io::Error::other)
+    }
+}
+
+/// Captures frames into a directory when a trigger fires, and keeps an index of the captures in it
+pub struct FrameCapturer {
+    dir: PathBuf,
+    triggers: CaptureTriggers,
+    cooldown: CaptureCooldown,
+    sink: Option<Box<dyn FrameSink>>, // Nothing is saved without one
+    records: Vec<CaptureRecord>,
+}
+
+/// Implementation of the FrameCapturer
+impl FrameCapturer {
+    /// Creates a capturer saving into the directory, which is created with the first capture
+    pub fn new(dir: PathBuf, triggers: CaptureTriggers) -> Self {
+        Self { dir, triggers, cooldown: CaptureCooldown::default(), sink: None, records: Vec::new() }
+    }
+
+    /// Sets what saves the frames
+    pub fn set_sink(&mut self, sink: Box<dyn FrameSink>) {
+        self.sink = Some(sink);
+    }
+
+    /// Directory the captures and the index are saved in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Captures saved so far, oldest first
+    pub fn records(&self) -> &[CaptureRecord] {
+        &self.records
+    }
+
+    /// Evaluates the triggers on the metrics of the frame just drawn and saves it if one fires, then rewrites
+    /// the index. Returns the new record, None when nothing fired or the cooldown held it back.
+    pub fn update(&mut self, metrics: &FrameMetrics, now: Instant, unix_ms: u64) -> io::Result<Option<&CaptureRecord>> {
+        let Some(sink) = self.sink.as_mut() else {
+            return Ok(None);
+        };
+        let Some(trigger) = self.triggers.evaluate(metrics).filter(|trigger| self.cooldown.allow(*trigger, now)) else {
+            return Ok(None);
+        };
+
+        let file = capture_file_name(trigger, metrics, unix_ms);
+        std::fs::create_dir_all(&self.dir)?;
+        sink.save(&self.dir.join(&file))?;
+        self.records.push(CaptureRecord {
+            file,
+            trigger,
+            unix_ms,
+            prediction_error: metrics.prediction_error,
+            snapshot_gap: metrics.snapshot_gap,
+            rtt_ms: metrics.rtt_ms,
+        });
+        let index = serde_json::to_string_pretty(&self.records).map_err(io::Error::other)?;
+        std::fs::write(self.dir.join(CAPTURE_INDEX_FILE), index)?;
+        Ok(self.records.last())
+    }
+}
+
+/// Tests for the frame capture hooks
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_world::SnapshotOutcome;
+    use std::time::Duration;
+
+    // Helper function to create the metrics of a frame
+    fn metrics(prediction_error: Option<f32>, snapshot_gap: u64, manual: bool) -> FrameMetrics {
+        FrameMetrics { prediction_error, snapshot_gap, rtt_ms: Some(120), manual }
+    }
+
+    /// Records the paths it was asked to save to and writes an empty file there
+    struct RecordingSink(std::rc::Rc<std::cell::RefCell<Vec<PathBuf>>>);
+
+    /// Implementation of the FrameSink for the RecordingSink
+    impl FrameSink for RecordingSink {
+        fn save(&mut self, path: &Path) -> io::Result<()> {
+            self.0.borrow_mut().push(path.to_path_buf());
+            std::fs::write(path, [])
+        }
+    }
+
+    #[test]
+    fn test_triggers_fire_above_their_thresholds() {
+        let triggers = CaptureTriggers { prediction_error: Some(30.0), snapshot_gap: Some(3) };
+        assert_eq!(triggers.evaluate(&metrics(Some(30.0), 3, false)), None);
+        assert_eq!(triggers.evaluate(&metrics(Some(31.0), 4, false)), Some(CaptureTrigger::PredictionError));
+        assert_eq!(triggers.evaluate(&metrics(None, 4, false)), Some(CaptureTrigger::SnapshotGap));
+        assert_eq!(triggers.evaluate(&metrics(Some(31.0), 4, true)), Some(CaptureTrigger::Manual));
+
+        // Without thresholds only the capture key captures
+        assert_eq!(CaptureTriggers::default().evaluate(&metrics(Some(500.0), 100, false)), None);
+        assert_eq!(CaptureTriggers::default().evaluate(&metrics(None, 0, true)), Some(CaptureTrigger::Manual));
+
+        // A frame keeps the largest error and gap of its snapshots
+        let mut frame = FrameMetrics::default();
+        frame.record_snapshot(Some(4.0), 2);
+        frame.record_snapshot(None, 0);
+        frame.record_snapshot(Some(3.0), 1);
+        assert_eq!((frame.prediction_error, frame.snapshot_gap), (Some(4.0), 2));
+
+        // The same from the events of the snapshots
+        let mut frame = FrameMetrics::default();
+        frame.record_event(&ClientEvent::SnapshotGap { count: 2 });
+        frame.record_event(&ClientEvent::Snapshot(SnapshotOutcome { prediction_error: Some(4.0), ..SnapshotOutcome::default() }));
+        frame.record_event(&ClientEvent::SnapCorrection { magnitude: 50.0 });
+        frame.record_event(&ClientEvent::SnapshotGap { count: 1 });
+        assert_eq!((frame.prediction_error, frame.snapshot_gap), (Some(4.0), 2));
+    }
+
+    #[test]
+    fn test_cooldown_holds_back_conditions_but_not_the_key() {
+        let start = Instant::now();
+        let mut cooldown = CaptureCooldown::default();
+        assert!(cooldown.allow(CaptureTrigger::PredictionError, start));
+        assert!(!cooldown.allow(CaptureTrigger::SnapshotGap, start + CAPTURE_COOLDOWN / 2));
+        assert!(cooldown.allow(CaptureTrigger::Manual, start + CAPTURE_COOLDOWN / 2));
+        assert!(cooldown.allow(CaptureTrigger::PredictionError, start + CAPTURE_COOLDOWN));
+        assert!(!cooldown.allow(CaptureTrigger::PredictionError, start + CAPTURE_COOLDOWN + Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_file_names_carry_the_trigger_and_metrics() {
+        let name = capture_file_name(CaptureTrigger::PredictionError, &metrics(Some(41.6), 0, false), 1760620212345);
+        assert_eq!(name, "1760620212345_prediction-error_error-42px_gap-0_rtt-120ms.png");
+        let name = capture_file_name(CaptureTrigger::SnapshotGap, &FrameMetrics { snapshot_gap: 7, ..FrameMetrics::default() }, 5);
+        assert_eq!(name, "5_snapshot-gap_gap-7.png");
+    }
+
+    #[test]
+    fn test_captures_are_saved_and_indexed() {
+        let dir = std::env::temp_dir().join(format!("netcode-captures-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let saved = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut capturer = FrameCapturer::new(dir.clone(), CaptureTriggers::standard());
+        let start = Instant::now();
+        let spike = metrics(Some(CAPTURE_PREDICTION_ERROR + 10.0), 0, false);
+
+        // Nothing is saved without a sink
+        assert!(capturer.update(&spike, start, 1000).unwrap().is_none());
+        capturer.set_sink(Box::new(RecordingSink(saved.clone())));
+
+        // One spike lasting several frames is saved once
+        assert_eq!(capturer.update(&spike, start, 2000).unwrap().map(|record| record.trigger), Some(CaptureTrigger::PredictionError));
+        assert!(capturer.update(&spike, start + Duration::from_millis(16), 2016).unwrap().is_none());
+        assert!(capturer.update(&metrics(None, 0, true), start + Duration::from_millis(32), 2032).unwrap().is_some());
+        assert_eq!(saved.borrow().len(), 2);
+        assert!(saved.borrow().iter().all(|path| path.starts_with(&dir) && path.exists()));
+
+        let index: Vec<CaptureRecord> = serde_json::from_str(&std::fs::read_to_string(dir.join(CAPTURE_INDEX_FILE)).unwrap()).unwrap();
+        assert_eq!(index, capturer.records());
+        assert_eq!(index[1].file, "2032_manual_gap-0_rtt-120ms.png");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::constants::FRAME_PACER_SPIN;
+
+use std::time::{Duration, Instant};
+
+/// Highest frame rate the client renders at, to save power on battery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameCap {
+    Fps30,
+    Fps60,
+    Fps120,
+    #[default]
+    Uncapped, // As fast as vsync allows
+}
+
+/// Implementation of the FrameCap
+impl FrameCap {
+    /// Every cap, in the order they are cycled through
+    pub const ALL: [FrameCap; 4] = [FrameCap::Fps30, FrameCap::Fps60, FrameCap::Fps120, FrameCap::Uncapped];
+
+    /// Time between two frames, None when uncapped
+    pub fn interval(self) -> Option<Duration> {
+        match self {
+            FrameCap::Fps30 => Some(Duration::from_secs(1) / 30),
+            FrameCap::Fps60 => Some(Duration::from_secs(1) / 60),
+            FrameCap::Fps120 => Some(Duration::from_secs(1) / 120),
+            FrameCap::Uncapped => None,
+        }
+    }
+
+    /// The cap after this one, wrapping around
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|cap| *cap == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// Name of the cap, also how it is stored in the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            FrameCap::Fps30 => "30",
+            FrameCap::Fps60 => "60",
+            FrameCap::Fps120 => "120",
+            FrameCap::Uncapped => "uncapped",
+        }
+    }
+
+    /// Describes the cap for the console
+    pub fn describe(self) -> String {
+        match self {
+            FrameCap::Uncapped => "uncapped".to_string(),
+            cap => format!("{} fps", cap.name()),
+        }
+    }
+
+    /// Reads a cap written by name, None for anything else
+    pub fn parse(text: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|cap| cap.name() == text.trim())
+    }
+}
+
+/// Time source and waiting of the pacer, a fake clock in tests
+pub trait PacerClock {
+    /// Time since a fixed start
+    fn now(&self) -> Duration;
+
+    /// Sleeps for about the duration, possibly longer
+    fn sleep(&mut self, duration: Duration);
+
+    /// Busy waits for a moment
+    fn spin(&mut self);
+}
+
+/// The real clock, sleeping the thread
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+/// Implementation of the SystemClock
+impl SystemClock {
+    /// Creates a clock starting now
+    pub fn new() -> Self {
+        Self { start: Instant::now() }
+    }
+}
+
+/// Default implementation for the SystemClock
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Implementation of the PacerClock for the SystemClock
+impl PacerClock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+
+    fn spin(&mut self) {
+        std::hint::spin_loop();
+    }
+}
+
+/// Holds each frame until the next one is due under the frame cap. Frames are due a whole interval after
+/// the previous deadline, not after the previous frame, so a wait that overshoots is taken off the next one
+/// and the rate stays right on average. The socket is read on the network thread, so waiting here
+/// doesn't slow down networking.
+#[derive(Debug, Clone, Default)]
+pub struct FramePacer {
+    cap: FrameCap,
+    deadline: Option<Duration>, // When the next frame is due on the pacer clock, None until the first frame
+}
+
+/// Implementation of the FramePacer
+impl FramePacer {
+    /// Creates a pacer for the cap
+    pub fn new(cap: FrameCap) -> Self {
+        Self { cap, deadline: None }
+    }
+
+    /// The current cap
+    pub fn cap(&self) -> FrameCap {
+        self.cap
+    }
+
+    /// Changes the cap, pacing starts over from the next frame
+    pub fn set_cap(&mut self, cap: FrameCap) {
+        self.cap = cap;
+        self.deadline = None;
+    }
+
+    /// How long a frame that finished at now waits for its deadline, moving the deadline on by one interval.
+    /// A frame that took longer than a whole interval past its deadline starts the pacing over,
+    /// so a stall isn't followed by a burst of unpaced frames.
+    pub fn wait_time(&mut self, now: Duration) -> Duration {
+        let Some(interval) = self.cap.interval() else {
+            return Duration::ZERO;
+        };
+        let deadline = match self.deadline {
+            Some(deadline) if now < deadline + interval => deadline,
+            _ => now,
+        };
+        self.deadline = Some(deadline + interval);
+        deadline.saturating_sub(now)
+    }
+
+    /// Waits until the frame is due: sleeps most of the wait, then spins the last FRAME_PACER_SPIN
+    /// because sleeps overshoot. Returns the time waited.
+    pub fn pace(&mut self, clock: &mut impl PacerClock) -> Duration {
+        let start = clock.now();
+        let wait = self.wait_time(start);
+        let due = start + wait;
+        if wait > FRAME_PACER_SPIN {
+            clock.sleep(wait - FRAME_PACER_SPIN);
+        }
+        while clock.now() < due {
+            clock.spin();
+        }
+        clock.now() - start
+    }
+}
+
+/// Tests for the frame pacer
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock that moves only when told to, with sleeps that overshoot
+    struct FakeClock {
+        now: Duration,
+        oversleep: Duration,
+        spins: u32,
+    }
+
+    /// Implementation of the PacerClock for the FakeClock
+    impl PacerClock for FakeClock {
+        fn now(&self) -> Duration {
+            self.now
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.now += duration + self.oversleep;
+        }
+
+        fn spin(&mut self) {
+            self.spins += 1;
+            self.now += Duration::from_micros(20);
+        }
+    }
+
+    // Helper function to run frames with varying costs, returning the average frame rate
+    fn run_frames(pacer: &mut FramePacer, clock: &mut FakeClock, frames: u32) -> f64 {
+        let start = clock.now;
+        for frame in 0..frames {
+            clock.now += Duration::from_micros(2_000 + (frame % 7) as u64 * 900);
+            pacer.pace(clock);
+        }
+        frames as f64 / (clock.now - start).as_secs_f64()
+    }
+
+    #[test]
+    fn test_caps_cycle_and_parse() {
+        assert_eq!(FrameCap::default(), FrameCap::Uncapped);
+        assert_eq!(FrameCap::Uncapped.next(), FrameCap::Fps30);
+        assert_eq!(FrameCap::Fps60.next(), FrameCap::Fps120);
+        for cap in FrameCap::ALL {
+            assert_eq!(FrameCap::parse(cap.name()), Some(cap));
+        }
+        assert_eq!(FrameCap::parse(" 60\n"), Some(FrameCap::Fps60));
+        assert_eq!(FrameCap::parse("59"), None);
+        assert_eq!(FrameCap::Uncapped.interval(), None);
+        assert_eq!(FrameCap::Fps120.describe(), "120 fps");
+        assert_eq!(FrameCap::Uncapped.describe(), "uncapped");
+    }
+
+    #[test]
+    fn test_long_run_rate_is_accurate() {
+        for (cap, target) in [(FrameCap::Fps30, 30.0), (FrameCap::Fps60, 60.0), (FrameCap::Fps120, 120.0)] {
+            for oversleep in [Duration::ZERO, Duration::from_micros(1_500)] {
+                let mut clock = FakeClock { now: Duration::from_secs(1), oversleep, spins: 0 };
+                let mut pacer = FramePacer::new(cap);
+                let rate = run_frames(&mut pacer, &mut clock, 600);
+                assert!((rate - target).abs() / target < 0.01, "{:?} with {:?} oversleep ran at {:.2} fps", cap, oversleep, rate);
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversleeping_is_caught_by_spinning() {
+        // Sleeps stop short of the deadline, the spin finishes the wait
+        let mut clock = FakeClock { now: Duration::ZERO, oversleep: Duration::from_micros(500), spins: 0 };
+        let mut pacer = FramePacer::new(FrameCap::Fps60);
+        pacer.pace(&mut clock);
+        clock.now += Duration::from_millis(4);
+        let waited = pacer.pace(&mut clock);
+        assert!(clock.spins > 0);
+        assert!(clock.now >= Duration::from_secs(1) / 60);
+        assert!(waited < Duration::from_millis(13));
+    }
+
+    #[test]
+    fn test_wait_time_corrects_drift_and_recovers_from_stalls() {
+        let interval = Duration::from_secs(1) / 60;
+        let mut pacer = FramePacer::new(FrameCap::Fps60);
+        assert_eq!(pacer.wait_time(Duration::ZERO), Duration::ZERO);
+
+        // A frame that ends late waits less, so the next one is still due on time
+        let late = interval + Duration::from_millis(3);
+        assert_eq!(pacer.wait_time(late), Duration::ZERO);
+        assert_eq!(pacer.wait_time(late + Duration::from_millis(5)), interval * 2 - late - Duration::from_millis(5));
+
+        // After a stall pacing starts over instead of rushing to catch up
+        let stalled = Duration::from_secs(2);
+        assert_eq!(pacer.wait_time(stalled), Duration::ZERO);
+        assert_eq!(pacer.wait_time(stalled + Duration::from_millis(1)), interval - Duration::from_millis(1));
+
+        // Uncapped never waits, changing the cap starts over
+        pacer.set_cap(FrameCap::Uncapped);
+        assert_eq!(pacer.wait_time(stalled), Duration::ZERO);
+        pacer.set_cap(FrameCap::Fps30);
+        assert_eq!(pacer.wait_time(stalled), Duration::ZERO);
+        assert_eq!(pacer.wait_time(stalled), Duration::from_secs(1) / 30);
+    }
+}
@@ -1,19 +1,224 @@
-use crate::colors::player_colors;
-use crate::constants::{BOARD_WIDTH, BOARD_HEIGHT, PLAYER_SPEED, TIMEOUT, PLAYER_SIZE, TOOL_BAR_HEIGHT};
-use crate::types::{Position, PlayerInput, Direction, GameState, PositionSnapshot};
-
-use std::{collections::HashMap, net::SocketAddr, time::Instant};
+use crate::build_info::BuildInfo;
+use crate::color_alloc::{Claimant, ColorAllocator};
+use crate::colors::{palette, PlayerColor};
+use crate::constants::{IMPULSE_RESEND_TICKS, KNOCKBACK_DISTANCE, MOVEMENT_STATS_TICKS, MATCH_COUNTDOWN, MATCH_END_PAUSE, MAX_NAME_CHARS, MAX_TEAMS, REORDER_RATE_WINDOW, BROADCAST_INTERVAL, COALESCED_INPUT_INTERVAL, MAX_COALESCED_INPUTS, BOARD_WIDTH, BOARD_HEIGHT, PLAYER_SPEED, TIMEOUT, PLAYER_SIZE, TOOL_BAR_HEIGHT, RECONNECT_GRACE, MAX_RESUMABLE_SESSIONS, LAG_COMP_MAX_REWIND};
+use crate::game_mode::{GameMode, ModeKind};
+use crate::lag_comp::{rewind, LagCompCounters, LagCompResult};
+use crate::movement::{step, MovementAccumulator};
+use crate::rtt::RttEstimator;
+use crate::snapshot::apply_extension;
+use crate::types::{AppliedImpulse, DisconnectReason, InputTiming, MoveOrigin, MovementStats, Position, PlayerInput, PlayerShape, GameState, MatchPhase, PositionSnapshot, Welcome};
+use crate::util::{Histogram, RingHistory};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::{collections::{HashMap, VecDeque}, net::SocketAddr, time::{Duration, Instant}};
 use uuid::Uuid;
 
 const MAX_POSITION_HISTORY: usize = 60; // Store 1 second of history at 60fps
+const SEQUENCE_WINDOW: u32 = 64; // Number of recent input sequences remembered for duplicate detection
 
 
 /// Stores state for one player
 pub struct PlayerState {
     pub position: Position,
     pub color: u32,
+    pub team: Option<u8>,   // Team of the player in team mode, kept for the whole session
     pub last_active: Instant,
-    pub position_history: Vec<PositionSnapshot>,
+    pub position_history: RingHistory<PositionSnapshot, u64>, // Keyed by timestamp
+    pub session_token: Uuid,
+    pub received_sequences: SequenceWindow,
+    pub rtt: RttEstimator,
+    pub score: u32,         // Pellets collected in the current match
+    pub best_score: u32,    // Best score in any match
+    pub total_pellets: u32, // Pellets collected in all matches
+    pub reorders: ReorderCounter,
+    pub name: String,        // Display name sent with the connect, empty if none
+    pub shape: PlayerShape,
+    pub instance: Option<u64>, // Hashed client instance id sent with the connect, to join client and server metrics
+    pub last_impulse: Option<AppliedImpulse>, // Latest push the player got without its own input, such as a knockback
+    pub input_timing: Option<InputTiming>, // When the newest applied input was received and applied
+    pub speed: f32, // Pixels per input, may be fractional
+    pub accumulator: MovementAccumulator, // Fractional movement carried to the next input
+    pub queued_inputs: VecDeque<(PlayerInput, Instant)>, // Inputs with their arrival waiting for their turn, when inputs are coalesced
+    pub input_credit: f32, // Inputs the player may still apply, earned at one per COALESCED_INPUT_INTERVAL
+    pub distance_moved: u64,      // Pixels applied inputs moved the player, after the board edges
+    pub inputs_applied: u32,      // Inputs applied since the player joined
+    pub connected_time: Duration, // Ticks the player was active for, the grace period doesn't count
+}
+
+/// Implementation of the PlayerState
+impl PlayerState {
+    /// Position history from the newest teleport on, the entries lag compensation may interpolate between
+    pub fn history_since_teleport(&self) -> impl Iterator<Item = &PositionSnapshot> {
+        let start = self.position_history.iter().rposition(|entry| entry.teleport).unwrap_or(0);
+        self.position_history.iter().skip(start)
+    }
+
+    /// How much the player moved since it joined, for the scoreboard
+    pub fn movement_stats(&self) -> MovementStats {
+        MovementStats {
+            distance: self.distance_moved,
+            inputs: self.inputs_applied,
+            connected_ms: self.connected_time.as_millis() as u64,
+        }
+    }
+}
+
+/// Scores of a player that are kept across matches and server restarts
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerRecord {
+    pub best_score: u32,
+    pub total_pellets: u32,
+    pub name: String, // Name the player had last, empty if it never picked one
+}
+
+/// Counts the inputs of a player that were applied after an input with a higher sequence
+#[derive(Debug, Clone, Default)]
+pub struct ReorderCounter {
+    total: u32,
+    recent: VecDeque<Instant>, // Times of the reorders within the rate window
+}
+
+/// Implementation of the ReorderCounter
+impl ReorderCounter {
+    /// Records a reordered input
+    pub fn record(&mut self, now: Instant) {
+        self.total += 1;
+        self.recent.push_back(now);
+        while self.recent.front().is_some_and(|time| now.saturating_duration_since(*time) >= REORDER_RATE_WINDOW) {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Number of reordered inputs since the player connected
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// Number of reordered inputs within the last minute
+    pub fn per_minute(&self, now: Instant) -> u32 {
+        self.recent.iter().filter(|time| now.saturating_duration_since(**time) < REORDER_RATE_WINDOW).count() as u32
+    }
+}
+
+/// Vertical strip of the board players spawn in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnZone {
+    pub min_x: i32, // Leftmost spawn x, inclusive
+    pub max_x: i32, // Rightmost spawn x, exclusive
+}
+
+/// Implementation of the SpawnZone
+impl SpawnZone {
+    /// The whole board, used for pellets and when there are no teams
+    pub fn whole_board() -> Self {
+        Self { min_x: PLAYER_SIZE, max_x: BOARD_WIDTH - PLAYER_SIZE }
+    }
+
+    /// The zone of a team: the board split into one equally wide strip per team, left to right.
+    /// Without a team the zone is the whole board.
+    pub fn for_team(team: Option<u8>, team_count: u8) -> Self {
+        let whole = Self::whole_board();
+        match team {
+            Some(team) if team_count > 0 => {
+                let width = BOARD_WIDTH / team_count as i32;
+                let start = width * team as i32;
+                Self {
+                    min_x: start.max(whole.min_x),
+                    max_x: (start + width).min(whole.max_x),
+                }
+            }
+            _ => whole,
+        }
+    }
+
+    /// Checks if a position is inside the zone
+    pub fn contains(&self, position: Position) -> bool {
+        (self.min_x..self.max_x).contains(&position.x)
+    }
+}
+
+/// Phase of the current match on the server, with the times it moves on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamePhase {
+    Warmup,                                // The first match, until it ends players move freely
+    Countdown { ends_at: Instant },        // Players are frozen at their spawn until ends_at
+    Active,                                // The match is running
+    Ended { next_countdown_at: Instant },  // Players are frozen until the next match is set up
+}
+
+/// Implementation of the GamePhase
+impl GamePhase {
+    /// The phase as sent to clients, a countdown carries the time left since clocks aren't shared
+    pub fn to_match_phase(&self, now: Instant) -> MatchPhase {
+        match self {
+            GamePhase::Warmup => MatchPhase::Warmup,
+            GamePhase::Countdown { ends_at } => MatchPhase::Countdown {
+                remaining_ms: ends_at.saturating_duration_since(now).as_millis() as u32,
+            },
+            GamePhase::Active => MatchPhase::Active,
+            GamePhase::Ended { .. } => MatchPhase::Ended,
+        }
+    }
+
+    /// Checks if inputs move players in this phase
+    pub fn allows_movement(&self) -> bool {
+        matches!(self, GamePhase::Warmup | GamePhase::Active)
+    }
+}
+
+/// A player that timed out but can still resume the session with its session token
+struct DisconnectedPlayer {
+    id: Uuid,
+    state: PlayerState,
+    last_processed: Option<u32>,
+    disconnected_at: Instant,
+}
+
+/// Remembers which of the most recent input sequences have been received, so duplicated
+/// inputs are ignored while inputs that arrive out of order are still applied
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SequenceWindow {
+    newest: Option<u32>,
+    received: u64, // Bit n is set if sequence newest - n has been received
+}
+
+/// Implementation of the SequenceWindow
+impl SequenceWindow {
+    /// Records a sequence, returns false if it was already received or is too old to tell
+    pub fn accept(&mut self, sequence: u32) -> bool {
+        let Some(newest) = self.newest else {
+            self.newest = Some(sequence);
+            self.received = 1;
+            return true;
+        };
+
+        if sequence > newest {
+            // Slide the window forward
+            let shift = sequence - newest;
+            self.received = if shift >= SEQUENCE_WINDOW { 0 } else { self.received << shift };
+            self.received |= 1;
+            self.newest = Some(sequence);
+            return true;
+        }
+
+        let age = newest - sequence;
+        if age >= SEQUENCE_WINDOW {
+            return false;
+        }
+        let bit = 1u64 << age;
+        if self.received & bit != 0 {
+            return false;
+        }
+        self.received |= bit;
+        true
+    }
+
+    /// The highest sequence received so far
+    pub fn newest(&self) -> Option<u32> {
+        self.newest
+    }
 }
 
 /// Game state that tracks all players and their positions, and ids for the players
@@ -22,54 +227,218 @@ pub struct Game {
     id_to_addr: HashMap<Uuid, SocketAddr>,
     addr_to_id: HashMap<SocketAddr, Uuid>,
     last_processed: HashMap<Uuid, u32>, // Track inputs
+    disconnected: HashMap<Uuid, DisconnectedPlayer>, // Timed out players by session token
+    colors: ColorAllocator, // Colors of the players in this game, kept for disconnected players during the grace period
+    match_number: u32,
+    match_started_at: Instant,
+    phase: GamePhase,
+    snapshot_id: u64, // Current server tick
+    last_tick_at: Instant, // When the current tick started, for server times on the tick timeline
+    mode: Box<dyn GameMode>, // Rules of the game mode, with all of its state
+    saved_records: HashMap<Uuid, PlayerRecord>, // Records from earlier server runs by session token
+    team_count: u8, // Number of teams, 0 when team mode is off
+    reorder_distances: Histogram, // How far behind the newest sequence reordered inputs were, for all players
+    player_timeout: Duration, // Players that sent nothing for this long are disconnected
+    reconnect_grace: Duration, // How long a timed out player can resume its session
+    max_resumable_sessions: usize, // Most sessions kept in disconnected, the one that timed out longest ago is forgotten first
+    tick_interval: Duration, // Time between broadcast ticks, stamped on every snapshot
+    events: Vec<GameEvent>, // Teleports and refused position writes since the last take_events
+    removed: Vec<(SocketAddr, Uuid, DisconnectReason)>, // Players the game removed since the last take_removed, to be told why
+    debug_teleports: bool, // Whether players may place themselves with DebugSetPosition
+    input_coalescing: bool, // Whether inputs are queued and applied at the rate of real time instead of on arrival
+    spawn_seed: u64, // Seed of spawn_rng, sent in the Welcome so a session's spawns can be replayed
+    spawn_rng: StdRng, // Picks the spawn positions of the players
+    max_rewind: Duration, // Furthest back lag compensation rewinds a player
+    lag_comp: LagCompCounters, // Rewinds per result since the stats were last taken
 }
 
 /// Implementation of the Game state
 impl Game {
     /// Creates a new Game instance
     pub fn new() -> Self {
+        let spawn_seed = rand::random();
         Self {
             players: HashMap::new(),
             id_to_addr: HashMap::new(),
             addr_to_id: HashMap::new(),
             last_processed: HashMap::new(),
+            disconnected: HashMap::new(),
+            colors: ColorAllocator::new(),
+            match_number: 0,
+            match_started_at: Instant::now(),
+            phase: GamePhase::Warmup,
+            snapshot_id: 0,
+            last_tick_at: Instant::now(),
+            mode: ModeKind::default().create(),
+            saved_records: HashMap::new(),
+            team_count: 0,
+            reorder_distances: Histogram::new(),
+            player_timeout: TIMEOUT,
+            reconnect_grace: RECONNECT_GRACE,
+            max_resumable_sessions: MAX_RESUMABLE_SESSIONS,
+            tick_interval: BROADCAST_INTERVAL,
+            events: Vec::new(),
+            removed: Vec::new(),
+            debug_teleports: false,
+            input_coalescing: false,
+            spawn_seed,
+            spawn_rng: StdRng::seed_from_u64(spawn_seed),
+            max_rewind: LAG_COMP_MAX_REWIND,
+            lag_comp: LagCompCounters::default(),
         }
     }
 
+    /// Seeds the spawn positions of the players from now on, a random seed by default. Pellets are placed
+    /// by the mode and stay random.
+    pub fn set_spawn_seed(&mut self, seed: u64) {
+        self.spawn_seed = seed;
+        self.spawn_rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Seed the spawn positions of the players are drawn with
+    pub fn spawn_seed(&self) -> u64 {
+        self.spawn_seed
+    }
+
+    /// Creates a game in team mode, players are split over team_count teams (at most MAX_TEAMS)
+    pub fn with_teams(team_count: u8) -> Self {
+        let mut game = Self::new();
+        game.team_count = team_count.min(MAX_TEAMS);
+        game
+    }
+
+    /// Sets how long a player may send nothing before it is disconnected, TIMEOUT by default
+    pub fn set_player_timeout(&mut self, timeout: Duration) {
+        self.player_timeout = timeout;
+    }
+
+    /// Sets how long a timed out player can resume its session and how many such sessions are kept at once,
+    /// RECONNECT_GRACE and MAX_RESUMABLE_SESSIONS by default. Colors stay reserved for as long as the sessions.
+    pub fn set_reconnect_retention(&mut self, grace: Duration, max_sessions: usize) {
+        self.reconnect_grace = grace;
+        self.max_resumable_sessions = max_sessions;
+        self.colors.set_grace(grace);
+        self.evict_resumable_sessions();
+    }
+
+    /// Sets how far back lag compensation may rewind a player, LAG_COMP_MAX_REWIND by default
+    pub fn set_max_rewind(&mut self, max_rewind: Duration) {
+        self.max_rewind = max_rewind;
+    }
+
+    /// Where the player was at the server time target_ms, seen from now_ms, with how the position was found.
+    /// Only the history since the last teleport is used, nothing is interpolated across a teleport. None for an
+    /// unknown player. Nothing is counted, so lookups such as the console's don't skew the stats.
+    pub fn position_at(&self, addr: SocketAddr, target_ms: u64, now_ms: u64) -> Option<(Position, LagCompResult)> {
+        let player = self.players.get(&addr)?;
+        Some(rewind(player.history_since_teleport(), player.position, target_ms, now_ms, self.max_rewind.as_millis() as u64))
+    }
+
+    /// Rewinds the player to the server time target_ms for lag compensation, like position_at seen from now,
+    /// and counts the result for the stats line
+    pub fn rewind_player(&mut self, addr: SocketAddr, target_ms: u64) -> Option<(Position, LagCompResult)> {
+        let rewound = self.position_at(addr, target_ms, self.tick_time_ms(Instant::now()))?;
+        self.lag_comp.record(rewound.1);
+        Some(rewound)
+    }
+
+    /// Rewinds per result since the last call, the counters start over afterwards
+    pub fn take_lag_comp_counters(&mut self) -> LagCompCounters {
+        std::mem::take(&mut self.lag_comp)
+    }
+
+    /// Number of timed out sessions that can still be resumed, and the most that are kept
+    pub fn resumable_sessions(&self) -> (usize, usize) {
+        (self.disconnected.len(), self.max_resumable_sessions)
+    }
+
+    /// Lets players place themselves with DebugSetPosition, off by default
+    pub fn set_debug_teleports(&mut self, enabled: bool) {
+        self.debug_teleports = enabled;
+    }
+
+    /// Queues the inputs of every player and applies them at one per COALESCED_INPUT_INTERVAL, off by default.
+    /// Bursts of delayed inputs then move the player over several ticks instead of all at once, at the cost of latency.
+    pub fn set_input_coalescing(&mut self, enabled: bool) {
+        self.input_coalescing = enabled;
+    }
+
+    /// Sets the time between broadcast ticks the server runs the game at, BROADCAST_INTERVAL by default
+    pub fn set_tick_interval(&mut self, interval: Duration) {
+        self.tick_interval = interval;
+    }
+
+    /// Time between broadcast ticks the server runs the game at
+    pub fn tick_interval(&self) -> Duration {
+        self.tick_interval
+    }
+
+    /// Number of teams, 0 when team mode is off
+    pub fn team_count(&self) -> u8 {
+        self.team_count
+    }
+
+    /// Replaces the rules of the game, best done before anyone joins since the new mode doesn't know the players yet
+    pub fn set_mode(&mut self, mode: Box<dyn GameMode>) {
+        self.mode = mode;
+    }
+
+    /// Command line name of the game mode
+    pub fn mode_name(&self) -> &'static str {
+        self.mode.name()
+    }
+
+    /// Picks a random spawn position within the zone and the board bounds
+    pub(crate) fn random_spawn_position(zone: SpawnZone) -> Position {
+        Self::spawn_position_from(&mut rand::rng(), zone)
+    }
+
+    /// Picks a spawn position within the zone and the board bounds with the given random generator
+    fn spawn_position_from(rng: &mut impl Rng, zone: SpawnZone) -> Position {
+        let x = rng.random_range(zone.min_x..zone.max_x);
+        let y = rng.random_range(PLAYER_SIZE..(BOARD_HEIGHT - (PLAYER_SIZE) - TOOL_BAR_HEIGHT));
+        Position { x, y }
+    }
+
     /// Handles new connection by adding player at random pos/color
     pub fn connect_player(&mut self, addr: SocketAddr) -> Uuid {
-        use rand::Rng;
-
         // Check if player already connected
-        if self.players.contains_key(&addr) {
-            // Player already connected
-            return *self.addr_to_id.get(&addr).unwrap();
+        if let Some(player) = self.players.get_mut(&addr) {
+            // A fresh connect starts counting input sequences from zero again
+            let id = *self.addr_to_id.get(&addr).unwrap();
+            player.received_sequences = SequenceWindow::default();
+            player.queued_inputs.clear();
+            self.last_processed.remove(&id);
+            self.debug_check_invariants();
+            return id;
         }
 
-        // Generate a random position within the board bounds
-        let mut rng = rand::rng();
-        let x = rng.random_range(PLAYER_SIZE..(BOARD_WIDTH - (PLAYER_SIZE)));
-        let y = rng.random_range(PLAYER_SIZE..(BOARD_HEIGHT - (PLAYER_SIZE) - TOOL_BAR_HEIGHT));
-        
-        // Pick a color from the palette randomly
-        let palette = player_colors::get_palette();
-        let color_base = palette[rng.random_range(0..palette.len())];
+        // New players join the smallest team, players already in a team are never moved
+        let team = self.smallest_team();
+
+        // Generate a random position within the spawn zone of the team
+        let initial_position = Self::spawn_position_from(&mut self.spawn_rng, SpawnZone::for_team(team, self.team_count));
+
+        // Pick a free color from the palette of the team, or the whole palette
+        let id = Uuid::new_v4();
+        let colors = match team {
+            Some(team) => palette::TEAM_PALETTES[team as usize],
+            None => &palette::PLAYER_PALETTE[..],
+        };
         // Pack the color as u32 for serialization
-        let color = ((color_base.r * 255.0) as u32) << 16
-            | ((color_base.g * 255.0) as u32) << 8
-            | ((color_base.b * 255.0) as u32);
+        let color = self.colors.allocate(id, Claimant::Human, colors, Instant::now()).packed();
 
         // Store the player ID
-        let id = Uuid::new_v4();
         self.id_to_addr.insert(id, addr);
         self.addr_to_id.insert(addr, id);
 
         // Initialize player position and history
-        let initial_position = Position { x, y };
-        let mut position_history = Vec::with_capacity(MAX_POSITION_HISTORY);
+        let mut position_history = RingHistory::new(MAX_POSITION_HISTORY, |snapshot: &PositionSnapshot| snapshot.timestamp);
         position_history.push(PositionSnapshot {
             position: initial_position,
-            timestamp: Instant::now().elapsed().as_millis() as u64,
+            timestamp: self.tick_time_ms(Instant::now()),
+            teleport: true,
+            origin: MoveOrigin::Spawn,
         });
 
         // Insert the player state into the game
@@ -78,42 +447,279 @@ impl Game {
             PlayerState {
                 position: initial_position,
                 color,
+                team,
                 last_active: Instant::now(),
                 position_history,
+                session_token: Uuid::new_v4(),
+                received_sequences: SequenceWindow::default(),
+                rtt: RttEstimator::default(),
+                score: 0,
+                best_score: 0,
+                total_pellets: 0,
+                reorders: ReorderCounter::default(),
+                name: String::new(),
+                shape: PlayerShape::default(),
+                instance: None,
+                last_impulse: None,
+                input_timing: None,
+                speed: PLAYER_SPEED as f32,
+                accumulator: MovementAccumulator::default(),
+                queued_inputs: VecDeque::new(),
+                input_credit: 0.0,
+                distance_moved: 0,
+                inputs_applied: 0,
+                connected_time: Duration::ZERO,
             },
         );
+        if let Some(player) = self.players.get_mut(&addr) {
+            self.mode.on_player_join(id, player);
+        }
+        self.debug_check_invariants();
         id
     }
 
+    /// Sets the name and shape a player chose. Control chars and surrounding spaces are dropped
+    /// and the name is cut to MAX_NAME_CHARS.
+    pub fn set_profile(&mut self, addr: &SocketAddr, name: &str, shape: PlayerShape) {
+        if let Some(player) = self.players.get_mut(addr) {
+            let name: String = name.chars().filter(|c| !c.is_control()).collect();
+            player.name = name.trim().chars().take(MAX_NAME_CHARS).collect();
+            player.shape = shape;
+        }
+    }
+
+    /// Remembers the hashed client instance id of an active player
+    pub fn set_instance(&mut self, addr: &SocketAddr, instance: u64) {
+        if let Some(player) = self.players.get_mut(addr) {
+            player.instance = Some(instance);
+        }
+    }
+
+    /// Hashed client instance id of every active player that sent one
+    pub fn instances(&self) -> HashMap<Uuid, u64> {
+        self.players.iter()
+            .filter_map(|(addr, player)| Some((*self.addr_to_id.get(addr)?, player.instance?)))
+            .collect()
+    }
+
+    /// Display name of an active player, None if it has none
+    pub fn player_name(&self, addr: &SocketAddr) -> Option<String> {
+        self.players.get(addr).map(|player| player.name.clone()).filter(|name| !name.is_empty())
+    }
+
+    /// The team with the fewest active players, the lowest team on a tie. None when team mode is off.
+    fn smallest_team(&self) -> Option<u8> {
+        (0..self.team_count).min_by_key(|team| {
+            self.players.values().filter(|player| player.team == Some(*team)).count()
+        })
+    }
+
+    /// Number of active players in every team, indexed by team
+    pub fn team_sizes(&self) -> Vec<usize> {
+        (0..self.team_count)
+            .map(|team| self.players.values().filter(|player| player.team == Some(team)).count())
+            .collect()
+    }
+
+    /// Address of the active session with the token, None if no active player holds it
+    pub fn session_addr(&self, session_token: &Uuid) -> Option<SocketAddr> {
+        self.players.iter()
+            .find(|(_, player)| player.session_token == *session_token)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Resumes a session with its session token, keeping the id, position and input sequences.
+    /// Returns None if the token is unknown or its grace period has expired.
+    pub fn reconnect_player(&mut self, addr: SocketAddr, session_token: Uuid) -> Option<Uuid> {
+        // The session may still be active if the client comes back before the timeout
+        let active_addr = self.session_addr(&session_token);
+
+        if let Some(old_addr) = active_addr {
+            if old_addr != addr {
+                // Free the new address before moving the session to it
+                self.disconnect_player(&addr);
+                self.migrate_addr(old_addr, addr);
+            }
+            // The client predicts from the welcome again, without a fraction of a pixel carried over
+            let player = self.players.get_mut(&addr).unwrap();
+            player.last_active = Instant::now();
+            player.accumulator.clear();
+            self.debug_check_invariants();
+            return self.addr_to_id.get(&addr).copied();
+        }
+
+        // Otherwise restore the player from the grace period storage
+        let disconnected = self.disconnected.remove(&session_token)?;
+        self.disconnect_player(&addr);
+
+        let mut state = disconnected.state;
+        state.last_active = Instant::now();
+        state.accumulator.clear();
+        self.mode.on_player_join(disconnected.id, &mut state);
+        self.colors.restore(disconnected.id, PlayerColor::from_packed(state.color));
+        let (stored_position, team) = (state.position, state.team);
+        self.players.insert(addr, state);
+        self.id_to_addr.insert(disconnected.id, addr);
+        self.addr_to_id.insert(addr, disconnected.id);
+        if let Some(sequence) = disconnected.last_processed {
+            self.last_processed.insert(disconnected.id, sequence);
+        }
+
+        // The world went on without the player, a spot that is now taken gets a fresh spawn instead
+        if !self.is_free_for(addr, stored_position) {
+            let spawn = Self::spawn_position_from(&mut self.spawn_rng, SpawnZone::for_team(team, self.team_count));
+            let _ = self.set_position_validated(addr, spawn, MoveOrigin::Spawn);
+        }
+        self.debug_check_invariants();
+        Some(disconnected.id)
+    }
+
+    /// Checks if the player at the address could stand at the position: on the board and clear of every other player
+    fn is_free_for(&self, addr: SocketAddr, position: Position) -> bool {
+        is_on_board(position) && self.players.iter()
+            .filter(|(other, _)| **other != addr)
+            .all(|(_, other)| knockback_direction(position, other.position).is_none())
+    }
+
+    /// Forgets the sessions that timed out longest ago until at most max_resumable_sessions are kept
+    fn evict_resumable_sessions(&mut self) {
+        while self.disconnected.len() > self.max_resumable_sessions {
+            let Some((token, id)) = self.disconnected.iter()
+                .min_by_key(|(_, player)| player.disconnected_at)
+                .map(|(token, player)| (*token, player.id)) else {
+                break;
+            };
+            self.disconnected.remove(&token);
+            self.colors.forget(&id);
+        }
+    }
+
+    /// Moves an active player from the old address to the new one, keeping its id, position, processed
+    /// inputs and everything else. Refused if there is no player at the old address or another one at the new.
+    pub fn migrate_addr(&mut self, old: SocketAddr, new: SocketAddr) -> bool {
+        if old == new {
+            return self.players.contains_key(&old);
+        }
+        if self.players.contains_key(&new) {
+            return false;
+        }
+        let (Some(state), Some(id)) = (self.players.remove(&old), self.addr_to_id.remove(&old)) else {
+            return false;
+        };
+        self.players.insert(new, state);
+        self.addr_to_id.insert(new, id);
+        self.id_to_addr.insert(id, new);
+        self.debug_check_invariants();
+        true
+    }
+
+    /// Follows an active session to a new address after the client's port changed, the player carries on
+    /// as if nothing happened. Returns the player id and the old address, None if the session isn't active here.
+    pub fn rebind_session(&mut self, addr: SocketAddr, session_token: Uuid) -> Option<(Uuid, SocketAddr)> {
+        let old_addr = self.session_addr(&session_token)?;
+        if old_addr == addr || !self.migrate_addr(old_addr, addr) {
+            return None;
+        }
+        let id = self.addr_to_id.get(&addr).copied()?;
+        self.players.get_mut(&addr)?.last_active = Instant::now();
+        Some((id, old_addr))
+    }
+
+    /// Builds the Welcome message for a connected player, with its color and shape. The last processed
+    /// input is only included for resumed sessions, fresh connects start counting from zero.
+    pub fn build_welcome(&self, addr: &SocketAddr, resumed: bool) -> Option<Welcome> {
+        let id = *self.addr_to_id.get(addr)?;
+        let player = self.players.get(addr)?;
+        let last_processed = if resumed { self.last_processed.get(&id).copied() } else { None };
+        let welcome = Welcome::new(id, player.session_token, player.position, last_processed);
+        Some(Welcome { build: Some(BuildInfo::current(Some(self.spawn_seed))), color: player.color, shape: player.shape, ..welcome })
+    }
+
     /// Handle player input and update position + activity
     pub fn handle_input(&mut self, addr: SocketAddr, input: PlayerInput) {
+        self.handle_input_received_at(addr, input, Instant::now());
+    }
+
+    /// Handles a player input that arrived at received_at, which is earlier than now when the server queued it.
+    /// With input coalescing the input waits in the queue of the player until a tick applies it.
+    pub fn handle_input_received_at(&mut self, addr: SocketAddr, input: PlayerInput, received_at: Instant) {
         if let Some(player) = self.players.get_mut(&addr) {
             player.last_active = Instant::now();
 
-            // Update last processed input
+            // Ignore duplicated inputs, inputs that arrive out of order are still applied
+            let newest_seen = player.received_sequences.newest();
+            if !player.received_sequences.accept(input.sequence) {
+                return;
+            }
+
+            // Measure how often and how far inputs are applied out of order
+            if let Some(newest) = newest_seen.filter(|newest| input.sequence < *newest) {
+                player.reorders.record(player.last_active);
+                self.reorder_distances.record((newest - input.sequence) as u64);
+            }
+
+            if self.input_coalescing {
+                player.queued_inputs.push_back((input, received_at));
+                return;
+            }
+            self.apply_input(addr, input, received_at, false);
+        }
+    }
+
+    /// Applies an accepted input: acknowledges it and moves the player the way the client predicts it.
+    /// Queued is set for inputs that waited in the coalescing queue.
+    fn apply_input(&mut self, addr: SocketAddr, input: PlayerInput, received_at: Instant, queued: bool) {
+        let (received_ms, applied_ms) = (self.tick_time_ms(received_at), self.tick_time_ms(Instant::now()));
+        let player_count = self.players.len();
+        if let Some(player) = self.players.get_mut(&addr) {
+            // Update last processed input to the newest applied sequence, a queued input isn't acknowledged yet
+            let mut acknowledged = None;
+            let mut late = false;
             if let Some(id) = self.addr_to_id.get(&addr) {
-                self.last_processed.insert(*id, input.sequence);
+                late = self.last_processed.get(id).is_some_and(|newest| *newest > input.sequence);
+                let newest = self.last_processed.get(id).map_or(input.sequence, |newest| input.sequence.max(*newest));
+                self.last_processed.insert(*id, newest);
+                acknowledged = Some(newest);
             }
 
-            // Update player position based on input direction for prediction
-            match input.dir {
-                Direction::Up => player.position.y = player.position.y.saturating_sub(PLAYER_SPEED).max(PLAYER_SIZE),
-                Direction::Down => player.position.y = player.position.y.saturating_add(PLAYER_SPEED).min(BOARD_HEIGHT - (PLAYER_SIZE) - TOOL_BAR_HEIGHT),
-                Direction::Left => player.position.x = player.position.x.saturating_sub(PLAYER_SPEED).max(PLAYER_SIZE),
-                Direction::Right => player.position.x = player.position.x.saturating_add(PLAYER_SPEED).min(BOARD_WIDTH - (PLAYER_SIZE)),
+            // The timing follows the newest input, so it matches the acknowledged sequence
+            if acknowledged == Some(input.sequence) {
+                player.input_timing = Some(InputTiming {
+                    sequence: input.sequence,
+                    client_timestamp: input.timestamp,
+                    received_ms,
+                    applied_ms,
+                });
             }
 
-            // Store current position in history
-            let current_time = Instant::now().elapsed().as_millis() as u64;
-            player.position_history.push(PositionSnapshot {
-                position: player.position,
-                timestamp: current_time,
-            });
+            player.inputs_applied += 1;
+
+            // Frozen players still have their inputs acknowledged, so clients drop them from prediction
+            if !self.phase.allows_movement() {
+                return;
+            }
+
+            // Update player position based on input direction, the way the client predicts it
+            let mut accumulator = player.accumulator;
+            let position = step(player.position, input.dir, player.speed, &mut accumulator);
+            let id = self.addr_to_id.get(&addr).copied();
+            let before = player.position;
+            let origin = match (late, queued) {
+                (true, _) => MoveOrigin::LateInput { sequence: input.sequence },
+                (false, true) => MoveOrigin::CoalescedInput { sequence: input.sequence },
+                (false, false) => MoveOrigin::Input { sequence: input.sequence },
+            };
+            if write_position(player, id, position, origin, player_count, &mut self.events, applied_ms).is_err() {
+                return;
+            }
+            player.accumulator = accumulator;
+            player.distance_moved += (position.x - before.x).unsigned_abs() as u64 + (position.y - before.y).unsigned_abs() as u64;
 
-            // Keep only the last MAX_POSITION_HISTORY entries
-            if player.position_history.len() > MAX_POSITION_HISTORY {
-                player.position_history.remove(0);
+            if let Some(id) = id {
+                self.mode.on_input_applied(id, player);
             }
+
+            self.debug_check_invariants();
         }
     }
 
@@ -122,20 +728,32 @@ impl Game {
         let now = Instant::now();
         let mut to_disconnect = Vec::new();
         
-        // Check for players that haven't sent a ping in TIMEOUT duration
+        // Check for players that haven't sent a ping in the timeout
         for (addr, player) in self.players.iter() {
-            if now.duration_since(player.last_active) >= TIMEOUT {
+            if now.saturating_duration_since(player.last_active) >= self.player_timeout {
                 to_disconnect.push(*addr);
             }
         }
         
         // Disconnect inactive players
         for addr in to_disconnect {
-            if let Some(id) = self.addr_to_id.get(&addr) {
+            if let Some(id) = self.addr_to_id.get(&addr).copied() {
                 println!("Player {} disconnected due to timeout", id);
+                self.removed.push((addr, id, DisconnectReason::TimedOut));
             }
             self.disconnect_player(&addr);
         }
+
+        // Forget sessions that can no longer be resumed
+        let grace = self.reconnect_grace;
+        self.disconnected.retain(|_, player| now.saturating_duration_since(player.disconnected_at) < grace);
+        self.colors.expire(now);
+        self.debug_check_invariants();
+    }
+
+    /// Checks if a player is active at the address
+    pub fn is_active(&self, addr: &SocketAddr) -> bool {
+        self.players.contains_key(addr)
     }
 
     /// Get player address of active player
@@ -143,109 +761,756 @@ impl Game {
         self.players.keys().cloned().collect()
     }
 
-    /// Remove player on disconnect
+    /// Remove player on disconnect, keeping its state for a while so the session can be resumed
     pub fn disconnect_player(&mut self, addr: &SocketAddr) {
         if let Some(id) = self.addr_to_id.remove(addr) {
             self.id_to_addr.remove(&id);
-            self.last_processed.remove(&id);
+            let last_processed = self.last_processed.remove(&id);
+            let now = Instant::now();
+
+            // The color stays reserved as long as the session can be resumed
+            let state = self.players.remove(addr);
+            self.colors.release(id, now, state.is_some());
+            self.mode.on_player_leave(id);
+            if let Some(mut state) = state {
+                // Inputs still queued are dropped like inputs lost on the way, a resumed client predicts from what was acknowledged
+                state.queued_inputs.clear();
+                self.disconnected.insert(state.session_token, DisconnectedPlayer {
+                    id,
+                    state,
+                    last_processed,
+                    disconnected_at: now,
+                });
+                self.evict_resumable_sessions();
+            }
         }
         self.players.remove(addr);
+        self.debug_check_invariants();
     }
 
-    /// Build a snapshot of active players for broadcasting
+    /// Removes a player that quit on purpose. Unlike a disconnect the session isn't kept for resuming,
+    /// so its color is free for the next player right away. Returns the player's id.
+    pub fn leave_player(&mut self, addr: &SocketAddr) -> Option<Uuid> {
+        let id = *self.addr_to_id.get(addr)?;
+        self.disconnect_player(addr);
+        self.disconnected.retain(|_, player| player.id != id);
+        self.colors.forget(&id);
+        self.debug_check_invariants();
+        Some(id)
+    }
+
+    /// Build a snapshot of active players for broadcasting, with the players sorted by id as the protocol requires
     pub fn build_snapshot(&self) -> GameState {
-        let players = self.players.iter()
+        let now = Instant::now();
+        let mut players: Vec<(Uuid, Position, u32)> = self.players.iter()
             .map(|(addr, p)| {
                 let player_id = *self.addr_to_id.get(addr).unwrap();
                 (player_id, p.position, p.color)
             })
             .collect();
-        GameState {
+        players.sort_by_key(|(id, _, _)| *id);
+        let mut state = GameState {
             players,
             last_processed: self.last_processed.clone(),
-            server_timestamp: Instant::now().elapsed().as_millis() as u64,
+            pellets: Vec::new(),
+            scores: self.player_values(|player| player.score),
+            best_scores: self.player_values(|player| player.best_score),
+            teams: self.teams(),
+            input_reorders: self.player_values(|player| player.reorders.per_minute(now)),
+            shapes: self.players.iter()
+                .filter_map(|(addr, player)| Some((*self.addr_to_id.get(addr)?, player.shape)))
+                .collect(),
+            impulses: self.players.iter()
+                .filter_map(|(addr, player)| {
+                    let impulse = player.last_impulse.filter(|impulse| self.snapshot_id.saturating_sub(impulse.tick) < IMPULSE_RESEND_TICKS)?;
+                    Some((*self.addr_to_id.get(addr)?, impulse))
+                })
+                .collect(),
+            input_timings: self.players.iter()
+                .filter_map(|(addr, player)| Some((*self.addr_to_id.get(addr)?, player.input_timing?)))
+                .collect(),
+            match_phase: self.phase.to_match_phase(now),
+            server_timestamp: self.tick_time_ms(self.last_tick_at), // The server adds the time the snapshot is held before sending
+            match_number: self.match_number,
+            ping_ms: self.ping_ms(),
+            snapshot_id: self.snapshot_id,
+            tick_interval_ms: self.tick_interval.as_millis() as u32,
+            tagged: None,
+            movement_stats: HashMap::new(),
+        };
+
+        // Only every MOVEMENT_STATS_TICKS ticks, at 20 bytes a player they would fill most of every snapshot
+        if self.snapshot_id.is_multiple_of(MOVEMENT_STATS_TICKS) {
+            state.movement_stats = self.players.iter()
+                .filter_map(|(addr, player)| Some((*self.addr_to_id.get(addr)?, player.movement_stats())))
+                .collect();
         }
-    }
 
-    /// Mutable access to players (use only when necessary)
-    pub fn get_players_mut(&mut self) -> &mut HashMap<SocketAddr, PlayerState> {
-        &mut self.players
+        // The mode fills in its own fields, the same way a client decodes them
+        for block in self.mode.snapshot_extensions() {
+            apply_extension(&mut state, &block);
+        }
+        state
     }
-}
 
-/// Unit tests for the Game state
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::net::{IpAddr, Ipv4Addr};
-    use std::time::Duration;
+    /// Pushes overlapping players apart by KNOCKBACK_DISTANCE along the axis their centers are furthest apart on.
+    /// Every push is stored as an impulse that the following snapshots carry, so clients can add it to their prediction.
+    /// Returns the number of players that were moved.
+    pub fn apply_knockback(&mut self) -> usize {
+        if !self.phase.allows_movement() {
+            return 0;
+        }
 
-    // Helper function to create test socket addresses
-    fn test_addr(port: u16) -> SocketAddr {
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port)
+        // Sorted by id so players on the same spot are pushed the same way every tick
+        let mut players: Vec<(Uuid, SocketAddr, Position)> = self.players.iter()
+            .filter_map(|(addr, player)| Some((*self.addr_to_id.get(addr)?, *addr, player.position)))
+            .collect();
+        players.sort_by_key(|(id, _, _)| *id);
+
+        // Sum the pushes of every collision, a player touching two others is pushed by both
+        let mut pushes: HashMap<SocketAddr, (i32, i32)> = HashMap::new();
+        for (index, (_, addr, position)) in players.iter().enumerate() {
+            for (_, other_addr, other_position) in &players[index + 1..] {
+                if let Some((dx, dy)) = knockback_direction(*position, *other_position) {
+                    let push = pushes.entry(*addr).or_default();
+                    *push = (push.0 - dx * KNOCKBACK_DISTANCE, push.1 - dy * KNOCKBACK_DISTANCE);
+                    let push = pushes.entry(*other_addr).or_default();
+                    *push = (push.0 + dx * KNOCKBACK_DISTANCE, push.1 + dy * KNOCKBACK_DISTANCE);
+                }
+            }
+        }
+
+        let mut moved = 0;
+        let player_count = self.players.len();
+        let now_ms = self.tick_time_ms(Instant::now());
+        for (addr, (dx, dy)) in pushes {
+            let Some(player) = self.players.get_mut(&addr) else { continue };
+            let old = player.position;
+            let new = clamp_to_board(Position { x: old.x + dx, y: old.y + dy });
+            if new == old {
+                continue; // Pushed against the edge of the board
+            }
+            let id = self.addr_to_id.get(&addr).copied();
+            if write_position(player, id, new, MoveOrigin::Knockback, player_count, &mut self.events, now_ms).is_err() {
+                continue;
+            }
+
+            // Stamped with the newest applied input, clients replay only the inputs after it on top of the push
+            let after_sequence = self.addr_to_id.get(&addr)
+                .and_then(|id| self.last_processed.get(id))
+                .copied()
+                .unwrap_or(0);
+            // Pushes add up until the player's next input is applied
+            let (total_dx, total_dy) = match player.last_impulse.filter(|impulse| impulse.after_sequence == after_sequence) {
+                Some(impulse) => (impulse.dx, impulse.dy),
+                None => (0, 0),
+            };
+            player.last_impulse = Some(AppliedImpulse {
+                dx: total_dx + new.x - old.x,
+                dy: total_dy + new.y - old.y,
+                after_sequence,
+                tick: self.snapshot_id,
+            });
+            moved += 1;
+        }
+        self.debug_check_invariants();
+        moved
     }
 
-    #[test]
-    fn test_new_game() {
-        let game = Game::new();
-        assert!(game.players.is_empty());
-        assert!(game.id_to_addr.is_empty());
-        assert!(game.addr_to_id.is_empty());
-        assert!(game.last_processed.is_empty());
+    /// Writes a player's position through the same checks as every other position change: it has to be on the board
+    /// and, unless the reason is a teleport, close enough to the last recorded position. Refused writes are reported
+    /// as an event and leave the player where it was.
+    pub fn set_position_validated(&mut self, addr: SocketAddr, position: Position, origin: MoveOrigin) -> Result<(), PositionRejection> {
+        let player_count = self.players.len();
+        let id = self.addr_to_id.get(&addr).copied();
+        let now_ms = self.tick_time_ms(Instant::now());
+        let Some(player) = self.players.get_mut(&addr) else {
+            return Err(PositionRejection::UnknownPlayer);
+        };
+        write_position(player, id, position, origin, player_count, &mut self.events, now_ms)?;
+        self.debug_check_invariants();
+        Ok(())
     }
 
-    #[test]
-    fn test_connect_player() {
-        let mut game = Game::new();
-        let addr = test_addr(8080);
+    /// Places a player where its client asked, as a debug teleport. Refused unless debug teleports are on.
+    pub fn debug_teleport(&mut self, addr: SocketAddr, position: Position) -> Result<(), PositionRejection> {
+        if !self.debug_teleports {
+            let id = self.addr_to_id.get(&addr).copied();
+            let rejection = PositionRejection::DebugTeleportsOff;
+            self.events.push(GameEvent::PositionRejected { id, to: position, reason: PositionChange::Debug, rejection });
+            return Err(rejection);
+        }
+        self.set_position_validated(addr, position, MoveOrigin::Teleport)
+    }
 
-        let id = game.connect_player(addr);
+    /// Address of the player with the id, None if it isn't in this game
+    pub fn addr_of(&self, id: &Uuid) -> Option<SocketAddr> {
+        self.id_to_addr.get(id).copied()
+    }
 
-        // Check player was added
-        assert_eq!(game.players.len(), 1);
-        assert!(game.players.contains_key(&addr));
+    /// The player's position history from the server time since_ms on, oldest first, with what made each move
+    /// and how far it went. None for an unknown player.
+    pub fn history_report(&self, addr: SocketAddr, since_ms: u64) -> Option<String> {
+        let player = self.players.get(&addr)?;
+        let id = self.addr_to_id.get(&addr).map_or_else(|| "unknown".to_string(), Uuid::to_string);
+        let mut report = format!(
+            "Position history of player {} at {} since {} ms, now at ({}, {}):\n",
+            id, addr, since_ms, player.position.x, player.position.y
+        );
+        let mut previous: Option<Position> = None;
+        let mut listed = 0;
+        for entry in player.position_history.iter() {
+            if entry.timestamp >= since_ms {
+                let moved = previous.map_or_else(String::new, |from| format!(" by ({:+}, {:+})", entry.position.x - from.x, entry.position.y - from.y));
+                report.push_str(&format!("  {:>8} ms  ({}, {}){}  {}\n", entry.timestamp, entry.position.x, entry.position.y, moved, entry.origin.describe()));
+                listed += 1;
+            }
+            previous = Some(entry.position);
+        }
+        if listed == 0 {
+            report.push_str("  no moves\n");
+        }
+        Some(report)
+    }
 
-        // Check mappings were created
-        assert_eq!(game.id_to_addr.len(), 1);
-        assert_eq!(game.addr_to_id.len(), 1);
-        assert_eq!(game.id_to_addr.get(&id), Some(&addr));
-        assert_eq!(game.addr_to_id.get(&addr), Some(&id));
+    /// Teleports and refused position writes since the last call
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
 
-        // Check position history initialization
-        let player = game.players.get(&addr).unwrap();
-        assert_eq!(player.position_history.len(), 1);
+    /// Takes the players removed since the last call with the reason, to tell each of them
+    pub fn take_removed(&mut self) -> Vec<(SocketAddr, Uuid, DisconnectReason)> {
+        std::mem::take(&mut self.removed)
+    }
 
-        // Position should be within bounds
-        assert!(player.position.x >= PLAYER_SIZE);
-        assert!(player.position.x <= BOARD_WIDTH - PLAYER_SIZE);
-        assert!(player.position.y >= PLAYER_SIZE);
-        assert!(player.position.y <= BOARD_HEIGHT - PLAYER_SIZE - TOOL_BAR_HEIGHT);
+    /// Distances of the reordered inputs since the last call, the histogram starts over afterwards
+    pub fn take_reorder_distances(&mut self) -> Histogram {
+        std::mem::take(&mut self.reorder_distances)
     }
 
-    #[test]
-    fn test_reconnect_existing_player() {
-        let mut game = Game::new();
-        let addr = test_addr(8080);
+    /// Moves on to the next server tick, snapshots built afterwards carry the new id
+    pub fn advance_tick(&mut self) -> u64 {
+        self.advance_tick_at(Instant::now())
+    }
 
-        let id1 = game.connect_player(addr);
-        let id2 = game.connect_player(addr);  // Reconnect same address
+    /// Moves on to the next server tick starting at now, running the tick of the game mode while players may move
+    pub fn advance_tick_at(&mut self, now: Instant) -> u64 {
+        let dt = now.saturating_duration_since(self.last_tick_at);
+        self.snapshot_id += 1;
+        self.last_tick_at = now;
+        for player in self.players.values_mut() {
+            player.connected_time += dt;
+        }
+        if self.input_coalescing {
+            self.apply_coalesced_inputs(dt);
+        }
 
-        // Should return same ID and not create new player
-        assert_eq!(id1, id2);
-        assert_eq!(game.players.len(), 1);
+        if self.phase.allows_movement() {
+            // Sorted by id so the mode sees the players in the same order every tick
+            let addr_to_id = &self.addr_to_id;
+            let mut players: Vec<(Uuid, &mut PlayerState)> = self.players.iter_mut()
+                .filter_map(|(addr, player)| Some((*addr_to_id.get(addr)?, player)))
+                .collect();
+            players.sort_by_key(|(id, _)| *id);
+            self.mode.on_tick(dt, &mut players);
+        }
+        self.snapshot_id
     }
 
-    #[test]
-    fn test_disconnect_player() {
-        let mut game = Game::new();
-        let addr = test_addr(8080);
+    /// Applies the queued inputs each player has earned credit for over dt, carrying the rest over to the next
+    /// ticks. Whole inputs of credit aren't banked while the queue is empty, so a burst is always spread over consecutive ticks.
+    fn apply_coalesced_inputs(&mut self, dt: Duration) {
+        let earned = dt.as_secs_f32() / COALESCED_INPUT_INTERVAL.as_secs_f32();
+        let mut due = Vec::new();
+        for (addr, player) in self.players.iter_mut() {
+            player.input_credit += earned;
+            while let Some((input, received_at)) = player.queued_inputs.front().copied() {
+                if player.input_credit >= 1.0 {
+                    player.input_credit -= 1.0;
+                } else if player.queued_inputs.len() <= MAX_COALESCED_INPUTS {
+                    break;
+                }
+                player.queued_inputs.pop_front();
+                due.push((*addr, input, received_at));
+            }
+            if player.queued_inputs.is_empty() {
+                player.input_credit = player.input_credit.fract();
+            }
+        }
 
-        game.connect_player(addr);
-        game.disconnect_player(&addr);
+        // Sorted by id like the tick of the mode, so pushes between players come out the same every run
+        due.sort_by_key(|(addr, _, _)| self.addr_to_id.get(addr).copied());
+        for (addr, input, received_at) in due {
+            self.apply_input(addr, input, received_at, true);
+        }
+    }
 
-        // Player should be removed
-        assert!(game.players.is_empty());
-        assert!(game.id_to_addr.is_empty());
+    /// Server time of a moment in milliseconds on the tick timeline, where tick n starts at n * the tick interval
+    pub fn tick_time_ms(&self, at: Instant) -> u64 {
+        let tick_start = self.snapshot_id * self.tick_interval.as_millis() as u64;
+        if at >= self.last_tick_at {
+            tick_start + at.saturating_duration_since(self.last_tick_at).as_millis() as u64
+        } else {
+            tick_start.saturating_sub(self.last_tick_at.saturating_duration_since(at).as_millis() as u64)
+        }
+    }
+
+    /// Starts a ping to every active player. Returns the address and nonce to send to each of them.
+    pub fn start_server_pings(&mut self, now: Instant) -> Vec<(SocketAddr, u32)> {
+        self.players.iter_mut()
+            .map(|(addr, player)| (*addr, player.rtt.start_ping(now)))
+            .collect()
+    }
+
+    /// Handles a ping echo from a player, updating its round trip time estimate
+    pub fn handle_server_pong(&mut self, addr: SocketAddr, nonce: u32, now: Instant) {
+        if let Some(player) = self.players.get_mut(&addr) {
+            player.last_active = now;
+            player.rtt.on_pong(nonce, now);
+        }
+    }
+
+    /// A value of every active player by id
+    fn player_values(&self, value: impl Fn(&PlayerState) -> u32) -> HashMap<Uuid, u32> {
+        self.players.iter()
+            .filter_map(|(addr, player)| Some((*self.addr_to_id.get(addr)?, value(player))))
+            .collect()
+    }
+
+    /// Team of every active player, empty when team mode is off
+    fn teams(&self) -> HashMap<Uuid, u8> {
+        self.players.iter()
+            .filter_map(|(addr, player)| Some((*self.addr_to_id.get(addr)?, player.team?)))
+            .collect()
+    }
+
+    /// Round trip time of every player that has been measured
+    pub fn ping_ms(&self) -> HashMap<Uuid, u32> {
+        self.players.iter()
+            .filter_map(|(addr, player)| Some((*self.addr_to_id.get(addr)?, player.rtt.rtt_ms()?)))
+            .collect()
+    }
+
+    /// Current match number, incremented on every match reset
+    pub fn match_number(&self) -> u32 {
+        self.match_number
+    }
+
+    /// Checks whether the current match has lasted longer than the given duration
+    pub fn is_match_over(&self, now: Instant, match_duration: Duration) -> bool {
+        now.saturating_duration_since(self.match_started_at) >= match_duration
+    }
+
+    /// Current phase of the match
+    pub fn phase(&self) -> GamePhase {
+        self.phase
+    }
+
+    /// Moves the match through its phases: a match that lasted match_duration ends, after a pause
+    /// the next one is reset and counts down, and it starts once the countdown is over.
+    /// Returns the spawn positions when a new match was reset, so clients can be told.
+    pub fn update_phase(&mut self, now: Instant, match_duration: Duration) -> Option<Vec<(Uuid, Position)>> {
+        match self.phase {
+            GamePhase::Warmup | GamePhase::Active if self.is_match_over(now, match_duration) => {
+                self.phase = GamePhase::Ended { next_countdown_at: now + MATCH_END_PAUSE };
+                None
+            }
+            GamePhase::Ended { next_countdown_at } if now >= next_countdown_at => Some(self.reset_match(now)),
+            GamePhase::Countdown { ends_at } if now >= ends_at => {
+                // The match duration counts from the end of the countdown
+                self.phase = GamePhase::Active;
+                self.match_started_at = ends_at;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Starts a new match: respawns every player at a fresh position while keeping connections and ids.
+    /// Players stay frozen until the countdown is over.
+    /// Returns the new position of every player so clients can reset their prediction.
+    pub fn reset_match(&mut self, now: Instant) -> Vec<(Uuid, Position)> {
+        self.match_number += 1;
+        self.match_started_at = now;
+        self.phase = GamePhase::Countdown { ends_at: now + MATCH_COUNTDOWN };
+
+        let mut positions = Vec::with_capacity(self.players.len());
+        let player_count = self.players.len();
+        let now_ms = self.tick_time_ms(now);
+        for (addr, player) in self.players.iter_mut() {
+            // Marked as a teleport in the history so nothing interpolates across the respawn
+            let position = Self::spawn_position_from(&mut self.spawn_rng, SpawnZone::for_team(player.team, self.team_count));
+            let id = self.addr_to_id.get(addr).copied();
+            if write_position(player, id, position, MoveOrigin::MatchReset, player_count, &mut self.events, now_ms).is_err() {
+                continue;
+            }
+
+            // Scores start over, best score and total pellets are kept
+            player.score = 0;
+            player.last_impulse = None;
+
+            // last_processed is intentionally kept, so sequence numbers keep counting up
+            if let Some(id) = self.addr_to_id.get(addr) {
+                positions.push((*id, position));
+            }
+        }
+        self.mode.on_match_reset();
+        self.debug_check_invariants();
+        positions
+    }
+
+    /// Sets the records from earlier server runs that returning players can claim
+    pub fn set_saved_records(&mut self, records: HashMap<Uuid, PlayerRecord>) {
+        self.saved_records = records;
+    }
+
+    /// Gives a freshly connected player the saved record of its old session token.
+    /// The player keeps using that token, so the record stays under the same key.
+    /// Returns false if the server has no record for the token, or another session here already holds it.
+    pub fn restore_saved_record(&mut self, addr: &SocketAddr, session_token: Uuid) -> bool {
+        if self.token_in_use(&session_token, addr) {
+            return false;
+        }
+        let Some(record) = self.saved_records.get(&session_token).cloned() else {
+            return false;
+        };
+        let Some(player) = self.players.get_mut(addr) else {
+            return false;
+        };
+        player.session_token = session_token;
+        player.best_score = player.best_score.max(record.best_score);
+        player.total_pellets = player.total_pellets.max(record.total_pellets);
+        self.debug_check_invariants();
+        true
+    }
+
+    /// Gives a freshly connected player the best saved record under its name, for a returning player
+    /// that lost its token. Only the scores are taken, the player keeps its fresh token, so a name is
+    /// never enough to take over a session. Records whose token a session here holds are skipped.
+    /// Returns false if the player has no name or no saved record matches it.
+    pub fn restore_saved_record_by_name(&mut self, addr: &SocketAddr) -> bool {
+        let Some(name) = self.players.get(addr).map(|player| player.name.clone()).filter(|name| !name.is_empty()) else {
+            return false;
+        };
+        let best = self.saved_records.iter()
+            .filter(|(token, record)| record.name == name && !self.token_in_use(token, addr))
+            .map(|(_, record)| (record.best_score, record.total_pellets))
+            .reduce(|(best, pellets), (other_best, other_pellets)| (best.max(other_best), pellets.max(other_pellets)));
+        let (Some((best_score, total_pellets)), Some(player)) = (best, self.players.get_mut(addr)) else {
+            return false;
+        };
+        player.best_score = player.best_score.max(best_score);
+        player.total_pellets = player.total_pellets.max(total_pellets);
+        true
+    }
+
+    /// Checks if a session other than the player at the address holds the token, active or timed out
+    fn token_in_use(&self, session_token: &Uuid, addr: &SocketAddr) -> bool {
+        self.disconnected.contains_key(session_token)
+            || self.players.iter().any(|(other, player)| other != addr && player.session_token == *session_token)
+    }
+
+    /// Records of every known player by session token: saved ones, active and disconnected players
+    pub fn player_records(&self) -> HashMap<Uuid, PlayerRecord> {
+        let mut records = self.saved_records.clone();
+        let live = self.players.values().chain(self.disconnected.values().map(|d| &d.state));
+        for player in live {
+            records.insert(player.session_token, PlayerRecord {
+                best_score: player.best_score,
+                total_pellets: player.total_pellets,
+                name: player.name.clone(),
+            });
+        }
+        records
+    }
+
+    /// Checks that the id and address maps agree with each other and with the players, that every
+    /// player is on the board and that position histories are in order.
+    /// Returns a description of every violation, an empty list means the state is consistent.
+    pub fn check_invariants(&self) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        for (id, addr) in &self.id_to_addr {
+            if self.addr_to_id.get(addr) != Some(id) {
+                violations.push(format!("id_to_addr maps {} to {}, but addr_to_id does not map it back", id, addr));
+            }
+        }
+        for (addr, id) in &self.addr_to_id {
+            if self.id_to_addr.get(id) != Some(addr) {
+                violations.push(format!("addr_to_id maps {} to {}, but id_to_addr does not map it back", addr, id));
+            }
+            if !self.players.contains_key(addr) {
+                violations.push(format!("addr_to_id has {} without a player", addr));
+            }
+        }
+
+        for id in self.last_processed.keys() {
+            if !self.id_to_addr.contains_key(id) {
+                violations.push(format!("last_processed has unknown id {}", id));
+            }
+        }
+
+        for (addr, player) in &self.players {
+            if !self.addr_to_id.contains_key(addr) {
+                violations.push(format!("player at {} is missing from addr_to_id", addr));
+            }
+            if !is_on_board(player.position) {
+                violations.push(format!("player at {} is off the board at ({}, {})", addr, player.position.x, player.position.y));
+            }
+            match player.team {
+                Some(team) if team >= self.team_count => {
+                    violations.push(format!("player at {} is in team {}, but there are only {} teams", addr, team, self.team_count));
+                }
+                None if self.team_count > 0 => violations.push(format!("player at {} has no team in team mode", addr)),
+                _ => {}
+            }
+            let history = &player.position_history;
+            if history.iter().zip(history.iter().skip(1)).any(|(older, newer)| older.timestamp > newer.timestamp) {
+                violations.push(format!("position history of the player at {} is not sorted by timestamp", addr));
+            }
+        }
+        violations
+    }
+
+    /// Panics with the violated invariants in debug builds, release builds skip the check
+    fn debug_check_invariants(&self) {
+        if cfg!(debug_assertions) {
+            let violations = self.check_invariants();
+            assert!(violations.is_empty(), "Game invariants violated: {}", violations.join("; "));
+        }
+    }
+
+    /// Mutable access to players (use only when necessary)
+    pub fn get_players_mut(&mut self) -> &mut HashMap<SocketAddr, PlayerState> {
+        &mut self.players
+    }
+}
+
+/// Why a player's position is written, which decides how far it may move at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionChange {
+    Input,     // One step of an applied input
+    Knockback, // Pushed apart from the players it ran into
+    Respawn,   // Placed at a spawn position, a teleport
+    Debug,     // Placed by a debug or mod command anywhere on the board, a teleport
+}
+
+/// Conversion from the MoveOrigin: the checks a move of that origin has to pass
+impl From<MoveOrigin> for PositionChange {
+    fn from(origin: MoveOrigin) -> Self {
+        match origin {
+            MoveOrigin::Input { .. } | MoveOrigin::CoalescedInput { .. } | MoveOrigin::LateInput { .. } => PositionChange::Input,
+            MoveOrigin::Knockback => PositionChange::Knockback,
+            MoveOrigin::Spawn | MoveOrigin::MatchReset => PositionChange::Respawn,
+            MoveOrigin::Teleport => PositionChange::Debug,
+        }
+    }
+}
+
+/// Implementation of the PositionChange
+impl PositionChange {
+    /// Checks if the change places the player instead of moving it, so it may go anywhere on the board
+    pub fn is_teleport(self) -> bool {
+        matches!(self, PositionChange::Respawn | PositionChange::Debug)
+    }
+
+    /// Furthest the change may move a player with the speed along either axis, None for teleports.
+    /// Every other player can push at once, so a knockback may add up to one push from each of them.
+    fn max_displacement(self, speed: f32, player_count: usize) -> Option<i32> {
+        match self {
+            PositionChange::Input => Some(speed.ceil() as i32),
+            PositionChange::Knockback => Some(KNOCKBACK_DISTANCE * player_count.saturating_sub(1).max(1) as i32),
+            PositionChange::Respawn | PositionChange::Debug => None,
+        }
+    }
+}
+
+/// Why a position write was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionRejection {
+    UnknownPlayer,
+    OffBoard,
+    TooFar { distance: i32, max: i32 }, // Moved further along an axis than the reason allows
+    DebugTeleportsOff, // A client asked for a debug teleport on a server that doesn't allow them
+}
+
+/// Something that happened in a game for the server to report
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    Teleported { id: Option<Uuid>, from: Position, to: Position, reason: PositionChange },
+    PositionRejected { id: Option<Uuid>, to: Position, reason: PositionChange, rejection: PositionRejection },
+}
+
+/// Implementation of the GameEvent
+impl GameEvent {
+    /// Describes the event for the server log
+    pub fn describe(&self) -> String {
+        let name = |id: &Option<Uuid>| id.map(|id| id.to_string()).unwrap_or_else(|| "unknown".to_string());
+        match self {
+            GameEvent::Teleported { id, from, to, reason } => {
+                format!("Player {} teleported from ({}, {}) to ({}, {}) by {:?}", name(id), from.x, from.y, to.x, to.y, reason)
+            }
+            GameEvent::PositionRejected { id, to, reason, rejection } => {
+                format!("Refused {:?} move of player {} to ({}, {}): {:?}", reason, name(id), to.x, to.y, rejection)
+            }
+        }
+    }
+}
+
+/// The one place a player's position is written. Checks the new position against the board and, for moves,
+/// against the last recorded position, then records it in the history with its origin and the server time.
+/// Teleports are marked in the history and reported as an event, refused writes are only reported.
+fn write_position(
+    player: &mut PlayerState,
+    id: Option<Uuid>,
+    position: Position,
+    origin: MoveOrigin,
+    player_count: usize,
+    events: &mut Vec<GameEvent>,
+    now_ms: u64,
+) -> Result<(), PositionRejection> {
+    let reason = PositionChange::from(origin);
+    let from = player.position_history.newest().map_or(player.position, |entry| entry.position);
+    // Off-board positions are rejected before the distance is taken, a client can send any i32
+    let distance = || position.x.saturating_sub(from.x).saturating_abs().max(position.y.saturating_sub(from.y).saturating_abs());
+    let rejection = match reason.max_displacement(player.speed, player_count) {
+        _ if !is_on_board(position) => Some(PositionRejection::OffBoard),
+        Some(max) if distance() > max => Some(PositionRejection::TooFar { distance: distance(), max }),
+        _ => None,
+    };
+    if let Some(rejection) = rejection {
+        events.push(GameEvent::PositionRejected { id, to: position, reason, rejection });
+        return Err(rejection);
+    }
+
+    // The oldest entry is dropped once the history holds MAX_POSITION_HISTORY. A late tick can put the
+    // tick timeline a few milliseconds behind the last write, the history stays sorted regardless.
+    player.position = position;
+    let timestamp = player.position_history.newest().map_or(now_ms, |entry| entry.timestamp.max(now_ms));
+    player.position_history.push(PositionSnapshot {
+        position,
+        timestamp,
+        teleport: reason.is_teleport(),
+        origin,
+    });
+    if reason.is_teleport() {
+        player.accumulator.clear();
+        events.push(GameEvent::Teleported { id, from, to: position, reason });
+    }
+    Ok(())
+}
+
+/// Checks if a player position is within the area players can move in
+fn is_on_board(position: Position) -> bool {
+    (PLAYER_SIZE..=BOARD_WIDTH - PLAYER_SIZE).contains(&position.x)
+        && (PLAYER_SIZE..=BOARD_HEIGHT - PLAYER_SIZE - TOOL_BAR_HEIGHT).contains(&position.y)
+}
+
+/// Moves a player position onto the area players can move in
+fn clamp_to_board(position: Position) -> Position {
+    Position {
+        x: position.x.clamp(PLAYER_SIZE, BOARD_WIDTH - PLAYER_SIZE),
+        y: position.y.clamp(PLAYER_SIZE, BOARD_HEIGHT - PLAYER_SIZE - TOOL_BAR_HEIGHT),
+    }
+}
+
+/// Direction the second player is pushed away from the first if they overlap, the first is pushed the opposite way.
+/// Players on the same spot are pushed apart horizontally.
+fn knockback_direction(first: Position, second: Position) -> Option<(i32, i32)> {
+    let (dx, dy) = (second.x - first.x, second.y - first.y);
+    if dx.abs() >= PLAYER_SIZE || dy.abs() >= PLAYER_SIZE {
+        return None;
+    }
+    if dx.abs() >= dy.abs() {
+        Some((if dx == 0 { 1 } else { dx.signum() }, 0))
+    } else {
+        Some((0, dy.signum()))
+    }
+}
+
+
+/// Default implementation for the Game
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Unit tests for the Game state
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::positions::AuthoritativePos;
+    use crate::prediction::PredictionState;
+    use crate::types::Direction;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    // Helper function to create test socket addresses
+    fn test_addr(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port)
+    }
+
+    #[test]
+    fn test_new_game() {
+        let game = Game::new();
+        assert!(game.players.is_empty());
+        assert!(game.id_to_addr.is_empty());
+        assert!(game.addr_to_id.is_empty());
+        assert!(game.last_processed.is_empty());
+    }
+
+    #[test]
+    fn test_connect_player() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+
+        let id = game.connect_player(addr);
+
+        // Check player was added
+        assert_eq!(game.players.len(), 1);
+        assert!(game.players.contains_key(&addr));
+
+        // Check mappings were created
+        assert_eq!(game.id_to_addr.len(), 1);
+        assert_eq!(game.addr_to_id.len(), 1);
+        assert_eq!(game.id_to_addr.get(&id), Some(&addr));
+        assert_eq!(game.addr_to_id.get(&addr), Some(&id));
+
+        // Check position history initialization
+        let player = game.players.get(&addr).unwrap();
+        assert_eq!(player.position_history.len(), 1);
+
+        // Position should be within bounds
+        assert!(player.position.x >= PLAYER_SIZE);
+        assert!(player.position.x <= BOARD_WIDTH - PLAYER_SIZE);
+        assert!(player.position.y >= PLAYER_SIZE);
+        assert!(player.position.y <= BOARD_HEIGHT - PLAYER_SIZE - TOOL_BAR_HEIGHT);
+    }
+
+    #[test]
+    fn test_reconnect_existing_player() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+
+        let id1 = game.connect_player(addr);
+        let id2 = game.connect_player(addr);  // Reconnect same address
+
+        // Should return same ID and not create new player
+        assert_eq!(id1, id2);
+        assert_eq!(game.players.len(), 1);
+    }
+
+    #[test]
+    fn test_disconnect_player() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+
+        game.connect_player(addr);
+        game.disconnect_player(&addr);
+
+        // Player should be removed
+        assert!(game.players.is_empty());
+        assert!(game.id_to_addr.is_empty());
         assert!(game.addr_to_id.is_empty());
     }
 
@@ -255,6 +1520,8 @@ mod tests {
         let addr = test_addr(8080);
 
         let id = game.connect_player(addr);
+        // Start away from the board edges so the moves aren't clamped
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
         let initial_pos = game.players.get(&addr).unwrap().position;
 
         // Test movement and input tracking
@@ -268,8 +1535,9 @@ mod tests {
         // Sequence should be updated
         assert_eq!(game.last_processed.get(&id), Some(&1));
 
-        // Position history should be updated
-        assert_eq!(player.position_history.len(), 2);
+        // Position history should be updated: the spawn, the placement and the move, which isn't a teleport
+        assert_eq!(player.position_history.len(), 3);
+        assert!(!player.position_history.newest().unwrap().teleport);
     }
 
     #[test]
@@ -322,8 +1590,8 @@ mod tests {
         // Should track processed inputs
         assert_eq!(snapshot.last_processed.get(&id1), Some(&5));
 
-        // No need to check timestamp >= 0 as u64 is always >= 0
-        assert!(true);
+        // Should carry the current match number
+        assert_eq!(snapshot.match_number, game.match_number());
     }
 
     #[test]
@@ -370,27 +1638,1462 @@ mod tests {
     }
 
     #[test]
-    fn test_update_server_dropped() {
+    fn test_reset_match_keeps_ids_and_moves_positions() {
         let mut game = Game::new();
-        let addr = test_addr(8080);
+        let addr1 = test_addr(8080);
+        let addr2 = test_addr(8081);
 
-        game.connect_player(addr);
+        let id1 = game.connect_player(addr1);
+        let id2 = game.connect_player(addr2);
 
-        // Manually set last_active to be longer than timeout
-        {
-            let player = game.players.get_mut(&addr).unwrap();
-            player.last_active = Instant::now() - TIMEOUT - Duration::from_secs(1);
+        // Put the players outside the spawn area so a respawn always moves them
+        for player in game.players.values_mut() {
+            player.position = Position { x: 0, y: 0 };
         }
 
-        // Check player exists before timeout
-        assert_eq!(game.players.len(), 1);
+        let positions = game.reset_match(Instant::now());
 
-        // Run timeout check
-        game.update_server_dropped();
+        // Every player is respawned with the same id and address
+        assert_eq!(positions.len(), 2);
+        assert_eq!(game.id_to_addr.get(&id1), Some(&addr1));
+        assert_eq!(game.id_to_addr.get(&id2), Some(&addr2));
 
-        // Player should be removed after timeout
-        assert!(game.players.is_empty());
-        assert!(game.id_to_addr.is_empty());
-        assert!(game.addr_to_id.is_empty());
+        // Positions changed and match the reported positions
+        for (id, pos) in positions {
+            let addr = game.id_to_addr.get(&id).unwrap();
+            let player = game.players.get(addr).unwrap();
+            assert_ne!(pos, Position { x: 0, y: 0 });
+            assert_eq!(player.position, pos);
+
+            // Nothing is interpolated across the respawn
+            let since_respawn: Vec<Position> = player.history_since_teleport().map(|entry| entry.position).collect();
+            assert_eq!(since_respawn, vec![pos]);
+            assert!(player.position_history.newest().unwrap().teleport);
+        }
+    }
+
+    #[test]
+    fn test_reset_match_preserves_last_processed() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+
+        game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence: 500, timestamp: 0 });
+        let start = Instant::now();
+        game.reset_match(start);
+
+        // Sequence bookkeeping must survive the reset
+        assert_eq!(game.last_processed.get(&id), Some(&500));
+
+        // New inputs continue to be applied once the countdown is over, away from the board edges
+        game.update_phase(start + MATCH_COUNTDOWN, Duration::from_secs(60));
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+        let spawn = game.players.get(&addr).unwrap().position;
+        game.handle_input(addr, PlayerInput { dir: Direction::Down, sequence: 501, timestamp: 0 });
+        assert_eq!(game.players.get(&addr).unwrap().position.y, spawn.y + PLAYER_SPEED);
+        assert_eq!(game.last_processed.get(&id), Some(&501));
+    }
+
+    #[test]
+    fn test_match_number_in_snapshot() {
+        let mut game = Game::new();
+        assert_eq!(game.build_snapshot().match_number, 0);
+
+        game.reset_match(Instant::now());
+        game.reset_match(Instant::now());
+
+        assert_eq!(game.match_number(), 2);
+        assert_eq!(game.build_snapshot().match_number, 2);
+    }
+
+    #[test]
+    fn test_is_match_over() {
+        let mut game = Game::new();
+        let start = Instant::now();
+        game.reset_match(start);
+
+        assert!(!game.is_match_over(start + Duration::from_secs(59), Duration::from_secs(60)));
+        assert!(game.is_match_over(start + Duration::from_secs(60), Duration::from_secs(60)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sequence_window() {
+        let mut window = SequenceWindow::default();
+        assert_eq!(window.newest(), None);
+
+        // In order and out of order sequences are accepted once
+        assert!(window.accept(5));
+        assert!(window.accept(7));
+        assert!(window.accept(6));
+        assert_eq!(window.newest(), Some(7));
+
+        // Duplicates are rejected
+        assert!(!window.accept(5));
+        assert!(!window.accept(6));
+        assert!(!window.accept(7));
+
+        // Sequences older than the window are rejected
+        assert!(window.accept(7 + SEQUENCE_WINDOW));
+        assert!(!window.accept(7));
+        assert!(window.accept(8));
+
+        // A large jump forward clears the window
+        assert!(window.accept(1000));
+        assert!(window.accept(999));
+        assert!(!window.accept(1000));
+    }
+
+    #[test]
+    fn test_duplicate_input_is_ignored() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        // Start away from the board edges so the moves aren't clamped
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+        let initial_pos = game.players.get(&addr).unwrap().position;
+
+        let input = PlayerInput { dir: Direction::Right, sequence: 1, timestamp: 0 };
+        game.handle_input(addr, input);
+        game.handle_input(addr, input);
+
+        // The player only moved once
+        assert_eq!(game.players.get(&addr).unwrap().position.x, initial_pos.x + PLAYER_SPEED);
+        assert_eq!(game.last_processed.get(&id), Some(&1));
+    }
+
+    #[test]
+    fn test_reordered_input_is_applied() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        // Start away from the board edges so the moves aren't clamped
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+        let initial_pos = game.players.get(&addr).unwrap().position;
+
+        game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence: 2, timestamp: 0 });
+        game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence: 1, timestamp: 0 });
+
+        // Both inputs moved the player, and last_processed doesn't go backwards
+        assert_eq!(game.players.get(&addr).unwrap().position.x, initial_pos.x + 2 * PLAYER_SPEED);
+        assert_eq!(game.last_processed.get(&id), Some(&2));
+    }
+
+    // Helper function to send inputs with the given sequences, alternating directions so the player stays put
+    fn send_sequences(game: &mut Game, addr: SocketAddr, sequences: &[u32]) {
+        for &sequence in sequences {
+            let dir = if sequence % 2 == 0 { Direction::Left } else { Direction::Right };
+            game.handle_input(addr, PlayerInput { dir, sequence, timestamp: 0 });
+        }
+    }
+
+    #[test]
+    fn test_profile_is_stored_and_broadcast() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let other = test_addr(8081);
+        let id = game.connect_player(addr);
+        let other_id = game.connect_player(other);
+
+        game.set_profile(&addr, " Kari\u{7}Nordmann-with-a-long-name", PlayerShape::Triangle);
+        assert_eq!(game.player_name(&addr), Some("KariNordmann-wit".to_string()));
+        assert_eq!(game.player_name(&other), None);
+
+        let snapshot = game.build_snapshot();
+        assert_eq!(snapshot.shapes.get(&id), Some(&PlayerShape::Triangle));
+        assert_eq!(snapshot.shapes.get(&other_id), Some(&PlayerShape::Square)); // Connected without a profile
+    }
+
+    #[test]
+    fn test_reorder_distances_are_counted() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+
+        // The 1 and 2 arrive after the 3, the 4 after the 5, the 6 and 7 after the 8. The duplicate 5 is not a reorder.
+        send_sequences(&mut game, addr, &[0, 3, 1, 2, 5, 4, 5, 8, 6, 7]);
+
+        let mut expected = Histogram::new();
+        for distance in [2, 1, 1, 2, 1] {
+            expected.record(distance);
+        }
+        assert_eq!(game.players[&addr].reorders.total(), 5);
+        assert_eq!(game.build_snapshot().input_reorders.get(&id), Some(&5));
+        assert_eq!(game.take_reorder_distances(), expected);
+        assert!(game.take_reorder_distances().is_empty()); // Starts over after being taken
+    }
+
+    #[test]
+    fn test_shuffled_inputs_match_the_permutation() {
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+
+        let mut game = Game::new();
+        let a = test_addr(8080);
+        let b = test_addr(8081);
+        game.connect_player(a);
+        game.connect_player(b);
+
+        // Shuffle within blocks so every input stays inside the duplicate detection window
+        let mut expected = Histogram::new();
+        let mut expected_per_player = Vec::new();
+        for addr in [a, b] {
+            let mut sequences: Vec<u32> = (0..200).collect();
+            for block in sequences.chunks_mut(16) {
+                block.shuffle(&mut rng);
+            }
+
+            let mut newest = None;
+            let mut count = 0;
+            for &sequence in &sequences {
+                match newest {
+                    Some(max) if sequence < max => {
+                        expected.record((max - sequence) as u64);
+                        count += 1;
+                    }
+                    _ => newest = Some(sequence),
+                }
+            }
+            expected_per_player.push(count);
+            send_sequences(&mut game, addr, &sequences);
+        }
+
+        assert!(expected.count() > 0);
+        assert_eq!(game.take_reorder_distances(), expected);
+        assert_eq!(game.players[&a].reorders.total(), expected_per_player[0]);
+        assert_eq!(game.players[&b].reorders.total(), expected_per_player[1]);
+    }
+
+    #[test]
+    fn test_reorder_rate_only_counts_the_last_minute() {
+        let mut counter = ReorderCounter::default();
+        let start = Instant::now();
+        counter.record(start);
+        counter.record(start + Duration::from_secs(30));
+        counter.record(start + Duration::from_secs(70));
+
+        assert_eq!(counter.total(), 3);
+        assert_eq!(counter.per_minute(start + Duration::from_secs(70)), 2);
+        assert_eq!(counter.per_minute(start + Duration::from_secs(100)), 1);
+        assert_eq!(counter.per_minute(start + Duration::from_secs(200)), 0);
+    }
+
+    #[test]
+    fn test_fresh_connect_resets_sequences() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence: 500, timestamp: 0 });
+
+        // Connecting again from the same address starts a new sequence space
+        assert_eq!(game.connect_player(addr), id);
+        assert_eq!(game.last_processed.get(&id), None);
+        assert_eq!(game.build_welcome(&addr, false).unwrap().last_processed, None);
+
+        // Start away from the board edges so the move isn't clamped
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+        let pos = game.players.get(&addr).unwrap().position;
+        game.handle_input(addr, PlayerInput { dir: Direction::Down, sequence: 0, timestamp: 0 });
+        assert_eq!(game.players.get(&addr).unwrap().position.y, pos.y + PLAYER_SPEED);
+        assert_eq!(game.last_processed.get(&id), Some(&0));
+    }
+
+    #[test]
+    fn test_spawn_seed_repeats_spawns_and_is_sent_in_the_welcome() {
+        // Spawns six players into a game seeded with the seed, the same seed puts them at the same positions
+        let spawns = |seed: u64| {
+            let mut game = Game::with_teams(2);
+            game.set_spawn_seed(seed);
+            let spawns: Vec<Position> = (0..6).map(|port| {
+                let addr = test_addr(8000 + port);
+                game.connect_player(addr);
+                game.players.get(&addr).unwrap().position
+            }).collect();
+            (spawns, game.build_welcome(&test_addr(8000), false).unwrap().build.unwrap())
+        };
+        let (first, build) = spawns(31);
+        assert_eq!(spawns(31).0, first);
+        assert_ne!(spawns(32).0, first);
+        assert_eq!(build, BuildInfo::current(Some(31)));
+    }
+
+    #[test]
+    fn test_reconnect_after_timeout_restores_session() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence: 500, timestamp: 0 });
+        let welcome = game.build_welcome(&addr, false).unwrap();
+        let position = game.players.get(&addr).unwrap().position;
+
+        game.disconnect_player(&addr);
+        assert!(game.players.is_empty());
+
+        // Resume from a different port with the session token
+        let new_addr = test_addr(9090);
+        assert_eq!(game.reconnect_player(new_addr, welcome.session_token), Some(id));
+        assert_eq!(game.id_to_addr.get(&id), Some(&new_addr));
+        assert_eq!(game.addr_to_id.get(&new_addr), Some(&id));
+        assert_eq!(game.players.get(&new_addr).unwrap().position, position);
+        assert_eq!(game.last_processed.get(&id), Some(&500));
+
+        let resumed = game.build_welcome(&new_addr, true).unwrap();
+        assert_eq!(resumed.id, id);
+        assert_eq!(resumed.last_processed, Some(500));
+
+        // Unknown tokens are rejected
+        assert_eq!(game.reconnect_player(test_addr(9091), Uuid::new_v4()), None);
+    }
+
+    #[test]
+    fn test_reconnect_before_timeout_moves_session() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+
+        let new_addr = test_addr(9090);
+        assert_eq!(game.reconnect_player(new_addr, token), Some(id));
+        assert_eq!(game.players.len(), 1);
+        assert!(game.players.contains_key(&new_addr));
+        assert_eq!(game.id_to_addr.get(&id), Some(&new_addr));
+        assert!(!game.addr_to_id.contains_key(&addr));
+    }
+
+    #[test]
+    fn test_migrate_addr_mid_movement_keeps_position_and_sequence() {
+        let mut game = Game::new();
+        let (addr, new_addr) = (test_addr(8080), test_addr(8081));
+        let id = game.connect_player(addr);
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+        for sequence in 1..=3 {
+            game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence, timestamp: 0 });
+        }
+        let before = game.players[&addr].position;
+        let history = game.players[&addr].position_history.len();
+
+        // The port changes, the next input carries the token from the new address
+        assert_eq!(game.rebind_session(new_addr, token), Some((id, addr)));
+        assert!(game.check_invariants().is_empty());
+        assert!(!game.players.contains_key(&addr));
+        assert_eq!(game.players[&new_addr].position, before);
+        assert_eq!(game.players[&new_addr].position_history.len(), history);
+        assert_eq!(game.last_processed.get(&id), Some(&3));
+
+        // The movement carries on without a jump, and a duplicate of an earlier input is still ignored
+        game.handle_input(new_addr, PlayerInput { dir: Direction::Right, sequence: 4, timestamp: 0 });
+        game.handle_input(new_addr, PlayerInput { dir: Direction::Right, sequence: 3, timestamp: 0 });
+        assert_eq!(game.players[&new_addr].position, Position { x: before.x + PLAYER_SPEED, y: before.y });
+        assert_eq!(game.last_processed.get(&id), Some(&4));
+        assert!(!game.players[&new_addr].position_history.newest().unwrap().teleport);
+
+        // Inputs from the old address no longer reach the player
+        game.handle_input(addr, PlayerInput { dir: Direction::Down, sequence: 5, timestamp: 0 });
+        assert_eq!(game.last_processed.get(&id), Some(&4));
+    }
+
+    #[test]
+    fn test_migrate_addr_refuses_an_address_in_use() {
+        let mut game = Game::new();
+        let (a, b) = (test_addr(8080), test_addr(8081));
+        game.connect_player(a);
+        game.connect_player(b);
+        let token = game.build_welcome(&a, false).unwrap().session_token;
+
+        // Another player's address and an address without a player are both refused
+        assert!(!game.migrate_addr(a, b));
+        assert!(!game.migrate_addr(test_addr(9000), test_addr(9001)));
+        assert_eq!(game.rebind_session(b, token), None);
+        assert_eq!(game.rebind_session(a, Uuid::new_v4()), None);
+        assert_eq!(game.players.len(), 2);
+        assert!(game.check_invariants().is_empty());
+    }
+
+    #[test]
+    fn test_reconnect_grace_expires() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+        game.disconnect_player(&addr);
+
+        // Pretend the player disconnected longer ago than the grace period
+        game.disconnected.get_mut(&token).unwrap().disconnected_at = Instant::now() - RECONNECT_GRACE;
+        game.update_server_dropped();
+
+        assert_eq!(game.reconnect_player(addr, token), None);
+        assert!(game.players.is_empty());
+    }
+
+    #[test]
+    fn test_disconnected_player_keeps_its_color_for_the_grace() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        let color = game.players[&addr].color;
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+        game.disconnect_player(&addr);
+
+        // A player joining during the grace period gets another color
+        let other = test_addr(8081);
+        game.connect_player(other);
+        assert_ne!(game.players[&other].color, color);
+
+        // The resumed session has its old color back
+        assert_eq!(game.reconnect_player(test_addr(9090), token), Some(id));
+        assert_eq!(game.players[&test_addr(9090)].color, color);
+        assert_eq!(game.colors.color_of(&id), Some(PlayerColor::from_packed(color)));
+    }
+
+    #[test]
+    fn test_leaving_frees_the_session_and_color_at_once() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        let color = PlayerColor::from_packed(game.players[&addr].color);
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+
+        // Unlike a timeout nothing is kept for a reconnect
+        assert_eq!(game.leave_player(&addr), Some(id));
+        assert!(game.players.is_empty() && game.addr_to_id.is_empty());
+        assert!(!game.colors.is_reserved(color));
+        assert_eq!(game.reconnect_player(test_addr(9090), token), None);
+        assert_eq!(game.leave_player(&addr), None);
+    }
+
+    #[test]
+    fn test_resumable_sessions_are_capped_under_churn() {
+        let mut game = Game::new();
+        game.set_reconnect_retention(RECONNECT_GRACE, 3);
+
+        // Ten players join and time out one after the other, only the three that left last can come back
+        let mut sessions = Vec::new();
+        for port in 0..10 {
+            let addr = test_addr(8000 + port);
+            let id = game.connect_player(addr);
+            let token = game.build_welcome(&addr, false).unwrap().session_token;
+            let color = PlayerColor::from_packed(game.players[&addr].color);
+            game.disconnect_player(&addr);
+            game.disconnected.get_mut(&token).unwrap().disconnected_at = Instant::now() - Duration::from_secs(10 - port as u64);
+            sessions.push((id, token, color));
+        }
+        assert_eq!(game.resumable_sessions(), (3, 3));
+
+        // The kept sessions hold their colors, the forgotten ones were free for the players that joined after them
+        assert!(sessions[..7].iter().all(|(_, token, _)| !game.disconnected.contains_key(token)));
+        assert!(sessions[7..].iter().all(|(_, _, color)| game.colors.is_reserved(*color)));
+        assert_eq!(game.reconnect_player(test_addr(9000), sessions[0].1), None);
+        assert_eq!(game.reconnect_player(test_addr(9001), sessions[9].1), Some(sessions[9].0));
+
+        // Lowering the cap forgets more right away
+        game.set_reconnect_retention(RECONNECT_GRACE, 1);
+        assert_eq!(game.resumable_sessions(), (1, 1));
+        assert!(game.disconnected.contains_key(&sessions[8].1));
+        game.set_reconnect_retention(RECONNECT_GRACE, 0);
+        assert!(!game.colors.is_reserved(sessions[8].2));
+    }
+
+    #[test]
+    fn test_configured_grace_expires_sessions_and_colors() {
+        let mut game = Game::new();
+        let grace = Duration::from_secs(2);
+        game.set_reconnect_retention(grace, MAX_RESUMABLE_SESSIONS);
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+        let color = PlayerColor::from_packed(game.players[&addr].color);
+        game.disconnect_player(&addr);
+
+        // Still resumable just inside the shorter grace, gone once it passed
+        game.disconnected.get_mut(&token).unwrap().disconnected_at = Instant::now() - grace + Duration::from_millis(500);
+        game.update_server_dropped();
+        assert_eq!(game.resumable_sessions().0, 1);
+        game.disconnected.get_mut(&token).unwrap().disconnected_at = Instant::now() - grace;
+        game.update_server_dropped();
+        assert_eq!(game.resumable_sessions().0, 0);
+        assert_eq!(game.reconnect_player(addr, token), None);
+        game.colors.expire(Instant::now() + grace);
+        assert!(!game.colors.is_reserved(color));
+    }
+
+    #[test]
+    fn test_restored_player_is_respawned_when_its_spot_was_taken() {
+        let mut game = Game::new();
+        let (addr, other_addr) = (test_addr(8080), test_addr(8081));
+        let id = game.connect_player(addr);
+        game.set_position_validated(addr, Position { x: 300, y: 300 }, MoveOrigin::Teleport).unwrap();
+        game.players.get_mut(&addr).unwrap().score = 7;
+        let color = game.players[&addr].color;
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+        game.players.get_mut(&addr).unwrap().last_active = Instant::now() - TIMEOUT;
+        game.update_server_dropped();
+
+        // Another player now stands where it timed out
+        game.connect_player(other_addr);
+        game.set_position_validated(other_addr, Position { x: 305, y: 302 }, MoveOrigin::Teleport).unwrap();
+        game.take_events();
+
+        // It comes back with its score and color, fresh activity and a spawn clear of the spot
+        let before = Instant::now();
+        assert_eq!(game.reconnect_player(addr, token), Some(id));
+        let player = &game.players[&addr];
+        assert_eq!((player.score, player.color), (7, color));
+        assert!(player.last_active >= before);
+        assert_ne!(player.position, Position { x: 300, y: 300 });
+        assert_eq!(player.position_history.newest().map(|entry| entry.origin), Some(MoveOrigin::Spawn));
+        assert!(matches!(game.take_events()[..], [GameEvent::Teleported { reason: PositionChange::Respawn, .. }]));
+    }
+
+    #[test]
+    fn test_restored_player_keeps_a_spot_that_is_still_free() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+        game.set_position_validated(addr, Position { x: 300, y: 300 }, MoveOrigin::Teleport).unwrap();
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+        game.disconnect_player(&addr);
+        game.take_events();
+
+        assert!(game.reconnect_player(addr, token).is_some());
+        assert_eq!(game.players[&addr].position, Position { x: 300, y: 300 });
+        assert!(game.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_token_reconnect_then_immediate_input_is_applied() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+
+        // Client sends a lot of inputs before dropping the connection
+        let mut prediction = PredictionState::new(AuthoritativePos::new(game.players.get(&addr).unwrap().position));
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+        for _ in 0..500 {
+            let input = PlayerInput { dir: Direction::Right, sequence: prediction.next_sequence, timestamp: 0 };
+            prediction.next_sequence += 1;
+            game.handle_input(addr, input);
+        }
+
+        // Server times the player out
+        game.players.get_mut(&addr).unwrap().last_active = Instant::now() - TIMEOUT;
+        game.update_server_dropped();
+        assert!(game.players.is_empty());
+
+        // Client reconnects with its token and resumes from the welcome
+        let new_addr = test_addr(8081);
+        assert_eq!(game.reconnect_player(new_addr, token), Some(id));
+        let welcome = game.build_welcome(&new_addr, true).unwrap();
+        prediction.resume(AuthoritativePos::new(welcome.position), welcome.last_processed);
+        assert_eq!(prediction.next_sequence, 500);
+
+        // The very next input is applied rather than discarded as a duplicate
+        let mut my_pos = AuthoritativePos::new(welcome.position);
+        let input = PlayerInput { dir: Direction::Down, sequence: prediction.next_sequence, timestamp: 0 };
+        prediction.apply_prediction(input, &mut my_pos);
+        game.handle_input(new_addr, input);
+
+        assert_eq!(game.players.get(&new_addr).unwrap().position, my_pos.position());
+        assert_eq!(game.last_processed.get(&id), Some(&500));
+    }
+
+    #[test]
+    fn test_fractional_speeds_move_the_same_on_server_and_client() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+        let directions = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+        for speed in [0.75, 2.5, 3.3, 7.25] {
+            let mut game = Game::new();
+            let addr = test_addr(8080);
+            game.connect_player(addr);
+            game.players.get_mut(&addr).unwrap().speed = speed;
+            let mut prediction = PredictionState::new(AuthoritativePos::new(game.players[&addr].position));
+            prediction.speed = speed;
+            let mut predicted = prediction.last_confirmed_position;
+
+            // Long runs in one direction reach the walls, where neither side may bank movement. The server
+            // is a few inputs behind, and every snapshot reconciles the prediction.
+            let (mut inputs, mut applied) = (Vec::new(), 0);
+            for round in 0..300 {
+                let dir = directions[rng.random_range(0..directions.len())];
+                for _ in 0..rng.random_range(1..40) {
+                    inputs.push(prediction.predict_input(dir, 0, &mut predicted));
+                }
+                let behind = rng.random_range(0..8).min(inputs.len() - applied);
+                for input in &inputs[applied..inputs.len() - behind] {
+                    game.handle_input(addr, *input);
+                }
+                applied = inputs.len() - behind;
+
+                // Replaying from the server position and fraction ends where the prediction did
+                let newest = inputs[..applied].last().map(|input: &PlayerInput| input.sequence);
+                if let Some(newest) = newest.filter(|newest| *newest > prediction.last_confirmed_sequence) {
+                    prediction.reconcile(AuthoritativePos::new(game.players[&addr].position), newest, round as f64 * 0.01);
+                    assert_eq!(prediction.last_confirmed_accumulator, game.players[&addr].accumulator, "speed {}", speed);
+                    let mut replayed = predicted;
+                    prediction.reapply_pending_inputs(&mut replayed);
+                    assert_eq!(replayed, predicted, "speed {}", speed);
+                }
+            }
+
+            for input in &inputs[applied..] {
+                game.handle_input(addr, *input);
+            }
+            assert_eq!(game.players[&addr].position, predicted.position(), "speed {}", speed);
+            assert_eq!(game.players[&addr].accumulator, prediction.accumulator, "speed {}", speed);
+        }
+    }
+
+    #[test]
+    fn test_update_server_dropped() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+
+        let id = game.connect_player(addr);
+
+        // Manually set last_active to be longer than timeout
+        {
+            let player = game.players.get_mut(&addr).unwrap();
+            player.last_active = Instant::now() - TIMEOUT - Duration::from_secs(1);
+        }
+
+        // Check player exists before timeout
+        assert_eq!(game.players.len(), 1);
+
+        // Run timeout check
+        game.update_server_dropped();
+
+        // Player should be removed after timeout
+        assert!(game.players.is_empty());
+        assert!(game.id_to_addr.is_empty());
+        assert!(game.addr_to_id.is_empty());
+
+        // The player is told once why it was removed
+        assert_eq!(game.take_removed(), vec![(addr, id, DisconnectReason::TimedOut)]);
+        assert!(game.take_removed().is_empty());
+    }
+
+    #[test]
+    fn test_timestamps_in_the_future_count_as_now() {
+        let mut game = Game::new();
+        let (addr, left_addr) = (test_addr(8080), test_addr(8081));
+        game.connect_player(addr);
+        game.connect_player(left_addr);
+        let token = game.build_welcome(&left_addr, false).unwrap().session_token;
+        game.players.get_mut(&left_addr).unwrap().last_active = Instant::now() - TIMEOUT;
+        game.update_server_dropped();
+
+        // Activity and a disconnect stamped ahead of the clock, as after a suspend, neither time out nor panic
+        game.players.get_mut(&addr).unwrap().last_active = Instant::now() + Duration::from_secs(3600);
+        game.disconnected.get_mut(&token).unwrap().disconnected_at = Instant::now() + Duration::from_secs(3600);
+        game.update_server_dropped();
+        assert!(game.players.contains_key(&addr));
+        assert!(game.disconnected.contains_key(&token));
+    }
+
+    #[test]
+    fn test_server_ping_measures_rtt() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let other = test_addr(8081);
+        let id = game.connect_player(addr);
+        game.connect_player(other);
+
+        // Nobody has been measured yet
+        assert!(game.build_snapshot().ping_ms.is_empty());
+
+        let start = Instant::now();
+        let pings = game.start_server_pings(start);
+        assert_eq!(pings.len(), 2);
+
+        // Only the player that answered gets a ping
+        let (_, nonce) = pings.iter().find(|(ping_addr, _)| *ping_addr == addr).unwrap();
+        game.handle_server_pong(addr, *nonce, start + Duration::from_millis(42));
+
+        let snapshot = game.build_snapshot();
+        assert_eq!(snapshot.ping_ms.len(), 1);
+        assert_eq!(snapshot.ping_ms.get(&id), Some(&42));
+    }
+
+    #[test]
+    fn test_snapshot_id_follows_ticks() {
+        let mut game = Game::new();
+        assert_eq!(game.build_snapshot().snapshot_id, 0);
+
+        game.advance_tick();
+        assert_eq!(game.advance_tick(), 2);
+
+        // Snapshots built within the same tick share the id
+        let snapshot = game.build_snapshot();
+        assert_eq!(snapshot.snapshot_id, 2);
+        assert_eq!(game.build_snapshot().snapshot_id, 2);
+        assert_eq!(snapshot.tick_interval_ms, BROADCAST_INTERVAL.as_millis() as u32);
+
+        // A slower tick rate of the server config is stamped on the snapshots and the tick timeline
+        game.set_tick_interval(Duration::from_millis(33));
+        assert_eq!(game.build_snapshot().tick_interval_ms, 33);
+        assert_eq!(game.tick_time_ms(game.last_tick_at), 66);
+    }
+
+    #[test]
+    fn test_reset_match_keeps_best_score() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+        let player = game.players.get_mut(&addr).unwrap();
+        player.score = 4;
+        player.best_score = 4;
+        player.total_pellets = 6;
+
+        game.reset_match(Instant::now());
+
+        let player = game.players.get(&addr).unwrap();
+        assert_eq!((player.score, player.best_score, player.total_pellets), (0, 4, 6));
+    }
+
+    #[test]
+    fn test_saved_record_is_restored_for_returning_player() {
+        let mut game = Game::new();
+        let token = Uuid::new_v4();
+        game.set_saved_records(HashMap::from([(token, PlayerRecord { best_score: 7, total_pellets: 30, name: String::new() })]));
+
+        // The token is unknown to this run, so the player connects fresh and claims the record
+        let addr = test_addr(8080);
+        assert_eq!(game.reconnect_player(addr, token), None);
+        game.connect_player(addr);
+        assert!(game.restore_saved_record(&addr, token));
+
+        let player = game.players.get(&addr).unwrap();
+        assert_eq!(player.session_token, token);
+        assert_eq!((player.best_score, player.total_pellets), (7, 30));
+        assert_eq!(game.build_welcome(&addr, false).unwrap().session_token, token);
+
+        // Unknown tokens keep the fresh session
+        let other = test_addr(8081);
+        game.connect_player(other);
+        assert!(!game.restore_saved_record(&other, Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_saved_record_of_a_token_in_use_is_refused() {
+        let mut game = Game::new();
+        let holder = test_addr(8080);
+        game.connect_player(holder);
+        let token = game.players[&holder].session_token;
+        game.set_saved_records(HashMap::from([(token, PlayerRecord { best_score: 7, total_pellets: 30, name: String::new() })]));
+
+        // Another player claiming the token of an active session keeps its own
+        let other = test_addr(8081);
+        game.connect_player(other);
+        let own_token = game.players[&other].session_token;
+        assert!(!game.restore_saved_record(&other, token));
+        assert_eq!(game.players[&other].session_token, own_token);
+        assert_eq!(game.players[&other].best_score, 0);
+
+        // The same once the holder timed out and its session can still be resumed
+        game.disconnect_player(&holder);
+        assert!(!game.restore_saved_record(&other, token));
+        assert_eq!(game.players[&other].session_token, own_token);
+        assert!(game.reconnect_player(test_addr(8082), token).is_some());
+        assert_eq!(game.players.values().filter(|player| player.session_token == token).count(), 1);
+    }
+
+    #[test]
+    fn test_saved_record_is_matched_by_name() {
+        let mut game = Game::new();
+        let (lost, held) = (Uuid::new_v4(), Uuid::new_v4());
+        game.set_saved_records(HashMap::from([
+            (lost, PlayerRecord { best_score: 7, total_pellets: 30, name: "Kari".to_string() }),
+            (Uuid::new_v4(), PlayerRecord { best_score: 3, total_pellets: 40, name: "Kari".to_string() }),
+            (held, PlayerRecord { best_score: 99, total_pellets: 99, name: "Ola".to_string() }),
+        ]));
+
+        // The best of the records under the name, the player keeps its fresh token
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+        let token = game.players[&addr].session_token;
+        game.set_profile(&addr, "Kari", PlayerShape::Square);
+        assert!(game.restore_saved_record_by_name(&addr));
+        let player = &game.players[&addr];
+        assert_eq!((player.best_score, player.total_pellets, player.session_token), (7, 40, token));
+
+        // A record whose session is active is not handed to a namesake, nor is anything to the unnamed
+        let holder = test_addr(8081);
+        game.connect_player(holder);
+        assert!(game.restore_saved_record(&holder, held));
+        let namesake = test_addr(8082);
+        game.connect_player(namesake);
+        game.set_profile(&namesake, "Ola", PlayerShape::Square);
+        assert!(!game.restore_saved_record_by_name(&namesake));
+        let unnamed = test_addr(8083);
+        game.connect_player(unnamed);
+        assert!(!game.restore_saved_record_by_name(&unnamed));
+    }
+
+    #[test]
+    fn test_player_records_include_saved_and_disconnected_players() {
+        let mut game = Game::new();
+        let saved = Uuid::new_v4();
+        game.set_saved_records(HashMap::from([(saved, PlayerRecord { best_score: 2, total_pellets: 2, name: String::new() })]));
+
+        let active = test_addr(8080);
+        let gone = test_addr(8081);
+        game.connect_player(active);
+        game.connect_player(gone);
+        game.players.get_mut(&gone).unwrap().best_score = 5;
+        let gone_token = game.players.get(&gone).unwrap().session_token;
+        game.disconnect_player(&gone);
+
+        let records = game.player_records();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records.get(&saved).map(|r| r.best_score), Some(2));
+        assert_eq!(records.get(&gone_token).map(|r| r.best_score), Some(5));
+    }
+
+    #[test]
+    fn test_invariants_hold_through_normal_use() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let other = test_addr(8081);
+        game.connect_player(addr);
+        game.connect_player(other);
+        game.handle_input(addr, PlayerInput { dir: Direction::Up, sequence: 0, timestamp: 0 });
+
+        let token = game.players.get(&addr).unwrap().session_token;
+        game.disconnect_player(&addr);
+        game.reconnect_player(test_addr(8082), token);
+        game.reset_match(Instant::now());
+
+        assert!(game.check_invariants().is_empty(), "{:?}", game.check_invariants());
+    }
+
+    #[test]
+    fn test_broken_id_maps_are_detected() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+
+        // The reverse entry points to another address
+        game.addr_to_id.remove(&addr);
+        game.addr_to_id.insert(test_addr(9000), id);
+
+        let violations = game.check_invariants();
+        assert!(violations.iter().any(|v| v.contains("id_to_addr maps")));
+        assert!(violations.iter().any(|v| v.contains("addr_to_id maps")));
+        assert!(violations.iter().any(|v| v.contains("without a player")));
+        assert!(violations.iter().any(|v| v.contains("missing from addr_to_id")));
+    }
+
+    #[test]
+    fn test_unknown_last_processed_is_detected() {
+        let mut game = Game::new();
+        game.connect_player(test_addr(8080));
+        game.last_processed.insert(Uuid::new_v4(), 3);
+
+        let violations = game.check_invariants();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("last_processed has unknown id"));
+    }
+
+    #[test]
+    fn test_player_off_board_is_detected() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+
+        game.players.get_mut(&addr).unwrap().position = Position { x: PLAYER_SIZE, y: BOARD_HEIGHT - PLAYER_SIZE - TOOL_BAR_HEIGHT };
+        assert!(game.check_invariants().is_empty());
+
+        game.players.get_mut(&addr).unwrap().position = Position { x: PLAYER_SIZE - 1, y: 200 };
+        let violations = game.check_invariants();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("off the board"));
+    }
+
+    #[test]
+    fn test_position_writes_are_validated() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+        game.take_events();
+
+        // A step of an input may move PLAYER_SPEED along an axis, not more
+        assert_eq!(game.set_position_validated(addr, Position { x: 200 + PLAYER_SPEED, y: 200 }, MoveOrigin::Input { sequence: 1 }), Ok(()));
+        let too_far = Position { x: 200 + PLAYER_SPEED * 3, y: 200 };
+        assert_eq!(
+            game.set_position_validated(addr, too_far, MoveOrigin::Input { sequence: 2 }),
+            Err(PositionRejection::TooFar { distance: PLAYER_SPEED * 2, max: PLAYER_SPEED }),
+        );
+        assert_eq!(game.players[&addr].position, Position { x: 200 + PLAYER_SPEED, y: 200 });
+
+        // One player can't push itself, a knockback is limited to a single push
+        let pushed_far = Position { x: 200 + PLAYER_SPEED + KNOCKBACK_DISTANCE + 1, y: 200 };
+        assert!(matches!(game.set_position_validated(addr, pushed_far, MoveOrigin::Knockback), Err(PositionRejection::TooFar { .. })));
+
+        // Nothing may leave the board, teleports neither
+        for origin in [MoveOrigin::Input { sequence: 3 }, MoveOrigin::MatchReset, MoveOrigin::Teleport] {
+            let off_board = Position { x: 200 + PLAYER_SPEED, y: PLAYER_SIZE - 1 };
+            assert_eq!(game.set_position_validated(addr, off_board, origin), Err(PositionRejection::OffBoard), "{:?}", origin);
+        }
+        assert_eq!(game.set_position_validated(test_addr(9999), Position { x: 200, y: 200 }, MoveOrigin::Teleport), Err(PositionRejection::UnknownPlayer));
+
+        // Every refused write is reported, the history only has the accepted ones
+        let events = game.take_events();
+        assert_eq!(events.len(), 5);
+        assert!(events.iter().all(|event| matches!(event, GameEvent::PositionRejected { id: Some(event_id), .. } if *event_id == id)));
+        assert_eq!(game.players[&addr].position_history.len(), 3);
+    }
+
+    #[test]
+    fn test_debug_teleports_need_to_be_turned_on() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+        let before = game.players[&addr].position;
+        let target = Position { x: 300, y: 250 };
+
+        assert_eq!(game.debug_teleport(addr, target), Err(PositionRejection::DebugTeleportsOff));
+        assert_eq!(game.players[&addr].position, before);
+        assert!(matches!(game.take_events()[..], [GameEvent::PositionRejected { rejection: PositionRejection::DebugTeleportsOff, .. }]));
+
+        game.set_debug_teleports(true);
+        assert_eq!(game.debug_teleport(addr, target), Ok(()));
+        assert_eq!(game.players[&addr].position, target);
+        assert!(matches!(game.take_events()[..], [GameEvent::Teleported { reason: PositionChange::Debug, .. }]));
+    }
+
+    #[test]
+    fn test_debug_teleport_to_the_ends_of_i32_is_rejected() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+        game.set_debug_teleports(true);
+        let before = game.players[&addr].position;
+
+        // Any i32 can come in a datagram, they are refused without overflowing
+        for (x, y) in [(i32::MIN, 100), (i32::MAX, 100), (100, i32::MIN), (100, i32::MAX), (i32::MIN, i32::MAX)] {
+            assert_eq!(game.debug_teleport(addr, Position { x, y }), Err(PositionRejection::OffBoard));
+        }
+        assert_eq!(game.players[&addr].position, before);
+    }
+
+    #[test]
+    fn test_teleports_are_marked_in_the_history() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        let spawn = game.players[&addr].position;
+        assert!(game.players[&addr].position_history.newest().unwrap().teleport);
+
+        // A debug teleport goes anywhere on the board, is marked and reported
+        let target = Position { x: 300, y: 300 };
+        game.set_position_validated(addr, target, MoveOrigin::Teleport).unwrap();
+        assert_eq!(game.take_events(), vec![GameEvent::Teleported { id: Some(id), from: spawn, to: target, reason: PositionChange::Debug }]);
+
+        // Moves after it aren't teleports and aren't reported
+        game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence: 1, timestamp: 0 });
+        game.handle_input(addr, PlayerInput { dir: Direction::Down, sequence: 2, timestamp: 0 });
+        assert!(game.take_events().is_empty());
+
+        // Lag compensation only sees the entries from the teleport on
+        let player = &game.players[&addr];
+        let since_teleport: Vec<(Position, bool)> = player.history_since_teleport().map(|entry| (entry.position, entry.teleport)).collect();
+        assert_eq!(since_teleport, vec![
+            (target, true),
+            (Position { x: 300 + PLAYER_SPEED, y: 300 }, false),
+            (Position { x: 300 + PLAYER_SPEED, y: 300 + PLAYER_SPEED }, false),
+        ]);
+        assert_eq!(player.position_history.len(), 4);
+    }
+
+    #[test]
+    fn test_unsorted_history_is_detected() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+
+        let history = &mut game.players.get_mut(&addr).unwrap().position_history;
+        let position = Position { x: 200, y: 200 };
+        history.push(PositionSnapshot { position, timestamp: 10, teleport: false, origin: MoveOrigin::Knockback });
+        history.push(PositionSnapshot { position, timestamp: 5, teleport: false, origin: MoveOrigin::Knockback });
+
+        let violations = game.check_invariants();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("not sorted"));
+    }
+
+    #[test]
+    fn test_rewinds_stop_at_the_last_teleport_and_are_counted() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+
+        let player = game.players.get_mut(&addr).unwrap();
+        player.position_history.clear();
+        for (timestamp, x, teleport, origin) in [(1000, 100, false, MoveOrigin::Spawn), (1100, 300, true, MoveOrigin::Teleport), (1200, 310, false, MoveOrigin::Input { sequence: 1 })] {
+            player.position_history.push(PositionSnapshot { position: Position { x, y: 100 }, timestamp, teleport, origin });
+        }
+        player.position = Position { x: 310, y: 100 };
+
+        // The spawn is still in the history, but nothing before the teleport is rewound to
+        assert_eq!(game.position_at(addr, 1150, 1200), Some((Position { x: 305, y: 100 }, LagCompResult::Exact)));
+        assert_eq!(game.position_at(addr, 1050, 1200), Some((Position { x: 300, y: 100 }, LagCompResult::ClampedToOldest { age_ms: 50 })));
+        assert_eq!(game.position_at(test_addr(9999), 1050, 1200), None);
+
+        // A shorter maximum refuses the same rewind
+        game.set_max_rewind(Duration::from_millis(100));
+        assert_eq!(game.position_at(addr, 1050, 1200), Some((Position { x: 310, y: 100 }, LagCompResult::Refused { age_ms: 150 })));
+
+        // Rewinds for lag compensation are counted until the stats take them
+        game.rewind_player(addr, 0);
+        game.rewind_player(addr, 1150);
+        assert_eq!(game.take_lag_comp_counters().total(), 2);
+        assert_eq!(game.take_lag_comp_counters().total(), 0);
+    }
+
+    #[test]
+    fn test_teams_stay_balanced_across_joins_and_leaves() {
+        let mut game = Game::with_teams(2);
+        for port in 0..4 {
+            game.connect_player(test_addr(8000 + port));
+        }
+        assert_eq!(game.team_sizes(), vec![2, 2]);
+
+        // Leaving players are not replaced by moving anyone, the next joins fill the gap
+        let left: Vec<SocketAddr> = game.players.iter()
+            .filter(|(_, player)| player.team == Some(0))
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in &left {
+            game.disconnect_player(addr);
+        }
+        assert_eq!(game.team_sizes(), vec![0, 2]);
+
+        game.connect_player(test_addr(9000));
+        game.connect_player(test_addr(9001));
+        assert_eq!(game.team_sizes(), vec![2, 2]);
+
+        // On a tie the lowest team is picked
+        game.connect_player(test_addr(9002));
+        assert_eq!(game.team_sizes(), vec![3, 2]);
+        assert!(game.check_invariants().is_empty(), "{:?}", game.check_invariants());
+    }
+
+    #[test]
+    fn test_resumed_session_keeps_its_team() {
+        let mut game = Game::with_teams(2);
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+        game.connect_player(test_addr(8081));
+        let player = game.players.get(&addr).unwrap();
+        let (team, token) = (player.team, player.session_token);
+
+        game.disconnect_player(&addr);
+        game.connect_player(test_addr(8082)); // Joins the team that was left
+        game.reconnect_player(test_addr(8083), token);
+
+        assert_eq!(game.players.get(&test_addr(8083)).unwrap().team, team);
+        // The resumed player is not moved, even though its team is now the larger one
+        let team = team.unwrap() as usize;
+        assert_eq!(game.team_sizes()[team], 2);
+        assert_eq!(game.team_sizes()[1 - team], 1);
+    }
+
+    #[test]
+    fn test_spawns_stay_in_team_zones() {
+        let left = SpawnZone::for_team(Some(0), 2);
+        let right = SpawnZone::for_team(Some(1), 2);
+        assert_eq!((left.min_x, left.max_x), (PLAYER_SIZE, BOARD_WIDTH / 2));
+        assert_eq!((right.min_x, right.max_x), (BOARD_WIDTH / 2, BOARD_WIDTH - PLAYER_SIZE));
+
+        let mut game = Game::with_teams(2);
+        for port in 0..20 {
+            game.connect_player(test_addr(8000 + port));
+        }
+        let in_zone = |game: &Game| game.players.values()
+            .all(|player| SpawnZone::for_team(player.team, 2).contains(player.position) && is_on_board(player.position));
+        assert!(in_zone(&game));
+
+        // A new match respawns every player in the zone of its team
+        game.reset_match(Instant::now());
+        assert!(in_zone(&game));
+    }
+
+    #[test]
+    fn test_team_colors_come_from_team_palettes() {
+        let mut game = Game::with_teams(2);
+        for port in 0..10 {
+            game.connect_player(test_addr(8000 + port));
+        }
+        // Each team has five players for four team colors, the fifth gets a generated color
+        for team in 0..2 {
+            let colors: Vec<u32> = game.players.values().filter(|player| player.team == Some(team)).map(|player| player.color).collect();
+            let from_palette = colors.iter().filter(|color| palette::TEAM_PALETTES[team as usize].contains(&palette::unpack(**color))).count();
+            assert_eq!((colors.len(), from_palette), (5, 4));
+        }
+        let distinct: std::collections::HashSet<u32> = game.players.values().map(|player| player.color).collect();
+        assert_eq!(distinct.len(), 10);
+
+        let snapshot = game.build_snapshot();
+        assert_eq!(snapshot.teams.len(), 10);
+        for (id, _, _) in &snapshot.players {
+            let addr = game.id_to_addr[id];
+            assert_eq!(snapshot.teams.get(id).copied(), game.players[&addr].team);
+        }
+    }
+
+    #[test]
+    fn test_no_teams_without_team_mode() {
+        let mut game = Game::new();
+        for port in 0..6 {
+            game.connect_player(test_addr(8000 + port));
+        }
+        game.reset_match(Instant::now());
+
+        assert_eq!(game.team_count(), 0);
+        assert!(game.team_sizes().is_empty());
+        assert!(game.players.values().all(|player| player.team.is_none()));
+        assert!(game.build_snapshot().teams.is_empty());
+        assert_eq!(SpawnZone::for_team(None, 0), SpawnZone::whole_board());
+
+        // A player with a team outside team mode breaks an invariant
+        game.players.values_mut().next().unwrap().team = Some(0);
+        assert_eq!(game.check_invariants().len(), 1);
+    }
+
+    #[test]
+    fn test_match_phase_transitions() {
+        let mut game = Game::new();
+        let start = Instant::now();
+        let duration = Duration::from_secs(60);
+        game.reset_match(start); // Pins the match start to the injected time
+        game.phase = GamePhase::Warmup;
+
+        // The warmup is the first match, it ends after the match duration
+        assert_eq!(game.update_phase(start + Duration::from_secs(59), duration), None);
+        assert_eq!(game.phase(), GamePhase::Warmup);
+        let ended = start + duration;
+        assert_eq!(game.update_phase(ended, duration), None);
+        assert_eq!(game.phase(), GamePhase::Ended { next_countdown_at: ended + MATCH_END_PAUSE });
+
+        // After the pause the next match is reset and counts down
+        assert_eq!(game.update_phase(ended + MATCH_END_PAUSE - Duration::from_millis(1), duration), None);
+        let reset_at = ended + MATCH_END_PAUSE;
+        assert!(game.update_phase(reset_at, duration).is_some());
+        assert_eq!(game.match_number(), 2);
+        assert_eq!(game.phase(), GamePhase::Countdown { ends_at: reset_at + MATCH_COUNTDOWN });
+        assert_eq!(
+            game.phase().to_match_phase(reset_at + Duration::from_millis(1500)),
+            MatchPhase::Countdown { remaining_ms: 3500 },
+        );
+
+        // The match starts when the countdown is over and lasts the full duration from there
+        let active_at = reset_at + MATCH_COUNTDOWN;
+        assert_eq!(game.update_phase(active_at, duration), None);
+        assert_eq!(game.phase(), GamePhase::Active);
+        assert!(!game.is_match_over(active_at + duration - Duration::from_secs(1), duration));
+        game.update_phase(active_at + duration, duration);
+        assert!(matches!(game.phase(), GamePhase::Ended { .. }));
+        assert_eq!(game.phase().to_match_phase(active_at + duration), MatchPhase::Ended);
+    }
+
+    #[test]
+    fn test_frozen_phases_acknowledge_inputs_without_moving() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        game.reset_match(Instant::now());
+        let spawn = game.players[&addr].position;
+        let input = |sequence| PlayerInput { dir: Direction::Right, sequence, timestamp: 0 };
+
+        // During the countdown inputs are acknowledged but don't move the player
+        let before = game.players[&addr].last_active;
+        game.handle_input(addr, input(0));
+        game.handle_input(addr, input(1));
+        assert_eq!(game.players[&addr].position, spawn);
+        assert_eq!(game.last_processed.get(&id), Some(&1));
+        assert!(game.players[&addr].last_active >= before);
+        assert!(matches!(game.build_snapshot().match_phase, MatchPhase::Countdown { .. }));
+
+        // Same after the match ended
+        game.phase = GamePhase::Ended { next_countdown_at: Instant::now() };
+        game.handle_input(addr, input(2));
+        assert_eq!(game.players[&addr].position, spawn);
+        assert_eq!(game.last_processed.get(&id), Some(&2));
+
+        // Once the match is active inputs move the player again
+        game.phase = GamePhase::Active;
+        game.handle_input(addr, input(3));
+        assert_ne!(game.players[&addr].position, spawn);
+        assert_eq!(game.build_snapshot().match_phase, MatchPhase::Active);
+    }
+
+    #[test]
+    fn test_overlapping_players_are_knocked_apart() {
+        let mut game = Game::new();
+        let (addr, other_addr) = (test_addr(8080), test_addr(8081));
+        let id = game.connect_player(addr);
+        let other_id = game.connect_player(other_addr);
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+        game.set_position_validated(other_addr, Position { x: 210, y: 204 }, MoveOrigin::Teleport).unwrap();
+
+        // Pushed apart along x, where their centers are furthest apart
+        assert_eq!(game.apply_knockback(), 2);
+        assert_eq!(game.players[&addr].position, Position { x: 200 - KNOCKBACK_DISTANCE, y: 200 });
+        assert_eq!(game.players[&other_addr].position, Position { x: 210 + KNOCKBACK_DISTANCE, y: 204 });
+
+        // Pushes between two inputs add up in one impulse
+        game.advance_tick();
+        game.apply_knockback();
+        let impulse = game.build_snapshot().impulses[&id];
+        assert_eq!((impulse.dx, impulse.dy), (-2 * KNOCKBACK_DISTANCE, 0));
+        assert_eq!(game.build_snapshot().impulses[&other_id].dx, 2 * KNOCKBACK_DISTANCE);
+
+        // Players that no longer overlap aren't pushed, the impulse is repeated for a while
+        game.set_position_validated(other_addr, Position { x: 400, y: 400 }, MoveOrigin::Teleport).unwrap();
+        assert_eq!(game.apply_knockback(), 0);
+        for _ in 1..IMPULSE_RESEND_TICKS {
+            game.advance_tick();
+        }
+        assert!(game.build_snapshot().impulses.contains_key(&id));
+        game.advance_tick();
+        assert!(game.build_snapshot().impulses.is_empty());
+
+        // Against the edge of the board only the other player moves
+        game.set_position_validated(addr, Position { x: PLAYER_SIZE, y: 300 }, MoveOrigin::Teleport).unwrap();
+        game.set_position_validated(other_addr, Position { x: PLAYER_SIZE, y: 300 }, MoveOrigin::Teleport).unwrap();
+        assert_eq!(game.apply_knockback(), 1);
+        let positions = [game.players[&addr].position.x, game.players[&other_addr].position.x];
+        assert!(positions.contains(&PLAYER_SIZE) && positions.contains(&(PLAYER_SIZE + KNOCKBACK_DISTANCE)));
+
+        // Frozen players aren't pushed
+        game.reset_match(Instant::now());
+        let position = game.players[&addr].position;
+        game.set_position_validated(other_addr, position, MoveOrigin::Teleport).unwrap();
+        assert_eq!(game.apply_knockback(), 0);
+        assert!(game.build_snapshot().impulses.is_empty());
+    }
+
+    #[test]
+    fn test_prediction_follows_knockback_between_inputs() {
+        let mut game = Game::new();
+        let (addr, other_addr) = (test_addr(8080), test_addr(8081));
+        let id = game.connect_player(addr);
+        game.connect_player(other_addr);
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+        game.set_position_validated(other_addr, Position { x: 600, y: 600 }, MoveOrigin::Teleport).unwrap();
+        let mut prediction = PredictionState::new(AuthoritativePos::new(Position { x: 200, y: 200 }));
+        let mut my_pos = prediction.last_confirmed_position;
+
+        // The client sends inputs 0 to 3, the server applies the first two and confirms them
+        let inputs: Vec<PlayerInput> = (0..4).map(|sequence| PlayerInput { dir: Direction::Down, sequence, timestamp: 0 }).collect();
+        for input in &inputs {
+            prediction.pending_inputs.push_back((input.sequence, *input));
+            prediction.apply_prediction(*input, &mut my_pos);
+        }
+        game.handle_input(addr, inputs[0]);
+        game.handle_input(addr, inputs[1]);
+        let snapshot = game.build_snapshot();
+        prediction.reconcile_with_impulse(AuthoritativePos::new(game.players[&addr].position), snapshot.last_processed[&id], None, 0.0);
+
+        // The other player runs into it before inputs 2 and 3 arrive
+        game.set_position_validated(other_addr, Position { x: 205, y: 195 }, MoveOrigin::Teleport).unwrap();
+        game.advance_tick();
+        game.apply_knockback();
+        let snapshot = game.build_snapshot();
+        let server_position = AuthoritativePos::new(game.players[&addr].position);
+        prediction.reconcile_with_impulse(server_position, snapshot.last_processed[&id], snapshot.impulses.get(&id).copied(), 0.0);
+        prediction.reapply_pending_inputs(&mut my_pos);
+
+        // The replayed inputs start from the pushed position and end where the server ends up
+        assert_eq!(prediction.get_prediction_error(server_position), 0.0);
+        game.set_position_validated(other_addr, Position { x: 600, y: 600 }, MoveOrigin::Teleport).unwrap();
+        game.handle_input(addr, inputs[2]);
+        game.handle_input(addr, inputs[3]);
+        assert_eq!(game.players[&addr].position, my_pos.position());
+    }
+
+    // Helper function to create a game with input coalescing and one player at 200, 200 whose ticks start at the returned time
+    fn coalescing_game() -> (Game, SocketAddr, Uuid, Instant) {
+        let mut game = Game::new();
+        game.set_input_coalescing(true);
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        game.set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+        let start = Instant::now();
+        game.last_tick_at = start;
+        (game, addr, id, start)
+    }
+
+    #[test]
+    fn test_coalescing_spreads_a_burst_over_ticks() {
+        let (mut game, addr, id, start) = coalescing_game();
+        for sequence in 0..4 {
+            game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence, timestamp: 0 });
+        }
+
+        // Nothing moves or is acknowledged on arrival, then one input is applied per tick
+        assert_eq!(game.players[&addr].position.x, 200);
+        assert_eq!(game.last_processed.get(&id), None);
+        let mut applied = Vec::new();
+        for tick in 1..=5 {
+            game.advance_tick_at(start + COALESCED_INPUT_INTERVAL * tick);
+            applied.push((game.players[&addr].position.x, game.last_processed.get(&id).copied()));
+        }
+        assert_eq!(applied, vec![(205, Some(0)), (210, Some(1)), (215, Some(2)), (220, Some(3)), (220, Some(3))]);
+
+        // The idle ticks didn't bank credit for the next burst
+        game.handle_input(addr, PlayerInput { dir: Direction::Left, sequence: 4, timestamp: 0 });
+        game.handle_input(addr, PlayerInput { dir: Direction::Left, sequence: 5, timestamp: 0 });
+        game.advance_tick_at(start + COALESCED_INPUT_INTERVAL * 6);
+        assert_eq!(game.last_processed.get(&id), Some(&4));
+    }
+
+    #[test]
+    fn test_coalesced_queue_drains_at_the_rate_of_real_time() {
+        let (mut game, addr, id, start) = coalescing_game();
+        send_sequences(&mut game, addr, &[0, 1, 2, 3, 4, 5]);
+
+        // Ticks twice as long as an input interval apply two inputs each
+        let mut acknowledged = Vec::new();
+        for tick in 1..=3 {
+            game.advance_tick_at(start + COALESCED_INPUT_INTERVAL * 2 * tick);
+            acknowledged.push(game.last_processed[&id]);
+        }
+        assert_eq!(acknowledged, vec![1, 3, 5]);
+        assert!(game.players[&addr].queued_inputs.is_empty());
+
+        // A queue longer than MAX_COALESCED_INPUTS is cut back to it at once
+        send_sequences(&mut game, addr, &(6..16).collect::<Vec<u32>>());
+        game.advance_tick_at(start + COALESCED_INPUT_INTERVAL * 7);
+        assert_eq!(game.players[&addr].queued_inputs.len(), MAX_COALESCED_INPUTS);
+        assert_eq!(game.last_processed[&id], 9);
+
+        // A disconnect drops what is still queued
+        game.disconnect_player(&addr);
+        assert!(game.disconnected.values().all(|player| player.state.queued_inputs.is_empty()));
+    }
+
+    #[test]
+    fn test_coalesced_inputs_reconcile_exactly() {
+        let (mut game, addr, id, start) = coalescing_game();
+        let mut prediction = PredictionState::new(AuthoritativePos::new(Position { x: 200, y: 200 }));
+        let mut my_pos = prediction.last_confirmed_position;
+
+        // A burst of five inputs the client predicted right away
+        let dirs = [Direction::Right, Direction::Right, Direction::Down, Direction::Left, Direction::Down];
+        for (sequence, dir) in dirs.into_iter().enumerate() {
+            let input = PlayerInput { dir, sequence: sequence as u32 + 1, timestamp: 0 };
+            prediction.pending_inputs.push_back((input.sequence, input));
+            prediction.apply_prediction(input, &mut my_pos);
+            game.handle_input(addr, input);
+        }
+        let predicted = my_pos;
+
+        // Each snapshot acknowledges exactly the inputs in the position it carries, so replaying the rest lands on the prediction
+        for tick in 1..=5 {
+            game.advance_tick_at(start + COALESCED_INPUT_INTERVAL * tick);
+            let snapshot = game.build_snapshot();
+            let server_position = AuthoritativePos::new(game.players[&addr].position);
+            prediction.reconcile_with_impulse(server_position, snapshot.last_processed[&id], None, 0.0);
+            assert_eq!(prediction.pending_inputs.len(), 5 - tick as usize);
+            prediction.reapply_pending_inputs(&mut my_pos);
+            assert_eq!(my_pos, predicted);
+        }
+        assert_eq!(game.players[&addr].position, predicted.position());
+    }
+
+    #[test]
+    fn test_history_tags_the_origin_of_every_move() {
+        let mut game = Game::new();
+        let (addr, other_addr) = (test_addr(8080), test_addr(8081));
+        let id = game.connect_player(addr);
+        game.connect_player(other_addr);
+        game.set_debug_teleports(true);
+        game.debug_teleport(addr, Position { x: 200, y: 200 }).unwrap();
+        game.debug_teleport(other_addr, Position { x: 600, y: 400 }).unwrap();
+        game.advance_tick();
+
+        // An input, one that arrives after it, a push, a coalesced input and a new match, a tick after the placement
+        let input = |sequence| PlayerInput { dir: Direction::Right, sequence, timestamp: 0 };
+        game.handle_input(addr, input(2));
+        game.handle_input(addr, input(1));
+        game.debug_teleport(other_addr, Position { x: 200 + PLAYER_SPEED * 2 + 4, y: 200 }).unwrap();
+        game.apply_knockback();
+        game.set_input_coalescing(true);
+        game.handle_input(addr, input(3));
+        game.advance_tick_at(game.last_tick_at + COALESCED_INPUT_INTERVAL);
+        game.reset_match(Instant::now());
+
+        let history = &game.players[&addr].position_history;
+        let origins: Vec<MoveOrigin> = history.iter().map(|entry| entry.origin).collect();
+        assert_eq!(origins, vec![
+            MoveOrigin::Spawn,
+            MoveOrigin::Teleport,
+            MoveOrigin::Input { sequence: 2 },
+            MoveOrigin::LateInput { sequence: 1 },
+            MoveOrigin::Knockback,
+            MoveOrigin::CoalescedInput { sequence: 3 },
+            MoveOrigin::MatchReset,
+        ]);
+        assert!(history.iter().zip(history.iter().skip(1)).all(|(older, newer)| older.timestamp <= newer.timestamp));
+
+        // The report lists the moves from the time given on, with how far each went
+        let since = history[2].timestamp;
+        let report = game.history_report(addr, since).unwrap();
+        assert!(report.starts_with(&format!("Position history of player {} at {} since {} ms", id, addr, since)));
+        assert!(report.contains(&format!("({}, 200) by ({:+}, +0)  input #2", 200 + PLAYER_SPEED, PLAYER_SPEED)));
+        assert!(report.contains(&format!("by ({:+}, +0)  knockback", -KNOCKBACK_DISTANCE)));
+        assert!(report.contains("late input #1") && report.contains("coalesced input #3") && report.trim_end().ends_with("match reset"));
+        assert!(!report.contains("spawn"));
+        assert_eq!(game.history_report(test_addr(9999), 0), None);
+        assert_eq!(game.addr_of(&id), Some(addr));
+    }
+
+    #[test]
+    fn test_movement_stats_count_the_applied_movement() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        let id = game.connect_player(addr);
+        let start = game.players[&addr].position;
+
+        // Far more inputs than it takes to reach the wall, only the pixels actually moved count
+        for sequence in 1..=250 {
+            game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence, timestamp: 0 });
+        }
+        let now = Instant::now();
+        game.last_tick_at = now;
+        game.advance_tick_at(now + Duration::from_secs(2));
+        let stats = MovementStats { distance: (BOARD_WIDTH - PLAYER_SIZE - start.x) as u64, inputs: 250, connected_ms: 2000 };
+        assert_eq!(game.players[&addr].movement_stats(), stats);
+
+        // Snapshots only carry the stats every MOVEMENT_STATS_TICKS ticks
+        assert!(game.build_snapshot().movement_stats.is_empty());
+        game.snapshot_id = MOVEMENT_STATS_TICKS;
+        assert_eq!(game.build_snapshot().movement_stats.get(&id), Some(&stats));
+        assert_eq!(stats.inputs_per_second(), Some(125.0));
+
+        // The stats come back with the session, the time it was gone isn't counted
+        let token = game.build_welcome(&addr, false).unwrap().session_token;
+        game.disconnect_player(&addr);
+        game.advance_tick_at(now + Duration::from_secs(5));
+        let new_addr = test_addr(8081);
+        assert_eq!(game.reconnect_player(new_addr, token), Some(id));
+        assert_eq!(game.players[&new_addr].movement_stats(), stats);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Game invariants violated")]
+    fn test_mutation_panics_on_violation_in_debug_builds() {
+        let mut game = Game::new();
+        let addr = test_addr(8080);
+        game.connect_player(addr);
+        game.last_processed.insert(Uuid::new_v4(), 3);
+
+        // Any mutating method runs the check
+        game.connect_player(test_addr(8081));
+    }
+}
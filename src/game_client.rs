@@ -0,0 +1,591 @@
+use crate::build_info::BuildInfo;
+use crate::client_events::{ClientEvent, ConnectionState};
+use crate::client_world::ClientWorld;
+use crate::constants::{CLIENT_PAUSE_RESYNC, OFFLINE_SERVER_LABEL, PING_INTERVAL, SNAPSHOT_BURST_THRESHOLD, WARNING_EVALUATION_INTERVAL};
+use crate::input_dispatch::{InputDispatcher, InputIntent};
+use crate::local_server::LocalServerHandle;
+use crate::movement::TunablesCheck;
+use crate::network::NetworkClient;
+use crate::positions::{AuthoritativePos, DisplayPos};
+use crate::prediction::PredictionConfig;
+use crate::profile_scope;
+use crate::types::{ClientMessage, Direction, PlayerInput, PlayerShape};
+use crate::warnings::{ActiveWarning, WarningEngine, WarningThresholds};
+
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Who the client connects as, and to which room
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientProfile {
+    pub name: String,             // Display name, empty for none
+    pub shape: PlayerShape,
+    pub instance: u64,            // Hashed client instance id, to join client and server metrics
+    pub room: String,             // Room to join, the default room if empty
+    pub password: Option<String>, // Password of a locked room
+}
+
+/// The glue between the network client and the client world every client needs: connecting, sending
+/// predicted moves, pinging, and applying what the server sends. Has nothing to do with drawing or the
+/// keyboard, so the windowed client, the headless analysis client and third party clients share it.
+pub struct GameClient {
+    pub net: NetworkClient,
+    pub world: ClientWorld,
+    dispatcher: InputDispatcher, // Turns the intents of the keyboard or a script into predicted and sent inputs
+    events: Vec<ClientEvent>, // Since the last call to events
+    connection: ConnectionState,
+    warnings: WarningEngine,  // Live metrics checked against the thresholds while connected
+    current_time: f64,        // Local time of the latest update in seconds, moves are stamped with it
+    updated: bool,            // Updated since it was created or reset, so the time since the latest update means something
+    render_time: f64,         // Render time of the latest update, remote players are placed at it
+    last_ping: Option<f64>,   // Local time of the latest ping
+    last_warning_check: Option<f64>, // Local time of the latest warning evaluation
+}
+
+/// Implementation of the GameClient
+impl GameClient {
+    /// Creates a client that talks to the server through net, with an empty world
+    pub fn new(net: NetworkClient) -> Self {
+        Self {
+            net,
+            world: ClientWorld::new(),
+            dispatcher: InputDispatcher::new(),
+            events: Vec::new(),
+            connection: ConnectionState::Disconnected,
+            warnings: WarningEngine::new(WarningThresholds::default()),
+            current_time: 0.0,
+            updated: false,
+            render_time: 0.0,
+            last_ping: None,
+            last_warning_check: None,
+        }
+    }
+
+    /// Binds a network client to the server address and sends a connect with the profile. The welcome
+    /// arrives with a later update, as a Joined event.
+    pub fn connect(server_addr: &str, profile: &ClientProfile) -> Result<Self, String> {
+        let net = NetworkClient::builder(server_addr)
+            .build()
+            .map_err(|e| format!("failed to bind a socket: {}", e))?;
+        let mut client = Self::new(net);
+        client.send_connect(profile);
+        Ok(client)
+    }
+
+    /// Connects to an in-process server over a loopback link instead of a socket, like connect otherwise
+    pub fn connect_offline(server: LocalServerHandle, profile: &ClientProfile) -> Result<Self, String> {
+        let net = NetworkClient::builder(OFFLINE_SERVER_LABEL)
+            .local_server(server)
+            .build()
+            .map_err(|e| format!("failed to start the network thread: {}", e))?;
+        let mut client = Self::new(net);
+        client.send_connect(profile);
+        Ok(client)
+    }
+
+    /// Sends a connect with the profile, also to join another room
+    pub fn send_connect(&mut self, profile: &ClientProfile) {
+        self.world.handshake_pending = true;
+        self.set_connection(ConnectionState::Connecting);
+        self.net.send_connect_with_profile(&profile.name, profile.shape, profile.instance, &profile.room, profile.password.as_deref());
+    }
+
+    /// Tells the server the player quits, so it frees the player right away
+    pub fn send_leave(&self) {
+        self.net.send_leave();
+    }
+
+    /// Stops the network thread once it sent what is still queued, waiting at most timeout.
+    /// Returns false if it didn't stop in time.
+    pub fn close(self, timeout: Duration) -> bool {
+        self.net.close(timeout)
+    }
+
+    /// Asks to resume an earlier session, so input sequences continue where the server left off
+    pub fn send_reconnect(&mut self, session_token: Uuid) {
+        self.world.handshake_pending = true;
+        self.set_connection(ConnectionState::Connecting);
+        self.net.send_reconnect(session_token);
+    }
+
+    /// Forgets the world and everything received, keeping the network client and the prediction config
+    pub fn reset(&mut self) {
+        let (strategy, config) = (self.world.interpolated_positions.strategy(), self.world.config);
+        self.world = ClientWorld::new();
+        self.world.interpolated_positions.set_strategy(strategy);
+        self.apply_prediction_config(config);
+        self.dispatcher.clear();
+        self.events.clear();
+        self.connection = ConnectionState::Disconnected;
+        self.warnings.reset();
+        self.updated = false;
+        self.last_ping = None;
+        self.last_warning_check = None;
+    }
+
+    /// Predicts and smooths by the config from now on, kept over resets. Without prediction the local player
+    /// is drawn from the snapshots right away.
+    pub fn set_prediction_config(&mut self, config: PredictionConfig) {
+        self.world.interpolated_positions.set_strategy(config.interpolation);
+        self.apply_prediction_config(config);
+    }
+
+    /// Moves the local player by the intents: each is predicted right away, stamped with the time it was
+    /// sampled at, and sent when the send rate allows. Returns the inputs in sequence order.
+    pub fn dispatch(&mut self, intents: impl IntoIterator<Item = InputIntent>) -> Vec<PlayerInput> {
+        profile_scope!("input");
+        self.dispatcher.push(intents);
+        self.dispatcher.dispatch(&mut self.world, &mut self.net)
+    }
+
+    /// Moves the local player one input in the direction, sampled at the time of the latest update.
+    /// Returns the input.
+    pub fn send_move(&mut self, dir: Direction) -> PlayerInput {
+        self.dispatch([InputIntent { dir, originated_at: self.current_time }])[0]
+    }
+
+    /// Runs the client at a local time in seconds: pings the server every PING_INTERVAL once it knows us,
+    /// sends the inputs that are due, applies everything received, turns the prediction off if it keeps going
+    /// wrong, checks the live metrics against the warning thresholds every WARNING_EVALUATION_INTERVAL while
+    /// connected and forgets players that are gone.
+    /// Render time is the time the world is drawn at, behind the local time in slow motion. An update
+    /// CLIENT_PAUSE_RESYNC or more after the one before starts the timing over first.
+    pub fn update(&mut self, current_time: f64, render_time: f64) {
+        let paused = current_time - self.current_time;
+        if self.updated && paused >= CLIENT_PAUSE_RESYNC {
+            self.world.resync_after_pause();
+            self.events.push(ClientEvent::Resynced { paused });
+        }
+        self.updated = true;
+        let frame_time = paused.max(0.0) as f32;
+        self.current_time = current_time;
+        self.render_time = render_time;
+
+        if self.world.my_id.is_some() && self.last_ping.is_none_or(|sent| current_time - sent >= PING_INTERVAL.as_secs_f64()) {
+            self.net.send_ping((current_time * 1000.0) as u64);
+            self.last_ping = Some(current_time);
+        }
+        self.world.flush_inputs(&mut self.net);
+        self.world.movement_speed.update(self.world.my_pos.position(), frame_time);
+        self.receive();
+        if self.world.check_prediction(current_time) {
+            self.events.push(ClientEvent::PredictionDisabled);
+        }
+        self.evaluate_warnings(current_time);
+        self.world.expire_players(current_time);
+    }
+
+    /// Takes the events since the last call, oldest first
+    pub fn events(&mut self) -> Vec<ClientEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Where the client is with the server
+    pub fn connection(&self) -> ConnectionState {
+        self.connection
+    }
+
+    /// Sets the thresholds the live metrics are warned about at, dropping the warnings shown
+    pub fn set_warning_thresholds(&mut self, thresholds: WarningThresholds) {
+        self.warnings = WarningEngine::new(thresholds);
+    }
+
+    /// The warnings to show
+    pub fn warnings(&self) -> Vec<ActiveWarning> {
+        self.warnings.active()
+    }
+
+    /// Drops the warnings without events, they describe a session that ended
+    pub fn clear_warnings(&mut self) {
+        self.warnings.reset();
+    }
+
+    /// Id the server gave the local player, None before it was welcomed
+    pub fn id(&self) -> Option<Uuid> {
+        self.world.my_id
+    }
+
+    /// Build of this client with its simulation seed, and the build of the server once it welcomed the client
+    pub fn builds(&self) -> (BuildInfo, Option<BuildInfo>) {
+        (BuildInfo::current(Some(self.net.sim_seed())), self.world.server_build.clone())
+    }
+
+    /// Predicted position of the local player
+    pub fn local_position(&self) -> AuthoritativePos {
+        self.world.my_pos
+    }
+
+    /// Where the local player is drawn at the render time of the latest update
+    pub fn local_display_position(&self) -> DisplayPos {
+        self.world.local_display_position(self.world.render_server_time(self.render_time, self.current_time))
+    }
+
+    /// Every other player with where it is drawn at the render time of the latest update, and its packed color
+    pub fn remote_players(&self) -> impl Iterator<Item = (Uuid, DisplayPos, u32)> + '_ {
+        let server_time = self.world.render_server_time(self.render_time, self.current_time);
+        self.world.all_players.iter()
+            .filter(|(id, _)| Some(**id) != self.world.my_id)
+            .map(move |(id, (position, color))| (*id, self.world.remote_position(id, *position, server_time), *color))
+    }
+
+    /// Sets the config of the world, falling back to the snapshots if it doesn't predict
+    fn apply_prediction_config(&mut self, config: PredictionConfig) {
+        self.world.config = config;
+        if !config.predict && self.world.is_predicting() {
+            self.world.fall_back_to_interpolation();
+        }
+    }
+
+    /// Moves the connection on, with an event if it changed. Warnings go with the connection.
+    fn set_connection(&mut self, connection: ConnectionState) {
+        if connection == self.connection {
+            return;
+        }
+        self.connection = connection;
+        if connection == ConnectionState::Disconnected {
+            self.warnings.reset();
+        }
+        self.events.push(ClientEvent::ConnectionStateChanged(connection));
+    }
+
+    /// Checks the live metrics against the warning thresholds once per evaluation interval while connected,
+    /// with an event for every warning that started or ended
+    fn evaluate_warnings(&mut self, current_time: f64) {
+        if self.connection != ConnectionState::Connected {
+            return;
+        }
+        if self.last_warning_check.is_some_and(|checked| current_time - checked < WARNING_EVALUATION_INTERVAL.as_secs_f64()) {
+            return;
+        }
+        self.last_warning_check = Some(current_time);
+
+        let rtt_ms = self.world.my_ping().map(f64::from);
+        let values = self.world.metric_window.take(rtt_ms, Some(self.world.snapshot_loss.loss()));
+        let changes = self.warnings.evaluate(&values);
+        self.events.extend(changes.into_iter().map(ClientEvent::Warning));
+    }
+
+    /// Receives everything the server sent since the last update
+    fn receive(&mut self) {
+        let frame = {
+            profile_scope!("network");
+            self.net.receive_frame(SNAPSHOT_BURST_THRESHOLD)
+        };
+        if let Some(burst) = frame.burst {
+            let tick_interval_ms = frame.snapshots.last().map_or(0, |snapshot| snapshot.tick_interval_ms);
+            self.world.cover_burst(&burst, tick_interval_ms, self.current_time);
+            self.events.push(ClientEvent::BurstCovered(burst));
+        }
+
+        // Handle messages first, so a Welcome is known before the snapshot that follows it
+        for msg in frame.messages {
+            self.handle_message(msg);
+        }
+
+        // Snapshots in tick order
+        for game_state in frame.snapshots {
+            profile_scope!("snapshots");
+            let outcome = self.world.apply_snapshot(game_state, &self.net, self.current_time, self.render_time);
+            self.net.record_snapshot_anomalies(outcome.anomalies, outcome.discarded);
+            self.events.extend(self.world.take_events());
+            if outcome.removed {
+                self.set_connection(ConnectionState::Disconnected);
+                self.events.push(ClientEvent::Removed(None));
+            }
+            self.events.push(ClientEvent::Snapshot(outcome));
+        }
+    }
+
+    /// Handles a message from the server, its events are taken with the others
+    pub fn handle_message(&mut self, msg: ClientMessage) {
+        match msg {
+            // Only ids answering our connect are taken, a stray duplicate mid-session is ignored
+            ClientMessage::PlayerId(id) => {
+                if self.world.adopt_id(id) {
+                    self.set_connection(ConnectionState::Connected);
+                    self.events.push(ClientEvent::Joined { id, session_token: None });
+                }
+            }
+            ClientMessage::Welcome(welcome) => {
+                // Playing with other movement than the server's leaves a prediction error nothing removes
+                let check = self.world.prediction.tunables.check(welcome.tunables_digest, welcome.tunables);
+                if let TunablesCheck::Incompatible { server_digest } = check {
+                    if self.world.handshake_pending {
+                        self.world.handshake_pending = false;
+                        self.set_connection(ConnectionState::Disconnected);
+                        self.events.push(ClientEvent::Incompatible { server_digest });
+                    }
+                    return;
+                }
+                if self.world.adopt_id(welcome.id) {
+                    self.net.set_session(Some(welcome.session_token));
+                    self.world.welcome(&welcome);
+                    if let TunablesCheck::Adopt(tunables) = check {
+                        self.world.prediction.adopt_tunables(tunables);
+                        self.events.push(ClientEvent::TunablesAdopted(tunables));
+                    }
+                    self.set_connection(ConnectionState::Connected);
+                    self.events.push(ClientEvent::Joined { id: welcome.id, session_token: Some(welcome.session_token) });
+                }
+            }
+            ClientMessage::MatchReset { match_number, positions } => {
+                // Respawn at the position assigned by the server
+                self.world.match_number = match_number;
+                if let Some((_, pos)) = positions.iter().find(|(id, _)| Some(*id) == self.world.my_id) {
+                    self.world.reset_for_new_match(*pos);
+                }
+                self.events.push(ClientEvent::MatchStarted { match_number });
+            }
+            ClientMessage::ServerPing(nonce) => self.net.send_server_pong(nonce),
+            ClientMessage::ConnectRejected { reason } => {
+                self.set_connection(ConnectionState::Disconnected);
+                self.events.push(ClientEvent::Rejected(reason));
+            }
+            ClientMessage::Disconnected { id, reason } if Some(id) == self.world.my_id => {
+                self.set_connection(ConnectionState::Disconnected);
+                self.events.push(ClientEvent::Removed(Some(reason)));
+            }
+            msg => self.events.push(ClientEvent::Message(msg)),
+        }
+    }
+}
+
+/// Tests for the GameClient
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_world::SnapshotOutcome;
+    use crate::local_server::LocalServer;
+    use crate::movement::MovementTunables;
+    use crate::rooms::RoomConfig;
+    use crate::server::{create_rooms, run_server, ServerTuning};
+    use crate::server_config::ServerConfig;
+    use crate::transport::Transport;
+    use crate::types::{DisconnectReason, Position, RejectReason, Welcome};
+    use crate::warnings::{Metric, WarningChange};
+
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+    use tokio::net::UdpSocket;
+    use tokio::runtime::Runtime;
+
+    /// A server running in process on a loopback port, stopped with its runtime when dropped
+    struct TestServer {
+        _runtime: Runtime,
+        addr: SocketAddr,
+    }
+
+    /// Implementation of the TestServer
+    impl TestServer {
+        // Helper function to start a server with the default config
+        fn start() -> Self {
+            let runtime = Runtime::new().unwrap();
+            let socket = runtime.block_on(UdpSocket::bind("127.0.0.1:0")).unwrap();
+            let addr = socket.local_addr().unwrap();
+            let (config, tuning) = (ServerConfig::default(), ServerTuning::default());
+            let rooms = create_rooms(&config, RoomConfig::default(), HashMap::new(), tuning);
+            runtime.spawn(run_server(Transport::new(socket, None), rooms, config, tuning, std::future::pending()));
+            Self { _runtime: runtime, addr }
+        }
+    }
+
+    // Helper function to connect a client with the network conditions turned off
+    fn connect(server: &TestServer, name: &str) -> GameClient {
+        let profile = ClientProfile { name: name.to_string(), ..ClientProfile::default() };
+        let client = GameClient::connect(&server.addr.to_string(), &profile).unwrap();
+        client.net.set_conditions(0, 0, 0);
+        client.net.set_duplicate_percent(0);
+        client
+    }
+
+    // Helper function to update the clients every few milliseconds until the check passes, or fail after two seconds
+    fn run_until(clients: &mut [&mut GameClient], started: Instant, mut check: impl FnMut(&mut [&mut GameClient]) -> bool) {
+        while !check(clients) {
+            assert!(started.elapsed() < Duration::from_secs(2), "timed out");
+            std::thread::sleep(Duration::from_millis(5));
+            let now = started.elapsed().as_secs_f64();
+            for client in clients.iter_mut() {
+                client.update(now, now);
+            }
+        }
+    }
+
+    #[test]
+    fn test_connect_is_welcomed_with_a_joined_event() {
+        let server = TestServer::start();
+        let mut client = connect(&server, "alice");
+        let started = Instant::now();
+        let mut events = Vec::new();
+        run_until(&mut [&mut client], started, |clients| {
+            events.extend(clients[0].events());
+            clients[0].id().is_some()
+        });
+
+        let id = client.id().unwrap();
+        assert!(events.iter().any(|event| matches!(event, ClientEvent::Joined { id: joined, session_token: Some(_) } if *joined == id)));
+    }
+
+    #[test]
+    fn test_moves_are_predicted_and_confirmed_by_the_server() {
+        let server = TestServer::start();
+        let mut client = connect(&server, "mover");
+        let started = Instant::now();
+        run_until(&mut [&mut client], started, |clients| clients[0].world.all_players.contains_key(&clients[0].id().unwrap_or_default()));
+
+        // Every move shows up locally at once
+        let spawn = client.local_position();
+        let inputs: Vec<PlayerInput> = (0..5).map(|_| client.send_move(Direction::Right)).collect();
+        assert!(inputs.windows(2).all(|pair| pair[1].sequence == pair[0].sequence + 1));
+        let predicted = client.local_position();
+        assert!(predicted.x > spawn.x, "{:?} from {:?}", predicted, spawn);
+
+        // Once the server applied them all, a snapshot confirms the prediction without an error
+        run_until(&mut [&mut client], started, |clients| {
+            clients[0].events().into_iter().any(|event| matches!(event, ClientEvent::Snapshot(SnapshotOutcome { prediction_error: Some(error), .. }) if error == 0.0))
+                && clients[0].world.prediction.pending_inputs.is_empty()
+        });
+        assert_eq!(client.local_position(), predicted);
+    }
+
+    #[test]
+    fn test_other_players_are_listed_with_their_render_positions() {
+        let server = TestServer::start();
+        let mut alice = connect(&server, "alice");
+        let mut bob = connect(&server, "bob");
+        let started = Instant::now();
+        run_until(&mut [&mut alice, &mut bob], started, |clients| {
+            let bob_id = clients[1].id();
+            bob_id.is_some() && clients[0].remote_players().any(|(id, _, _)| Some(id) == bob_id)
+        });
+
+        // Alice lists Bob but not herself, Bob where the snapshots have him
+        let bob_id = bob.id().unwrap();
+        let remote: Vec<(Uuid, DisplayPos, u32)> = alice.remote_players().collect();
+        assert_eq!(remote.len(), 1);
+        let (id, position, color) = remote[0];
+        assert_eq!(id, bob_id);
+        assert_eq!(color, alice.world.all_players[&bob_id].1);
+        assert_eq!(position, DisplayPos::from_authoritative(bob.local_position()));
+    }
+
+    #[test]
+    fn test_welcome_with_other_tunables_is_adopted_or_refused() {
+        let welcome = |tunables: MovementTunables, sent: bool| {
+            let welcome = Welcome::new(Uuid::new_v4(), Uuid::new_v4(), Position { x: 100, y: 100 }, None);
+            ClientMessage::Welcome(Welcome { tunables_digest: tunables.digest(), tunables: sent.then_some(tunables), ..welcome })
+        };
+        let faster = MovementTunables { player_speed: 8.0, ..MovementTunables::default() };
+
+        // A server that sent its values is played by them
+        let mut client = GameClient::new(NetworkClient::new("127.0.0.1:9"));
+        client.world.handshake_pending = true;
+        client.handle_message(welcome(faster, true));
+        let events = client.events();
+        assert!(matches!(
+            events[..],
+            [ClientEvent::TunablesAdopted(tunables), ClientEvent::ConnectionStateChanged(ConnectionState::Connected), ClientEvent::Joined { .. }] if tunables == faster
+        ), "{:?}", events);
+        client.send_move(Direction::Right);
+        assert_eq!(client.local_position().position(), Position { x: 108, y: 100 });
+
+        // A server that only sent the digest can't be played with, and the client doesn't join
+        let mut client = GameClient::new(NetworkClient::new("127.0.0.1:9"));
+        client.world.handshake_pending = true;
+        client.handle_message(welcome(faster, false));
+        assert_eq!(client.events(), vec![ClientEvent::Incompatible { server_digest: faster.digest() }]);
+        assert_eq!(client.id(), None);
+        client.handle_message(welcome(faster, false));
+        assert!(client.events().is_empty());
+    }
+
+    #[test]
+    fn test_offline_client_plays_against_the_bots_without_a_socket() {
+        let server = LocalServerHandle::start(LocalServer::new(&ServerConfig::default(), 2)).unwrap();
+        let profile = ClientProfile { name: "solo".to_string(), ..ClientProfile::default() };
+        let mut client = GameClient::connect_offline(server, &profile).unwrap();
+        client.net.set_conditions(0, 0, 0);
+        client.net.set_duplicate_percent(0);
+        assert!(client.net.is_offline());
+        assert_eq!(client.net.stats().recv_buffer_size, None);
+
+        // Welcomed with both bots in the snapshots, which keep walking
+        let started = Instant::now();
+        run_until(&mut [&mut client], started, |clients| clients[0].id().is_some() && clients[0].remote_players().count() == 2);
+        let bots: HashMap<Uuid, DisplayPos> = client.remote_players().map(|(id, position, _)| (id, position)).collect();
+
+        // Moves are predicted and confirmed like against the real server
+        let predicted = (0..5).map(|_| client.send_move(Direction::Down)).count();
+        assert_eq!(predicted, 5);
+        let expected = client.local_position();
+        run_until(&mut [&mut client], started, |clients| {
+            clients[0].world.prediction.pending_inputs.is_empty()
+                && clients[0].remote_players().any(|(id, position, _)| bots.get(&id).is_some_and(|before| *before != position))
+        });
+        assert_eq!(client.local_position(), expected);
+
+        // A rebuilt client talks to the same server through a link of its own
+        let rebuilt = client.net.rebuild().unwrap();
+        assert!(rebuilt.is_offline());
+        assert_ne!(rebuilt.local_addr(), client.net.local_addr());
+    }
+
+    #[test]
+    fn test_long_pause_between_updates_resyncs_the_timing() {
+        let mut client = GameClient::new(NetworkClient::new("127.0.0.1:9"));
+        let other = Uuid::from_u128(2);
+        client.update(1.0, 1.0);
+        client.world.server_clock.observe(60, 16, 1.0);
+        client.world.interpolated_positions.update(other, Position { x: 50, y: 50 }, 60, 16, 1.0);
+        client.update(1.5, 1.5);
+        assert!(client.events().is_empty());
+
+        // Woken up from a suspend an hour later, nothing is played back across the gap
+        client.update(3601.5, 3601.5);
+        let events = client.events();
+        assert!(matches!(events.as_slice(), [ClientEvent::Resynced { paused }] if (*paused - 3600.0).abs() < 1e-9), "{:?}", events);
+        assert_eq!(client.world.render_server_time(3601.5, 3601.5), None);
+        assert!(client.world.interpolated_positions.get(&other).is_none());
+
+        // The first update after a reset has nothing to compare with
+        client.reset();
+        client.update(7200.0, 7200.0);
+        assert!(client.events().is_empty());
+    }
+
+    #[test]
+    fn test_connection_changes_and_warnings_are_events() {
+        let mut client = GameClient::new(NetworkClient::new("127.0.0.1:9"));
+        client.send_connect(&ClientProfile::default());
+        client.handle_message(ClientMessage::ConnectRejected { reason: RejectReason::WrongPassword });
+        assert_eq!(client.events(), vec![
+            ClientEvent::ConnectionStateChanged(ConnectionState::Connecting),
+            ClientEvent::ConnectionStateChanged(ConnectionState::Disconnected),
+            ClientEvent::Rejected(RejectReason::WrongPassword),
+        ]);
+
+        let id = Uuid::new_v4();
+        client.send_connect(&ClientProfile::default());
+        client.handle_message(ClientMessage::Welcome(Welcome::new(id, Uuid::new_v4(), Position { x: 100, y: 100 }, None)));
+        assert_eq!(client.connection(), ConnectionState::Connected);
+        assert_eq!(client.events().len(), 3);
+
+        // A high prediction error in three evaluations in a row raises a warning, once per evaluation interval
+        for evaluation in 0..3 {
+            assert!(client.warnings().is_empty(), "evaluation {}", evaluation);
+            client.world.metric_window.record_prediction_error(50.0);
+            client.update(evaluation as f64, evaluation as f64);
+        }
+        let events = client.events();
+        assert!(matches!(events.as_slice(), [ClientEvent::Warning(WarningChange::Raised { metric: Metric::PredictionError, .. })]), "{:?}", events);
+        assert_eq!(client.warnings().len(), 1);
+
+        // Warnings go with the connection, without events of their own
+        client.handle_message(ClientMessage::Disconnected { id, reason: DisconnectReason::Kicked });
+        assert_eq!(client.events(), vec![
+            ClientEvent::ConnectionStateChanged(ConnectionState::Disconnected),
+            ClientEvent::Removed(Some(DisconnectReason::Kicked)),
+        ]);
+        assert!(client.warnings().is_empty());
+    }
+}
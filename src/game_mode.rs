@@ -0,0 +1,455 @@
+use crate::codec::encode;
+use crate::constants::{PELLET_COUNT, PELLET_SIZE, PLAYER_SIZE, TAG_COOLDOWN};
+use crate::game::{Game, PlayerState, SpawnZone};
+use crate::snapshot::{ExtensionBlock, ExtensionKind};
+use crate::types::Position;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Rules of a game mode. Game calls the hooks at fixed points and keeps no state of its own for the mode,
+/// everything a mode needs lives inside it. Every hook does nothing by default.
+pub trait GameMode: Send + Sync {
+    /// Command line name of the mode
+    fn name(&self) -> &'static str;
+
+    /// A player joined the game, either freshly or by resuming a session from the grace period
+    fn on_player_join(&mut self, _id: Uuid, _player: &mut PlayerState) {}
+
+    /// A player left the game or timed out
+    fn on_player_leave(&mut self, _id: Uuid) {}
+
+    /// A server tick dt after the last one, while players may move. The players are sorted by id.
+    fn on_tick(&mut self, _dt: Duration, _players: &mut [(Uuid, &mut PlayerState)]) {}
+
+    /// A player moved by one of its inputs
+    fn on_input_applied(&mut self, _id: Uuid, _player: &mut PlayerState) {}
+
+    /// A new match started, scores were already set to zero
+    fn on_match_reset(&mut self) {}
+
+    /// Extension blocks the mode adds to every snapshot
+    fn snapshot_extensions(&self) -> Vec<ExtensionBlock> {
+        Vec::new()
+    }
+}
+
+/// The game modes the server can run, picked with --mode or the mode of the server config file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModeKind {
+    #[serde(rename = "free")]
+    FreeRoam,
+    #[default]
+    #[serde(rename = "pellets")]
+    PelletHunt,
+    #[serde(rename = "tag")]
+    Tag,
+}
+
+/// Implementation of the ModeKind
+impl ModeKind {
+    /// Looks up a mode by its command line name, free, pellets or tag
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "free" => Some(ModeKind::FreeRoam),
+            "pellets" => Some(ModeKind::PelletHunt),
+            "tag" => Some(ModeKind::Tag),
+            _ => None,
+        }
+    }
+
+    /// Command line name of the mode
+    pub fn name(self) -> &'static str {
+        match self {
+            ModeKind::FreeRoam => "free",
+            ModeKind::PelletHunt => "pellets",
+            ModeKind::Tag => "tag",
+        }
+    }
+
+    /// Creates the rules of the mode for a new game
+    pub fn create(self) -> Box<dyn GameMode> {
+        match self {
+            ModeKind::FreeRoam => Box::new(FreeRoam),
+            ModeKind::PelletHunt => Box::new(PelletHunt::new()),
+            ModeKind::Tag => Box::new(TagMode::new()),
+        }
+    }
+}
+
+/// Players only move around, nothing is scored
+#[derive(Debug, Default)]
+pub struct FreeRoam;
+
+/// Implementation of the GameMode for FreeRoam
+impl GameMode for FreeRoam {
+    fn name(&self) -> &'static str {
+        ModeKind::FreeRoam.name()
+    }
+}
+
+/// Players score a point for every pellet they touch, a collected pellet respawns somewhere else
+#[derive(Debug)]
+pub struct PelletHunt {
+    pellets: Vec<Position>,
+}
+
+/// Implementation of the PelletHunt
+impl PelletHunt {
+    /// Creates the mode with PELLET_COUNT pellets at random positions
+    pub fn new() -> Self {
+        Self::with_pellets(random_pellets())
+    }
+
+    /// Creates the mode with the pellets at the given positions
+    pub fn with_pellets(pellets: Vec<Position>) -> Self {
+        Self { pellets }
+    }
+
+    /// Positions of the pellets on the board
+    pub fn pellets(&self) -> &[Position] {
+        &self.pellets
+    }
+}
+
+/// Default implementation for the PelletHunt
+impl Default for PelletHunt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Implementation of the GameMode for PelletHunt
+impl GameMode for PelletHunt {
+    fn name(&self) -> &'static str {
+        ModeKind::PelletHunt.name()
+    }
+
+    fn on_input_applied(&mut self, _id: Uuid, player: &mut PlayerState) {
+        // Collect touched pellets, each one respawns somewhere else
+        for pellet in self.pellets.iter_mut() {
+            if touches_pellet(player.position, *pellet) {
+                *pellet = Game::random_spawn_position(SpawnZone::whole_board());
+                player.score += 1;
+                player.total_pellets += 1;
+                player.best_score = player.best_score.max(player.score);
+            }
+        }
+    }
+
+    fn on_match_reset(&mut self) {
+        // New pellets for the new match
+        self.pellets = random_pellets();
+    }
+
+    fn snapshot_extensions(&self) -> Vec<ExtensionBlock> {
+        vec![ExtensionBlock {
+            tag: ExtensionKind::Pellets as u8,
+            data: encode(&self.pellets),
+        }]
+    }
+}
+
+/// One player is "it" and passes it on by touching another player. The score of a player is the
+/// whole seconds it was "it" in the current match, so the lowest score wins. Best scores are left
+/// to the pellet hunt, where higher is better.
+#[derive(Debug, Default)]
+pub struct TagMode {
+    it: Option<Uuid>,
+    time_as_it: HashMap<Uuid, Duration>, // Time every player was "it" in the current match
+    cooldown: Duration, // Time left before the player that was just tagged can tag someone, so it can't tag straight back
+}
+
+/// Implementation of the TagMode
+impl TagMode {
+    /// Creates the mode without anyone being "it" yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The player that is "it", None while nobody is playing
+    pub fn it(&self) -> Option<Uuid> {
+        self.it
+    }
+
+    /// Time the player was "it" in the current match
+    pub fn time_as_it(&self, id: Uuid) -> Duration {
+        self.time_as_it.get(&id).copied().unwrap_or_default()
+    }
+}
+
+/// Implementation of the GameMode for TagMode
+impl GameMode for TagMode {
+    fn name(&self) -> &'static str {
+        ModeKind::Tag.name()
+    }
+
+    fn on_player_join(&mut self, id: Uuid, _player: &mut PlayerState) {
+        // The first player in an empty game is "it"
+        self.it.get_or_insert(id);
+    }
+
+    fn on_player_leave(&mut self, id: Uuid) {
+        // Someone else is picked on the next tick
+        if self.it == Some(id) {
+            self.it = None;
+        }
+    }
+
+    fn on_tick(&mut self, dt: Duration, players: &mut [(Uuid, &mut PlayerState)]) {
+        // Hand "it" to the first player if nobody has it
+        if !players.iter().any(|(id, _)| Some(*id) == self.it) {
+            self.it = players.first().map(|(id, _)| *id);
+            self.cooldown = Duration::ZERO;
+        }
+        let Some(it) = self.it else { return };
+
+        // Time as "it" adds up as score
+        let time = self.time_as_it.entry(it).or_default();
+        *time += dt;
+        let seconds = time.as_secs() as u32;
+        let Some(index) = players.iter().position(|(id, _)| *id == it) else { return };
+        players[index].1.score = seconds;
+
+        // Touching another player passes "it" on, once the cooldown is over
+        self.cooldown = self.cooldown.saturating_sub(dt);
+        if !self.cooldown.is_zero() {
+            return;
+        }
+        let position = players[index].1.position;
+        let tagged = players.iter()
+            .find(|(id, player)| *id != it && players_overlap(position, player.position))
+            .map(|(id, _)| *id);
+        if let Some(tagged) = tagged {
+            self.it = Some(tagged);
+            self.cooldown = TAG_COOLDOWN;
+        }
+    }
+
+    fn on_match_reset(&mut self) {
+        self.time_as_it.clear();
+        self.cooldown = Duration::ZERO;
+    }
+
+    fn snapshot_extensions(&self) -> Vec<ExtensionBlock> {
+        self.it.iter()
+            .map(|it| ExtensionBlock {
+                tag: ExtensionKind::Tagged as u8,
+                data: encode(it),
+            })
+            .collect()
+    }
+}
+
+/// PELLET_COUNT pellets at random positions on the whole board
+fn random_pellets() -> Vec<Position> {
+    (0..PELLET_COUNT).map(|_| Game::random_spawn_position(SpawnZone::whole_board())).collect()
+}
+
+/// Checks if a player touches a pellet, both are centered on their position
+fn touches_pellet(player: Position, pellet: Position) -> bool {
+    let reach = (PLAYER_SIZE + PELLET_SIZE) / 2;
+    (player.x - pellet.x).abs() < reach && (player.y - pellet.y).abs() < reach
+}
+
+/// Checks if two players overlap, both are centered on their position
+fn players_overlap(first: Position, second: Position) -> bool {
+    (first.x - second.x).abs() < PLAYER_SIZE && (first.y - second.y).abs() < PLAYER_SIZE
+}
+
+/// Tests for the game modes
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{BROADCAST_INTERVAL, MATCH_COUNTDOWN, PLAYER_SPEED};
+    use crate::types::{Direction, MoveOrigin, PlayerInput};
+
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::Instant;
+
+    // Helper function to create test socket addresses
+    fn test_addr(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port)
+    }
+
+    // Helper function to connect a player and put it at a known position
+    fn join_at(game: &mut Game, port: u16, x: i32, y: i32) -> (SocketAddr, Uuid) {
+        let addr = test_addr(port);
+        let id = game.connect_player(addr);
+        game.set_position_validated(addr, Position { x, y }, MoveOrigin::Teleport).unwrap();
+        (addr, id)
+    }
+
+    // Helper function to run server ticks of BROADCAST_INTERVAL, returning the time after the last one
+    fn run_ticks(game: &mut Game, mut now: Instant, ticks: u32) -> Instant {
+        for _ in 0..ticks {
+            now += BROADCAST_INTERVAL;
+            game.advance_tick_at(now);
+        }
+        now
+    }
+
+    // Helper function for the number of ticks that cover a duration
+    fn ticks_for(duration: Duration) -> u32 {
+        duration.as_millis().div_ceil(BROADCAST_INTERVAL.as_millis()) as u32
+    }
+
+    #[test]
+    fn test_mode_names() {
+        for kind in [ModeKind::FreeRoam, ModeKind::PelletHunt, ModeKind::Tag] {
+            assert_eq!(ModeKind::parse(kind.name()), Some(kind));
+            assert_eq!(kind.create().name(), kind.name());
+        }
+        assert_eq!(ModeKind::parse("TAG"), Some(ModeKind::Tag));
+        assert_eq!(ModeKind::parse("race"), None);
+        assert_eq!(ModeKind::default(), ModeKind::PelletHunt);
+    }
+
+    #[test]
+    fn test_overlap() {
+        let origin = Position { x: 100, y: 100 };
+        assert!(players_overlap(origin, Position { x: 100 + PLAYER_SIZE - 1, y: 100 }));
+        assert!(!players_overlap(origin, Position { x: 100 + PLAYER_SIZE, y: 100 }));
+        assert!(touches_pellet(origin, Position { x: 100, y: 100 + (PLAYER_SIZE + PELLET_SIZE) / 2 - 1 }));
+        assert!(!touches_pellet(origin, Position { x: 100, y: 100 + (PLAYER_SIZE + PELLET_SIZE) / 2 }));
+    }
+
+    /// Tests for the free roam mode
+    mod free_roam {
+        use super::*;
+
+        #[test]
+        fn test_nothing_is_scored() {
+            let mut game = Game::new();
+            game.set_mode(Box::new(FreeRoam));
+            let (addr, id) = join_at(&mut game, 8080, 200, 200);
+            let (_, other) = join_at(&mut game, 8081, 210, 200);
+
+            let mut now = Instant::now();
+            for sequence in 1..=20 {
+                game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence, timestamp: 0 });
+                now = run_ticks(&mut game, now, 1);
+            }
+
+            let snapshot = game.build_snapshot();
+            assert_eq!(game.mode_name(), "free");
+            assert!(snapshot.pellets.is_empty());
+            assert_eq!(snapshot.tagged, None);
+            assert_eq!(snapshot.scores, HashMap::from([(id, 0), (other, 0)]));
+            assert_eq!(snapshot.players.iter().find(|(player, _, _)| *player == id).unwrap().1.x, 200 + 20 * PLAYER_SPEED);
+        }
+    }
+
+    /// Tests for the pellet hunt mode
+    mod pellet_hunt {
+        use super::*;
+
+        #[test]
+        fn test_collecting_pellet_scores() {
+            let mut game = Game::new();
+            assert_eq!(game.mode_name(), "pellets");
+
+            // Only one pellet, right where the player moves to
+            let pellet = Position { x: 200 + PLAYER_SPEED, y: 200 };
+            game.set_mode(Box::new(PelletHunt::with_pellets(vec![pellet])));
+            let (addr, id) = join_at(&mut game, 8080, 200, 200);
+            game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence: 1, timestamp: 0 });
+            run_ticks(&mut game, Instant::now(), 1);
+
+            let snapshot = game.build_snapshot();
+            assert_eq!(snapshot.scores.get(&id), Some(&1));
+            assert_eq!(snapshot.best_scores.get(&id), Some(&1));
+            assert_eq!(snapshot.pellets.len(), 1);
+        }
+
+        #[test]
+        fn test_match_reset_brings_new_pellets() {
+            let mut game = Game::new();
+            game.set_mode(Box::new(PelletHunt::with_pellets(vec![Position { x: 200 + PLAYER_SPEED, y: 200 }])));
+            let (addr, id) = join_at(&mut game, 8080, 200, 200);
+            game.handle_input(addr, PlayerInput { dir: Direction::Right, sequence: 1, timestamp: 0 });
+
+            game.reset_match(Instant::now());
+            let snapshot = game.build_snapshot();
+            assert_eq!(snapshot.pellets.len(), PELLET_COUNT);
+            assert_eq!(snapshot.scores.get(&id), Some(&0));
+            assert_eq!(snapshot.best_scores.get(&id), Some(&1));
+        }
+    }
+
+    /// Tests for the tag mode
+    mod tag {
+        use super::*;
+
+        #[test]
+        fn test_first_player_is_it_and_time_scores() {
+            let mut game = Game::new();
+            game.set_mode(Box::new(TagMode::new()));
+            let (_, first) = join_at(&mut game, 8080, 200, 200);
+            let (_, second) = join_at(&mut game, 8081, 600, 200);
+            assert_eq!(game.build_snapshot().tagged, Some(first));
+
+            // Players far apart, so the first one stays "it" for a second and a half
+            run_ticks(&mut game, Instant::now(), ticks_for(Duration::from_millis(1500)));
+            let snapshot = game.build_snapshot();
+            assert_eq!(snapshot.tagged, Some(first));
+            assert_eq!(snapshot.scores, HashMap::from([(first, 1), (second, 0)]));
+        }
+
+        #[test]
+        fn test_tag_passes_on_overlap_after_cooldown() {
+            let mut game = Game::new();
+            game.set_mode(Box::new(TagMode::new()));
+            let (_, first) = join_at(&mut game, 8080, 200, 200);
+            let (second_addr, second) = join_at(&mut game, 8081, 600, 200);
+            let now = run_ticks(&mut game, Instant::now(), 1);
+
+            // Touching the player that is "it" makes the second player "it"
+            game.set_position_validated(second_addr, Position { x: 200 + PLAYER_SIZE - 1, y: 200 }, MoveOrigin::Teleport).unwrap();
+            let now = run_ticks(&mut game, now, 1);
+            assert_eq!(game.build_snapshot().tagged, Some(second));
+
+            // Still touching, but it can't tag straight back until the cooldown is over
+            let now = run_ticks(&mut game, now, ticks_for(TAG_COOLDOWN) - 1);
+            assert_eq!(game.build_snapshot().tagged, Some(second));
+            run_ticks(&mut game, now, 1);
+            assert_eq!(game.build_snapshot().tagged, Some(first));
+        }
+
+        #[test]
+        fn test_it_leaving_hands_it_on() {
+            let mut game = Game::new();
+            game.set_mode(Box::new(TagMode::new()));
+            let (first_addr, _) = join_at(&mut game, 8080, 200, 200);
+            let (_, second) = join_at(&mut game, 8081, 600, 200);
+
+            game.disconnect_player(&first_addr);
+            assert_eq!(game.build_snapshot().tagged, None);
+            run_ticks(&mut game, Instant::now(), 1);
+            assert_eq!(game.build_snapshot().tagged, Some(second));
+        }
+
+        #[test]
+        fn test_match_reset_clears_time_as_it() {
+            let mut game = Game::new();
+            game.set_mode(Box::new(TagMode::new()));
+            let (_, first) = join_at(&mut game, 8080, 200, 200);
+            let now = run_ticks(&mut game, Instant::now(), ticks_for(Duration::from_secs(2)));
+            assert_eq!(game.build_snapshot().scores.get(&first), Some(&2));
+
+            // Frozen during the countdown, nobody is "it" for longer
+            game.reset_match(now);
+            let mut now = run_ticks(&mut game, now, ticks_for(MATCH_COUNTDOWN) - 1);
+            assert_eq!(game.build_snapshot().scores.get(&first), Some(&0));
+
+            // Once the match runs, time counts from zero again
+            for _ in 0..ticks_for(Duration::from_millis(1500)) + 1 {
+                game.update_phase(now, Duration::from_secs(600));
+                now = run_ticks(&mut game, now, 1);
+            }
+            assert_eq!(game.build_snapshot().scores.get(&first), Some(&1));
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use crate::analysis::{default_conditions, PerformanceAnalyzer, Series};
+use crate::constants::{ANALYZE_CONNECT_TIMEOUT, ANALYZE_TEST_DURATION, ANALYZE_WARMUP};
+use crate::frame_pacer::{FrameCap, FramePacer, SystemClock};
+use crate::client_events::ClientEvent;
+use crate::game_client::{ClientProfile, GameClient};
+use crate::input_dispatch::InputIntent;
+use crate::network::NetworkClient;
+use crate::profiler;
+use crate::scripted_input::ScriptedInputDriver;
+use crate::streaming_stats::Capture;
+use crate::types::NetworkCondition;
+
+use std::time::{Duration, Instant};
+
+/// What the headless client tests and how
+#[derive(Debug, Clone)]
+pub struct SweepConfig {
+    pub conditions: Vec<NetworkCondition>, // Tested in order
+    pub test_duration: Duration,           // How long each condition is tested
+    pub warmup: Duration,                  // Left out of the metrics after each condition change
+    pub capture: Capture,                  // How many samples of each series are kept for the CSV
+    pub connect_timeout: Duration,
+    pub room: String,                      // Room to join, the default room if empty
+}
+
+/// Default implementation for the SweepConfig
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self {
+            conditions: default_conditions(),
+            test_duration: ANALYZE_TEST_DURATION,
+            warmup: ANALYZE_WARMUP,
+            capture: Capture::default(),
+            connect_timeout: ANALYZE_CONNECT_TIMEOUT,
+            room: String::new(),
+        }
+    }
+}
+
+/// A client without a window that runs the performance tests: the scripted walk stands in for the
+/// keyboard, and the snapshot processing and prediction are the windowed client's, through GameClient.
+/// Frames run at a fixed 60 Hz with real sleeping, so the timing matches a client at 60 fps.
+pub struct HeadlessClient {
+    client: GameClient,
+    driver: ScriptedInputDriver,
+    analyzer: PerformanceAnalyzer,
+    instance: u64, // Hashed instance id sent with the connect
+    pacer: FramePacer,
+    clock: SystemClock,
+    started: Instant,    // Local time is measured from here, like the windowed client's time since start
+    last_frame: Instant,
+}
+
+/// Implementation of the HeadlessClient
+impl HeadlessClient {
+    /// Creates a client that sends through net and tests the conditions of the config
+    pub fn new(net: NetworkClient, driver: ScriptedInputDriver, config: &SweepConfig, instance: u64) -> Self {
+        let mut analyzer = PerformanceAnalyzer::with_conditions(config.conditions.clone(), config.test_duration);
+        analyzer.set_warmup(config.warmup);
+        analyzer.set_capture(config.capture);
+        Self {
+            client: GameClient::new(net),
+            driver,
+            analyzer,
+            instance,
+            pacer: FramePacer::new(FrameCap::Fps60),
+            clock: SystemClock::new(),
+            started: Instant::now(),
+            last_frame: Instant::now(),
+        }
+    }
+
+    /// The analyzer with the results of the conditions tested so far
+    pub fn analyzer(&self) -> &PerformanceAnalyzer {
+        &self.analyzer
+    }
+
+    /// The network client and the world as the client sees it
+    pub fn client(&self) -> &GameClient {
+        &self.client
+    }
+
+    /// Connects to a room of the server with the network conditions turned off and runs frames until
+    /// the server welcomes us. Fails if the server refuses or doesn't answer within the timeout.
+    pub fn connect(&mut self, room: &str, timeout: Duration) -> Result<(), String> {
+        self.client.net.set_conditions(0, 0, 0);
+        self.client.net.set_duplicate_percent(0);
+        let profile = ClientProfile { name: "analyze".to_string(), instance: self.instance, room: room.to_string(), ..ClientProfile::default() };
+        self.client.send_connect(&profile);
+        let started = Instant::now();
+        while self.client.id().is_none() {
+            if started.elapsed() >= timeout {
+                return Err(format!("no answer from {} within {} ms", self.client.net.server_addr(), timeout.as_millis()));
+            }
+            self.tick()?;
+        }
+        Ok(())
+    }
+
+    /// Tests every condition in order and puts the network conditions back to none afterwards
+    pub fn run_sweep(&mut self) -> Result<(), String> {
+        let (client_build, server_build) = self.client.builds();
+        self.analyzer.set_builds(client_build, server_build);
+        while let Some(condition) = self.analyzer.start_next_test() {
+            println!("Testing condition: {}", condition.name);
+            self.client.net.set_conditions(condition.latency_ms, condition.jitter_ms, condition.packet_loss_percent);
+            self.client.net.set_duplicate_percent(condition.duplicate_percent);
+            while !self.analyzer.is_test_complete() {
+                self.tick()?;
+            }
+            self.analyzer.record_traffic(self.client.net.stats().traffic.rates(Instant::now()));
+            self.analyzer.complete_current_test();
+        }
+        self.client.net.set_conditions(0, 0, 0);
+        self.client.net.set_duplicate_percent(0);
+        Ok(())
+    }
+
+    /// Runs one frame, waiting for it to be due first: what the windowed client does in a frame of the
+    /// performance tests, without the drawing. Fails if the server refuses the connect or removes us.
+    pub fn tick(&mut self) -> Result<(), String> {
+        self.pacer.pace(&mut self.clock);
+
+        // The frame is profiled without the wait for it, like the windowed client measures its frame cost
+        let (result, profile) = profiler::capture(|| self.run_frame());
+        self.analyzer.record_profile(&profile);
+        result
+    }
+
+    /// The work of one frame, once it is due
+    fn run_frame(&mut self) -> Result<(), String> {
+        let now = Instant::now();
+        let frame_time = now.saturating_duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+        let current_time = self.started.elapsed().as_secs_f64();
+
+        if let Some(breakdown) = self.client.world.present_frame(current_time) {
+            self.analyzer.record_latency(&breakdown);
+        }
+
+        // Pings, sends the due inputs and applies everything received since the last frame
+        self.client.update(current_time, current_time);
+        for event in self.client.events() {
+            self.handle_event(event)?;
+        }
+
+        // The scripted walk stands in for the keyboard, no inputs are sent while the match phase freezes players
+        if self.client.id().is_some() && self.client.world.match_phase.allows_movement() {
+            let intents = self.driver.next_direction(frame_time).map(|dir| InputIntent { dir, originated_at: current_time });
+            self.client.dispatch(intents);
+        }
+        if let Some(share) = self.client.world.starvation_share(current_time, current_time) {
+            self.analyzer.record(Series::Starvation, share);
+        }
+        if let Some(cadence) = self.client.world.tick_cadence.check() {
+            self.analyzer.record(Series::TickCadence, cadence.ratio() as f32);
+        }
+        let server_time = self.client.world.render_server_time(current_time, current_time);
+        self.analyzer.record_overlaps(&self.client.world.overlaps(server_time));
+        Ok(())
+    }
+
+    /// Records the prediction error of every reconciled snapshot, with the round trip time for cross-plotting.
+    /// Fails if the server refused the connect or removed us.
+    fn handle_event(&mut self, event: ClientEvent) -> Result<(), String> {
+        match event {
+            ClientEvent::Snapshot(outcome) => {
+                if let Some(error) = outcome.prediction_error {
+                    self.analyzer.record_prediction_error(error);
+                    if let Some(position) = outcome.prediction_error_at {
+                        self.analyzer.record_error_position(position, error);
+                    }
+                    if let Some(rtt) = self.client.world.my_ping() {
+                        self.analyzer.record(Series::Rtt, rtt as f32);
+                    }
+                }
+            }
+            ClientEvent::Rejected(reason) => return Err(format!("connect refused: {}", reason.describe())),
+            ClientEvent::Incompatible { server_digest } => {
+                return Err(format!("the server moves players by other tunables (digest {:016x}), update the client or the server", server_digest));
+            }
+            ClientEvent::TunablesAdopted(tunables) => println!("Adopted the movement tunables of the server: {:?}", tunables),
+            ClientEvent::Removed(Some(reason)) => return Err(format!("removed by the server: {}", reason.describe())),
+            ClientEvent::Removed(None) => return Err("the server no longer lists this player".to_string()),
+            ClientEvent::PredictionDisabled => println!("Warning: the prediction kept going wrong and was turned off, its errors are still measured"),
+            ClientEvent::SnapshotDiscarded { snapshot_id, anomalies, players } => {
+                println!("Warning: discarded snapshot {}, {} of its {} players were off the board", snapshot_id, anomalies, players);
+            }
+            ClientEvent::Warning(change) => println!("{}", change.describe()),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Connects a headless client to the server, walks the square while testing every condition of the config
+/// and returns the analyzer with the results
+pub fn run_sweep(server_addr: &str, config: &SweepConfig, instance: u64) -> Result<PerformanceAnalyzer, String> {
+    let net = NetworkClient::builder(server_addr)
+        .build()
+        .map_err(|e| format!("failed to bind a socket: {}", e))?;
+    let mut client = HeadlessClient::new(net, ScriptedInputDriver::square(), config, instance);
+    client.connect(&config.room, config.connect_timeout)?;
+    client.run_sweep()?;
+    Ok(client.analyzer)
+}
+
+/// Tests for the headless client
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_info::BuildInfo;
+    use crate::regression::RegressionThresholds;
+    use crate::rooms::RoomConfig;
+    use crate::server::{create_rooms, run_server, ServerTuning};
+    use crate::server_config::ServerConfig;
+    use crate::transport::Transport;
+
+    use std::collections::HashMap;
+    use tokio::net::UdpSocket;
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn test_micro_sweep_against_an_in_process_server() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let socket = runtime.block_on(UdpSocket::bind("127.0.0.1:0")).unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let (config, tuning) = (ServerConfig { spawn_seed: Some(21), ..ServerConfig::default() }, ServerTuning::default());
+        let rooms = create_rooms(&config, RoomConfig::default(), HashMap::new(), tuning);
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = runtime.spawn(run_server(Transport::new(socket, None), rooms, config, tuning, async {
+            let _ = stopped.await;
+        }));
+
+        // Two short conditions, the second with latency so the round trip time shows it
+        let config = SweepConfig {
+            conditions: vec![
+                NetworkCondition { latency_ms: 0, jitter_ms: 0, packet_loss_percent: 0, duplicate_percent: 0, name: "Ideal".to_string() },
+                NetworkCondition { latency_ms: 60, jitter_ms: 5, packet_loss_percent: 0, duplicate_percent: 0, name: "Good".to_string() },
+            ],
+            test_duration: Duration::from_millis(1200),
+            warmup: Duration::from_millis(200),
+            ..SweepConfig::default()
+        };
+        let started = Instant::now();
+        let result = run_sweep(&server_addr.to_string(), &config, 7);
+        let _ = stop.send(());
+        let _ = runtime.block_on(server);
+        let analyzer = result.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+
+        let summaries = analyzer.summaries();
+        let names: Vec<&str> = summaries.iter().map(|summary| summary.condition.as_str()).collect();
+        assert_eq!(names, vec!["Ideal", "Good"]);
+        for summary in &summaries {
+            assert!(summary.reconciliation_count > 20, "{:?}", summary);
+        }
+        assert!(RegressionThresholds::default().check(&summaries).is_empty(), "{:?}", summaries);
+        let csv = analyzer.export_csv("0000000000000007");
+        assert!(csv.lines().count() > 3);
+
+        // The server sent its build and seed in the Welcome, both sides head the exports
+        let builds: Vec<_> = csv.lines().take(2).map(|line| BuildInfo::parse_csv_comment(line).unwrap()).collect();
+        assert_eq!(builds[0].0, "client");
+        assert_eq!(builds[1], ("server".to_string(), Some(BuildInfo::current(Some(21)))));
+        assert!(analyzer.export_json("0000000000000007").contains("\"condition\": \"Good\""));
+    }
+
+    #[test]
+    fn test_connect_times_out_without_a_server() {
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = SweepConfig { connect_timeout: Duration::from_millis(100), ..SweepConfig::default() };
+        let error = run_sweep(&silent.local_addr().unwrap().to_string(), &config, 7).err().unwrap();
+        assert!(error.starts_with("no answer from"), "{}", error);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::coords::{play_area_size, PLAY_AREA};
+use crate::types::Position;
+
+use serde::{Deserialize, Serialize};
+
+/// Sum and number of the prediction errors measured in one cell
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct HeatCell {
+    sum: f64,
+    count: u32,
+}
+
+/// How the mean error of a cell is turned into a color intensity from 0 to 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeatmapScale {
+    Relative,   // The cell with the largest mean is full intensity
+    Fixed(f64), // This many pixels of mean error and more are full intensity
+}
+
+/// Prediction errors bucketed by where on the board they were measured, in a grid of equal cells over
+/// the board above the toolbar. Shows whether errors gather somewhere, such as along the walls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorHeatmap {
+    columns: usize,
+    rows: usize,
+    cells: Vec<HeatCell>, // Row by row from the top left
+}
+
+/// The grid in the analysis JSON, row by row from the top left
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeatmapExport {
+    pub columns: usize,
+    pub rows: usize,
+    pub means: Vec<Option<f32>>, // Mean error of each cell in pixels, None for cells without samples
+    pub counts: Vec<u32>,        // Samples of each cell
+}
+
+/// Implementation of the ErrorHeatmap
+impl ErrorHeatmap {
+    /// Creates an empty grid of columns by rows cells, at least one of each
+    pub fn new(columns: usize, rows: usize) -> Self {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        Self { columns, rows, cells: vec![HeatCell::default(); columns * rows] }
+    }
+
+    /// Number of cells across the board
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Number of cells down the board
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Width and height of a cell in board pixels
+    pub fn cell_size(&self) -> (f32, f32) {
+        let (width, height) = play_area_size();
+        (width / self.columns as f32, height / self.rows as f32)
+    }
+
+    /// Column and row of the cell a position is in, positions off the board count towards the nearest cell
+    pub fn cell_of(&self, position: Position) -> (usize, usize) {
+        let index = |coordinate: i32, length: i32, cells: usize| {
+            (coordinate.clamp(0, length - 1) as usize * cells / length as usize).min(cells - 1)
+        };
+        (index(position.x, PLAY_AREA.0, self.columns), index(position.y, PLAY_AREA.1, self.rows))
+    }
+
+    /// Adds an error measured at a position
+    pub fn insert(&mut self, position: Position, error: f32) {
+        let (column, row) = self.cell_of(position);
+        let cell = &mut self.cells[row * self.columns + column];
+        cell.sum += error as f64;
+        cell.count += 1;
+    }
+
+    /// Mean error of a cell, None without samples or outside the grid
+    pub fn mean(&self, column: usize, row: usize) -> Option<f64> {
+        let cell = self.cells.get(row * self.columns + column).filter(|_| column < self.columns)?;
+        (cell.count > 0).then(|| cell.sum / cell.count as f64)
+    }
+
+    /// Largest mean of any cell, None while the grid is empty
+    pub fn max_mean(&self) -> Option<f64> {
+        self.means().flatten().reduce(f64::max)
+    }
+
+    /// Intensity of a cell from 0 to 1 on the scale, None without samples
+    pub fn normalized(&self, column: usize, row: usize, scale: HeatmapScale) -> Option<f32> {
+        let mean = self.mean(column, row)?;
+        let top = match scale {
+            HeatmapScale::Relative => self.max_mean()?,
+            HeatmapScale::Fixed(top) => top,
+        };
+        Some(if top > 0.0 { (mean / top).clamp(0.0, 1.0) as f32 } else { 0.0 })
+    }
+
+    /// Checks if no error was inserted since the grid was created or cleared
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|cell| cell.count == 0)
+    }
+
+    /// Forgets every sample, such as when the next condition starts
+    pub fn clear(&mut self) {
+        self.cells.fill(HeatCell::default());
+    }
+
+    /// The grid for the analysis JSON
+    pub fn export(&self) -> HeatmapExport {
+        HeatmapExport {
+            columns: self.columns,
+            rows: self.rows,
+            means: self.means().map(|mean| mean.map(|mean| mean as f32)).collect(),
+            counts: self.cells.iter().map(|cell| cell.count).collect(),
+        }
+    }
+
+    /// Mean of every cell, row by row
+    fn means(&self) -> impl Iterator<Item = Option<f64>> + '_ {
+        self.cells.iter().map(|cell| (cell.count > 0).then(|| cell.sum / cell.count as f64))
+    }
+}
+
+/// Tests for the prediction error heatmap
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH, TOOL_BAR_HEIGHT};
+
+    #[test]
+    fn test_errors_are_averaged_per_cell() {
+        let mut heatmap = ErrorHeatmap::new(32, 24);
+        assert!(heatmap.is_empty());
+        assert_eq!(heatmap.max_mean(), None);
+
+        heatmap.insert(Position { x: 1, y: 1 }, 2.0);
+        heatmap.insert(Position { x: 10, y: 10 }, 4.0);
+        heatmap.insert(Position { x: BOARD_WIDTH - 1, y: BOARD_HEIGHT - TOOL_BAR_HEIGHT - 1 }, 12.0);
+        assert_eq!(heatmap.mean(0, 0), Some(3.0));
+        assert_eq!(heatmap.mean(31, 23), Some(12.0));
+        assert_eq!(heatmap.mean(5, 5), None);
+        assert_eq!(heatmap.mean(32, 0), None);
+        assert_eq!(heatmap.max_mean(), Some(12.0));
+    }
+
+    #[test]
+    fn test_positions_off_the_board_land_in_the_edge_cells() {
+        let mut heatmap = ErrorHeatmap::new(32, 24);
+        assert_eq!(heatmap.cell_of(Position { x: -50, y: BOARD_HEIGHT }), (0, 23));
+        assert_eq!(heatmap.cell_of(Position { x: i32::MIN, y: i32::MAX }), (0, 23));
+        heatmap.insert(Position { x: i32::MAX, y: i32::MIN }, 5.0);
+        assert_eq!(heatmap.mean(31, 0), Some(5.0));
+        assert_eq!(heatmap.cell_of(Position { x: BOARD_WIDTH / 2, y: 0 }), (16, 0));
+        let (width, height) = heatmap.cell_size();
+        assert_eq!(heatmap.cell_of(Position { x: width as i32 * 3, y: height.ceil() as i32 * 2 }), (3, 2));
+    }
+
+    #[test]
+    fn test_intensity_is_relative_or_on_a_fixed_scale() {
+        let mut heatmap = ErrorHeatmap::new(4, 4);
+        heatmap.insert(Position { x: 0, y: 0 }, 5.0);
+        heatmap.insert(Position { x: BOARD_WIDTH - 1, y: 0 }, 20.0);
+        assert_eq!(heatmap.normalized(0, 0, HeatmapScale::Relative), Some(0.25));
+        assert_eq!(heatmap.normalized(3, 0, HeatmapScale::Relative), Some(1.0));
+        assert_eq!(heatmap.normalized(0, 0, HeatmapScale::Fixed(10.0)), Some(0.5));
+        assert_eq!(heatmap.normalized(3, 0, HeatmapScale::Fixed(10.0)), Some(1.0));
+        assert_eq!(heatmap.normalized(1, 1, HeatmapScale::Relative), None);
+
+        // The export has every cell, and clearing starts the grid over
+        let export = heatmap.export();
+        assert_eq!((export.means.len(), export.counts[3], export.means[3]), (16, 1, Some(20.0)));
+        heatmap.clear();
+        assert!(heatmap.is_empty());
+    }
+}
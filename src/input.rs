@@ -1,58 +1,332 @@
-use crate::constants::{INITIAL_DELAY, REPEAT_START, REPEAT_MIN, REPEAT_ACCEL, DELAY_MS, PACKET_LOSS};
-use crate::network::NetworkClient;
-use crate::prediction::PredictionState;
-use crate::types::{PlayerInput, Direction, Position};
+use crate::constants::{INITIAL_DELAY, REPEAT_START, REPEAT_MIN, REPEAT_ACCEL, DELAY_MS, DUPLICATE_PERCENT, JITTER_MS, PACKET_LOSS};
+use crate::input_dispatch::InputIntent;
+use crate::strings::{Strings, Text};
+use crate::types::Direction;
 
 use macroquad::prelude::*;
 use std::collections::HashMap;
 
+/// Keys that move the player
+pub const MOVE_BINDINGS: &[(KeyCode, Direction)] = &[
+    (KeyCode::W, Direction::Up),
+    (KeyCode::A, Direction::Left),
+    (KeyCode::S, Direction::Down),
+    (KeyCode::D, Direction::Right),
+];
+
+/// Keys that pan the spectator camera, the arrow keys as well since nothing is typed while spectating
+pub const PAN_BINDINGS: &[(KeyCode, Direction)] = &[
+    (KeyCode::W, Direction::Up),
+    (KeyCode::A, Direction::Left),
+    (KeyCode::S, Direction::Down),
+    (KeyCode::D, Direction::Right),
+    (KeyCode::Up, Direction::Up),
+    (KeyCode::Left, Direction::Left),
+    (KeyCode::Down, Direction::Down),
+    (KeyCode::Right, Direction::Right),
+];
+
+/// Actions of the toolbar that are bound to a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveLeft,
+    MoveDown,
+    MoveRight,
+    DelayDown,
+    DelayUp,
+    JitterDown,
+    JitterUp,
+    LossDown,
+    LossUp,
+    DuplicatesDown,
+    DuplicatesUp,
+    ToggleConnection,
+    StartTests,
+}
+
+/// Movement actions in toolbar order, with the direction each one moves
+const MOVE_ACTIONS: [(Action, Direction); 4] = [
+    (Action::MoveUp, Direction::Up),
+    (Action::MoveLeft, Direction::Left),
+    (Action::MoveDown, Direction::Down),
+    (Action::MoveRight, Direction::Right),
+];
+
+/// Key of every bound action. An action without a key is unbound, a key triggers one action at most.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    keys: HashMap<Action, KeyCode>,
+}
+
+/// Implementation of the KeyBindings
+impl KeyBindings {
+    /// Creates the default bindings
+    pub fn new() -> Self {
+        let mut bindings = Self { keys: HashMap::new() };
+        for (action, (key, _)) in MOVE_ACTIONS.iter().zip(MOVE_BINDINGS) {
+            bindings.bind(action.0, *key);
+        }
+        for (action, key) in [
+            (Action::DelayDown, KeyCode::V),
+            (Action::DelayUp, KeyCode::B),
+            (Action::JitterDown, KeyCode::J),
+            (Action::JitterUp, KeyCode::K),
+            (Action::LossDown, KeyCode::N),
+            (Action::LossUp, KeyCode::M),
+            (Action::DuplicatesDown, KeyCode::Y),
+            (Action::DuplicatesUp, KeyCode::U),
+            (Action::ToggleConnection, KeyCode::R),
+            (Action::StartTests, KeyCode::T),
+        ] {
+            bindings.bind(action, key);
+        }
+        bindings
+    }
+
+    /// Key of an action, None when it is unbound
+    pub fn key(&self, action: Action) -> Option<KeyCode> {
+        self.keys.get(&action).copied()
+    }
+
+    /// Binds a key to an action, taking it from any other action it was bound to
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+        self.keys.retain(|_, bound| *bound != key);
+        self.keys.insert(action, key);
+    }
+
+    /// Leaves an action without a key
+    pub fn unbind(&mut self, action: Action) {
+        self.keys.remove(&action);
+    }
+
+    /// Checks if the key of an action was pressed this frame
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.key(action).is_some_and(is_key_pressed)
+    }
+
+    /// Keys of the bound movement actions with the direction each one moves
+    pub fn move_bindings(&self) -> Vec<(KeyCode, Direction)> {
+        MOVE_ACTIONS.iter()
+            .filter_map(|(action, dir)| Some((self.key(*action)?, *dir)))
+            .collect()
+    }
+
+    /// Hint for a group of actions, such as "[V/B]". Without a separator single character keys are written
+    /// together, like "[WASD]", and other keys are joined by commas. Unbound actions of a group show as "unbound".
+    pub fn hint(&self, actions: &[Action], separator: &str) -> String {
+        let labels: Vec<Option<String>> = actions.iter().map(|action| self.key(*action).map(key_label)).collect();
+        if labels.iter().all(Option::is_none) {
+            return "[unbound]".to_string();
+        }
+        let separator = match separator {
+            "" if labels.iter().all(|label| label.as_ref().is_some_and(|label| label.chars().count() == 1)) => "",
+            "" => ",",
+            separator => separator,
+        };
+        let labels: Vec<String> = labels.into_iter().map(|label| label.unwrap_or_else(|| "unbound".to_string())).collect();
+        format!("[{}]", labels.join(separator))
+    }
+
+    /// Hints of every toolbar action
+    pub fn toolbar_hints(&self) -> ToolbarHints {
+        let movement: Vec<Action> = MOVE_ACTIONS.iter().map(|(action, _)| *action).collect();
+        ToolbarHints {
+            movement: self.hint(&movement, ""),
+            delay: self.hint(&[Action::DelayDown, Action::DelayUp], "/"),
+            jitter: self.hint(&[Action::JitterDown, Action::JitterUp], "/"),
+            packet_loss: self.hint(&[Action::LossDown, Action::LossUp], "/"),
+            duplicates: self.hint(&[Action::DuplicatesDown, Action::DuplicatesUp], "/"),
+            connection: self.hint(&[Action::ToggleConnection], ""),
+            test: self.hint(&[Action::StartTests], ""),
+        }
+    }
+}
+
+/// Default implementation for the KeyBindings
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Short readable name of a key, the character it types where there is one
+pub fn key_label(key: KeyCode) -> String {
+    let label = match key {
+        KeyCode::Up => "↑",
+        KeyCode::Down => "↓",
+        KeyCode::Left => "←",
+        KeyCode::Right => "→",
+        KeyCode::Apostrophe => "'",
+        KeyCode::Comma => ",",
+        KeyCode::Minus => "-",
+        KeyCode::Period => ".",
+        KeyCode::Slash => "/",
+        KeyCode::Semicolon => ";",
+        KeyCode::Equal => "=",
+        KeyCode::LeftBracket => "[",
+        KeyCode::Backslash => "\\",
+        KeyCode::RightBracket => "]",
+        KeyCode::GraveAccent => "`",
+        KeyCode::Escape => "Esc",
+        KeyCode::Delete => "Del",
+        KeyCode::PageUp => "PgUp",
+        KeyCode::PageDown => "PgDn",
+        KeyCode::KpDecimal => "Num .",
+        KeyCode::KpDivide => "Num /",
+        KeyCode::KpMultiply => "Num *",
+        KeyCode::KpSubtract => "Num -",
+        KeyCode::KpAdd => "Num +",
+        KeyCode::KpEnter => "Num Enter",
+        KeyCode::KpEqual => "Num =",
+        _ => {
+            // Letters, function keys and named keys read well as they are, digits drop their prefix
+            let name = format!("{:?}", key);
+            return match (name.strip_prefix("Key"), name.strip_prefix("Kp")) {
+                (Some(digit), _) => digit.to_string(),
+                (_, Some(digit)) => format!("Num {}", digit),
+                _ => name,
+            };
+        }
+    };
+    label.to_string()
+}
+
+/// Key hints of the toolbar, generated from the active bindings so they never show a key that does something else
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolbarHints {
+    pub movement: String,
+    pub delay: String,
+    pub jitter: String,
+    pub packet_loss: String,
+    pub duplicates: String,
+    pub connection: String,
+    pub test: String,
+}
+
+/// Implementation of the ToolbarHints
+impl ToolbarHints {
+    /// Label of the movement keys
+    pub fn movement_label(&self, strings: &Strings) -> String {
+        strings.format(Text::Movement, &[&self.movement])
+    }
+
+    /// Network conditions with the keys that adjust them
+    pub fn network_label(&self, strings: &Strings, delay_ms: i32, jitter_ms: i32, packet_loss: i32, duplicate_percent: i32) -> String {
+        strings.format(
+            Text::NetworkConditions,
+            &[&delay_ms, &self.delay, &jitter_ms, &self.jitter, &packet_loss, &self.packet_loss, &duplicate_percent, &self.duplicates],
+        )
+    }
+
+    /// Label of the connection key, which drops or restores the connection
+    pub fn connection_label(&self, strings: &Strings, is_connected: bool) -> String {
+        let action = if is_connected { Text::DropConnection } else { Text::Reconnect };
+        strings.format(action, &[&self.connection])
+    }
+
+    /// Label of the performance test key
+    pub fn test_label(&self, strings: &Strings) -> String {
+        strings.format(Text::Test, &[&self.test])
+    }
+}
+
 /// Input handler for managing player inputs and network conditions
 pub struct InputHandler {
     key_timers: HashMap<KeyCode, f32>,
     key_states: HashMap<KeyCode, bool>,
     pub delay_ms: i32,
+    pub jitter_ms: i32,
     pub packet_loss: i32,
+    pub duplicate_percent: i32,
+    pub step_mode: bool, // One input per key press, holding a key doesn't repeat it
+    pub keys: KeyBindings, // Keys of the toolbar actions
+    bindings: Vec<(KeyCode, Direction)>, // Keys read and the direction each one stands for
 }
 
 /// Implementation of the InputHandler
 impl InputHandler {
     /// Creates a new InputHandler with default settings
     pub fn new() -> Self {
+        Self::with_keys(KeyBindings::new())
+    }
+
+    /// Creates an InputHandler that moves with the movement keys of the bindings
+    pub fn with_keys(keys: KeyBindings) -> Self {
+        Self {
+            bindings: keys.move_bindings(),
+            keys,
+            ..Self::with_bindings(&[])
+        }
+    }
+
+    /// Creates an InputHandler reading another set of keys, such as the spectator pan keys
+    pub fn with_bindings(bindings: &[(KeyCode, Direction)]) -> Self {
         InputHandler {
             key_timers: HashMap::new(),
             key_states: HashMap::new(),
             delay_ms: DELAY_MS,
+            jitter_ms: JITTER_MS,
             packet_loss: PACKET_LOSS,
+            duplicate_percent: DUPLICATE_PERCENT,
+            step_mode: false,
+            keys: KeyBindings::new(),
+            bindings: bindings.to_vec(),
         }
     }
 
+    /// Directions of the bound keys held down right now, for input that isn't sent such as panning
+    pub fn held_directions(&self) -> Vec<Direction> {
+        self.bindings.iter()
+            .filter(|(key, _)| is_key_down(*key))
+            .map(|(_, dir)| *dir)
+            .collect()
+    }
+
     /// Input keys for selector input
     pub fn handle_selector_input(&mut self) {
-        if is_key_pressed(KeyCode::V) {
+        if self.keys.is_pressed(Action::DelayDown) {
             self.delay_ms = (self.delay_ms - 10).max(0);
         }
-        if is_key_pressed(KeyCode::B) {
+        if self.keys.is_pressed(Action::DelayUp) {
             self.delay_ms = (self.delay_ms + 10).min(1000);
         }
-        if is_key_pressed(KeyCode::N) {
+        if self.keys.is_pressed(Action::JitterDown) {
+            self.jitter_ms = (self.jitter_ms - 5).max(0);
+        }
+        if self.keys.is_pressed(Action::JitterUp) {
+            self.jitter_ms = (self.jitter_ms + 5).min(500);
+        }
+        if self.keys.is_pressed(Action::LossDown) {
             self.packet_loss = (self.packet_loss - 1).max(0);
         }
-        if is_key_pressed(KeyCode::M) {
+        if self.keys.is_pressed(Action::LossUp) {
             self.packet_loss = (self.packet_loss + 1).min(100);
         }
+        if self.keys.is_pressed(Action::DuplicatesDown) {
+            self.duplicate_percent = (self.duplicate_percent - 5).max(0);
+        }
+        if self.keys.is_pressed(Action::DuplicatesUp) {
+            self.duplicate_percent = (self.duplicate_percent + 5).min(100);
+        }
+        if is_key_pressed(KeyCode::F9) {
+            self.step_mode = !self.step_mode;
+        }
+    }
+
+    /// Handles the movement keys with their repeat. Returns the intents of the moves made this frame,
+    /// sampled at the local time now, for the caller to dispatch through the GameClient.
+    pub fn handle_input(&mut self, dt: f32, now: f64) -> Vec<InputIntent> {
+        self.intents_from_keys(is_key_down, dt, now)
     }
 
-    /// Handles player input and applies prediction logic
-    pub fn handle_input(
-        &mut self,
-        my_pos: &mut Position,
-        net: &mut NetworkClient,
-        dt: f32,
-        prediction: &mut PredictionState,
-    ) {
-        // Input handling and prediction
-        for &key in &[KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D] {
-            let is_down = is_key_down(key);
+    /// Makes the intents of a frame from which keys are down. In step mode a held key never repeats.
+    pub fn intents_from_keys(&mut self, key_down: impl Fn(KeyCode) -> bool, dt: f32, now: f64) -> Vec<InputIntent> {
+        let mut sent = Vec::new();
+
+        // Input handling with the key repeat
+        for &(key, dir) in &self.bindings {
+            let is_down = key_down(key);
             let was_down = *self.key_states.get(&key).unwrap_or(&false);
 
             if is_down && !was_down {
@@ -60,31 +334,8 @@ impl InputHandler {
                 self.key_timers.insert(key, INITIAL_DELAY);
                 self.key_states.insert(key, true);
 
-                // Create and send input
-                let dir = match key {
-                    KeyCode::W => Direction::Up,
-                    KeyCode::A => Direction::Left,
-                    KeyCode::S => Direction::Down,
-                    KeyCode::D => Direction::Right,
-                    _ => continue,
-                };
-
-                let input = PlayerInput {
-                    dir,
-                    sequence: prediction.next_sequence,
-                    timestamp: get_time() as u64,
-                };
-
-                // Store input for prediction
-                prediction.pending_inputs.push_back((prediction.next_sequence, input.clone()));
-                prediction.next_sequence += 1;
-
-                // Send to server
-                net.send_input(input.clone());
-
-                // Apply prediction locally
-                prediction.apply_prediction(input, my_pos);
-            } else if is_down && was_down {
+                sent.push(InputIntent { dir, originated_at: now });
+            } else if is_down && was_down && !self.step_mode {
                 // Key is still down, update timer
                 let timer = self.key_timers.entry(key).or_insert(INITIAL_DELAY);
                 *timer -= dt;
@@ -94,30 +345,7 @@ impl InputHandler {
                     let next_interval = (*timer + REPEAT_START) * REPEAT_ACCEL;
                     *timer = next_interval.max(REPEAT_MIN);
 
-                    // Create and send input
-                    let dir = match key {
-                        KeyCode::W => Direction::Up,
-                        KeyCode::A => Direction::Left,
-                        KeyCode::S => Direction::Down,
-                        KeyCode::D => Direction::Right,
-                        _ => continue,
-                    };
-
-                    let input = PlayerInput {
-                        dir,
-                        sequence: prediction.next_sequence,
-                        timestamp: get_time() as u64,
-                    };
-
-                    // Store input for prediction
-                    prediction.pending_inputs.push_back((prediction.next_sequence, input.clone()));
-                    prediction.next_sequence += 1;
-
-                    // Send to server
-                    net.send_input(input.clone());
-
-                    // Apply prediction locally
-                    prediction.apply_prediction(input, my_pos);
+                    sent.push(InputIntent { dir, originated_at: now });
                 }
             } else if !is_down && was_down {
                 // Key released: reset state
@@ -125,6 +353,14 @@ impl InputHandler {
                 self.key_timers.remove(&key);
             }
         }
+        sent
+    }
+}
+
+/// Default implementation for the InputHandler
+impl Default for InputHandler {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -132,6 +368,7 @@ impl InputHandler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::strings::Language;
 
     #[test]
     fn test_new_input_handler() {
@@ -139,7 +376,116 @@ mod tests {
         assert!(handler.key_timers.is_empty());
         assert!(handler.key_states.is_empty());
         assert_eq!(handler.delay_ms, DELAY_MS);
+        assert_eq!(handler.jitter_ms, JITTER_MS);
         assert_eq!(handler.packet_loss, PACKET_LOSS);
+        assert_eq!(handler.duplicate_percent, DUPLICATE_PERCENT);
+        assert!(!handler.step_mode);
+        assert_eq!(handler.bindings, MOVE_BINDINGS);
+    }
+
+    #[test]
+    fn test_pan_bindings_cover_the_move_keys() {
+        let handler = InputHandler::with_bindings(PAN_BINDINGS);
+        for binding in MOVE_BINDINGS {
+            assert!(handler.bindings.contains(binding), "{:?}", binding);
+        }
+        assert_eq!(handler.delay_ms, DELAY_MS);
+    }
+
+    #[test]
+    fn test_key_labels() {
+        // Letters, digits and function keys
+        assert_eq!(key_label(KeyCode::W), "W");
+        assert_eq!(key_label(KeyCode::Key7), "7");
+        assert_eq!(key_label(KeyCode::F1), "F1");
+        assert_eq!(key_label(KeyCode::F12), "F12");
+
+        // Punctuation types its character
+        assert_eq!(key_label(KeyCode::Semicolon), ";");
+        assert_eq!(key_label(KeyCode::Comma), ",");
+        assert_eq!(key_label(KeyCode::Slash), "/");
+        assert_eq!(key_label(KeyCode::Backslash), "\\");
+        assert_eq!(key_label(KeyCode::LeftBracket), "[");
+
+        // Arrows, the keypad and named keys
+        assert_eq!([KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right].map(key_label).concat(), "↑↓←→");
+        assert_eq!(key_label(KeyCode::Kp4), "Num 4");
+        assert_eq!(key_label(KeyCode::KpAdd), "Num +");
+        assert_eq!(key_label(KeyCode::Escape), "Esc");
+        assert_eq!(key_label(KeyCode::Space), "Space");
+    }
+
+    #[test]
+    fn test_default_toolbar_hints() {
+        let hints = KeyBindings::new().toolbar_hints();
+        let strings = Strings::default();
+        assert_eq!(hints.movement_label(&strings), "Movement [WASD]");
+        assert_eq!(
+            hints.network_label(&strings, 50, 5, 2, 20),
+            "Delay: 50 ms [V/B]   Jitter: 5 ms [J/K]   Packet Loss: 2% [N/M]   Duplicates: 20% [Y/U]"
+        );
+        assert_eq!(hints.connection_label(&strings, true), "Drop connection [R]");
+        assert_eq!(hints.connection_label(&strings, false), "Reconnect [R]");
+        assert_eq!(hints.test_label(&strings), "Test [T]");
+    }
+
+    #[test]
+    fn test_toolbar_follows_a_language_switch() {
+        let hints = KeyBindings::new().toolbar_hints();
+        let mut strings = Strings::default();
+        let english = (hints.movement_label(&strings), hints.network_label(&strings, 50, 5, 2, 20), hints.connection_label(&strings, true));
+
+        // The same hints are labeled in the new language from the next frame on, and back again
+        strings.set_language(Language::Norwegian);
+        assert_eq!(hints.movement_label(&strings), "Bevegelse [WASD]");
+        assert_eq!(
+            hints.network_label(&strings, 50, 5, 2, 20),
+            "Forsinkelse: 50 ms [V/B]   Jitter: 5 ms [J/K]   Pakketap: 2% [N/M]   Duplikater: 20% [Y/U]"
+        );
+        assert_eq!(hints.connection_label(&strings, false), "Koble til igjen [R]");
+        strings.set_language(Language::English);
+        assert_eq!((hints.movement_label(&strings), hints.network_label(&strings, 50, 5, 2, 20), hints.connection_label(&strings, true)), english);
+    }
+
+    #[test]
+    fn test_hints_follow_rebound_keys() {
+        let mut keys = KeyBindings::new();
+        for (action, key) in [(Action::MoveUp, KeyCode::Up), (Action::MoveLeft, KeyCode::Left), (Action::MoveDown, KeyCode::Down), (Action::MoveRight, KeyCode::Right)] {
+            keys.bind(action, key);
+        }
+        keys.bind(Action::DelayDown, KeyCode::Semicolon);
+        keys.bind(Action::StartTests, KeyCode::F5);
+        let hints = keys.toolbar_hints();
+        assert_eq!(hints.movement, "[↑←↓→]");
+        assert_eq!(hints.delay, "[;/B]");
+        assert_eq!(hints.test, "[F5]");
+
+        // Keys that aren't one character keep a separator
+        keys.bind(Action::MoveUp, KeyCode::PageUp);
+        assert_eq!(keys.toolbar_hints().movement, "[PgUp,←,↓,→]");
+
+        // A key does one thing, binding it elsewhere takes it from the action it had
+        keys.bind(Action::JitterUp, KeyCode::B);
+        assert_eq!(keys.key(Action::DelayUp), None);
+        assert_eq!(keys.toolbar_hints().delay, "[;/unbound]");
+        assert_eq!(keys.toolbar_hints().jitter, "[J/B]");
+    }
+
+    #[test]
+    fn test_unbound_actions() {
+        let mut keys = KeyBindings::new();
+        keys.unbind(Action::ToggleConnection);
+        keys.unbind(Action::LossDown);
+        keys.unbind(Action::LossUp);
+        keys.unbind(Action::MoveLeft);
+        let hints = keys.toolbar_hints();
+        assert_eq!(hints.connection_label(&Strings::default(), true), "Drop connection [unbound]");
+        assert_eq!(hints.packet_loss, "[unbound]");
+        assert_eq!(hints.movement, "[W,unbound,S,D]");
+
+        // Movement only reads the bound keys
+        let handler = InputHandler::with_keys(keys);
+        assert_eq!(handler.bindings, vec![(KeyCode::W, Direction::Up), (KeyCode::S, Direction::Down), (KeyCode::D, Direction::Right)]);
     }
 
     #[test]
@@ -175,4 +521,28 @@ mod tests {
         assert_eq!(handler.key_states.get(&KeyCode::W), Some(&false));
         assert!(!handler.key_timers.contains_key(&KeyCode::W));
     }
+
+    #[test]
+    fn test_intents_from_scripted_key_states() {
+        let mut handler = InputHandler::new();
+        let held = |keys: &'static [KeyCode]| move |key: KeyCode| keys.contains(&key);
+        let dt = 0.06;
+
+        // A press moves at once, stamped with the frame time, holding it waits out the initial delay
+        assert_eq!(handler.intents_from_keys(held(&[KeyCode::D]), dt, 1.0), vec![InputIntent { dir: Direction::Right, originated_at: 1.0 }]);
+        assert!(handler.intents_from_keys(held(&[KeyCode::D]), dt, 1.06).is_empty());
+        assert!(handler.intents_from_keys(held(&[KeyCode::D]), dt, 1.12).is_empty());
+        assert_eq!(handler.intents_from_keys(held(&[KeyCode::D]), dt, 1.18), vec![InputIntent { dir: Direction::Right, originated_at: 1.18 }]);
+
+        // Keys pressed together move in the order of the bindings, a released key presses anew
+        let both = handler.intents_from_keys(held(&[KeyCode::W, KeyCode::S]), dt, 1.2);
+        assert_eq!(both.iter().map(|intent| intent.dir).collect::<Vec<_>>(), vec![Direction::Up, Direction::Down]);
+        assert!(handler.intents_from_keys(held(&[]), dt, 1.25).is_empty());
+        assert_eq!(handler.intents_from_keys(held(&[KeyCode::D]), dt, 1.3).len(), 1);
+
+        // In step mode a held key never repeats
+        handler.step_mode = true;
+        let repeats: usize = (0..20).map(|frame| handler.intents_from_keys(held(&[KeyCode::D]), dt, 1.35 + frame as f64 * 0.05).len()).sum();
+        assert_eq!(repeats, 0);
+    }
 }
\ No newline at end of file
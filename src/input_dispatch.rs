@@ -0,0 +1,123 @@
+use crate::client_world::ClientWorld;
+use crate::network::NetworkClient;
+use crate::types::{Direction, PlayerInput};
+
+use std::collections::VecDeque;
+
+/// A move the player asked for, before it has a sequence. Made by whatever samples the input, the
+/// keyboard or a script, without knowing anything about the network.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputIntent {
+    pub dir: Direction,
+    pub originated_at: f64, // Local time in seconds the input was sampled at, the input is stamped with it
+}
+
+/// Turns intents into inputs on one path for every source: assigns their sequences, applies them to the
+/// prediction and hands them to the network client, which sends them when the send rate allows
+#[derive(Debug, Default)]
+pub struct InputDispatcher {
+    queue: VecDeque<InputIntent>, // Intents not dispatched yet, oldest first
+}
+
+/// Implementation of the InputDispatcher
+impl InputDispatcher {
+    /// Creates a dispatcher with nothing queued
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues intents for the next dispatch. They are kept in the order they were sampled in, intents
+    /// sampled at the same time keep the order they were pushed in.
+    pub fn push(&mut self, intents: impl IntoIterator<Item = InputIntent>) {
+        for intent in intents {
+            let at = self.queue.partition_point(|queued| queued.originated_at <= intent.originated_at);
+            self.queue.insert(at, intent);
+        }
+    }
+
+    /// Number of intents waiting for a dispatch
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Drops the queued intents, when the world they were meant for is gone
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Takes the queued intents oldest first and makes each the next input of the prediction, applying it
+    /// to the predicted position before the next one gets its sequence. Returns the inputs in that order.
+    pub fn predict(&mut self, world: &mut ClientWorld) -> Vec<PlayerInput> {
+        self.queue.drain(..)
+            .map(|intent| world.prediction.predict_input(intent.dir, (intent.originated_at * 1000.0) as u64, &mut world.my_pos))
+            .collect()
+    }
+
+    /// Predicts the queued intents and queues the inputs for sending, then sends what the send rate says
+    /// is due. Returns the inputs made.
+    pub fn dispatch(&mut self, world: &mut ClientWorld, net: &mut NetworkClient) -> Vec<PlayerInput> {
+        let inputs = self.predict(world);
+        world.queue_inputs(inputs.clone(), net);
+        world.flush_inputs(net);
+        inputs
+    }
+}
+
+/// Tests for the InputDispatcher
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::positions::AuthoritativePos;
+    use crate::types::Position;
+
+    // Helper function to create an intent
+    fn intent(dir: Direction, originated_at: f64) -> InputIntent {
+        InputIntent { dir, originated_at }
+    }
+
+    #[test]
+    fn test_sequences_follow_the_sampling_order() {
+        let mut dispatcher = InputDispatcher::new();
+        let mut world = ClientWorld::new();
+        world.prediction.next_sequence = 7;
+
+        // Pushed out of order, the later frame first
+        dispatcher.push([intent(Direction::Down, 0.032), intent(Direction::Left, 0.032)]);
+        dispatcher.push([intent(Direction::Right, 0.016), intent(Direction::Up, 0.016)]);
+        assert_eq!(dispatcher.pending(), 4);
+
+        let inputs = dispatcher.predict(&mut world);
+        assert_eq!(dispatcher.pending(), 0);
+        assert_eq!(inputs.iter().map(|input| input.sequence).collect::<Vec<_>>(), vec![7, 8, 9, 10]);
+        assert_eq!(inputs.iter().map(|input| input.dir).collect::<Vec<_>>(), vec![Direction::Right, Direction::Up, Direction::Down, Direction::Left]);
+        assert_eq!(inputs.iter().map(|input| input.timestamp).collect::<Vec<_>>(), vec![16, 16, 32, 32]);
+        assert_eq!(world.prediction.next_sequence, 11);
+        assert!(dispatcher.predict(&mut world).is_empty());
+    }
+
+    #[test]
+    fn test_each_input_is_predicted_before_the_next() {
+        let mut dispatcher = InputDispatcher::new();
+        let mut world = ClientWorld::new();
+        world.my_pos = AuthoritativePos::new(Position { x: 300, y: 300 });
+        dispatcher.push([intent(Direction::Right, 0.0), intent(Direction::Right, 0.0), intent(Direction::Down, 0.0)]);
+        let inputs = dispatcher.predict(&mut world);
+
+        // Pending in sequence order, and the history holds the position before each input
+        let pending: Vec<PlayerInput> = world.prediction.pending_inputs.iter().map(|(_, input)| *input).collect();
+        assert_eq!(pending, inputs);
+        let before: Vec<Position> = inputs.iter()
+            .map(|input| world.prediction.position_history.find(&input.sequence).unwrap().position.position())
+            .collect();
+        assert_eq!(before[0], Position { x: 300, y: 300 });
+        assert!(before[1].x > before[0].x && before[1].y == 300);
+        assert!(before[2].x > before[1].x && before[2].y == 300);
+        assert!(world.my_pos.y > 300 && world.my_pos.x == before[2].x);
+
+        // Cleared intents are never predicted
+        dispatcher.push([intent(Direction::Up, 1.0)]);
+        dispatcher.clear();
+        assert!(dispatcher.predict(&mut world).is_empty());
+        assert_eq!(world.prediction.next_sequence, 3);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::constants::INPUT_LOG_ROWS;
+use crate::types::{Direction, PlayerInput};
+
+use std::collections::VecDeque;
+
+/// What is known about a sent input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputLogStatus {
+    Pending, // Sent, the server hasn't confirmed it yet
+    Acked,   // The server's last processed sequence covers it
+    Dropped, // Lost by the simulated packet loss before it was sent
+}
+
+/// Implementation of the InputLogStatus
+impl InputLogStatus {
+    /// Short label for the log table
+    pub fn label(self) -> &'static str {
+        match self {
+            InputLogStatus::Pending => "pending",
+            InputLogStatus::Acked => "acked",
+            InputLogStatus::Dropped => "dropped",
+        }
+    }
+}
+
+/// One sent input in the log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputLogEntry {
+    pub sequence: u32,
+    pub dir: Direction,
+    pub status: InputLogStatus,
+}
+
+/// The most recent inputs the client sent and whether the server has seen them, for explaining sequence numbers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputLog {
+    entries: VecDeque<InputLogEntry>, // Oldest first, at most INPUT_LOG_ROWS
+}
+
+/// Implementation of the InputLog
+impl InputLog {
+    /// Creates an empty log
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a sent input, the oldest entry is forgotten once the log is full
+    pub fn record(&mut self, input: PlayerInput) {
+        if self.entries.len() == INPUT_LOG_ROWS {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(InputLogEntry { sequence: input.sequence, dir: input.dir, status: InputLogStatus::Pending });
+    }
+
+    /// Marks an input lost by the simulated packet loss
+    pub fn mark_dropped(&mut self, sequence: u32) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.sequence == sequence) {
+            entry.status = InputLogStatus::Dropped;
+        }
+    }
+
+    /// Marks every pending input up to the last sequence the server processed as acked.
+    /// Dropped inputs stay dropped, the server skipped them.
+    pub fn acknowledge(&mut self, last_processed: u32) {
+        for entry in self.entries.iter_mut().filter(|entry| entry.sequence <= last_processed) {
+            if entry.status == InputLogStatus::Pending {
+                entry.status = InputLogStatus::Acked;
+            }
+        }
+    }
+
+    /// The logged inputs, newest first
+    pub fn newest_first(&self) -> impl Iterator<Item = &InputLogEntry> {
+        self.entries.iter().rev()
+    }
+
+    /// Forgets every logged input
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Tests for the input log
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to create an input moving up with the given sequence
+    fn input(sequence: u32) -> PlayerInput {
+        PlayerInput { dir: Direction::Up, sequence, timestamp: 0 }
+    }
+
+    #[test]
+    fn test_log_keeps_the_newest_rows() {
+        let mut log = InputLog::new();
+        for sequence in 0..INPUT_LOG_ROWS as u32 + 3 {
+            log.record(input(sequence));
+        }
+
+        let sequences: Vec<u32> = log.newest_first().map(|entry| entry.sequence).collect();
+        let expected: Vec<u32> = (3..INPUT_LOG_ROWS as u32 + 3).rev().collect();
+        assert_eq!(sequences, expected);
+        assert!(log.newest_first().all(|entry| entry.status == InputLogStatus::Pending));
+
+        log.clear();
+        assert_eq!(log.newest_first().count(), 0);
+    }
+
+    #[test]
+    fn test_acknowledge_and_drop() {
+        let mut log = InputLog::new();
+        for sequence in 0..4 {
+            log.record(input(sequence));
+        }
+        log.mark_dropped(1);
+        log.mark_dropped(9); // Not logged, ignored
+
+        // The server processed up to 2, the lost input stays dropped
+        log.acknowledge(2);
+        let statuses: Vec<InputLogStatus> = log.newest_first().map(|entry| entry.status).collect();
+        assert_eq!(statuses, vec![InputLogStatus::Pending, InputLogStatus::Acked, InputLogStatus::Dropped, InputLogStatus::Acked]);
+
+        // An older last processed sequence from a reordered snapshot doesn't undo anything
+        log.acknowledge(0);
+        assert_eq!(log.newest_first().nth(1).unwrap().status, InputLogStatus::Acked);
+        log.acknowledge(3);
+        assert!(log.newest_first().all(|entry| entry.status != InputLogStatus::Pending));
+    }
+}
@@ -1,88 +1,563 @@
+use crate::positions::DisplayPos;
 use crate::types::{InterpolatedPosition, Position};
-use crate::constants::{INTERPOLATION_DELAY, MAX_POSITION_HISTORY};
+use crate::util::RingHistory;
+use crate::constants::{
+    BURST_RECOVERY_TIME, CLOCK_DRIFT_MIN_WINDOWS, CLOCK_DRIFT_WINDOW, CLOCK_DRIFT_WINDOWS, CLOCK_MAX_DRIFT_RATE,
+    CLOCK_STEP_THRESHOLD, INTERPOLATION_DELAY, INTERPOLATION_MAX_SPEED, MAX_EXTRAPOLATION, MAX_POSITION_HISTORY,
+    MAX_REMOTE_PLAYERS, REMOTE_PLAYER_STALE_AFTER, SERVER_CLOCK_RESYNC,
+};
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
 
-/// Represents a position with a timestamp and sequence number for interpolation
+/// Server time of a tick in seconds
+pub fn tick_time(snapshot_id: u64, tick_interval_ms: u32) -> f64 {
+    snapshot_id as f64 * tick_interval_ms as f64 / 1000.0
+}
+
+/// Result of looking up a position in a history at a given time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PositionAt {
+    Exact(Position),   // Within the span of the history, interpolated between the entries around the time
+    Clamped(Position), // Outside the span, the entry closest to the time
+    Unavailable,       // Nothing in the history
+}
+
+/// Implementation of the PositionAt
+impl PositionAt {
+    /// The position found, exact or clamped
+    pub fn position(self) -> Option<Position> {
+        match self {
+            PositionAt::Exact(position) | PositionAt::Clamped(position) => Some(position),
+            PositionAt::Unavailable => None,
+        }
+    }
+}
+
+/// How the positions between and after the buffered ticks are played back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InterpolationStrategy {
+    #[default]
+    Linear,                  // Straight lines between ticks, held at the newest position when the buffer runs dry
+    Hermite,                 // Cubic curves through the ticks with the speed at each tick, smoother turns
+    LinearWithExtrapolation, // Linear, carried on at the last speed for up to MAX_EXTRAPOLATION when the buffer runs dry
+}
+
+/// Implementation of the InterpolationStrategy
+impl InterpolationStrategy {
+    /// Every strategy, in the order the comparison lists them
+    pub const ALL: [InterpolationStrategy; 3] = [InterpolationStrategy::Linear, InterpolationStrategy::Hermite, InterpolationStrategy::LinearWithExtrapolation];
+
+    /// Name of the strategy, for the --interpolation flag and the comparison table
+    pub fn name(self) -> &'static str {
+        match self {
+            InterpolationStrategy::Linear => "linear",
+            InterpolationStrategy::Hermite => "hermite",
+            InterpolationStrategy::LinearWithExtrapolation => "extrapolate",
+        }
+    }
+
+    /// Parses the name of a strategy, ignoring case
+    pub fn parse(text: &str) -> Option<Self> {
+        InterpolationStrategy::ALL.into_iter().find(|strategy| strategy.name().eq_ignore_ascii_case(text.trim()))
+    }
+}
+
+/// Buffers the positions of a remote player keyed by server tick, and plays them back
+/// against the estimated server time so jitter in delivery doesn't affect playback speed
 pub struct InterpolationState {
-    position_history: VecDeque<InterpolatedPosition>,
-    interpolation_delay: f32,
-    last_sequence: u32,
+    position_history: RingHistory<InterpolatedPosition, u64>, // Sorted by snapshot id
+    interpolation_delay: f64,
+    tick_interval_ms: Option<u32>,
     last_position: Option<Position>,
+    max_speed: f64, // Fastest movement between two samples that is believed, in px per second of server time
+    suspect: Option<InterpolatedPosition>, // Sample that moved too fast, held back until the next one agrees with it
+    rejected_samples: u32, // Samples dropped as spikes
+    strategy: InterpolationStrategy,
 }
 
 /// Implementation of the InterpolationState
 impl InterpolationState {
     /// Creates a new InterpolationState with default values
     pub fn new() -> Self {
+        Self::with_max_speed(INTERPOLATION_MAX_SPEED)
+    }
+
+    /// Creates an InterpolationState that treats samples implying more than max_speed px/s as spikes
+    pub fn with_max_speed(max_speed: f64) -> Self {
         Self {
-            position_history: VecDeque::with_capacity(MAX_POSITION_HISTORY),
+            position_history: RingHistory::new(MAX_POSITION_HISTORY, |pos| pos.snapshot_id),
             interpolation_delay: INTERPOLATION_DELAY,
-            last_sequence: 0,
+            tick_interval_ms: None,
             last_position: None,
+            max_speed,
+            suspect: None,
+            rejected_samples: 0,
+            strategy: InterpolationStrategy::default(),
         }
     }
 
-    /// Function to add a new position to the history
-    pub fn add_position(&mut self, position: Position, timestamp: f32, sequence: u32) {
-        // Skip if we already have this sequence
-        if sequence <= self.last_sequence {
-            return;
+    /// How positions are played back
+    pub fn strategy(&self) -> InterpolationStrategy {
+        self.strategy
+    }
+
+    /// Plays the buffered positions back another way from the next lookup on
+    pub fn set_strategy(&mut self, strategy: InterpolationStrategy) {
+        self.strategy = strategy;
+    }
+
+    /// Function to add the position of a player in the snapshot with the given id
+    pub fn add_position(&mut self, position: Position, snapshot_id: u64, tick_interval_ms: u32) {
+        // Ticks of a different length are on a different timeline, start over
+        if self.tick_interval_ms != Some(tick_interval_ms) {
+            self.position_history.clear();
+            self.suspect = None;
+            self.tick_interval_ms = Some(tick_interval_ms);
         }
-        self.last_sequence = sequence;
 
-        // Add new position to history
-        self.position_history.push_back(InterpolatedPosition {
+        let sample = InterpolatedPosition {
             position,
-            timestamp,
-            sequence,
-        });
+            timestamp: tick_time(snapshot_id, tick_interval_ms),
+            snapshot_id,
+        };
 
-        // Keep only the last MAX_POSITION_HISTORY entries
-        while self.position_history.len() > MAX_POSITION_HISTORY {
-            self.position_history.pop_front();
+        // A sample that moves too fast is held back. If the next one agrees with it the player
+        // really teleported and both are kept, otherwise it was a lone spike and is dropped.
+        if self.is_spike(&sample) {
+            match self.suspect.take() {
+                Some(suspect) if suspect.snapshot_id < sample.snapshot_id && self.is_believable(&suspect, &sample) => {
+                    self.position_history.insert(suspect);
+                    self.position_history.insert(sample);
+                }
+                previous => {
+                    self.rejected_samples += u32::from(previous.is_some());
+                    self.suspect = Some(sample);
+                }
+            }
+        } else {
+            self.rejected_samples += u32::from(self.suspect.take().is_some());
+
+            // Snapshots may arrive out of order, the history keeps them sorted by tick and skips
+            // ticks it already has or that are older than everything in a full history
+            self.position_history.insert(sample);
         }
 
-        self.last_position = Some(position);
+        self.last_position = self.position_history.newest().map(|pos| pos.position);
     }
 
-    /// Function to get the interpolated position based on the current time
-    pub fn get_interpolated_position(&self, current_time: f32) -> Option<Position> {
-        if self.position_history.len() < 2 {
-            return self.last_position;
+    /// Number of samples dropped as spikes
+    pub fn rejected_samples(&self) -> u32 {
+        self.rejected_samples
+    }
+
+    /// Checks if a sample moved too fast from the buffered sample of the tick before it.
+    /// Without an earlier sample there is nothing to compare with, so it is believed.
+    fn is_spike(&self, sample: &InterpolatedPosition) -> bool {
+        let index = self.position_history.partition_point(|pos| pos.snapshot_id < sample.snapshot_id);
+        index.checked_sub(1)
+            .and_then(|i| self.position_history.get(i))
+            .is_some_and(|previous| !self.is_believable(previous, sample))
+    }
+
+    /// Checks if moving between two samples of different ticks stays within the maximum speed
+    fn is_believable(&self, from: &InterpolatedPosition, to: &InterpolatedPosition) -> bool {
+        let dx = (to.position.x - from.position.x) as f64;
+        let dy = (to.position.y - from.position.y) as f64;
+        let elapsed = (to.timestamp - from.timestamp).abs();
+        dx.hypot(dy) <= self.max_speed * elapsed
+    }
+
+    /// Function to get the interpolated position at the estimated server time, for drawing only
+    pub fn get_interpolated_position(&self, server_time: f64) -> Option<DisplayPos> {
+        let playback_time = server_time - self.interpolation_delay;
+        if self.strategy == InterpolationStrategy::LinearWithExtrapolation {
+            if let Some(position) = self.extrapolate(playback_time) {
+                return Some(DisplayPos::smoothed(position));
+            }
         }
+        self.position_at(playback_time).position().or(self.last_position).map(DisplayPos::smoothed)
+    }
+
+    /// Position past the newest tick, carried on at the speed between the two newest ticks for at most
+    /// MAX_EXTRAPOLATION. None before the newest tick, or if the speed isn't known or believable.
+    fn extrapolate(&self, server_time: f64) -> Option<Position> {
+        let newest = self.position_history.newest()?;
+        if server_time <= newest.timestamp {
+            return None;
+        }
+        let before = self.position_history.get(self.position_history.len().checked_sub(2)?)?;
+        if !self.is_believable(before, newest) {
+            return None;
+        }
+        let ahead = (server_time - newest.timestamp).min(MAX_EXTRAPOLATION) / (newest.timestamp - before.timestamp);
+        let carry = |from: i32, to: i32| (to as f64 + (to - from) as f64 * ahead).round() as i32;
+        Some(Position { x: carry(before.position.x, newest.position.x), y: carry(before.position.y, newest.position.y) })
+    }
 
-        let target_time = current_time - self.interpolation_delay;
+    /// Position of the player at any server time the buffer covers, without the interpolation delay.
+    /// Times outside the buffered ticks are clamped to the oldest or newest one.
+    pub fn position_at(&self, server_time: f64) -> PositionAt {
+        let (Some(oldest), Some(newest)) = (self.position_history.oldest(), self.position_history.newest()) else {
+            return PositionAt::Unavailable;
+        };
+        if server_time < oldest.timestamp {
+            return PositionAt::Clamped(oldest.position);
+        }
+        if server_time > newest.timestamp {
+            return PositionAt::Clamped(newest.position);
+        }
 
         // Find the two positions to interpolate between
-        let mut prev_pos = None;
-        let mut next_pos = None;
-
-        for pos in self.position_history.iter() {
-            if pos.timestamp <= target_time {
-                prev_pos = Some(pos);
-            } else {
-                next_pos = Some(pos);
-                break;
+        let index = self.position_history.partition_point(|pos| pos.timestamp <= server_time);
+        let prev = &self.position_history[index - 1];
+        let Some(next) = self.position_history.get(index) else {
+            return PositionAt::Exact(prev.position);
+        };
+
+        let t = (server_time - prev.timestamp) / (next.timestamp - prev.timestamp);
+        match self.strategy {
+            InterpolationStrategy::Hermite => PositionAt::Exact(self.hermite(index - 1, t)),
+            InterpolationStrategy::Linear | InterpolationStrategy::LinearWithExtrapolation => PositionAt::Exact(Position {
+                x: (prev.position.x as f64 + (next.position.x - prev.position.x) as f64 * t).round() as i32,
+                y: (prev.position.y as f64 + (next.position.y - prev.position.y) as f64 * t).round() as i32,
+            }),
+        }
+    }
+
+    /// Cubic Hermite interpolation at t between the buffered entry at index and the one after it
+    fn hermite(&self, index: usize, t: f64) -> Position {
+        let (prev, next) = (&self.position_history[index], &self.position_history[index + 1]);
+        let span = next.timestamp - prev.timestamp;
+        let (from, to) = (self.velocity_at(index), self.velocity_at(index + 1));
+        let (t2, t3) = (t * t, t * t * t);
+        let (h00, h10, h01, h11) = (2.0 * t3 - 3.0 * t2 + 1.0, t3 - 2.0 * t2 + t, -2.0 * t3 + 3.0 * t2, t3 - t2);
+        let curve = |p0: i32, p1: i32, v0: f64, v1: f64| (h00 * p0 as f64 + h10 * span * v0 + h01 * p1 as f64 + h11 * span * v1).round() as i32;
+        Position {
+            x: curve(prev.position.x, next.position.x, from.0, to.0),
+            y: curve(prev.position.y, next.position.y, from.1, to.1),
+        }
+    }
+
+    /// Speed in px per second at the buffered entry at index, from its neighbours. A neighbour it couldn't
+    /// have moved from or to, such as across a teleport, is left out so the curve doesn't swing wide.
+    fn velocity_at(&self, index: usize) -> (f64, f64) {
+        let entry = &self.position_history[index];
+        let before = index.checked_sub(1)
+            .and_then(|i| self.position_history.get(i))
+            .filter(|before| self.is_believable(before, entry));
+        let after = self.position_history.get(index + 1).filter(|after| self.is_believable(entry, after));
+        let (from, to) = match (before, after) {
+            (Some(before), Some(after)) => (before, after),
+            (Some(before), None) => (before, entry),
+            (None, Some(after)) => (entry, after),
+            (None, None) => return (0.0, 0.0),
+        };
+        let elapsed = to.timestamp - from.timestamp;
+        (
+            (to.position.x - from.position.x) as f64 / elapsed,
+            (to.position.y - from.position.y) as f64 / elapsed,
+        )
+    }
+
+    /// Checks if playback at the estimated server time has run past the newest buffered position
+    pub fn is_starved(&self, server_time: f64) -> bool {
+        self.position_history.newest()
+            .is_some_and(|newest| server_time - self.interpolation_delay > newest.timestamp)
+    }
+}
+
+/// Default implementation for the InterpolationState
+impl Default for InterpolationState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A buffered remote player and when it was last updated
+struct RemotePlayer {
+    interpolation: InterpolationState,
+    updated_at: f64, // Local time of the last update in seconds
+    touch: u64,      // Update counter value of the last update, orders updates within the same frame
+}
+
+/// Interpolation buffers of the remote players. Entries are forgotten when no snapshot updated them for
+/// a while, even if no snapshot arrives to say the player left, and the number of entries is capped by
+/// forgetting the least recently updated one.
+pub struct RemotePlayers {
+    players: HashMap<Uuid, RemotePlayer>,
+    stale_after: f64, // Seconds without an update before an entry is forgotten
+    max_players: usize,
+    touches: u64,
+    strategy: InterpolationStrategy, // Given to every buffer, also the ones created later
+}
+
+/// Implementation of the RemotePlayers
+impl RemotePlayers {
+    /// Creates an empty set with the default staleness window and cap
+    pub fn new() -> Self {
+        Self::with_limits(REMOTE_PLAYER_STALE_AFTER, MAX_REMOTE_PLAYERS)
+    }
+
+    /// Creates an empty set that forgets entries after stale_after seconds without an update and keeps at most max_players
+    pub fn with_limits(stale_after: f64, max_players: usize) -> Self {
+        Self { players: HashMap::new(), stale_after, max_players: max_players.max(1), touches: 0, strategy: InterpolationStrategy::default() }
+    }
+
+    /// How the buffers play positions back
+    pub fn strategy(&self) -> InterpolationStrategy {
+        self.strategy
+    }
+
+    /// Plays every buffer back another way, the ones of players that appear later too
+    pub fn set_strategy(&mut self, strategy: InterpolationStrategy) {
+        self.strategy = strategy;
+        for player in self.players.values_mut() {
+            player.interpolation.set_strategy(strategy);
+        }
+    }
+
+    /// An empty buffer playing back with the strategy of the set, for a player buffered on its own
+    pub fn new_state(&self) -> InterpolationState {
+        let mut interpolation = InterpolationState::new();
+        interpolation.set_strategy(self.strategy);
+        interpolation
+    }
+
+    /// Adds a position of a player from the snapshot with the given id, creating its buffer if needed.
+    /// Returns the player forgotten to stay within the cap, if any.
+    pub fn update(&mut self, id: Uuid, position: Position, snapshot_id: u64, tick_interval_ms: u32, now: f64) -> Option<Uuid> {
+        let evicted = if self.players.contains_key(&id) || self.players.len() < self.max_players {
+            None
+        } else {
+            self.least_recently_updated()
+        };
+        if let Some(evicted) = evicted {
+            self.players.remove(&evicted);
+        }
+
+        self.touches += 1;
+        let interpolation = self.new_state();
+        let player = self.players.entry(id).or_insert_with(|| RemotePlayer { interpolation, updated_at: now, touch: 0 });
+        player.interpolation.add_position(position, snapshot_id, tick_interval_ms);
+        player.updated_at = now;
+        player.touch = self.touches;
+        evicted
+    }
+
+    /// Forgets every player not updated for the staleness window, returns their ids
+    pub fn evict_stale(&mut self, now: f64) -> Vec<Uuid> {
+        let stale: Vec<Uuid> = self.players.iter()
+            .filter(|(_, player)| now - player.updated_at >= self.stale_after)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &stale {
+            self.players.remove(id);
+        }
+        stale
+    }
+
+    /// Interpolation buffer of a player
+    pub fn get(&self, id: &Uuid) -> Option<&InterpolationState> {
+        self.players.get(id).map(|player| &player.interpolation)
+    }
+
+    /// Forgets a player
+    pub fn remove(&mut self, id: &Uuid) {
+        self.players.remove(id);
+    }
+
+    /// Forgets every player
+    pub fn clear(&mut self) {
+        self.players.clear();
+    }
+
+    /// Interpolation buffers of all players
+    pub fn iter(&self) -> impl Iterator<Item = (&Uuid, &InterpolationState)> {
+        self.players.iter().map(|(id, player)| (id, &player.interpolation))
+    }
+
+    /// Number of buffered players
+    pub fn entity_count(&self) -> usize {
+        self.players.len()
+    }
+
+    /// Rough number of bytes the buffers take, counting the entries and their buffered positions
+    pub fn approx_memory(&self) -> usize {
+        let entry = std::mem::size_of::<(Uuid, RemotePlayer)>();
+        let sample = std::mem::size_of::<InterpolatedPosition>();
+        self.players.values().map(|player| entry + player.interpolation.position_history.len() * sample).sum()
+    }
+
+    /// The player updated longest ago
+    fn least_recently_updated(&self) -> Option<Uuid> {
+        self.players.iter().min_by_key(|(_, player)| player.touch).map(|(id, _)| *id)
+    }
+}
+
+/// Default implementation for the RemotePlayers
+impl Default for RemotePlayers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Offset between the local clock and the server clock at a local time
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OffsetSample {
+    local_time: f64,
+    offset: f64, // Local time minus server time
+}
+
+/// Estimates the server time from the ticks of received snapshots.
+/// The snapshot delivered fastest has the least delay, so its offset is kept, which makes
+/// the estimate advance at exactly the speed of the local clock regardless of jitter.
+/// Clocks drift, so the fastest offset of every window is also used to fit the drift rate,
+/// and the kept offset follows that rate.
+pub struct ServerClock {
+    anchor: Option<OffsetSample>, // Fastest offset, moved along the drift rate
+    rate: f64,                    // Change of the offset per second of local time
+    tick_interval_ms: Option<u32>,
+    window_started_at: f64,
+    window_fastest: Option<OffsetSample>,
+    window_minima: VecDeque<OffsetSample>, // Fastest offset of each recent window
+}
+
+/// Implementation of the ServerClock
+impl ServerClock {
+    /// Creates a new ServerClock without any estimate
+    pub fn new() -> Self {
+        Self {
+            anchor: None,
+            rate: 0.0,
+            tick_interval_ms: None,
+            window_started_at: 0.0,
+            window_fastest: None,
+            window_minima: VecDeque::new(),
+        }
+    }
+
+    /// Updates the estimate with a snapshot received at the given local time
+    pub fn observe(&mut self, snapshot_id: u64, tick_interval_ms: u32, local_time: f64) {
+        // A different tick length means a different timeline
+        if self.tick_interval_ms != Some(tick_interval_ms) {
+            self.anchor = None;
+            self.rate = 0.0;
+            self.window_minima.clear();
+            self.tick_interval_ms = Some(tick_interval_ms);
+        }
+
+        let sample = OffsetSample {
+            local_time,
+            offset: local_time - tick_time(snapshot_id, tick_interval_ms),
+        };
+
+        if self.window_fastest.is_some() && local_time - self.window_started_at >= CLOCK_DRIFT_WINDOW {
+            self.close_window();
+        }
+
+        match self.offset_at(local_time) {
+            // Snapshots keep arriving much later than expected, the server fell behind
+            Some(expected) if sample.offset - expected > SERVER_CLOCK_RESYNC => {
+                self.window_minima.clear();
+                self.window_fastest = None;
+                self.anchor = Some(sample);
             }
+            Some(expected) if sample.offset >= expected => {}
+            // Delivered faster than ever, or the first snapshot
+            _ => self.anchor = Some(sample),
         }
 
-        match (prev_pos, next_pos) {
-            (Some(prev), Some(next)) => {
-                // Simple linear interpolation
-                let t = ((target_time - prev.timestamp) / (next.timestamp - prev.timestamp))
-                    .max(0.0)
-                    .min(1.0);
+        if self.window_fastest.is_none_or(|fastest| sample.offset < fastest.offset) {
+            if self.window_fastest.is_none() {
+                self.window_started_at = local_time;
+            }
+            self.window_fastest = Some(sample);
+        }
+    }
+
+    /// Estimated server time at the given local time
+    pub fn estimate(&self, local_time: f64) -> Option<f64> {
+        self.offset_at(local_time).map(|offset| local_time - offset)
+    }
+
+    /// Estimated change of the offset per second of local time, positive when the local clock runs fast
+    pub fn drift_rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Offset at a local time, following the drift rate from the anchor
+    fn offset_at(&self, local_time: f64) -> Option<f64> {
+        self.anchor.map(|anchor| anchor.offset + self.rate * (local_time - anchor.local_time))
+    }
 
-                Some(Position {
-                    x: (prev.position.x as f32 + (next.position.x - prev.position.x) as f32 * t) as i32,
-                    y: (prev.position.y as f32 + (next.position.y - prev.position.y) as f32 * t) as i32,
-                })
+    /// Ends the current window: fits the drift rate over the recent windows and lets the anchor
+    /// move up if no snapshot of the window was as fast as expected
+    fn close_window(&mut self) {
+        let Some(fastest) = self.window_fastest.take() else {
+            return;
+        };
+
+        // A window far off the previous ones means the local clock stepped, older windows no longer fit
+        if let Some(last) = self.window_minima.back() {
+            let expected = last.offset + self.rate * (fastest.local_time - last.local_time);
+            if (fastest.offset - expected).abs() > CLOCK_STEP_THRESHOLD {
+                self.window_minima.clear();
             }
-            (Some(prev), None) => Some(prev.position),
-            (None, Some(next)) => Some(next.position),
-            (None, None) => self.last_position,
         }
+        self.window_minima.push_back(fastest);
+        while self.window_minima.len() > CLOCK_DRIFT_WINDOWS {
+            self.window_minima.pop_front();
+        }
+
+        if self.window_minima.len() >= CLOCK_DRIFT_MIN_WINDOWS {
+            self.rate = fitted_slope(&self.window_minima).clamp(-CLOCK_MAX_DRIFT_RATE, CLOCK_MAX_DRIFT_RATE);
+        }
+
+        if self.offset_at(fastest.local_time).is_some_and(|expected| fastest.offset > expected) {
+            self.anchor = Some(fastest);
+        }
+    }
+}
+
+/// Least squares slope of the offset over local time
+fn fitted_slope(samples: &VecDeque<OffsetSample>) -> f64 {
+    let count = samples.len() as f64;
+    let mean_time = samples.iter().map(|s| s.local_time).sum::<f64>() / count;
+    let mean_offset = samples.iter().map(|s| s.offset).sum::<f64>() / count;
+
+    let covariance: f64 = samples.iter().map(|s| (s.local_time - mean_time) * (s.offset - mean_offset)).sum();
+    let variance: f64 = samples.iter().map(|s| (s.local_time - mean_time).powi(2)).sum();
+    if variance > 0.0 { covariance / variance } else { 0.0 }
+}
+
+/// Default implementation for the ServerClock
+impl Default for ServerClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Temporary extra interpolation delay, used to cover the hole left by skipped snapshots.
+/// It decays linearly back to zero over BURST_RECOVERY_TIME.
+#[derive(Debug, Default, Clone)]
+pub struct DelayBump {
+    amount: f64,
+    started_at: f64,
+}
+
+/// Implementation of the DelayBump
+impl DelayBump {
+    /// Raises the extra delay to at least the given amount
+    pub fn bump(&mut self, amount: f64, now: f64) {
+        self.amount = self.current(now).max(amount);
+        self.started_at = now;
+    }
+
+    /// Extra delay in seconds at the given time
+    pub fn current(&self, now: f64) -> f64 {
+        let progress = ((now - self.started_at) / BURST_RECOVERY_TIME).clamp(0.0, 1.0);
+        self.amount * (1.0 - progress)
     }
 }
 
@@ -90,6 +565,39 @@ impl InterpolationState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::CLOCK_DEBUG_STEP;
+    use crate::render_clock::ClockDrift;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const TICK_MS: u32 = 100; // Long ticks keep the expected values easy to read
+    const LATENCY: f64 = 0.05; // Fastest delivery time of the simulated snapshots
+
+    // Helper function to deliver 16ms snapshots with up to 20ms of jitter to a client whose clock drifts.
+    // Returns the real time and estimate error after every delivery.
+    fn run_drifting_clock(drift: &ClockDrift, seconds: f64, seed: u64) -> Vec<(f64, f64)> {
+        let tick_ms = 16;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut deliveries: Vec<(f64, u64)> = (1..(seconds * 1000.0 / tick_ms as f64) as u64)
+            .map(|id| (tick_time(id, tick_ms) + LATENCY + rng.random_range(0.0..0.02), id))
+            .collect();
+        deliveries.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut clock = ServerClock::new();
+        deliveries.into_iter()
+            .map(|(received_at, id)| {
+                let local_time = drift.apply(received_at);
+                clock.observe(id, tick_ms, local_time);
+                let expected = received_at - LATENCY;
+                (received_at, clock.estimate(local_time).unwrap() - expected)
+            })
+            .collect()
+    }
+
+    // Helper function to get the server time that renders the given tick time
+    fn render_time(tick_time: f64) -> f64 {
+        tick_time + INTERPOLATION_DELAY
+    }
 
     #[test]
     fn test_new_interpolation_state() {
@@ -97,7 +605,7 @@ mod tests {
 
         assert!(state.position_history.is_empty());
         assert_eq!(state.interpolation_delay, INTERPOLATION_DELAY);
-        assert_eq!(state.last_sequence, 0);
+        assert_eq!(state.tick_interval_ms, None);
         assert_eq!(state.last_position, None);
     }
 
@@ -105,35 +613,42 @@ mod tests {
     fn test_add_position() {
         let mut state = InterpolationState::new();
         let pos = Position { x: 100, y: 200 };
-        let timestamp = 1.0;
 
-        state.add_position(pos, timestamp, 1);
+        state.add_position(pos, 10, TICK_MS);
 
         assert_eq!(state.position_history.len(), 1);
         assert_eq!(state.position_history[0].position.x, 100);
         assert_eq!(state.position_history[0].position.y, 200);
         assert_eq!(state.position_history[0].timestamp, 1.0);
-        assert_eq!(state.position_history[0].sequence, 1);
-        assert_eq!(state.last_sequence, 1);
+        assert_eq!(state.position_history[0].snapshot_id, 10);
         assert_eq!(state.last_position, Some(pos));
     }
 
     #[test]
-    fn test_skip_older_sequence() {
+    fn test_out_of_order_ticks_are_sorted() {
         let mut state = InterpolationState::new();
 
-        // Add position with sequence 5
-        state.add_position(Position { x: 100, y: 100 }, 1.0, 5);
-        assert_eq!(state.last_sequence, 5);
-        assert_eq!(state.position_history.len(), 1);
+        state.add_position(Position { x: 300, y: 0 }, 3, TICK_MS);
+        state.add_position(Position { x: 100, y: 0 }, 1, TICK_MS);
+        state.add_position(Position { x: 200, y: 0 }, 2, TICK_MS);
+
+        let ids: Vec<u64> = state.position_history.iter().map(|pos| pos.snapshot_id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        // The last position is the newest tick, not the last one received
+        assert_eq!(state.last_position, Some(Position { x: 300, y: 0 }));
+    }
+
+    #[test]
+    fn test_skip_duplicate_tick() {
+        let mut state = InterpolationState::new();
 
-        // Try to add position with sequence 3 (older)
-        state.add_position(Position { x: 200, y: 200 }, 1.5, 3);
+        state.add_position(Position { x: 100, y: 100 }, 5, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 5, TICK_MS);
 
-        // Should still have only one position with sequence 5
-        assert_eq!(state.last_sequence, 5);
+        // Should still have only the first position of tick 5
         assert_eq!(state.position_history.len(), 1);
-        assert_eq!(state.position_history[0].sequence, 5);
+        assert_eq!(state.position_history[0].position, Position { x: 100, y: 100 });
     }
 
     #[test]
@@ -144,8 +659,8 @@ mod tests {
         for i in 1..=MAX_POSITION_HISTORY + 5 {
             state.add_position(
                 Position { x: i as i32 * 10, y: i as i32 * 20 },
-                i as f32,
-                i as u32
+                i as u64,
+                TICK_MS,
             );
         }
 
@@ -153,11 +668,27 @@ mod tests {
         assert_eq!(state.position_history.len(), MAX_POSITION_HISTORY);
 
         // Verify that we kept the most recent positions
-        assert_eq!(state.position_history[0].sequence, (6) as u32);
+        assert_eq!(state.position_history[0].snapshot_id, 6_u64);
         assert_eq!(
-            state.position_history[MAX_POSITION_HISTORY - 1].sequence,
-            (MAX_POSITION_HISTORY + 5) as u32
+            state.position_history[MAX_POSITION_HISTORY - 1].snapshot_id,
+            (MAX_POSITION_HISTORY + 5) as u64
         );
+
+        // A tick older than the whole history is ignored
+        state.add_position(Position { x: 0, y: 0 }, 1, TICK_MS);
+        assert_eq!(state.position_history[0].snapshot_id, 6_u64);
+    }
+
+    #[test]
+    fn test_tick_interval_change_starts_over() {
+        let mut state = InterpolationState::new();
+
+        state.add_position(Position { x: 100, y: 100 }, 1, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 2, TICK_MS);
+        state.add_position(Position { x: 300, y: 300 }, 3, TICK_MS / 2);
+
+        assert_eq!(state.position_history.len(), 1);
+        assert_eq!(state.position_history[0].timestamp, 0.15);
     }
 
     #[test]
@@ -169,72 +700,71 @@ mod tests {
 
         // With one position
         let pos = Position { x: 100, y: 200 };
-        state.add_position(pos, 1.0, 1);
-        assert_eq!(state.get_interpolated_position(2.0), Some(pos));
+        state.add_position(pos, 10, TICK_MS);
+        assert_eq!(state.get_interpolated_position(2.0), Some(DisplayPos::smoothed(pos)));
     }
 
     #[test]
     fn test_normal_interpolation() {
         let mut state = InterpolationState::new();
 
-        // Add two positions
-        state.add_position(Position { x: 100, y: 100 }, 1.0, 1);
-        state.add_position(Position { x: 200, y: 200 }, 2.0, 2);
+        // Add two positions at 1.0 and 2.0 seconds
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 20, TICK_MS);
 
-        // Target time: 1.5 (halfway between positions)
-        // With default interpolation delay of 0.1:
-        // Current time 1.6 means target time 1.5
-        let interpolated = state.get_interpolated_position(1.6);
-
-        // Match what's actually calculated by the implementation
-        assert_eq!(interpolated, Some(Position { x: 158, y: 158 }));
+        // Halfway between the ticks
+        let interpolated = state.get_interpolated_position(render_time(1.5));
+        assert_eq!(interpolated, Some(DisplayPos::smoothed(Position { x: 150, y: 150 })));
     }
 
     #[test]
     fn test_interpolation_edge_cases() {
         let mut state = InterpolationState::new();
 
-        // Add two positions
-        state.add_position(Position { x: 100, y: 100 }, 1.0, 1);
-        state.add_position(Position { x: 200, y: 200 }, 2.0, 2);
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 20, TICK_MS);
 
-        // Target time at exactly prev timestamp (t = 0.0)
-        let interpolated = state.get_interpolated_position(1.1); // 1.1 - 0.1 = 1.0
-        assert_eq!(interpolated, Some(Position { x: 108, y: 108 }));
+        // Target time at exactly prev tick (t = 0.0)
+        assert_eq!(state.get_interpolated_position(render_time(1.0)), Some(DisplayPos::smoothed(Position { x: 100, y: 100 })));
 
-        // Target time at exactly next timestamp (t = 1.0)
-        let interpolated = state.get_interpolated_position(2.1); // 2.1 - 0.1 = 2.0
-        assert_eq!(interpolated, Some(Position { x: 200, y: 200 }));
+        // Target time at exactly next tick (t = 1.0)
+        assert_eq!(state.get_interpolated_position(render_time(2.0)), Some(DisplayPos::smoothed(Position { x: 200, y: 200 })));
     }
 
     #[test]
     fn test_interpolation_target_before_all_positions() {
         let mut state = InterpolationState::new();
 
-        // Add positions starting at timestamp 2.0
-        state.add_position(Position { x: 100, y: 100 }, 2.0, 1);
-        state.add_position(Position { x: 200, y: 200 }, 3.0, 2);
-
-        // Target time before all positions (1.5)
-        let interpolated = state.get_interpolated_position(1.6); // 1.6 - 0.1 = 1.5
+        // Add positions starting at 2.0 seconds
+        state.add_position(Position { x: 100, y: 100 }, 20, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 30, TICK_MS);
 
         // Should use the first position
-        assert_eq!(interpolated, Some(Position { x: 100, y: 100 }));
+        assert_eq!(state.get_interpolated_position(render_time(1.5)), Some(DisplayPos::smoothed(Position { x: 100, y: 100 })));
     }
 
     #[test]
-    fn test_interpolation_target_after_all_positions() {
+    fn test_starved_after_newest_position() {
         let mut state = InterpolationState::new();
+        assert!(!state.is_starved(5.0)); // Nothing buffered is not starvation
 
-        // Add positions ending at timestamp 2.0
-        state.add_position(Position { x: 100, y: 100 }, 1.0, 1);
-        state.add_position(Position { x: 200, y: 200 }, 2.0, 2);
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 20, TICK_MS);
 
-        // Target time after all positions (2.5)
-        let interpolated = state.get_interpolated_position(2.6); // 2.6 - 0.1 = 2.5
+        assert!(!state.is_starved(render_time(2.0)));
+        assert!(state.is_starved(render_time(2.1)));
+    }
+
+    #[test]
+    fn test_interpolation_target_after_all_positions() {
+        let mut state = InterpolationState::new();
+
+        // Add positions ending at 2.0 seconds
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 20, TICK_MS);
 
         // Should use the last position
-        assert_eq!(interpolated, Some(Position { x: 200, y: 200 }));
+        assert_eq!(state.get_interpolated_position(render_time(2.5)), Some(DisplayPos::smoothed(Position { x: 200, y: 200 })));
     }
 
     #[test]
@@ -242,15 +772,420 @@ mod tests {
         let mut state = InterpolationState::new();
 
         // Add several positions
-        state.add_position(Position { x: 100, y: 100 }, 1.0, 1);
-        state.add_position(Position { x: 200, y: 200 }, 2.0, 2);
-        state.add_position(Position { x: 300, y: 300 }, 3.0, 3);
-        state.add_position(Position { x: 400, y: 400 }, 4.0, 4);
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 20, TICK_MS);
+        state.add_position(Position { x: 300, y: 300 }, 30, TICK_MS);
+        state.add_position(Position { x: 400, y: 400 }, 40, TICK_MS);
+
+        // Target time in the middle of the second pair
+        assert_eq!(state.get_interpolated_position(render_time(2.5)), Some(DisplayPos::smoothed(Position { x: 250, y: 250 })));
+    }
+
+    #[test]
+    fn test_position_at_without_samples_is_unavailable() {
+        let state = InterpolationState::new();
+        assert_eq!(state.position_at(1.0), PositionAt::Unavailable);
+        assert_eq!(PositionAt::Unavailable.position(), None);
+    }
+
+    #[test]
+    fn test_position_at_a_single_sample() {
+        let mut state = InterpolationState::new();
+        let pos = Position { x: 100, y: 200 };
+        state.add_position(pos, 10, TICK_MS);
+
+        // Only the tick of the sample is exact
+        assert_eq!(state.position_at(1.0), PositionAt::Exact(pos));
+        assert_eq!(state.position_at(0.5), PositionAt::Clamped(pos));
+        assert_eq!(state.position_at(1.5), PositionAt::Clamped(pos));
+    }
+
+    #[test]
+    fn test_position_at_any_time_within_the_buffer() {
+        let mut state = InterpolationState::new();
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 20, TICK_MS);
+        state.add_position(Position { x: 300, y: 100 }, 30, TICK_MS);
+
+        // The time is used as given, where rendering subtracts the interpolation delay first
+        assert_eq!(state.position_at(1.0), PositionAt::Exact(Position { x: 100, y: 100 }));
+        assert_eq!(state.position_at(1.25), PositionAt::Exact(Position { x: 125, y: 125 }));
+        assert_eq!(state.position_at(2.5), PositionAt::Exact(Position { x: 250, y: 150 }));
+        assert_eq!(state.position_at(3.0), PositionAt::Exact(Position { x: 300, y: 100 }));
+        assert_eq!(state.position_at(2.5).position().map(DisplayPos::smoothed), state.get_interpolated_position(render_time(2.5)));
+    }
+
+    #[test]
+    fn test_position_at_outside_the_buffer_is_clamped() {
+        let mut state = InterpolationState::new();
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 20, TICK_MS);
+        assert_eq!(state.position_at(0.5), PositionAt::Clamped(Position { x: 100, y: 100 }));
+        assert_eq!(state.position_at(2.5), PositionAt::Clamped(Position { x: 200, y: 200 }));
+
+        // A new tick interval starts the buffer over, like a teleport on the server nothing before it is looked at
+        state.add_position(Position { x: 300, y: 300 }, 50, TICK_MS / 2);
+        assert_eq!(state.position_at(1.5), PositionAt::Clamped(Position { x: 300, y: 300 }));
+        assert_eq!(state.position_at(2.5), PositionAt::Exact(Position { x: 300, y: 300 }));
+    }
+
+    #[test]
+    fn test_hermite_curves_through_corners_and_keeps_straight_lines() {
+        let mut state = InterpolationState::new();
+        state.set_strategy(InterpolationStrategy::Hermite);
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 200, y: 100 }, 11, TICK_MS);
+        state.add_position(Position { x: 200, y: 200 }, 12, TICK_MS);
+
+        // The ticks themselves are met, between them the curve leans into the turn where a line wouldn't
+        assert_eq!(state.position_at(1.1), PositionAt::Exact(Position { x: 200, y: 100 }));
+        assert_eq!(state.position_at(1.05), PositionAt::Exact(Position { x: 156, y: 94 }));
+        state.set_strategy(InterpolationStrategy::Linear);
+        assert_eq!(state.position_at(1.05), PositionAt::Exact(Position { x: 150, y: 100 }));
+
+        // At a constant speed in a straight line both agree
+        let mut straight = InterpolationState::new();
+        straight.set_strategy(InterpolationStrategy::Hermite);
+        for (tick, x) in [(10, 100), (11, 200), (12, 300)] {
+            straight.add_position(Position { x, y: 100 }, tick, TICK_MS);
+        }
+        assert_eq!(straight.position_at(1.05), PositionAt::Exact(Position { x: 150, y: 100 }));
+    }
+
+    #[test]
+    fn test_extrapolation_carries_on_for_a_while_when_starved() {
+        let mut state = InterpolationState::new();
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 200, y: 100 }, 11, TICK_MS);
+        let drawn = |state: &InterpolationState, time| state.get_interpolated_position(render_time(time)).map(DisplayPos::layout_position);
+
+        // Linear holds the newest position, extrapolating goes on at the last speed up to the limit
+        assert_eq!(drawn(&state, 1.15), Some(Position { x: 200, y: 100 }));
+        state.set_strategy(InterpolationStrategy::LinearWithExtrapolation);
+        assert_eq!(drawn(&state, 1.05), Some(Position { x: 150, y: 100 }));
+        assert_eq!(drawn(&state, 1.15), Some(Position { x: 250, y: 100 }));
+        assert_eq!(drawn(&state, 1.1 + MAX_EXTRAPOLATION + 0.5), Some(Position { x: 300, y: 100 }));
+        assert!(state.is_starved(render_time(1.15)));
+
+        // A single sample has no speed to carry on with
+        let mut single = InterpolationState::new();
+        single.set_strategy(InterpolationStrategy::LinearWithExtrapolation);
+        single.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        assert_eq!(drawn(&single, 1.5), Some(Position { x: 100, y: 100 }));
+    }
+
+    #[test]
+    fn test_strategies_parse_by_name() {
+        for strategy in InterpolationStrategy::ALL {
+            assert_eq!(InterpolationStrategy::parse(strategy.name()), Some(strategy));
+        }
+        assert_eq!(InterpolationStrategy::parse(" Hermite "), Some(InterpolationStrategy::Hermite));
+        assert_eq!(InterpolationStrategy::parse("cubic"), None);
+        assert_eq!(InterpolationStrategy::default(), InterpolationStrategy::Linear);
+
+        // Buffers created after the switch play back the same way as the existing ones
+        let mut players = RemotePlayers::new();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        players.update(first, Position { x: 0, y: 0 }, 1, TICK_MS, 0.0);
+        players.set_strategy(InterpolationStrategy::Hermite);
+        players.update(second, Position { x: 0, y: 0 }, 1, TICK_MS, 0.0);
+        assert!(players.iter().all(|(_, interpolation)| interpolation.strategy() == InterpolationStrategy::Hermite));
+    }
 
-        // Target time in the middle (2.5)
-        let interpolated = state.get_interpolated_position(2.6); // 2.6 - 0.1 = 2.5
+    #[test]
+    fn test_lone_spike_is_suppressed() {
+        let mut state = InterpolationState::new();
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+        state.add_position(Position { x: 105, y: 100 }, 11, TICK_MS);
+
+        // One corrupted sample far across the board, then the player carries on
+        state.add_position(Position { x: 900, y: 600 }, 12, TICK_MS);
+        assert_eq!(state.last_position, Some(Position { x: 105, y: 100 }));
+        state.add_position(Position { x: 115, y: 100 }, 13, TICK_MS);
+
+        let ids: Vec<u64> = state.position_history.iter().map(|pos| pos.snapshot_id).collect();
+        assert_eq!(ids, vec![10, 11, 13]);
+        assert_eq!(state.get_interpolated_position(render_time(1.2)), Some(DisplayPos::smoothed(Position { x: 110, y: 100 })));
+        assert_eq!(state.rejected_samples(), 1);
+    }
+
+    #[test]
+    fn test_two_agreeing_samples_are_a_teleport() {
+        let mut state = InterpolationState::new();
+        state.add_position(Position { x: 100, y: 100 }, 10, TICK_MS);
+
+        // Both samples land at the new place, so the player really moved there
+        state.add_position(Position { x: 900, y: 600 }, 11, TICK_MS);
+        state.add_position(Position { x: 905, y: 600 }, 12, TICK_MS);
+
+        assert_eq!(state.position_history.len(), 3);
+        assert_eq!(state.last_position, Some(Position { x: 905, y: 600 }));
+        assert_eq!(state.rejected_samples(), 0);
 
-        // Match what's actually calculated by the implementation
-        assert_eq!(interpolated, Some(Position { x: 258, y: 258 }));
+        // Two spikes that disagree with each other are both dropped
+        state.add_position(Position { x: 0, y: 0 }, 13, TICK_MS);
+        state.add_position(Position { x: 500, y: 0 }, 14, TICK_MS);
+        state.add_position(Position { x: 910, y: 600 }, 15, TICK_MS);
+        assert_eq!(state.last_position, Some(Position { x: 910, y: 600 }));
+        assert_eq!(state.rejected_samples(), 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_dash_speed_passes_with_a_higher_max() {
+        // 200 px per 100 ms tick is 2000 px/s, faster than the default maximum
+        let dash = |state: &mut InterpolationState| {
+            for id in 0..5_u64 {
+                state.add_position(Position { x: id as i32 * 200, y: 0 }, id, TICK_MS);
+            }
+        };
+        let mut state = InterpolationState::new();
+        dash(&mut state);
+        assert_eq!(state.position_history.len(), 1); // Every step looks like a spike from the one before
+
+        let mut state = InterpolationState::with_max_speed(2500.0);
+        dash(&mut state);
+        assert_eq!(state.position_history.len(), 5);
+        assert_eq!(state.rejected_samples(), 0);
+        assert_eq!(state.get_interpolated_position(render_time(0.25)), Some(DisplayPos::smoothed(Position { x: 500, y: 0 })));
+    }
+
+    #[test]
+    fn test_server_clock_keeps_fastest_offset() {
+        let mut clock = ServerClock::new();
+        assert_eq!(clock.estimate(1.0), None);
+
+        // Tick 10 (1.0s) arrives after 0.08s, tick 11 (1.1s) after only 0.05s
+        clock.observe(10, TICK_MS, 1.08);
+        clock.observe(11, TICK_MS, 1.15);
+
+        // Later, slower snapshots don't move the estimate
+        clock.observe(12, TICK_MS, 1.29);
+
+        let estimate = clock.estimate(2.05).unwrap();
+        assert!((estimate - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_server_clock_resyncs_when_server_falls_behind() {
+        let mut clock = ServerClock::new();
+        clock.observe(10, TICK_MS, 1.05);
+
+        // The server stalled, snapshots now arrive a second later than expected
+        clock.observe(11, TICK_MS, 2.15);
+
+        let estimate = clock.estimate(2.15).unwrap();
+        assert!((estimate - 1.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_server_clock_tracks_drift() {
+        for rate_ppm in [500.0, -500.0, 0.0] {
+            let errors = run_drifting_clock(&ClockDrift::new(rate_ppm), 600.0, 11);
+
+            // Without the drift rate the error would grow to 0.3 seconds by the end
+            let worst = errors.iter()
+                .filter(|(time, _)| *time > 30.0)
+                .map(|(_, error)| error.abs())
+                .fold(0.0, f64::max);
+            assert!(worst < 0.005, "{} ppm drifted {} s off", rate_ppm, worst);
+        }
+    }
+
+    #[test]
+    fn test_server_clock_recovers_from_steps() {
+        let mut drift = ClockDrift::new(200.0);
+        drift.step_at(100.0, CLOCK_DEBUG_STEP);
+        drift.step_at(200.0, -CLOCK_DEBUG_STEP);
+        drift.step_at(300.0, CLOCK_DEBUG_STEP);
+
+        let errors = run_drifting_clock(&drift, 400.0, 12);
+
+        // Back on track within three drift windows of every step
+        let settling = |time: f64| [100.0, 200.0, 300.0].iter().any(|step| (0.0..3.0 * CLOCK_DRIFT_WINDOW).contains(&(time - step)));
+        for (time, error) in errors.into_iter().filter(|(time, _)| *time > 30.0 && !settling(*time)) {
+            assert!(error.abs() < 0.005, "{} s off at {} s", error, time);
+        }
+    }
+
+    #[test]
+    fn test_server_clock_estimates_drift_rate() {
+        let mut clock = ServerClock::new();
+
+        // The local clock runs 300 ppm fast, snapshots arrive without jitter
+        for id in 1..2000 {
+            let real_time = tick_time(id, TICK_MS) + LATENCY;
+            clock.observe(id, TICK_MS, real_time * (1.0 + 300e-6));
+        }
+        assert!((clock.drift_rate() - 300e-6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_jittery_delivery_has_uniform_velocity() {
+        let tick_ms = 16;
+        let latency = 0.05;
+        let speed_per_tick = 2;
+        let mut rng = StdRng::seed_from_u64(7);
+
+        // Evenly ticked samples delivered with up to 30ms of jitter, so some arrive out of order
+        let mut deliveries: Vec<(f64, u64)> = (1..400_u64)
+            .map(|id| {
+                let jitter = if id == 1 { 0.0 } else { rng.random_range(0.0..0.03) };
+                (tick_time(id, tick_ms) + latency + jitter, id)
+            })
+            .collect();
+        deliveries.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut state = InterpolationState::new();
+        let mut clock = ServerClock::new();
+        let mut delivered = 0;
+        let mut previous_x = None;
+        let frame_time = 1.0 / 60.0;
+        let expected_step = speed_per_tick as f64 * frame_time / (tick_ms as f64 / 1000.0);
+
+        for frame in 0..300 {
+            let local_time = 0.2 + frame as f64 * frame_time;
+
+            // Deliver every sample that has arrived by this frame
+            while delivered < deliveries.len() && deliveries[delivered].0 <= local_time {
+                let (received_at, id) = deliveries[delivered];
+                clock.observe(id, tick_ms, received_at);
+                state.add_position(Position { x: id as i32 * speed_per_tick, y: 0 }, id, tick_ms);
+                delivered += 1;
+            }
+
+            let x = state.get_interpolated_position(clock.estimate(local_time).unwrap()).unwrap().x();
+
+            // Every frame moves the same distance, give or take rounding
+            if let Some(previous_x) = previous_x {
+                let step = (x - previous_x) as f64;
+                assert!((step - expected_step).abs() <= 1.0, "frame {} moved {} instead of {}", frame, step, expected_step);
+            }
+            previous_x = Some(x);
+        }
+    }
+
+    #[test]
+    fn test_playback_stays_smooth_after_a_day_of_uptime() {
+        // A server ticking for 24 hours and a client whose clock has run for as long, where seconds in f32
+        // would only resolve steps of about 8 ms
+        let tick_ms = 16;
+        let day = 24.0 * 3600.0;
+        let first_id = (day * 1000.0 / tick_ms as f64) as u64;
+        let latency = 0.05;
+        let mut state = InterpolationState::new();
+        let mut clock = ServerClock::new();
+        let mut next_id = first_id;
+        let mut previous_x = None;
+        let frame_time = 1.0 / 144.0;
+        let speed_per_tick = 8;
+        let expected_step = speed_per_tick as f64 * frame_time / (tick_ms as f64 / 1000.0);
+
+        for frame in 0..600 {
+            let local_time = day + 0.3 + frame as f64 * frame_time;
+
+            // Snapshots arrive a fixed latency after their tick, the local clock started with the server's
+            while tick_time(next_id, tick_ms) + latency <= local_time {
+                clock.observe(next_id, tick_ms, tick_time(next_id, tick_ms) + latency);
+                state.add_position(Position { x: (next_id - first_id) as i32 * speed_per_tick, y: 0 }, next_id, tick_ms);
+                next_id += 1;
+            }
+            let server_time = clock.estimate(local_time).unwrap();
+            assert!((server_time - (local_time - latency)).abs() < 1e-6, "estimate off by {} s", server_time - (local_time - latency));
+
+            // Every frame moves the same distance, give or take rounding, instead of standing still and jumping
+            let x = state.get_interpolated_position(server_time).unwrap().x();
+            if let Some(previous_x) = previous_x.filter(|_| server_time - INTERPOLATION_DELAY > tick_time(first_id, tick_ms)) {
+                let step = (x - previous_x) as f64;
+                assert!((step - expected_step).abs() <= 1.0, "frame {} moved {} instead of {}", frame, step, expected_step);
+            }
+            previous_x = Some(x);
+        }
+    }
+
+    #[test]
+    fn test_delay_bump_decays() {
+        let mut bump = DelayBump::default();
+        assert_eq!(bump.current(0.0), 0.0);
+
+        bump.bump(0.2, 10.0);
+        assert!((bump.current(10.0) - 0.2).abs() < 1e-9);
+        assert!((bump.current(10.0 + BURST_RECOVERY_TIME / 2.0) - 0.1).abs() < 1e-9);
+        assert_eq!(bump.current(10.0 + BURST_RECOVERY_TIME), 0.0);
+    }
+
+    #[test]
+    fn test_smaller_bump_keeps_larger_delay() {
+        let mut bump = DelayBump::default();
+        bump.bump(0.2, 0.0);
+
+        // A smaller bump right after doesn't shrink the delay, but restarts the decay
+        bump.bump(0.05, 0.0);
+        assert!((bump.current(0.0) - 0.2).abs() < 1e-9);
+    }
+
+    // Helper function to update a remote player at the given local time, one tick per 100 ms
+    fn update_at(players: &mut RemotePlayers, id: Uuid, now: f64) -> Option<Uuid> {
+        players.update(id, Position { x: 100, y: 100 }, (now * 10.0) as u64, 100, now)
+    }
+
+    #[test]
+    fn test_remote_players_are_forgotten_without_snapshots() {
+        let mut players = RemotePlayers::with_limits(5.0, 10);
+        let (stays, leaves) = (Uuid::new_v4(), Uuid::new_v4());
+        update_at(&mut players, stays, 0.0);
+        update_at(&mut players, leaves, 0.0);
+
+        // Only one of them keeps getting updates
+        for frame in 1..=40 {
+            update_at(&mut players, stays, frame as f64 * 0.1);
+        }
+        assert!(players.evict_stale(4.9).is_empty());
+        assert_eq!(players.evict_stale(5.0), vec![leaves]);
+        assert!(players.get(&stays).is_some());
+
+        // The snapshot stream pauses, e.g. while the window is minimized, and the rest goes too
+        assert!(players.evict_stale(8.9).is_empty());
+        assert_eq!(players.evict_stale(9.0), vec![stays]);
+        assert_eq!(players.entity_count(), 0);
+        assert_eq!(players.approx_memory(), 0);
+    }
+
+    #[test]
+    fn test_remote_players_are_capped_least_recently_updated_first() {
+        let mut players = RemotePlayers::with_limits(5.0, 3);
+        let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        for (index, id) in ids[..3].iter().enumerate() {
+            assert_eq!(update_at(&mut players, *id, index as f64 * 0.1), None);
+        }
+
+        // Updating the oldest one makes the second the least recently updated
+        assert_eq!(update_at(&mut players, ids[0], 0.3), None);
+        assert_eq!(update_at(&mut players, ids[3], 0.4), Some(ids[1]));
+        // Within one frame the update order decides
+        assert_eq!(update_at(&mut players, ids[2], 0.5), None);
+        assert_eq!(update_at(&mut players, ids[0], 0.5), None);
+        assert_eq!(update_at(&mut players, ids[4], 0.5), Some(ids[3]));
+
+        assert_eq!(players.entity_count(), 3);
+        let mut kept: Vec<Uuid> = players.iter().map(|(id, _)| *id).collect();
+        kept.sort();
+        let mut expected = vec![ids[0], ids[2], ids[4]];
+        expected.sort();
+        assert_eq!(kept, expected);
+    }
+
+    #[test]
+    fn test_remote_players_memory_grows_with_buffered_samples() {
+        let mut players = RemotePlayers::new();
+        let id = Uuid::new_v4();
+        update_at(&mut players, id, 0.0);
+        let one_sample = players.approx_memory();
+        assert!(one_sample > 0);
+
+        update_at(&mut players, id, 0.1);
+        assert_eq!(players.approx_memory() - one_sample, std::mem::size_of::<InterpolatedPosition>());
+
+        players.remove(&id);
+        assert_eq!(players.approx_memory(), 0);
+    }
+}
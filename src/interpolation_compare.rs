@@ -0,0 +1,231 @@
+use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH, INTERPOLATION_COMPARE_FRAME, INTERPOLATION_COMPARE_WARMUP, INTERPOLATION_DELAY, PLAYER_SPEED};
+use crate::interpolation::{tick_time, InterpolationState, InterpolationStrategy, ServerClock};
+use crate::jitter::{JitterDistribution, JitterModel};
+use crate::movement::{step, MovementAccumulator};
+use crate::scripted_input::ScriptedInputDriver;
+use crate::streaming_stats::RunningStats;
+use crate::types::{NetworkCondition, Position};
+use crate::warnings::percentile;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// The true path of a remote player, one position per server tick. It is recorded once and every
+/// strategy plays back the same snapshots of it, so the strategies are the only difference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedPath {
+    pub tick_interval_ms: u32,
+    pub positions: Vec<Position>, // Position at each tick, the index is the snapshot id
+}
+
+/// Implementation of the RecordedPath
+impl RecordedPath {
+    /// Walks the script from the middle of the board, one input per tick at the default speed
+    pub fn scripted(mut driver: ScriptedInputDriver, ticks: usize, tick_interval_ms: u32) -> Self {
+        let mut position = Position { x: BOARD_WIDTH / 2, y: BOARD_HEIGHT / 2 };
+        let mut accumulator = MovementAccumulator::default();
+        let mut positions = Vec::with_capacity(ticks);
+        for _ in 0..ticks {
+            positions.push(position);
+            if let Some(dir) = driver.next_direction(tick_interval_ms as f32 / 1000.0) {
+                position = step(position, dir, PLAYER_SPEED as f32, &mut accumulator);
+            }
+        }
+        Self { tick_interval_ms, positions }
+    }
+
+    /// Server time of the last tick in seconds
+    pub fn duration(&self) -> f64 {
+        tick_time(self.positions.len().saturating_sub(1) as u64, self.tick_interval_ms)
+    }
+
+    /// Where the player truly was at a server time, on a straight line between the ticks around it
+    pub fn true_position(&self, server_time: f64) -> Option<(f64, f64)> {
+        let last = self.positions.len().checked_sub(1)?;
+        let ticks = (server_time / tick_time(1, self.tick_interval_ms)).clamp(0.0, last as f64);
+        let (before, after) = (self.positions[ticks.floor() as usize], self.positions[ticks.ceil() as usize]);
+        let t = ticks.fract();
+        Some((
+            before.x as f64 + (after.x - before.x) as f64 * t,
+            before.y as f64 + (after.y - before.y) as f64 * t,
+        ))
+    }
+
+    /// When each snapshot of the path arrives under the condition, as (local time, snapshot id) sorted by
+    /// arrival. Lost snapshots are missing, duplicated ones arrive twice. The local clock runs with the
+    /// server clock, so the times compare directly. The same seed gives the same arrivals.
+    pub fn deliveries(&self, condition: &NetworkCondition, seed: u64) -> Vec<(f64, u64)> {
+        let mut jitter = JitterModel::new(JitterDistribution::Uniform, 0, seed);
+        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(1));
+        let loss = condition.packet_loss_percent.clamp(0, 100) as f64 / 100.0;
+        let duplicates = condition.duplicate_percent.clamp(0, 100) as f64 / 100.0;
+        let mut arrivals = Vec::with_capacity(self.positions.len());
+        for id in 0..self.positions.len() as u64 {
+            let (lost, copies) = (rng.random_bool(loss), if rng.random_bool(duplicates) { 2 } else { 1 });
+            if lost {
+                continue;
+            }
+            for _ in 0..copies {
+                let delay_ms = (condition.latency_ms as f64 + jitter.sample_offset_ms(condition.jitter_ms)).max(0.0);
+                arrivals.push((tick_time(id, self.tick_interval_ms) + delay_ms / 1000.0, id));
+            }
+        }
+        arrivals.sort_by(|a, b| a.0.total_cmp(&b.0));
+        arrivals
+    }
+}
+
+/// How one strategy played back a path under one condition
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrategyResult {
+    pub condition: String,
+    pub strategy: InterpolationStrategy,
+    pub frames: usize,          // Frames measured after the warmup
+    pub avg_deviation_px: f64,  // Distance from where the player truly was at the playback time
+    pub p95_deviation_px: f64,
+    pub avg_lag_px: f64,        // Distance from where the player truly was at the server time of the frame
+    pub starvation: f64,        // Share of frames that played back past the newest snapshot
+}
+
+/// Plays the deliveries back with the strategy at a fixed frame rate, like the client draws a remote
+/// player, and measures the drawn positions against the true path
+pub fn replay(path: &RecordedPath, deliveries: &[(f64, u64)], strategy: InterpolationStrategy, condition: &str) -> StrategyResult {
+    let mut interpolation = InterpolationState::new();
+    interpolation.set_strategy(strategy);
+    let mut clock = ServerClock::new();
+    let (mut deviations, mut lag, mut starved) = (Vec::new(), RunningStats::new(), 0);
+    let mut next = 0;
+    let end = path.duration() + INTERPOLATION_DELAY;
+    let mut frame = 0;
+    loop {
+        let local_time = frame as f64 * INTERPOLATION_COMPARE_FRAME;
+        if local_time > end {
+            break;
+        }
+        frame += 1;
+        while let Some((arrived_at, id)) = deliveries.get(next).filter(|(arrived_at, _)| *arrived_at <= local_time) {
+            clock.observe(*id, path.tick_interval_ms, *arrived_at);
+            interpolation.add_position(path.positions[*id as usize], *id, path.tick_interval_ms);
+            next += 1;
+        }
+        if local_time < INTERPOLATION_COMPARE_WARMUP {
+            continue;
+        }
+        let Some(server_time) = clock.estimate(local_time) else {
+            continue;
+        };
+        let (Some(drawn), Some(then), Some(now)) = (
+            interpolation.get_interpolated_position(server_time),
+            path.true_position(server_time - INTERPOLATION_DELAY),
+            path.true_position(local_time),
+        ) else {
+            continue;
+        };
+        let distance = |(x, y): (f64, f64)| (drawn.x() as f64 - x).hypot(drawn.y() as f64 - y);
+        deviations.push(distance(then) as f32);
+        lag.record(distance(now));
+        starved += usize::from(interpolation.is_starved(server_time));
+    }
+    let frames = deviations.len();
+    StrategyResult {
+        condition: condition.to_string(),
+        strategy,
+        frames,
+        avg_deviation_px: deviations.iter().map(|deviation| *deviation as f64).sum::<f64>() / frames.max(1) as f64,
+        p95_deviation_px: percentile(&mut deviations, 0.95).unwrap_or_default() as f64,
+        avg_lag_px: lag.mean().unwrap_or_default(),
+        starvation: starved as f64 / frames.max(1) as f64,
+    }
+}
+
+/// Plays the path back with every strategy under every condition. The arrivals are drawn once per
+/// condition, so the strategies of a condition see the very same snapshots. One row per
+/// (condition, strategy), in the order of the conditions and InterpolationStrategy::ALL.
+pub fn compare(path: &RecordedPath, conditions: &[NetworkCondition], seed: u64) -> Vec<StrategyResult> {
+    conditions.iter()
+        .flat_map(|condition| {
+            let deliveries = path.deliveries(condition, seed);
+            InterpolationStrategy::ALL.map(|strategy| replay(path, &deliveries, strategy, &condition.name))
+        })
+        .collect()
+}
+
+/// The comparison as a markdown table
+pub fn comparison_report(results: &[StrategyResult]) -> String {
+    let mut report = "# Interpolation Strategy Comparison\n\n".to_string();
+    report.push_str("| Network Condition | Strategy | Avg Deviation | P95 Deviation | Avg Lag | Starvation |\n");
+    report.push_str("|------------------|----------|---------------|---------------|---------|------------|\n");
+    for result in results {
+        report.push_str(&format!("| {:<16} | {:<11} | {:>10.2} px | {:>10.2} px | {:>5.1} px | {:>8.1}% |\n",
+                 result.condition,
+                 result.strategy.name(),
+                 result.avg_deviation_px,
+                 result.p95_deviation_px,
+                 result.avg_lag_px,
+                 result.starvation * 100.0));
+    }
+    report
+}
+
+/// Tests for the interpolation comparison
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::default_conditions;
+
+    // Helper function to create a condition
+    fn condition(name: &str, latency_ms: i32, jitter_ms: i32, packet_loss_percent: i32) -> NetworkCondition {
+        NetworkCondition { latency_ms, jitter_ms, packet_loss_percent, duplicate_percent: 0, name: name.to_string() }
+    }
+
+    #[test]
+    fn test_sweep_has_a_row_per_strategy_and_condition() {
+        let path = RecordedPath::scripted(ScriptedInputDriver::square(), 300, 16);
+        let conditions = default_conditions();
+        let results = compare(&path, &conditions, 5);
+        assert_eq!(results.len(), conditions.len() * InterpolationStrategy::ALL.len());
+        for (index, result) in results.iter().enumerate() {
+            assert_eq!(result.condition, conditions[index / InterpolationStrategy::ALL.len()].name);
+            assert_eq!(result.strategy, InterpolationStrategy::ALL[index % InterpolationStrategy::ALL.len()]);
+            assert!(result.frames > 0, "{:?}", result);
+        }
+        let report = comparison_report(&results);
+        assert_eq!(report.lines().count(), 4 + results.len());
+        assert!(report.contains("| Very Poor        | hermite"));
+    }
+
+    #[test]
+    fn test_same_seed_gives_the_same_arrivals() {
+        let path = RecordedPath::scripted(ScriptedInputDriver::square(), 200, 16);
+        let lossy = condition("Lossy", 100, 30, 20);
+        let arrivals = path.deliveries(&lossy, 9);
+        assert_eq!(arrivals, path.deliveries(&lossy, 9));
+        assert!(arrivals.len() < 200 && arrivals.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+        // Without any loss or jitter every snapshot arrives once, the latency after its tick
+        let steady = path.deliveries(&condition("Steady", 100, 0, 0), 9);
+        assert_eq!(steady.len(), 200);
+        assert!(steady.iter().all(|(at, id)| (at - tick_time(*id, 16) - 0.1).abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_ideal_network_plays_back_the_true_path() {
+        let path = RecordedPath::scripted(ScriptedInputDriver::square(), 400, 16);
+        let ideal = condition("Ideal", 0, 0, 0);
+        for result in compare(&path, &[ideal], 1) {
+            assert!(result.avg_deviation_px < 1.0, "{:?}", result);
+            assert_eq!(result.starvation, 0.0, "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_extrapolation_keeps_up_with_a_starved_buffer() {
+        // Straight line at constant speed with most snapshots lost, the buffer keeps running dry
+        let path = RecordedPath { tick_interval_ms: 16, positions: (0..600).map(|tick| Position { x: 100 + tick, y: 300 }).collect() };
+        let deliveries: Vec<(f64, u64)> = path.deliveries(&condition("Ideal", 40, 0, 0), 3).into_iter().filter(|(_, id)| id % 10 == 0).collect();
+        let linear = replay(&path, &deliveries, InterpolationStrategy::Linear, "Sparse");
+        let extrapolated = replay(&path, &deliveries, InterpolationStrategy::LinearWithExtrapolation, "Sparse");
+        assert!(linear.starvation > 0.3, "{:?}", linear);
+        assert!(extrapolated.avg_deviation_px < linear.avg_deviation_px / 2.0, "{:?} against {:?}", extrapolated, linear);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::constants::JITTER_NORMAL_CLAMP;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::time::{Duration, Instant};
+
+/// Distribution the simulated jitter is drawn from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JitterDistribution {
+    #[default]
+    Uniform, // Evenly spread between -jitter and +jitter
+    Normal,  // Normal with jitter as the standard deviation, clamped to a few deviations
+}
+
+/// Random per-packet delays for one direction of the simulated network.
+/// Packets may overtake each other, but never by more than the reorder window.
+#[derive(Debug, Clone)]
+pub struct JitterModel {
+    pub distribution: JitterDistribution,
+    pub max_reorder_ms: u64, // How far a packet may be delivered ahead of one sent before it
+    rng: StdRng,
+    latest_release: Option<Instant>, // Latest release time handed out so far
+}
+
+/// Implementation of the JitterModel
+impl JitterModel {
+    /// Creates a jitter model with its own seeded random generator
+    pub fn new(distribution: JitterDistribution, max_reorder_ms: u64, seed: u64) -> Self {
+        Self {
+            distribution,
+            max_reorder_ms,
+            rng: StdRng::seed_from_u64(seed),
+            latest_release: None,
+        }
+    }
+
+    /// Draws a delay offset in milliseconds, centered on zero
+    pub fn sample_offset_ms(&mut self, jitter_ms: i32) -> f64 {
+        let jitter = jitter_ms.max(0) as f64;
+        if jitter == 0.0 {
+            return 0.0;
+        }
+        match self.distribution {
+            JitterDistribution::Uniform => self.rng.random_range(-jitter..=jitter),
+            JitterDistribution::Normal => {
+                // Box-Muller transform, 1 - u keeps the logarithm away from zero
+                let u1: f64 = 1.0 - self.rng.random::<f64>();
+                let u2: f64 = self.rng.random();
+                let standard = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                standard.clamp(-JITTER_NORMAL_CLAMP, JITTER_NORMAL_CLAMP) * jitter
+            }
+        }
+    }
+
+    /// Picks the time a packet sent now is delivered, base_delay_ms plus jitter but never negative.
+    /// The release is moved later if it would overtake an earlier packet by more than the reorder window.
+    pub fn release_at(&mut self, now: Instant, base_delay_ms: i32, jitter_ms: i32) -> Instant {
+        let delay_ms = (base_delay_ms as f64 + self.sample_offset_ms(jitter_ms)).max(0.0);
+        let mut release = now + Duration::from_secs_f64(delay_ms / 1000.0);
+
+        if let Some(latest) = self.latest_release {
+            let earliest_allowed = latest.checked_sub(Duration::from_millis(self.max_reorder_ms)).unwrap_or(latest);
+            release = release.max(earliest_allowed);
+        }
+        self.latest_release = Some(self.latest_release.map_or(release, |latest| latest.max(release)));
+        release
+    }
+}
+
+/// Tests for the jitter model
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to draw many offsets from a seeded model
+    fn samples(distribution: JitterDistribution, jitter_ms: i32, count: usize) -> Vec<f64> {
+        let mut model = JitterModel::new(distribution, 0, 7);
+        (0..count).map(|_| model.sample_offset_ms(jitter_ms)).collect()
+    }
+
+    // Helper function for the mean and standard deviation of samples
+    fn mean_and_deviation(values: &[f64]) -> (f64, f64) {
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / values.len() as f64;
+        (mean, variance.sqrt())
+    }
+
+    #[test]
+    fn test_uniform_samples_stay_in_range() {
+        let values = samples(JitterDistribution::Uniform, 20, 10_000);
+        assert!(values.iter().all(|value| (-20.0..=20.0).contains(value)));
+
+        // A uniform spread over [-20, 20] has a standard deviation of 20 / sqrt(3)
+        let (mean, deviation) = mean_and_deviation(&values);
+        assert!(mean.abs() < 1.0, "mean {}", mean);
+        assert!((deviation - 20.0 / 3f64.sqrt()).abs() < 0.5, "deviation {}", deviation);
+    }
+
+    #[test]
+    fn test_normal_samples_match_deviation() {
+        let values = samples(JitterDistribution::Normal, 10, 10_000);
+        let limit = 10.0 * JITTER_NORMAL_CLAMP;
+        assert!(values.iter().all(|value| (-limit..=limit).contains(value)));
+
+        let (mean, deviation) = mean_and_deviation(&values);
+        assert!(mean.abs() < 0.5, "mean {}", mean);
+        assert!((deviation - 10.0).abs() < 0.5, "deviation {}", deviation);
+    }
+
+    #[test]
+    fn test_same_seed_gives_same_samples() {
+        assert_eq!(samples(JitterDistribution::Normal, 15, 100), samples(JitterDistribution::Normal, 15, 100));
+        assert!(samples(JitterDistribution::Uniform, 0, 10).iter().all(|value| *value == 0.0));
+    }
+
+    #[test]
+    fn test_release_never_before_send() {
+        let mut model = JitterModel::new(JitterDistribution::Uniform, 1000, 3);
+        let now = Instant::now();
+        for _ in 0..1000 {
+            // The jitter is larger than the base delay, so many offsets are clamped
+            assert!(model.release_at(now, 5, 50) >= now);
+        }
+    }
+
+    #[test]
+    fn test_reorder_window_is_enforced() {
+        for window_ms in [0, 10, 30] {
+            let mut model = JitterModel::new(JitterDistribution::Normal, window_ms, 11);
+            let start = Instant::now();
+            let mut latest = start;
+            let mut overtakes = 0;
+
+            for index in 0..2000 {
+                // One packet every millisecond with a lot of jitter
+                let sent = start + Duration::from_millis(index);
+                let release = model.release_at(sent, 100, 40);
+                assert!(release + Duration::from_millis(window_ms) >= latest, "window {} ms", window_ms);
+                if release < latest {
+                    overtakes += 1;
+                }
+                latest = latest.max(release);
+            }
+
+            // Without a window packets stay in order, with one they do overtake
+            assert_eq!(overtakes == 0, window_ms == 0, "window {} ms", window_ms);
+        }
+    }
+}
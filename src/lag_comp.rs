@@ -0,0 +1,187 @@
+use crate::types::{Position, PositionSnapshot};
+
+/// How a rewound position was found. Every fallback says why the history couldn't answer exactly, so the
+/// caller decides what to do with it and the counters show which fallbacks the history length causes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagCompResult {
+    Exact,                         // Within the history, interpolated between the entries around the time
+    ClampedToOldest { age_ms: u64 }, // Older than the history, the oldest entry is used, age_ms before it
+    ClampedToNewest,               // After the newest entry, the player hasn't moved since, so it is where it is now
+    NoHistory,                     // Nothing recorded since the last teleport, the current position is used
+    Refused { age_ms: u64 },       // Further back than the maximum rewind, the current position is used
+}
+
+/// Implementation of the LagCompResult
+impl LagCompResult {
+    /// True if the position is where the player was at the time asked for. After the newest entry the
+    /// player stood still, so the newest position is right as well.
+    pub fn is_accurate(self) -> bool {
+        matches!(self, LagCompResult::Exact | LagCompResult::ClampedToNewest)
+    }
+
+    /// Describes the result for the console
+    pub fn describe(self) -> String {
+        match self {
+            LagCompResult::Exact => "exact".to_string(),
+            LagCompResult::ClampedToOldest { age_ms } => format!("clamped to the oldest entry, {} ms older than the history", age_ms),
+            LagCompResult::ClampedToNewest => "after the newest entry, not moved since".to_string(),
+            LagCompResult::NoHistory => "no history since the last teleport, current position".to_string(),
+            LagCompResult::Refused { age_ms } => format!("{} ms is beyond the maximum rewind, current position", age_ms),
+        }
+    }
+}
+
+/// Where a player was at the server time target_ms, from its history since the last teleport (oldest first)
+/// and its current position. Rewinds further back from now_ms than max_rewind_ms are refused, the
+/// current position is used instead so a client with a huge delay can't reach far into the past.
+pub fn rewind<'a>(
+    history: impl IntoIterator<Item = &'a PositionSnapshot>,
+    current: Position,
+    target_ms: u64,
+    now_ms: u64,
+    max_rewind_ms: u64,
+) -> (Position, LagCompResult) {
+    let age_ms = now_ms.saturating_sub(target_ms);
+    if age_ms > max_rewind_ms {
+        return (current, LagCompResult::Refused { age_ms });
+    }
+
+    let entries: Vec<&PositionSnapshot> = history.into_iter().collect();
+    let (Some(oldest), Some(newest)) = (entries.first(), entries.last()) else {
+        return (current, LagCompResult::NoHistory);
+    };
+    if target_ms < oldest.timestamp {
+        return (oldest.position, LagCompResult::ClampedToOldest { age_ms: oldest.timestamp - target_ms });
+    }
+    if target_ms > newest.timestamp {
+        return (newest.position, LagCompResult::ClampedToNewest);
+    }
+
+    // The last entry at or before the time, and the one after it to interpolate towards
+    let index = entries.partition_point(|entry| entry.timestamp <= target_ms) - 1;
+    let before = entries[index];
+    let Some(after) = entries.get(index + 1).filter(|after| after.timestamp > before.timestamp) else {
+        return (before.position, LagCompResult::Exact);
+    };
+    let t = (target_ms - before.timestamp) as f64 / (after.timestamp - before.timestamp) as f64;
+    let lerp = |from: i32, to: i32| (from as f64 + (to - from) as f64 * t).round() as i32;
+    (Position { x: lerp(before.position.x, after.position.x), y: lerp(before.position.y, after.position.y) }, LagCompResult::Exact)
+}
+
+/// Rewinds per result since the stats were last taken, to tune the history length from how often it fell short
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LagCompCounters {
+    pub exact: u64,
+    pub clamped_to_oldest: u64,
+    pub clamped_to_newest: u64,
+    pub no_history: u64,
+    pub refused: u64,
+}
+
+/// Implementation of the LagCompCounters
+impl LagCompCounters {
+    /// Counts a rewind
+    pub fn record(&mut self, result: LagCompResult) {
+        match result {
+            LagCompResult::Exact => self.exact += 1,
+            LagCompResult::ClampedToOldest { .. } => self.clamped_to_oldest += 1,
+            LagCompResult::ClampedToNewest => self.clamped_to_newest += 1,
+            LagCompResult::NoHistory => self.no_history += 1,
+            LagCompResult::Refused { .. } => self.refused += 1,
+        }
+    }
+
+    /// Adds the counts of another room
+    pub fn merge(&mut self, other: &LagCompCounters) {
+        self.exact += other.exact;
+        self.clamped_to_oldest += other.clamped_to_oldest;
+        self.clamped_to_newest += other.clamped_to_newest;
+        self.no_history += other.no_history;
+        self.refused += other.refused;
+    }
+
+    /// Number of rewinds counted
+    pub fn total(&self) -> u64 {
+        self.exact + self.clamped_to_oldest + self.clamped_to_newest + self.no_history + self.refused
+    }
+
+    /// The counts for the stats line
+    pub fn describe(&self) -> String {
+        format!(
+            "{} exact, {} after newest, {} clamped to oldest, {} without history, {} refused",
+            self.exact, self.clamped_to_newest, self.clamped_to_oldest, self.no_history, self.refused
+        )
+    }
+}
+
+/// Tests for the lag compensation rewind
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MoveOrigin;
+
+    // Helper function to create a history entry at the time
+    fn entry(timestamp: u64, x: i32) -> PositionSnapshot {
+        PositionSnapshot { position: Position { x, y: 50 }, timestamp, teleport: false, origin: MoveOrigin::Spawn }
+    }
+
+    #[test]
+    fn test_rewind_within_the_history_is_exact() {
+        let history = [entry(1000, 100), entry(1100, 200), entry(1100, 210), entry(1300, 310)];
+        let current = Position { x: 310, y: 50 };
+        let at = |target_ms| rewind(&history, current, target_ms, 1300, 1000);
+        assert_eq!(at(1000), (Position { x: 100, y: 50 }, LagCompResult::Exact));
+        assert_eq!(at(1050), (Position { x: 150, y: 50 }, LagCompResult::Exact));
+
+        // Of two entries at the same time the later one counts, the newest entry is exact too
+        assert_eq!(at(1100), (Position { x: 210, y: 50 }, LagCompResult::Exact));
+        assert_eq!(at(1200), (Position { x: 260, y: 50 }, LagCompResult::Exact));
+        assert_eq!(at(1300), (current, LagCompResult::Exact));
+    }
+
+    #[test]
+    fn test_rewind_outside_the_history_is_clamped() {
+        let history = [entry(1000, 100), entry(1100, 200)];
+        let current = Position { x: 200, y: 50 };
+
+        // A millisecond before the oldest entry is clamped to it, with how much history was missing
+        assert_eq!(rewind(&history, current, 999, 1500, 1000), (Position { x: 100, y: 50 }, LagCompResult::ClampedToOldest { age_ms: 1 }));
+        assert_eq!(rewind(&history, current, 700, 1500, 1000), (Position { x: 100, y: 50 }, LagCompResult::ClampedToOldest { age_ms: 300 }));
+
+        // After the newest entry the player stood still
+        let (position, result) = rewind(&history, current, 1101, 1500, 1000);
+        assert_eq!((position, result), (current, LagCompResult::ClampedToNewest));
+        assert!(result.is_accurate() && !LagCompResult::ClampedToOldest { age_ms: 1 }.is_accurate());
+
+        // Nothing since the last teleport gives the current position
+        assert_eq!(rewind(&[], current, 1400, 1500, 1000), (current, LagCompResult::NoHistory));
+    }
+
+    #[test]
+    fn test_rewinds_beyond_the_maximum_are_refused() {
+        let history = [entry(0, 0), entry(2000, 200)];
+        let current = Position { x: 200, y: 50 };
+
+        // Exactly the maximum back is still rewound, a millisecond more is refused even with history to answer it
+        assert_eq!(rewind(&history, current, 1000, 2000, 1000), (Position { x: 100, y: 50 }, LagCompResult::Exact));
+        assert_eq!(rewind(&history, current, 999, 2000, 1000), (current, LagCompResult::Refused { age_ms: 1001 }));
+        assert_eq!(rewind(&[], current, 0, 2000, 1000), (current, LagCompResult::Refused { age_ms: 2000 }));
+
+        // A time ahead of now isn't a rewind at all
+        assert_eq!(rewind(&history, current, 2500, 2000, 0), (current, LagCompResult::ClampedToNewest));
+    }
+
+    #[test]
+    fn test_counters_per_result() {
+        let mut counters = LagCompCounters::default();
+        for result in [LagCompResult::Exact, LagCompResult::Exact, LagCompResult::ClampedToOldest { age_ms: 5 }, LagCompResult::NoHistory, LagCompResult::Refused { age_ms: 900 }] {
+            counters.record(result);
+        }
+        let mut other = LagCompCounters::default();
+        other.record(LagCompResult::ClampedToNewest);
+        counters.merge(&other);
+        assert_eq!(counters, LagCompCounters { exact: 2, clamped_to_oldest: 1, clamped_to_newest: 1, no_history: 1, refused: 1 });
+        assert_eq!(counters.total(), 6);
+        assert_eq!(counters.describe(), "2 exact, 1 after newest, 1 clamped to oldest, 1 without history, 1 refused");
+    }
+}
@@ -0,0 +1,372 @@
+use crate::streaming_stats::RunningStats;
+use crate::types::InputTiming;
+
+const STAGE_COUNT: usize = LatencyStage::ALL.len(); // Number of stages in a breakdown
+
+/// Stages an input goes through until the snapshot acknowledging it is on screen, in pipeline order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LatencyStage {
+    ClientQueue, // Captured until it left the client's simulated send delay
+    Uplink,      // Sent until the server received it
+    ServerQueue, // Received until the server applied it
+    TickWait,    // Applied until the tick of the first snapshot including it
+    SendWait,    // Snapshot tick until the server sent the snapshot
+    Downlink,    // Sent until the client received the snapshot
+    Render,      // Received until the next frame
+}
+
+/// Implementation of the LatencyStage
+impl LatencyStage {
+    pub const ALL: [LatencyStage; 7] = [
+        LatencyStage::ClientQueue,
+        LatencyStage::Uplink,
+        LatencyStage::ServerQueue,
+        LatencyStage::TickWait,
+        LatencyStage::SendWait,
+        LatencyStage::Downlink,
+        LatencyStage::Render,
+    ];
+
+    /// Name of the stage in reports and overlays
+    pub fn name(self) -> &'static str {
+        match self {
+            LatencyStage::ClientQueue => "client queue",
+            LatencyStage::Uplink => "uplink",
+            LatencyStage::ServerQueue => "server queue",
+            LatencyStage::TickWait => "tick wait",
+            LatencyStage::SendWait => "send wait",
+            LatencyStage::Downlink => "downlink",
+            LatencyStage::Render => "render",
+        }
+    }
+}
+
+/// Milliseconds one input spent in each stage, stages that couldn't be measured are None
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LatencyBreakdown {
+    stages: [Option<f64>; STAGE_COUNT],
+    tick_interval_ms: f64, // Tick interval the server reported, the tick wait is spread evenly below it
+}
+
+/// Implementation of the LatencyBreakdown
+impl LatencyBreakdown {
+    /// Milliseconds spent in a stage
+    pub fn stage(&self, stage: LatencyStage) -> Option<f64> {
+        self.stages[stage as usize]
+    }
+
+    /// Milliseconds from capture to screen, None unless every stage was measured
+    pub fn total(&self) -> Option<f64> {
+        self.stages.iter().copied().sum()
+    }
+
+    /// The total without the half tick an input waits on average for the next tick. What is left can be
+    /// compared between servers with different tick rates.
+    pub fn tick_compensated_total(&self) -> Option<f64> {
+        self.total().map(|total| total - self.tick_interval_ms / 2.0)
+    }
+
+    /// How much longer than the expected half tick the input waited for its tick, None if the wait wasn't measured
+    pub fn tick_residual(&self) -> Option<f64> {
+        self.stage(LatencyStage::TickWait).map(|wait| wait - self.tick_interval_ms / 2.0)
+    }
+}
+
+/// What is known about an input when a snapshot acknowledging it arrives.
+/// Client times are local milliseconds, server times are milliseconds on the tick timeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputJourney {
+    pub timing: InputTiming,
+    pub send_delay_ms: Option<f64>,    // Time in the client's send delay, None if the client no longer knows it
+    pub snapshot_tick_ms: f64,         // Server time of the tick the snapshot was built on
+    pub snapshot_sent_ms: f64,         // Server time the snapshot was sent
+    pub tick_interval_ms: f64,
+    pub received_at_ms: f64,           // Local time the snapshot arrived
+    pub server_offset_ms: Option<f64>, // Local time minus server time, None before the server clock is estimated
+}
+
+/// Implementation of the InputJourney
+impl InputJourney {
+    /// Splits the journey into stages, with the frame after the snapshot arrived starting at rendered_at_ms.
+    /// The stages crossing the network need the server clock, they are None without it.
+    /// If the first snapshot after the input was applied got lost, the wait for the next one isn't counted as tick wait.
+    pub fn breakdown(&self, rendered_at_ms: f64) -> LatencyBreakdown {
+        let captured = self.timing.client_timestamp as f64;
+        let sent = self.send_delay_ms.map(|delay| captured + delay);
+        let received = self.timing.received_ms as f64;
+        let applied = self.timing.applied_ms as f64;
+        let first_snapshot = self.snapshot_tick_ms - applied <= self.tick_interval_ms;
+
+        let mut stages = [None; STAGE_COUNT];
+        stages[LatencyStage::ClientQueue as usize] = self.send_delay_ms;
+        stages[LatencyStage::Uplink as usize] = sent.zip(self.server_offset_ms).map(|(sent, offset)| received - (sent - offset));
+        stages[LatencyStage::ServerQueue as usize] = Some(applied - received);
+        stages[LatencyStage::TickWait as usize] = first_snapshot.then_some(self.snapshot_tick_ms - applied);
+        stages[LatencyStage::SendWait as usize] = Some(self.snapshot_sent_ms - self.snapshot_tick_ms);
+        stages[LatencyStage::Downlink as usize] = self.server_offset_ms.map(|offset| self.received_at_ms - offset - self.snapshot_sent_ms);
+        stages[LatencyStage::Render as usize] = Some(rendered_at_ms - self.received_at_ms);
+
+        // The clock estimate is not exact, so a stage can come out slightly negative
+        for stage in stages.iter_mut().flatten() {
+            *stage = stage.max(0.0);
+        }
+        LatencyBreakdown { stages, tick_interval_ms: self.tick_interval_ms }
+    }
+}
+
+/// Turns the input timings of received snapshots into one breakdown per acknowledged input
+#[derive(Debug, Clone, Default)]
+pub struct LatencyTracker {
+    newest_sequence: Option<u32>,  // Newest input a breakdown was started for
+    pending: Option<InputJourney>, // Waiting for the next frame
+}
+
+/// Implementation of the LatencyTracker
+impl LatencyTracker {
+    /// Creates a tracker that hasn't seen any input yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a breakdown for the input of a received snapshot. Inputs already seen in earlier snapshots are skipped.
+    pub fn on_snapshot(&mut self, journey: InputJourney) {
+        if self.newest_sequence.is_some_and(|newest| journey.timing.sequence <= newest) {
+            return;
+        }
+        self.newest_sequence = Some(journey.timing.sequence);
+        self.pending = Some(journey);
+    }
+
+    /// Completes the pending breakdown at the start of a frame, returns it if there was one
+    pub fn on_frame(&mut self, now_ms: f64) -> Option<LatencyBreakdown> {
+        self.pending.take().map(|journey| journey.breakdown(now_ms))
+    }
+
+    /// Forgets the seen inputs, for a session that counts sequences from zero again
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Average time per stage over many inputs. Each stage is averaged over the inputs it was measured for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyBudget {
+    sums: [f64; STAGE_COUNT],
+    counts: [u32; STAGE_COUNT],
+    inputs: u32,
+    tick_intervals: RunningStats, // Tick interval of each input, for the expected tick wait
+    tick_residuals: RunningStats, // Tick wait minus the expected half tick, of the inputs it was measured for
+}
+
+/// Implementation of the LatencyBudget
+impl LatencyBudget {
+    /// Creates an empty budget
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the breakdown of one input
+    pub fn record(&mut self, breakdown: &LatencyBreakdown) {
+        self.inputs += 1;
+        for stage in LatencyStage::ALL {
+            if let Some(ms) = breakdown.stage(stage) {
+                self.sums[stage as usize] += ms;
+                self.counts[stage as usize] += 1;
+            }
+        }
+        self.tick_intervals.record(breakdown.tick_interval_ms);
+        if let Some(residual) = breakdown.tick_residual() {
+            self.tick_residuals.record(residual);
+        }
+    }
+
+    /// Average milliseconds of a stage, None if it was never measured
+    pub fn average(&self, stage: LatencyStage) -> Option<f64> {
+        let count = self.counts[stage as usize];
+        (count > 0).then(|| self.sums[stage as usize] / count as f64)
+    }
+
+    /// Number of inputs a stage was measured for
+    pub fn count(&self, stage: LatencyStage) -> u32 {
+        self.counts[stage as usize]
+    }
+
+    /// Number of inputs recorded
+    pub fn inputs(&self) -> u32 {
+        self.inputs
+    }
+
+    /// Sum of the stage averages, stages that were never measured count as zero
+    pub fn total_average(&self) -> f64 {
+        LatencyStage::ALL.iter().filter_map(|stage| self.average(*stage)).fold(0.0, |total, ms| total + ms)
+    }
+
+    /// The total average without the expected half tick of waiting, None if nothing was recorded
+    pub fn tick_compensated_average(&self) -> Option<f64> {
+        self.tick_intervals.mean().map(|interval| self.total_average() - interval / 2.0)
+    }
+
+    /// Distribution of how far the tick waits were off the expected half tick. Centered on zero with a
+    /// deviation of about a tick over the square root of 12 when inputs arrive at random points of a tick.
+    pub fn tick_residuals(&self) -> &RunningStats {
+        &self.tick_residuals
+    }
+}
+
+/// Tests for the latency breakdown
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to create the journey of an input through a server with queued inputs and a held snapshot
+    fn journey() -> InputJourney {
+        InputJourney {
+            timing: InputTiming { sequence: 7, client_timestamp: 10_000, received_ms: 5_080, applied_ms: 5_100 },
+            send_delay_ms: Some(30.0),
+            snapshot_tick_ms: 5_104.0,
+            snapshot_sent_ms: 5_110.0,
+            tick_interval_ms: 16.0,
+            received_at_ms: 10_160.0,
+            server_offset_ms: Some(4_980.0), // The client clock is 4980 ms ahead of the tick timeline
+        }
+    }
+
+    #[test]
+    fn test_breakdown_of_every_stage() {
+        let breakdown = journey().breakdown(10_170.0);
+        let expected = [
+            (LatencyStage::ClientQueue, 30.0), // Sent at 10030 local
+            (LatencyStage::Uplink, 30.0),      // 10030 local is 5050 server, received at 5080
+            (LatencyStage::ServerQueue, 20.0),
+            (LatencyStage::TickWait, 4.0),
+            (LatencyStage::SendWait, 6.0),
+            (LatencyStage::Downlink, 70.0),    // Received at 10160 local, 5180 server
+            (LatencyStage::Render, 10.0),
+        ];
+        for (stage, ms) in expected {
+            assert_eq!(breakdown.stage(stage), Some(ms), "{}", stage.name());
+        }
+        assert_eq!(breakdown.total(), Some(170.0)); // From capture at 10000 to the frame at 10170
+    }
+
+    #[test]
+    fn test_missing_stages() {
+        // Without the server clock the stages crossing the network are unknown
+        let breakdown = InputJourney { server_offset_ms: None, ..journey() }.breakdown(10_170.0);
+        assert_eq!(breakdown.stage(LatencyStage::Uplink), None);
+        assert_eq!(breakdown.stage(LatencyStage::Downlink), None);
+        assert_eq!(breakdown.stage(LatencyStage::ServerQueue), Some(20.0));
+        assert_eq!(breakdown.total(), None);
+
+        // Without the send delay the uplink has no start
+        let breakdown = InputJourney { send_delay_ms: None, ..journey() }.breakdown(10_170.0);
+        assert_eq!(breakdown.stage(LatencyStage::ClientQueue), None);
+        assert_eq!(breakdown.stage(LatencyStage::Uplink), None);
+        assert_eq!(breakdown.stage(LatencyStage::Downlink), Some(70.0));
+
+        // The first snapshot after the input was lost, the one that arrived is two ticks later
+        let breakdown = InputJourney { snapshot_tick_ms: 5_136.0, snapshot_sent_ms: 5_142.0, ..journey() }.breakdown(10_170.0);
+        assert_eq!(breakdown.stage(LatencyStage::TickWait), None);
+        assert_eq!(breakdown.stage(LatencyStage::SendWait), Some(6.0));
+
+        // An estimate that puts the server ahead doesn't make a stage negative
+        let breakdown = InputJourney { server_offset_ms: Some(5_100.0), ..journey() }.breakdown(10_170.0);
+        assert_eq!(breakdown.stage(LatencyStage::Downlink), Some(0.0));
+    }
+
+    #[test]
+    fn test_tracker_takes_each_input_once() {
+        let mut tracker = LatencyTracker::new();
+        assert_eq!(tracker.on_frame(10_170.0), None);
+
+        tracker.on_snapshot(journey());
+        assert_eq!(tracker.on_frame(10_170.0).and_then(|breakdown| breakdown.stage(LatencyStage::Render)), Some(10.0));
+        assert_eq!(tracker.on_frame(10_186.0), None);
+
+        // The same input repeated in the next snapshot, or an older one arriving late, starts nothing
+        tracker.on_snapshot(InputJourney { snapshot_tick_ms: 5_120.0, ..journey() });
+        let mut older = journey();
+        older.timing.sequence = 6;
+        tracker.on_snapshot(older);
+        assert_eq!(tracker.on_frame(10_186.0), None);
+
+        // After a reset sequences count from zero again
+        tracker.reset();
+        older.timing.sequence = 0;
+        tracker.on_snapshot(older);
+        assert!(tracker.on_frame(10_186.0).is_some());
+    }
+
+    #[test]
+    fn test_budget_averages_measured_stages() {
+        let mut budget = LatencyBudget::new();
+        assert_eq!(budget.average(LatencyStage::Uplink), None);
+        assert_eq!(budget.total_average(), 0.0);
+
+        budget.record(&journey().breakdown(10_170.0));
+        budget.record(&InputJourney { server_offset_ms: None, ..journey() }.breakdown(10_190.0));
+
+        assert_eq!(budget.inputs(), 2);
+        assert_eq!(budget.count(LatencyStage::Uplink), 1);
+        assert_eq!(budget.average(LatencyStage::Uplink), Some(30.0)); // Only the input it was measured for
+        assert_eq!(budget.count(LatencyStage::Render), 2);
+        assert_eq!(budget.average(LatencyStage::Render), Some(20.0));
+        assert_eq!(budget.total_average(), 180.0);
+    }
+
+    // Helper function to create the journey of an input applied the given whole milliseconds into a tick of the
+    // given length, sent at 0 local time with a 30 ms uplink and the snapshot of the next tick taking 50 ms back
+    fn journey_into_tick(tick_ms: f64, into_tick_ms: f64) -> InputJourney {
+        let tick_start = 100.0 * tick_ms;
+        let applied = tick_start + into_tick_ms;
+        let server_offset_ms = 30.0 - applied;
+        InputJourney {
+            timing: InputTiming { sequence: 1, client_timestamp: 0, received_ms: applied as u64, applied_ms: applied as u64 },
+            send_delay_ms: Some(0.0),
+            snapshot_tick_ms: tick_start + tick_ms,
+            snapshot_sent_ms: tick_start + tick_ms,
+            tick_interval_ms: tick_ms,
+            received_at_ms: tick_start + tick_ms + server_offset_ms + 50.0,
+            server_offset_ms: Some(server_offset_ms),
+        }
+    }
+
+    #[test]
+    fn test_tick_compensation_removes_the_half_tick() {
+        // The tick wait of an input half a tick before its tick is exactly the expected one
+        let breakdown = journey_into_tick(50.0, 25.0).breakdown(journey_into_tick(50.0, 25.0).received_at_ms + 10.0);
+        assert_eq!(breakdown.stage(LatencyStage::TickWait), Some(25.0));
+        assert_eq!(breakdown.tick_residual(), Some(0.0));
+        assert_eq!(breakdown.tick_compensated_total(), breakdown.total().map(|total| total - 25.0));
+
+        // Without a measured tick wait there is no residual, the total is still compensated once it is known
+        let lost = InputJourney { snapshot_tick_ms: 100.0 * 50.0 + 100.0, ..journey_into_tick(50.0, 25.0) }.breakdown(0.0);
+        assert_eq!(lost.tick_residual(), None);
+    }
+
+    #[test]
+    fn test_compensated_lag_is_comparable_across_tick_rates() {
+        // Inputs spread evenly over the tick at 25 Hz and at 50 Hz, otherwise on the same network
+        let budget_at = |tick_ms: f64| {
+            let mut budget = LatencyBudget::new();
+            for step in 0..10 {
+                let journey = journey_into_tick(tick_ms, tick_ms * (step as f64 + 0.5) / 10.0);
+                budget.record(&journey.breakdown(journey.received_at_ms + 10.0));
+            }
+            budget
+        };
+        let (slow, fast) = (budget_at(40.0), budget_at(20.0));
+
+        // The raw lag differs by the difference of the half ticks, the compensated one doesn't
+        assert!((slow.total_average() - fast.total_average() - 10.0).abs() < 1e-9);
+        let (slow_compensated, fast_compensated) = (slow.tick_compensated_average().unwrap(), fast.tick_compensated_average().unwrap());
+        assert!((slow_compensated - fast_compensated).abs() < 1e-9, "{} {}", slow_compensated, fast_compensated);
+
+        // The residuals are centered on zero and spread over the tick
+        let residuals = slow.tick_residuals();
+        assert_eq!(residuals.count(), 10);
+        assert!(residuals.mean().unwrap().abs() < 1e-9);
+        assert_eq!((residuals.min(), residuals.max()), (Some(-18.0), Some(18.0)));
+        assert_eq!(LatencyBudget::new().tick_compensated_average(), None);
+    }
+}
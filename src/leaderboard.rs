@@ -0,0 +1,250 @@
+use crate::game::PlayerRecord;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use uuid::Uuid;
+
+const LEADERBOARD_VERSION: u32 = 2; // Schema version written to the leaderboard file, version 2 added the names
+
+/// Best score and total pellets of one player in the leaderboard file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub best_score: u32,
+    pub total_pellets: u32,
+    #[serde(default)]
+    pub name: String, // Name the player had last, empty in files from before names were kept
+}
+
+/// Implementation of the LeaderboardEntry
+impl LeaderboardEntry {
+    /// Merges two entries for the same player, keeping the highest value of each field and the
+    /// other entry's name unless it has none
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            best_score: self.best_score.max(other.best_score),
+            total_pellets: self.total_pellets.max(other.total_pellets),
+            name: if other.name.is_empty() { self.name } else { other.name },
+        }
+    }
+}
+
+/// Current layout of the leaderboard file
+#[derive(Debug, Serialize, Deserialize)]
+struct LeaderboardFile {
+    version: u32,
+    entries: HashMap<Uuid, LeaderboardEntry>,
+}
+
+/// Any layout the leaderboard file has had, the first version was a plain map of entries
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredLeaderboard {
+    Versioned(LeaderboardFile),
+    Unversioned(HashMap<Uuid, LeaderboardEntry>),
+}
+
+/// Best scores of every player the server has seen, keyed by session token
+#[derive(Debug, Default, PartialEq)]
+pub struct Leaderboard {
+    entries: HashMap<Uuid, LeaderboardEntry>,
+}
+
+/// Implementation of the Leaderboard
+impl Leaderboard {
+    /// Loads the leaderboard from a file. A missing file gives an empty leaderboard,
+    /// a file written by a newer server is refused instead of being overwritten later.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        let entries = match serde_json::from_str(&data).map_err(invalid_data)? {
+            StoredLeaderboard::Versioned(file) if file.version > LEADERBOARD_VERSION => {
+                return Err(invalid_data(format!(
+                    "leaderboard version {} is newer than the supported version {}",
+                    file.version, LEADERBOARD_VERSION
+                )));
+            }
+            StoredLeaderboard::Versioned(file) => file.entries,
+            StoredLeaderboard::Unversioned(entries) => entries,
+        };
+        Ok(Self { entries })
+    }
+
+    /// Writes the leaderboard to a temporary file next to the path and renames it over the old file,
+    /// so a crash while saving never leaves a half written leaderboard behind
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = LeaderboardFile {
+            version: LEADERBOARD_VERSION,
+            entries: self.entries.clone(),
+        };
+        let data = serde_json::to_string_pretty(&file).map_err(invalid_data)?;
+
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        fs::write(&temp_path, data)?;
+        fs::rename(&temp_path, path)
+    }
+
+    /// Adds the record of a player, merging it with an existing entry for the same token
+    pub fn record(&mut self, token: Uuid, record: PlayerRecord) {
+        let entry = LeaderboardEntry {
+            best_score: record.best_score,
+            total_pellets: record.total_pellets,
+            name: record.name,
+        };
+        let merged = match self.entries.remove(&token) {
+            Some(old) => old.merge(entry),
+            None => entry,
+        };
+        self.entries.insert(token, merged);
+    }
+
+    /// Number of players in the leaderboard
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks if the leaderboard has no players
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Records of every player, in the form the game uses for returning players
+    pub fn player_records(&self) -> HashMap<Uuid, PlayerRecord> {
+        self.entries.iter()
+            .map(|(token, entry)| (*token, PlayerRecord {
+                best_score: entry.best_score,
+                total_pellets: entry.total_pellets,
+                name: entry.name.clone(),
+            }))
+            .collect()
+    }
+}
+
+/// Wraps a parse or format error as an invalid data error
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Tests for the leaderboard persistence
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Helper function to get a fresh path in a temporary directory for one test
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("leaderboard-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    // Helper function to create a record
+    fn record(best_score: u32, total_pellets: u32) -> PlayerRecord {
+        PlayerRecord { best_score, total_pellets, name: String::new() }
+    }
+
+    #[test]
+    fn test_missing_file_is_empty() {
+        let leaderboard = Leaderboard::load(&temp_path("missing.json")).unwrap();
+        assert_eq!(leaderboard.len(), 0);
+    }
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let path = temp_path("leaderboard.json");
+        let token = Uuid::new_v4();
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.record(token, record(5, 12));
+
+        leaderboard.save(&path).unwrap();
+        let loaded = Leaderboard::load(&path).unwrap();
+
+        assert_eq!(loaded, leaderboard);
+        assert_eq!(loaded.entries.get(&token).cloned(), Some(LeaderboardEntry { best_score: 5, total_pellets: 12, name: String::new() }));
+
+        // The temporary file was renamed away
+        let files: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().collect();
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn test_save_replaces_old_file() {
+        let path = temp_path("leaderboard.json");
+        fs::write(&path, "old contents").unwrap();
+
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.record(Uuid::new_v4(), record(1, 1));
+        leaderboard.save(&path).unwrap();
+
+        assert_eq!(Leaderboard::load(&path).unwrap(), leaderboard);
+    }
+
+    #[test]
+    fn test_colliding_entries_keep_highest_values() {
+        let token = Uuid::new_v4();
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.record(token, record(8, 20));
+        leaderboard.record(token, record(3, 25));
+
+        assert_eq!(leaderboard.len(), 1);
+        assert_eq!(leaderboard.entries.get(&token).cloned(), Some(LeaderboardEntry { best_score: 8, total_pellets: 25, name: String::new() }));
+    }
+
+    #[test]
+    fn test_loads_unversioned_file() {
+        let path = temp_path("leaderboard.json");
+        let token = Uuid::new_v4();
+        fs::write(&path, format!(r#"{{"{}": {{"best_score": 4, "total_pellets": 9}}}}"#, token)).unwrap();
+
+        let leaderboard = Leaderboard::load(&path).unwrap();
+        assert_eq!(leaderboard.entries.get(&token).cloned(), Some(LeaderboardEntry { best_score: 4, total_pellets: 9, name: String::new() }));
+    }
+
+    #[test]
+    fn test_names_are_kept_and_version_1_files_load() {
+        let path = temp_path("leaderboard.json");
+        let token = Uuid::new_v4();
+        fs::write(&path, format!(r#"{{"version": 1, "entries": {{"{}": {{"best_score": 4, "total_pellets": 9}}}}}}"#, token)).unwrap();
+        let mut leaderboard = Leaderboard::load(&path).unwrap();
+        assert_eq!(leaderboard.entries[&token].name, "");
+
+        // A later record brings the name, a record without one doesn't erase it
+        leaderboard.record(token, PlayerRecord { name: "Kari".to_string(), ..record(2, 12) });
+        leaderboard.record(token, record(1, 1));
+        leaderboard.save(&path).unwrap();
+        assert_eq!(Leaderboard::load(&path).unwrap().player_records()[&token], PlayerRecord { best_score: 4, total_pellets: 12, name: "Kari".to_string() });
+    }
+
+    #[test]
+    fn test_refuses_newer_version_and_garbage() {
+        let path = temp_path("leaderboard.json");
+
+        fs::write(&path, r#"{"version": 99, "entries": {}}"#).unwrap();
+        let error = Leaderboard::load(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        fs::write(&path, "not json").unwrap();
+        let error = Leaderboard::load(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_player_records() {
+        let token = Uuid::new_v4();
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.record(token, record(2, 3));
+
+        assert_eq!(leaderboard.player_records(), HashMap::from([(token, record(2, 3))]));
+    }
+}
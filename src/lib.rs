@@ -1,11 +1,78 @@
 pub mod types; // Different types used across the game
 pub mod network; // Network communication and client handling
 pub mod game; // Game logic and state management
+pub mod game_mode; // Rules of the game modes the server can run, free roam, pellet hunt and tag
+#[cfg(feature = "client")]
 pub mod input; // Input handling and prediction logic
+#[cfg(feature = "client")]
 pub mod render; // Rendering for visual output
 pub mod colors; // Colors used in the game
+pub mod color_alloc; // Hands out distinct player colors in a room, with reservations for reconnecting players
 pub mod constants; // Constants for game settings and configurations
+#[cfg(feature = "client")]
 pub mod config; // Configuration settings for the game window and other parameters
+pub mod movement; // Movement of a player by one input, shared by the server and the prediction
+pub mod positions; // Authoritative and drawn positions as separate types, so a drawn position never reaches the prediction or the server
 pub mod prediction; // Prediction logic for client-side movement
 pub mod interpolation; // Interpolation for smooth rendering of player positions
-pub mod analysis; // Performance analysis and testing utilities
\ No newline at end of file
+pub mod interpolation_compare; // Offline playback of one recorded path with every interpolation strategy under every network condition
+pub mod analysis; // Performance analysis and testing utilities
+pub mod streaming_stats; // Aggregates, histograms and reservoir samples that record long runs in constant memory
+pub mod accessibility; // Accessibility helpers such as screen-reader status output
+pub mod util; // Generic helpers shared by the client and the server
+pub mod rtt; // Round trip time estimation from ping echoes
+pub mod scoreboard; // Scoreboard rows and layout
+pub mod render_clock; // Scaled time source for rendering, used by slow motion
+pub mod snapshot; // Snapshot wire format with a core section and optional extension blocks
+pub mod codec; // Pluggable encoding of datagrams, the binary protocol and a JSON one for debugging
+pub mod visuals; // Fade in/out and color blending of players on the client
+pub mod jitter; // Simulated network jitter with a limit on packet reordering
+pub mod text_field; // Editing state of single line text fields, separate from rendering
+pub mod app_state; // Client screens and the table of transitions between them
+pub mod rooms; // Rooms of the server, each with its own game, and the room config file
+pub mod transport; // Server socket that answers every client in the protocol it speaks
+pub mod leaderboard; // Best scores of the players, saved to a file between server runs
+pub mod server_config; // Typed server configuration merged from defaults, a TOML file and command line flags
+pub mod stats_query; // Stats query for external dashboards: request parsing, token check, rate limit and the JSON reply
+pub mod load_shedding; // Token buckets per source and a per tick budget that keep the server receive loop responsive under datagram floods
+pub mod server; // The server loop and message handling, run by the server binary and in process by the smoke test
+pub mod smoke; // Named checks with timings and a report, used by the end-to-end smoke test binary
+pub mod room_browser; // Room list and pick on the client menu, separate from rendering
+pub mod profiler; // Named scopes timed per frame on the thread that ran them, with their averages over a rolling window or a whole test
+pub mod frame_budget; // Frame cost watchdog that sheds optional rendering layers on slow machines
+pub mod latency; // Breakdown of input to screen latency into pipeline stages
+pub mod tick_cadence; // Observed server tick interval from snapshot arrivals, checked against the reported one
+pub mod build_info; // Build metadata and random seeds, exchanged in the handshake and written into reports and exports
+pub mod overlap; // Players drawn overlapping each other, found each frame and counted per pair as a proxy for interpolation artifacts
+pub mod strings; // User-facing strings of the interface in English and Norwegian, picked by language
+pub mod lag_comp; // Rewinding a player through its position history, with explicit fallbacks when the history can't answer
+pub mod input_log; // Log of the recently sent inputs and whether they were acked or dropped, shown in step mode
+pub mod spectator; // Spectator camera that follows players or pans freely over the board
+pub mod heatmap; // Prediction errors bucketed into a grid over the board, by where they were measured
+pub mod coords; // Board space and 2D render space with the one set of conversions between them, the play area and the views
+pub mod minimap; // Layout of the mini-map: board positions and the camera viewport scaled into a corner of the screen
+pub mod split_view; // Single or side by side layout of the board, the client view next to the raw snapshots
+pub mod culling; // Visible board area of the view and the checks that skip drawing what lies outside it
+pub mod traffic; // Bytes and datagrams per message category in both directions, with rates over the last seconds
+pub mod send_rate; // Adaptive input send rate and the loss estimate it is driven by
+pub mod frame_pacer; // Frame rate cap that paces frames with a sleep followed by a short spin
+pub mod warnings; // Thresholds on the live client metrics with hysteresis, shown as warnings and logged
+pub mod session_log; // Plain text timeline of the client events and network condition changes of a session, with totals per event type at the end
+pub mod audio_cues; // Sounds for joins, pickups and corrections, picked from the client events and rate limited per cue
+pub mod client_events; // Typed events of the client, joins, corrections, warnings and connection changes, taken once per frame by every consumer
+pub mod client_world; // Snapshot processing and prediction of the client, shared by the windowed and the headless client
+pub mod scripted_input; // Scripted walk that stands in for the keyboard in the headless client
+pub mod bot_script; // Timelines of scripted bots read from TOML files, played against a clock by the smoke test
+pub mod regression; // Limits the results of the headless analysis are checked against, to catch netcode regressions
+pub mod local_server; // The server loop in process without a socket, with wandering bots, for the offline mode of the client
+pub mod input_dispatch; // Queue of input intents that get their sequences, prediction and sending on one path
+pub mod prediction_watchdog; // Turns the prediction off when its error stays pathological, the local player then follows the snapshots
+pub mod scrubber; // Captures of the recent client state every 100 ms, paused and stepped through to see what led up to a snap
+pub mod game_client; // Network client and client world glued together: connect, move, update and read the world back
+pub mod mirror; // Second connection driven by the same intents as the first, each predicting with its own config for an A/B comparison
+pub mod headless; // Client without a window that runs the performance tests, used by the analyze binary
+pub mod demo; // Unattended demo mode that cycles through scripted network failures with captions
+pub mod diagnostics; // Self-test of encoding, clocks, sockets and config parsing, run with --self-test
+pub mod frame_capture; // Saves the frame to a PNG when the prediction error or a snapshot gap is large, or on a key
+#[cfg(test)]
+mod wire_format; // Golden byte tests that catch accidental wire format changes
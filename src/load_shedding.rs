@@ -0,0 +1,311 @@
+use crate::constants::{RECV_MAX_SOURCES, RECV_SOURCE_BURST, RECV_SOURCE_RATE, RECV_TICK_BUDGET, RECV_UNKNOWN_TICK_BUDGET, BROADCAST_INTERVAL};
+use crate::types::ClientMessage;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How much the receive loop takes from each source and per tick, the constants unless a test changes them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReceiveLimits {
+    pub source_rate: f64,      // Datagrams per second the bucket of a source refills with
+    pub source_burst: f64,     // Datagrams the bucket of a source holds when full
+    pub max_sources: usize,    // Most sources that get a bucket
+    pub tick: Duration,        // Length of the window the budgets are counted over
+    pub tick_budget: usize,    // Datagrams handled per tick
+    pub unknown_budget: usize, // Datagrams from addresses without a player handled per tick, out of the tick budget
+}
+
+/// Default implementation for the ReceiveLimits
+impl Default for ReceiveLimits {
+    fn default() -> Self {
+        Self {
+            source_rate: RECV_SOURCE_RATE,
+            source_burst: RECV_SOURCE_BURST,
+            max_sources: RECV_MAX_SOURCES,
+            tick: BROADCAST_INTERVAL,
+            tick_budget: RECV_TICK_BUDGET,
+            unknown_budget: RECV_UNKNOWN_TICK_BUDGET,
+        }
+    }
+}
+
+/// How many datagrams the receive loop handled and dropped since the server started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShedCounters {
+    pub handled: u64,      // Decoded and handed to the server
+    pub rate_limited: u64, // Dropped undecoded, their source sent faster than its bucket allows
+    pub shed: u64,         // Dropped because the tick budget was used up
+    pub undecodable: u64,  // Dropped because they didn't decode
+}
+
+/// Rank of a datagram when the budget doesn't cover the whole batch, the lower ones are handled first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Gameplay,    // Pings and inputs of a player, what keeps the game playable
+    KnownPlayer, // Anything else from a player
+    Unknown,     // Connects and room lists from addresses without a player
+}
+
+/// Priority of a message from an address, with the message inside a session looked at in its place
+pub fn priority_of(message: &ClientMessage, known: bool) -> Priority {
+    match message {
+        _ if !known => Priority::Unknown,
+        ClientMessage::WithSession { message, .. } => priority_of(message, known),
+        ClientMessage::Ping(_) | ClientMessage::ServerPong(_) | ClientMessage::Input(_) | ClientMessage::InputBatch(_) => Priority::Gameplay,
+        _ => Priority::KnownPlayer,
+    }
+}
+
+/// Tokens of one source, refilled by the time since it last sent
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Protects the receive loop from datagram floods: each source address has a token bucket that is checked
+/// before its datagrams are decoded, and every tick only so many datagrams are handled, with the gameplay
+/// of known players handled before anything from unknown addresses
+#[derive(Debug, Clone)]
+pub struct ReceiveGuard {
+    limits: ReceiveLimits,
+    buckets: HashMap<SocketAddr, TokenBucket>, // Bucket of each source that sent recently
+    window_start: Option<Instant>,             // Start of the current tick window
+    used: usize,                               // Datagrams handled in the current tick window
+    unknown_used: usize,                       // Datagrams from unknown addresses handled in it
+    counters: ShedCounters,
+}
+
+/// Implementation of the ReceiveGuard
+impl ReceiveGuard {
+    /// Creates a guard that has seen nothing yet
+    pub fn new(limits: ReceiveLimits) -> Self {
+        Self { limits, buckets: HashMap::new(), window_start: None, used: 0, unknown_used: 0, counters: ShedCounters::default() }
+    }
+
+    /// True if the source may send a datagram now, which takes a token from its bucket. A new source is
+    /// refused while max_sources others have buckets that aren't full.
+    pub fn allow(&mut self, source: SocketAddr, now: Instant) -> bool {
+        let ReceiveLimits { source_rate, source_burst, .. } = self.limits;
+        if !self.buckets.contains_key(&source) && self.buckets.len() >= self.limits.max_sources {
+            // A full bucket holds nothing a new one wouldn't
+            self.buckets.retain(|_, bucket| bucket.tokens + now.saturating_duration_since(bucket.refilled_at).as_secs_f64() * source_rate < source_burst);
+            if self.buckets.len() >= self.limits.max_sources {
+                return false;
+            }
+        }
+        let bucket = self.buckets.entry(source).or_insert(TokenBucket { tokens: source_burst, refilled_at: now });
+        let elapsed = now.saturating_duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * source_rate).min(source_burst);
+        bucket.refilled_at = bucket.refilled_at.max(now);
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Datagrams still handled in the tick window of now, in total and from unknown addresses
+    fn remaining(&mut self, now: Instant) -> (usize, usize) {
+        if self.window_start.is_none_or(|start| now.saturating_duration_since(start) >= self.limits.tick) {
+            self.window_start = Some(now);
+            self.used = 0;
+            self.unknown_used = 0;
+        }
+        let remaining = self.limits.tick_budget.saturating_sub(self.used);
+        (remaining, remaining.min(self.limits.unknown_budget.saturating_sub(self.unknown_used)))
+    }
+
+    /// Counters since the guard was created
+    pub fn counters(&self) -> ShedCounters {
+        self.counters
+    }
+
+    /// Number of sources that have a bucket
+    pub fn tracked_sources(&self) -> usize {
+        self.buckets.len()
+    }
+}
+
+/// Picks the datagrams of a batch the server handles. Datagrams of sources over their rate are dropped
+/// before they are decoded, the rest are decoded and ranked by priority, and as many as the tick budget
+/// allows are returned in that order, in arrival order within a priority. Once the budget of the tick is
+/// used up the remaining datagrams are shed without being decoded.
+pub fn triage(
+    batch: Vec<(SocketAddr, Vec<u8>)>,
+    guard: &mut ReceiveGuard,
+    now: Instant,
+    mut decode: impl FnMut(SocketAddr, &[u8]) -> Option<ClientMessage>,
+    is_known: impl Fn(&SocketAddr) -> bool,
+) -> Vec<(SocketAddr, ClientMessage)> {
+    let (mut remaining, mut unknown_remaining) = guard.remaining(now);
+    let mut ranked = Vec::new();
+    for (addr, data) in batch {
+        if remaining == 0 {
+            guard.counters.shed += 1;
+        } else if !guard.allow(addr, now) {
+            guard.counters.rate_limited += 1;
+        } else if let Some(message) = decode(addr, &data) {
+            ranked.push((priority_of(&message, is_known(&addr)), addr, message));
+        } else {
+            guard.counters.undecodable += 1;
+        }
+    }
+    ranked.sort_by_key(|(priority, _, _)| *priority);
+
+    let mut handled = Vec::with_capacity(ranked.len().min(remaining));
+    for (priority, addr, message) in ranked {
+        let unknown = priority == Priority::Unknown;
+        if remaining == 0 || (unknown && unknown_remaining == 0) {
+            guard.counters.shed += 1;
+            continue;
+        }
+        remaining -= 1;
+        guard.used += 1;
+        if unknown {
+            unknown_remaining -= 1;
+            guard.unknown_used += 1;
+        }
+        handled.push((addr, message));
+    }
+    guard.counters.handled += handled.len() as u64;
+    handled
+}
+
+/// Tests for the load shedding
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::CodecKind;
+    use crate::types::{Direction, PlayerInput};
+
+    // Helper function to encode a message in the binary protocol
+    fn datagram(message: &ClientMessage) -> Vec<u8> {
+        CodecKind::default().codec().encode_message(message)
+    }
+
+    // Helper function to decode a datagram like the transport does
+    fn decode(_addr: SocketAddr, data: &[u8]) -> Option<ClientMessage> {
+        CodecKind::sniff(data).codec().decode_message(data)
+    }
+
+    // Helper function to create an address on the loopback
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    // Helper function to create an input
+    fn input(sequence: u32) -> ClientMessage {
+        ClientMessage::Input(PlayerInput { dir: Direction::Right, sequence, timestamp: sequence as u64 })
+    }
+
+    #[test]
+    fn test_token_bucket_refills_with_time() {
+        let limits = ReceiveLimits { source_rate: 10.0, source_burst: 3.0, ..Default::default() };
+        let mut guard = ReceiveGuard::new(limits);
+        let start = Instant::now();
+        assert!((0..3).all(|_| guard.allow(addr(1), start)));
+        assert!(!guard.allow(addr(1), start));
+
+        // Another source has a bucket of its own, a tenth of a second refills one token
+        assert!(guard.allow(addr(2), start));
+        assert!(guard.allow(addr(1), start + Duration::from_millis(100)));
+        assert!(!guard.allow(addr(1), start + Duration::from_millis(100)));
+
+        // A long pause refills only up to the burst
+        let later = start + Duration::from_secs(60);
+        assert!((0..3).all(|_| guard.allow(addr(1), later)));
+        assert!(!guard.allow(addr(1), later));
+    }
+
+    #[test]
+    fn test_sources_beyond_the_limit_wait_for_full_buckets() {
+        let limits = ReceiveLimits { source_rate: 10.0, source_burst: 2.0, max_sources: 2, ..Default::default() };
+        let mut guard = ReceiveGuard::new(limits);
+        let start = Instant::now();
+        assert!(guard.allow(addr(1), start) && guard.allow(addr(2), start));
+        assert!(!guard.allow(addr(3), start));
+        assert_eq!(guard.tracked_sources(), 2);
+
+        // Once the buckets refilled they are forgotten to make room
+        assert!(guard.allow(addr(3), start + Duration::from_secs(1)));
+        assert_eq!(guard.tracked_sources(), 1);
+    }
+
+    #[test]
+    fn test_flood_from_one_source_leaves_the_players_through() {
+        let mut guard = ReceiveGuard::new(ReceiveLimits::default());
+        let now = Instant::now();
+        let flooder = addr(666);
+        let players = [addr(1), addr(2), addr(3)];
+
+        // The flood is interleaved with the inputs and pings of the players
+        let mut batch = Vec::new();
+        for round in 0..200u32 {
+            for _ in 0..10 {
+                batch.push((flooder, datagram(&ClientMessage::Connect)));
+            }
+            if round % 20 == 0 {
+                for player in players {
+                    batch.push((player, datagram(&input(round))));
+                    batch.push((player, datagram(&ClientMessage::Ping(round as u64))));
+                }
+            }
+        }
+        let handled = triage(batch, &mut guard, now, decode, |addr| players.contains(addr));
+
+        // All 60 datagrams of the players come first, the flood is cut at its bucket and the unknown budget
+        let from_players = handled.iter().filter(|(addr, _)| players.contains(addr)).count();
+        assert_eq!(from_players, 60);
+        assert!(handled[..60].iter().all(|(addr, _)| players.contains(addr)));
+        let counters = guard.counters();
+        assert_eq!(counters.handled as usize, handled.len());
+        assert_eq!(counters.rate_limited, 2000 - RECV_SOURCE_BURST as u64);
+        assert_eq!(counters.shed, RECV_SOURCE_BURST as u64 - RECV_UNKNOWN_TICK_BUDGET as u64);
+        assert_eq!(handled.len(), 60 + RECV_UNKNOWN_TICK_BUDGET);
+    }
+
+    #[test]
+    fn test_spoofed_flood_is_shed_by_the_tick_budget() {
+        let limits = ReceiveLimits { tick: Duration::from_millis(16), tick_budget: 50, unknown_budget: 10, ..Default::default() };
+        let mut guard = ReceiveGuard::new(limits);
+        let start = Instant::now();
+        let player = addr(1);
+
+        // Every connect has a source of its own, so no bucket runs dry, and garbage is mixed in
+        let mut batch: Vec<(SocketAddr, Vec<u8>)> = (0..1000).map(|i| (addr(10_000 + i), datagram(&ClientMessage::Connect))).collect();
+        batch.extend((0..5).map(|i| (addr(20_000 + i), vec![0xff; 3])));
+        batch.push((player, datagram(&input(1))));
+        batch.push((player, datagram(&ClientMessage::Leave)));
+        let handled = triage(batch, &mut guard, start, decode, |addr| *addr == player);
+        assert_eq!(handled.len(), 12);
+        assert_eq!(handled[0], (player, input(1)));
+        assert_eq!(handled[1], (player, ClientMessage::Leave));
+        assert_eq!(guard.counters(), ShedCounters { handled: 12, rate_limited: 0, shed: 990, undecodable: 5 });
+
+        // The tick's budget for unknown addresses is used up, the player still gets through until the whole budget is
+        let batch = vec![(addr(30_000), datagram(&ClientMessage::Connect)), (player, datagram(&input(2)))];
+        assert_eq!(triage(batch, &mut guard, start, decode, |addr| *addr == player), vec![(player, input(2))]);
+        let flood: Vec<_> = (0..100).map(|_| (player, datagram(&ClientMessage::Ping(0)))).collect();
+        assert_eq!(triage(flood, &mut guard, start, decode, |addr| *addr == player).len(), 37);
+
+        // Once the budget is spent the rest of the tick is drained undecoded, the next tick starts over
+        let batch = vec![(player, datagram(&input(3)))];
+        assert!(triage(batch.clone(), &mut guard, start + Duration::from_millis(5), decode, |addr| *addr == player).is_empty());
+        assert_eq!(guard.counters().shed, 990 + 1 + 63 + 1);
+        assert_eq!(triage(batch, &mut guard, start + Duration::from_millis(16), decode, |addr| *addr == player), vec![(player, input(3))]);
+    }
+
+    #[test]
+    fn test_priorities() {
+        let session = |message| ClientMessage::WithSession { session_token: uuid::Uuid::nil(), message: Box::new(message) };
+        assert_eq!(priority_of(&input(1), true), Priority::Gameplay);
+        assert_eq!(priority_of(&ClientMessage::Ping(1), true), Priority::Gameplay);
+        assert_eq!(priority_of(&session(ClientMessage::InputBatch(vec![])), true), Priority::Gameplay);
+        assert_eq!(priority_of(&ClientMessage::Leave, true), Priority::KnownPlayer);
+        assert_eq!(priority_of(&input(1), false), Priority::Unknown);
+        assert_eq!(priority_of(&ClientMessage::Connect, false), Priority::Unknown);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::codec::CodecKind;
+use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH, OFFLINE_BOT_TURN_TICKS, PLAYER_SIZE};
+use crate::network::Link;
+use crate::rooms::{RoomConfig, RoomRegistry};
+use crate::server::{accept_session, create_rooms, handle_message, run_tick, Reply, ServerTuning};
+use crate::server_config::ServerConfig;
+use crate::types::{ClientMessage, Direction, PlayerInput, PlayerShape, Position};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A bot of the offline mode that walks the board, turning now and then and before it reaches an edge
+struct WanderingBot {
+    addr: SocketAddr,
+    dir: Direction,
+    sequence: u32,
+    turn_in: u32, // Ticks left before it picks another direction
+    rng: StdRng,
+}
+
+/// Implementation of the WanderingBot
+impl WanderingBot {
+    /// The direction of the next input from the position, turning when it is time or an edge is close
+    fn next_direction(&mut self, position: Position) -> Direction {
+        let margin = PLAYER_SIZE * 2;
+        let blocked = match self.dir {
+            Direction::Up => position.y < margin,
+            Direction::Down => position.y > BOARD_HEIGHT - margin,
+            Direction::Left => position.x < margin,
+            Direction::Right => position.x > BOARD_WIDTH - margin,
+        };
+        self.turn_in = self.turn_in.saturating_sub(1);
+        if blocked || self.turn_in == 0 {
+            let open: Vec<Direction> = [Direction::Up, Direction::Down, Direction::Left, Direction::Right]
+                .into_iter()
+                .filter(|dir| *dir != self.dir)
+                .filter(|dir| match dir {
+                    Direction::Up => position.y >= margin,
+                    Direction::Down => position.y <= BOARD_HEIGHT - margin,
+                    Direction::Left => position.x >= margin,
+                    Direction::Right => position.x <= BOARD_WIDTH - margin,
+                })
+                .collect();
+            if !open.is_empty() {
+                self.dir = open[self.rng.random_range(0..open.len())];
+            }
+            self.turn_in = self.rng.random_range(OFFLINE_BOT_TURN_TICKS);
+        }
+        self.dir
+    }
+}
+
+/// The server of run_server without a socket, for the offline mode of the client. Datagrams are handled
+/// and rooms ticked by the same code as on the real server, the replies come back as encoded datagrams
+/// for the loopback links instead of being sent. The simulated processing delay of the config is not
+/// applied, the client's own network conditions stand in for it.
+pub struct LocalServer {
+    rooms: RoomRegistry,
+    codecs: HashMap<SocketAddr, CodecKind>, // Protocol of the last datagram each client sent, bots send none
+    match_duration: Option<Duration>,
+    tick_interval: Duration,
+    tuning: ServerTuning,
+    last_ping: Instant,
+    bots: Vec<WanderingBot>,
+}
+
+/// Implementation of the LocalServer
+impl LocalServer {
+    /// Creates the rooms of the config and puts bot_count wandering bots in the default room
+    pub fn new(config: &ServerConfig, bot_count: usize) -> Self {
+        let tuning = ServerTuning::default();
+        let mut rooms = create_rooms(config, RoomConfig::default(), HashMap::new(), tuning);
+        let mut rng = config.spawn_seed.map_or_else(StdRng::from_os_rng, StdRng::seed_from_u64);
+        let room = rooms.default_room().to_string();
+        let bots = (1..=bot_count as u16)
+            .filter_map(|index| {
+                let addr = SocketAddr::from(([127, 0, 0, 2], index));
+                let game = rooms.join(addr, &room, None).ok()?;
+                game.connect_player(addr);
+                game.set_profile(&addr, &format!("Bot {}", index), PlayerShape::Circle);
+                let dir = [Direction::Up, Direction::Down, Direction::Left, Direction::Right][rng.random_range(0..4)];
+                Some(WanderingBot { addr, dir, sequence: 0, turn_in: rng.random_range(OFFLINE_BOT_TURN_TICKS), rng: StdRng::seed_from_u64(rng.random()) })
+            })
+            .collect();
+        Self {
+            rooms,
+            codecs: HashMap::new(),
+            match_duration: config.match_duration,
+            tick_interval: config.tick_interval(),
+            tuning,
+            last_ping: Instant::now(),
+            bots,
+        }
+    }
+
+    /// Time between two ticks
+    pub fn tick_interval(&self) -> Duration {
+        self.tick_interval
+    }
+
+    /// The rooms of the server
+    pub fn rooms(&self) -> &RoomRegistry {
+        &self.rooms
+    }
+
+    /// Handles a datagram from a client like the server's receive loop, returning the datagrams it answers with
+    pub fn receive(&mut self, addr: SocketAddr, data: &[u8]) -> Vec<(SocketAddr, Vec<u8>)> {
+        let kind = CodecKind::sniff(data);
+        let Some(msg) = kind.codec().decode_message(data) else {
+            return Vec::new();
+        };
+        self.codecs.insert(addr, kind);
+        let msg = accept_session(&mut self.rooms, addr, msg);
+        let mut replies = Vec::new();
+        handle_message(&mut self.rooms, addr, msg, &mut replies);
+        let mut datagrams = Vec::new();
+        for (addr, reply) in replies {
+            match reply {
+                Reply::Message(message) => self.encode(&[addr], |kind| kind.codec().encode_message(&message), &mut datagrams),
+                Reply::Snapshot(snapshot) => self.encode(&[addr], |kind| kind.codec().encode_snapshot(&snapshot), &mut datagrams),
+            }
+        }
+        datagrams
+    }
+
+    /// Moves the bots and runs one tick of every room like the server's broadcast task, returning the
+    /// match resets, removals, snapshots and pings it sends, in that order
+    pub fn tick(&mut self, now: Instant) -> Vec<(SocketAddr, Vec<u8>)> {
+        for bot in &mut self.bots {
+            let Some(game) = self.rooms.game_of_mut(&bot.addr) else {
+                continue;
+            };
+            let Some(position) = game.get_players_mut().get(&bot.addr).map(|player| player.position) else {
+                continue;
+            };
+            bot.sequence += 1;
+            let input = PlayerInput { dir: bot.next_direction(position), sequence: bot.sequence, timestamp: game.tick_time_ms(now) };
+            game.handle_input(bot.addr, input);
+        }
+
+        let ping_due = now.saturating_duration_since(self.last_ping) >= self.tuning.server_ping_interval;
+        if ping_due {
+            self.last_ping = now;
+        }
+        let output = run_tick(&mut self.rooms, Vec::new(), self.match_duration, ping_due, now);
+        for line in &output.log {
+            println!("{}", line);
+        }
+        self.rooms.remove_empty_rooms(now);
+
+        let mut datagrams = Vec::new();
+        for (players, reset) in &output.resets {
+            self.encode(players, |kind| kind.codec().encode_message(reset), &mut datagrams);
+        }
+        for (addr, removal) in &output.removals {
+            self.encode(&[*addr], |kind| kind.codec().encode_message(removal), &mut datagrams);
+        }
+        for (players, snapshot) in &output.snapshots {
+            self.encode(players, |kind| kind.codec().encode_snapshot(snapshot), &mut datagrams);
+        }
+        for (addr, nonce) in &output.pings {
+            self.encode(&[*addr], |kind| kind.codec().encode_message(&ClientMessage::ServerPing(*nonce)), &mut datagrams);
+        }
+
+        // Forget the protocol of clients that left, once they were told why
+        let rooms = &self.rooms;
+        self.codecs.retain(|addr, _| rooms.room_of(addr).is_some());
+        datagrams
+    }
+
+    /// Encodes a datagram for each client in its protocol, once per protocol like the Transport, leaving out the bots
+    fn encode(&self, addrs: &[SocketAddr], encode: impl Fn(CodecKind) -> Vec<u8>, datagrams: &mut Vec<(SocketAddr, Vec<u8>)>) {
+        let mut payloads: HashMap<CodecKind, Vec<u8>> = HashMap::new();
+        for addr in addrs {
+            let Some(kind) = self.codecs.get(addr) else {
+                continue;
+            };
+            let payload = payloads.entry(*kind).or_insert_with(|| encode(*kind));
+            datagrams.push((*addr, payload.clone()));
+        }
+    }
+}
+
+/// A LocalServer running on a thread of its own, ticking at its tick rate. Clones talk to the same server,
+/// which stops once every handle and every link to it is dropped.
+#[derive(Clone)]
+pub struct LocalServerHandle {
+    inbound: Sender<(SocketAddr, Vec<u8>)>,
+    clients: Arc<Mutex<HashMap<SocketAddr, Sender<Vec<u8>>>>>, // Where the datagrams for each link go
+    next_port: Arc<AtomicU16>,
+}
+
+/// Implementation of the LocalServerHandle
+impl LocalServerHandle {
+    /// Starts the server on its own thread
+    pub fn start(server: LocalServer) -> std::io::Result<Self> {
+        let (inbound, inbound_receiver) = mpsc::channel();
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let thread_clients = Arc::clone(&clients);
+        std::thread::Builder::new()
+            .name("local server".to_string())
+            .spawn(move || run_local_server(server, inbound_receiver, thread_clients))?;
+        Ok(Self { inbound, clients, next_port: Arc::new(AtomicU16::new(1)) })
+    }
+
+    /// A new link to the server with an address of its own, like a freshly bound socket
+    pub fn connect(&self) -> LoopbackLink {
+        let addr = SocketAddr::from(([127, 0, 0, 1], self.next_port.fetch_add(1, Ordering::Relaxed)));
+        let (sender, outbound) = mpsc::channel();
+        self.clients.lock().unwrap().insert(addr, sender);
+        LoopbackLink { addr, inbound: self.inbound.clone(), outbound }
+    }
+}
+
+/// Receives and ticks until every handle and link is gone. Like the server's interval, a late tick
+/// doesn't make the following ones come faster.
+fn run_local_server(mut server: LocalServer, inbound: Receiver<(SocketAddr, Vec<u8>)>, clients: Arc<Mutex<HashMap<SocketAddr, Sender<Vec<u8>>>>>) {
+    let mut next_tick = Instant::now() + server.tick_interval();
+    loop {
+        match inbound.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+            Ok((addr, data)) => deliver(&clients, server.receive(addr, &data)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        let now = Instant::now();
+        if now >= next_tick {
+            deliver(&clients, server.tick(now));
+            next_tick = (next_tick + server.tick_interval()).max(now);
+        }
+    }
+}
+
+/// Hands the datagrams to their links, forgetting links that were dropped
+fn deliver(clients: &Mutex<HashMap<SocketAddr, Sender<Vec<u8>>>>, datagrams: Vec<(SocketAddr, Vec<u8>)>) {
+    let mut clients = clients.lock().unwrap();
+    for (addr, data) in datagrams {
+        if clients.get(&addr).is_some_and(|sender| sender.send(data).is_err()) {
+            clients.remove(&addr);
+        }
+    }
+}
+
+/// Link of a client to the in-process server, in place of its socket
+pub struct LoopbackLink {
+    addr: SocketAddr, // Address the server knows the client by
+    inbound: Sender<(SocketAddr, Vec<u8>)>,
+    outbound: Receiver<Vec<u8>>,
+}
+
+/// Implementation of the LoopbackLink
+impl LoopbackLink {
+    /// Address the server knows the client by
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+/// Link implementation for the LoopbackLink
+impl Link for LoopbackLink {
+    fn send(&mut self, data: &[u8]) {
+        let _ = self.inbound.send((self.addr, data.to_vec()));
+    }
+
+    fn recv(&mut self, wait: Duration) -> Option<Vec<u8>> {
+        self.outbound.recv_timeout(wait).ok()
+    }
+}
+
+/// Tests for the in-process server
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Incoming;
+use crate::constants::PLAYER_SPEED;
+    use crate::server::run_server;
+    use crate::transport::Transport;
+    use crate::types::{GameState, RoomInfo, Welcome};
+
+    use tokio::runtime::Runtime;
+    use uuid::Uuid;
+
+    /// What a client sees of a server: the messages apart from server pings, and the newest snapshot
+    #[derive(Debug, Default)]
+    struct Observed {
+        messages: Vec<ClientMessage>,
+        latest: Option<GameState>,
+    }
+
+    /// Implementation of the Observed
+    impl Observed {
+        // Helper function to take in a datagram from the server
+        fn record(&mut self, data: &[u8]) {
+            match CodecKind::sniff(data).codec().decode_incoming(data) {
+                Some(Incoming::Snapshot(snapshot)) => self.latest = Some(*snapshot),
+                Some(Incoming::Message(ClientMessage::ServerPing(_))) | None => {}
+                Some(Incoming::Message(message)) => self.messages.push(message),
+            }
+        }
+
+        // Helper function to get the welcome without the random id and session token
+        fn welcome(&self) -> Welcome {
+            let welcome = self.messages.iter()
+                .find_map(|message| match message {
+                    ClientMessage::Welcome(welcome) => Some(welcome.clone()),
+                    _ => None,
+                })
+                .expect("no welcome");
+            Welcome { id: Uuid::nil(), session_token: Uuid::nil(), ..welcome }
+        }
+    }
+
+    // Helper function to create the datagrams of a client that connects, moves right five times, pings and lists the rooms
+    fn script(kind: CodecKind) -> Vec<Vec<u8>> {
+        let connect = ClientMessage::ConnectWithProfile { name: "Kari".to_string(), shape: 1, instance: 7, room: String::new(), password: None };
+        let moves = (1..=5).map(|sequence| ClientMessage::Input(PlayerInput { dir: Direction::Right, sequence, timestamp: sequence as u64 * 16 }));
+        std::iter::once(connect)
+            .chain(moves)
+            .chain([ClientMessage::Ping(42), ClientMessage::ListRooms])
+            .map(|message| kind.codec().encode_message(&message))
+            .collect()
+    }
+
+    // Helper function to play the script against a real server on a loopback socket
+    fn observe_real_server(config: &ServerConfig, kind: CodecKind) -> Observed {
+        let runtime = Runtime::new().unwrap();
+        let socket = runtime.block_on(tokio::net::UdpSocket::bind("127.0.0.1:0")).unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let rooms = create_rooms(config, RoomConfig::default(), HashMap::new(), ServerTuning::default());
+        runtime.spawn(run_server(Transport::new(socket, None), rooms, config.clone(), ServerTuning::default(), std::future::pending()));
+
+        let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server_addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        for data in script(kind) {
+            client.send(&data).unwrap();
+        }
+        let mut observed = Observed::default();
+        let mut buf = [0u8; 4096];
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(300) {
+            if let Ok(size) = client.recv(&mut buf) {
+                observed.record(&buf[..size]);
+            }
+        }
+        observed
+    }
+
+    // Helper function to play the script against a local server, ticking it a few times after
+    fn observe_local_server(server: &mut LocalServer, addr: SocketAddr, kind: CodecKind) -> Observed {
+        let mut observed = Observed::default();
+        let mut datagrams: Vec<(SocketAddr, Vec<u8>)> = script(kind).iter().flat_map(|data| server.receive(addr, data)).collect();
+        for _ in 0..5 {
+            datagrams.extend(server.tick(Instant::now()));
+        }
+        for (to, data) in datagrams {
+            assert_eq!(to, addr);
+            observed.record(&data);
+        }
+        observed
+    }
+
+    #[test]
+    fn test_connect_input_and_snapshots_match_the_real_server() {
+        let config = ServerConfig { spawn_seed: Some(11), ..ServerConfig::default() };
+        for kind in [CodecKind::Bincode, CodecKind::Json] {
+            let real = observe_real_server(&config, kind);
+            let mut server = LocalServer::new(&config, 0);
+            let local = observe_local_server(&mut server, SocketAddr::from(([127, 0, 0, 1], 1)), kind);
+
+            // The same welcome at the same seeded spawn, then the pong and the room list
+            assert_eq!(local.welcome(), real.welcome());
+            assert_eq!(local.messages[1..], real.messages[1..]);
+            assert_eq!(local.messages[1..], [
+                ClientMessage::Pong(42),
+                ClientMessage::RoomList(vec![RoomInfo { name: "lobby".to_string(), players: 1, password_required: false }]),
+            ]);
+
+            // Every input was applied and acknowledged the same way
+            let spawn = local.welcome().position;
+            let (real, local) = (real.latest.unwrap(), local.latest.unwrap());
+            assert_eq!(local.players.len(), 1);
+            assert_eq!(local.players[0].1, real.players[0].1);
+            assert_eq!(local.players[0].1.x, spawn.x + 5 * PLAYER_SPEED);
+            assert_eq!(local.last_processed.values().collect::<Vec<_>>(), [&5]);
+            assert_eq!(real.last_processed.values().collect::<Vec<_>>(), [&5]);
+            assert_eq!((local.tick_interval_ms, local.match_phase), (real.tick_interval_ms, real.match_phase));
+        }
+    }
+
+    #[test]
+    fn test_bots_wander_within_the_board() {
+        let mut server = LocalServer::new(&ServerConfig { spawn_seed: Some(3), ..ServerConfig::default() }, 2);
+        let spawns = server.rooms().games().next().unwrap().1.build_snapshot().players;
+        assert_eq!(spawns.len(), 2);
+
+        // Nobody connected, so nothing is sent, not even to the bots
+        for _ in 0..600 {
+            assert!(server.tick(Instant::now()).is_empty());
+        }
+        let players = server.rooms().games().next().unwrap().1.build_snapshot().players;
+        assert_eq!(players.len(), 2);
+        for ((_, spawn, _), (_, position, _)) in spawns.iter().zip(&players) {
+            assert_ne!(spawn, position);
+            assert!((0..=BOARD_WIDTH).contains(&position.x) && (0..=BOARD_HEIGHT).contains(&position.y), "{:?}", position);
+        }
+    }
+}
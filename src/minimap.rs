@@ -0,0 +1,82 @@
+use crate::constants::{MINIMAP_MARGIN, MINIMAP_WIDTH};
+use crate::coords::{board_point, clamp_to_play_area, play_area_size, to_rect};
+use crate::positions::DisplayPos;
+use crate::spectator::View;
+
+/// Area on the screen as x, y, width and height, like View::visible_rect
+pub type ScreenRect = (f32, f32, f32, f32);
+
+/// Where the mini-map is drawn: the right corner of a screen of the given width, with its bottom a margin
+/// above the screen y given, such as the top of the toolbar. It is as wide as MINIMAP_WIDTH allows and
+/// has the board's aspect.
+pub fn minimap_rect(screen_width: f32, bottom: f32) -> ScreenRect {
+    let (board_width, board_height) = play_area_size();
+    let width = MINIMAP_WIDTH.min(screen_width - MINIMAP_MARGIN * 2.0).max(0.0);
+    let height = width * board_height / board_width;
+    (screen_width - width - MINIMAP_MARGIN, bottom - height - MINIMAP_MARGIN, width, height)
+}
+
+/// Point in the mini-map of a drawn position, positions off the board are kept on its edge
+pub fn to_minimap(position: DisplayPos, rect: ScreenRect) -> (f32, f32) {
+    to_rect(board_point(clamp_to_play_area(position.layout_position())), rect)
+}
+
+/// Outline in the mini-map of the board area a view shows on a screen of the given size, the part of it
+/// on the board. None when the view shows the whole board, so there is nothing to outline.
+pub fn viewport_outline(view: &View, screen: (f32, f32), rect: ScreenRect) -> Option<ScreenRect> {
+    let (board_width, board_height) = play_area_size();
+    let (left, top, width, height) = view.visible_rect(screen);
+    let (left, top) = (left.max(0.0), top.max(0.0));
+    let (right, bottom) = ((left + width).min(board_width), (top + height).min(board_height));
+    if left <= 0.0 && top <= 0.0 && right >= board_width && bottom >= board_height {
+        return None;
+    }
+
+    let (x, y) = to_rect((left, top), rect);
+    let (x_end, y_end) = to_rect((right.max(left), bottom.max(top)), rect);
+    Some((x, y, x_end - x, y_end - y))
+}
+
+/// Tests for the mini-map layout
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH, TOOL_BAR_HEIGHT};
+    use crate::types::Position;
+
+    #[test]
+    fn test_minimap_sits_in_the_corner_with_the_board_aspect() {
+        let (x, y, width, height) = minimap_rect(1000.0, 600.0);
+        assert_eq!(width, MINIMAP_WIDTH);
+        assert_eq!(height, MINIMAP_WIDTH * (BOARD_HEIGHT - TOOL_BAR_HEIGHT) as f32 / BOARD_WIDTH as f32);
+        assert_eq!((x + width + MINIMAP_MARGIN, y + height + MINIMAP_MARGIN), (1000.0, 600.0));
+
+        // A narrow window shrinks it instead of pushing it off the screen
+        let (x, _, width, _) = minimap_rect(100.0, 600.0);
+        assert_eq!((x, width), (MINIMAP_MARGIN, 100.0 - MINIMAP_MARGIN * 2.0));
+    }
+
+    #[test]
+    fn test_board_positions_are_scaled_into_the_minimap() {
+        let rect = (100.0, 50.0, BOARD_WIDTH as f32 / 4.0, (BOARD_HEIGHT - TOOL_BAR_HEIGHT) as f32 / 4.0);
+        assert_eq!(to_minimap(DisplayPos::smoothed(Position { x: 0, y: 0 }), rect), (100.0, 50.0));
+        assert_eq!(to_minimap(DisplayPos::smoothed(Position { x: 400, y: 280 }), rect), (200.0, 120.0));
+        assert_eq!(to_minimap(DisplayPos::smoothed(Position { x: -30, y: BOARD_HEIGHT }), rect), (100.0, 50.0 + rect.3));
+        assert_eq!(to_minimap(DisplayPos::smoothed(Position { x: i32::MAX, y: i32::MIN }), rect), (100.0 + rect.2, 50.0));
+    }
+
+    #[test]
+    fn test_viewport_is_outlined_only_when_zoomed_or_panned() {
+        let screen = (BOARD_WIDTH as f32, BOARD_HEIGHT as f32);
+        let rect = (0.0, 0.0, BOARD_WIDTH as f32 / 4.0, (BOARD_HEIGHT - TOOL_BAR_HEIGHT) as f32 / 4.0);
+        assert_eq!(viewport_outline(&View::whole_board(), screen, rect), None);
+
+        // At twice the zoom a quarter of the screen is seen, clipped to the board above the toolbar
+        let (half_width, half_height) = (BOARD_WIDTH as f32 / 2.0, BOARD_HEIGHT as f32 / 2.0);
+        let view = View::centered_on(Position { x: 0, y: 0 }, 2.0, screen);
+        assert_eq!(viewport_outline(&view, screen, rect), Some((0.0, 0.0, half_width / 4.0, half_height / 4.0)));
+        let view = View::centered_on(Position { x: BOARD_WIDTH, y: BOARD_HEIGHT }, 2.0, screen);
+        let clipped_height = (BOARD_HEIGHT - TOOL_BAR_HEIGHT) as f32 - half_height;
+        assert_eq!(viewport_outline(&view, screen, rect), Some((half_width / 4.0, half_height / 4.0, half_width / 4.0, clipped_height / 4.0)));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::client_events::ClientEvent;
+use crate::constants::MAX_NAME_CHARS;
+use crate::game_client::{ClientProfile, GameClient};
+use crate::input_dispatch::InputIntent;
+use crate::positions::DisplayPos;
+use crate::prediction::PredictionConfig;
+use crate::types::PlayerInput;
+
+use uuid::Uuid;
+
+/// One of the two connections of the mirroring mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorSide {
+    A, // The primary client, the one the keyboard drives anyway
+    B, // The mirror next to it
+}
+
+/// Implementation of the MirrorSide
+impl MirrorSide {
+    pub const ALL: [MirrorSide; 2] = [MirrorSide::A, MirrorSide::B];
+
+    /// Letter of the side in labels and names
+    pub fn name(self) -> &'static str {
+        match self {
+            MirrorSide::A => "A",
+            MirrorSide::B => "B",
+        }
+    }
+}
+
+/// The second connection of the mirroring mode, to compare two prediction configs under the same input.
+/// Every intent of the primary client goes to the mirror too, and each client gives them sequences of its
+/// own and predicts them with its own state and config. The primary client stays where it was, the mirror
+/// only adds what is needed next to it.
+pub struct InputMirror {
+    pub client: GameClient,         // Second connection to the same server, predicting with config B
+    configs: [PredictionConfig; 2], // Of side A and side B
+}
+
+/// Implementation of the InputMirror
+impl InputMirror {
+    /// Pairs the mirror client with the primary one, injecting config A into the primary and B into the mirror
+    pub fn new(primary: &mut GameClient, mut client: GameClient, configs: [PredictionConfig; 2]) -> Self {
+        primary.set_prediction_config(configs[0]);
+        client.set_prediction_config(configs[1]);
+        Self { client, configs }
+    }
+
+    /// Profile the mirror connects with: the primary's, with the side after the name so the players are told apart.
+    /// A long name is cut to make room for the side, the server refuses names over MAX_NAME_CHARS.
+    pub fn profile(primary: &ClientProfile) -> ClientProfile {
+        let side = MirrorSide::B.name();
+        let kept: String = primary.name.trim().chars().take(MAX_NAME_CHARS - side.chars().count() - 1).collect();
+        let name = if kept.is_empty() { side.to_string() } else { format!("{} {}", kept, side) };
+        ClientProfile { name, ..primary.clone() }
+    }
+
+    /// Config of a side
+    pub fn config(&self, side: MirrorSide) -> PredictionConfig {
+        self.configs[side as usize]
+    }
+
+    /// Label of the square of a side: the side and where its config differs from the defaults
+    pub fn label(&self, side: MirrorSide) -> String {
+        format!("{}: {}", side.name(), self.config(side).describe())
+    }
+
+    /// Dispatches the same intents to both clients in the same order. Returns the inputs of side A and side B,
+    /// which move the same ways at the same times with the sequences of each client.
+    pub fn dispatch(&mut self, primary: &mut GameClient, intents: impl IntoIterator<Item = InputIntent>) -> [Vec<PlayerInput>; 2] {
+        let intents: Vec<InputIntent> = intents.into_iter().collect();
+        let mirrored = self.client.dispatch(intents.iter().copied());
+        [primary.dispatch(intents), mirrored]
+    }
+
+    /// Runs the mirror client at the same times as the primary, returning its events
+    pub fn update(&mut self, current_time: f64, render_time: f64) -> Vec<ClientEvent> {
+        self.client.update(current_time, render_time);
+        self.client.events()
+    }
+
+    /// Every player as the primary client draws it, with the mirror's square where the mirror draws it instead
+    /// of where the primary plays it back from the snapshots, so both squares the keyboard moves are shown
+    /// the way their own config shows them
+    pub fn mirrored_positions(&self, positions: Vec<(Uuid, DisplayPos, u32)>) -> Vec<(Uuid, DisplayPos, u32)> {
+        let Some(mirror_id) = self.client.id() else {
+            return positions;
+        };
+        let drawn = self.client.local_display_position();
+        positions.into_iter()
+            .map(|(id, position, color)| if id == mirror_id { (id, drawn, color) } else { (id, position, color) })
+            .collect()
+    }
+
+    /// Id and label of the square of each side the server welcomed
+    pub fn labels(&self, primary: &GameClient) -> Vec<(Uuid, String)> {
+        [(MirrorSide::A, primary.id()), (MirrorSide::B, self.client.id())].into_iter()
+            .filter_map(|(side, id)| Some((id?, self.label(side))))
+            .collect()
+    }
+}
+
+/// Tests for the InputMirror
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpolation::InterpolationStrategy;
+    use crate::network::NetworkClient;
+    use crate::positions::AuthoritativePos;
+    use crate::types::{Direction, Position};
+
+    // Helper function to create a client that sends to a port nothing listens on
+    fn client() -> GameClient {
+        GameClient::new(NetworkClient::new("127.0.0.1:9"))
+    }
+
+    // Helper function to create an intent
+    fn intent(dir: Direction, originated_at: f64) -> InputIntent {
+        InputIntent { dir, originated_at }
+    }
+
+    #[test]
+    fn test_both_pipelines_get_the_same_intents_with_their_own_sequences() {
+        let mut primary = client();
+        let mut mirror = InputMirror::new(&mut primary, client(), [PredictionConfig::default(); 2]);
+
+        // The mirror already sent inputs of its own, so its sequences are ahead
+        mirror.client.dispatch([intent(Direction::Up, 0.5), intent(Direction::Up, 0.5)]);
+        let start = [primary.local_position().position(), mirror.client.local_position().position()];
+
+        let intents = [intent(Direction::Right, 1.1), intent(Direction::Down, 1.0), intent(Direction::Right, 1.1)];
+        let [a, b] = mirror.dispatch(&mut primary, intents);
+        let moves = |inputs: &[PlayerInput]| inputs.iter().map(|input| (input.dir, input.timestamp)).collect::<Vec<_>>();
+        assert_eq!(moves(&a), vec![(Direction::Down, 1000), (Direction::Right, 1100), (Direction::Right, 1100)]);
+        assert_eq!(moves(&a), moves(&b));
+        assert_eq!(a.iter().map(|input| input.sequence).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(b.iter().map(|input| input.sequence).collect::<Vec<_>>(), [2, 3, 4]);
+
+        // Each predicted the same moves from where it was, with its own pending inputs
+        let moved = |from: Position, to: Position| (to.x - from.x, to.y - from.y);
+        assert_eq!(moved(start[0], primary.local_position().position()), moved(start[1], mirror.client.local_position().position()));
+        assert_eq!((primary.world.prediction.pending_inputs.len(), mirror.client.world.prediction.pending_inputs.len()), (3, 5));
+
+        // The sequences go on independently
+        let [a, b] = mirror.dispatch(&mut primary, [intent(Direction::Left, 1.2)]);
+        assert_eq!((a[0].sequence, b[0].sequence), (3, 5));
+        assert_eq!((primary.world.prediction.next_sequence, mirror.client.world.prediction.next_sequence), (4, 6));
+    }
+
+    #[test]
+    fn test_each_side_keeps_its_config() {
+        let b = PredictionConfig::parse("predict=off,interpolation=hermite").unwrap();
+        let mut primary = client();
+        let mut mirror = InputMirror::new(&mut primary, client(), [PredictionConfig::default(), b]);
+        assert_eq!((primary.world.config, mirror.client.world.config), (PredictionConfig::default(), b));
+        assert!(primary.world.is_predicting() && !mirror.client.world.is_predicting());
+        assert_eq!(mirror.client.world.interpolated_positions.strategy(), InterpolationStrategy::Hermite);
+        assert_eq!((mirror.label(MirrorSide::A), mirror.label(MirrorSide::B)), ("A: default".to_string(), "B: no prediction, hermite".to_string()));
+
+        // A reset or the prediction key don't turn the prediction of the mirror back on
+        mirror.client.reset();
+        mirror.client.world.enable_prediction();
+        assert!(!mirror.client.world.is_predicting());
+        assert_eq!(mirror.client.world.interpolated_positions.strategy(), InterpolationStrategy::Hermite);
+
+        let profile = ClientProfile { name: "Ada".to_string(), room: "lab".to_string(), ..ClientProfile::default() };
+        assert_eq!(InputMirror::profile(&profile), ClientProfile { name: "Ada B".to_string(), ..profile.clone() });
+        let long = ClientProfile { name: "Bartholomew Smith".to_string(), ..profile };
+        assert_eq!(InputMirror::profile(&long).name, "Bartholomew Sm B");
+    }
+
+    #[test]
+    fn test_the_mirror_square_is_drawn_where_the_mirror_has_it() {
+        let mut primary = client();
+        let mut mirror = InputMirror::new(&mut primary, client(), [PredictionConfig::default(); 2]);
+        let (a, b, other) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let at = |x| DisplayPos::from_authoritative(AuthoritativePos::new(Position { x, y: 100 }));
+        let positions = vec![(a, at(10), 1), (b, at(20), 2), (other, at(30), 3)];
+
+        // Before the mirror is welcomed there is nothing to replace
+        assert_eq!(mirror.mirrored_positions(positions.clone()), positions);
+        assert!(mirror.labels(&primary).is_empty());
+
+        primary.world.my_id = Some(a);
+        mirror.client.world.my_id = Some(b);
+        mirror.client.world.my_pos = AuthoritativePos::new(Position { x: 25, y: 100 });
+        assert_eq!(mirror.mirrored_positions(positions), vec![(a, at(10), 1), (b, at(25), 2), (other, at(30), 3)]);
+        assert_eq!(mirror.labels(&primary), vec![(a, "A: default".to_string()), (b, "B: default".to_string())]);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH, PLAYER_SIZE, PLAYER_SPEED, TOOL_BAR_HEIGHT};
+use crate::types::{Direction, Position};
+
+use serde::{Deserialize, Serialize};
+
+/// Movement along each axis that hasn't added up to a whole pixel yet. Positions stay whole pixels, so a
+/// speed such as 2.5 px per input moves 2 and 3 px in turn. The server keeps one for every player and
+/// the prediction one for the local player, both stepped by the same function.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MovementAccumulator {
+    pub x: f32, // Fraction of a pixel moved along x, negative when moving left
+    pub y: f32, // Fraction of a pixel moved along y, negative when moving up
+}
+
+/// Implementation of the MovementAccumulator
+impl MovementAccumulator {
+    /// Forgets the fractions, such as after a teleport
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Values the server and the prediction have to agree on to move a player the same way. A client with
+/// different values would carry a prediction error that no reconciliation removes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MovementTunables {
+    pub board_width: i32,
+    pub board_height: i32,   // Including the toolbar
+    pub toolbar_height: i32, // Strip at the bottom of the board players can't enter
+    pub player_size: i32,    // Distance players keep from the edges
+    pub player_speed: f32,   // Pixels per input of a player that wasn't given its own speed
+}
+
+/// Default implementation for the MovementTunables, the values this build was compiled with
+impl Default for MovementTunables {
+    fn default() -> Self {
+        Self {
+            board_width: BOARD_WIDTH,
+            board_height: BOARD_HEIGHT,
+            toolbar_height: TOOL_BAR_HEIGHT,
+            player_size: PLAYER_SIZE,
+            player_speed: PLAYER_SPEED as f32,
+        }
+    }
+}
+
+/// What a client does about the movement tunables the server sent in its Welcome
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TunablesCheck {
+    Matching,                            // The digests are the same, the prediction is exact
+    Adopt(MovementTunables),             // The values differ but the server sent all of them, the client moves by them
+    Incompatible { server_digest: u64 }, // The values differ and the client can't learn them, playing would desync
+}
+
+/// Implementation of the MovementTunables
+impl MovementTunables {
+    /// FNV-1a hash of the values in a fixed order, each as fixed width little endian bytes, so the same
+    /// values give the same digest on every platform and release
+    pub fn digest(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let values = [
+            self.board_width as u32,
+            self.board_height as u32,
+            self.toolbar_height as u32,
+            self.player_size as u32,
+            self.player_speed.to_bits(),
+        ];
+        for byte in values.iter().flat_map(|value| value.to_le_bytes()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        hash
+    }
+
+    /// Compares the digest from the server with these values. The server's values are only adopted when
+    /// they were sent and hash to its digest, otherwise the digest covers something this client doesn't know.
+    pub fn check(&self, server_digest: u64, server_values: Option<MovementTunables>) -> TunablesCheck {
+        if server_digest == self.digest() {
+            return TunablesCheck::Matching;
+        }
+        match server_values.filter(|values| values.digest() == server_digest) {
+            Some(values) => TunablesCheck::Adopt(values),
+            None => TunablesCheck::Incompatible { server_digest },
+        }
+    }
+
+    /// Width and height of the play area, the board above the toolbar
+    pub fn play_area(&self) -> (i32, i32) {
+        (self.board_width, self.board_height - self.toolbar_height)
+    }
+
+    /// Moves a position one input in the direction at a speed in pixels per input, keeping it on the board.
+    /// Whole pixels are moved as soon as the accumulated movement reaches them, the rest is carried to the
+    /// next input. Movement that ran into the edge of the board is discarded instead of banked.
+    pub fn step(&self, position: Position, dir: Direction, speed: f32, accumulator: &mut MovementAccumulator) -> Position {
+        let (min_x, max_x) = (self.player_size, self.board_width - self.player_size);
+        let (min_y, max_y) = (self.player_size, self.board_height - self.player_size - self.toolbar_height);
+        match dir {
+            Direction::Up => Position { y: advance(position.y, -speed, &mut accumulator.y, min_y, max_y), ..position },
+            Direction::Down => Position { y: advance(position.y, speed, &mut accumulator.y, min_y, max_y), ..position },
+            Direction::Left => Position { x: advance(position.x, -speed, &mut accumulator.x, min_x, max_x), ..position },
+            Direction::Right => Position { x: advance(position.x, speed, &mut accumulator.x, min_x, max_x), ..position },
+        }
+    }
+
+    /// Moves a position the given number of inputs in one direction at once, ending where as many calls of step
+    /// would. None when that can't be worked out exactly: the speed or the carried fraction aren't whole
+    /// 1/256ths, where rounding in each step adds up differently, or the position is off the board.
+    pub fn step_repeated(&self, position: Position, dir: Direction, speed: f32, count: u32, accumulator: &mut MovementAccumulator) -> Option<Position> {
+        let (min_x, max_x) = (self.player_size, self.board_width - self.player_size);
+        let (min_y, max_y) = (self.player_size, self.board_height - self.player_size - self.toolbar_height);
+        match dir {
+            Direction::Up => Some(Position { y: advance_repeated(position.y, -speed, count, &mut accumulator.y, min_y, max_y)?, ..position }),
+            Direction::Down => Some(Position { y: advance_repeated(position.y, speed, count, &mut accumulator.y, min_y, max_y)?, ..position }),
+            Direction::Left => Some(Position { x: advance_repeated(position.x, -speed, count, &mut accumulator.x, min_x, max_x)?, ..position }),
+            Direction::Right => Some(Position { x: advance_repeated(position.x, speed, count, &mut accumulator.x, min_x, max_x)?, ..position }),
+        }
+    }
+}
+
+/// Moves a position one input on the board of this build, see MovementTunables::step
+pub fn step(position: Position, dir: Direction, speed: f32, accumulator: &mut MovementAccumulator) -> Position {
+    MovementTunables::default().step(position, dir, speed, accumulator)
+}
+
+/// Moves a coordinate by delta plus the carried fraction, within min and max
+fn advance(coordinate: i32, delta: f32, carried: &mut f32, min: i32, max: i32) -> i32 {
+    let total = *carried + delta;
+    let whole = total.trunc();
+    let moved = coordinate.saturating_add(whole as i32).clamp(min, max);
+
+    // Pressing into a wall doesn't bank movement for when the player turns around
+    let blocked = (delta > 0.0 && moved == max) || (delta < 0.0 && moved == min);
+    *carried = if blocked { 0.0 } else { total - whole };
+    moved
+}
+
+/// True if every sum advance makes of the value stays exact in an f32: whole 1/256ths well below 2^15
+fn exact_in_steps(value: f32) -> bool {
+    value.abs() < 32_768.0 && (value * 256.0).fract() == 0.0
+}
+
+/// Moves a coordinate like count calls of advance. The carried total only ever grows in the direction of
+/// delta, so the whole pixels of the steps add up to the whole pixels of the total, and an edge reached on
+/// the way is where the coordinate stays with nothing carried.
+fn advance_repeated(coordinate: i32, delta: f32, count: u32, carried: &mut f32, min: i32, max: i32) -> Option<i32> {
+    if !exact_in_steps(delta) || !exact_in_steps(*carried) || !(min..=max).contains(&coordinate) {
+        return None;
+    }
+    if count == 0 {
+        return Some(coordinate);
+    }
+    let total = *carried as f64 + delta as f64 * count as f64;
+    let whole = total.trunc();
+    let moved = (coordinate as i64 + whole as i64).clamp(min as i64, max as i64) as i32;
+
+    let blocked = (delta > 0.0 && moved == max) || (delta < 0.0 && moved == min);
+    *carried = if blocked { 0.0 } else { (total - whole) as f32 };
+    Some(moved)
+}
+
+/// Tests for the shared movement
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fractional_speed_moves_whole_pixels_in_turn() {
+        let mut accumulator = MovementAccumulator::default();
+        let mut position = Position { x: 100, y: 100 };
+        let mut steps = Vec::new();
+        for _ in 0..4 {
+            let next = step(position, Direction::Right, 2.5, &mut accumulator);
+            steps.push(next.x - position.x);
+            position = next;
+        }
+        assert_eq!(steps, vec![2, 3, 2, 3]);
+        assert_eq!(position.x, 110);
+
+        // Going back undoes the same steps, the other axis keeps its own fraction
+        step(position, Direction::Down, 0.5, &mut accumulator);
+        for _ in 0..4 {
+            position = step(position, Direction::Left, 2.5, &mut accumulator);
+        }
+        assert_eq!(position, Position { x: 100, y: 100 });
+        assert_eq!(accumulator, MovementAccumulator { x: 0.0, y: 0.5 });
+    }
+
+    #[test]
+    fn test_whole_speed_matches_plain_steps() {
+        let mut accumulator = MovementAccumulator::default();
+        let position = step(Position { x: 100, y: 100 }, Direction::Up, 5.0, &mut accumulator);
+        assert_eq!(position, Position { x: 100, y: 95 });
+        assert_eq!(accumulator, MovementAccumulator::default());
+    }
+
+    #[test]
+    fn test_pressing_into_a_wall_banks_nothing() {
+        let mut accumulator = MovementAccumulator::default();
+        let wall = BOARD_WIDTH - PLAYER_SIZE;
+        let mut position = Position { x: wall - 1, y: 100 };
+        for _ in 0..10 {
+            position = step(position, Direction::Right, 0.75, &mut accumulator);
+        }
+        assert_eq!(position.x, wall);
+        assert_eq!(accumulator.x, 0.0);
+
+        // Turning around moves at the plain speed, with nothing saved up from the wall
+        position = step(position, Direction::Left, 0.75, &mut accumulator);
+        assert_eq!(position.x, wall);
+        position = step(position, Direction::Left, 0.75, &mut accumulator);
+        assert_eq!(position.x, wall - 1);
+    }
+
+    #[test]
+    fn test_digest_changes_with_every_value() {
+        let tunables = MovementTunables { board_width: 800, board_height: 600, toolbar_height: 40, player_size: 20, player_speed: 2.5 };
+        // Pinned, so a change to the encoding before hashing shows up as a failing test
+        assert_eq!(tunables.digest(), 0xaac2_0380_cc26_735e);
+
+        let changed = [
+            MovementTunables { board_width: 801, ..tunables },
+            MovementTunables { board_height: 601, ..tunables },
+            MovementTunables { toolbar_height: 41, ..tunables },
+            MovementTunables { player_size: 21, ..tunables },
+            MovementTunables { player_speed: 2.75, ..tunables },
+            MovementTunables { board_width: 600, board_height: 800, ..tunables },
+        ];
+        for other in changed {
+            assert_ne!(other.digest(), tunables.digest(), "{:?}", other);
+        }
+    }
+
+    #[test]
+    fn test_server_tunables_are_matched_adopted_or_refused() {
+        let own = MovementTunables::default();
+        let server = MovementTunables { player_speed: 3.0, ..own };
+        assert_eq!(own.check(own.digest(), Some(own)), TunablesCheck::Matching);
+        assert_eq!(own.check(own.digest(), None), TunablesCheck::Matching);
+        assert_eq!(own.check(server.digest(), Some(server)), TunablesCheck::Adopt(server));
+
+        // Without the values, or with values that don't hash to the digest, there is nothing to adopt
+        assert_eq!(own.check(server.digest(), None), TunablesCheck::Incompatible { server_digest: server.digest() });
+        assert_eq!(own.check(server.digest(), Some(own)), TunablesCheck::Incompatible { server_digest: server.digest() });
+
+        // Adopted values move the player by the server's rules
+        let mut accumulator = MovementAccumulator::default();
+        let moved = server.step(Position { x: 100, y: 100 }, Direction::Right, server.player_speed, &mut accumulator);
+        assert_eq!(moved.x, 103);
+        let small = MovementTunables { board_width: 200, ..own };
+        assert_eq!(small.step(Position { x: 178, y: 100 }, Direction::Right, 5.0, &mut accumulator).x, 180);
+    }
+
+    #[test]
+    fn test_repeated_steps_match_single_steps() {
+        let tunables = MovementTunables { board_width: 300, ..MovementTunables::default() };
+        let (min_x, max_x) = (tunables.player_size, tunables.board_width - tunables.player_size);
+        let starts = [min_x, 100, max_x - 7, max_x];
+        let carries = [0.0, 0.5, -0.75, 0.25];
+        for speed in [5.0, 2.5, 0.75, 7.125] {
+            for dir in [Direction::Left, Direction::Right, Direction::Up] {
+                for (x, carried) in starts.into_iter().flat_map(|x| carries.map(|carried| (x, carried))) {
+                    for count in [0, 1, 2, 7, 40, 200] {
+                        let start = Position { x, y: 150 };
+                        let mut naive_accumulator = MovementAccumulator { x: carried, y: carried };
+                        let mut naive = start;
+                        for _ in 0..count {
+                            naive = tunables.step(naive, dir, speed, &mut naive_accumulator);
+                        }
+                        let mut accumulator = MovementAccumulator { x: carried, y: carried };
+                        let repeated = tunables.step_repeated(start, dir, speed, count, &mut accumulator);
+                        let case = (speed, dir, x, carried, count);
+                        assert_eq!(repeated, Some(naive), "{:?}", case);
+                        assert_eq!(accumulator, naive_accumulator, "{:?}", case);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_repeated_steps_refuse_what_they_cant_do_exactly() {
+        // 2.3 isn't a whole number of 1/256ths, the rounding of each step can't be added up at once
+        let mut accumulator = MovementAccumulator::default();
+        let position = Position { x: 100, y: 100 };
+        assert_eq!(MovementTunables::default().step_repeated(position, Direction::Right, 2.3, 10, &mut accumulator), None);
+        let mut carried = MovementAccumulator { x: 0.1, y: 0.0 };
+        assert_eq!(MovementTunables::default().step_repeated(position, Direction::Right, 2.5, 10, &mut carried), None);
+
+        // Nor can a position off the board, which the first step would pull back onto it
+        let off_board = Position { x: -40, y: 100 };
+        assert_eq!(MovementTunables::default().step_repeated(off_board, Direction::Right, 5.0, 3, &mut accumulator), None);
+        assert_eq!(accumulator, MovementAccumulator::default());
+    }
+}
@@ -1,130 +1,818 @@
-use bincode;
+use crate::codec::{CodecKind, Incoming};
+use crate::types::{ClientMessage, PlayerInput, PlayerShape, Position, GameState};
+use crate::constants::{CLIENT_RECV_BUFFER_SIZE, DELAY_MS, DUPLICATE_PERCENT, INCOMING_MESSAGE_QUEUE, INCOMING_SNAPSHOT_QUEUE, INPUT_SEND_DELAY_HISTORY, JITTER_MS, MAX_BURST_DELAY, MAX_DATAGRAM_SIZE, MAX_INPUT_BATCH, MAX_REORDER_MS, NETWORK_THREAD_POLL, PACKET_LOSS, SEND_PACING};
+use crate::jitter::{JitterDistribution, JitterModel};
+use crate::local_server::LocalServerHandle;
+use crate::send_rate::{next_send_rate, SendRate};
+use crate::traffic::{TrafficCategory, TrafficDirection, TrafficMeter};
+use crate::util::{DelayQueue, RingHistory};
 
-use crate::types::{ClientMessage, PlayerInput, GameState};
-use crate::constants::{DELAY_MS, PACKET_LOSS};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-use rand::Rng;
-use rand::seq::SliceRandom;
+use socket2::{Domain, Protocol, Socket, Type};
 
 use std::collections::VecDeque;
-use std::net::UdpSocket;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
+use uuid::Uuid;
 
-/// Network client that handles sending and receiving messages with simulated network conditions
+/// Statistics about the client socket and what it received
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NetworkStats {
+    pub recv_buffer_size: Option<usize>, // Effective SO_RCVBUF, the OS may round or cap the requested size
+    pub bursts: u32,                     // Frames where too many snapshots arrived at once
+    pub last_burst_size: Option<usize>,
+    pub skipped_snapshots: u64,
+    pub dropped_snapshots: u64, // Snapshots the network thread dropped because the render thread fell behind
+    pub dropped_messages: u64,  // Control messages the network thread dropped because the render thread fell behind
+    pub snapshot_anomalies: u64,  // Players in snapshots placed further off the board than the margin, their entries were dropped
+    pub discarded_snapshots: u64, // Snapshots dropped whole because they couldn't be made sense of, such as ones of another room
+    pub duplicated_sent: u64,     // Extra copies of sent packets made by the simulated duplication
+    pub duplicated_received: u64, // Extra copies of received datagrams made by the simulated duplication
+    pub max_send_burst: usize,    // Most delayed packets the network thread sent back to back in one pass
+    pub traffic: TrafficMeter,    // Bytes and datagrams per message category in both directions
+}
+
+/// Everything received from the server during one frame
+#[derive(Debug, Default)]
+pub struct ReceivedFrame {
+    pub snapshots: Vec<GameState>, // Sorted by snapshot id
+    pub messages: Vec<ClientMessage>,
+    pub burst: Option<SnapshotBurst>,
+}
+
+/// A frame where too many snapshots arrived at once and the older ones were skipped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotBurst {
+    pub size: usize,
+    pub skipped: usize,
+}
+
+/// Decides which of the snapshots received in one frame to apply. Normally all of them are
+/// applied in tick order, but after a hitch the stale ones are skipped and only the newest is kept.
+pub fn handle_snapshot_burst(mut snapshots: Vec<GameState>, threshold: usize) -> (Vec<GameState>, Option<SnapshotBurst>) {
+    snapshots.sort_by_key(|snapshot| snapshot.snapshot_id);
+    if snapshots.len() <= threshold {
+        return (snapshots, None);
+    }
+
+    let size = snapshots.len();
+    let newest = snapshots.split_off(size - 1);
+    (newest, Some(SnapshotBurst { size, skipped: size - 1 }))
+}
+
+/// Extra interpolation delay in seconds that covers the hole left by skipped snapshots
+pub fn burst_delay(burst: &SnapshotBurst, tick_interval_ms: u32) -> f64 {
+    (burst.skipped as f64 * tick_interval_ms as f64 / 1000.0).min(MAX_BURST_DELAY)
+}
+
+/// Times to send matured packets at, in the order they matured. The first goes out now, or spacing after the
+/// packet sent or scheduled last, and every further one spacing after the one before it, so packets that
+/// matured together leave one at a time in the order the jitter drew. A spacing of zero sends them all now.
+pub fn pace_releases(matured: usize, now: Instant, last_scheduled: Option<Instant>, spacing: Duration) -> Vec<Instant> {
+    let first = last_scheduled.map_or(now, |last| (last + spacing).max(now));
+    (0..matured as u32).map(|index| first + spacing * index).collect()
+}
+
+/// Where the network thread sends its datagrams and receives the server's from. The simulated network
+/// conditions are applied around it, so every link sees the same delay, jitter, loss and duplication.
+pub trait Link: Send {
+    /// Sends a datagram to the server
+    fn send(&mut self, data: &[u8]);
+
+    /// Waits up to wait for a datagram from the server
+    fn recv(&mut self, wait: Duration) -> Option<Vec<u8>>;
+}
+
+/// Link to a server over a UDP socket
+struct UdpLink {
+    socket: UdpSocket,
+    server_addr: String,
+    buf: Vec<u8>, // Receive buffer sized for the largest datagram, a smaller one would cut off the snapshots of full rooms
+}
+
+/// Link implementation for the UdpLink
+impl Link for UdpLink {
+    fn send(&mut self, data: &[u8]) {
+        let _ = self.socket.send_to(data, &self.server_addr);
+    }
+
+    fn recv(&mut self, wait: Duration) -> Option<Vec<u8>> {
+        self.socket.set_read_timeout(Some(wait)).ok()?;
+        let (size, _) = self.socket.recv_from(&mut self.buf).ok()?;
+        Some(self.buf[..size].to_vec())
+    }
+}
+
+/// Builder for the NetworkClient, used to configure the socket before it is created
+pub struct NetworkClientBuilder {
+    server_addr: String,
+    recv_buffer_size: Option<usize>,
+    jitter_distribution: JitterDistribution,
+    max_reorder_ms: u64,
+    jitter_seed: Option<u64>,
+    send_pacing: Duration,
+    codec: CodecKind,
+    local_server: Option<LocalServerHandle>, // Talk to this in-process server instead of binding a socket
+}
+
+/// Implementation of the NetworkClientBuilder
+impl NetworkClientBuilder {
+    /// Sets the requested size of the socket receive buffer, None keeps the OS default
+    pub fn recv_buffer_size(mut self, size: Option<usize>) -> Self {
+        self.recv_buffer_size = size;
+        self
+    }
+
+    /// Sets the distribution the simulated jitter is drawn from
+    pub fn jitter_distribution(mut self, distribution: JitterDistribution) -> Self {
+        self.jitter_distribution = distribution;
+        self
+    }
+
+    /// Sets how far a delayed packet may arrive ahead of one sent before it, 0 keeps packets in order
+    pub fn max_reorder_ms(mut self, max_reorder_ms: u64) -> Self {
+        self.max_reorder_ms = max_reorder_ms;
+        self
+    }
+
+    /// Seeds the jitter and the duplication so runs can be repeated, None picks a random seed
+    pub fn jitter_seed(mut self, seed: Option<u64>) -> Self {
+        self.jitter_seed = seed;
+        self
+    }
+
+    /// Sets the least time between delayed packets sent to the server, zero sends matured packets at once
+    pub fn send_pacing(mut self, spacing: Duration) -> Self {
+        self.send_pacing = spacing;
+        self
+    }
+
+    /// Sets the protocol spoken with the server
+    pub fn codec(mut self, codec: CodecKind) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Talks to an in-process server over a loopback link instead of a socket, for the offline mode.
+    /// The server address is then only a label.
+    pub fn local_server(mut self, server: LocalServerHandle) -> Self {
+        self.local_server = Some(server);
+        self
+    }
+
+    /// Creates the socket or the loopback link, starts the network thread and creates the NetworkClient
+    pub fn build(self) -> std::io::Result<NetworkClient> {
+        let mut stats = NetworkStats::default();
+        let (link, local_addr): (Box<dyn Link>, SocketAddr) = match &self.local_server {
+            Some(server) => {
+                let link = server.connect();
+                let local_addr = link.addr();
+                (Box::new(link), local_addr)
+            }
+            None => {
+                let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+                if let Some(size) = self.recv_buffer_size {
+                    socket.set_recv_buffer_size(size)?;
+                }
+                socket.bind(&SocketAddr::from(([0, 0, 0, 0], 0)).into())?;
+                stats.recv_buffer_size = socket.recv_buffer_size().ok();
+                let socket: UdpSocket = socket.into();
+                let local_addr = socket.local_addr()?;
+                (Box::new(UdpLink { socket, server_addr: self.server_addr.clone(), buf: vec![0; MAX_DATAGRAM_SIZE] }), local_addr)
+            }
+        };
+
+        // Each direction gets its own generators, so the send and receive jitter and duplication are independent
+        let seed = self.jitter_seed.unwrap_or_else(rand::random);
+        let shared = Arc::new(SharedState {
+            running: AtomicBool::new(true),
+            delay_ms: AtomicI32::new(DELAY_MS),
+            jitter_ms: AtomicI32::new(JITTER_MS),
+            packet_loss: AtomicI32::new(PACKET_LOSS),
+            duplicate_percent: AtomicI32::new(DUPLICATE_PERCENT),
+            duplicated_received: AtomicU64::new(0),
+            max_send_burst: AtomicUsize::new(0),
+            inbox: Mutex::new(Inbox::default()),
+            traffic: Mutex::new(TrafficMeter::default()),
+        });
+        let (outgoing, outgoing_receiver) = mpsc::channel();
+        let thread = NetworkThread {
+            link,
+            codec: self.codec,
+            outgoing: outgoing_receiver,
+            delayed_packets: DelayQueue::new(),
+            paced_packets: DelayQueue::new(),
+            send_pacing: self.send_pacing,
+            last_paced: None,
+            received_packets: DelayQueue::new(),
+            receive_jitter: JitterModel::new(self.jitter_distribution, self.max_reorder_ms, seed.wrapping_add(1)),
+            receive_duplicates: StdRng::seed_from_u64(seed.wrapping_add(3)),
+            shared: Arc::clone(&shared),
+        };
+        let handle = std::thread::Builder::new()
+            .name("network".to_string())
+            .spawn(move || thread.run())?;
+
+        Ok(NetworkClient {
+            server_addr: self.server_addr,
+            local_addr,
+            outgoing,
+            thread: Some(handle),
+            shared,
+            send_jitter: JitterModel::new(self.jitter_distribution, self.max_reorder_ms, seed),
+            send_duplicates: StdRng::seed_from_u64(seed.wrapping_add(2)),
+            input_send_delays: RingHistory::new(INPUT_SEND_DELAY_HISTORY, |(sequence, _)| *sequence),
+            dropped_inputs: Vec::new(),
+            input_batch: Vec::new(),
+            send_rate: SendRate::default(),
+            last_input_send: None,
+            codec: self.codec,
+            local_server: self.local_server,
+            session: None,
+            sim_seed: seed,
+            stats,
+        })
+    }
+}
+
+/// A datagram for the network thread to send once its simulated delay has passed
+struct Outgoing {
+    data: Vec<u8>,
+    release: Instant,
+}
+
+/// Decoded datagrams waiting for the render thread, snapshots and control messages apart.
+/// Both queues are bounded and drop their oldest entry when full, a stalled render thread would
+/// skip the old snapshots anyway and must not make the network thread wait.
+#[derive(Default)]
+struct Inbox {
+    snapshots: VecDeque<GameState>,
+    messages: VecDeque<ClientMessage>,
+    dropped_snapshots: u64,
+    dropped_messages: u64,
+}
+
+/// Implementation of the Inbox
+impl Inbox {
+    /// Queues a decoded datagram, dropping the oldest of its kind if the queue is full
+    fn push(&mut self, incoming: Incoming) {
+        match incoming {
+            Incoming::Snapshot(snapshot) => {
+                if self.snapshots.len() == INCOMING_SNAPSHOT_QUEUE {
+                    self.snapshots.pop_front();
+                    self.dropped_snapshots += 1;
+                }
+                self.snapshots.push_back(*snapshot);
+            }
+            Incoming::Message(message) => {
+                if self.messages.len() == INCOMING_MESSAGE_QUEUE {
+                    self.messages.pop_front();
+                    self.dropped_messages += 1;
+                }
+                self.messages.push_back(message);
+            }
+        }
+    }
+}
+
+/// State shared by the NetworkClient and its network thread
+struct SharedState {
+    running: AtomicBool, // Cleared to stop the network thread
+    delay_ms: AtomicI32,
+    jitter_ms: AtomicI32,
+    packet_loss: AtomicI32,
+    duplicate_percent: AtomicI32,
+    duplicated_received: AtomicU64, // Copies the network thread made of received datagrams
+    max_send_burst: AtomicUsize,    // Most delayed packets the network thread sent in one pass
+    inbox: Mutex<Inbox>,
+    traffic: Mutex<TrafficMeter>, // Sent datagrams are counted by the client, received ones by the network thread
+}
+
+/// Implementation of the SharedState
+impl SharedState {
+    /// Whether a packet is lost, rolled with the current packet loss percentage
+    fn roll_loss(&self) -> bool {
+        simulate_packet_loss(self.packet_loss.load(Ordering::Relaxed))
+    }
+
+    /// Whether a packet is delivered twice, rolled with the current duplication percentage
+    fn roll_duplicate(&self, rng: &mut StdRng) -> bool {
+        simulate_duplicate(rng, self.duplicate_percent.load(Ordering::Relaxed))
+    }
+}
+
+/// Rolls whether a packet is lost at the given loss percentage
+fn simulate_packet_loss(packet_loss: i32) -> bool {
+    rand::rng().random_bool(packet_loss.clamp(0, 100) as f64 / 100.0)
+}
+
+/// Rolls whether a packet is duplicated at the given percentage. Nothing is drawn while duplication is
+/// off, so turning it on doesn't change what a seeded run drew before.
+fn simulate_duplicate(rng: &mut StdRng, duplicate_percent: i32) -> bool {
+    duplicate_percent > 0 && rng.random_bool(duplicate_percent.min(100) as f64 / 100.0)
+}
+
+/// The background thread that owns the socket. It sends delayed packets when they are due and
+/// receives, jitters and decodes datagrams as they arrive, so slow frames don't skew the timing.
+struct NetworkThread {
+    link: Box<dyn Link>,
+    codec: CodecKind,
+    outgoing: Receiver<Outgoing>,
+    delayed_packets: DelayQueue<Vec<u8>>,  // Packets waiting for their simulated delay
+    paced_packets: DelayQueue<Vec<u8>>,    // Matured packets waiting for their turn to be sent
+    send_pacing: Duration,                 // Least time between two sent packets
+    last_paced: Option<Instant>,           // When the packet paced last is or was sent
+    received_packets: DelayQueue<Vec<u8>>, // Received datagrams waiting for their simulated jitter
+    receive_jitter: JitterModel,
+    receive_duplicates: StdRng,
+    shared: Arc<SharedState>,
+}
+
+/// Implementation of the NetworkThread
+impl NetworkThread {
+    /// Runs until the client is dropped or closed. Each pass waits at most NETWORK_THREAD_POLL for a
+    /// datagram, less if a delayed packet is due sooner.
+    fn run(mut self) {
+        while self.shared.running.load(Ordering::Acquire) {
+            if !self.take_outgoing() {
+                break;
+            }
+            let now = Instant::now();
+            self.pace(now);
+            self.send_paced(now);
+
+            let next_due = [self.delayed_packets.next_ready_at(), self.paced_packets.next_ready_at(), self.received_packets.next_ready_at()]
+                .into_iter()
+                .flatten()
+                .min()
+                .map(|due| due.saturating_duration_since(now));
+            let wait = next_due.unwrap_or(NETWORK_THREAD_POLL).clamp(Duration::from_micros(50), NETWORK_THREAD_POLL);
+            self.receive(wait);
+            self.deliver(Instant::now());
+        }
+        self.flush();
+    }
+
+    /// Sends every packet still waiting for its delay or its turn right away, so the last messages of a
+    /// client that quits, like its Leave, aren't lost with the thread
+    fn flush(&mut self) {
+        self.take_outgoing();
+        let pending = self.paced_packets.drain_all().into_iter().chain(self.delayed_packets.drain_all());
+        for data in pending {
+            self.link.send(&data);
+        }
+    }
+
+    /// Moves queued outgoing packets into the delay queue. Returns false once the client is gone.
+    fn take_outgoing(&mut self) -> bool {
+        loop {
+            match self.outgoing.try_recv() {
+                Ok(packet) => self.delayed_packets.push(packet.data, packet.release),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+
+    /// Moves the packets whose simulated delay has passed to the paced queue, each spaced after the one before it
+    fn pace(&mut self, now: Instant) {
+        let matured = self.delayed_packets.pop_ready(now);
+        let releases = pace_releases(matured.len(), now, self.last_paced, self.send_pacing);
+        self.last_paced = releases.last().copied().or(self.last_paced);
+        for (data, release) in matured.into_iter().zip(releases) {
+            self.paced_packets.push(data, release);
+        }
+    }
+
+    /// Sends the paced packets that are due, recording how many went out back to back
+    fn send_paced(&mut self, now: Instant) {
+        let due = self.paced_packets.pop_ready(now);
+        for data in &due {
+            self.link.send(data);
+        }
+        self.shared.max_send_burst.fetch_max(due.len(), Ordering::Relaxed);
+    }
+
+    /// Waits up to wait for a datagram and holds it back by the simulated jitter, unless it is lost.
+    /// A duplicated datagram is queued twice, the copy with its own jitter.
+    fn receive(&mut self, wait: Duration) {
+        let Some(data) = self.link.recv(wait) else {
+            return;
+        };
+        if self.shared.roll_loss() {
+            // Drop the packet (simulate loss)
+            return;
+        }
+        // The delay is simulated on the send side, received datagrams are only held back by the jitter
+        let jitter_ms = self.shared.jitter_ms.load(Ordering::Relaxed);
+        let release = self.receive_jitter.release_at(Instant::now(), 0, jitter_ms);
+        self.received_packets.push(data.clone(), release);
+        if self.shared.roll_duplicate(&mut self.receive_duplicates) {
+            let release = self.receive_jitter.release_at(Instant::now(), 0, jitter_ms);
+            self.received_packets.push(data, release);
+            self.shared.duplicated_received.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Decodes the datagrams whose jitter has passed and hands them to the render thread
+    fn deliver(&mut self, now: Instant) {
+        let ready = self.received_packets.pop_ready(now);
+        if ready.is_empty() {
+            return;
+        }
+        let codec = self.codec.codec();
+        let mut inbox = self.shared.inbox.lock().unwrap();
+        let mut traffic = self.shared.traffic.lock().unwrap();
+        for data in &ready {
+            let Some(incoming) = codec.decode_incoming(data) else {
+                continue;
+            };
+            traffic.record(TrafficDirection::Down, TrafficCategory::of_incoming(&incoming), data.len(), now);
+            inbox.push(incoming);
+        }
+    }
+}
+
+/// Network client that handles sending and receiving messages with simulated network conditions.
+/// The socket lives on a network thread, the client only queues outgoing packets and takes decoded
+/// incoming ones, so every call returns right away no matter how long frames take.
 pub struct NetworkClient {
-    pub socket: UdpSocket,
     server_addr: String,
-    pub delay_ms: i32,
-    pub packet_loss: i32,
-    delayed_packets: VecDeque<(Vec<u8>, Instant, u32, i32)>, // (data, send_time, sequence, delay)
+    local_addr: SocketAddr,
+    outgoing: Sender<Outgoing>,
+    thread: Option<JoinHandle<()>>, // Joined when the client is dropped
+    shared: Arc<SharedState>,
+    send_jitter: JitterModel,
+    send_duplicates: StdRng,
+    input_send_delays: RingHistory<(u32, Duration), u32>, // (sequence, time the input was held back) keyed by sequence
+    dropped_inputs: Vec<u32>, // Sequences of inputs lost by the simulated packet loss since the last drain
+    input_batch: Vec<(PlayerInput, Instant)>, // Queued inputs waiting for the next send, with when they were queued
+    send_rate: SendRate,
+    last_input_send: Option<Instant>, // None before the first input is sent
+    codec: CodecKind, // Protocol spoken with the server
+    local_server: Option<LocalServerHandle>, // In-process server of the offline mode, None over a socket
+    session: Option<Uuid>, // Session token sent with inputs and pings once the server welcomed us
+    sim_seed: u64, // Seed of the simulated jitter and duplication, given to the builder or picked at random
+    stats: NetworkStats,
 }
 
 /// Implementation of the NetworkClient
 impl NetworkClient {
-    /// Creates a new NetworkClient bound to the specified server address
+    /// Creates a new NetworkClient bound to the specified server address, with an enlarged receive buffer
     pub fn new(server_addr: &str) -> Self {
-        let socket = UdpSocket::bind("0.0.0.0:0").expect("Failed to bind UDP socket");
-        socket.set_nonblocking(true).expect("Failed to set non-blocking");
-        Self {
-            socket,
+        Self::builder(server_addr)
+            .build()
+            .expect("Failed to bind UDP socket")
+    }
+
+    /// Starts building a NetworkClient for the specified server address
+    pub fn builder(server_addr: &str) -> NetworkClientBuilder {
+        NetworkClientBuilder {
             server_addr: server_addr.to_string(),
-            delay_ms: DELAY_MS,
-            packet_loss: PACKET_LOSS,
-            delayed_packets: VecDeque::new(),
+            recv_buffer_size: Some(CLIENT_RECV_BUFFER_SIZE),
+            jitter_distribution: JitterDistribution::default(),
+            max_reorder_ms: MAX_REORDER_MS,
+            jitter_seed: None,
+            send_pacing: SEND_PACING,
+            codec: CodecKind::default(),
+            local_server: None,
         }
     }
-    
+
+    /// A fresh client for the same server, socket or in-process, speaking the same protocol
+    pub fn rebuild(&self) -> std::io::Result<Self> {
+        let builder = Self::builder(&self.server_addr).codec(self.codec);
+        match &self.local_server {
+            Some(server) => builder.local_server(server.clone()).build(),
+            None => builder.build(),
+        }
+    }
+
+    /// True if the client talks to an in-process server instead of a socket
+    pub fn is_offline(&self) -> bool {
+        self.local_server.is_some()
+    }
+
+    /// Address of the server this client sends to
+    pub fn server_addr(&self) -> &str {
+        &self.server_addr
+    }
+
+    /// Local address of the client socket
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Seed of the simulated jitter and duplication, to repeat a run with the builder's jitter_seed
+    pub fn sim_seed(&self) -> u64 {
+        self.sim_seed
+    }
+
+    /// Protocol spoken with the server
+    pub fn codec_kind(&self) -> CodecKind {
+        self.codec
+    }
+
+    /// Statistics about the socket and received data
+    pub fn stats(&self) -> &NetworkStats {
+        &self.stats
+    }
+
+    /// Counts the anomalies the client world found in a snapshot, and the snapshot if it was discarded
+    pub fn record_snapshot_anomalies(&mut self, anomalies: usize, discarded: bool) {
+        self.stats.snapshot_anomalies += anomalies as u64;
+        self.stats.discarded_snapshots += discarded as u64;
+    }
+
+    /// Simulated network delay in milliseconds
+    pub fn delay_ms(&self) -> i32 {
+        self.shared.delay_ms.load(Ordering::Relaxed)
+    }
+
+    /// Simulated jitter in milliseconds
+    pub fn jitter_ms(&self) -> i32 {
+        self.shared.jitter_ms.load(Ordering::Relaxed)
+    }
+
+    /// Simulated packet loss percentage (0-100)
+    pub fn packet_loss(&self) -> i32 {
+        self.shared.packet_loss.load(Ordering::Relaxed)
+    }
+
+    /// Percentage of packets the simulated network delivers twice
+    pub fn duplicate_percent(&self) -> i32 {
+        self.shared.duplicate_percent.load(Ordering::Relaxed)
+    }
+
+    /// Sets the percentage of packets delivered twice, in both directions
+    pub fn set_duplicate_percent(&self, duplicate_percent: i32) {
+        self.shared.duplicate_percent.store(duplicate_percent, Ordering::Relaxed);
+    }
+
+    /// Sets the simulated network conditions, the network thread uses them from its next packet on
+    pub fn set_conditions(&self, delay_ms: i32, jitter_ms: i32, packet_loss: i32) {
+        self.shared.delay_ms.store(delay_ms, Ordering::Relaxed);
+        self.shared.jitter_ms.store(jitter_ms, Ordering::Relaxed);
+        self.shared.packet_loss.store(packet_loss, Ordering::Relaxed);
+    }
+
     /// Connects to the server by sending a connect message
     pub fn send_connect(&self) {
-        let msg = ClientMessage::Connect;
-        let data = bincode::serialize(&msg).unwrap();
-        let _ = self.socket.send_to(&data, &self.server_addr);
+        self.send_message(&ClientMessage::Connect);
     }
     
-    /// Sends a ping message with the current timestamp
+    /// Connects to a room of the server with the name and shape the player chose and the hashed instance id.
+    /// An empty room name joins the default room.
+    pub fn send_connect_with_profile(&self, name: &str, shape: PlayerShape, instance: u64, room: &str, password: Option<&str>) {
+        let msg = ClientMessage::ConnectWithProfile {
+            name: name.to_string(),
+            shape: shape.to_wire(),
+            instance,
+            room: room.to_string(),
+            password: password.map(str::to_string),
+        };
+        self.send_message(&msg);
+    }
+
+    /// Asks the server for its rooms, answered with a RoomList
+    pub fn send_list_rooms(&self) {
+        self.send_message(&ClientMessage::ListRooms);
+    }
+
+    /// Resumes a previous session by sending its session token
+    pub fn send_reconnect(&self, session_token: Uuid) {
+        self.send_message(&ClientMessage::Reconnect { session_token });
+    }
+
+    /// Sends a ping message with the current timestamp. It also keeps the NAT mapping open while the player is idle.
     pub fn send_ping(&self, timestamp: u64) {
-        let msg = ClientMessage::Ping(timestamp);
-        let data = bincode::serialize(&msg).unwrap();
-        let _ = self.socket.send_to(&data, &self.server_addr);
+        self.send_message(&self.with_session(ClientMessage::Ping(timestamp)));
+    }
+
+    /// Tells the server the player quits, so it is removed right away instead of timing out
+    pub fn send_leave(&self) {
+        self.send_message(&self.with_session(ClientMessage::Leave));
+    }
+
+    /// Asks the server to place the local player at the position, for servers started with debug teleports
+    pub fn send_debug_set_position(&self, position: Position) {
+        self.send_message(&self.with_session(ClientMessage::DebugSetPosition(position)));
     }
 
-    /// Sends a player input message with the specified input
+    /// Sets the session token that goes with every input, ping and ping echo, so the server can
+    /// follow the client if its address changes mid-session
+    pub fn set_session(&mut self, session_token: Option<Uuid>) {
+        self.session = session_token;
+    }
+
+    /// Puts the message in a session header once there is a session
+    fn with_session(&self, message: ClientMessage) -> ClientMessage {
+        match self.session {
+            Some(session_token) => ClientMessage::WithSession { session_token, message: Box::new(message) },
+            None => message,
+        }
+    }
+
+    /// Sends a message to the server right away, without the simulated network conditions
+    fn send_message(&self, message: &ClientMessage) {
+        let data = self.codec.codec().encode_message(message);
+        self.queue(data, Instant::now(), TrafficCategory::of_message(message));
+    }
+
+    /// Sends a player input message with the specified input right away, ahead of any queued inputs
     pub fn send_input(&mut self, input: PlayerInput) {
-        if self.simulate_network_conditions() {
-            // Drop the packet (simulate loss)
-            return;
+        let now = Instant::now();
+        self.send_input_datagram(vec![(input, now)], now);
+    }
+
+    /// Queues an input for the next send. It goes out at once if every input is sent on its own
+    /// or the batch is full, otherwise with the batch when the send interval has passed.
+    pub fn queue_input(&mut self, input: PlayerInput, now: Instant) {
+        self.input_batch.push((input, now));
+        if self.send_rate == SendRate::Immediate || self.input_batch.len() >= MAX_INPUT_BATCH {
+            self.flush_inputs(now);
         }
-        let msg = ClientMessage::Input(input);
-        let data = bincode::serialize(&msg).unwrap();
-        
-        // Add artificial delay with jitter
-        if self.delay_ms > 0 {
-            let jitter = rand::rng().random_range(-5..=5); // ±5ms jitter
-            let delay = (self.delay_ms + jitter).max(0);
-            self.delayed_packets.push_back((data, Instant::now(), input.sequence, delay));
-        } else {
-            let _ = self.socket.send_to(&data, &self.server_addr);
+    }
+
+    /// Sends the queued inputs in one datagram once the send interval has passed since the last send,
+    /// called every frame
+    pub fn flush_due_inputs(&mut self, now: Instant) {
+        let due = self.last_input_send.is_none_or(|last| now.saturating_duration_since(last) >= self.send_rate.interval());
+        if !self.input_batch.is_empty() && due {
+            self.flush_inputs(now);
         }
     }
 
-    /// Tries to receive a game state snapshot from the server
-    pub fn try_receive_snapshot(&mut self) -> Option<GameState> {
-        self.receive_data()
+    /// Picks the send rate from the round trip time in milliseconds, the estimated loss and the inputs waiting for an ack
+    pub fn update_send_rate(&mut self, rtt_ms: Option<f64>, loss: f64, unacked: usize) {
+        self.send_rate = next_send_rate(self.send_rate, rtt_ms, loss, unacked);
     }
 
-    /// Tries to receive a client message from the server
-    pub fn try_receive_message(&mut self) -> Option<ClientMessage> {
-        self.receive_data()
+    /// How often queued inputs are sent
+    pub fn send_rate(&self) -> SendRate {
+        self.send_rate
     }
-    
-    /// Simulates network conditions like packet loss
-    fn simulate_network_conditions(&self) -> bool {
-        // Simulate packet loss
-        rand::rng().random_bool(self.packet_loss as f64 / 100.0)
+
+    /// Inputs queued for the next send
+    pub fn queued_inputs(&self) -> usize {
+        self.input_batch.len()
     }
 
-    /// Processes delayed packets and sends them when their delay has elapsed
-    fn process_delayed_packets(&mut self) {
-        let now = Instant::now();
-        let mut ready_packets: Vec<(Vec<u8>, u32)> = Vec::new();
-
-        // Collect all packets that are ready to be sent
-        while let Some((data, send_time, sequence, delay)) = self.delayed_packets.front() {
-            if now.duration_since(*send_time) >= Duration::from_millis(*delay as u64) {
-                ready_packets.push((data.clone(), *sequence));
-                self.delayed_packets.pop_front();
-            } else {
-                break;
-            }
+    /// Sends every queued input now
+    fn flush_inputs(&mut self, now: Instant) {
+        let batch = std::mem::take(&mut self.input_batch);
+        if !batch.is_empty() {
+            self.send_input_datagram(batch, now);
+            self.last_input_send = Some(now);
         }
+    }
 
-        // Shuffle ready packets to simulate out-of-order delivery
-        if !ready_packets.is_empty() {
-            let mut rng = rand::rng();
-            ready_packets.shuffle(&mut rng);
+    /// Sends inputs in one datagram, a single input as an Input message. The whole datagram is lost or
+    /// delayed together, the time each input waited in the queue counts as held back.
+    fn send_input_datagram(&mut self, inputs: Vec<(PlayerInput, Instant)>, now: Instant) {
+        if self.shared.roll_loss() {
+            // Drop the packet (simulate loss)
+            self.dropped_inputs.extend(inputs.iter().map(|(input, _)| input.sequence));
+            return;
+        }
+        let message = match inputs.as_slice() {
+            [(input, _)] => ClientMessage::Input(*input),
+            _ => ClientMessage::InputBatch(inputs.iter().map(|(input, _)| *input).collect()),
+        };
+        let data = self.codec.codec().encode_message(&self.with_session(message));
+        let held = self.send_delayed(data, TrafficCategory::Input);
 
-            // Send packets in shuffled order
-            for (data, _) in ready_packets {
-                let _ = self.socket.send_to(&data, &self.server_addr);
+        for (input, queued_at) in inputs {
+            // A fresh session counts sequences from zero again
+            if self.input_send_delays.newest().is_some_and(|(newest, _)| *newest >= input.sequence) {
+                self.input_send_delays.clear();
             }
+            self.input_send_delays.push((input.sequence, now.saturating_duration_since(queued_at) + held));
         }
     }
 
-    /// Receives data from the server for game state or client messages
-    fn receive_data<T: serde::de::DeserializeOwned>(&mut self) -> Option<T> {
-        // Process delayed packets
-        self.process_delayed_packets();
+    /// How long an input was held back in the queue and by the simulated delay before it was sent, None for unknown or lost inputs
+    pub fn input_send_delay(&self, sequence: u32) -> Option<Duration> {
+        self.input_send_delays.find(&sequence).map(|(_, held)| *held)
+    }
+
+    /// Sequences of the inputs the simulated packet loss dropped since the last call
+    pub fn drain_dropped_inputs(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.dropped_inputs)
+    }
 
-        if self.simulate_network_conditions() {
+    /// Echoes a server ping. The echo goes through the simulated network conditions,
+    /// so the measured ping includes the simulated delay.
+    pub fn send_server_pong(&mut self, nonce: u32) {
+        if self.shared.roll_loss() {
             // Drop the packet (simulate loss)
-            return None;
+            return;
         }
+        let data = self.codec.codec().encode_message(&self.with_session(ClientMessage::ServerPong(nonce)));
+        self.send_delayed(data, TrafficCategory::Ping);
+    }
+
+    /// Sends data to the server after the simulated delay, returns how long it is held back.
+    /// The release time is drawn when the packet is sent, the network thread sends it when it is due.
+    /// A duplicated packet is sent twice, the copy with its own delay.
+    fn send_delayed(&mut self, data: Vec<u8>, category: TrafficCategory) -> Duration {
+        let now = Instant::now();
+        let release = self.draw_release(now);
+        if self.shared.roll_duplicate(&mut self.send_duplicates) {
+            self.queue(data.clone(), release, category);
+            let copy_release = self.draw_release(now);
+            self.queue(data, copy_release, category);
+            self.stats.duplicated_sent += 1;
+        } else {
+            self.queue(data, release, category);
+        }
+        release.saturating_duration_since(now)
+    }
 
-        let mut buf = [0u8; 2048];
-        if let Ok((size, _)) = self.socket.recv_from(&mut buf) {
-            bincode::deserialize(&buf[..size]).ok()
+    /// When a packet sent now is released, after the simulated delay with jitter
+    fn draw_release(&mut self, now: Instant) -> Instant {
+        let (delay_ms, jitter_ms) = (self.delay_ms(), self.jitter_ms());
+        if delay_ms > 0 || jitter_ms > 0 {
+            self.send_jitter.release_at(now, delay_ms, jitter_ms)
         } else {
-            None
+            now
+        }
+    }
+
+    /// Hands a packet to the network thread, counting it as sent traffic of the category
+    fn queue(&self, data: Vec<u8>, release: Instant, category: TrafficCategory) {
+        self.shared.traffic.lock().unwrap().record(TrafficDirection::Up, category, data.len(), Instant::now());
+        let _ = self.outgoing.send(Outgoing { data, release });
+    }
+
+    /// Takes everything the network thread received since the last frame. Snapshots are sorted by tick,
+    /// and if more than burst_threshold arrived only the newest is kept.
+    pub fn receive_frame(&mut self, burst_threshold: usize) -> ReceivedFrame {
+        let (snapshots, messages) = {
+            let mut inbox = self.shared.inbox.lock().unwrap();
+            self.stats.dropped_snapshots = inbox.dropped_snapshots;
+            self.stats.dropped_messages = inbox.dropped_messages;
+            self.stats.duplicated_received = self.shared.duplicated_received.load(Ordering::Relaxed);
+            self.stats.max_send_burst = self.shared.max_send_burst.load(Ordering::Relaxed);
+            self.stats.traffic = self.shared.traffic.lock().unwrap().clone();
+            (Vec::from(std::mem::take(&mut inbox.snapshots)), Vec::from(std::mem::take(&mut inbox.messages)))
+        };
+
+        let (snapshots, burst) = handle_snapshot_burst(snapshots, burst_threshold);
+        if let Some(burst) = burst {
+            self.stats.bursts += 1;
+            self.stats.last_burst_size = Some(burst.size);
+            self.stats.skipped_snapshots += burst.skipped as u64;
+        }
+        ReceivedFrame { snapshots, messages, burst }
+    }
+
+    /// Tries to take the oldest received game state snapshot
+    pub fn try_receive_snapshot(&mut self) -> Option<GameState> {
+        self.shared.inbox.lock().unwrap().snapshots.pop_front()
+    }
+
+    /// Tries to take the oldest received client message
+    pub fn try_receive_message(&mut self) -> Option<ClientMessage> {
+        self.shared.inbox.lock().unwrap().messages.pop_front()
+    }
+
+    /// Whether the network thread is still running
+    pub fn is_running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// Stops the network thread after it sent everything still queued, waiting at most timeout for it.
+    /// Returns false if the thread didn't stop in time, it is left behind instead of blocking the caller.
+    pub fn close(mut self, timeout: Duration) -> bool {
+        self.shared.running.store(false, Ordering::Release);
+        let deadline = Instant::now() + timeout;
+        let Some(thread) = self.thread.take() else {
+            return true;
+        };
+        while !thread.is_finished() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let _ = thread.join();
+        true
+    }
+}
+
+/// Stops the network thread and waits for it, which closes the socket
+impl Drop for NetworkClient {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
         }
     }
 }
@@ -133,30 +821,29 @@ impl NetworkClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::codec::{decode, encode, BincodeCodec, Codec};
+    use crate::traffic::TrafficCount;
+    use crate::types::{AppliedImpulse, Direction, InputTiming, MovementStats};
 
     #[test]
     fn test_new_client() {
         let client = NetworkClient::new("127.0.0.1:8080");
         assert_eq!(client.server_addr, "127.0.0.1:8080");
-        assert_eq!(client.delay_ms, DELAY_MS);
-        assert_eq!(client.packet_loss, PACKET_LOSS);
-        assert!(client.delayed_packets.is_empty());
+        assert_eq!(client.delay_ms(), DELAY_MS);
+        assert_eq!(client.packet_loss(), PACKET_LOSS);
+        assert!(client.is_running());
     }
 
     #[test]
     fn test_simulate_network_conditions() {
-        let mut client = NetworkClient::new("127.0.0.1:8080");
-
         // With 0% loss, should never drop packets
-        client.packet_loss = 0;
         for _ in 0..100 {
-            assert!(!client.simulate_network_conditions());
+            assert!(!simulate_packet_loss(0));
         }
 
         // With 100% loss, should always drop packets
-        client.packet_loss = 100;
         for _ in 0..100 {
-            assert!(client.simulate_network_conditions());
+            assert!(simulate_packet_loss(100));
         }
     }
 
@@ -168,6 +855,162 @@ mod tests {
         client.send_connect(); // Should not panic
     }
 
+    #[test]
+    fn test_send_reconnect() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        let token = Uuid::new_v4();
+        client.send_reconnect(token);
+
+        let mut buf = [0u8; 1024];
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let (size, _) = server.recv_from(&mut buf).unwrap();
+        match decode(&buf[..size]).unwrap() {
+            ClientMessage::Reconnect { session_token } => assert_eq!(session_token, token),
+            other => panic!("Expected Reconnect, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_input_send_delay_is_remembered() {
+        let mut client = NetworkClient::new("127.0.0.1:8080");
+        client.set_conditions(0, 0, 0);
+        let input = |sequence| PlayerInput { dir: crate::types::Direction::Up, sequence, timestamp: 0 };
+
+        client.send_input(input(0));
+        assert_eq!(client.input_send_delay(0), Some(Duration::ZERO));
+
+        client.set_conditions(100, 0, 0);
+        client.send_input(input(1));
+        let held = client.input_send_delay(1).unwrap();
+        assert!(held > Duration::from_millis(90) && held <= Duration::from_millis(100));
+        assert_eq!(client.input_send_delay(2), None);
+
+        // A fresh session starts over
+        client.send_input(input(0));
+        assert_eq!(client.input_send_delay(1), None);
+        assert!(client.input_send_delay(0).is_some());
+    }
+
+    #[test]
+    fn test_dropped_inputs_are_reported_once() {
+        let mut client = NetworkClient::new("127.0.0.1:8080");
+        let input = |sequence| PlayerInput { dir: crate::types::Direction::Up, sequence, timestamp: 0 };
+
+        client.set_conditions(0, 0, 100);
+        client.send_input(input(0));
+        client.send_input(input(1));
+        client.set_conditions(0, 0, 0);
+        client.send_input(input(2));
+
+        assert_eq!(client.drain_dropped_inputs(), vec![0, 1]);
+        assert!(client.drain_dropped_inputs().is_empty());
+    }
+
+    #[test]
+    fn test_queued_inputs_are_sent_in_batches() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        client.set_conditions(0, 0, 0);
+        let input = |sequence| PlayerInput { dir: crate::types::Direction::Up, sequence, timestamp: 0 };
+        let mut buf = [0u8; 1024];
+        let mut receive = || {
+            let (size, _) = server.recv_from(&mut buf).unwrap();
+            decode::<ClientMessage>(&buf[..size]).unwrap()
+        };
+
+        // At the base rate inputs wait for the interval and go out together
+        let start = Instant::now();
+        client.queue_input(input(0), start);
+        client.flush_due_inputs(start);
+        client.queue_input(input(1), start);
+        client.queue_input(input(2), start + Duration::from_millis(10));
+        client.flush_due_inputs(start + Duration::from_millis(10));
+        assert_eq!(client.queued_inputs(), 2);
+        let due = start + SendRate::Base.interval();
+        client.flush_due_inputs(due);
+        assert_eq!(client.queued_inputs(), 0);
+        assert_eq!(receive(), ClientMessage::Input(input(0)));
+        assert_eq!(receive(), ClientMessage::InputBatch(vec![input(1), input(2)]));
+
+        // The time in the queue counts as held back
+        assert_eq!(client.input_send_delay(1), Some(SendRate::Base.interval()));
+        assert_eq!(client.input_send_delay(2), Some(SendRate::Base.interval() - Duration::from_millis(10)));
+
+        // A full batch doesn't wait
+        for sequence in 3..3 + MAX_INPUT_BATCH as u32 {
+            client.queue_input(input(sequence), due);
+        }
+        assert_eq!(client.queued_inputs(), 0);
+        assert!(matches!(receive(), ClientMessage::InputBatch(inputs) if inputs.len() == MAX_INPUT_BATCH));
+
+        // On a fast connection every input goes out on its own
+        client.update_send_rate(Some(10.0), 0.0, 0);
+        assert_eq!(client.send_rate(), SendRate::Immediate);
+        client.queue_input(input(20), due);
+        assert_eq!(receive(), ClientMessage::Input(input(20)));
+    }
+
+    #[test]
+    fn test_lost_batch_drops_every_input() {
+        let mut client = NetworkClient::new("127.0.0.1:8080");
+        let input = |sequence| PlayerInput { dir: crate::types::Direction::Up, sequence, timestamp: 0 };
+        client.set_conditions(0, 0, 100);
+        let now = Instant::now();
+        client.queue_input(input(0), now);
+        client.queue_input(input(1), now);
+        client.flush_due_inputs(now + SendRate::Base.interval());
+        assert_eq!(client.drain_dropped_inputs(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_send_server_pong() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        client.send_server_pong(9);
+
+        let mut buf = [0u8; 1024];
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let (size, _) = server.recv_from(&mut buf).unwrap();
+        match decode(&buf[..size]).unwrap() {
+            ClientMessage::ServerPong(nonce) => assert_eq!(nonce, 9),
+            other => panic!("Expected ServerPong, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_session_header_is_added_once_welcomed() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        client.set_conditions(0, 0, 0);
+        let input = PlayerInput { dir: crate::types::Direction::Up, sequence: 0, timestamp: 0 };
+        let mut buf = [0u8; 1024];
+        let mut receive = || {
+            let (size, _) = server.recv_from(&mut buf).unwrap();
+            decode::<ClientMessage>(&buf[..size]).unwrap()
+        };
+
+        // Before the Welcome there is no session to send
+        client.send_ping(1);
+        assert_eq!(receive(), ClientMessage::Ping(1));
+
+        let token = Uuid::new_v4();
+        let with_session = |message| ClientMessage::WithSession { session_token: token, message: Box::new(message) };
+        client.set_session(Some(token));
+        client.send_ping(2);
+        client.send_input(input);
+        client.send_server_pong(3);
+        assert_eq!(receive(), with_session(ClientMessage::Ping(2)));
+        assert_eq!(receive(), with_session(ClientMessage::Input(input)));
+        assert_eq!(receive(), with_session(ClientMessage::ServerPong(3)));
+
+        // Connect messages go out as they are
+        client.send_list_rooms();
+        assert_eq!(receive(), ClientMessage::ListRooms);
+    }
+
     #[test]
     fn test_send_ping() {
         // Similar to above, just ensuring it compiles and runs
@@ -176,15 +1019,530 @@ mod tests {
     }
 
     #[test]
-    fn test_receive_data_with_packet_loss() {
-        let mut client = NetworkClient::new("127.0.0.1:8080");
-        client.packet_loss = 100; // Always drop packets
+    fn test_receive_with_packet_loss() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        let client_addr = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+        client.set_conditions(0, 0, 100); // Always drop packets
 
-        // Since it will always simulate packet loss, this should be None
-        let result: Option<GameState> = client.receive_data();
-        assert!(result.is_none());
+        // Since it will always simulate packet loss, nothing is received
+        server.send_to(&crate::snapshot::encode_snapshot(&snapshot(1)), client_addr).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(client.try_receive_snapshot().is_none());
+        assert!(client.receive_frame(5).snapshots.is_empty());
     }
 
-    // For complete socket testing, you'd need more complex setup with
-    // mocked UdpSocket, but that's outside the scope of basic unit tests
+    // Helper function to create an empty snapshot for the given tick
+    fn snapshot(snapshot_id: u64) -> GameState {
+        GameState {
+            players: Vec::new(),
+            last_processed: std::collections::HashMap::new(),
+            pellets: Vec::new(),
+            scores: std::collections::HashMap::new(),
+            best_scores: std::collections::HashMap::new(),
+            teams: std::collections::HashMap::new(),
+            input_reorders: std::collections::HashMap::new(),
+            shapes: std::collections::HashMap::new(),
+            impulses: std::collections::HashMap::new(),
+            input_timings: std::collections::HashMap::new(),
+            match_phase: Default::default(),
+            server_timestamp: 0,
+            match_number: 0,
+            ping_ms: std::collections::HashMap::new(),
+            snapshot_id,
+            tick_interval_ms: 16,
+            tagged: None,
+            movement_stats: std::collections::HashMap::new(),
+        }
+    }
+
+    // Helper function to get the snapshot ids in order
+    fn ids(snapshots: &[GameState]) -> Vec<u64> {
+        snapshots.iter().map(|snapshot| snapshot.snapshot_id).collect()
+    }
+
+    #[test]
+    fn test_snapshots_below_threshold_are_all_applied_in_order() {
+        let (snapshots, burst) = handle_snapshot_burst(vec![snapshot(3), snapshot(1), snapshot(2)], 3);
+
+        assert_eq!(ids(&snapshots), vec![1, 2, 3]);
+        assert_eq!(burst, None);
+    }
+
+    #[test]
+    fn test_burst_skips_to_newest() {
+        let received: Vec<GameState> = (1..=20).rev().map(snapshot).collect();
+        let (snapshots, burst) = handle_snapshot_burst(received, 5);
+
+        assert_eq!(ids(&snapshots), vec![20]);
+        assert_eq!(burst, Some(SnapshotBurst { size: 20, skipped: 19 }));
+    }
+
+    #[test]
+    fn test_no_snapshots() {
+        let (snapshots, burst) = handle_snapshot_burst(Vec::new(), 5);
+        assert!(snapshots.is_empty());
+        assert_eq!(burst, None);
+    }
+
+    #[test]
+    fn test_burst_delay() {
+        let small = SnapshotBurst { size: 6, skipped: 5 };
+        assert!((burst_delay(&small, 16) - 0.08).abs() < 1e-9);
+
+        // A long hitch is capped
+        let large = SnapshotBurst { size: 1000, skipped: 999 };
+        assert_eq!(burst_delay(&large, 16), MAX_BURST_DELAY);
+    }
+
+    #[test]
+    fn test_builder_sets_receive_buffer() {
+        // Setting the buffer size must not fail, the OS may round or cap the effective size
+        let client = NetworkClient::builder("127.0.0.1:8080")
+            .recv_buffer_size(Some(CLIENT_RECV_BUFFER_SIZE))
+            .build()
+            .unwrap();
+        assert!(client.stats().recv_buffer_size.is_some_and(|size| size > 0));
+
+        let default_client = NetworkClient::builder("127.0.0.1:8080")
+            .recv_buffer_size(None)
+            .build()
+            .unwrap();
+        assert!(default_client.stats().recv_buffer_size.is_some());
+    }
+
+    #[test]
+    fn test_decode_incoming() {
+        let snapshot_data = crate::snapshot::encode_snapshot(&snapshot(7));
+        assert!(matches!(BincodeCodec.decode_incoming(&snapshot_data), Some(Incoming::Snapshot(s)) if s.snapshot_id == 7));
+
+        let message_data = encode(&ClientMessage::ServerPing(3));
+        assert!(matches!(BincodeCodec.decode_incoming(&message_data), Some(Incoming::Message(ClientMessage::ServerPing(3)))));
+
+        assert!(BincodeCodec.decode_incoming(&[]).is_none());
+    }
+
+    #[test]
+    fn test_receive_frame_drains_socket() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        let client_addr = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+
+        for id in [2, 1] {
+            server.send_to(&crate::snapshot::encode_snapshot(&snapshot(id)), client_addr).unwrap();
+        }
+        server.send_to(&encode(&ClientMessage::ServerPing(5)), client_addr).unwrap();
+
+        // Local datagrams arrive almost immediately, give them a moment
+        let mut snapshots = Vec::new();
+        let mut messages = Vec::new();
+        for _ in 0..50 {
+            let frame = client.receive_frame(5);
+            snapshots.extend(frame.snapshots);
+            messages.extend(frame.messages);
+            if snapshots.len() == 2 && messages.len() == 1 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(ids(&snapshots), vec![1, 2]);
+        assert!(matches!(messages[..], [ClientMessage::ServerPing(5)]));
+        assert_eq!(client.stats().bursts, 0);
+    }
+
+    #[test]
+    fn test_traffic_is_counted_per_category() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        let client_addr = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+
+        // Sent datagrams are counted when they are handed to the network thread
+        client.send_ping(1);
+        client.send_input(PlayerInput { dir: Direction::Up, sequence: 1, timestamp: 0 });
+        client.send_list_rooms();
+        let snapshot_data = crate::snapshot::encode_snapshot(&snapshot(1));
+        server.send_to(&snapshot_data, client_addr).unwrap();
+        server.send_to(&encode(&ClientMessage::ServerPing(5)), client_addr).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        client.receive_frame(usize::MAX);
+
+        let traffic = &client.stats().traffic;
+        let ping = encode(&ClientMessage::Ping(1));
+        assert_eq!(traffic.total(TrafficDirection::Up, TrafficCategory::Ping), TrafficCount { bytes: ping.len() as u64, packets: 1 });
+        assert_eq!(traffic.total(TrafficDirection::Up, TrafficCategory::Input).packets, 1);
+        assert_eq!(traffic.total(TrafficDirection::Up, TrafficCategory::Control).packets, 1);
+        assert_eq!(traffic.total(TrafficDirection::Down, TrafficCategory::Snapshot).bytes, snapshot_data.len() as u64);
+        assert_eq!(traffic.total(TrafficDirection::Down, TrafficCategory::Ping).packets, 1);
+        assert_eq!(traffic.total(TrafficDirection::Down, TrafficCategory::Input), TrafficCount::default());
+    }
+
+    #[test]
+    fn test_json_client_sends_and_receives_json() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut client = NetworkClient::builder(&server.local_addr().unwrap().to_string())
+            .codec(CodecKind::Json)
+            .build()
+            .unwrap();
+        assert_eq!(client.codec_kind(), CodecKind::Json);
+
+        client.send_ping(42);
+        let mut buf = [0u8; 1024];
+        let (size, client_addr) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..size], br#"{"Message":{"Ping":42}}"#);
+
+        // A bincode datagram isn't understood by a JSON client
+        let json = CodecKind::Json.codec();
+        server.send_to(&json.encode_snapshot(&snapshot(3)), client_addr).unwrap();
+        server.send_to(&BincodeCodec.encode_message(&ClientMessage::ServerPing(1)), client_addr).unwrap();
+        server.send_to(&json.encode_message(&ClientMessage::ServerPing(2)), client_addr).unwrap();
+
+        let mut snapshots = Vec::new();
+        let mut messages = Vec::new();
+        for _ in 0..50 {
+            let frame = client.receive_frame(5);
+            snapshots.extend(frame.snapshots);
+            messages.extend(frame.messages);
+            if !snapshots.is_empty() && !messages.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        messages.extend(client.receive_frame(5).messages);
+
+        assert_eq!(ids(&snapshots), vec![3]);
+        assert!(matches!(messages[..], [ClientMessage::ServerPing(2)]));
+    }
+
+    // Helper function to create a snapshot of a full room with every per player field set, the movement stats too
+    fn full_room_snapshot(player_count: u128) -> GameState {
+        let mut state = snapshot(120);
+        for n in 0..player_count {
+            let id = Uuid::from_u128(0x5000 + n);
+            state.players.push((id, Position { x: 20 + n as i32 * 30, y: 300 }, 0x17ff17));
+            state.last_processed.insert(id, 4_096);
+            state.scores.insert(id, 12);
+            state.best_scores.insert(id, 40);
+            state.teams.insert(id, (n % 2) as u8);
+            state.input_reorders.insert(id, 3);
+            state.shapes.insert(id, PlayerShape::Circle);
+            state.impulses.insert(id, AppliedImpulse { dx: 3, dy: -3, after_sequence: 4_095, tick: 118 });
+            state.input_timings.insert(id, InputTiming { sequence: 4_096, client_timestamp: 81_920, received_ms: 1_900, applied_ms: 1_916 });
+            state.ping_ms.insert(id, 180);
+            state.movement_stats.insert(id, MovementStats { distance: 20_480, inputs: 4_096, connected_ms: 600_000 });
+        }
+        state
+    }
+
+    #[test]
+    fn test_full_room_snapshot_arrives_in_either_protocol() {
+        for codec in [CodecKind::Bincode, CodecKind::Json] {
+            let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let mut client = NetworkClient::builder(&server.local_addr().unwrap().to_string())
+                .codec(codec)
+                .build()
+                .unwrap();
+            let client_addr = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+
+            let state = full_room_snapshot(32);
+            server.send_to(&codec.codec().encode_snapshot(&state), client_addr).unwrap();
+
+            let mut snapshots = Vec::new();
+            for _ in 0..50 {
+                snapshots.extend(client.receive_frame(5).snapshots);
+                if !snapshots.is_empty() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            // The whole datagram was read and decoded, nothing was cut off
+            assert_eq!(snapshots.len(), 1, "{:?}", codec);
+            assert_eq!(snapshots[0].players, state.players);
+            assert_eq!(snapshots[0].movement_stats, state.movement_stats);
+        }
+    }
+
+    #[test]
+    fn test_receive_frame_records_burst() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        let client_addr = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+
+        for id in 1..=10 {
+            server.send_to(&crate::snapshot::encode_snapshot(&snapshot(id)), client_addr).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+
+        let frame = client.receive_frame(5);
+        assert_eq!(ids(&frame.snapshots), vec![10]);
+        assert_eq!(client.stats().bursts, 1);
+        assert_eq!(client.stats().last_burst_size, Some(10));
+        assert_eq!(client.stats().skipped_snapshots, 9);
+    }
+
+    #[test]
+    fn test_jittered_sends_keep_order_without_reorder_window() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::builder(&server.local_addr().unwrap().to_string())
+            .jitter_distribution(JitterDistribution::Normal)
+            .max_reorder_ms(0)
+            .jitter_seed(Some(5))
+            .build()
+            .unwrap();
+        client.set_conditions(0, 20, 0);
+        for nonce in 0..10 {
+            client.send_server_pong(nonce);
+        }
+
+        let mut buf = [0u8; 1024];
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        for expected in 0..10 {
+            let (size, _) = server.recv_from(&mut buf).unwrap();
+            match decode(&buf[..size]).unwrap() {
+                ClientMessage::ServerPong(nonce) => assert_eq!(nonce, expected),
+                other => panic!("Expected ServerPong, got {:?}", other),
+            }
+        }
+    }
+
+    // Helper function to receive the nonces of the server pongs until none arrive for a while
+    fn receive_pongs(server: &UdpSocket) -> Vec<u32> {
+        let mut buf = [0u8; 1024];
+        let mut nonces = Vec::new();
+        server.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        while let Ok((size, _)) = server.recv_from(&mut buf) {
+            match decode(&buf[..size]).unwrap() {
+                ClientMessage::ServerPong(nonce) => nonces.push(nonce),
+                other => panic!("Expected ServerPong, got {:?}", other),
+            }
+        }
+        nonces
+    }
+
+    #[test]
+    fn test_seeded_duplication_repeats_and_is_counted() {
+        let runs: Vec<(Vec<u32>, u64)> = (0..2)
+            .map(|_| {
+                let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+                let mut client = NetworkClient::builder(&server.local_addr().unwrap().to_string())
+                    .jitter_seed(Some(9))
+                    .build()
+                    .unwrap();
+                client.set_duplicate_percent(50);
+                for nonce in 0..40 {
+                    client.send_server_pong(nonce);
+                }
+                let mut nonces = receive_pongs(&server);
+                nonces.sort();
+                (nonces, client.stats().duplicated_sent)
+            })
+            .collect();
+
+        // The same seed duplicates the same packets, and every copy is counted
+        let (nonces, duplicated) = &runs[0];
+        assert_eq!(runs[0], runs[1]);
+        assert!(*duplicated > 5 && *duplicated < 35, "{} duplicated", duplicated);
+        assert_eq!(nonces.len() as u64, 40 + duplicated);
+    }
+
+    #[test]
+    fn test_duplicates_keep_order_without_reorder_window() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::builder(&server.local_addr().unwrap().to_string())
+            .jitter_distribution(JitterDistribution::Normal)
+            .max_reorder_ms(0)
+            .jitter_seed(Some(5))
+            .build()
+            .unwrap();
+        client.set_conditions(0, 20, 0);
+        client.set_duplicate_percent(100);
+        for nonce in 0..10 {
+            client.send_server_pong(nonce);
+        }
+
+        // Each copy is jittered on its own but neither overtakes a packet sent before it
+        let nonces = receive_pongs(&server);
+        assert_eq!(nonces.len(), 20);
+        assert!(nonces.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", nonces);
+        assert_eq!(client.stats().duplicated_sent, 10);
+    }
+
+    #[test]
+    fn test_received_datagrams_are_duplicated() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        let client_addr = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+        client.set_duplicate_percent(100);
+
+        for id in 1..=5 {
+            server.send_to(&crate::snapshot::encode_snapshot(&snapshot(id)), client_addr).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(100));
+
+        // Both copies reach the render thread, telling them apart is left to the snapshot id
+        let frame = client.receive_frame(usize::MAX);
+        assert_eq!(ids(&frame.snapshots), vec![1, 1, 2, 2, 3, 3, 4, 4, 5, 5]);
+        assert_eq!(client.stats().duplicated_received, 5);
+        assert_eq!(client.stats().duplicated_sent, 0);
+    }
+
+    #[test]
+    fn test_received_datagrams_wait_for_jitter() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::builder(&server.local_addr().unwrap().to_string())
+            .jitter_seed(Some(1))
+            .build()
+            .unwrap();
+        let client_addr = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+        client.set_conditions(0, 200, 0);
+
+        for id in 1..=20 {
+            server.send_to(&crate::snapshot::encode_snapshot(&snapshot(id)), client_addr).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+
+        // Most datagrams are held back by up to 200 ms, all of them arrive eventually
+        let mut received = client.receive_frame(20).snapshots.len();
+        assert!(received < 20);
+        std::thread::sleep(Duration::from_millis(250));
+        received += client.receive_frame(20).snapshots.len();
+        assert_eq!(received, 20);
+    }
+
+    #[test]
+    fn test_network_thread_stops_on_drop() {
+        let client = NetworkClient::new("127.0.0.1:8080");
+        let port = client.local_addr().port();
+        assert!(client.is_running());
+
+        let started = Instant::now();
+        drop(client);
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        // The thread took the socket with it, so its port is free again
+        assert!(UdpSocket::bind(("0.0.0.0", port)).is_ok());
+    }
+
+    #[test]
+    fn test_leave_is_sent_with_the_session() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        let token = Uuid::new_v4();
+        client.set_session(Some(token));
+        client.send_leave();
+
+        let mut buf = [0u8; 1024];
+        let (size, _) = server.recv_from(&mut buf).unwrap();
+        match decode(&buf[..size]).unwrap() {
+            ClientMessage::WithSession { session_token, message } => {
+                assert_eq!(session_token, token);
+                assert!(matches!(*message, ClientMessage::Leave));
+            }
+            other => panic!("Expected Leave with the session, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_close_sends_what_is_still_delayed() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        let port = client.local_addr().port();
+        client.set_conditions(5_000, 0, 0);
+
+        // The pong would be held back for seconds, closing sends it at once and frees the socket
+        client.send_server_pong(8);
+        let started = Instant::now();
+        assert!(client.close(Duration::from_secs(1)));
+        let mut buf = [0u8; 1024];
+        let (size, _) = server.recv_from(&mut buf).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(matches!(decode(&buf[..size]).unwrap(), ClientMessage::ServerPong(8)));
+        assert!(UdpSocket::bind(("0.0.0.0", port)).is_ok());
+    }
+
+    #[test]
+    fn test_stalled_render_thread_keeps_newest_snapshots() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        let client_addr = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+
+        // Nothing is taken while the server sends more snapshots than the queue holds
+        let count = INCOMING_SNAPSHOT_QUEUE as u64 + 20;
+        for id in 1..=count {
+            server.send_to(&crate::snapshot::encode_snapshot(&snapshot(id)), client_addr).unwrap();
+        }
+        server.send_to(&encode(&ClientMessage::ServerPing(5)), client_addr).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        // The oldest snapshots made room, control messages are queued apart and kept
+        let frame = client.receive_frame(usize::MAX);
+        assert_eq!(ids(&frame.snapshots), (21..=count).collect::<Vec<_>>());
+        assert!(matches!(frame.messages[..], [ClientMessage::ServerPing(5)]));
+        assert_eq!(client.stats().dropped_snapshots, 20);
+        assert_eq!(client.stats().dropped_messages, 0);
+    }
+
+    #[test]
+    fn test_delayed_sends_are_on_time_while_render_thread_stalls() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut client = NetworkClient::new(&server.local_addr().unwrap().to_string());
+        client.set_conditions(100, 0, 0);
+
+        // No frame runs after the send, the network thread releases the packet by itself
+        let sent = Instant::now();
+        client.send_server_pong(3);
+        let mut buf = [0u8; 1024];
+        let (size, _) = server.recv_from(&mut buf).unwrap();
+        let held = sent.elapsed();
+        assert!(held >= Duration::from_millis(100) && held < Duration::from_millis(150), "held for {:?}", held);
+        assert!(matches!(decode(&buf[..size]).unwrap(), ClientMessage::ServerPong(3)));
+    }
+
+    #[test]
+    fn test_matured_packets_are_paced_in_order() {
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+
+        // Packets maturing together are spread out, starting now or after the packet scheduled last
+        assert_eq!(pace_releases(3, now, None, ms(1)), vec![now, now + ms(1), now + ms(2)]);
+        assert_eq!(pace_releases(2, now, Some(now + ms(4)), ms(1)), vec![now + ms(5), now + ms(6)]);
+        assert_eq!(pace_releases(1, now + ms(10), Some(now + ms(4)), ms(1)), vec![now + ms(10)]);
+
+        // Without spacing everything goes out at once
+        assert_eq!(pace_releases(3, now, Some(now), Duration::ZERO), vec![now; 3]);
+        assert!(pace_releases(0, now, None, ms(1)).is_empty());
+    }
+
+    #[test]
+    fn test_delayed_sends_leave_one_at_a_time() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut client = NetworkClient::builder(&server.local_addr().unwrap().to_string())
+            .send_pacing(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        client.set_conditions(30, 0, 0);
+
+        // The pongs mature together but arrive in the order sent, spaced by the pacing
+        for nonce in 0..4 {
+            client.send_server_pong(nonce);
+        }
+        let mut buf = [0u8; 1024];
+        let mut arrivals = Vec::new();
+        for expected in 0..4 {
+            let (size, _) = server.recv_from(&mut buf).unwrap();
+            arrivals.push(Instant::now());
+            assert!(matches!(decode(&buf[..size]).unwrap(), ClientMessage::ServerPong(nonce) if nonce == expected));
+        }
+        let spread = arrivals[3] - arrivals[0];
+        assert!(spread >= Duration::from_millis(25), "all sent within {:?}", spread);
+        client.receive_frame(5);
+        assert_eq!(client.stats().max_send_burst, 1);
+    }
 }
@@ -0,0 +1,190 @@
+use crate::constants::{OVERLAP_GRID_MIN_PLAYERS, OVERLAP_HIGHLIGHT_MARGIN};
+use crate::minimap::ScreenRect;
+use crate::positions::DisplayPos;
+
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Two players whose drawn squares overlap, the smaller id first
+pub type OverlapPair = (Uuid, Uuid);
+
+/// Pairs of players whose squares of the given size, centered on where they are drawn, overlap, sorted.
+/// Squares that only touch don't overlap. Up to OVERLAP_GRID_MIN_PLAYERS players every pair is checked,
+/// more are put into grid cells one square wide so only players in neighbouring cells are compared.
+pub fn find_overlaps(players: &[(Uuid, DisplayPos)], size: i32) -> Vec<OverlapPair> {
+    let mut pairs = if players.len() < OVERLAP_GRID_MIN_PLAYERS {
+        overlaps_of_every_pair(players, size)
+    } else {
+        overlaps_in_grid(players, size)
+    };
+    pairs.sort();
+    pairs
+}
+
+/// Checks every pair of players
+fn overlaps_of_every_pair(players: &[(Uuid, DisplayPos)], size: i32) -> Vec<OverlapPair> {
+    let mut pairs = Vec::new();
+    for (index, (a, a_position)) in players.iter().enumerate() {
+        for (b, b_position) in &players[index + 1..] {
+            if squares_overlap(*a_position, *b_position, size) {
+                pairs.push(ordered(*a, *b));
+            }
+        }
+    }
+    pairs
+}
+
+/// Buckets the players into cells one square wide. Overlapping squares are less than a square apart on
+/// both axes, so they are in the same or a neighbouring cell, and each pair is compared from its lower index.
+fn overlaps_in_grid(players: &[(Uuid, DisplayPos)], size: i32) -> Vec<OverlapPair> {
+    let size = size.max(1);
+    let cell_of = |position: DisplayPos| {
+        let position = position.layout_position();
+        (position.x.div_euclid(size), position.y.div_euclid(size))
+    };
+    let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+    for (index, (_, position)) in players.iter().enumerate() {
+        cells.entry(cell_of(*position)).or_default().push(index);
+    }
+
+    let mut pairs = Vec::new();
+    for (index, (a, a_position)) in players.iter().enumerate() {
+        let (column, row) = cell_of(*a_position);
+        for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+            let Some(neighbours) = cells.get(&(column + dx, row + dy)) else {
+                continue;
+            };
+            for (b, b_position) in neighbours.iter().filter(|other| **other > index).map(|other| &players[*other]) {
+                if squares_overlap(*a_position, *b_position, size) {
+                    pairs.push(ordered(*a, *b));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// True if squares of the size centered on the positions share more than an edge
+fn squares_overlap(a: DisplayPos, b: DisplayPos, size: i32) -> bool {
+    let (a, b) = (a.layout_position(), b.layout_position());
+    (a.x - b.x).abs() < size && (a.y - b.y).abs() < size
+}
+
+/// The pair with the smaller id first, so a pair is counted the same whichever player was found first
+fn ordered(a: Uuid, b: Uuid) -> OverlapPair {
+    if a <= b { (a, b) } else { (b, a) }
+}
+
+/// Board area around both squares of an overlapping pair as x, y, width and height, with a margin so the
+/// outline doesn't cover the players
+pub fn highlight_rect(a: DisplayPos, b: DisplayPos, size: i32) -> ScreenRect {
+    let half = size as f32 / 2.0 + OVERLAP_HIGHLIGHT_MARGIN;
+    let (left, top) = (a.x().min(b.x()) - half, a.y().min(b.y()) - half);
+    let (right, bottom) = (a.x().max(b.x()) + half, a.y().max(b.y()) + half);
+    (left, top, right - left, bottom - top)
+}
+
+/// Frames each pair of players was drawn overlapping, a proxy for interpolation artifacts: players rarely
+/// stand inside each other, drawn positions that lag or overshoot put them there
+#[derive(Debug, Clone, Default)]
+pub struct OverlapCounter {
+    frames: HashMap<OverlapPair, u64>,
+}
+
+/// Implementation of the OverlapCounter
+impl OverlapCounter {
+    /// Creates a counter without frames
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts one frame for every pair found overlapping in it
+    pub fn record(&mut self, pairs: &[OverlapPair]) {
+        for pair in pairs {
+            *self.frames.entry(*pair).or_insert(0) += 1;
+        }
+    }
+
+    /// Frames the two players were drawn overlapping, in either order
+    pub fn frames_of(&self, a: Uuid, b: Uuid) -> u64 {
+        self.frames.get(&ordered(a, b)).copied().unwrap_or(0)
+    }
+
+    /// Overlap frames summed over every pair
+    pub fn total_frames(&self) -> u64 {
+        self.frames.values().sum()
+    }
+
+    /// Number of pairs that overlapped at least once
+    pub fn pair_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Overlap frames of the pair that overlapped longest, 0 without overlaps
+    pub fn max_frames(&self) -> u64 {
+        self.frames.values().copied().max().unwrap_or(0)
+    }
+
+    /// True if no pair overlapped yet
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+/// Tests for the overlap detection
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Position;
+
+    use rand::{Rng, SeedableRng};
+
+    // Helper function to place a player with the given id number
+    fn player(number: u128, x: i32, y: i32) -> (Uuid, DisplayPos) {
+        (Uuid::from_u128(number), DisplayPos::smoothed(Position { x, y }))
+    }
+
+    #[test]
+    fn test_only_squares_sharing_area_overlap() {
+        let players = [player(3, 100, 100), player(1, 119, 110), player(2, 140, 100), player(4, 300, 300), player(5, 320, 300)];
+        assert_eq!(find_overlaps(&players, 20), vec![(Uuid::from_u128(1), Uuid::from_u128(3))]);
+
+        // The pair is ordered by id, and touching edges aren't an overlap
+        assert_eq!(find_overlaps(&players[1..3], 20), vec![]);
+        assert_eq!(find_overlaps(&[player(9, 0, 0), player(8, 0, 0)], 20), vec![(Uuid::from_u128(8), Uuid::from_u128(9))]);
+    }
+
+    #[test]
+    fn test_grid_finds_the_same_pairs_as_every_pair() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        for count in [OVERLAP_GRID_MIN_PLAYERS, 80, 200] {
+            let players: Vec<(Uuid, DisplayPos)> = (0..count as u128)
+                .map(|number| player(number, rng.random_range(-10..400), rng.random_range(-10..300)))
+                .collect();
+            let mut every_pair = overlaps_of_every_pair(&players, 20);
+            every_pair.sort();
+            assert!(!every_pair.is_empty());
+            assert_eq!(find_overlaps(&players, 20), every_pair, "with {} players", count);
+        }
+    }
+
+    #[test]
+    fn test_overlap_frames_are_counted_per_pair() {
+        let (a, b, c) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let mut counter = OverlapCounter::new();
+        assert!(counter.is_empty());
+        counter.record(&[(a, b)]);
+        counter.record(&[(a, b), (b, c)]);
+        counter.record(&[]);
+        assert_eq!((counter.frames_of(b, a), counter.frames_of(b, c), counter.frames_of(a, c)), (2, 1, 0));
+        assert_eq!((counter.total_frames(), counter.pair_count(), counter.max_frames()), (3, 2, 2));
+    }
+
+    #[test]
+    fn test_highlight_surrounds_both_squares() {
+        let (x, y, width, height) = highlight_rect(DisplayPos::smoothed(Position { x: 110, y: 100 }), DisplayPos::smoothed(Position { x: 100, y: 105 }), 20);
+        let margin = OVERLAP_HIGHLIGHT_MARGIN;
+        assert_eq!((x, y), (90.0 - margin, 90.0 - margin));
+        assert_eq!((width, height), (30.0 + 2.0 * margin, 25.0 + 2.0 * margin));
+    }
+}
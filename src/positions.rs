@@ -0,0 +1,106 @@
+use crate::types::Position;
+
+use std::ops::Deref;
+
+/// Position by the rules of the server: one it sent, or one the prediction worked out by replaying inputs the
+/// way the server moves players. The prediction reconciles and replays from these and only these, and they are
+/// what may be sent to the server. A drawn position can't become one:
+///
+/// ```compile_fail
+/// use netcode_game::positions::{AuthoritativePos, DisplayPos};
+/// use netcode_game::prediction::PredictionState;
+/// use netcode_game::types::Position;
+///
+/// let mut prediction = PredictionState::new(AuthoritativePos::new(Position { x: 100, y: 100 }));
+/// let drawn = DisplayPos::smoothed(Position { x: 104, y: 100 });
+/// prediction.reconcile(drawn, 1, 0.0); // An interpolated or frozen position fed back into the prediction
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuthoritativePos(Position);
+
+/// Implementation of the AuthoritativePos
+impl AuthoritativePos {
+    /// Wraps a position from a server message or one the movement rules produced
+    pub const fn new(position: Position) -> Self {
+        Self(position)
+    }
+
+    /// The raw position, for the movement rules, messages to the server and comparisons with snapshots
+    pub fn position(self) -> Position {
+        self.0
+    }
+
+    /// The position moved by a push the server applied, such as a knockback
+    pub fn moved_by(self, dx: i32, dy: i32) -> Self {
+        Self(Position { x: self.0.x + dx, y: self.0.y + dy })
+    }
+}
+
+/// Reads the raw position, which is what the server sends and moves by. DisplayPos has no such shortcut.
+impl Deref for AuthoritativePos {
+    type Target = Position;
+
+    fn deref(&self) -> &Position {
+        &self.0
+    }
+}
+
+/// Position a player is drawn at: interpolated between snapshots, frozen while fading out, or an authoritative
+/// position shown as it is. Only the renderer and layout such as the mini-map and the camera take these.
+///
+/// ```compile_fail
+/// use netcode_game::positions::{AuthoritativePos, DisplayPos};
+/// use netcode_game::types::Position;
+///
+/// let drawn = DisplayPos::smoothed(Position { x: 104, y: 100 });
+/// let confirmed: AuthoritativePos = drawn.into(); // There is no way back
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayPos(Position);
+
+/// Implementation of the DisplayPos
+impl DisplayPos {
+    /// Draws an authoritative position where it is, such as the predicted local player or a pellet
+    pub fn from_authoritative(position: AuthoritativePos) -> Self {
+        Self(position.0)
+    }
+
+    /// Wraps a position worked out only for drawing, such as an interpolated or frozen one
+    pub const fn smoothed(position: Position) -> Self {
+        Self(position)
+    }
+
+    /// Board x in pixels, for drawing
+    pub fn x(self) -> f32 {
+        self.0.x as f32
+    }
+
+    /// Board y in pixels, for drawing
+    pub fn y(self) -> f32 {
+        self.0.y as f32
+    }
+
+    /// The drawn position on the board, for layout such as scaling it into the mini-map or centering the camera.
+    /// Never a position to predict from or to send.
+    pub fn layout_position(self) -> Position {
+        self.0
+    }
+}
+
+/// Tests for the position types
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions_only_convert_towards_the_screen() {
+        let confirmed = AuthoritativePos::new(Position { x: 120, y: 80 });
+        assert_eq!(confirmed.position(), Position { x: 120, y: 80 });
+
+        // An authoritative position is drawn where it is, a smoothed one only ever gets drawn
+        let drawn = DisplayPos::from_authoritative(confirmed);
+        assert_eq!((drawn.x(), drawn.y()), (120.0, 80.0));
+        assert_eq!(drawn, DisplayPos::smoothed(Position { x: 120, y: 80 }));
+        assert_eq!(DisplayPos::smoothed(Position { x: 5, y: 6 }).layout_position(), Position { x: 5, y: 6 });
+    }
+}
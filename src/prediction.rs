@@ -1,57 +1,137 @@
-use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH, PLAYER_SIZE, PLAYER_SPEED, TOOL_BAR_HEIGHT};
-use crate::types::{Position, PlayerInput, Direction};
+use crate::constants::{MAX_PREDICTION_HISTORY, MAX_REPLAY_RUNS_PER_FRAME, PLAYER_SPEED};
+use crate::constants::{MOVEMENT_SPEED_TIME_CONSTANT, PREDICTION_ERROR_BAD_FACTOR, PREDICTION_ERROR_GOOD_FACTOR, PREDICTION_ERROR_THRESHOLD};
+use crate::interpolation::{InterpolationStrategy, PositionAt};
+use crate::movement::{MovementAccumulator, MovementTunables};
+use crate::positions::{AuthoritativePos, DisplayPos};
+use crate::types::{AppliedImpulse, Position, PlayerInput, Direction, MatchPhase};
+use crate::util::RingHistory;
 
 use std::collections::VecDeque;
 
+/// What the position a predicted input started from came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PredictionOrigin {
+    Input,          // The input before it was predicted from there
+    ReconcileReset, // A reconciliation replayed the pending inputs from the confirmed position
+    Impulse,        // Like ReconcileReset, with a push from the server moving the confirmed position
+}
+
+/// Implementation of the PredictionOrigin
+impl PredictionOrigin {
+    /// Describes the origin for the history report
+    pub fn describe(&self) -> &'static str {
+        match self {
+            PredictionOrigin::Input => "input",
+            PredictionOrigin::ReconcileReset => "reconcile reset",
+            PredictionOrigin::Impulse => "impulse",
+        }
+    }
+}
+
+/// Prediction state right before an input was applied
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PredictedMove {
+    pub sequence: u32,
+    pub position: AuthoritativePos,
+    pub accumulator: MovementAccumulator, // Fractional movement carried into the input
+    pub origin: PredictionOrigin,
+}
+
+/// Where a replay of the pending inputs that didn't fit into one frame goes on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayCursor {
+    pub sequence: u32,              // First pending input not replayed yet
+    pub origin: PredictionOrigin,   // What the position it starts from came from
+}
+
 /// Represents the state of player movement prediction and reconciliation
 pub struct PredictionState {
     pub next_sequence: u32,
     pub pending_inputs: VecDeque<(u32, PlayerInput)>,
-    pub position_history: VecDeque<(u32, Position)>, // (sequence, position)
+    pub position_history: RingHistory<PredictedMove, u32>, // State before each input, keyed by sequence
     pub last_confirmed_sequence: u32,
-    pub last_confirmed_position: Position,
+    pub last_confirmed_position: AuthoritativePos,
+    pub last_confirmed_accumulator: MovementAccumulator, // Fractional movement the server carried after the confirmed input
+    pub speed: f32, // Pixels per input, the same as the server's speed for the player
+    pub tunables: MovementTunables, // Board and movement the server moves the player by
+    pub accumulator: MovementAccumulator, // Fractional movement carried to the next predicted input
     pub last_reconciliation_time: f64,
+    pub match_phase: MatchPhase, // Phase of the latest snapshot, inputs don't move the player while it freezes players
+    pub applied_impulse: Option<AppliedImpulse>, // Latest impulse from the server that the confirmed position includes
+    pub corrected: bool, // A reconciliation snapped the prediction to the server since the last take_correction
+    pub replay: Option<ReplayCursor>, // Replay of the pending inputs still in progress, the position is partway through it
 }
 
 /// Implementation of the PredictionState
 impl PredictionState {
     /// Creates a new PredictionState with the initial position
-    pub fn new(initial_position: Position) -> Self {
+    pub fn new(initial_position: AuthoritativePos) -> Self {
         Self {
             next_sequence: 0,
             pending_inputs: VecDeque::new(),
-            position_history: VecDeque::new(),
+            position_history: RingHistory::new(MAX_PREDICTION_HISTORY, |entry| entry.sequence),
             last_confirmed_sequence: 0,
             last_confirmed_position: initial_position,
+            last_confirmed_accumulator: MovementAccumulator::default(),
+            speed: PLAYER_SPEED as f32,
+            tunables: MovementTunables::default(),
+            accumulator: MovementAccumulator::default(),
             last_reconciliation_time: 0.0,
+            match_phase: MatchPhase::default(),
+            applied_impulse: None,
+            corrected: false,
+            replay: None,
         }
     }
 
     /// Adds a prediction input to the pending inputs queue
-    pub fn apply_prediction(&mut self, input: PlayerInput, current_position: &mut Position) {
+    pub fn apply_prediction(&mut self, input: PlayerInput, current_position: &mut AuthoritativePos) {
+        self.apply_prediction_from(input, current_position, PredictionOrigin::Input);
+    }
+
+    /// Applies a prediction input, recording what the position it starts from came from
+    fn apply_prediction_from(&mut self, input: PlayerInput, current_position: &mut AuthoritativePos, origin: PredictionOrigin) {
+        // A replay still in progress gets to the input in order, applying it now would skip the ones before it
+        if self.replay.is_some() {
+            return;
+        }
+
         // Store the current position before applying the prediction
-        self.position_history.push_back((input.sequence, *current_position));
-        
-        // Apply the movement prediction
-        match input.dir {
-            Direction::Up => current_position.y = current_position.y.saturating_sub(PLAYER_SPEED).max(PLAYER_SIZE),
-            Direction::Down => current_position.y = current_position.y.saturating_add(PLAYER_SPEED).min(BOARD_HEIGHT - (PLAYER_SIZE) - TOOL_BAR_HEIGHT),
-            Direction::Left => current_position.x = current_position.x.saturating_sub(PLAYER_SPEED).max(PLAYER_SIZE),
-            Direction::Right => current_position.x = current_position.x.saturating_add(PLAYER_SPEED).min(BOARD_WIDTH - (PLAYER_SIZE)),
+        let entry = PredictedMove { sequence: input.sequence, position: *current_position, accumulator: self.accumulator, origin };
+        self.position_history.push(entry);
+
+        // The server doesn't move frozen players, so neither does the prediction
+        if !self.match_phase.allows_movement() {
+            return;
         }
+
+        // Apply the movement prediction the way the server moves the player
+        *current_position = AuthoritativePos::new(self.tunables.step(current_position.position(), input.dir, self.speed, &mut self.accumulator));
+    }
+
+    /// Makes the next input in the direction, keeps it pending until the server confirms it and applies it
+    /// to the predicted position
+    pub fn predict_input(&mut self, dir: Direction, timestamp: u64, current_position: &mut AuthoritativePos) -> PlayerInput {
+        let input = PlayerInput { dir, sequence: self.next_sequence, timestamp };
+        self.pending_inputs.push_back((self.next_sequence, input));
+        self.next_sequence += 1;
+        self.apply_prediction(input, current_position);
+        input
     }
 
     /// Reconciles the client state with the server state
-    pub fn reconcile(&mut self, server_position: Position, server_sequence: u32, current_time: f64) {
+    pub fn reconcile(&mut self, server_position: AuthoritativePos, server_sequence: u32, current_time: f64) {
         // If we've received a newer server state
         if server_sequence > self.last_confirmed_sequence {
             // Calculate time since last reconciliation
             let time_since_last = current_time - self.last_reconciliation_time;
             self.last_reconciliation_time = current_time;
 
-            // Update our confirmed state
+            // Update our confirmed state. The server carried what the next input was predicted from.
             self.last_confirmed_sequence = server_sequence;
             self.last_confirmed_position = server_position;
+            self.last_confirmed_accumulator = self.position_history.find(&(server_sequence + 1))
+                .map_or(self.accumulator, |entry| entry.accumulator);
 
             // Remove all pending inputs that have been confirmed
             while let Some((seq, _)) = self.pending_inputs.front() {
@@ -63,13 +143,7 @@ impl PredictionState {
             }
 
             // Remove old position history
-            while let Some((seq, _)) = self.position_history.front() {
-                if *seq <= server_sequence {
-                    self.position_history.pop_front();
-                } else {
-                    break;
-                }
-            }
+            self.position_history.remove_through(&server_sequence);
 
             // If we have a large gap between server and client sequence,
             // or if it's been too long since last reconciliation, be more aggressive
@@ -81,36 +155,362 @@ impl PredictionState {
         }
     }
 
-    /// Reapplies all pending inputs to the current position
-    pub fn reapply_pending_inputs(&mut self, current_position: &mut Position) {
-        // Start from the last confirmed position
+    /// Reconciles like reconcile, also moving the confirmed position by pushes the server applied without an input.
+    /// A snapshot that confirms no new input doesn't replace the confirmed position, so without this the replayed
+    /// inputs would pull the player back to where it was before the push.
+    pub fn reconcile_with_impulse(&mut self, server_position: AuthoritativePos, server_sequence: u32, impulse: Option<AppliedImpulse>, current_time: f64) {
+        if server_sequence > self.last_confirmed_sequence {
+            // The server position already includes the pushes since this input
+            self.reconcile(server_position, server_sequence, current_time);
+            self.applied_impulse = impulse.filter(|impulse| impulse.after_sequence == server_sequence);
+            return;
+        }
+
+        // Only pushes since the confirmed input, newer than the ones already added
+        let Some(impulse) = impulse.filter(|impulse| impulse.after_sequence == self.last_confirmed_sequence) else {
+            return;
+        };
+        let applied = self.applied_impulse.filter(|applied| applied.after_sequence == impulse.after_sequence);
+        if applied.is_some_and(|applied| applied.tick >= impulse.tick) {
+            return;
+        }
+
+        // Pushes of the same input add up, so only the part not added yet is added
+        let (applied_dx, applied_dy) = applied.map_or((0, 0), |applied| (applied.dx, applied.dy));
+        self.last_confirmed_position = self.last_confirmed_position.moved_by(impulse.dx - applied_dx, impulse.dy - applied_dy);
+        self.applied_impulse = Some(impulse);
+    }
+
+    /// Predicted position right before the input with the sequence was applied. Inputs the server already
+    /// confirmed are clamped to the confirmed position, inputs that were never predicted are unavailable.
+    pub fn position_before(&self, sequence: u32) -> PositionAt {
+        if let Some(entry) = self.position_history.find(&sequence) {
+            return PositionAt::Exact(entry.position.position());
+        }
+        if sequence <= self.last_confirmed_sequence {
+            return PositionAt::Clamped(self.last_confirmed_position.position());
+        }
+        PositionAt::Unavailable
+    }
+
+    /// Sets the match phase from the latest snapshot
+    pub fn set_match_phase(&mut self, phase: MatchPhase) {
+        self.match_phase = phase;
+    }
+
+    /// Resets the prediction to a server-assigned position (e.g. after a match reset).
+    /// Pending inputs are dropped, but sequence numbers keep counting up.
+    pub fn reset_to(&mut self, position: AuthoritativePos) {
+        self.pending_inputs.clear();
+        self.position_history.clear();
+        self.last_confirmed_position = position;
+        self.last_confirmed_accumulator.clear();
+        self.accumulator.clear();
+        self.applied_impulse = None;
+        self.replay = None;
+    }
+
+    /// Moves by the tunables of the server from now on, players start with its speed
+    pub fn adopt_tunables(&mut self, tunables: MovementTunables) {
+        self.tunables = tunables;
+        self.speed = tunables.player_speed;
+    }
+
+    /// Snaps the prediction to the server position after an error too large to smooth over, and flags the correction
+    pub fn snap_to(&mut self, server_position: AuthoritativePos) {
+        self.reset_to(server_position);
+        self.corrected = true;
+    }
+
+    /// Whether the prediction was snapped to the server since the last call
+    pub fn take_correction(&mut self) -> bool {
+        std::mem::take(&mut self.corrected)
+    }
+
+    /// Re-initializes the prediction from a Welcome message. For a resumed session, sequences
+    /// continue after the server's last processed input so new inputs aren't discarded as duplicates.
+    pub fn resume(&mut self, position: AuthoritativePos, last_processed: Option<u32>) {
+        self.reset_to(position);
+        match last_processed {
+            Some(sequence) => {
+                self.next_sequence = sequence + 1;
+                self.last_confirmed_sequence = sequence;
+            }
+            None => {
+                self.next_sequence = 0;
+                self.last_confirmed_sequence = 0;
+            }
+        }
+    }
+
+    /// Reapplies the pending inputs to the current position. Runs of inputs in the same direction are moved at
+    /// once, and at most MAX_REPLAY_RUNS_PER_FRAME runs are replayed, continue_replay replays the rest in the
+    /// next frames while the position is drawn partway through.
+    pub fn reapply_pending_inputs(&mut self, current_position: &mut AuthoritativePos) {
+        // Start from the last confirmed position, the replay records the history of the pending inputs again
         *current_position = self.last_confirmed_position;
+        self.accumulator = self.last_confirmed_accumulator;
+        self.position_history.clear();
+
+        // The first input is replayed from the confirmed position, moved by a push if the server applied one
+        let impulse = self.applied_impulse.is_some_and(|impulse| impulse.after_sequence == self.last_confirmed_sequence);
+        let origin = if impulse { PredictionOrigin::Impulse } else { PredictionOrigin::ReconcileReset };
+        self.replay = self.pending_inputs.front().map(|(sequence, _)| ReplayCursor { sequence: *sequence, origin });
+        self.replay_runs(current_position, MAX_REPLAY_RUNS_PER_FRAME);
+    }
 
-        // Collect inputs into a Vec to avoid borrowing issues
-        let inputs: Vec<_> = self.pending_inputs.iter().map(|(_, input)| input.clone()).collect();
-        
-        // Reapply all pending inputs
-        for input in inputs {
-            self.apply_prediction(input, current_position);
+    /// Replays the next runs of a replay that didn't fit into the frame it started in, once per frame
+    pub fn continue_replay(&mut self, current_position: &mut AuthoritativePos) {
+        self.replay_runs(current_position, MAX_REPLAY_RUNS_PER_FRAME);
+    }
+
+    /// Whether a replay is still in progress, the position isn't the prediction of every pending input yet
+    pub fn replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Replays up to the given number of runs from the cursor. A run of inputs in the same direction is moved
+    /// at once if step_repeated can, otherwise, such as at a speed it can't add up exactly, each input is a run.
+    fn replay_runs(&mut self, current_position: &mut AuthoritativePos, runs: usize) {
+        let Some(ReplayCursor { sequence, mut origin }) = self.replay else {
+            return;
+        };
+        let mut index = self.pending_inputs.partition_point(|(pending, _)| *pending < sequence);
+        for _ in 0..runs {
+            let Some((_, first)) = self.pending_inputs.get(index).copied() else {
+                break;
+            };
+            let run: Vec<PlayerInput> = self.pending_inputs.range(index..)
+                .map(|(_, input)| *input)
+                .take_while(|input| input.dir == first.dir)
+                .collect();
+            let (start, start_accumulator) = (current_position.position(), self.accumulator);
+
+            // Frozen players don't move, the whole run starts where the player is
+            if !self.match_phase.allows_movement() {
+                for input in &run {
+                    self.record_replayed(input, start, start_accumulator, &mut origin);
+                }
+                index += run.len();
+                continue;
+            }
+
+            let mut accumulator = start_accumulator;
+            match self.tunables.step_repeated(start, first.dir, self.speed, run.len() as u32, &mut accumulator) {
+                Some(end) => {
+                    // Each input of the run starts where the ones before it in the run moved the player
+                    for (count, input) in run.iter().enumerate() {
+                        let mut before = start_accumulator;
+                        let position = self.tunables.step_repeated(start, first.dir, self.speed, count as u32, &mut before).unwrap_or(start);
+                        self.record_replayed(input, position, before, &mut origin);
+                    }
+                    *current_position = AuthoritativePos::new(end);
+                    self.accumulator = accumulator;
+                    index += run.len();
+                }
+                None => {
+                    self.record_replayed(&first, start, start_accumulator, &mut origin);
+                    *current_position = AuthoritativePos::new(self.tunables.step(start, first.dir, self.speed, &mut self.accumulator));
+                    index += 1;
+                }
+            }
+        }
+        self.replay = self.pending_inputs.get(index).map(|(sequence, _)| ReplayCursor { sequence: *sequence, origin });
+    }
+
+    /// Records the state a replayed input starts from, inputs after the first start from the one before
+    fn record_replayed(&mut self, input: &PlayerInput, position: Position, accumulator: MovementAccumulator, origin: &mut PredictionOrigin) {
+        let entry = PredictedMove { sequence: input.sequence, position: AuthoritativePos::new(position), accumulator, origin: *origin };
+        self.position_history.push(entry);
+        *origin = PredictionOrigin::Input;
+    }
+
+    /// The predicted inputs still in the history, one line each with the position they started from and its origin
+    pub fn history_report(&self) -> String {
+        let mut report = format!("Prediction history since input #{}:\n", self.last_confirmed_sequence);
+        for entry in self.position_history.iter() {
+            report.push_str(&format!("  #{:<6} ({}, {})  {}\n", entry.sequence, entry.position.x, entry.position.y, entry.origin.describe()));
         }
+        if self.position_history.is_empty() {
+            report.push_str("  no pending inputs\n");
+        }
+        report
     }
 
     /// Gets error in prediction by comparing the last confirmed position with the server position
-    pub fn get_prediction_error(&self, server_position: Position) -> f32 {
+    pub fn get_prediction_error(&self, server_position: AuthoritativePos) -> f32 {
         let dx = (server_position.x - self.last_confirmed_position.x) as f32;
         let dy = (server_position.y - self.last_confirmed_position.y) as f32;
         (dx * dx + dy * dy).sqrt()
     }
+
+    /// Estimates where the server has the player now: the confirmed position advanced by the pending inputs sent
+    /// at least half a round trip before now, which have most likely arrived. The time is in seconds on the clock
+    /// the input timestamps were taken from, the round trip time in milliseconds. None until the round trip time is
+    /// known. Inputs still pending long after that are counted as arrived, the server may just not have confirmed
+    /// them yet. Only for drawing, the prediction never starts from it.
+    pub fn estimate_server_view(&self, now: f64, rtt_ms: Option<u32>) -> Option<DisplayPos> {
+        let arrived_before_ms = now * 1000.0 - rtt_ms? as f64 / 2.0;
+        let mut position = self.last_confirmed_position.position();
+        let mut accumulator = self.last_confirmed_accumulator;
+        let arrived = self.pending_inputs.iter().take_while(|(_, input)| input.timestamp as f64 <= arrived_before_ms);
+        for (_, input) in arrived.filter(|_| self.match_phase.allows_movement()) {
+            position = self.tunables.step(position, input.dir, self.speed, &mut accumulator);
+        }
+        Some(DisplayPos::smoothed(position))
+    }
+}
+
+/// How a prediction error compares with the error expected at the current latency and speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Good,     // Well below the expected error
+    Expected, // About what the latency causes
+    Bad,      // Well above the expected error, the prediction is snapped to the server
+}
+
+/// Prediction error in pixels that the latency alone causes: the distance moved during one round trip.
+/// Never below PREDICTION_ERROR_THRESHOLD, which is also used while the round trip time is still unknown.
+pub fn expected_error_baseline(rtt_ms: Option<u32>, speed_px_per_s: f32) -> f32 {
+    let Some(rtt_ms) = rtt_ms else {
+        return PREDICTION_ERROR_THRESHOLD;
+    };
+    (speed_px_per_s.max(0.0) * rtt_ms as f32 / 1000.0).max(PREDICTION_ERROR_THRESHOLD)
+}
+
+/// Classifies a prediction error relative to the expected error baseline
+pub fn classify_prediction_error(error: f32, baseline: f32) -> ErrorClass {
+    if error <= baseline * PREDICTION_ERROR_GOOD_FACTOR {
+        ErrorClass::Good
+    } else if error <= baseline * PREDICTION_ERROR_BAD_FACTOR {
+        ErrorClass::Expected
+    } else {
+        ErrorClass::Bad
+    }
+}
+
+/// How a client predicts its own player and smooths the others, the defaults are what every client does.
+/// The mirroring mode runs two connections with different configurations next to each other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PredictionConfig {
+    pub predict: bool,    // False draws the local player from the snapshots like a remote player
+    pub snap_factor: f32, // Errors above this multiple of the expected error snap the prediction to the server
+    pub watchdog: bool,   // Whether the watchdog may turn the prediction off when it keeps going wrong
+    pub interpolation: InterpolationStrategy, // How players drawn from the snapshots are played back
+}
+
+/// Default implementation for the PredictionConfig
+impl Default for PredictionConfig {
+    fn default() -> Self {
+        Self { predict: true, snap_factor: PREDICTION_ERROR_BAD_FACTOR, watchdog: true, interpolation: InterpolationStrategy::default() }
+    }
+}
+
+/// Implementation of the PredictionConfig
+impl PredictionConfig {
+    /// Parses comma separated settings over the defaults, such as `predict=off` or `snap=3,interpolation=hermite`.
+    /// The settings are predict and watchdog (on or off), snap (a factor above zero) and interpolation (a strategy
+    /// name). An empty text is the defaults, an unknown setting or value fails with what to fix.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        for setting in text.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+            let Some((key, value)) = setting.split_once('=') else {
+                return Err(format!("{} is not a setting, use key=value", setting));
+            };
+            let switch = |value: &str| match value.trim() {
+                "on" | "true" => Ok(true),
+                "off" | "false" => Ok(false),
+                other => Err(format!("{} of {} is not on or off", other, key.trim())),
+            };
+            match key.trim() {
+                "predict" => config.predict = switch(value)?,
+                "watchdog" => config.watchdog = switch(value)?,
+                "snap" => {
+                    config.snap_factor = value.trim().parse().ok().filter(|factor: &f32| *factor > 0.0)
+                        .ok_or_else(|| format!("snap {} is not a factor above zero", value.trim()))?;
+                }
+                "interpolation" => {
+                    config.interpolation = InterpolationStrategy::parse(value)
+                        .ok_or_else(|| format!("unknown interpolation strategy {}", value.trim()))?;
+                }
+                other => return Err(format!("unknown setting {}, use predict, snap, watchdog or interpolation", other)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Short description of where the config differs from the defaults, "default" where it doesn't
+    pub fn describe(&self) -> String {
+        let defaults = Self::default();
+        let mut parts = Vec::new();
+        if !self.predict {
+            parts.push("no prediction".to_string());
+        }
+        if self.snap_factor != defaults.snap_factor {
+            parts.push(format!("snap {}x", self.snap_factor));
+        }
+        if !self.watchdog {
+            parts.push("no watchdog".to_string());
+        }
+        if self.interpolation != defaults.interpolation {
+            parts.push(self.interpolation.name().to_string());
+        }
+        if parts.is_empty() { "default".to_string() } else { parts.join(", ") }
+    }
+
+    /// True if a prediction error this large for the expected error snaps the prediction to the server
+    pub fn snaps(&self, error: f32, baseline: f32) -> bool {
+        error > baseline * self.snap_factor
+    }
+}
+
+/// Smoothed speed of the local player in pixels per second
+#[derive(Debug, Clone, Default)]
+pub struct MovementSpeed {
+    speed: f32,
+    last_position: Option<Position>,
+}
+
+/// Implementation of the MovementSpeed
+impl MovementSpeed {
+    /// Adds the position after a frame of dt seconds and returns the smoothed speed
+    pub fn update(&mut self, position: Position, dt: f32) -> f32 {
+        if let Some(last) = self.last_position.filter(|_| dt > 0.0) {
+            let dx = (position.x - last.x) as f32;
+            let dy = (position.y - last.y) as f32;
+            let frame_speed = (dx * dx + dy * dy).sqrt() / dt;
+            let weight = 1.0 - (-dt / MOVEMENT_SPEED_TIME_CONSTANT).exp();
+            self.speed += (frame_speed - self.speed) * weight;
+        }
+        self.last_position = Some(position);
+        self.speed
+    }
+
+    /// The smoothed speed in pixels per second
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Forgets the last position, so a teleport such as a respawn isn't counted as movement
+    pub fn reset(&mut self) {
+        self.last_position = None;
+    }
 }
 
 /// Tests for the PredictionState
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH, PLAYER_SIZE, TOOL_BAR_HEIGHT};
+
+    // Helper function to create the state before a predicted input at the position
+    fn predicted(sequence: u32, x: i32, y: i32) -> PredictedMove {
+        PredictedMove { sequence, position: AuthoritativePos::new(Position { x, y }), accumulator: MovementAccumulator::default(), origin: PredictionOrigin::Input }
+    }
 
     #[test]
     fn test_new_prediction_state() {
-        let initial_position = Position { x: 100, y: 100 };
+        let initial_position = AuthoritativePos::new(Position { x: 100, y: 100 });
         let state = PredictionState::new(initial_position);
 
         assert_eq!(state.next_sequence, 0);
@@ -124,7 +524,7 @@ mod tests {
 
     #[test]
     fn test_apply_prediction_up() {
-        let initial_position = Position { x: 100, y: 100 };
+        let initial_position = AuthoritativePos::new(Position { x: 100, y: 100 });
         let mut state = PredictionState::new(initial_position);
         let mut position = initial_position;
 
@@ -139,14 +539,14 @@ mod tests {
         assert_eq!(position.x, initial_position.x);
         assert_eq!(position.y, initial_position.y - PLAYER_SPEED);
         assert_eq!(state.position_history.len(), 1);
-        assert_eq!(state.position_history[0].0, 0);  // sequence
-        assert_eq!(state.position_history[0].1.x, initial_position.x);  // original position
-        assert_eq!(state.position_history[0].1.y, initial_position.y);
+        assert_eq!(state.position_history[0].sequence, 0);  // sequence
+        assert_eq!(state.position_history[0].position.x, initial_position.x);  // original position
+        assert_eq!(state.position_history[0].position.y, initial_position.y);
     }
 
     #[test]
     fn test_apply_prediction_down() {
-        let initial_position = Position { x: 100, y: 100 };
+        let initial_position = AuthoritativePos::new(Position { x: 100, y: 100 });
         let mut state = PredictionState::new(initial_position);
         let mut position = initial_position;
 
@@ -161,12 +561,12 @@ mod tests {
         assert_eq!(position.x, initial_position.x);
         assert_eq!(position.y, initial_position.y + PLAYER_SPEED);
         assert_eq!(state.position_history.len(), 1);
-        assert_eq!(state.position_history[0].0, 1);  // sequence
+        assert_eq!(state.position_history[0].sequence, 1);  // sequence
     }
 
     #[test]
     fn test_apply_prediction_left() {
-        let initial_position = Position { x: 100, y: 100 };
+        let initial_position = AuthoritativePos::new(Position { x: 100, y: 100 });
         let mut state = PredictionState::new(initial_position);
         let mut position = initial_position;
 
@@ -181,12 +581,12 @@ mod tests {
         assert_eq!(position.x, initial_position.x - PLAYER_SPEED);
         assert_eq!(position.y, initial_position.y);
         assert_eq!(state.position_history.len(), 1);
-        assert_eq!(state.position_history[0].0, 2);  // sequence
+        assert_eq!(state.position_history[0].sequence, 2);  // sequence
     }
 
     #[test]
     fn test_apply_prediction_right() {
-        let initial_position = Position { x: 100, y: 100 };
+        let initial_position = AuthoritativePos::new(Position { x: 100, y: 100 });
         let mut state = PredictionState::new(initial_position);
         let mut position = initial_position;
 
@@ -201,37 +601,37 @@ mod tests {
         assert_eq!(position.x, initial_position.x + PLAYER_SPEED);
         assert_eq!(position.y, initial_position.y);
         assert_eq!(state.position_history.len(), 1);
-        assert_eq!(state.position_history[0].0, 3);  // sequence
+        assert_eq!(state.position_history[0].sequence, 3);  // sequence
     }
 
     #[test]
     fn test_prediction_boundary_limits() {
         // Test hitting the left boundary
-        let mut state = PredictionState::new(Position { x: PLAYER_SIZE + 1, y: 100 });
-        let mut position = Position { x: PLAYER_SIZE + 1, y: 100 };
+        let mut state = PredictionState::new(AuthoritativePos::new(Position { x: PLAYER_SIZE + 1, y: 100 }));
+        let mut position = AuthoritativePos::new(Position { x: PLAYER_SIZE + 1, y: 100 });
 
         state.apply_prediction(PlayerInput { dir: Direction::Left, sequence: 1, timestamp: 0 }, &mut position);
         assert_eq!(position.x, PLAYER_SIZE);  // Should stop at boundary
 
         // Test hitting the right boundary
-        position = Position { x: BOARD_WIDTH - PLAYER_SIZE - 1, y: 100 };
+        position = AuthoritativePos::new(Position { x: BOARD_WIDTH - PLAYER_SIZE - 1, y: 100 });
         state.apply_prediction(PlayerInput { dir: Direction::Right, sequence: 2, timestamp: 0 }, &mut position);
         assert_eq!(position.x, BOARD_WIDTH - PLAYER_SIZE);  // Should stop at boundary
 
         // Test hitting the top boundary
-        position = Position { x: 100, y: PLAYER_SIZE + 1 };
+        position = AuthoritativePos::new(Position { x: 100, y: PLAYER_SIZE + 1 });
         state.apply_prediction(PlayerInput { dir: Direction::Up, sequence: 3, timestamp: 0 }, &mut position);
         assert_eq!(position.y, PLAYER_SIZE);  // Should stop at boundary
 
         // Test hitting the bottom boundary
-        position = Position { x: 100, y: BOARD_HEIGHT - PLAYER_SIZE - TOOL_BAR_HEIGHT - 1 };
+        position = AuthoritativePos::new(Position { x: 100, y: BOARD_HEIGHT - PLAYER_SIZE - TOOL_BAR_HEIGHT - 1 });
         state.apply_prediction(PlayerInput { dir: Direction::Down, sequence: 4, timestamp: 0 }, &mut position);
         assert_eq!(position.y, BOARD_HEIGHT - PLAYER_SIZE - TOOL_BAR_HEIGHT);  // Should stop at boundary
     }
 
     #[test]
     fn test_reconcile_normal_case() {
-        let initial_position = Position { x: 100, y: 100 };
+        let initial_position = AuthoritativePos::new(Position { x: 100, y: 100 });
         let mut state = PredictionState::new(initial_position);
 
         // Initialize last_reconciliation_time to avoid the time-based aggressive clean
@@ -243,12 +643,12 @@ mod tests {
         state.pending_inputs.push_back((3, PlayerInput { dir: Direction::Right, sequence: 3, timestamp: 0 }));
 
         // Add position history
-        state.position_history.push_back((1, Position { x: 100, y: 100 }));
-        state.position_history.push_back((2, Position { x: 100, y: 90 }));
-        state.position_history.push_back((3, Position { x: 90, y: 90 }));
+        state.position_history.push(predicted(1, 100, 100));
+        state.position_history.push(predicted(2, 100, 90));
+        state.position_history.push(predicted(3, 90, 90));
 
         // Server confirms up to sequence 2
-        let server_position = Position { x: 95, y: 85 };  // Slightly different from client's prediction
+        let server_position = AuthoritativePos::new(Position { x: 95, y: 85 });  // Slightly different from client's prediction
         state.reconcile(server_position, 2, 1.0);
 
         // Check state after reconciliation
@@ -258,14 +658,35 @@ mod tests {
         assert_eq!(state.pending_inputs.len(), 1);  // Only sequence 3 should remain
         assert_eq!(state.pending_inputs[0].0, 3);
         assert_eq!(state.position_history.len(), 1);  // Only sequence 3 position should remain
-        assert_eq!(state.position_history[0].0, 3);
+        assert_eq!(state.position_history[0].sequence, 3);
+    }
+
+    #[test]
+    fn test_position_before_an_input() {
+        let mut state = PredictionState::new(AuthoritativePos::new(Position { x: 100, y: 100 }));
+        state.last_reconciliation_time = 0.8;
+        let mut position = AuthoritativePos::new(Position { x: 100, y: 100 });
+        for _ in 0..3 {
+            state.predict_input(Direction::Right, 0, &mut position);
+        }
+
+        // Every predicted input has the position it started from, later ones weren't made yet
+        assert_eq!(state.position_before(0), PositionAt::Exact(Position { x: 100, y: 100 }));
+        assert_eq!(state.position_before(2), PositionAt::Exact(Position { x: 100 + 2 * PLAYER_SPEED, y: 100 }));
+        assert_eq!(state.position_before(3), PositionAt::Unavailable);
+
+        // Confirmed inputs leave the history, the confirmed position is the closest that is still known
+        let confirmed = AuthoritativePos::new(Position { x: 100 + 2 * PLAYER_SPEED, y: 100 });
+        state.reconcile(confirmed, 1, 1.0);
+        assert_eq!(state.position_before(0), PositionAt::Clamped(confirmed.position()));
+        assert_eq!(state.position_before(2), PositionAt::Exact(Position { x: 100 + 2 * PLAYER_SPEED, y: 100 }));
     }
 
     #[test]
     fn test_reapply_pending_inputs() {
-        let initial_position = Position { x: 100, y: 100 };
+        let initial_position = AuthoritativePos::new(Position { x: 100, y: 100 });
         let mut state = PredictionState::new(initial_position);
-        let mut current_position = Position { x: 200, y: 200 };  // Intentionally different
+        let mut current_position = AuthoritativePos::new(Position { x: 200, y: 200 });  // Intentionally different
 
         // Add pending inputs: right, right, down
         state.pending_inputs.push_back((1, PlayerInput { dir: Direction::Right, sequence: 1, timestamp: 0 }));
@@ -284,16 +705,337 @@ mod tests {
         assert_eq!(current_position.y, expected_y);
     }
 
+    #[test]
+    fn test_reconcile_with_impulse() {
+        let mut state = PredictionState::new(AuthoritativePos::new(Position { x: 100, y: 100 }));
+        state.last_reconciliation_time = 0.9;
+        let mut position = AuthoritativePos::new(Position { x: 100, y: 100 });
+
+        // Inputs 0 to 3 move right, the server has applied 0 and 1 when the player is pushed
+        for sequence in 0..4 {
+            let input = PlayerInput { dir: Direction::Right, sequence, timestamp: 0 };
+            state.pending_inputs.push_back((sequence, input));
+            state.apply_prediction(input, &mut position);
+        }
+        state.reconcile_with_impulse(AuthoritativePos::new(Position { x: 110, y: 100 }), 1, None, 1.0);
+
+        // Pushed up by 3 after input 1, before inputs 2 and 3 arrive at the server
+        let impulse = AppliedImpulse { dx: 0, dy: -3, after_sequence: 1, tick: 20 };
+        state.reconcile_with_impulse(AuthoritativePos::new(Position { x: 110, y: 97 }), 1, Some(impulse), 1.0);
+        state.reapply_pending_inputs(&mut position);
+        assert_eq!(state.last_confirmed_position, AuthoritativePos::new(Position { x: 110, y: 97 }));
+        assert_eq!(position, AuthoritativePos::new(Position { x: 120, y: 97 })); // Where the server ends up after inputs 2 and 3
+
+        // The same impulse repeated in the next snapshots isn't added again, a further push only adds its own part
+        state.reconcile_with_impulse(AuthoritativePos::new(Position { x: 110, y: 97 }), 1, Some(impulse), 1.0);
+        assert_eq!(state.last_confirmed_position, AuthoritativePos::new(Position { x: 110, y: 97 }));
+        let impulse = AppliedImpulse { dx: 0, dy: -6, after_sequence: 1, tick: 21 };
+        state.reconcile_with_impulse(AuthoritativePos::new(Position { x: 110, y: 94 }), 1, Some(impulse), 1.0);
+        assert_eq!(state.last_confirmed_position, AuthoritativePos::new(Position { x: 110, y: 94 }));
+
+        // A snapshot confirming input 3 already includes the push
+        state.reconcile_with_impulse(AuthoritativePos::new(Position { x: 120, y: 94 }), 3, Some(impulse), 1.0);
+        assert_eq!(state.last_confirmed_position, AuthoritativePos::new(Position { x: 120, y: 94 }));
+        assert_eq!(state.applied_impulse, None);
+        state.reconcile_with_impulse(AuthoritativePos::new(Position { x: 120, y: 94 }), 3, Some(impulse), 1.0);
+        assert_eq!(state.last_confirmed_position, AuthoritativePos::new(Position { x: 120, y: 94 }));
+    }
+
+    #[test]
+    fn test_history_tags_the_origin_of_every_prediction() {
+        let mut state = PredictionState::new(AuthoritativePos::new(Position { x: 100, y: 100 }));
+        state.last_reconciliation_time = 0.9;
+        let mut position = AuthoritativePos::new(Position { x: 100, y: 100 });
+        for _ in 0..4 {
+            state.predict_input(Direction::Right, 0, &mut position);
+        }
+        let origins = |state: &PredictionState| state.position_history.iter().map(|entry| (entry.sequence, entry.origin)).collect::<Vec<_>>();
+        assert!(origins(&state).iter().all(|(_, origin)| *origin == PredictionOrigin::Input));
+
+        // The server confirms input 1 somewhere else, the replay starts from its position
+        state.reconcile_with_impulse(AuthoritativePos::new(Position { x: 110, y: 105 }), 1, None, 1.0);
+        state.reapply_pending_inputs(&mut position);
+        assert_eq!(origins(&state), vec![(2, PredictionOrigin::ReconcileReset), (3, PredictionOrigin::Input)]);
+        assert_eq!(state.position_history[0].position, AuthoritativePos::new(Position { x: 110, y: 105 }));
+
+        // A push after input 1 moves the confirmed position the replay starts from
+        let impulse = AppliedImpulse { dx: -4, dy: 0, after_sequence: 1, tick: 30 };
+        state.reconcile_with_impulse(AuthoritativePos::new(Position { x: 106, y: 105 }), 1, Some(impulse), 1.0);
+        state.reapply_pending_inputs(&mut position);
+        state.predict_input(Direction::Up, 0, &mut position);
+        assert_eq!(origins(&state), vec![(2, PredictionOrigin::Impulse), (3, PredictionOrigin::Input), (4, PredictionOrigin::Input)]);
+
+        let report = state.history_report();
+        assert!(report.starts_with("Prediction history since input #1:\n  #2      (106, 105)  impulse\n"), "{}", report);
+        state.reset_to(position);
+        assert_eq!(state.history_report(), "Prediction history since input #1:\n  no pending inputs\n");
+    }
+
+    #[test]
+    fn test_reset_to() {
+        let mut state = PredictionState::new(AuthoritativePos::new(Position { x: 100, y: 100 }));
+        state.next_sequence = 10;
+        state.last_confirmed_sequence = 7;
+        state.pending_inputs.push_back((8, PlayerInput { dir: Direction::Up, sequence: 8, timestamp: 0 }));
+        state.position_history.push(predicted(8, 100, 100));
+
+        state.reset_to(AuthoritativePos::new(Position { x: 300, y: 400 }));
+
+        // Pending work is dropped and the new position becomes the confirmed base
+        assert!(state.pending_inputs.is_empty());
+        assert!(state.position_history.is_empty());
+        assert_eq!(state.last_confirmed_position, AuthoritativePos::new(Position { x: 300, y: 400 }));
+
+        // Sequence numbers are untouched
+        assert_eq!(state.next_sequence, 10);
+        assert_eq!(state.last_confirmed_sequence, 7);
+
+        let mut position = AuthoritativePos::new(Position { x: 0, y: 0 });
+        state.reapply_pending_inputs(&mut position);
+        assert_eq!(position, AuthoritativePos::new(Position { x: 300, y: 400 }));
+    }
+
+    #[test]
+    fn test_snap_is_flagged_once() {
+        let mut state = PredictionState::new(AuthoritativePos::new(Position { x: 100, y: 100 }));
+        state.reset_to(AuthoritativePos::new(Position { x: 200, y: 200 }));
+        assert!(!state.take_correction());
+
+        // Only a snap counts as a correction, and it is taken once
+        state.snap_to(AuthoritativePos::new(Position { x: 300, y: 400 }));
+        assert_eq!(state.last_confirmed_position, AuthoritativePos::new(Position { x: 300, y: 400 }));
+        assert!(state.take_correction());
+        assert!(!state.take_correction());
+    }
+
+    #[test]
+    fn test_resume_session() {
+        let mut state = PredictionState::new(AuthoritativePos::new(Position { x: 100, y: 100 }));
+        state.next_sequence = 3;
+        state.pending_inputs.push_back((2, PlayerInput { dir: Direction::Up, sequence: 2, timestamp: 0 }));
+
+        // Resumed session continues after the server's last processed input
+        state.resume(AuthoritativePos::new(Position { x: 50, y: 60 }), Some(500));
+        assert_eq!(state.next_sequence, 501);
+        assert_eq!(state.last_confirmed_sequence, 500);
+        assert_eq!(state.last_confirmed_position, AuthoritativePos::new(Position { x: 50, y: 60 }));
+        assert!(state.pending_inputs.is_empty());
+
+        // Fresh session starts from zero
+        state.resume(AuthoritativePos::new(Position { x: 70, y: 80 }), None);
+        assert_eq!(state.next_sequence, 0);
+        assert_eq!(state.last_confirmed_sequence, 0);
+        assert_eq!(state.last_confirmed_position, AuthoritativePos::new(Position { x: 70, y: 80 }));
+    }
+
     #[test]
     fn test_prediction_error_calculation() {
-        let initial_position = Position { x: 100, y: 100 };
+        let initial_position = AuthoritativePos::new(Position { x: 100, y: 100 });
         let state = PredictionState::new(initial_position);
 
         // Test with position offset by 3 horizontally and 4 vertically (5 units total distance)
-        let server_position = Position { x: 103, y: 104 };
+        let server_position = AuthoritativePos::new(Position { x: 103, y: 104 });
         let error = state.get_prediction_error(server_position);
 
         // Error should be sqrt(3^2 + 4^2) = 5.0
         assert_eq!(error, 5.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_server_view_counts_the_inputs_that_have_probably_arrived() {
+        let mut state = PredictionState::new(AuthoritativePos::new(Position { x: 100, y: 100 }));
+        let at = |x, y| Some(DisplayPos::smoothed(Position { x, y }));
+
+        // Nothing to estimate from before the round trip time is known, nothing pending is the confirmed position
+        assert_eq!(state.estimate_server_view(1.0, None), None);
+        assert_eq!(state.estimate_server_view(1.0, Some(100)), at(100, 100));
+
+        // Inputs sent at 1.00 s, 1.04 s and 1.08 s, half of a 100 ms round trip later only the first has arrived
+        let mut position = state.last_confirmed_position;
+        for timestamp in [1000, 1040, 1080] {
+            state.predict_input(Direction::Right, timestamp, &mut position);
+        }
+        assert_eq!(state.estimate_server_view(1.05, Some(100)), at(100 + PLAYER_SPEED, 100));
+        assert_eq!(state.estimate_server_view(1.10, Some(100)), at(100 + 2 * PLAYER_SPEED, 100));
+        assert_eq!(state.estimate_server_view(1.05, Some(0)), at(100 + 2 * PLAYER_SPEED, 100));
+
+        // Inputs long overdue are still counted, the estimate never passes the prediction
+        assert_eq!(state.estimate_server_view(5.0, Some(100)), Some(DisplayPos::from_authoritative(position)));
+
+        // A confirmation moves the start, the server doesn't move frozen players
+        state.last_reconciliation_time = 0.9;
+        state.reconcile(AuthoritativePos::new(Position { x: 100 + 2 * PLAYER_SPEED, y: 100 }), 1, 1.1);
+        assert_eq!(state.estimate_server_view(1.10, Some(100)), at(100 + 2 * PLAYER_SPEED, 100));
+        assert_eq!(state.estimate_server_view(5.0, Some(100)), at(100 + 3 * PLAYER_SPEED, 100));
+        state.set_match_phase(MatchPhase::Ended);
+        assert_eq!(state.estimate_server_view(5.0, Some(100)), at(100 + 2 * PLAYER_SPEED, 100));
+    }
+
+    #[test]
+    fn test_expected_error_baseline() {
+        // Unknown round trip time during warmup, and standing still, use the fixed threshold
+        assert_eq!(expected_error_baseline(None, 300.0), PREDICTION_ERROR_THRESHOLD);
+        assert_eq!(expected_error_baseline(Some(200), 0.0), PREDICTION_ERROR_THRESHOLD);
+        assert_eq!(expected_error_baseline(Some(0), 300.0), PREDICTION_ERROR_THRESHOLD);
+
+        // Otherwise it is the distance moved during one round trip
+        assert_eq!(expected_error_baseline(Some(100), 300.0), 30.0);
+        assert_eq!(expected_error_baseline(Some(200), 300.0), 60.0);
+        assert_eq!(expected_error_baseline(Some(200), 150.0), 30.0);
+        assert_eq!(expected_error_baseline(Some(10), 300.0), PREDICTION_ERROR_THRESHOLD); // 3 px is below the floor
+    }
+
+    #[test]
+    fn test_prediction_config_parses_over_the_defaults() {
+        assert_eq!(PredictionConfig::parse(""), Ok(PredictionConfig::default()));
+        assert_eq!(PredictionConfig::default().describe(), "default");
+
+        let config = PredictionConfig::parse(" snap=3, interpolation=Hermite ,watchdog=off").unwrap();
+        assert_eq!(config, PredictionConfig { predict: true, snap_factor: 3.0, watchdog: false, interpolation: InterpolationStrategy::Hermite });
+        assert_eq!(config.describe(), "snap 3x, no watchdog, hermite");
+        assert_eq!(PredictionConfig::parse("predict=off").unwrap().describe(), "no prediction");
+
+        // The snap factor moves the error that snaps, the default snaps where the classification says bad
+        assert!(!config.snaps(80.0, 30.0) && config.snaps(91.0, 30.0));
+        assert_eq!(PredictionConfig::default().snaps(46.0, 30.0), classify_prediction_error(46.0, 30.0) == ErrorClass::Bad);
+
+        assert_eq!(PredictionConfig::parse("snap=0"), Err("snap 0 is not a factor above zero".to_string()));
+        assert_eq!(PredictionConfig::parse("predict=maybe"), Err("maybe of predict is not on or off".to_string()));
+        assert_eq!(PredictionConfig::parse("smoothing"), Err("smoothing is not a setting, use key=value".to_string()));
+        assert!(PredictionConfig::parse("interpolation=cubic").is_err() && PredictionConfig::parse("speed=2").is_err());
+    }
+
+    #[test]
+    fn test_error_classification_bands() {
+        let cases = [
+            // (rtt, speed, error, class)
+            (Some(20), 300.0, 2.0, ErrorClass::Good),
+            (Some(20), 300.0, 6.0, ErrorClass::Expected),
+            (Some(20), 300.0, 12.0, ErrorClass::Bad),
+            (Some(200), 300.0, 12.0, ErrorClass::Good), // Bad at 20 ms, but normal at 200 ms
+            (Some(200), 300.0, 60.0, ErrorClass::Expected),
+            (Some(200), 300.0, 91.0, ErrorClass::Bad),
+            (Some(200), 0.0, 12.0, ErrorClass::Bad), // Standing still there should be no error
+            (None, 300.0, 12.0, ErrorClass::Bad),
+        ];
+        for (rtt, speed, error, class) in cases {
+            let baseline = expected_error_baseline(rtt, speed);
+            assert_eq!(classify_prediction_error(error, baseline), class, "rtt {:?}, speed {}, error {}", rtt, speed, error);
+        }
+
+        // The band edges belong to the better class
+        assert_eq!(classify_prediction_error(5.0, 10.0), ErrorClass::Good);
+        assert_eq!(classify_prediction_error(15.0, 10.0), ErrorClass::Expected);
+    }
+
+    #[test]
+    fn test_movement_speed_follows_movement() {
+        let mut speed = MovementSpeed::default();
+        let dt = 1.0 / 60.0;
+        let mut position = Position { x: 100, y: 100 };
+        assert_eq!(speed.update(position, dt), 0.0);
+
+        // Moving 5 px every frame is 300 px/s, the estimate settles there after about a second
+        for _ in 0..60 {
+            position.x += 5;
+            speed.update(position, dt);
+        }
+        assert!((speed.speed() - 300.0).abs() < 10.0, "speed {}", speed.speed());
+
+        // A respawn after a reset is not a jump in speed
+        speed.reset();
+        let settled = speed.speed();
+        assert_eq!(speed.update(Position { x: 900, y: 600 }, dt), settled);
+    }
+
+    #[test]
+    fn test_no_movement_while_frozen() {
+        let spawn = AuthoritativePos::new(Position { x: 100, y: 100 });
+        let mut state = PredictionState::new(spawn);
+        let mut position = spawn;
+        let input = |sequence| PlayerInput { dir: Direction::Right, sequence, timestamp: 0 };
+
+        // Inputs during the countdown keep the player at its spawn
+        state.set_match_phase(MatchPhase::Countdown { remaining_ms: 3000 });
+        state.apply_prediction(input(0), &mut position);
+        assert_eq!(position, spawn);
+        assert_eq!(state.position_history.len(), 1);
+
+        // Once the match is active the same input moves the player
+        state.set_match_phase(MatchPhase::Active);
+        state.apply_prediction(input(1), &mut position);
+        assert_eq!(position, AuthoritativePos::new(Position { x: 100 + PLAYER_SPEED, y: 100 }));
+
+        // Reapplying after the match ended doesn't move either
+        state.set_match_phase(MatchPhase::Ended);
+        state.pending_inputs.push_back((2, input(2)));
+        state.last_confirmed_position = spawn;
+        state.reapply_pending_inputs(&mut position);
+        assert_eq!(position, spawn);
+    }
+
+    // Helper function to predict inputs one step at a time, returning the position and the history they left
+    fn predict_all(state: &mut PredictionState, dirs: &[Direction], position: &mut AuthoritativePos) -> Vec<(u32, AuthoritativePos, MovementAccumulator)> {
+        for dir in dirs {
+            state.predict_input(*dir, 0, position);
+        }
+        state.position_history.iter().map(|entry| (entry.sequence, entry.position, entry.accumulator)).collect()
+    }
+
+    #[test]
+    fn test_collapsed_replay_matches_stepping_every_input() {
+        // Runs of up to six inputs, one of them running into the left edge of the board
+        let dirs: Vec<Direction> = [(Direction::Right, 3), (Direction::Down, 6), (Direction::Left, 40), (Direction::Up, 1), (Direction::Right, 5)]
+            .into_iter()
+            .flat_map(|(dir, count)| std::iter::repeat_n(dir, count))
+            .collect();
+        for speed in [5.0, 2.5, 0.75, 2.3] {
+            let spawn = AuthoritativePos::new(Position { x: 100, y: 100 });
+            let mut state = PredictionState::new(spawn);
+            state.speed = speed;
+            let mut position = spawn;
+            let stepped = predict_all(&mut state, &dirs, &mut position);
+            let (stepped_position, stepped_accumulator) = (position, state.accumulator);
+
+            // The replay from the same confirmed state ends up at the same place with the same history
+            let mut replayed = AuthoritativePos::new(Position { x: 0, y: 0 });
+            state.reapply_pending_inputs(&mut replayed);
+            assert!(!state.replaying());
+            assert_eq!((replayed, state.accumulator), (stepped_position, stepped_accumulator), "at speed {}", speed);
+            let history: Vec<_> = state.position_history.iter().map(|entry| (entry.sequence, entry.position, entry.accumulator)).collect();
+            assert_eq!(history, stepped, "at speed {}", speed);
+        }
+    }
+
+    #[test]
+    fn test_long_replay_is_spread_over_frames() {
+        // Turning every other input gives a run per two inputs, too many to replay in one frame
+        let dirs: Vec<Direction> = (0..MAX_REPLAY_RUNS_PER_FRAME * 3).map(|i| if i % 2 == 0 { Direction::Right } else { Direction::Down }).collect();
+        let spawn = AuthoritativePos::new(Position { x: 100, y: 100 });
+        let mut state = PredictionState::new(spawn);
+        state.speed = 1.0;
+        let mut position = spawn;
+        let stepped = predict_all(&mut state, &dirs, &mut position);
+
+        // The first frame replays part of the inputs, the player is drawn partway along them
+        let mut replayed = position;
+        state.reapply_pending_inputs(&mut replayed);
+        assert!(state.replaying());
+        assert_eq!(state.position_history.len(), MAX_REPLAY_RUNS_PER_FRAME);
+        assert_eq!(replayed, stepped[MAX_REPLAY_RUNS_PER_FRAME].1);
+
+        // An input made meanwhile waits for the replay to get to it instead of skipping ahead
+        state.predict_input(Direction::Right, 0, &mut replayed);
+        assert_eq!(replayed, stepped[MAX_REPLAY_RUNS_PER_FRAME].1);
+        while state.replaying() {
+            state.continue_replay(&mut replayed);
+        }
+        assert_eq!(replayed, AuthoritativePos::new(Position { x: position.x + 1, y: position.y }));
+        assert_eq!(state.position_history.len(), dirs.len() + 1);
+
+        // A reset drops the replay with the inputs
+        state.reapply_pending_inputs(&mut replayed);
+        state.reset_to(spawn);
+        assert!(!state.replaying());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::constants::{PREDICTION_WATCHDOG_MIN_RESYNCS, PREDICTION_WATCHDOG_MIN_SAMPLES, PREDICTION_WATCHDOG_P95, PREDICTION_WATCHDOG_WINDOW};
+use crate::warnings::percentile;
+
+use std::collections::VecDeque;
+
+/// Watches the prediction errors of the local player for a prediction that keeps going wrong, such as
+/// one running on other movement than the server's. Trips when the 95th percentile error over a full
+/// window stays above a large threshold even though the prediction was snapped to the server several
+/// times in it, the point where following the snapshots plays better than predicting.
+#[derive(Debug, Clone, Default)]
+pub struct PredictionWatchdog {
+    errors: VecDeque<(f64, f32)>, // Local time in seconds and error in pixels of each measurement, oldest first
+    resyncs: VecDeque<f64>,       // Local times the prediction was snapped to the server
+    watching_since: Option<f64>,  // Local time of the first measurement since the last reset
+}
+
+/// Implementation of the PredictionWatchdog
+impl PredictionWatchdog {
+    /// Creates a watchdog that has seen nothing yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the prediction error a snapshot measured at a local time
+    pub fn record_error(&mut self, now: f64, error: f32) {
+        self.watching_since.get_or_insert(now);
+        self.errors.push_back((now, error));
+        self.prune(now);
+    }
+
+    /// Records that the prediction was snapped to the server at a local time
+    pub fn record_resync(&mut self, now: f64) {
+        self.watching_since.get_or_insert(now);
+        self.resyncs.push_back(now);
+        self.prune(now);
+    }
+
+    /// 95th percentile of the errors within the window before now, None without any
+    pub fn p95(&self, now: f64) -> Option<f32> {
+        let mut errors: Vec<f32> = self.errors.iter()
+            .filter(|(at, _)| now - at <= PREDICTION_WATCHDOG_WINDOW)
+            .map(|(_, error)| *error)
+            .collect();
+        percentile(&mut errors, 0.95)
+    }
+
+    /// True if the prediction should be turned off: it was watched for a whole window, with enough errors
+    /// measured and snaps made within it, and the 95th percentile error is still above the threshold
+    pub fn tripped(&mut self, now: f64) -> bool {
+        self.prune(now);
+        let watched = self.watching_since.is_some_and(|since| now - since >= PREDICTION_WATCHDOG_WINDOW);
+        watched
+            && self.errors.len() >= PREDICTION_WATCHDOG_MIN_SAMPLES
+            && self.resyncs.len() >= PREDICTION_WATCHDOG_MIN_RESYNCS
+            && self.p95(now).is_some_and(|p95| p95 > PREDICTION_WATCHDOG_P95)
+    }
+
+    /// Forgets everything, so a prediction turned back on gets a whole window before it is judged again
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Drops what is older than the window
+    fn prune(&mut self, now: f64) {
+        while self.errors.front().is_some_and(|(at, _)| now - at > PREDICTION_WATCHDOG_WINDOW) {
+            self.errors.pop_front();
+        }
+        while self.resyncs.front().is_some_and(|at| now - at > PREDICTION_WATCHDOG_WINDOW) {
+            self.resyncs.pop_front();
+        }
+    }
+}
+
+/// Tests for the PredictionWatchdog
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to feed a snapshot every 50 ms from start to end with the error, snapping every snap_every seconds
+    fn feed(watchdog: &mut PredictionWatchdog, start: f64, end: f64, error: f32, snap_every: f64) {
+        let mut time = start;
+        let mut next_snap = start;
+        while time < end {
+            watchdog.record_error(time, error);
+            if time >= next_snap {
+                watchdog.record_resync(time);
+                next_snap += snap_every;
+            }
+            time += 0.05;
+        }
+    }
+
+    #[test]
+    fn test_trips_only_after_a_whole_bad_window_with_resyncs() {
+        let mut watchdog = PredictionWatchdog::new();
+        feed(&mut watchdog, 0.0, 5.0, 400.0, 1.0);
+        assert!(!watchdog.tripped(5.0), "half a window is not enough");
+        feed(&mut watchdog, 5.0, 10.5, 400.0, 1.0);
+        assert!(watchdog.tripped(10.5));
+        assert_eq!(watchdog.p95(10.5), Some(400.0));
+
+        // Just as bad, but never snapped, the reconciliation may still catch up
+        let mut unsnapped = PredictionWatchdog::new();
+        feed(&mut unsnapped, 0.0, 11.0, 400.0, 100.0);
+        assert!(!unsnapped.tripped(11.0));
+    }
+
+    #[test]
+    fn test_good_errors_and_old_spikes_keep_the_prediction() {
+        // Mostly small errors with a few large ones stay under the 95th percentile
+        let mut watchdog = PredictionWatchdog::new();
+        for frame in 0..300 {
+            let error = if frame % 50 == 0 { 500.0 } else { 4.0 };
+            watchdog.record_error(frame as f64 * 0.05, error);
+            if frame % 50 == 0 {
+                watchdog.record_resync(frame as f64 * 0.05);
+            }
+        }
+        assert!(!watchdog.tripped(15.0));
+
+        // A bad stretch that is over slides out of the window
+        let mut recovered = PredictionWatchdog::new();
+        feed(&mut recovered, 0.0, 10.5, 400.0, 1.0);
+        feed(&mut recovered, 10.5, 21.0, 2.0, 100.0);
+        assert!(!recovered.tripped(21.0));
+        assert!(recovered.p95(21.0).unwrap() < 10.0);
+    }
+
+    #[test]
+    fn test_reset_waits_another_window() {
+        let mut watchdog = PredictionWatchdog::new();
+        feed(&mut watchdog, 0.0, 10.5, 400.0, 1.0);
+        assert!(watchdog.tripped(10.5));
+        watchdog.reset();
+        assert!(!watchdog.tripped(10.5));
+        assert_eq!(watchdog.p95(10.5), None);
+        feed(&mut watchdog, 10.5, 15.0, 400.0, 1.0);
+        assert!(!watchdog.tripped(15.0));
+        feed(&mut watchdog, 15.0, 21.0, 400.0, 1.0);
+        assert!(watchdog.tripped(21.0));
+    }
+}
@@ -0,0 +1,292 @@
+use crate::constants::PROFILE_WINDOW;
+
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Instant;
+
+/// Times the rest of the enclosing block as a named scope of the current frame, while the thread records.
+/// Otherwise it only checks a flag. A scope entered several times in a frame counts the sum of its times.
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        let _profile_scope = $crate::profiler::ScopeGuard::enter($name);
+    };
+}
+
+thread_local! {
+    static RECORDING: Cell<bool> = const { Cell::new(false) };
+    static SCOPES: RefCell<Vec<(&'static str, f64)>> = const { RefCell::new(Vec::new()) }; // Milliseconds per scope of the current frame
+}
+
+/// Times a scope from its creation until it is dropped, made by profile_scope
+pub struct ScopeGuard {
+    name: &'static str,
+    started: Option<Instant>, // None while the thread doesn't record
+}
+
+/// Implementation of the ScopeGuard
+impl ScopeGuard {
+    /// Starts timing the scope if the thread records
+    pub fn enter(name: &'static str) -> Self {
+        Self { name, started: RECORDING.get().then(Instant::now) }
+    }
+}
+
+/// Drop implementation for the ScopeGuard
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        if let Some(started) = self.started {
+            let ms = started.elapsed().as_secs_f64() * 1000.0;
+            SCOPES.with_borrow_mut(|scopes| match scopes.iter_mut().find(|(name, _)| *name == self.name) {
+                Some((_, total)) => *total += ms,
+                None => scopes.push((self.name, ms)),
+            });
+        }
+    }
+}
+
+/// Turns recording of the scopes on the thread on or off, from the next scope that is entered
+pub fn set_recording(recording: bool) {
+    RECORDING.set(recording);
+}
+
+/// True while the scopes of the thread are recorded
+pub fn is_recording() -> bool {
+    RECORDING.get()
+}
+
+/// Ends the frame of the thread that took frame_ms, returning its scopes. None if the thread neither
+/// records nor recorded anything since the last frame.
+pub fn end_frame(frame_ms: f64) -> Option<FrameProfile> {
+    let scopes = SCOPES.take();
+    (is_recording() || !scopes.is_empty()).then_some(FrameProfile { frame_ms, scopes })
+}
+
+/// Runs the work as a frame of its own with recording on, returning what it returned and its scopes.
+/// The frame the thread was in and whether it recorded are left as they were.
+pub fn capture<R>(work: impl FnOnce() -> R) -> (R, FrameProfile) {
+    let outer_scopes = SCOPES.take();
+    let outer_recording = RECORDING.replace(true);
+    let started = Instant::now();
+    let result = work();
+    let frame = FrameProfile { frame_ms: started.elapsed().as_secs_f64() * 1000.0, scopes: SCOPES.replace(outer_scopes) };
+    RECORDING.set(outer_recording);
+    (result, frame)
+}
+
+/// Milliseconds each scope took in one frame, with how long the whole frame took
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrameProfile {
+    pub frame_ms: f64,
+    pub scopes: Vec<(&'static str, f64)>, // In the order the scopes first ended
+}
+
+/// Where the frame time of a scope went over many frames
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScopeStats {
+    pub name: String,
+    pub avg_ms: f64,        // Per frame, frames the scope didn't run in count as zero
+    pub max_ms: f64,        // Longest single frame
+    pub frame_percent: f64, // Share of the frame time spent in the scope, nested scopes count towards their parents too
+}
+
+/// Sums of the frames added, per scope
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProfileTotals {
+    frames: u64,
+    frame_ms: f64,                              // Time of every frame added
+    scopes: BTreeMap<&'static str, (f64, f64)>, // Total and longest milliseconds per scope
+}
+
+/// Implementation of the ProfileTotals
+impl ProfileTotals {
+    /// Creates totals without any frame
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the scopes of a frame
+    pub fn add(&mut self, frame: &FrameProfile) {
+        self.frames += 1;
+        self.frame_ms += frame.frame_ms;
+        for (name, ms) in &frame.scopes {
+            let (total, max) = self.scopes.entry(name).or_default();
+            *total += ms;
+            *max = max.max(*ms);
+        }
+    }
+
+    /// Frames added
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Average, longest and share of the frame time of every scope, the most expensive first
+    pub fn stats(&self) -> Vec<ScopeStats> {
+        let mut stats: Vec<ScopeStats> = self.scopes.iter()
+            .map(|(name, (total, max))| ScopeStats {
+                name: name.to_string(),
+                avg_ms: total / self.frames as f64,
+                max_ms: *max,
+                frame_percent: if self.frame_ms > 0.0 { total / self.frame_ms * 100.0 } else { 0.0 },
+            })
+            .collect();
+        stats.sort_by(|a, b| b.avg_ms.total_cmp(&a.avg_ms).then_with(|| a.name.cmp(&b.name)));
+        stats
+    }
+}
+
+/// The scopes of the last frames, for a live view of where the frame time goes
+#[derive(Debug, Clone)]
+pub struct ProfileWindow {
+    window: usize,
+    frames: VecDeque<FrameProfile>, // Oldest first, at most window of them
+}
+
+/// Default implementation for the ProfileWindow
+impl Default for ProfileWindow {
+    fn default() -> Self {
+        Self::new(PROFILE_WINDOW)
+    }
+}
+
+/// Implementation of the ProfileWindow
+impl ProfileWindow {
+    /// Keeps the scopes of the last window frames
+    pub fn new(window: usize) -> Self {
+        Self { window: window.max(1), frames: VecDeque::new() }
+    }
+
+    /// Adds a frame, forgetting the oldest one past the window
+    pub fn push(&mut self, frame: FrameProfile) {
+        if self.frames.len() == self.window {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// Frames in the window
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// True before the first frame
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Totals of the frames in the window
+    pub fn totals(&self) -> ProfileTotals {
+        let mut totals = ProfileTotals::new();
+        for frame in &self.frames {
+            totals.add(frame);
+        }
+        totals
+    }
+
+    /// Stats of every scope over the frames in the window, the most expensive first
+    pub fn stats(&self) -> Vec<ScopeStats> {
+        self.totals().stats()
+    }
+}
+
+/// Tests for the profiler
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to create a frame of the given milliseconds with the scopes
+    fn frame(frame_ms: f64, scopes: &[(&'static str, f64)]) -> FrameProfile {
+        FrameProfile { frame_ms, scopes: scopes.to_vec() }
+    }
+
+    #[test]
+    fn test_scopes_are_recorded_only_while_recording() {
+        set_recording(false);
+        {
+            profile_scope!("ignored");
+        }
+        assert_eq!(end_frame(16.0), None);
+
+        set_recording(true);
+        {
+            profile_scope!("outer");
+            for _ in 0..3 {
+                profile_scope!("inner");
+            }
+        }
+        let recorded = end_frame(16.0).unwrap();
+        set_recording(false);
+        let names: Vec<&str> = recorded.scopes.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["inner", "outer"]);
+        assert!(recorded.scopes[1].1 >= recorded.scopes[0].1);
+
+        // A frame that recorded nothing while recording is still a frame, of no scopes
+        set_recording(true);
+        assert_eq!(end_frame(5.0), Some(frame(5.0, &[])));
+        set_recording(false);
+        assert_eq!(end_frame(5.0), None);
+    }
+
+    #[test]
+    fn test_capture_leaves_the_outer_frame_alone() {
+        set_recording(true);
+        {
+            profile_scope!("before");
+        }
+        let (value, captured) = capture(|| {
+            profile_scope!("tick");
+            7
+        });
+        assert_eq!(value, 7);
+        assert_eq!(captured.scopes.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["tick"]);
+        assert!(captured.frame_ms >= captured.scopes[0].1);
+        assert!(is_recording());
+        assert_eq!(end_frame(1.0).unwrap().scopes.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["before"]);
+
+        // Capturing records even on a thread that doesn't, and leaves it that way
+        set_recording(false);
+        let ((), captured) = capture(|| {
+            profile_scope!("tick");
+        });
+        assert_eq!(captured.scopes.len(), 1);
+        assert!(!is_recording());
+    }
+
+    #[test]
+    fn test_totals_average_over_every_frame() {
+        let mut totals = ProfileTotals::new();
+        assert!(totals.stats().is_empty());
+        totals.add(&frame(10.0, &[("draw", 4.0), ("network", 1.0)]));
+        totals.add(&frame(20.0, &[("draw", 6.0), ("reconcile", 8.0)]));
+        totals.add(&frame(10.0, &[("draw", 2.0)]));
+        assert_eq!(totals.frames(), 3);
+
+        // Frames a scope didn't run in pull its average down, the share is of the time of every frame
+        assert_eq!(totals.stats(), vec![
+            ScopeStats { name: "draw".to_string(), avg_ms: 4.0, max_ms: 6.0, frame_percent: 30.0 },
+            ScopeStats { name: "reconcile".to_string(), avg_ms: 8.0 / 3.0, max_ms: 8.0, frame_percent: 20.0 },
+            ScopeStats { name: "network".to_string(), avg_ms: 1.0 / 3.0, max_ms: 1.0, frame_percent: 2.5 },
+        ]);
+
+        // Frames without time don't divide by zero
+        let mut empty = ProfileTotals::new();
+        empty.add(&frame(0.0, &[("draw", 0.0)]));
+        assert_eq!(empty.stats()[0].frame_percent, 0.0);
+    }
+
+    #[test]
+    fn test_window_forgets_the_oldest_frames() {
+        let mut window = ProfileWindow::new(2);
+        assert!(window.is_empty() && window.stats().is_empty());
+        window.push(frame(10.0, &[("draw", 9.0)]));
+        window.push(frame(10.0, &[("draw", 1.0)]));
+        assert_eq!(window.stats()[0].max_ms, 9.0);
+
+        // The spike leaves the window, only the last two frames count
+        window.push(frame(10.0, &[("draw", 3.0)]));
+        assert_eq!(window.len(), 2);
+        assert_eq!(window.stats(), vec![ScopeStats { name: "draw".to_string(), avg_ms: 2.0, max_ms: 3.0, frame_percent: 20.0 }]);
+    }
+}
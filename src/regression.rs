@@ -0,0 +1,158 @@
+use crate::analysis::ConditionSummary;
+use crate::constants::{REGRESSION_AVG_PREDICTION_ERROR, REGRESSION_MIN_RECONCILIATIONS, REGRESSION_P95_PREDICTION_ERROR};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Limits one condition's metrics have to stay within. A limit set to null in the file isn't checked,
+/// missing fields keep their default.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegressionLimits {
+    pub avg_prediction_error: Option<f32>,
+    pub p95_prediction_error: Option<f32>,
+    pub max_prediction_error: Option<f32>,
+    pub avg_rtt_ms: Option<f64>,
+    pub avg_starvation: Option<f64>,
+    pub min_reconciliations: u32, // Fewer reconciled snapshots mean the condition wasn't measured at all
+}
+
+/// Default implementation for the RegressionLimits
+impl Default for RegressionLimits {
+    fn default() -> Self {
+        Self {
+            avg_prediction_error: Some(REGRESSION_AVG_PREDICTION_ERROR),
+            p95_prediction_error: Some(REGRESSION_P95_PREDICTION_ERROR),
+            max_prediction_error: None,
+            avg_rtt_ms: None,
+            avg_starvation: None,
+            min_reconciliations: REGRESSION_MIN_RECONCILIATIONS,
+        }
+    }
+}
+
+/// Limits the headless analysis checks every tested condition against, read from a JSON file.
+/// A condition listed by name uses its own limits instead of the shared ones.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegressionThresholds {
+    pub limits: RegressionLimits,
+    pub conditions: HashMap<String, RegressionLimits>,
+}
+
+/// A metric of a condition that went past its limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regression {
+    pub condition: String,
+    pub metric: &'static str,
+    pub value: f64,
+    pub limit: f64,
+}
+
+/// Implementation of the Regression
+impl Regression {
+    /// Describes the regression for the console and the report
+    pub fn describe(&self) -> String {
+        let direction = if self.metric == "reconciliation_count" { "under the minimum" } else { "over the limit" };
+        format!("{}: {} {:.2} {} of {:.2}", self.condition, self.metric, self.value, direction, self.limit)
+    }
+}
+
+/// Implementation of the RegressionThresholds
+impl RegressionThresholds {
+    /// Limits of a condition
+    pub fn limits_for(&self, condition: &str) -> &RegressionLimits {
+        self.conditions.get(condition).unwrap_or(&self.limits)
+    }
+
+    /// Every metric of the summaries past its limit, in the order of the summaries. Metrics that
+    /// weren't measured, such as starvation without remote players, aren't held against a condition.
+    pub fn check(&self, summaries: &[ConditionSummary]) -> Vec<Regression> {
+        let mut regressions = Vec::new();
+        for summary in summaries {
+            let limits = self.limits_for(&summary.condition);
+            let mut over = |metric: &'static str, value: Option<f64>, limit: Option<f64>| {
+                if let Some((value, limit)) = value.zip(limit).filter(|(value, limit)| value > limit) {
+                    regressions.push(Regression { condition: summary.condition.clone(), metric, value, limit });
+                }
+            };
+            over("avg_prediction_error", Some(summary.avg_prediction_error as f64), limits.avg_prediction_error.map(f64::from));
+            over("p95_prediction_error", Some(summary.p95_prediction_error as f64), limits.p95_prediction_error.map(f64::from));
+            over("max_prediction_error", Some(summary.max_prediction_error as f64), limits.max_prediction_error.map(f64::from));
+            over("avg_rtt_ms", summary.avg_rtt_ms, limits.avg_rtt_ms);
+            over("avg_starvation", summary.avg_starvation, limits.avg_starvation);
+            if summary.reconciliation_count < limits.min_reconciliations {
+                regressions.push(Regression {
+                    condition: summary.condition.clone(),
+                    metric: "reconciliation_count",
+                    value: summary.reconciliation_count as f64,
+                    limit: limits.min_reconciliations as f64,
+                });
+            }
+        }
+        regressions
+    }
+}
+
+/// Tests for the regression thresholds
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to create a summary of a condition with the prediction errors and reconciliations
+    fn summary(condition: &str, avg: f32, p95: f32, reconciliations: u32) -> ConditionSummary {
+        ConditionSummary {
+            condition: condition.to_string(),
+            latency_ms: 100,
+            jitter_ms: 10,
+            packet_loss_percent: 0,
+            duplicate_percent: 0,
+            avg_prediction_error: avg,
+            p95_prediction_error: p95,
+            max_prediction_error: p95 * 2.0,
+            reconciliation_count: reconciliations,
+            avg_rtt_ms: Some(210.0),
+            avg_starvation: None,
+            avg_latency_ms: None,
+            avg_compensated_latency_ms: None,
+            avg_tick_cadence: None,
+            traffic: Vec::new(),
+            heatmap: None,
+            overlap_frames: 0,
+            overlapping_pairs: 0,
+            max_pair_overlap_frames: 0,
+            profile: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_metrics_over_the_limits_regress() {
+        let thresholds = RegressionThresholds::default();
+        let summaries = [summary("Good", 2.0, 5.0, 300), summary("Poor", 25.0, 70.0, 300), summary("Silent", 0.0, 0.0, 0)];
+
+        let regressions = thresholds.check(&summaries);
+        let found: Vec<(&str, &str)> = regressions.iter().map(|regression| (regression.condition.as_str(), regression.metric)).collect();
+        assert_eq!(found, vec![
+            ("Poor", "avg_prediction_error"),
+            ("Poor", "p95_prediction_error"),
+            ("Silent", "reconciliation_count"),
+        ]);
+        assert_eq!(regressions[0].describe(), "Poor: avg_prediction_error 25.00 over the limit of 20.00");
+        assert_eq!(regressions[2].describe(), "Silent: reconciliation_count 0.00 under the minimum of 1.00");
+    }
+
+    #[test]
+    fn test_conditions_can_have_their_own_limits() {
+        let thresholds: RegressionThresholds = serde_json::from_str(r#"{
+            "limits": { "avg_rtt_ms": 200 },
+            "conditions": { "Poor": { "avg_prediction_error": 30, "p95_prediction_error": null } }
+        }"#).unwrap();
+
+        // The shared limits keep their defaults next to the one set, a condition's own limits replace them
+        assert_eq!(thresholds.limits.avg_prediction_error, Some(REGRESSION_AVG_PREDICTION_ERROR));
+        assert_eq!(thresholds.limits_for("Poor").avg_rtt_ms, None);
+        let regressions = thresholds.check(&[summary("Good", 2.0, 5.0, 300), summary("Poor", 25.0, 70.0, 300)]);
+        let found: Vec<(&str, &str)> = regressions.iter().map(|regression| (regression.condition.as_str(), regression.metric)).collect();
+        assert_eq!(found, vec![("Good", "avg_rtt_ms")]);
+    }
+}
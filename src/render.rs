@@ -1,54 +1,166 @@
-use crate::colors::bg_colors;
-use crate::constants::{PLAYER_SIZE, TOOL_BAR_HEIGHT};
+use crate::colors::{palette, player_colors, Theme};
+use crate::constants::{HIGH_CONTRAST_TEXT_SIZE, INPUT_LOG_ROWS, MINIMAP_DOT_SIZE, PELLET_SIZE, PLAYER_SIZE, TOOL_BAR_HEIGHT, TOOL_BAR_TEXT_SIZE};
+use crate::heatmap::{ErrorHeatmap, HeatmapScale};
+use crate::input::ToolbarHints;
+use crate::input_log::{InputLogEntry, InputLogStatus};
+use crate::minimap::{to_minimap, viewport_outline, ScreenRect};
+use crate::positions::DisplayPos;
+use crate::profiler::ScopeStats;
+use crate::scoreboard::{ScoreboardLayout, ScoreboardRow, ScoreboardSort};
+use crate::spectator::View;
+use crate::split_view::Viewport;
+use crate::strings::{Language, Strings, Text};
+use crate::text_field::TextFieldState;
+use crate::traffic::TrafficRate;
+use crate::types::{PlayerShape, RoomInfo};
+use crate::warnings::{ActiveWarning, WarningLevel};
 
 use macroquad::prelude::*;
+use uuid::Uuid;
 
 /// Renderer for the game, responsible for drawing the game elements
-pub struct Renderer;
+pub struct Renderer {
+    theme: Theme,
+    text_size: f32,
+    strings: Strings, // Language the text is drawn in
+}
 
 /// Implementation of the Renderer
 impl Renderer {
     /// Creates a new Renderer instance
     pub fn new() -> Self {
-        Renderer
+        Self::with_high_contrast(false)
+    }
+
+    /// Creates a new Renderer, using the high-contrast theme and larger text if requested
+    pub fn with_high_contrast(high_contrast: bool) -> Self {
+        Renderer {
+            theme: Theme::select(high_contrast),
+            text_size: if high_contrast { HIGH_CONTRAST_TEXT_SIZE } else { TOOL_BAR_TEXT_SIZE },
+            strings: Strings::default(),
+        }
+    }
+
+    /// The strings of the language the text is drawn in
+    pub fn strings(&self) -> &Strings {
+        &self.strings
+    }
+
+    /// Draws the text in another language from the next frame on
+    pub fn set_language(&mut self, language: Language) {
+        self.strings.set_language(language);
     }
 
-    /// Clears the screen with a black background
+    /// The colors used by this renderer
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// Clears the screen with the theme background
     pub fn clear(&self) {
-        clear_background(bg_colors::BLACK);
+        clear_background(self.theme.background);
     }
     
-    /// Draws the player at the specified position with the given color
+    /// Draws the player at the specified position with the given color and opacity
     pub fn draw_player(
         &self,
         x: f32,
         y: f32,
         color: Color,
+        alpha: f32,
+    ) {
+        self.draw_player_shape(x, y, color, alpha, PlayerShape::Square);
+    }
+
+    /// Draws the player as the given shape, centered on the position and filling the same
+    /// PLAYER_SIZE bounding box for every shape
+    pub fn draw_player_shape(
+        &self,
+        x: f32,
+        y: f32,
+        color: Color,
+        alpha: f32,
+        shape: PlayerShape,
     ) {
+        let mut color = self.theme.player_color(color);
+        color.a *= alpha.clamp(0.0, 1.0);
+        let size = PLAYER_SIZE as f32;
+        match shape {
+            PlayerShape::Square => draw_rectangle(x - size / 2.0, y - size / 2.0, size, size, color),
+            PlayerShape::Circle => draw_circle(x, y, size / 2.0, color),
+            PlayerShape::Triangle => {
+                let [apex, left, right] = triangle_vertices(x, y, size);
+                draw_triangle(apex, left, right, color);
+            }
+        }
+    }
+
+    /// Draws a pellet centered on the specified position
+    pub fn draw_pellet(&self, x: f32, y: f32) {
         draw_rectangle(
-            x - (PLAYER_SIZE as f32) / 2.0,
-            y - (PLAYER_SIZE as f32) / 2.0,
-            PLAYER_SIZE as f32,
-            PLAYER_SIZE as f32,
-            color,
+            x - (PELLET_SIZE as f32) / 2.0,
+            y - (PELLET_SIZE as f32) / 2.0,
+            PELLET_SIZE as f32,
+            PELLET_SIZE as f32,
+            self.theme.warning,
         );
     }
 
+    /// Draws the prediction error heatmap over the board, one translucent cell per grid cell with samples.
+    /// Cells go from a faint yellow for small errors to a stronger red for the largest.
+    pub fn draw_heatmap(&self, heatmap: &ErrorHeatmap, scale: HeatmapScale) {
+        let (width, height) = heatmap.cell_size();
+        for row in 0..heatmap.rows() {
+            for column in 0..heatmap.columns() {
+                if let Some(intensity) = heatmap.normalized(column, row, scale) {
+                    let color = Color::new(1.0, 1.0 - 0.8 * intensity, 0.0, 0.15 + 0.45 * intensity);
+                    draw_rectangle(column as f32 * width, row as f32 * height, width, height, color);
+                }
+            }
+        }
+    }
+
+    /// Draws a faint outline of the player's box where the server most likely has the local player now.
+    /// Only an outline, so it stays visible under and around the player drawn where it is predicted.
+    pub fn draw_server_view_ghost(&self, x: f32, y: f32) {
+        let size = PLAYER_SIZE as f32;
+        let mut color = self.theme.text;
+        color.a = 0.45;
+        draw_rectangle_lines(x - size / 2.0, y - size / 2.0, size, size, 1.5, color);
+    }
+
+    /// Draws an outline around an area of the board, such as two players drawn overlapping
+    pub fn draw_highlight_rect(&self, x: f32, y: f32, width: f32, height: f32) {
+        draw_rectangle_lines(x, y, width, height, 2.0, self.theme.warning);
+    }
+
+    /// Draws a label centered above a player, such as the side of a mirrored square
+    pub fn draw_player_label(&self, x: f32, y: f32, text: &str) {
+        let text_size = self.text_size * 0.8;
+        let width = measure_text(text, None, text_size as u16, 1.0).width;
+        draw_text(text, x - width / 2.0, y - PLAYER_SIZE as f32 / 2.0 - 6.0, text_size, self.theme.text);
+    }
+
+    /// Draws a ring around the player that is "it" in tag mode
+    pub fn draw_tag_marker(&self, x: f32, y: f32) {
+        draw_circle_lines(x, y, PLAYER_SIZE as f32, 2.0, self.theme.warning);
+    }
+
     /// Draws the toolbar with network stats and controls
-    pub fn draw_tool_bar(&self, delay_ms: i32, packet_loss: i32, is_connected: bool, is_testing: bool) {
+    pub fn draw_tool_bar(&self, hints: &ToolbarHints, network_stats: &str, link_status: &str, is_connected: bool, is_testing: bool) {
         let bar_height = TOOL_BAR_HEIGHT as f32;
         let width = screen_width();
         let height = screen_height();
-        let text_size = 20.0;
+        let text_size = self.text_size;
         let text_spacing = 20.0;
 
-        // Check if we need a two-line layout
-        let min_width_for_single_line = 900.0;
+        // Check if we need a two-line layout, larger text needs more room
+        let min_width_for_single_line = 1300.0 * text_size / TOOL_BAR_TEXT_SIZE;
         let is_two_line = width < min_width_for_single_line;
         let bar_total_height = if is_two_line { bar_height * 2.0 } else { bar_height };
 
         // Draw toolbar background
-        draw_rectangle(0.0, height - bar_total_height, width, bar_total_height, bg_colors::DARK_GRAY);
+        draw_rectangle(0.0, height - bar_total_height, width, bar_total_height, self.theme.toolbar);
 
         // First line (or only line if enough space)
         let y_pos = if is_two_line {
@@ -58,25 +170,26 @@ impl Renderer {
         };
 
         // Draw movement controls text
+        let movement_text = hints.movement_label(&self.strings);
         draw_text(
-            "Movement [W,A,S,D]",
+            &movement_text,
             text_spacing,
             y_pos,
             text_size,
-            bg_colors::WHITE,
+            self.theme.text,
         );
 
         // Calculate position for network stats text
-        let movement_width = measure_text("Movement [W,A,S,D]", None, text_size as u16, 1.0).width;
+        let movement_width = measure_text(&movement_text, None, text_size as u16, 1.0).width;
         let network_stats_x = text_spacing + movement_width + 30.0; // Add some spacing between texts
 
         // Draw network stats
         draw_text(
-            &format!("Delay: {} ms [V/B]   Packet Loss: {}% [N/M]", delay_ms, packet_loss),
+            network_stats,
             network_stats_x,
             y_pos,
             text_size,
-            bg_colors::WHITE,
+            self.theme.text,
         );
 
         // Second line or right side of the bar
@@ -87,10 +200,10 @@ impl Renderer {
         };
 
         // Calculate spacing for right-aligned elements
-        let connect_text = if is_connected { "Drop connection [R]" } else { "Reconnect [R]" };
-        let connect_width = measure_text(connect_text, None, text_size as u16, 1.0).width;
-        let test_text = "Test [T]";
-        let test_width = measure_text(test_text, None, text_size as u16, 1.0).width;
+        let connect_text = hints.connection_label(&self.strings, is_connected);
+        let connect_width = measure_text(&connect_text, None, text_size as u16, 1.0).width;
+        let test_text = hints.test_label(&self.strings);
+        let test_width = measure_text(&test_text, None, text_size as u16, 1.0).width;
 
         // Testing indicator and label
         let indicator_size = 10.0;
@@ -99,50 +212,526 @@ impl Renderer {
         // Position from right side
         let test_x = width - connect_width - text_spacing * 2.0 - test_width - indicator_size - indicator_spacing;
 
+        // Show the link status, such as the input send rate and the expected prediction error
+        let status_x = if is_two_line {
+            text_spacing
+        } else {
+            test_x - measure_text(link_status, None, text_size as u16, 1.0).width - 30.0
+        };
+        draw_text(link_status, status_x, status_y_pos, text_size, self.theme.text);
+
         // Draw indicator
         let indicator_x = test_x;
         let indicator_y = status_y_pos - text_size/3.0;
 
         // Draw indicator light
         let indicator_color = if is_testing {
-            bg_colors::ORANGE // Orange when testing
+            self.theme.warning // Orange when testing
         } else {
-            bg_colors::DARK_GRAY // Dim gray when not testing
+            self.theme.toolbar // Dim gray when not testing
         };
 
         draw_circle(indicator_x, indicator_y, indicator_size / 2.0, indicator_color);
-        draw_circle_lines(indicator_x, indicator_y, indicator_size / 2.0, 1.0, bg_colors::WHITE);
+        draw_circle_lines(indicator_x, indicator_y, indicator_size / 2.0, 1.0, self.theme.text);
 
         // Draw test text
         draw_text(
-            test_text,
+            &test_text,
             indicator_x + indicator_size + indicator_spacing,
             status_y_pos,
             text_size,
-            bg_colors::WHITE,
+            self.theme.text,
         );
 
         // Connection status
         draw_text(
-            connect_text,
+            &connect_text,
             width - connect_width - text_spacing,
             status_y_pos,
             text_size,
-            bg_colors::WHITE,
+            self.theme.text,
         );
     }
+
+    /// Draws a small note on top of the right end of the toolbar, used while optional effects are shed
+    pub fn draw_tool_bar_note(&self, text: &str) {
+        let text_size = self.text_size * 0.8;
+        let padding = 4.0;
+        let width = measure_text(text, None, text_size as u16, 1.0).width;
+        let bar_height = TOOL_BAR_HEIGHT as f32;
+        let bar_total_height = if screen_width() < 1300.0 * self.text_size / TOOL_BAR_TEXT_SIZE { bar_height * 2.0 } else { bar_height };
+        let top = screen_height() - bar_total_height - text_size - padding * 2.0;
+
+        draw_rectangle(screen_width() - width - padding * 2.0, top, width + padding * 2.0, text_size + padding * 2.0, self.theme.toolbar);
+        draw_text(text, screen_width() - width - padding, top + padding + text_size * 0.75, text_size, self.theme.warning);
+    }
+
+    /// Screen y the overlays in the bottom right corner sit above, clear of the toolbar and its note
+    pub fn corner_overlay_bottom(&self) -> f32 {
+        let bar_height = TOOL_BAR_HEIGHT as f32;
+        let bar_total_height = if screen_width() < 1300.0 * self.text_size / TOOL_BAR_TEXT_SIZE { bar_height * 2.0 } else { bar_height };
+        screen_height() - bar_total_height - self.text_size * 0.8 - 8.0
+    }
+
+    /// Draws the mini-map in the rect: a dot in each player's color at the render positions given, the local
+    /// player larger and ringed, and the area the view shows outlined while it is zoomed or panned
+    pub fn draw_minimap(&self, rect: ScreenRect, players: &[(Uuid, DisplayPos, u32)], local_id: Option<Uuid>, view: Option<&View>) {
+        let (x, y, width, height) = rect;
+        draw_rectangle(x, y, width, height, self.theme.toolbar);
+        draw_rectangle_lines(x, y, width, height, 1.0, self.theme.text);
+
+        for (id, position, color) in players {
+            let (dot_x, dot_y) = to_minimap(*position, rect);
+            let (r, g, b) = palette::unpack(*color);
+            if Some(*id) == local_id {
+                draw_circle(dot_x, dot_y, MINIMAP_DOT_SIZE * 1.5, Color::from_rgba(r, g, b, 255));
+                draw_circle_lines(dot_x, dot_y, MINIMAP_DOT_SIZE * 1.5 + 1.0, 1.0, self.theme.text);
+            } else {
+                draw_circle(dot_x, dot_y, MINIMAP_DOT_SIZE, Color::from_rgba(r, g, b, 255));
+            }
+        }
+
+        if let Some((view_x, view_y, view_width, view_height)) = view.and_then(|view| viewport_outline(view, (screen_width(), screen_height()), rect)) {
+            draw_rectangle_lines(view_x, view_y, view_width, view_height, 1.0, self.theme.warning);
+        }
+    }
+
+    /// Draws how long an input takes to reach the screen as one bar split into stages, with a legend below it.
+    /// The stages are named with their average milliseconds, stages that weren't measured are left out.
+    /// The footer is a line of debug text below the legend.
+    pub fn draw_latency_bars(&self, stages: &[(&str, f64)], footer: &str) {
+        let text_size = self.text_size * 0.8;
+        let padding = 8.0;
+        let line_height = text_size * 1.3;
+        let bar_width = 240.0;
+        let bar_height = 12.0;
+        let total: f64 = stages.iter().map(|(_, ms)| ms).sum();
+        let colors = player_colors::get_palette();
+
+        let height = bar_height + padding * 3.0 + line_height * (stages.len() + 2) as f32;
+        let left = screen_width() - bar_width - padding * 3.0;
+        draw_rectangle(left, padding, bar_width + padding * 2.0, height, self.theme.toolbar);
+
+        let title = format!("Input to screen {:.0} ms", total);
+        let mut y = padding * 2.0;
+        draw_text(&title, left + padding, y + text_size * 0.75, text_size, self.theme.text);
+        y += line_height;
+
+        // One segment per stage, as wide as its share of the total
+        let mut x = left + padding;
+        for (index, (_, ms)) in stages.iter().enumerate() {
+            let width = if total > 0.0 { (ms / total) as f32 * bar_width } else { 0.0 };
+            draw_rectangle(x, y, width, bar_height, colors[index % colors.len()]);
+            x += width;
+        }
+        y += bar_height + padding;
+
+        for (index, (name, ms)) in stages.iter().enumerate() {
+            draw_rectangle(left + padding, y + text_size * 0.2, text_size * 0.6, text_size * 0.6, colors[index % colors.len()]);
+            draw_text(&format!("{} {:.1} ms", name, ms), left + padding + text_size, y + text_size * 0.75, text_size, self.theme.text);
+            y += line_height;
+        }
+        draw_text(footer, left + padding, y + text_size * 0.75, text_size, self.theme.text);
+    }
+
+    /// Draws the step mode log of sent inputs as a table above the left end of the toolbar, newest first
+    pub fn draw_input_log<'a>(&self, entries: impl Iterator<Item = &'a InputLogEntry>) {
+        let text_size = self.text_size * 0.8;
+        let padding = 8.0;
+        let line_height = text_size * 1.3;
+        let column = text_size * 5.0;
+        let bar_height = TOOL_BAR_HEIGHT as f32;
+        let bar_total_height = if screen_width() < 1300.0 * self.text_size / TOOL_BAR_TEXT_SIZE { bar_height * 2.0 } else { bar_height };
+        let height = line_height * (INPUT_LOG_ROWS + 1) as f32 + padding * 2.0;
+        let top = screen_height() - bar_total_height - height;
+
+        draw_rectangle(0.0, top, column * 3.0 + padding * 2.0, height, self.theme.toolbar);
+        let rows: Vec<(Vec<String>, Color)> = entries.take(INPUT_LOG_ROWS)
+            .map(|entry| {
+                let color = match entry.status {
+                    InputLogStatus::Pending => self.theme.text,
+                    InputLogStatus::Acked => self.theme.success,
+                    InputLogStatus::Dropped => self.theme.error,
+                };
+                (vec![entry.sequence.to_string(), format!("{:?}", entry.dir), entry.status.label().to_string()], color)
+            })
+            .collect();
+        self.draw_text_table(padding, top + padding, column, &["Seq", "Dir", "Step [F9]"], &rows);
+    }
+
+    /// Draws the bandwidth per message category as a table in the top left corner, below the notice.
+    /// The bars show each category's share of the busiest one, sent and received together.
+    pub fn draw_traffic_table(&self, rates: &[TrafficRate]) {
+        let text_size = self.text_size * 0.8;
+        let padding = 8.0;
+        let line_height = text_size * 1.3;
+        let column = text_size * 5.0;
+        let bar_width = column * 1.5;
+        let height = line_height * (rates.len() + 1) as f32 + padding * 2.0;
+        let top = self.text_size + padding * 3.0;
+
+        draw_rectangle(0.0, top, column * 3.0 + bar_width + padding * 3.0, height, self.theme.toolbar);
+        let rows: Vec<(Vec<String>, Color)> = rates.iter()
+            .map(|rate| (vec![rate.category.name().to_string(), format!("{:.0}", rate.up_bytes_per_s), format!("{:.0}", rate.down_bytes_per_s)], self.theme.text))
+            .collect();
+        self.draw_text_table(padding, top + padding, column, &["Traffic", "Up B/s", "Down B/s"], &rows);
+
+        let busiest = rates.iter().map(TrafficRate::total_bytes_per_s).fold(0.0, f64::max);
+        let colors = player_colors::get_palette();
+        for (index, rate) in rates.iter().enumerate() {
+            let share = if busiest > 0.0 { (rate.total_bytes_per_s() / busiest) as f32 } else { 0.0 };
+            let y = top + padding + line_height * (index + 1) as f32 + text_size * 0.15;
+            draw_rectangle(padding + column * 3.0, y, bar_width * share, text_size * 0.7, colors[index % colors.len()]);
+        }
+    }
+
+    /// Draws the frame time by scope as a table at the top of the screen, centered. The bars show each scope's
+    /// share of the frame time.
+    pub fn draw_profile_table(&self, stats: &[ScopeStats]) {
+        let text_size = self.text_size * 0.8;
+        let padding = 8.0;
+        let line_height = text_size * 1.3;
+        let column = text_size * 6.0;
+        let bar_width = column * 1.5;
+        let width = column * 4.0 + bar_width + padding * 3.0;
+        let height = line_height * (stats.len() + 1) as f32 + padding * 2.0;
+        let (left, top) = ((screen_width() - width) / 2.0, self.text_size + padding * 3.0);
+
+        draw_rectangle(left, top, width, height, self.theme.toolbar);
+        let rows: Vec<(Vec<String>, Color)> = stats.iter()
+            .map(|scope| (vec![scope.name.clone(), format!("{:.2}", scope.avg_ms), format!("{:.2}", scope.max_ms), format!("{:.0}%", scope.frame_percent)], self.theme.text))
+            .collect();
+        self.draw_text_table(left + padding, top + padding, column, &["Scope", "Avg ms", "Max ms", "Frame"], &rows);
+
+        let colors = player_colors::get_palette();
+        for (index, scope) in stats.iter().enumerate() {
+            let share = (scope.frame_percent / 100.0).clamp(0.0, 1.0) as f32;
+            let y = top + padding + line_height * (index + 1) as f32 + text_size * 0.15;
+            draw_rectangle(left + padding + column * 4.0, y, bar_width * share, text_size * 0.7, colors[index % colors.len()]);
+        }
+    }
+
+    /// Draws a table of text with a header row in the warning color, each row in its own color
+    fn draw_text_table(&self, left: f32, top: f32, column: f32, headers: &[&str], rows: &[(Vec<String>, Color)]) {
+        let text_size = self.text_size * 0.8;
+        let line_height = text_size * 1.3;
+        let mut baseline = top + text_size * 0.75;
+        for (index, header) in headers.iter().enumerate() {
+            draw_text(header, left + column * index as f32, baseline, text_size, self.theme.warning);
+        }
+        for (cells, color) in rows {
+            baseline += line_height;
+            for (index, cell) in cells.iter().enumerate() {
+                draw_text(cell, left + column * index as f32, baseline, text_size, *color);
+            }
+        }
+    }
+
+    /// Draws a short notice in the top left corner
+    pub fn draw_notice(&self, text: &str) {
+        let text_size = self.text_size;
+        let padding = 8.0;
+        let width = measure_text(text, None, text_size as u16, 1.0).width;
+
+        draw_rectangle(0.0, 0.0, width + padding * 2.0, text_size + padding * 2.0, self.theme.toolbar);
+        draw_text(text, padding, padding + text_size * 0.75, text_size, self.theme.warning);
+    }
+
+    /// Draws the active metric warnings stacked in the top right corner, styled like the notice,
+    /// in red when critical and orange otherwise
+    pub fn draw_warnings(&self, warnings: &[ActiveWarning]) {
+        let text_size = self.text_size;
+        let padding = 8.0;
+        let line_height = text_size + padding * 2.0;
+        for (index, warning) in warnings.iter().enumerate() {
+            let text = warning.describe(&self.strings);
+            let width = measure_text(&text, None, text_size as u16, 1.0).width + padding * 2.0;
+            let (x, y) = (screen_width() - width, index as f32 * line_height);
+            let color = match warning.level {
+                WarningLevel::Critical => self.theme.error,
+                WarningLevel::Warning => self.theme.warning,
+            };
+            draw_rectangle(x, y, width, line_height, self.theme.toolbar);
+            draw_text(&text, x + padding, y + padding + text_size * 0.75, text_size, color);
+        }
+    }
+
+    /// Draws a caption banner centered above the toolbar, a heading in the warning color over a line of text
+    pub fn draw_caption(&self, heading: &str, text: &str) {
+        let text_size = self.text_size;
+        let padding = 8.0;
+        let line_height = text_size * 1.3;
+        let bar_height = TOOL_BAR_HEIGHT as f32;
+        let bar_total_height = if screen_width() < 1300.0 * self.text_size / TOOL_BAR_TEXT_SIZE { bar_height * 2.0 } else { bar_height };
+        let width = [heading, text].iter()
+            .map(|line| measure_text(line, None, text_size as u16, 1.0).width)
+            .fold(0.0, f32::max) + padding * 2.0;
+        let height = line_height * 2.0 + padding * 2.0;
+        let (x, y) = ((screen_width() - width) / 2.0, screen_height() - bar_total_height - height - padding);
+
+        draw_rectangle(x, y, width, height, self.theme.toolbar);
+        draw_text(heading, x + padding, y + padding + text_size * 0.75, text_size, self.theme.warning);
+        draw_text(text, x + padding, y + padding + line_height + text_size * 0.75, text_size, self.theme.text);
+    }
+
+    /// Draws the seconds left of the match countdown as a large number in the middle of the board
+    pub fn draw_countdown(&self, seconds: u32) {
+        self.draw_countdown_text(&seconds.to_string());
+    }
+
+    /// Draws large text in the middle of the board, for the countdown and the end of a match
+    pub fn draw_countdown_text(&self, text: &str) {
+        let text_size = self.text_size * 6.0;
+        let dimensions = measure_text(text, None, text_size as u16, 1.0);
+        let x = (screen_width() - dimensions.width) / 2.0;
+        let y = (screen_height() - TOOL_BAR_HEIGHT as f32 + dimensions.offset_y) / 2.0;
+        draw_text(text, x, y, text_size, self.theme.warning);
+    }
+
+    /// Draws lines of text in a panel at the right edge, halfway down the board. The first line is the title.
+    pub fn draw_side_panel(&self, lines: &[String]) {
+        let text_size = self.text_size;
+        let padding = 12.0;
+        let line_height = text_size * 1.4;
+        let text_width = lines.iter()
+            .map(|line| measure_text(line, None, text_size as u16, 1.0).width)
+            .fold(0.0, f32::max);
+
+        let width = (text_width + padding * 2.0).min(screen_width());
+        let height = lines.len() as f32 * line_height + padding * 2.0;
+        let x = screen_width() - width;
+        let y = ((screen_height() - TOOL_BAR_HEIGHT as f32 - height) / 2.0).max(0.0);
+
+        draw_rectangle(x, y, width, height, self.theme.toolbar);
+        for (index, line) in lines.iter().enumerate() {
+            let baseline = y + padding + line_height * (index as f32 + 0.75);
+            let color = if index == 0 { self.theme.warning } else { self.theme.text };
+            draw_text(line, x + padding, baseline, text_size, color);
+        }
+    }
+
+    /// Draws everything until end_view through the view, so board positions are passed as they are
+    pub fn begin_view(&self, view: &View) {
+        let (x, y, width, height) = view.visible_rect((screen_width(), screen_height()));
+        set_camera(&Camera2D::from_display_rect(Rect::new(x, y, width, height)));
+    }
+
+    /// Draws everything until end_view into the viewport's part of the screen through its view, so board
+    /// positions are passed as they are and nothing spills into the other viewport
+    pub fn begin_viewport(&self, viewport: &Viewport) {
+        let (x, y, width, height) = viewport.visible_rect();
+        let (left, top, viewport_width, viewport_height) = viewport.rect;
+        let mut camera = Camera2D::from_display_rect(Rect::new(x, y, width, height));
+        // The GL viewport is in physical pixels and counts from the bottom of the window
+        let dpi = screen_dpi_scale();
+        camera.viewport = Some((
+            (left * dpi) as i32,
+            ((screen_height() - top - viewport_height) * dpi) as i32,
+            (viewport_width * dpi) as i32,
+            (viewport_height * dpi) as i32,
+        ));
+        set_camera(&camera);
+    }
+
+    /// Draws the frame and caption of a viewport of the dual view, in screen pixels after end_view
+    pub fn draw_viewport_frame(&self, viewport: &Viewport) {
+        let (x, y, width, height) = viewport.rect;
+        let text_size = self.text_size * 0.8;
+        draw_rectangle_lines(x, y, width, height, 1.0, self.theme.text);
+        draw_text(viewport.source.label(), x + 6.0, y + text_size, text_size, self.theme.text);
+    }
+
+    /// Goes back to drawing in screen pixels, for the overlays on top of the board
+    pub fn end_view(&self) {
+        set_default_camera();
+    }
+
+    /// Draws lines of text in a panel centered above the board, such as a menu or a report
+    pub fn draw_message_panel(&self, lines: &[String]) {
+        let text_size = self.text_size;
+        let padding = 16.0;
+        let line_height = text_size * 1.4;
+        let text_width = lines.iter()
+            .map(|line| measure_text(line, None, text_size as u16, 1.0).width)
+            .fold(0.0, f32::max);
+
+        let width = (text_width + padding * 2.0).min(screen_width());
+        let height = lines.len() as f32 * line_height + padding * 2.0;
+        let x = ((screen_width() - width) / 2.0).max(0.0);
+        let y = ((screen_height() - TOOL_BAR_HEIGHT as f32 - height) / 2.0).max(0.0);
+
+        draw_rectangle(x, y, width, height, self.theme.toolbar);
+        draw_rectangle_lines(x, y, width, height, 2.0, self.theme.text);
+        for (index, line) in lines.iter().enumerate() {
+            let baseline = y + padding + line_height * (index as f32 + 0.75);
+            draw_text(line, x + padding, baseline, text_size, self.theme.text);
+        }
+    }
+
+    /// Draws a single line text field in the given rectangle, with a caret while it is focused
+    pub fn draw_text_field(&self, rect: Rect, state: &TextFieldState) {
+        self.draw_field(rect, state.text(), state.text_before_caret(), state.is_focused());
+    }
+
+    /// Draws a text field like draw_text_field, with every char shown as a star, for passwords
+    pub fn draw_masked_text_field(&self, rect: Rect, state: &TextFieldState) {
+        let masked = "*".repeat(state.char_count());
+        self.draw_field(rect, &masked, &masked[..state.caret()], state.is_focused());
+    }
+
+    /// Draws the box, text and caret of a text field
+    fn draw_field(&self, rect: Rect, text: &str, text_before_caret: &str, focused: bool) {
+        let text_size = self.text_size;
+        let padding = 6.0;
+        let border = if focused { self.theme.warning } else { self.theme.text };
+
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, self.theme.background);
+        draw_rectangle_lines(rect.x, rect.y, rect.w, rect.h, 2.0, border);
+
+        let baseline = rect.y + (rect.h + text_size * 0.6) / 2.0;
+        draw_text(text, rect.x + padding, baseline, text_size, self.theme.text);
+
+        if focused {
+            let caret_x = rect.x + padding + measure_text(text_before_caret, None, text_size as u16, 1.0).width;
+            draw_line(caret_x, rect.y + padding, caret_x, rect.y + rect.h - padding, 2.0, self.theme.text);
+        }
+    }
+
+    /// Draws the rooms the server listed below the top of the rectangle, one per line with the
+    /// player count, a lock icon for rooms that need a password and the picked room highlighted
+    pub fn draw_room_list(&self, rect: Rect, rooms: &[RoomInfo], selected: Option<usize>) {
+        let text_size = self.text_size;
+        let line_height = text_size * 1.4;
+        let icon_size = text_size * 0.7;
+
+        if rooms.is_empty() {
+            draw_text(self.strings.get(Text::LookingForRooms), rect.x, rect.y + line_height * 0.75, text_size, self.theme.text);
+            return;
+        }
+        draw_text(self.strings.get(Text::Rooms), rect.x, rect.y + line_height * 0.75, text_size, self.theme.text);
+        let visible = ((rect.h / line_height) as usize).saturating_sub(1);
+        for (index, room) in rooms.iter().enumerate().take(visible) {
+            let baseline = rect.y + line_height * (index as f32 + 1.75);
+            let color = if Some(index) == selected { self.theme.warning } else { self.theme.text };
+            if room.password_required {
+                self.draw_lock_icon(rect.x, baseline - icon_size, icon_size, color);
+            }
+            let marker = if Some(index) == selected { ">" } else { " " };
+            let label = format!("{} {}", marker, room.name);
+            draw_text(&label, rect.x + icon_size * 1.5, baseline, text_size, color);
+            let players = self.strings.count(u64::from(room.players), Text::PlayerCountOne, Text::PlayerCountMany);
+            let players_width = measure_text(&players, None, text_size as u16, 1.0).width;
+            draw_text(&players, rect.x + rect.w - players_width, baseline, text_size, color);
+        }
+    }
+
+    /// Draws a padlock filling the size by size box with its top left corner at the position
+    pub fn draw_lock_icon(&self, x: f32, y: f32, size: f32, color: Color) {
+        let shackle = size * 0.3;
+        let thickness = (size * 0.12).max(1.0);
+        draw_rectangle_lines(x + size * 0.2, y, size * 0.6, shackle * 2.0, thickness, color);
+        draw_rectangle(x, y + shackle, size, size - shackle, color);
+    }
+
+    /// Draws the scoreboard overlay with the ping of every player, and team lines in team mode. The movement
+    /// of the players is listed in a table under it, in the same order.
+    pub fn draw_scoreboard(&self, rows: &[ScoreboardRow], sort: ScoreboardSort) {
+        let text_size = self.text_size;
+        let area_height = screen_height() - TOOL_BAR_HEIGHT as f32;
+        let layout = ScoreboardLayout::new(rows.len(), text_size, screen_width(), area_height);
+
+        // Draw panel background and border
+        draw_rectangle(layout.x, layout.y, layout.width, layout.height, self.theme.toolbar);
+        draw_rectangle_lines(layout.x, layout.y, layout.width, layout.height, 2.0, self.theme.text);
+
+        // Draw header
+        let name_x = layout.x + layout.padding;
+        draw_text("Player", name_x, layout.row_baseline(0), text_size, self.theme.text);
+        draw_text("Score", layout.score_column_x, layout.row_baseline(0), text_size, self.theme.text);
+        draw_text("Best", layout.best_column_x, layout.row_baseline(0), text_size, self.theme.text);
+        draw_text("Ping", layout.ping_column_x, layout.row_baseline(0), text_size, self.theme.text);
+        draw_text("Reorders", layout.reorder_column_x, layout.row_baseline(0), text_size, self.theme.text);
+
+        // Draw one row per player, highlighting the local player
+        for (index, row) in rows.iter().take(layout.visible_rows()).enumerate() {
+            let y = layout.row_baseline(index + 1);
+            let color = if row.is_local {
+                self.theme.success
+            } else if row.is_team_header {
+                self.theme.warning
+            } else {
+                self.theme.text
+            };
+            draw_text(&row.label, name_x, y, text_size, color);
+            draw_text(&row.score, layout.score_column_x, y, text_size, color);
+            draw_text(&row.best, layout.best_column_x, y, text_size, color);
+            draw_text(&row.ping, layout.ping_column_x, y, text_size, color);
+            draw_text(&row.reorders, layout.reorder_column_x, y, text_size, color);
+        }
+
+        // The movement table under the panel, with as many rows as fit above the toolbar next to its header and the sort hint
+        let padding = 8.0;
+        let line_height = text_size * 0.8 * 1.3;
+        let top = layout.y + layout.height + padding;
+        let fitting = ((area_height - top - padding * 2.0) / line_height).floor() as usize;
+        if fitting < 3 {
+            return;
+        }
+        let table_rows: Vec<(Vec<String>, Color)> = rows.iter()
+            .take(layout.visible_rows().min(fitting - 2))
+            .map(|row| {
+                let color = if row.is_local { self.theme.success } else if row.is_team_header { self.theme.warning } else { self.theme.text };
+                (vec![row.label.clone(), row.distance.clone(), row.time.clone(), row.input_rate.clone()], color)
+            })
+            .collect();
+        let height = line_height * (table_rows.len() + 2) as f32 + padding * 2.0;
+        draw_rectangle(layout.x, top, layout.width, height, self.theme.toolbar);
+        self.draw_text_table(layout.x + layout.padding, top + padding, layout.width / 4.0 - layout.padding / 2.0, &["Movement", "Distance", "Time", "Inputs/s"], &table_rows);
+        let hint = format!("Sorted by {}, hold Tab and press 1 ping, 2 distance, 3 time", sort.name());
+        draw_text(&hint, layout.x + layout.padding, top + padding + line_height * (table_rows.len() as f32 + 1.75), text_size * 0.7, self.theme.text);
+    }
+}
+
+/// Corners of an upward pointing triangle filling the size by size box centered on the position:
+/// the apex at the top middle, then the bottom left and bottom right corners
+pub fn triangle_vertices(x: f32, y: f32, size: f32) -> [Vec2; 3] {
+    let half = size / 2.0;
+    [
+        vec2(x, y - half),
+        vec2(x - half, y + half),
+        vec2(x + half, y + half),
+    ]
+}
+
+/// Default implementation for the Renderer
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Tests for the Renderer
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::colors::bg_colors;
 
     #[test]
     fn test_renderer_creation() {
         Renderer::new();
     }
 
+    #[test]
+    fn test_high_contrast_renderer() {
+        let standard = Renderer::new();
+        assert_eq!(*standard.theme(), Theme::standard());
+        assert_eq!(standard.text_size, TOOL_BAR_TEXT_SIZE);
+
+        let high_contrast = Renderer::with_high_contrast(true);
+        assert_eq!(*high_contrast.theme(), Theme::high_contrast());
+        assert!(high_contrast.text_size > standard.text_size);
+    }
+
     #[test]
     fn test_player_position_calculation() {
         let player_x = 100.0;
@@ -158,6 +747,26 @@ mod tests {
         assert_eq!(expected_rect_y, player_y - half_size);
     }
 
+    #[test]
+    fn test_triangle_vertices() {
+        let size = PLAYER_SIZE as f32;
+        let vertices = triangle_vertices(100.0, 200.0, size);
+
+        // The bounding box is the same as the square's
+        let min_x = vertices.iter().map(|v| v.x).fold(f32::MAX, f32::min);
+        let max_x = vertices.iter().map(|v| v.x).fold(f32::MIN, f32::max);
+        let min_y = vertices.iter().map(|v| v.y).fold(f32::MAX, f32::min);
+        let max_y = vertices.iter().map(|v| v.y).fold(f32::MIN, f32::max);
+        assert_eq!((min_x, max_x), (100.0 - size / 2.0, 100.0 + size / 2.0));
+        assert_eq!((min_y, max_y), (200.0 - size / 2.0, 200.0 + size / 2.0));
+
+        // Apex up and the same winding wherever the triangle is drawn
+        assert_eq!(vertices[0], vec2(100.0, 200.0 - size / 2.0));
+        let winding = |[a, b, c]: [Vec2; 3]| (b - a).perp_dot(c - a);
+        assert!(winding(vertices) < 0.0);
+        assert_eq!(winding(triangle_vertices(-50.0, 7.5, size)), winding(vertices));
+    }
+
     #[test]
     fn test_toolbar_responsive_layout() {
         let wide_screen_width = 1000.0;
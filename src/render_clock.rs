@@ -0,0 +1,216 @@
+use crate::constants::{RENDER_CLOCK_CATCH_UP_SCALE, SLOW_MOTION_SCALE};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Scaled time source for rendering-side systems such as interpolation playback.
+/// Networking and input keep using real time, so in slow motion the rendered world
+/// falls behind the authoritative one. When slow motion is turned off the clock catches
+/// up at a limited speed instead of jumping.
+#[derive(Debug, Clone)]
+pub struct RenderClock {
+    time: f64,
+    last_real_time: f64,
+    slow_motion: bool,
+}
+
+/// Implementation of the RenderClock
+impl RenderClock {
+    /// Creates a new RenderClock in sync with the given real time
+    pub fn new(real_time: f64) -> Self {
+        Self {
+            time: real_time,
+            last_real_time: real_time,
+            slow_motion: false,
+        }
+    }
+
+    /// Advances the clock to the given real time, should be called once per frame
+    pub fn update(&mut self, real_time: f64) -> f64 {
+        let delta = (real_time - self.last_real_time).max(0.0);
+        self.last_real_time = real_time;
+
+        if self.slow_motion {
+            self.time += delta * SLOW_MOTION_SCALE;
+        } else {
+            // Run faster until caught up with real time, without overshooting it
+            let behind = (real_time - self.time).max(0.0);
+            self.time += (delta * RENDER_CLOCK_CATCH_UP_SCALE).min(behind);
+        }
+        self.time
+    }
+
+    /// Current render time in seconds
+    pub fn now(&self) -> f64 {
+        self.time
+    }
+
+    /// Turns slow motion on or off
+    pub fn toggle_slow_motion(&mut self) {
+        self.slow_motion = !self.slow_motion;
+    }
+
+    /// Checks if slow motion is on
+    pub fn is_slow_motion(&self) -> bool {
+        self.slow_motion
+    }
+
+    /// How far the render clock is behind real time, in seconds
+    pub fn lag(&self) -> f64 {
+        (self.last_real_time - self.time).max(0.0)
+    }
+}
+
+/// Simulated clock error of the client, to test how well the server clock estimate copes with it.
+/// The local time runs off by a constant rate and can jump by sudden steps.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClockDrift {
+    rate_ppm: f64,
+    steps: Vec<(f64, f64)>, // Real time of a step and how far the clock jumps
+}
+
+/// Implementation of the ClockDrift
+impl ClockDrift {
+    /// Creates a drift of the given parts per million, positive makes the clock run fast
+    pub fn new(rate_ppm: f64) -> Self {
+        Self {
+            rate_ppm,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Creates a drift with a number of steps of the given size at random times within the span.
+    /// The same seed always gives the same steps, each one forwards or backwards.
+    pub fn with_random_steps(rate_ppm: f64, seed: u64, count: usize, span: f64, step: f64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut drift = Self::new(rate_ppm);
+        for _ in 0..count {
+            let amount = if rng.random_bool(0.5) { step } else { -step };
+            drift.step_at(rng.random_range(0.0..span), amount);
+        }
+        drift
+    }
+
+    /// Makes the clock jump by the amount from the given real time on
+    pub fn step_at(&mut self, real_time: f64, amount: f64) {
+        self.steps.push((real_time, amount));
+    }
+
+    /// Checks if the clock is off at all
+    pub fn is_enabled(&self) -> bool {
+        self.rate_ppm != 0.0 || !self.steps.is_empty()
+    }
+
+    /// The drifted local time at the given real time
+    pub fn apply(&self, real_time: f64) -> f64 {
+        let stepped: f64 = self.steps.iter()
+            .filter(|(at, _)| *at <= real_time)
+            .map(|(_, amount)| amount)
+            .sum();
+        real_time + real_time * self.rate_ppm / 1_000_000.0 + stepped
+    }
+}
+
+/// Tests for the RenderClock
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f64 = 1.0 / 60.0;
+
+    // Helper function to run the clock for a number of frames, returning the real time afterwards
+    fn run_frames(clock: &mut RenderClock, start: f64, frames: usize) -> f64 {
+        let mut real_time = start;
+        for _ in 0..frames {
+            real_time += FRAME;
+            clock.update(real_time);
+        }
+        real_time
+    }
+
+    #[test]
+    fn test_follows_real_time() {
+        let mut clock = RenderClock::new(10.0);
+        assert_eq!(clock.now(), 10.0);
+
+        let real_time = run_frames(&mut clock, 10.0, 60);
+        assert!((clock.now() - real_time).abs() < 1e-9);
+        assert_eq!(clock.lag(), 0.0);
+    }
+
+    #[test]
+    fn test_slow_motion_scales_time() {
+        let mut clock = RenderClock::new(0.0);
+        clock.toggle_slow_motion();
+        assert!(clock.is_slow_motion());
+
+        clock.update(2.0);
+        assert!((clock.now() - 2.0 * SLOW_MOTION_SCALE).abs() < 1e-9);
+        assert!((clock.lag() - 2.0 * (1.0 - SLOW_MOTION_SCALE)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_time_never_goes_backwards() {
+        let mut clock = RenderClock::new(5.0);
+
+        // A real time from the past doesn't move the clock back
+        clock.update(4.0);
+        assert_eq!(clock.now(), 5.0);
+    }
+
+    #[test]
+    fn test_toggling_back_catches_up_without_jumps() {
+        let mut clock = RenderClock::new(0.0);
+        clock.toggle_slow_motion();
+        let mut real_time = run_frames(&mut clock, 0.0, 120);
+        assert!(clock.lag() > 1.0);
+
+        clock.toggle_slow_motion();
+        let mut previous = clock.now();
+        for _ in 0..600 {
+            real_time += FRAME;
+            let now = clock.update(real_time);
+
+            // Never jumps more than one frame beyond a normal frame, and never overshoots real time
+            assert!(now - previous - FRAME <= FRAME + 1e-9);
+            assert!(now <= real_time + 1e-9);
+            previous = now;
+        }
+
+        // Back in sync with real time
+        assert!((clock.now() - real_time).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_drift_skews_time() {
+        assert_eq!(ClockDrift::default().apply(100.0), 100.0);
+        assert!(!ClockDrift::default().is_enabled());
+
+        // 500 ppm runs half a millisecond fast every second
+        let drift = ClockDrift::new(500.0);
+        assert!((drift.apply(100.0) - 100.05).abs() < 1e-9);
+        assert!((ClockDrift::new(-500.0).apply(100.0) - 99.95).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_steps_apply_from_their_time_on() {
+        let mut drift = ClockDrift::new(0.0);
+        drift.step_at(10.0, 0.2);
+        assert!(drift.is_enabled());
+
+        assert_eq!(drift.apply(9.0), 9.0);
+        assert!((drift.apply(10.0) - 10.2).abs() < 1e-9);
+        assert!((drift.apply(20.0) - 20.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_random_steps_are_deterministic() {
+        let a = ClockDrift::with_random_steps(100.0, 3, 5, 60.0, 0.2);
+        let b = ClockDrift::with_random_steps(100.0, 3, 5, 60.0, 0.2);
+        assert_eq!(a, b);
+        assert_ne!(a, ClockDrift::with_random_steps(100.0, 4, 5, 60.0, 0.2));
+
+        assert_eq!(a.steps.len(), 5);
+        assert!(a.steps.iter().all(|(at, amount)| (0.0..60.0).contains(at) && amount.abs() == 0.2));
+    }
+}
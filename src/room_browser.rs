@@ -0,0 +1,110 @@
+use crate::types::RoomInfo;
+
+/// Rooms the server listed and the one the player picked, separate from rendering.
+/// The pick is kept by name, so it survives the list being refreshed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoomBrowser {
+    rooms: Vec<RoomInfo>,
+    selected: Option<String>, // Name of the picked room, None joins the default room
+}
+
+/// Implementation of the RoomBrowser
+impl RoomBrowser {
+    /// Creates a browser with no rooms listed yet, with a room picked up front if one is given
+    pub fn new(selected: Option<String>) -> Self {
+        Self { rooms: Vec::new(), selected }
+    }
+
+    /// Replaces the listed rooms with a new list from the server
+    pub fn set_rooms(&mut self, rooms: Vec<RoomInfo>) {
+        self.rooms = rooms;
+    }
+
+    /// The listed rooms, in the order the server sent them
+    pub fn rooms(&self) -> &[RoomInfo] {
+        &self.rooms
+    }
+
+    /// Name of the picked room
+    pub fn selected_name(&self) -> Option<&str> {
+        self.selected.as_deref()
+    }
+
+    /// Index of the picked room in the list, None if nothing is picked or it isn't listed
+    pub fn selected_index(&self) -> Option<usize> {
+        let name = self.selected.as_deref()?;
+        self.rooms.iter().position(|room| room.name == name)
+    }
+
+    /// Checks if the picked room is listed as needing a password
+    pub fn selected_needs_password(&self) -> bool {
+        self.selected_index().is_some_and(|index| self.rooms[index].password_required)
+    }
+
+    /// Moves the pick down the list by delta rooms, or up for a negative delta, stopping at the ends.
+    /// Without a listed pick, moving down starts at the first room and moving up at the last.
+    pub fn move_selection(&mut self, delta: i32) {
+        if delta == 0 || self.rooms.is_empty() {
+            return;
+        }
+        let last = self.rooms.len() as i32 - 1;
+        let index = match self.selected_index() {
+            Some(index) => (index as i32 + delta).clamp(0, last),
+            None if delta > 0 => 0,
+            None => last,
+        };
+        self.selected = Some(self.rooms[index as usize].name.clone());
+    }
+}
+
+/// Tests for the room browser
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to create a listed room
+    fn room(name: &str, password_required: bool) -> RoomInfo {
+        RoomInfo { name: name.to_string(), players: 0, password_required }
+    }
+
+    #[test]
+    fn test_move_selection() {
+        let mut browser = RoomBrowser::new(None);
+        browser.move_selection(1);
+        assert_eq!(browser.selected_name(), None); // Nothing listed yet
+
+        browser.set_rooms(vec![room("arena", false), room("lobby", false), room("vault", true)]);
+        browser.move_selection(1);
+        assert_eq!(browser.selected_name(), Some("arena"));
+        browser.move_selection(-1);
+        assert_eq!(browser.selected_name(), Some("arena"));
+        browser.move_selection(5);
+        assert_eq!(browser.selected_name(), Some("vault"));
+        assert!(browser.selected_needs_password());
+
+        let mut browser = RoomBrowser::new(None);
+        browser.set_rooms(vec![room("arena", false), room("lobby", false)]);
+        browser.move_selection(-1);
+        assert_eq!(browser.selected_name(), Some("lobby"));
+    }
+
+    #[test]
+    fn test_selection_survives_refresh() {
+        let mut browser = RoomBrowser::new(Some("vault".to_string()));
+        assert_eq!(browser.selected_index(), None);
+        assert!(!browser.selected_needs_password()); // Unknown until listed
+
+        browser.set_rooms(vec![room("lobby", false), room("vault", true)]);
+        assert_eq!(browser.selected_index(), Some(1));
+
+        // A new room sorts in front of the pick
+        browser.set_rooms(vec![room("arena", false), room("lobby", false), room("vault", true)]);
+        assert_eq!(browser.selected_index(), Some(2));
+        assert!(browser.selected_needs_password());
+
+        // The pick is kept when its room disappears, the server decides if it can still be joined
+        browser.set_rooms(vec![room("lobby", false)]);
+        assert_eq!(browser.selected_name(), Some("vault"));
+        assert_eq!(browser.selected_index(), None);
+    }
+}
@@ -0,0 +1,691 @@
+use crate::constants::{DEFAULT_ROOM, EMPTY_ROOM_GRACE, MAX_PASSWORD_CHARS, MAX_ROOMS, MAX_ROOM_NAME_CHARS, MAX_TICK_RATE, MIN_TICK_RATE};
+use crate::game::Game;
+use crate::profiler::ProfileTotals;
+use crate::server_config::tick_interval_of;
+use crate::stats_query::{TickPercentiles, TickTimings};
+use crate::types::{RejectReason, RoomInfo};
+
+use serde::Deserialize;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// A room listed in the server config file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RoomSettings {
+    pub name: String,
+    #[serde(default)]
+    pub password: Option<String>, // Players have to send this password to join
+    #[serde(default)]
+    pub tick_rate: Option<u32>,   // Broadcast ticks per second of this room, the server's tick rate by default
+}
+
+/// Rooms section of the server config file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct RoomConfig {
+    #[serde(default)]
+    pub rooms: Vec<RoomSettings>,
+    #[serde(default)]
+    pub fixed: bool, // Only the listed rooms exist, joins to any other room are rejected
+}
+
+/// Implementation of the RoomConfig
+impl RoomConfig {
+    /// Loads the room config from a JSON file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        config.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(config)
+    }
+
+    /// Checks the tick rate of every room is within the range the server accepts, and that clients can send its password
+    pub fn validate(&self) -> Result<(), String> {
+        for room in &self.rooms {
+            if let Some(tick_rate) = room.tick_rate.filter(|rate| !(MIN_TICK_RATE..=MAX_TICK_RATE).contains(rate)) {
+                return Err(format!("tick_rate {} of room {} is out of range, use {} to {} ticks per second", tick_rate, room.name, MIN_TICK_RATE, MAX_TICK_RATE));
+            }
+            if room.password.as_ref().is_some_and(|password| password.chars().count() > MAX_PASSWORD_CHARS) {
+                return Err(format!("password of room {} is longer than {} chars, clients could never send it", room.name, MAX_PASSWORD_CHARS));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the broadcast task of a room is started with
+pub struct RoomTaskStart {
+    pub name: String,
+    pub tick_interval: Duration,          // Time between the room's ticks
+    pub timings: Arc<Mutex<TickTimings>>, // Durations of the room's ticks, for the stats
+}
+
+/// Creates the broadcast task of a room
+type RoomTaskFactory = Box<dyn Fn(RoomTaskStart) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Handle of the broadcast task of a room, which stops the task when the room is removed
+struct RoomTask(JoinHandle<()>);
+
+/// Drop implementation for the RoomTask
+impl Drop for RoomTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Counts a running room task until its future is dropped, when it ends or once it is aborted
+struct RunningTask(Arc<AtomicUsize>);
+
+/// Drop implementation for the RunningTask
+impl Drop for RunningTask {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// One room with its own game
+struct Room {
+    game: Game,
+    password: Option<String>,
+    permanent: bool, // Configured rooms and the default room are never removed
+    empty_since: Option<Instant>,
+    tick_rate: Option<u32>,           // Configured tick rate, None for the server's
+    timings: Arc<Mutex<TickTimings>>, // Durations of the room's recent ticks, recorded by its task
+    task: Option<RoomTask>,           // Broadcast task of the room once the tasks are started
+}
+
+/// Implementation of the Room
+impl Room {
+    /// Creates an empty room
+    fn new(game: Game, password: Option<String>, permanent: bool) -> Self {
+        Self { game, password, permanent, empty_since: None, tick_rate: None, timings: Arc::default(), task: None }
+    }
+
+    /// Checks if a join with this password is let in
+    fn accepts(&self, password: Option<&str>) -> bool {
+        match &self.password {
+            Some(expected) => password == Some(expected.as_str()),
+            None => true,
+        }
+    }
+
+    /// Number of connected players
+    fn player_count(&self) -> usize {
+        self.game.active_player_addrs().len()
+    }
+}
+
+/// Every room of the server and the room each player is in. Unless the room list is fixed or
+/// MAX_ROOMS are open, joining a room that doesn't exist creates it, and such rooms are removed once they have been
+/// empty for EMPTY_ROOM_GRACE. Once the tasks are started every room has a broadcast task of its own,
+/// started with the room and stopped when it is removed.
+pub struct RoomRegistry {
+    rooms: BTreeMap<String, Room>,
+    members: HashMap<SocketAddr, String>, // Room of every player that joined one
+    fixed: bool,
+    default_room: String,
+    new_game: Box<dyn Fn() -> Game + Send + Sync>, // Creates the game of a new room
+    start_task: Option<RoomTaskFactory>,           // Creates the broadcast task of a room, None until the tasks are started
+    running_tasks: Arc<AtomicUsize>,               // Room tasks whose future wasn't dropped yet
+}
+
+/// Implementation of the RoomRegistry
+impl RoomRegistry {
+    /// Creates the configured rooms and the default room. The default room is DEFAULT_ROOM,
+    /// or the first configured room when the list is fixed and doesn't include it.
+    pub fn new(config: RoomConfig, new_game: impl Fn() -> Game + Send + Sync + 'static) -> Self {
+        let default_room = match config.rooms.first() {
+            Some(first) if config.fixed && !config.rooms.iter().any(|room| room.name == DEFAULT_ROOM) => first.name.clone(),
+            _ => DEFAULT_ROOM.to_string(),
+        };
+        let mut registry = Self {
+            rooms: BTreeMap::new(),
+            members: HashMap::new(),
+            fixed: config.fixed,
+            default_room,
+            new_game: Box::new(new_game),
+            start_task: None,
+            running_tasks: Arc::default(),
+        };
+        for settings in config.rooms {
+            let mut game = (registry.new_game)();
+            if let Some(tick_rate) = settings.tick_rate {
+                game.set_tick_interval(tick_interval_of(tick_rate));
+            }
+            let mut room = Room::new(game, settings.password, true);
+            room.tick_rate = settings.tick_rate;
+            registry.rooms.insert(settings.name, room);
+        }
+        if !registry.rooms.contains_key(&registry.default_room) {
+            let room = Room::new((registry.new_game)(), None, true);
+            registry.rooms.insert(registry.default_room.clone(), room);
+        }
+        registry
+    }
+
+    /// Name of the room players join when they don't name one
+    pub fn default_room(&self) -> &str {
+        &self.default_room
+    }
+
+    /// Trims a requested room name and cuts it to MAX_ROOM_NAME_CHARS, an empty name is the default room
+    pub fn resolve_name(&self, name: &str) -> String {
+        let name: String = name.trim().chars().take(MAX_ROOM_NAME_CHARS).collect();
+        if name.is_empty() {
+            self.default_room.clone()
+        } else {
+            name
+        }
+    }
+
+    /// Puts a player in a room, creating the room unless the list is fixed or MAX_ROOMS are open. A player
+    /// that was in another room is disconnected there. Returns the game of the room to connect the player to.
+    pub fn join(&mut self, addr: SocketAddr, room: &str, password: Option<&str>) -> Result<&mut Game, RejectReason> {
+        let name = self.resolve_name(room);
+        match self.rooms.get(&name) {
+            Some(room) if !room.accepts(password) => return Err(RejectReason::WrongPassword),
+            Some(_) => {}
+            None if self.fixed => return Err(RejectReason::UnknownRoom),
+            None if self.rooms.len() >= MAX_ROOMS => return Err(RejectReason::TooManyRooms),
+            None => {
+                let mut room = Room::new((self.new_game)(), None, false);
+                room.task = self.spawn_task(&name, &room);
+                self.rooms.insert(name.clone(), room);
+            }
+        }
+        self.leave_other_room(addr, &name);
+        self.members.insert(addr, name.clone());
+        let room = self.rooms.get_mut(&name).unwrap();
+        room.empty_since = None;
+        Ok(&mut room.game)
+    }
+
+    /// Resumes a session in whichever room holds its token, the token proves the player was let in.
+    /// An active session that moves to the new address leaves its old one behind, like a rebind.
+    /// Returns the room name and the player id, None if no room knows the token.
+    pub fn reconnect(&mut self, addr: SocketAddr, session_token: Uuid) -> Option<(String, Uuid)> {
+        let (name, id, old_addr) = self.rooms.iter_mut().find_map(|(name, room)| {
+            let old_addr = room.game.session_addr(&session_token);
+            room.game.reconnect_player(addr, session_token).map(|id| (name.clone(), id, old_addr))
+        })?;
+        self.leave_other_room(addr, &name);
+        if let Some(old_addr) = old_addr.filter(|old_addr| *old_addr != addr) {
+            self.members.remove(&old_addr);
+        }
+        self.members.insert(addr, name.clone());
+        Some((name, id))
+    }
+
+    /// Moves the active session with the token to an address that isn't in any room yet, used when a
+    /// client's port changed mid-session. Returns the room, player id and old address of the session.
+    pub fn rebind(&mut self, addr: SocketAddr, session_token: Uuid) -> Option<(String, Uuid, SocketAddr)> {
+        if self.members.contains_key(&addr) {
+            return None;
+        }
+        let (name, id, old_addr) = self.rooms.iter_mut()
+            .find_map(|(name, room)| room.game.rebind_session(addr, session_token).map(|(id, old)| (name.clone(), id, old)))?;
+        self.members.remove(&old_addr);
+        self.members.insert(addr, name.clone());
+        Some((name, id, old_addr))
+    }
+
+    /// Disconnects a player from the room it was in, unless that is the given room
+    fn leave_other_room(&mut self, addr: SocketAddr, name: &str) {
+        let Some(previous) = self.members.get(&addr) else {
+            return;
+        };
+        if previous != name {
+            if let Some(room) = self.rooms.get_mut(previous) {
+                room.game.disconnect_player(&addr);
+            }
+        }
+    }
+
+    /// Removes a player that quit from its room for good, returning the room and the player's id
+    pub fn leave(&mut self, addr: SocketAddr) -> Option<(String, Uuid)> {
+        let name = self.members.remove(&addr)?;
+        let id = self.rooms.get_mut(&name)?.game.leave_player(&addr)?;
+        Some((name, id))
+    }
+
+    /// Forgets the room of a player its game removed, such as one that timed out. A player that is
+    /// active in its room again is kept, it came back before its removal was handled.
+    pub fn forget(&mut self, addr: &SocketAddr) {
+        let Some(name) = self.members.get(addr) else {
+            return;
+        };
+        if !self.rooms.get(name).is_some_and(|room| room.game.is_active(addr)) {
+            self.members.remove(addr);
+        }
+    }
+
+    /// Name of the room a player is in
+    pub fn room_of(&self, addr: &SocketAddr) -> Option<&str> {
+        self.members.get(addr).map(String::as_str)
+    }
+
+    /// Game of the room a player is in
+    pub fn game_of_mut(&mut self, addr: &SocketAddr) -> Option<&mut Game> {
+        let name = self.members.get(addr)?;
+        self.rooms.get_mut(name).map(|room| &mut room.game)
+    }
+
+    /// Game of a room by name
+    pub fn game_mut(&mut self, name: &str) -> Option<&mut Game> {
+        self.rooms.get_mut(name).map(|room| &mut room.game)
+    }
+
+    /// Every room name with its game, sorted by name
+    pub fn games(&self) -> impl Iterator<Item = (&str, &Game)> {
+        self.rooms.iter().map(|(name, room)| (name.as_str(), &room.game))
+    }
+
+    /// Every room name with its game, sorted by name
+    pub fn games_mut(&mut self) -> impl Iterator<Item = (&str, &mut Game)> {
+        self.rooms.iter_mut().map(|(name, room)| (name.as_str(), &mut room.game))
+    }
+
+    /// Starts a broadcast task for every room, and from now on for every room created by a join. The
+    /// task of a room is aborted when the room is removed, and every task when the registry is dropped.
+    pub fn start_tasks<F>(&mut self, task: impl Fn(RoomTaskStart) -> F + Send + Sync + 'static)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.start_task = Some(Box::new(move |start| Box::pin(task(start))));
+        let tasks: Vec<(String, Option<RoomTask>)> = self.rooms.iter()
+            .map(|(name, room)| (name.clone(), self.spawn_task(name, room)))
+            .collect();
+        for (name, task) in tasks {
+            if let Some(room) = self.rooms.get_mut(&name) {
+                room.task = task;
+            }
+        }
+    }
+
+    /// Aborts the task of every room and starts no more, used on shutdown
+    pub fn stop_tasks(&mut self) {
+        self.start_task = None;
+        for room in self.rooms.values_mut() {
+            room.task = None;
+        }
+    }
+
+    /// Number of room tasks that are still running. An aborted task counts until the runtime dropped it.
+    pub fn running_tasks(&self) -> usize {
+        self.running_tasks.load(Ordering::Relaxed)
+    }
+
+    /// Spawns the broadcast task of a room, None before the tasks are started
+    fn spawn_task(&self, name: &str, room: &Room) -> Option<RoomTask> {
+        let start_task = self.start_task.as_ref()?;
+        let task = start_task(RoomTaskStart {
+            name: name.to_string(),
+            tick_interval: room.game.tick_interval(),
+            timings: Arc::clone(&room.timings),
+        });
+        self.running_tasks.fetch_add(1, Ordering::Relaxed);
+        let running = RunningTask(Arc::clone(&self.running_tasks));
+        Some(RoomTask(tokio::spawn(async move {
+            let _running = running;
+            task.await;
+        })))
+    }
+
+    /// Tick rate the room is configured with, None if it ticks at the server's rate
+    pub fn configured_tick_rate(&self, name: &str) -> Option<u32> {
+        self.rooms.get(name)?.tick_rate
+    }
+
+    /// Tick timing percentiles of a room
+    pub fn tick_percentiles(&self, name: &str) -> Option<TickPercentiles> {
+        self.rooms.get(name).map(|room| room.timings.lock().unwrap().percentiles())
+    }
+
+    /// Time of the profiled scopes of a room's recent ticks
+    pub fn tick_profile(&self, name: &str) -> Option<ProfileTotals> {
+        self.rooms.get(name).map(|room| room.timings.lock().unwrap().profile())
+    }
+
+    /// Tick timing percentiles of every room's ticks taken together
+    pub fn combined_tick_percentiles(&self) -> TickPercentiles {
+        let timings: Vec<_> = self.rooms.values().map(|room| room.timings.lock().unwrap()).collect();
+        TickTimings::combined(timings.iter().map(|timings| &**timings))
+    }
+
+    /// Number of rooms
+    pub fn len(&self) -> usize {
+        self.rooms.len()
+    }
+
+    /// Checks if there are no rooms, which never happens since the default room always exists
+    pub fn is_empty(&self) -> bool {
+        self.rooms.is_empty()
+    }
+
+    /// Lists every room with its player count and whether it needs a password, sorted by name
+    pub fn listing(&self) -> Vec<RoomInfo> {
+        self.rooms.iter()
+            .map(|(name, room)| RoomInfo {
+                name: name.clone(),
+                players: room.player_count() as u32,
+                password_required: room.password.is_some(),
+            })
+            .collect()
+    }
+
+    /// Removes the rooms created by a join that have been empty for EMPTY_ROOM_GRACE, aborting their tasks.
+    /// Returns the names of the removed rooms.
+    pub fn remove_empty_rooms(&mut self, now: Instant) -> Vec<String> {
+        let mut removed = Vec::new();
+        for (name, room) in self.rooms.iter_mut().filter(|(_, room)| !room.permanent) {
+            if room.player_count() > 0 {
+                room.empty_since = None;
+                continue;
+            }
+            let empty_since = *room.empty_since.get_or_insert(now);
+            if now.saturating_duration_since(empty_since) >= EMPTY_ROOM_GRACE {
+                removed.push(name.clone());
+            }
+        }
+        for name in &removed {
+            self.rooms.remove(name);
+        }
+        self.members.retain(|_, room| !removed.contains(room));
+        removed
+    }
+}
+
+/// Tests for the room registry
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::BROADCAST_INTERVAL;
+
+    // Helper function to create a registry from rooms with optional passwords
+    fn registry(rooms: &[(&str, Option<&str>)], fixed: bool) -> RoomRegistry {
+        let rooms = rooms.iter()
+            .map(|(name, password)| RoomSettings { name: name.to_string(), password: password.map(str::to_string), tick_rate: None })
+            .collect();
+        RoomRegistry::new(RoomConfig { rooms, fixed }, Game::new)
+    }
+
+    // Helper function to create a player address
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_rooms_are_created_on_first_join() {
+        let mut rooms = registry(&[], false);
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms.default_room(), DEFAULT_ROOM);
+
+        rooms.join(addr(1), "", None).unwrap().connect_player(addr(1));
+        assert_eq!(rooms.room_of(&addr(1)), Some(DEFAULT_ROOM));
+
+        rooms.join(addr(2), "  arena ", None).unwrap().connect_player(addr(2));
+        assert_eq!(rooms.room_of(&addr(2)), Some("arena"));
+        let listing = rooms.listing();
+        assert_eq!(listing, vec![
+            RoomInfo { name: "arena".to_string(), players: 1, password_required: false },
+            RoomInfo { name: DEFAULT_ROOM.to_string(), players: 1, password_required: false },
+        ]);
+
+        // Long names are cut, so two names that differ past the limit end up in the same room
+        let long = "x".repeat(MAX_ROOM_NAME_CHARS + 5);
+        assert_eq!(rooms.resolve_name(&long).chars().count(), MAX_ROOM_NAME_CHARS);
+    }
+
+    #[test]
+    fn test_password_check() {
+        let mut rooms = registry(&[("vault", Some("hunter2"))], false);
+        assert!(rooms.listing().iter().any(|room| room.name == "vault" && room.password_required));
+
+        assert_eq!(rooms.join(addr(1), "vault", None).err(), Some(RejectReason::WrongPassword));
+        assert_eq!(rooms.join(addr(1), "vault", Some("hunter3")).err(), Some(RejectReason::WrongPassword));
+        assert_eq!(rooms.room_of(&addr(1)), None);
+        assert!(rooms.join(addr(1), "vault", Some("hunter2")).is_ok());
+        assert_eq!(rooms.room_of(&addr(1)), Some("vault"));
+
+        // Rooms without a password ignore one that is sent anyway
+        assert!(rooms.join(addr(2), DEFAULT_ROOM, Some("hunter2")).is_ok());
+    }
+
+    #[test]
+    fn test_fixed_room_list() {
+        let mut rooms = registry(&[("red", None), ("blue", Some("pw"))], true);
+        assert_eq!(rooms.default_room(), "red");
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms.join(addr(1), "green", None).err(), Some(RejectReason::UnknownRoom));
+        assert!(rooms.join(addr(1), "", None).is_ok());
+        assert_eq!(rooms.room_of(&addr(1)), Some("red"));
+    }
+
+    #[test]
+    fn test_room_count_is_capped() {
+        let mut rooms = registry(&[("vault", None)], false);
+        // The default room and the configured one are open already
+        for room in 2..MAX_ROOMS {
+            assert!(rooms.join(addr(room as u16), &format!("room {}", room), None).is_ok());
+        }
+        assert_eq!(rooms.games().count(), MAX_ROOMS);
+
+        // Names that would open one more room are refused, existing rooms can still be joined
+        assert_eq!(rooms.join(addr(1000), "one too many", None).err(), Some(RejectReason::TooManyRooms));
+        assert_eq!(rooms.room_of(&addr(1000)), None);
+        assert!(rooms.game_mut("one too many").is_none());
+        assert!(rooms.join(addr(1000), "vault", None).is_ok());
+        assert!(rooms.join(addr(1001), "", None).is_ok());
+    }
+
+    #[test]
+    fn test_switching_rooms_leaves_the_old_game() {
+        let mut rooms = registry(&[], false);
+        rooms.join(addr(1), "a", None).unwrap().connect_player(addr(1));
+        rooms.join(addr(1), "b", None).unwrap().connect_player(addr(1));
+
+        assert!(rooms.game_mut("a").unwrap().active_player_addrs().is_empty());
+        assert_eq!(rooms.game_of_mut(&addr(1)).unwrap().active_player_addrs(), vec![addr(1)]);
+    }
+
+    #[test]
+    fn test_reconnect_finds_the_room_of_the_session() {
+        let mut rooms = registry(&[("vault", Some("pw"))], false);
+        let game = rooms.join(addr(1), "vault", Some("pw")).unwrap();
+        let id = game.connect_player(addr(1));
+        let token = game.build_welcome(&addr(1), false).unwrap().session_token;
+
+        // The token is enough to get back in, without the password
+        assert_eq!(rooms.reconnect(addr(2), token), Some(("vault".to_string(), id)));
+        assert_eq!(rooms.room_of(&addr(2)), Some("vault"));
+        assert_eq!(rooms.reconnect(addr(3), Uuid::new_v4()), None);
+        assert_eq!(rooms.room_of(&addr(3)), None);
+    }
+
+    #[test]
+    fn test_reconnect_of_an_active_session_forgets_the_old_address() {
+        let mut rooms = registry(&[], false);
+        let game = rooms.join(addr(1), "arena", None).unwrap();
+        let id = game.connect_player(addr(1));
+        let token = game.build_welcome(&addr(1), false).unwrap().session_token;
+        rooms.join(addr(2), "arena", None).unwrap().connect_player(addr(2));
+
+        // The session is still active at the old address when the client reconnects from a new one
+        assert_eq!(rooms.reconnect(addr(3), token), Some(("arena".to_string(), id)));
+        assert_eq!(rooms.room_of(&addr(3)), Some("arena"));
+        assert_eq!(rooms.room_of(&addr(1)), None);
+        assert_eq!(rooms.room_of(&addr(2)), Some("arena"));
+
+        // Reconnecting from the same address keeps it
+        assert_eq!(rooms.reconnect(addr(3), token), Some(("arena".to_string(), id)));
+        assert_eq!(rooms.room_of(&addr(3)), Some("arena"));
+    }
+
+    #[test]
+    fn test_leaving_removes_the_member_and_its_session() {
+        let mut rooms = registry(&[], false);
+        let game = rooms.join(addr(1), "arena", None).unwrap();
+        let id = game.connect_player(addr(1));
+        let token = game.build_welcome(&addr(1), false).unwrap().session_token;
+
+        assert_eq!(rooms.leave(addr(1)), Some(("arena".to_string(), id)));
+        assert_eq!(rooms.room_of(&addr(1)), None);
+        assert!(rooms.game_mut("arena").unwrap().active_player_addrs().is_empty());
+        assert_eq!(rooms.reconnect(addr(2), token), None);
+        assert_eq!(rooms.leave(addr(1)), None);
+    }
+
+    #[test]
+    fn test_forget_drops_removed_players_only() {
+        let mut rooms = registry(&[], false);
+        for port in [1, 2] {
+            rooms.join(addr(port), "arena", None).unwrap().connect_player(addr(port));
+        }
+        rooms.game_mut("arena").unwrap().disconnect_player(&addr(1));
+
+        rooms.forget(&addr(1));
+        assert_eq!(rooms.room_of(&addr(1)), None);
+
+        // A player still in its game stays a member
+        rooms.forget(&addr(2));
+        assert_eq!(rooms.room_of(&addr(2)), Some("arena"));
+        rooms.forget(&addr(3));
+    }
+
+    #[test]
+    fn test_rebind_moves_the_member_to_the_new_address() {
+        let mut rooms = registry(&[], false);
+        let game = rooms.join(addr(1), "arena", None).unwrap();
+        let id = game.connect_player(addr(1));
+        let token = game.build_welcome(&addr(1), false).unwrap().session_token;
+        rooms.join(addr(2), "arena", None).unwrap().connect_player(addr(2));
+
+        assert_eq!(rooms.rebind(addr(3), token), Some(("arena".to_string(), id, addr(1))));
+        assert_eq!(rooms.room_of(&addr(3)), Some("arena"));
+        assert_eq!(rooms.room_of(&addr(1)), None);
+
+        // Known addresses and unknown tokens are left alone
+        assert_eq!(rooms.rebind(addr(2), token), None);
+        assert_eq!(rooms.rebind(addr(4), Uuid::new_v4()), None);
+        assert_eq!(rooms.game_mut("arena").unwrap().active_player_addrs().len(), 2);
+    }
+
+    #[test]
+    fn test_empty_rooms_are_removed_after_grace_period() {
+        let mut rooms = registry(&[("kept", None)], false);
+        rooms.join(addr(1), "temp", None).unwrap().connect_player(addr(1));
+        let start = Instant::now();
+
+        // A room with players is kept
+        assert!(rooms.remove_empty_rooms(start).is_empty());
+
+        // Once empty it has the grace period to fill up again
+        rooms.game_of_mut(&addr(1)).unwrap().disconnect_player(&addr(1));
+        assert!(rooms.remove_empty_rooms(start).is_empty());
+        assert!(rooms.remove_empty_rooms(start + EMPTY_ROOM_GRACE / 2).is_empty());
+        assert_eq!(rooms.remove_empty_rooms(start + EMPTY_ROOM_GRACE), vec!["temp".to_string()]);
+        assert_eq!(rooms.room_of(&addr(1)), None);
+
+        // Configured rooms and the default room stay, even when empty
+        let names: Vec<String> = rooms.listing().into_iter().map(|room| room.name).collect();
+        assert_eq!(names, vec!["kept".to_string(), DEFAULT_ROOM.to_string()]);
+    }
+
+    #[test]
+    fn test_room_config_file() {
+        let json = r#"{ "rooms": [{ "name": "lobby" }, { "name": "vault", "password": "pw" }], "fixed": true }"#;
+        let config: RoomConfig = serde_json::from_str(json).unwrap();
+        assert!(config.fixed);
+        assert_eq!(config.rooms[1], RoomSettings { name: "vault".to_string(), password: Some("pw".to_string()), tick_rate: None });
+
+        // Everything is optional
+        assert_eq!(serde_json::from_str::<RoomConfig>("{}").unwrap(), RoomConfig::default());
+    }
+
+    #[test]
+    fn test_rooms_tick_at_their_own_rate() {
+        let json = r#"{ "rooms": [{ "name": "casual", "tick_rate": 20 }, { "name": "testing", "tick_rate": 60 }] }"#;
+        let config: RoomConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        let mut rooms = RoomRegistry::new(config, || {
+            let mut game = Game::new();
+            game.set_tick_interval(Duration::from_millis(33));
+            game
+        });
+
+        // Configured rates win, the default room and rooms created by a join tick at the server's rate
+        assert_eq!(rooms.game_mut("casual").unwrap().build_snapshot().tick_interval_ms, 50);
+        assert_eq!(rooms.game_mut("testing").unwrap().build_snapshot().tick_interval_ms, 16);
+        assert_eq!(rooms.game_mut(DEFAULT_ROOM).unwrap().tick_interval(), Duration::from_millis(33));
+        assert_eq!(rooms.join(addr(1), "new", None).unwrap().tick_interval(), Duration::from_millis(33));
+        assert_eq!((rooms.configured_tick_rate("casual"), rooms.configured_tick_rate("new")), (Some(20), None));
+
+        let too_fast: RoomConfig = serde_json::from_str(r#"{ "rooms": [{ "name": "turbo", "tick_rate": 500 }] }"#).unwrap();
+        assert_eq!(too_fast.validate().unwrap_err(), "tick_rate 500 of room turbo is out of range, use 10 to 120 ticks per second");
+        let long_password = RoomConfig { rooms: vec![RoomSettings { name: "vault".to_string(), password: Some("x".repeat(MAX_PASSWORD_CHARS + 1)), tick_rate: None }], fixed: false };
+        assert_eq!(long_password.validate().unwrap_err(), "password of room vault is longer than 64 chars, clients could never send it");
+    }
+
+    #[tokio::test]
+    async fn test_room_tasks_stop_with_their_rooms() {
+        let mut rooms = registry(&[("kept", Some("pw"))], false);
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let started_clone = Arc::clone(&started);
+        rooms.start_tasks(move |start| {
+            started_clone.lock().unwrap().push((start.name, start.tick_interval));
+            std::future::pending()
+        });
+        let baseline = rooms.running_tasks();
+        assert_eq!(baseline, 2);
+
+        // A task is spawned with every room a join creates, joining an existing room spawns none
+        let created = (MAX_ROOMS - 2) as u16;
+        for port in 1..=created {
+            let name = format!("room-{}", port);
+            rooms.join(addr(port), &name, None).unwrap().connect_player(addr(port));
+            rooms.join(addr(port + 1000), &name, None).unwrap().connect_player(addr(port + 1000));
+        }
+        assert_eq!(rooms.running_tasks(), baseline + created as usize);
+        assert_eq!(started.lock().unwrap().len(), MAX_ROOMS);
+        assert!(started.lock().unwrap().iter().all(|(_, interval)| *interval == BROADCAST_INTERVAL));
+
+        // Emptied rooms are removed after the grace period and their tasks aborted, none is left behind
+        for port in 1..=created {
+            rooms.game_of_mut(&addr(port)).unwrap().disconnect_player(&addr(port));
+            rooms.game_of_mut(&addr(port + 1000)).unwrap().disconnect_player(&addr(port + 1000));
+        }
+        let start = Instant::now();
+        assert!(rooms.remove_empty_rooms(start).is_empty());
+        assert_eq!(rooms.remove_empty_rooms(start + EMPTY_ROOM_GRACE).len(), created as usize);
+        for _ in 0..100 {
+            if rooms.running_tasks() == baseline {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(rooms.running_tasks(), baseline);
+
+        // Stopping aborts the tasks of the rooms that stay
+        rooms.stop_tasks();
+        for _ in 0..100 {
+            if rooms.running_tasks() == 0 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(rooms.running_tasks(), 0);
+        rooms.join(addr(1), "later", None).unwrap();
+        assert_eq!(rooms.running_tasks(), 0);
+    }
+}
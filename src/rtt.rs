@@ -0,0 +1,209 @@
+use crate::constants::{RTT_MAX_PENDING_PINGS, RTT_OUTLIER_FACTOR, RTT_OUTLIERS_TO_ACCEPT, RTT_PING_EXPIRY, RTT_SMOOTHING};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Estimates the round trip time to a peer from ping nonces and their echoes.
+/// Samples are smoothed, and single spikes are rejected as outliers.
+#[derive(Debug, Clone)]
+pub struct RttEstimator {
+    next_nonce: u32,
+    pending: VecDeque<(u32, Instant)>, // (nonce, sent at)
+    smoothed: Option<f64>,             // Smoothed RTT in milliseconds
+    consecutive_outliers: u32,
+}
+
+/// Implementation of the RttEstimator
+impl RttEstimator {
+    /// Creates a new RttEstimator without any samples
+    pub fn new() -> Self {
+        Self {
+            next_nonce: 0,
+            pending: VecDeque::new(),
+            smoothed: None,
+            consecutive_outliers: 0,
+        }
+    }
+
+    /// Registers a ping sent at the given time and returns its nonce
+    pub fn start_ping(&mut self, now: Instant) -> u32 {
+        let nonce = self.next_nonce;
+        self.next_nonce = self.next_nonce.wrapping_add(1);
+
+        // Forget pings that were lost
+        self.pending.retain(|(_, sent_at)| now.saturating_duration_since(*sent_at) < RTT_PING_EXPIRY);
+        if self.pending.len() >= RTT_MAX_PENDING_PINGS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((nonce, now));
+        nonce
+    }
+
+    /// Handles the echo of a ping. Returns the measured RTT if the nonce was pending,
+    /// unknown or duplicated nonces are ignored.
+    pub fn on_pong(&mut self, nonce: u32, now: Instant) -> Option<Duration> {
+        let index = self.pending.iter().position(|(pending, _)| *pending == nonce)?;
+        let (_, sent_at) = self.pending.remove(index)?;
+        let sample = now.saturating_duration_since(sent_at);
+        self.add_sample(sample.as_secs_f64() * 1000.0);
+        Some(sample)
+    }
+
+    /// Adds an RTT sample in milliseconds, rejecting single outliers
+    fn add_sample(&mut self, sample_ms: f64) {
+        let Some(smoothed) = self.smoothed else {
+            self.smoothed = Some(sample_ms);
+            return;
+        };
+
+        // A spike far above the current estimate is ignored, unless it keeps happening
+        if sample_ms > smoothed * RTT_OUTLIER_FACTOR {
+            self.consecutive_outliers += 1;
+            if self.consecutive_outliers < RTT_OUTLIERS_TO_ACCEPT {
+                return;
+            }
+            // The latency really changed, start over from the new value
+            self.consecutive_outliers = 0;
+            self.smoothed = Some(sample_ms);
+            return;
+        }
+
+        self.consecutive_outliers = 0;
+        self.smoothed = Some(smoothed + (sample_ms - smoothed) * RTT_SMOOTHING);
+    }
+
+    /// The smoothed RTT in whole milliseconds, if any sample has been received
+    pub fn rtt_ms(&self) -> Option<u32> {
+        self.smoothed.map(|rtt| rtt.round() as u32)
+    }
+
+    /// Number of pings waiting for an echo
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Default implementation for the RttEstimator
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tests for the RttEstimator
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to ping and answer after the given number of milliseconds
+    fn ping_pong(estimator: &mut RttEstimator, start: Instant, rtt_ms: u64) -> Option<Duration> {
+        let nonce = estimator.start_ping(start);
+        estimator.on_pong(nonce, start + Duration::from_millis(rtt_ms))
+    }
+
+    #[test]
+    fn test_new_estimator() {
+        let estimator = RttEstimator::new();
+        assert_eq!(estimator.rtt_ms(), None);
+        assert_eq!(estimator.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_first_sample_sets_estimate() {
+        let mut estimator = RttEstimator::new();
+        let sample = ping_pong(&mut estimator, Instant::now(), 80);
+
+        assert_eq!(sample, Some(Duration::from_millis(80)));
+        assert_eq!(estimator.rtt_ms(), Some(80));
+        assert_eq!(estimator.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_unknown_and_duplicate_nonces_are_ignored() {
+        let mut estimator = RttEstimator::new();
+        let start = Instant::now();
+        let nonce = estimator.start_ping(start);
+
+        // Unknown nonce
+        assert_eq!(estimator.on_pong(nonce + 100, start + Duration::from_millis(10)), None);
+        assert_eq!(estimator.rtt_ms(), None);
+
+        // First echo counts, the duplicate doesn't
+        assert!(estimator.on_pong(nonce, start + Duration::from_millis(50)).is_some());
+        assert_eq!(estimator.on_pong(nonce, start + Duration::from_millis(90)), None);
+        assert_eq!(estimator.rtt_ms(), Some(50));
+    }
+
+    #[test]
+    fn test_out_of_order_pongs_are_matched_by_nonce() {
+        let mut estimator = RttEstimator::new();
+        let start = Instant::now();
+        let first = estimator.start_ping(start);
+        let second = estimator.start_ping(start + Duration::from_millis(100));
+
+        // The second echo arrives first
+        assert_eq!(estimator.on_pong(second, start + Duration::from_millis(140)), Some(Duration::from_millis(40)));
+        assert_eq!(estimator.on_pong(first, start + Duration::from_millis(150)), Some(Duration::from_millis(150)));
+    }
+
+    #[test]
+    fn test_pending_pings_are_capped_and_expire() {
+        let mut estimator = RttEstimator::new();
+        let start = Instant::now();
+
+        let first = estimator.start_ping(start);
+        for _ in 0..RTT_MAX_PENDING_PINGS {
+            estimator.start_ping(start);
+        }
+        assert_eq!(estimator.pending_count(), RTT_MAX_PENDING_PINGS);
+
+        // The oldest ping was dropped to make room
+        assert_eq!(estimator.on_pong(first, start + Duration::from_millis(10)), None);
+
+        // All pending pings expire once they are older than the expiry time
+        estimator.start_ping(start + RTT_PING_EXPIRY);
+        assert_eq!(estimator.pending_count(), 1);
+    }
+
+    #[test]
+    fn test_single_outlier_is_rejected() {
+        let mut estimator = RttEstimator::new();
+        let start = Instant::now();
+        ping_pong(&mut estimator, start, 50);
+
+        // One huge spike doesn't move the estimate
+        ping_pong(&mut estimator, start, 1000);
+        assert_eq!(estimator.rtt_ms(), Some(50));
+    }
+
+    #[test]
+    fn test_sustained_change_is_accepted() {
+        let mut estimator = RttEstimator::new();
+        let start = Instant::now();
+        ping_pong(&mut estimator, start, 50);
+
+        // Consecutive high samples mean the latency really changed
+        for _ in 0..RTT_OUTLIERS_TO_ACCEPT {
+            ping_pong(&mut estimator, start, 400);
+        }
+        assert_eq!(estimator.rtt_ms(), Some(400));
+    }
+
+    #[test]
+    fn test_smoothing_converges() {
+        let mut estimator = RttEstimator::new();
+        let start = Instant::now();
+        ping_pong(&mut estimator, start, 100);
+
+        // A single lower sample only moves the estimate part of the way
+        ping_pong(&mut estimator, start, 60);
+        let rtt = estimator.rtt_ms().unwrap();
+        assert!(rtt < 100 && rtt > 60);
+
+        // Many samples converge on the new value
+        for _ in 0..100 {
+            ping_pong(&mut estimator, start, 60);
+        }
+        assert_eq!(estimator.rtt_ms(), Some(60));
+    }
+}
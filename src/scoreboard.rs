@@ -0,0 +1,392 @@
+use crate::types::MovementStats;
+
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const ROW_HEIGHT_FACTOR: f32 = 1.6; // Row height relative to the text size
+const PADDING: f32 = 16.0; // Space between the panel border and its content
+const MIN_PANEL_WIDTH: f32 = 420.0; // Minimum width of the scoreboard panel
+const ID_LENGTH: usize = 8; // Number of characters of the player id that are shown
+
+/// One line of the scoreboard
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreboardRow {
+    pub id: Uuid,
+    pub label: String,
+    pub score: String,
+    pub best: String,
+    pub ping: String,
+    pub reorders: String, // Inputs the server applied out of order in the last minute
+    pub distance: String, // Pixels moved since joining
+    pub time: String,     // Time connected
+    pub input_rate: String, // Average inputs per second while connected
+    pub is_local: bool,
+    pub is_team_header: bool, // Team line with the summed score of its players, not a player
+}
+
+/// Builds the scoreboard rows for the players in a snapshot, sorted by ping.
+/// Players the server has not measured yet are listed last. The local player is labeled
+/// with its name if it entered one, other players with the start of their id.
+pub fn build_rows(
+    players: &[Uuid],
+    ping_ms: &HashMap<Uuid, u32>,
+    scores: &HashMap<Uuid, u32>,
+    best_scores: &HashMap<Uuid, u32>,
+    input_reorders: &HashMap<Uuid, u32>,
+    my_id: Option<Uuid>,
+    my_name: Option<&str>,
+) -> Vec<ScoreboardRow> {
+    let mut sorted: Vec<(Uuid, Option<u32>)> = players.iter()
+        .map(|id| (*id, ping_ms.get(id).copied()))
+        .collect();
+    sorted.sort_by_key(|(id, ping)| (ping.is_none(), *ping, *id));
+
+    sorted.into_iter()
+        .map(|(id, ping)| {
+            let is_local = my_id == Some(id);
+            let short_id: String = id.to_string().chars().take(ID_LENGTH).collect();
+            let label = match my_name.filter(|name| is_local && !name.is_empty()) {
+                Some(name) => format!("{} (you)", name),
+                None if is_local => format!("{} (you)", short_id),
+                None => short_id,
+            };
+            ScoreboardRow {
+                id,
+                label,
+                score: format_count(scores.get(&id)),
+                best: format_count(best_scores.get(&id)),
+                ping: ping.map_or("-".to_string(), |ms| format!("{} ms", ms)),
+                reorders: input_reorders.get(&id).map_or("-".to_string(), |count| format!("{}/min", count)),
+                distance: "-".to_string(),
+                time: "-".to_string(),
+                input_rate: "-".to_string(),
+                is_local,
+                is_team_header: false,
+            }
+        })
+        .collect()
+}
+
+/// Column the scoreboard rows are sorted by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScoreboardSort {
+    #[default]
+    Ping,     // Lowest ping first, the order of build_rows
+    Distance, // Farthest moved first
+    Time,     // Longest connected first
+}
+
+/// Implementation of the ScoreboardSort
+impl ScoreboardSort {
+    /// Name of the column in the sort hint
+    pub fn name(self) -> &'static str {
+        match self {
+            ScoreboardSort::Ping => "ping",
+            ScoreboardSort::Distance => "distance",
+            ScoreboardSort::Time => "time",
+        }
+    }
+}
+
+/// Fills in the movement columns of the player rows and sorts them by the column. Players the server
+/// sent no stats for are listed last, ties keep their order by ping.
+pub fn with_movement(mut rows: Vec<ScoreboardRow>, movement: &HashMap<Uuid, MovementStats>, sort: ScoreboardSort) -> Vec<ScoreboardRow> {
+    for row in rows.iter_mut() {
+        if let Some(stats) = movement.get(&row.id) {
+            row.distance = format_distance(stats.distance);
+            row.time = format_duration(stats.connected_ms);
+            row.input_rate = stats.inputs_per_second().map_or("-".to_string(), |rate| format!("{:.1}/s", rate));
+        }
+    }
+    let key = |row: &ScoreboardRow| -> Option<u64> {
+        let stats = movement.get(&row.id)?;
+        match sort {
+            ScoreboardSort::Ping => Some(0),
+            ScoreboardSort::Distance => Some(stats.distance),
+            ScoreboardSort::Time => Some(stats.connected_ms),
+        }
+    };
+    if sort != ScoreboardSort::Ping {
+        rows.sort_by_key(|row| std::cmp::Reverse(key(row)));
+    }
+    rows
+}
+
+/// Formats a distance in pixels, in thousands from 10 000 px on
+pub fn format_distance(pixels: u64) -> String {
+    if pixels < 10_000 {
+        format!("{} px", pixels)
+    } else {
+        format!("{:.1}k px", pixels as f64 / 1000.0)
+    }
+}
+
+/// Formats a duration in milliseconds as minutes and seconds, with hours in front from the first hour on
+pub fn format_duration(ms: u64) -> String {
+    let seconds = ms / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Groups the player rows by team in team mode: every team gets a header line with the summed
+/// score of its players, followed by its players in their existing order. Players without a team
+/// are listed last. Without teams the rows are returned unchanged.
+pub fn group_by_team(rows: Vec<ScoreboardRow>, teams: &HashMap<Uuid, u8>, scores: &HashMap<Uuid, u32>) -> Vec<ScoreboardRow> {
+    if teams.is_empty() {
+        return rows;
+    }
+    let mut team_ids: Vec<u8> = teams.values().copied().collect();
+    team_ids.sort_unstable();
+    team_ids.dedup();
+
+    let mut grouped = Vec::with_capacity(rows.len() + team_ids.len());
+    for team in team_ids {
+        let members: Vec<&ScoreboardRow> = rows.iter().filter(|row| teams.get(&row.id) == Some(&team)).collect();
+        let total: u32 = members.iter().filter_map(|row| scores.get(&row.id)).sum();
+        grouped.push(ScoreboardRow {
+            id: Uuid::nil(),
+            label: format!("Team {} ({})", team + 1, members.len()),
+            score: total.to_string(),
+            best: String::new(),
+            ping: String::new(),
+            reorders: String::new(),
+            distance: String::new(),
+            time: String::new(),
+            input_rate: String::new(),
+            is_local: false,
+            is_team_header: true,
+        });
+        grouped.extend(members.into_iter().cloned());
+    }
+    grouped.extend(rows.into_iter().filter(|row| !teams.contains_key(&row.id)));
+    grouped
+}
+
+/// Formats a count from the snapshot, "-" if the server didn't send it
+fn format_count(count: Option<&u32>) -> String {
+    count.map_or("-".to_string(), |count| count.to_string())
+}
+
+/// Where the scoreboard panel and its rows are drawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreboardLayout {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub row_height: f32,
+    pub padding: f32,
+    pub score_column_x: f32, // Left edge of the score column
+    pub best_column_x: f32,  // Left edge of the best score column
+    pub ping_column_x: f32,  // Left edge of the ping column
+    pub reorder_column_x: f32, // Left edge of the reordered inputs column
+}
+
+/// Implementation of the ScoreboardLayout
+impl ScoreboardLayout {
+    /// Centers a panel with a header row and the given number of player rows in the area
+    pub fn new(row_count: usize, text_size: f32, area_width: f32, area_height: f32) -> Self {
+        let row_height = text_size * ROW_HEIGHT_FACTOR;
+        let width = MIN_PANEL_WIDTH.max(text_size * 24.0).min(area_width);
+        let height = ((row_count + 1) as f32 * row_height + PADDING * 2.0).min(area_height);
+
+        let x = ((area_width - width) / 2.0).max(0.0);
+        let y = ((area_height - height) / 2.0).max(0.0);
+
+        Self {
+            x,
+            y,
+            width,
+            height,
+            row_height,
+            padding: PADDING,
+            score_column_x: x + width * 0.42,
+            best_column_x: x + width * 0.54,
+            ping_column_x: x + width * 0.66,
+            reorder_column_x: x + width * 0.8,
+        }
+    }
+
+    /// Baseline of the text in a row, the header is row 0 and players start at row 1
+    pub fn row_baseline(&self, row: usize) -> f32 {
+        self.y + self.padding + self.row_height * (row as f32 + 0.75)
+    }
+
+    /// Number of player rows that fit in the panel
+    pub fn visible_rows(&self) -> usize {
+        let content = self.height - self.padding * 2.0;
+        ((content / self.row_height).floor() as usize).saturating_sub(1)
+    }
+}
+
+/// Tests for the scoreboard
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_sorted_by_ping() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let c = Uuid::new_v4();
+        let pings = HashMap::from([(a, 80), (b, 20)]);
+
+        let rows = build_rows(&[a, b, c], &pings, &HashMap::new(), &HashMap::new(), &HashMap::new(), Some(c), None);
+
+        let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+        assert_eq!(ids, vec![b, a, c]);
+        assert_eq!(rows[0].ping, "20 ms");
+        assert_eq!(rows[2].ping, "-"); // Not measured yet
+    }
+
+    #[test]
+    fn test_local_player_is_marked() {
+        let me = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        let rows = build_rows(&[me, other], &HashMap::new(), &HashMap::new(), &HashMap::new(), &HashMap::new(), Some(me), None);
+        let mine = rows.iter().find(|row| row.id == me).unwrap();
+        let theirs = rows.iter().find(|row| row.id == other).unwrap();
+
+        assert!(mine.is_local);
+        assert!(mine.label.ends_with("(you)"));
+        assert!(!theirs.is_local);
+        assert_eq!(theirs.label.len(), ID_LENGTH);
+
+        // An entered name replaces the id of the local player only
+        let rows = build_rows(&[me, other], &HashMap::new(), &HashMap::new(), &HashMap::new(), &HashMap::new(), Some(me), Some("Kari"));
+        assert_eq!(rows.iter().find(|row| row.id == me).unwrap().label, "Kari (you)");
+        assert_eq!(rows.iter().find(|row| row.id == other).unwrap().label.len(), ID_LENGTH);
+    }
+
+    #[test]
+    fn test_rows_show_scores() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let scores = HashMap::from([(a, 3), (b, 0)]);
+        let best_scores = HashMap::from([(a, 9)]);
+
+        let rows = build_rows(&[a, b], &HashMap::new(), &scores, &best_scores, &HashMap::new(), None, None);
+        let row_a = rows.iter().find(|row| row.id == a).unwrap();
+        let row_b = rows.iter().find(|row| row.id == b).unwrap();
+
+        assert_eq!((row_a.score.as_str(), row_a.best.as_str()), ("3", "9"));
+        assert_eq!((row_b.score.as_str(), row_b.best.as_str()), ("0", "-")); // Best score not sent
+    }
+
+    #[test]
+    fn test_rows_show_reorders() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let reorders = HashMap::from([(a, 12)]);
+
+        let rows = build_rows(&[a, b], &HashMap::new(), &HashMap::new(), &HashMap::new(), &reorders, None, None);
+        assert_eq!(rows.iter().find(|row| row.id == a).unwrap().reorders, "12/min");
+        assert_eq!(rows.iter().find(|row| row.id == b).unwrap().reorders, "-"); // Not sent by the server
+    }
+
+    #[test]
+    fn test_rows_sorted_by_movement() {
+        let (a, b, c, d) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let pings = HashMap::from([(a, 10), (b, 20), (c, 30), (d, 40)]);
+        let movement = HashMap::from([
+            (a, MovementStats { distance: 300, inputs: 60, connected_ms: 90_000 }),
+            (b, MovementStats { distance: 12_345, inputs: 2_470, connected_ms: 30_000 }),
+            (c, MovementStats { distance: 300, inputs: 0, connected_ms: 500 }),
+        ]);
+        let ids = |rows: &[ScoreboardRow]| rows.iter().map(|row| row.id).collect::<Vec<Uuid>>();
+        let rows = build_rows(&[a, b, c, d], &pings, &HashMap::new(), &HashMap::new(), &HashMap::new(), None, None);
+
+        // Ties stay in ping order, players without stats go last
+        assert_eq!(ids(&with_movement(rows.clone(), &movement, ScoreboardSort::Ping)), vec![a, b, c, d]);
+        assert_eq!(ids(&with_movement(rows.clone(), &movement, ScoreboardSort::Distance)), vec![b, a, c, d]);
+        let by_time = with_movement(rows, &movement, ScoreboardSort::Time);
+        assert_eq!(ids(&by_time), vec![a, b, c, d]);
+
+        let cells = |row: &ScoreboardRow| (row.distance.clone(), row.time.clone(), row.input_rate.clone());
+        assert_eq!(cells(&by_time[1]), ("12.3k px".to_string(), "0:30".to_string(), "82.3/s".to_string()));
+        assert_eq!(cells(&by_time[2]), ("300 px".to_string(), "0:00".to_string(), "-".to_string())); // Under a second
+        assert_eq!(cells(&by_time[3]), ("-".to_string(), "-".to_string(), "-".to_string())); // Not sent by the server
+    }
+
+    #[test]
+    fn test_movement_formatting() {
+        assert_eq!(format_distance(9_999), "9999 px");
+        assert_eq!(format_distance(10_000), "10.0k px");
+        assert_eq!(format_duration(61_999), "1:01");
+        assert_eq!(format_duration(3_725_000), "1:02:05");
+    }
+
+    #[test]
+    fn test_rows_grouped_by_team() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let c = Uuid::new_v4();
+        let pings = HashMap::from([(a, 10), (b, 20), (c, 30)]);
+        let scores = HashMap::from([(a, 3), (b, 5), (c, 4)]);
+        let teams = HashMap::from([(a, 1), (b, 0), (c, 1)]);
+
+        let rows = build_rows(&[a, b, c], &pings, &scores, &HashMap::new(), &HashMap::new(), None, None);
+        let grouped = group_by_team(rows, &teams, &scores);
+
+        let labels: Vec<&str> = grouped.iter().filter(|row| row.is_team_header).map(|row| row.label.as_str()).collect();
+        assert_eq!(labels, vec!["Team 1 (1)", "Team 2 (2)"]);
+        assert_eq!(grouped[0].score, "5");
+        assert_eq!(grouped[2].score, "7");
+
+        // Players follow their team header, still sorted by ping
+        let ids: Vec<Uuid> = grouped.iter().map(|row| row.id).collect();
+        assert_eq!(ids, vec![Uuid::nil(), b, Uuid::nil(), a, c]);
+    }
+
+    #[test]
+    fn test_rows_unchanged_without_teams() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let rows = build_rows(&[a, b], &HashMap::new(), &HashMap::new(), &HashMap::new(), &HashMap::new(), Some(a), None);
+
+        assert_eq!(group_by_team(rows.clone(), &HashMap::new(), &HashMap::new()), rows);
+        assert!(rows.iter().all(|row| !row.is_team_header));
+    }
+
+    #[test]
+    fn test_layout_is_centered() {
+        let layout = ScoreboardLayout::new(4, 20.0, 1024.0, 768.0);
+
+        assert_eq!(layout.x, (1024.0 - layout.width) / 2.0);
+        assert_eq!(layout.y, (768.0 - layout.height) / 2.0);
+        assert!(layout.x < layout.score_column_x);
+        assert!(layout.score_column_x < layout.best_column_x);
+        assert!(layout.best_column_x < layout.ping_column_x);
+        assert!(layout.ping_column_x < layout.reorder_column_x);
+        assert!(layout.reorder_column_x < layout.x + layout.width);
+        assert_eq!(layout.visible_rows(), 4);
+    }
+
+    #[test]
+    fn test_rows_are_stacked() {
+        let layout = ScoreboardLayout::new(3, 20.0, 1024.0, 768.0);
+
+        // Every row is one row height below the previous one and inside the panel
+        for row in 1..=3 {
+            assert_eq!(layout.row_baseline(row) - layout.row_baseline(row - 1), layout.row_height);
+            assert!(layout.row_baseline(row) < layout.y + layout.height);
+        }
+    }
+
+    #[test]
+    fn test_layout_fits_small_area() {
+        // Too many players for the area, the panel is clamped and fewer rows are visible
+        let layout = ScoreboardLayout::new(100, 20.0, 300.0, 200.0);
+
+        assert!(layout.width <= 300.0);
+        assert!(layout.height <= 200.0);
+        assert_eq!(layout.x, 0.0);
+        assert!(layout.visible_rows() < 100);
+        assert!(layout.row_baseline(layout.visible_rows()) <= layout.y + layout.height);
+    }
+}
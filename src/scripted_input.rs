@@ -0,0 +1,126 @@
+use crate::types::Direction;
+
+use std::time::Duration;
+
+/// One leg of a scripted walk: a direction held for a while, or standing still
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScriptLeg {
+    pub dir: Option<Direction>, // None stands still
+    pub duration: Duration,
+}
+
+/// Plays the inputs of a scripted walk in place of the keyboard, one input per frame while a direction
+/// is held like a held key after its repeat has sped up. The script loops, so a sweep of any length
+/// keeps moving. Used by the headless analysis client so every run walks the same way.
+#[derive(Debug, Clone)]
+pub struct ScriptedInputDriver {
+    legs: Vec<ScriptLeg>,
+    elapsed: f64, // Seconds into the script, wrapped at its end
+}
+
+/// Implementation of the ScriptedInputDriver
+impl ScriptedInputDriver {
+    /// Creates a driver playing the legs in order, over and over
+    pub fn new(legs: Vec<ScriptLeg>) -> Self {
+        Self { legs, elapsed: 0.0 }
+    }
+
+    /// Walks a square with a pause after each side, so the prediction is measured while moving,
+    /// turning and stopping. The walk comes back to where it started, so it doesn't drift off the board.
+    pub fn square() -> Self {
+        let side = Duration::from_millis(1000);
+        let pause = Duration::from_millis(500);
+        let legs = [Direction::Right, Direction::Down, Direction::Left, Direction::Up]
+            .into_iter()
+            .flat_map(|dir| [ScriptLeg { dir: Some(dir), duration: side }, ScriptLeg { dir: None, duration: pause }])
+            .collect();
+        Self::new(legs)
+    }
+
+    /// Length of one pass through the script
+    pub fn script_duration(&self) -> Duration {
+        self.legs.iter().map(|leg| leg.duration).sum()
+    }
+
+    /// Direction held at a time into the script, None while standing still or without legs
+    pub fn direction_at(&self, elapsed: f64) -> Option<Direction> {
+        let total = self.script_duration().as_secs_f64();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut offset = elapsed.rem_euclid(total);
+        for leg in &self.legs {
+            let duration = leg.duration.as_secs_f64();
+            if offset < duration {
+                return leg.dir;
+            }
+            offset -= duration;
+        }
+        None
+    }
+
+    /// Moves the script on by the frame time and returns the direction held this frame, if any.
+    /// The caller dispatches it as an intent of the GameClient, like the intents of InputHandler::handle_input.
+    pub fn next_direction(&mut self, dt: f32) -> Option<Direction> {
+        let dir = self.direction_at(self.elapsed);
+        let total = self.script_duration().as_secs_f64();
+        if total > 0.0 {
+            self.elapsed = (self.elapsed + dt as f64).rem_euclid(total);
+        }
+        dir
+    }
+}
+
+/// Default implementation for the ScriptedInputDriver
+impl Default for ScriptedInputDriver {
+    fn default() -> Self {
+        Self::square()
+    }
+}
+
+/// Tests for the ScriptedInputDriver
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::PLAYER_SPEED;
+    use crate::positions::AuthoritativePos;
+    use crate::prediction::PredictionState;
+    use crate::types::{PlayerInput, Position};
+
+    #[test]
+    fn test_square_follows_the_legs_and_loops() {
+        let driver = ScriptedInputDriver::square();
+        assert_eq!(driver.script_duration(), Duration::from_secs(6));
+        assert_eq!(driver.direction_at(0.0), Some(Direction::Right));
+        assert_eq!(driver.direction_at(1.2), None);
+        assert_eq!(driver.direction_at(1.5), Some(Direction::Down));
+        assert_eq!(driver.direction_at(4.9), Some(Direction::Up));
+        assert_eq!(driver.direction_at(6.1), Some(Direction::Right));
+        assert_eq!(ScriptedInputDriver::new(Vec::new()).direction_at(1.0), None);
+    }
+
+    #[test]
+    fn test_one_predicted_input_per_frame_while_walking() {
+        let mut driver = ScriptedInputDriver::square();
+        let start = Position { x: 300, y: 300 };
+        let mut position = AuthoritativePos::new(start);
+        let mut prediction = PredictionState::new(position);
+        let dt = 1.0 / 60.0;
+
+        // One pass of the script at 60 frames per second
+        let mut inputs = Vec::new();
+        for frame in 0..360 {
+            let timestamp = (frame as f64 * dt as f64 * 1000.0) as u64;
+            inputs.extend(driver.next_direction(dt).map(|dir| prediction.predict_input(dir, timestamp, &mut position)));
+        }
+
+        // About a second of inputs on each side
+        assert!((236..=244).contains(&inputs.len()), "{} inputs", inputs.len());
+        assert_eq!(inputs[0], PlayerInput { dir: Direction::Right, sequence: 0, timestamp: 0 });
+        assert!(inputs.windows(2).all(|pair| pair[1].sequence == pair[0].sequence + 1));
+        assert_eq!(prediction.pending_inputs.len(), inputs.len());
+        let rights = inputs.iter().filter(|input| input.dir == Direction::Right).count() as i32;
+        let lefts = inputs.iter().filter(|input| input.dir == Direction::Left).count() as i32;
+        assert_eq!(position.x - start.x, (rights - lefts) * PLAYER_SPEED);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::client_world::ClientWorld;
+use crate::constants::{SCRUB_CAPTURE_INTERVAL, SCRUB_HISTORY};
+use crate::positions::DisplayPos;
+use crate::split_view::PositionSource;
+use crate::util::RingHistory;
+
+use uuid::Uuid;
+
+/// The prediction at a capture, summarized instead of copying its history
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PredictionSummary {
+    pub predicting: bool,      // False while the watchdog has the local player follow the snapshots
+    pub pending_inputs: usize, // Inputs the server hadn't acknowledged yet
+    pub next_sequence: u32,
+    pub error: Option<f32>,     // Last prediction error of the local player in pixels
+    pub error_p95: Option<f32>, // 95th percentile error over the watchdog window
+}
+
+/// The network as the client saw it at a capture
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NetworkSummary {
+    pub ping_ms: Option<u32>,
+    pub snapshot_loss: f64,       // Share of the snapshots estimated lost
+    pub snapshot_id: Option<u64>, // Newest snapshot applied
+    pub remote_players: usize,    // Remote players with buffered positions
+}
+
+/// The client at one moment, as much as is needed to draw it again and show what led up to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrubFrame {
+    pub number: u64, // Captures made before this one
+    pub time: f64,   // Local time in seconds of the capture
+    pub positions: Vec<(Uuid, DisplayPos, u32)>,     // Drawn positions and packed colors of the client view
+    pub raw_positions: Vec<(Uuid, DisplayPos, u32)>, // Positions of the latest snapshot, for the dual view
+    pub prediction: PredictionSummary,
+    pub network: NetworkSummary,
+}
+
+/// Implementation of the ScrubFrame
+impl ScrubFrame {
+    /// Captures the world at a local time, with the players where they are drawn at the server time
+    pub fn capture(world: &ClientWorld, time: f64, server_time: Option<f64>) -> Self {
+        Self {
+            number: 0,
+            time,
+            positions: world.positions(PositionSource::Smoothed, server_time),
+            raw_positions: world.positions(PositionSource::RawSnapshot, server_time),
+            prediction: PredictionSummary {
+                predicting: world.is_predicting(),
+                pending_inputs: world.prediction.pending_inputs.len(),
+                next_sequence: world.prediction.next_sequence,
+                error: world.my_id.and_then(|id| world.prediction_errors.get(&id).copied()),
+                error_p95: world.prediction_watchdog.p95(time),
+            },
+            network: NetworkSummary {
+                ping_ms: world.my_ping(),
+                snapshot_loss: world.snapshot_loss.loss(),
+                snapshot_id: world.seen_snapshots.newest().copied(),
+                remote_players: world.interpolated_positions.entity_count(),
+            },
+        }
+    }
+}
+
+/// Keeps the last seconds of the client state and steps through them while paused, to see what exactly
+/// happened right before a snap. Nothing is captured while paused, so the frames stay where they were.
+#[derive(Debug, Clone)]
+pub struct Scrubber {
+    frames: RingHistory<ScrubFrame, u64>, // Captures by number, oldest first
+    captured: u64,                        // Captures made, the number of the next one
+    last_capture: Option<f64>,            // Local time of the last capture
+    cursor: Option<usize>,                // Index of the frame shown while paused, None while live
+}
+
+/// Default implementation for the Scrubber
+impl Default for Scrubber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Implementation of the Scrubber
+impl Scrubber {
+    /// Creates a live scrubber without any captures
+    pub fn new() -> Self {
+        Self { frames: RingHistory::new(SCRUB_HISTORY, |frame| frame.number), captured: 0, last_capture: None, cursor: None }
+    }
+
+    /// True if a capture is due at the local time: live, and a capture interval after the last one
+    pub fn capture_due(&self, now: f64) -> bool {
+        !self.is_paused() && self.last_capture.is_none_or(|last| now - last >= SCRUB_CAPTURE_INTERVAL || now < last)
+    }
+
+    /// Captures the frame made by capture if one is due at the local time, returns whether it did
+    pub fn capture(&mut self, now: f64, capture: impl FnOnce() -> ScrubFrame) -> bool {
+        if !self.capture_due(now) {
+            return false;
+        }
+        let mut frame = capture();
+        frame.number = self.captured;
+        self.frames.push(frame);
+        self.captured += 1;
+        self.last_capture = Some(now);
+        true
+    }
+
+    /// Pauses on the newest frame, or goes back to live. Stays live when there is nothing to show.
+    pub fn toggle(&mut self) {
+        self.cursor = match self.cursor {
+            Some(_) => None,
+            None => self.frames.len().checked_sub(1),
+        };
+    }
+
+    /// Goes back to live
+    pub fn resume(&mut self) {
+        self.cursor = None;
+    }
+
+    /// True while a captured frame is shown instead of the live one
+    pub fn is_paused(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Moves the shown frame by steps, forwards when positive, stopping at the oldest and the newest
+    pub fn step(&mut self, steps: i32) {
+        if let Some(cursor) = self.cursor {
+            self.cursor = Some(cursor.saturating_add_signed(steps as isize).min(self.frames.len() - 1));
+        }
+    }
+
+    /// The frame shown while paused
+    pub fn current(&self) -> Option<&ScrubFrame> {
+        self.cursor.and_then(|cursor| self.frames.get(cursor))
+    }
+
+    /// Seconds the shown frame is before the newest one
+    pub fn offset(&self) -> Option<f64> {
+        self.current().zip(self.frames.newest()).map(|(current, newest)| newest.time - current.time)
+    }
+
+    /// Forgets every capture and goes back to live, when the world they show is gone
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Lines of the side panel while paused: where in the history the shown frame is and its metrics
+    pub fn panel_lines(&self) -> Vec<String> {
+        let (Some(cursor), Some(frame), Some(offset)) = (self.cursor, self.current(), self.offset()) else {
+            return Vec::new();
+        };
+        let PredictionSummary { predicting, pending_inputs, next_sequence, error, error_p95 } = frame.prediction;
+        let NetworkSummary { ping_ms, snapshot_loss, snapshot_id, remote_players } = frame.network;
+        let pixels = |value: Option<f32>| value.map_or("-".to_string(), |value| format!("{:.1} px", value));
+        vec![
+            format!("Scrubbing {} / {}   -{:.1} s", cursor + 1, self.frames.len(), offset),
+            format!("Ping {}", ping_ms.map_or("-".to_string(), |ping| format!("{} ms", ping))),
+            format!("Snapshot loss {:.0}%", snapshot_loss * 100.0),
+            format!("Snapshot {}", snapshot_id.map_or("-".to_string(), |id| id.to_string())),
+            format!("Remote players {}", remote_players),
+            format!("Prediction {}", if predicting { "on" } else { "off" }),
+            format!("Pending inputs {}   Next sequence {}", pending_inputs, next_sequence),
+            format!("Prediction error {}   p95 {}", pixels(error), pixels(error_p95)),
+            "Left/Right step, F8 back to live".to_string(),
+        ]
+    }
+}
+
+/// Tests for the Scrubber
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::positions::AuthoritativePos;
+    use crate::types::Position;
+
+    // Helper function to create a frame with the local player at x
+    fn frame(x: i32) -> ScrubFrame {
+        let position = DisplayPos::from_authoritative(AuthoritativePos::new(Position { x, y: 100 }));
+        ScrubFrame {
+            number: 0,
+            time: 0.0,
+            positions: vec![(Uuid::from_u128(1), position, 0)],
+            raw_positions: Vec::new(),
+            prediction: PredictionSummary { error: Some(x as f32), ..Default::default() },
+            network: NetworkSummary::default(),
+        }
+    }
+
+    // Helper function to capture a frame every 16 ms frame from start for the seconds, with x counting the captures
+    fn run(scrubber: &mut Scrubber, start: f64, seconds: f64) -> usize {
+        let mut captures = 0;
+        let mut now = start;
+        while now < start + seconds {
+            if scrubber.capture(now, || ScrubFrame { time: now, ..frame(captures as i32) }) {
+                captures += 1;
+            }
+            now += 0.016;
+        }
+        captures
+    }
+
+    #[test]
+    fn test_captures_every_interval_and_keeps_ten_seconds() {
+        let mut scrubber = Scrubber::new();
+        assert!(scrubber.capture_due(0.0));
+        assert!(scrubber.capture(0.0, || frame(0)));
+        assert!(!scrubber.capture_due(0.05));
+        assert!(!scrubber.capture(0.09, || panic!("captured too early")));
+        assert!(scrubber.capture(0.1, || frame(1)));
+
+        // Frames of 16 ms capture every 112 ms, the oldest captures are dropped after 100
+        let captures = run(&mut scrubber, 0.2, 20.0);
+        assert!((170..=180).contains(&captures), "{} captures", captures);
+        assert_eq!(scrubber.frames.len(), SCRUB_HISTORY);
+        assert_eq!(scrubber.frames.newest().unwrap().number, captures as u64 + 1);
+
+        // A clock that went backwards captures right away instead of waiting for the old time
+        assert!(scrubber.capture(5.0, || frame(0)));
+    }
+
+    #[test]
+    fn test_scrubbing_steps_through_the_frozen_history() {
+        let mut scrubber = Scrubber::new();
+
+        // Nothing to show stays live
+        scrubber.toggle();
+        assert!(!scrubber.is_paused());
+
+        run(&mut scrubber, 0.0, 1.0);
+        let frames = scrubber.frames.len();
+        scrubber.toggle();
+        assert!(scrubber.is_paused());
+        assert_eq!(scrubber.current().unwrap().number, frames as u64 - 1);
+        assert_eq!(scrubber.offset(), Some(0.0));
+
+        // Stepping stops at the ends of the history
+        scrubber.step(-3);
+        assert_eq!(scrubber.current().unwrap().number, frames as u64 - 4);
+        assert!((scrubber.offset().unwrap() - 3.0 * 0.112).abs() < 1e-6);
+        scrubber.step(-1000);
+        assert_eq!(scrubber.current().unwrap().number, 0);
+        scrubber.step(2);
+        assert_eq!(scrubber.current().unwrap().prediction.error, Some(2.0));
+        scrubber.step(1000);
+        assert_eq!(scrubber.current().unwrap().number, frames as u64 - 1);
+
+        // Nothing is captured while paused, going back to live captures again
+        assert!(!scrubber.capture(100.0, || frame(0)));
+        assert_eq!(scrubber.frames.len(), frames);
+        assert!(scrubber.panel_lines()[0].starts_with(&format!("Scrubbing {} / {}", frames, frames)));
+        scrubber.toggle();
+        assert!(scrubber.current().is_none() && scrubber.panel_lines().is_empty());
+        assert!(scrubber.capture(100.0, || frame(0)));
+    }
+
+    #[test]
+    fn test_capture_summarizes_the_world() {
+        let mut world = ClientWorld::new();
+        let id = Uuid::from_u128(7);
+        world.my_id = Some(id);
+        world.my_pos = AuthoritativePos::new(Position { x: 40, y: 60 });
+        world.all_players.insert(id, (Position { x: 30, y: 60 }, 5));
+        world.prediction_errors.insert(id, 10.0);
+        world.prediction.next_sequence = 12;
+
+        let frame = ScrubFrame::capture(&world, 3.0, None);
+        assert_eq!(frame.positions, vec![(id, world.local_display_position(None), 5)]);
+        assert_eq!(frame.raw_positions, vec![(id, DisplayPos::from_authoritative(AuthoritativePos::new(Position { x: 30, y: 60 })), 5)]);
+        assert_eq!(frame.prediction, PredictionSummary { predicting: true, pending_inputs: 0, next_sequence: 12, error: Some(10.0), error_p95: None });
+        assert_eq!(frame.network.snapshot_id, None);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::constants::{
+    IMMEDIATE_ENTER_RTT_MS, IMMEDIATE_ENTER_UNACKED, IMMEDIATE_EXIT_RTT_MS, IMMEDIATE_EXIT_UNACKED, IMMEDIATE_MAX_LOSS,
+    INPUT_SEND_BASE_INTERVAL, INPUT_SEND_REDUCED_INTERVAL, LOSS_SMOOTHING, REDUCED_ENTER_LOSS, REDUCED_ENTER_UNACKED,
+    REDUCED_EXIT_LOSS, REDUCED_EXIT_UNACKED,
+};
+
+use std::time::Duration;
+
+/// How often the client sends its inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SendRate {
+    Immediate, // Every input in its own datagram as soon as it is made, while the connection is fast and clean
+    #[default]
+    Base,      // Inputs batched at the base rate
+    Reduced,   // Inputs batched at the reduced rate, while many inputs wait for an ack or loss is high
+}
+
+/// Implementation of the SendRate
+impl SendRate {
+    /// Time between two sends, zero when every input is sent right away
+    pub fn interval(self) -> Duration {
+        match self {
+            SendRate::Immediate => Duration::ZERO,
+            SendRate::Base => INPUT_SEND_BASE_INTERVAL,
+            SendRate::Reduced => INPUT_SEND_REDUCED_INTERVAL,
+        }
+    }
+
+    /// Short text for the toolbar
+    pub fn describe(self) -> String {
+        match self {
+            SendRate::Immediate => "every input".to_string(),
+            rate => format!("{:.0} Hz", 1.0 / rate.interval().as_secs_f64()),
+        }
+    }
+}
+
+/// Picks the send rate for the next inputs from the round trip time in milliseconds (None before it is measured),
+/// the estimated loss from 0 to 1 and the inputs waiting for an ack. Each rate is left at a different threshold
+/// than it is entered at, so conditions close to a threshold don't make the rate flap.
+pub fn next_send_rate(current: SendRate, rtt_ms: Option<f64>, loss: f64, unacked: usize) -> SendRate {
+    // A long backlog or heavy loss calls for fewer, fuller datagrams
+    let reduced = if current == SendRate::Reduced {
+        unacked >= REDUCED_EXIT_UNACKED || loss >= REDUCED_EXIT_LOSS
+    } else {
+        unacked >= REDUCED_ENTER_UNACKED || loss >= REDUCED_ENTER_LOSS
+    };
+    if reduced {
+        return SendRate::Reduced;
+    }
+
+    // A fast connection with few inputs in flight can afford one datagram per input
+    let (max_rtt, max_unacked) = if current == SendRate::Immediate {
+        (IMMEDIATE_EXIT_RTT_MS, IMMEDIATE_EXIT_UNACKED)
+    } else {
+        (IMMEDIATE_ENTER_RTT_MS, IMMEDIATE_ENTER_UNACKED)
+    };
+    let immediate = rtt_ms.is_some_and(|rtt| rtt <= max_rtt) && unacked <= max_unacked && loss <= IMMEDIATE_MAX_LOSS;
+    if immediate { SendRate::Immediate } else { SendRate::Base }
+}
+
+/// Estimate of the packet loss from the snapshots that never arrived. The server sends one every tick,
+/// so a gap in the snapshot ids is loss on the way to the client, taken as a stand-in for loss both ways.
+#[derive(Debug, Clone, Default)]
+pub struct LossEstimator {
+    newest: Option<u64>,
+    loss: f64,
+}
+
+/// Implementation of the LossEstimator
+impl LossEstimator {
+    /// Creates an estimator that hasn't seen any snapshot
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a received snapshot and the ones missing since the newest one before it, returns how many were missing.
+    /// Snapshots older than the newest were already counted as lost and are ignored.
+    pub fn record_snapshot(&mut self, snapshot_id: u64) -> u64 {
+        let mut missing = 0;
+        if let Some(newest) = self.newest {
+            if snapshot_id <= newest {
+                return 0;
+            }
+            missing = snapshot_id - newest - 1;
+            self.loss = 1.0 - (1.0 - self.loss) * (1.0 - LOSS_SMOOTHING).powi(missing.min(i32::MAX as u64) as i32);
+        }
+        self.loss *= 1.0 - LOSS_SMOOTHING;
+        self.newest = Some(snapshot_id);
+        missing
+    }
+
+    /// Estimated share of lost packets, from 0 to 1
+    pub fn loss(&self) -> f64 {
+        self.loss
+    }
+
+    /// Forgets everything, for a new session
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Tests for the input send rate
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATES: [SendRate; 3] = [SendRate::Immediate, SendRate::Base, SendRate::Reduced];
+    const RTTS: [Option<f64>; 7] = [None, Some(5.0), Some(50.0), Some(65.0), Some(80.0), Some(120.0), Some(400.0)];
+    const LOSSES: [f64; 7] = [0.0, 0.01, 0.02, 0.04, 0.07, 0.12, 0.5];
+
+    // Helper function to order rates by how often they send, higher is more often
+    fn speed(rate: SendRate) -> u8 {
+        match rate {
+            SendRate::Immediate => 2,
+            SendRate::Base => 1,
+            SendRate::Reduced => 0,
+        }
+    }
+
+    #[test]
+    fn test_intervals() {
+        assert_eq!(SendRate::Immediate.interval(), Duration::ZERO);
+        assert_eq!(SendRate::Base.describe(), "30 Hz");
+        assert_eq!(SendRate::Reduced.describe(), "15 Hz");
+        assert_eq!(SendRate::default(), SendRate::Base);
+    }
+
+    #[test]
+    fn test_rates_for_clear_conditions() {
+        for current in RATES {
+            assert_eq!(next_send_rate(current, Some(20.0), 0.0, 0), SendRate::Immediate, "{:?}", current);
+            assert_eq!(next_send_rate(current, Some(150.0), 0.0, 4), SendRate::Base, "{:?}", current);
+            assert_eq!(next_send_rate(current, Some(20.0), 0.0, 30), SendRate::Reduced, "{:?}", current);
+            assert_eq!(next_send_rate(current, Some(20.0), 0.3, 0), SendRate::Reduced, "{:?}", current);
+        }
+
+        // Without a measured round trip time inputs are batched
+        assert_eq!(next_send_rate(SendRate::Immediate, None, 0.0, 0), SendRate::Base);
+    }
+
+    #[test]
+    fn test_hysteresis_keeps_the_rate_between_thresholds() {
+        // Between the enter and exit round trip times, the rate depends on where it came from
+        let rtt = Some((IMMEDIATE_ENTER_RTT_MS + IMMEDIATE_EXIT_RTT_MS) / 2.0);
+        assert_eq!(next_send_rate(SendRate::Immediate, rtt, 0.0, 0), SendRate::Immediate);
+        assert_eq!(next_send_rate(SendRate::Base, rtt, 0.0, 0), SendRate::Base);
+
+        // The same for the unacked backlog at both ends
+        let unacked = (IMMEDIATE_ENTER_UNACKED + IMMEDIATE_EXIT_UNACKED) / 2;
+        assert_eq!(next_send_rate(SendRate::Immediate, Some(10.0), 0.0, unacked), SendRate::Immediate);
+        assert_eq!(next_send_rate(SendRate::Base, Some(10.0), 0.0, unacked), SendRate::Base);
+        let unacked = (REDUCED_ENTER_UNACKED + REDUCED_EXIT_UNACKED) / 2;
+        assert_eq!(next_send_rate(SendRate::Reduced, Some(10.0), 0.0, unacked), SendRate::Reduced);
+        assert_eq!(next_send_rate(SendRate::Base, Some(10.0), 0.0, unacked), SendRate::Base);
+
+        // And for the loss
+        let loss = (REDUCED_ENTER_LOSS + REDUCED_EXIT_LOSS) / 2.0;
+        assert_eq!(next_send_rate(SendRate::Reduced, Some(10.0), loss, 0), SendRate::Reduced);
+        assert_eq!(next_send_rate(SendRate::Base, Some(10.0), loss, 0), SendRate::Base);
+    }
+
+    #[test]
+    fn test_worse_conditions_never_send_more_often() {
+        for current in RATES {
+            for (rtt_index, rtt) in RTTS.iter().enumerate() {
+                for (loss_index, loss) in LOSSES.iter().enumerate() {
+                    for unacked in 0..30 {
+                        let rate = speed(next_send_rate(current, *rtt, *loss, unacked));
+                        let context = format!("{:?} rtt {:?} loss {} unacked {}", current, rtt, loss, unacked);
+                        assert!(speed(next_send_rate(current, *rtt, *loss, unacked + 1)) <= rate, "more unacked, {}", context);
+                        if let Some(worse) = LOSSES.get(loss_index + 1) {
+                            assert!(speed(next_send_rate(current, *rtt, *worse, unacked)) <= rate, "more loss, {}", context);
+                        }
+                        if let Some(slower) = RTTS.get(rtt_index + 1).filter(|_| rtt.is_some()) {
+                            assert!(speed(next_send_rate(current, *slower, *loss, unacked)) <= rate, "slower, {}", context);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rate_settles_under_steady_conditions() {
+        // Feeding the result back in reaches a rate that doesn't change anymore
+        for rtt in RTTS {
+            for loss in LOSSES {
+                for unacked in 0..30 {
+                    for start in RATES {
+                        let settled = next_send_rate(next_send_rate(start, rtt, loss, unacked), rtt, loss, unacked);
+                        assert_eq!(next_send_rate(settled, rtt, loss, unacked), settled);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_loss_estimate_follows_snapshot_gaps() {
+        let mut estimator = LossEstimator::new();
+        for id in 1..=100 {
+            estimator.record_snapshot(id);
+        }
+        assert_eq!(estimator.loss(), 0.0);
+
+        // Every other snapshot missing settles close to half
+        for id in (102..=1000).step_by(2) {
+            assert_eq!(estimator.record_snapshot(id), 1);
+        }
+        assert!((estimator.loss() - 0.5).abs() < 0.05, "{}", estimator.loss());
+
+        // Old and repeated snapshots don't count, the estimate recovers once nothing is lost
+        let before = estimator.loss();
+        assert_eq!(estimator.record_snapshot(500), 0);
+        assert_eq!(estimator.record_snapshot(1000), 0);
+        assert_eq!(estimator.loss(), before);
+        for id in 1001..=1200 {
+            estimator.record_snapshot(id);
+        }
+        assert!(estimator.loss() < 0.01);
+
+        estimator.reset();
+        estimator.record_snapshot(5_000);
+        assert_eq!(estimator.loss(), 0.0);
+    }
+}
@@ -0,0 +1,2028 @@
+use crate::build_info::BuildInfo;
+use crate::constants::{CONSOLE_HISTORY_WINDOW, LEADERBOARD_SAVE_INTERVAL, MAX_DATAGRAM_SIZE, MAX_NAME_CHARS, MAX_PASSWORD_CHARS, RECV_BATCH_MAX, SERVER_PING_INTERVAL, SERVER_STATS_INTERVAL, STATS_REPLY_MAX_BYTES, TIMEOUT};
+use crate::game::{Game, GameEvent, PlayerRecord, PositionChange};
+use crate::lag_comp::{LagCompCounters, LagCompResult};
+use crate::leaderboard::Leaderboard;
+use crate::load_shedding::{triage, ReceiveGuard, ReceiveLimits};
+use crate::profile_scope;
+use crate::profiler;
+use crate::rooms::{RoomConfig, RoomRegistry, RoomTaskStart};
+use crate::server_config::{millis, ServerConfig};
+use crate::stats_query::{encode_reply, parse_request, resident_bytes, MemoryStats, RoomStats, ServerStats, StatsRequest, StatsResponder};
+use crate::transport::Transport;
+use crate::types::{ClientMessage, GameState, PlayerInput, PlayerShape, Position, RejectReason};
+use crate::util::{format_instance, DelayQueue, Histogram};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time;
+use uuid::Uuid;
+
+/// Timeouts and intervals of the server, the constants unless a test shortens them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerTuning {
+    pub player_timeout: Duration,          // Players that sent nothing for this long are disconnected
+    pub server_ping_interval: Duration,    // How often every player is pinged to measure its round trip time
+    pub stats_interval: Duration,          // How often the stats line is printed
+    pub leaderboard_save_interval: Duration,
+}
+
+/// Default implementation for the ServerTuning
+impl Default for ServerTuning {
+    fn default() -> Self {
+        Self {
+            player_timeout: TIMEOUT,
+            server_ping_interval: SERVER_PING_INTERVAL,
+            stats_interval: SERVER_STATS_INTERVAL,
+            leaderboard_save_interval: LEADERBOARD_SAVE_INTERVAL,
+        }
+    }
+}
+
+/// Creates the rooms of the server. Every room gets its own game in the configured mode and tick rate,
+/// which knows the records of earlier runs. With a spawn seed the rooms are seeded with it plus the number
+/// of rooms created before them, so every room spawns differently and a run can still be repeated.
+pub fn create_rooms(config: &ServerConfig, room_config: RoomConfig, saved_records: HashMap<Uuid, PlayerRecord>, tuning: ServerTuning) -> RoomRegistry {
+    let team_count = config.team_count;
+    let mode = config.mode;
+    let tick_interval = config.tick_interval();
+    let debug_teleports = config.debug_teleports;
+    let coalesce_inputs = config.coalesce_inputs;
+    let (reconnect_grace, max_resumable_sessions) = (config.reconnect_grace, config.max_resumable_sessions);
+    let spawn_seed = config.spawn_seed;
+    let max_rewind = config.max_rewind;
+    let rooms_created = AtomicU64::new(0);
+    RoomRegistry::new(room_config, move || {
+        let mut game = if team_count > 0 { Game::with_teams(team_count) } else { Game::new() };
+        game.set_mode(mode.create());
+        game.set_tick_interval(tick_interval);
+        game.set_saved_records(saved_records.clone());
+        game.set_player_timeout(tuning.player_timeout);
+        game.set_debug_teleports(debug_teleports);
+        game.set_input_coalescing(coalesce_inputs);
+        game.set_reconnect_retention(reconnect_grace, max_resumable_sessions);
+        game.set_max_rewind(max_rewind);
+        if let Some(seed) = spawn_seed {
+            game.set_spawn_seed(seed.wrapping_add(rooms_created.fetch_add(1, Ordering::Relaxed)));
+        }
+        game
+    })
+}
+
+/// Runs the server on the socket until shutdown completes, then writes the leaderboard one last time.
+/// The server binary stops on Ctrl+C, the smoke test runs it in process and stops it when done.
+pub async fn run_server(socket: Transport, rooms: RoomRegistry, config: ServerConfig, tuning: ServerTuning, shutdown: impl Future<Output = ()>) {
+    let socket = Arc::new(socket);
+    let rooms = Arc::new(Mutex::new(rooms));
+    let match_duration = config.match_duration;
+    let processing_delay = config.processing_delay;
+    let tick_interval = config.tick_interval();
+    let leaderboard_path = config.leaderboard_path.clone();
+
+    // Inputs waiting for the simulated input processing delay, with the time they were received
+    let input_queue: Arc<InputQueue> = Arc::default();
+    let delays = Arc::new(std::sync::Mutex::new(BroadcastDelays::default()));
+    let receive_guard = Arc::new(std::sync::Mutex::new(ReceiveGuard::new(ReceiveLimits { tick: tick_interval, ..Default::default() })));
+    let stats_source = StatsSource {
+        rooms: Arc::clone(&rooms),
+        transport: Arc::clone(&socket),
+        input_queue: Arc::clone(&input_queue),
+        receive_guard: Arc::clone(&receive_guard),
+        started_at: Instant::now(),
+        tick_rate: config.tick_rate,
+    };
+
+    // Every room ticks on a broadcast task of its own, at its own rate
+    let shared = RoomTaskShared {
+        rooms: Arc::clone(&rooms),
+        socket: Arc::clone(&socket),
+        input_queue: Arc::clone(&input_queue),
+        delays: Arc::clone(&delays),
+        match_duration,
+        processing_delay,
+        tuning,
+    };
+    rooms.lock().await.start_tasks(move |start| run_room(start, shared.clone()));
+
+    // Clone handles for housekeeping task
+    let socket_clone = Arc::clone(&socket);
+    let rooms_clone = Arc::clone(&rooms);
+    let input_queue_clone = Arc::clone(&input_queue);
+    let leaderboard_path_clone = leaderboard_path.clone();
+    let metrics_csv = config.metrics_csv.clone();
+    let build = BuildInfo::current(config.spawn_seed);
+
+    // Spawn periodic housekeeping task, which saves the leaderboard, prints the stats and removes empty rooms
+    let housekeeping_task = tokio::spawn(async move {
+        let mut interval = time::interval(tick_interval);
+        let mut last_stats = Instant::now();
+        let mut last_leaderboard_save = Instant::now();
+
+        loop {
+            interval.tick().await;
+            let save_due = leaderboard_path_clone.is_some() && last_leaderboard_save.elapsed() >= tuning.leaderboard_save_interval;
+            if save_due {
+                last_leaderboard_save = Instant::now();
+            }
+            let stats_due = last_stats.elapsed() >= tuning.stats_interval;
+
+            // Only game state is touched under the lock, file writes happen after it is released
+            let (leaderboard, stats, removed_rooms) = {
+                let mut rooms = rooms_clone.lock().await;
+                let leaderboard = save_due.then(|| collect_leaderboard(&rooms));
+                let stats = stats_due.then(|| sample_stats(&mut rooms, metrics_csv.is_some()));
+
+                // Inputs of players that left their room before the inputs were due have nowhere to go
+                input_queue_clone.lock().await.pop_ready_where(Instant::now(), |(addr, _, _)| rooms.room_of(addr).is_none());
+
+                // Forget the protocol of clients that left
+                socket_clone.retain(|addr| rooms.room_of(addr).is_some());
+
+                // Rooms created by a join are removed once they have stayed empty for a while, which stops their tasks
+                let removed_rooms = rooms.remove_empty_rooms(Instant::now());
+                (leaderboard, stats, removed_rooms)
+            };
+
+            // Write the leaderboard periodically so a crash loses little progress
+            if let (Some(path), Some(leaderboard)) = (&leaderboard_path_clone, leaderboard) {
+                save_leaderboard(&leaderboard, path);
+            }
+
+            // Print the stats line periodically, with the delays of every room since the last one
+            if let Some(stats) = stats {
+                let delays = std::mem::take(&mut *delays.lock().unwrap());
+                println!("{}", format_stats_line(stats.players, &stats, &processing_delay, &delays.tick, &delays.input, &delays.lock_hold));
+                if let (Some(path), Some(rows)) = (&metrics_csv, stats.metrics_rows) {
+                    if let Err(e) = append_metrics_csv(path, &build, &rows) {
+                        eprintln!("Failed to write metrics to {}: {}", path.display(), e);
+                    }
+                }
+                for violation in stats.violations {
+                    eprintln!("[stats] {}", violation);
+                }
+                last_stats = Instant::now();
+            }
+
+            for name in removed_rooms {
+                println!("Room {} removed after being empty", name);
+            }
+        }
+    });
+
+    // Stats queries get a port of their own if one is configured, otherwise they arrive on the game port
+    let mut stats_responder = StatsResponder::new(config.stats_token.clone());
+    let stats_task = match config.stats_port {
+        Some(port) => {
+            let addr = SocketAddr::new(socket.socket().local_addr().map_or(IpAddr::from([0, 0, 0, 0]), |addr| addr.ip()), port);
+            match UdpSocket::bind(addr).await {
+                Ok(stats_socket) => {
+                    println!("Answering stats queries on {}", stats_socket.local_addr().unwrap_or(addr));
+                    Some(tokio::spawn(serve_stats_queries(stats_socket, stats_responder.clone(), stats_source.clone())))
+                }
+                Err(e) => {
+                    eprintln!("Failed to bind the stats port {}: {}", addr, e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Commands typed on the console are answered with the rooms locked, like a tick
+    let console_task = config.console.then(|| {
+        let rooms = Arc::clone(&rooms);
+        let mut lines = spawn_console_reader();
+        tokio::spawn(async move {
+            while let Some(line) = lines.recv().await {
+                print!("{}", console_command(&*rooms.lock().await, &line));
+            }
+        })
+    });
+
+    // Big enough for any datagram, a truncated one would fail to decode and be dropped without an answer
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            result = socket.socket().recv_from(&mut buf) => match result {
+                Ok(first) => {
+                    // Read what else is waiting, so a flood is shed a batch at a time instead of a datagram at a time
+                    let mut batch = Vec::new();
+                    let mut next = Some(first);
+                    let mut read = 0;
+                    while let Some((size, addr)) = next {
+                        read += 1;
+                        if let Some(request) = parse_request(&buf[..size]) {
+                            if config.stats_port.is_none() {
+                                answer_stats_query(socket.socket(), &mut stats_responder, &request, addr, &stats_source).await;
+                            }
+                        } else {
+                            batch.push((addr, buf[..size].to_vec()));
+                        }
+                        next = if read < RECV_BATCH_MAX { socket.socket().try_recv_from(&mut buf).ok() } else { None };
+                    }
+                    if batch.is_empty() {
+                        continue;
+                    }
+                    let known: HashSet<SocketAddr> = {
+                        let rooms = rooms.lock().await;
+                        batch.iter().map(|(addr, _)| *addr).filter(|addr| rooms.room_of(addr).is_some()).collect()
+                    };
+                    let messages = triage(batch, &mut receive_guard.lock().unwrap(), Instant::now(), |addr, data| socket.decode(addr, data), |addr| known.contains(addr));
+
+                    for (addr, msg) in messages {
+                        // Follow a client whose port changed before its inputs are queued
+                        let msg = match msg {
+                            ClientMessage::WithSession { .. } => accept_session(&mut *rooms.lock().await, addr, msg),
+                            msg => msg,
+                        };
+                        match msg {
+                            // Queue inputs when simulating a slow server, the broadcast task applies them
+                            ClientMessage::Input(input) if !processing_delay.input_delay.is_zero() => {
+                                let now = Instant::now();
+                                input_queue.lock().await.push((addr, input, now), now + processing_delay.input_delay);
+                            }
+                            ClientMessage::InputBatch(inputs) if !processing_delay.input_delay.is_zero() => {
+                                let now = Instant::now();
+                                let mut queue = input_queue.lock().await;
+                                for input in inputs {
+                                    queue.push((addr, input, now), now + processing_delay.input_delay);
+                                }
+                            }
+                            msg => {
+                                let mut rooms = rooms.lock().await;
+                                handle_client_message(&socket, &mut rooms, addr, msg).await;
+                            }
+                        }
+                    }
+                }
+                Err(_e) => {
+                    // Handle errors (e.g., log them)
+                }
+            },
+            _ = &mut shutdown => {
+                println!("Shutting down");
+                housekeeping_task.abort();
+                rooms.lock().await.stop_tasks();
+                if let Some(console_task) = &console_task {
+                    console_task.abort();
+                }
+                if let Some(stats_task) = &stats_task {
+                    stats_task.abort();
+                }
+                if let Some(path) = &leaderboard_path {
+                    let leaderboard = collect_leaderboard(&*rooms.lock().await);
+                    save_leaderboard(&leaderboard, path);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Everything a stats reply is built from, shared with the tasks of the server
+#[derive(Clone)]
+struct StatsSource {
+    rooms: Arc<Mutex<RoomRegistry>>,
+    transport: Arc<Transport>,
+    input_queue: Arc<InputQueue>,
+    receive_guard: Arc<std::sync::Mutex<ReceiveGuard>>,
+    started_at: Instant,
+    tick_rate: u32,
+}
+
+/// Implementation of the StatsSource
+impl StatsSource {
+    /// Takes the stats of the server, locking the rooms briefly like a console command
+    async fn snapshot(&self, responder: &StatsResponder) -> ServerStats {
+        let queued_inputs = self.input_queue.lock().await.len();
+        let rooms = self.rooms.lock().await;
+        let room_stats: Vec<RoomStats> = rooms.games()
+            .map(|(name, game)| RoomStats {
+                name: name.to_string(),
+                mode: game.mode_name().to_string(),
+                players: game.active_player_addrs().len(),
+                match_number: game.match_number(),
+                tick_rate: rooms.configured_tick_rate(name).unwrap_or(self.tick_rate),
+                tick: rooms.tick_percentiles(name).unwrap_or_default(),
+            })
+            .collect();
+        let resumable_sessions = rooms.games().map(|(_, game)| game.resumable_sessions().0).sum();
+        let tick = rooms.combined_tick_percentiles();
+        drop(rooms);
+        let (receive, receive_sources) = {
+            let guard = self.receive_guard.lock().unwrap();
+            (guard.counters(), guard.tracked_sources())
+        };
+        ServerStats {
+            version: BuildInfo::current(None).version,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            tick_rate: self.tick_rate,
+            players: room_stats.iter().map(|room| room.players).sum(),
+            room_count: room_stats.len(),
+            rooms: room_stats,
+            rooms_left_out: 0,
+            tick,
+            memory: MemoryStats {
+                resident_bytes: resident_bytes(),
+                known_clients: self.transport.client_count(),
+                resumable_sessions,
+                queued_inputs,
+                stats_sources: responder.tracked_sources(),
+                receive_sources,
+            },
+            queries: responder.counters(),
+            receive,
+        }
+    }
+}
+
+/// Answers a stats query with the JSON stats if the responder lets it through, refused queries get nothing
+async fn answer_stats_query(socket: &UdpSocket, responder: &mut StatsResponder, request: &StatsRequest, addr: SocketAddr, source: &StatsSource) {
+    if responder.check(request, addr.ip(), Instant::now()).is_err() {
+        return;
+    }
+    let reply = encode_reply(source.snapshot(responder).await, STATS_REPLY_MAX_BYTES);
+    let _ = socket.send_to(&reply, addr).await;
+}
+
+/// Answers the stats queries arriving on the stats port, anything else sent there is ignored
+async fn serve_stats_queries(socket: UdpSocket, mut responder: StatsResponder, source: StatsSource) {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    while let Ok((size, addr)) = socket.recv_from(&mut buf).await {
+        if let Some(request) = parse_request(&buf[..size]) {
+            answer_stats_query(&socket, &mut responder, &request, addr, &source).await;
+        }
+    }
+}
+
+/// Reads lines from standard input on a thread of its own. A blocking read on the runtime would keep it
+/// from shutting down until the next line, the thread just ends with the process.
+fn spawn_console_reader() -> mpsc::UnboundedReceiver<String> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Answers a line typed on the server console. history <id> lists the moves of a player over the last
+/// CONSOLE_HISTORY_WINDOW with what made each of them, rewind <id> <ms> shows where lag compensation would
+/// put the player that long ago, dump lists every room and the history of all players.
+pub fn console_command(rooms: &RoomRegistry, line: &str) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[..] {
+        [] => String::new(),
+        ["history", id] => {
+            let Ok(id) = Uuid::parse_str(id) else {
+                return format!("{} is not a player id\n", id);
+            };
+            rooms.games()
+                .find_map(|(_, game)| {
+                    let since = game.tick_time_ms(Instant::now()).saturating_sub(CONSOLE_HISTORY_WINDOW.as_millis() as u64);
+                    game.history_report(game.addr_of(&id)?, since)
+                })
+                .unwrap_or_else(|| format!("No player {} in any room\n", id))
+        }
+        ["rewind", id, ms] => {
+            let (Ok(id), Ok(ms)) = (Uuid::parse_str(id), ms.parse::<u64>()) else {
+                return format!("Use rewind <player id> <milliseconds ago>, not {}\n", line.trim());
+            };
+            rooms.games()
+                .find_map(|(_, game)| {
+                    let addr = game.addr_of(&id)?;
+                    let now_ms = game.tick_time_ms(Instant::now());
+                    let Some(target_ms) = now_ms.checked_sub(ms) else {
+                        return Some(format!("Player {} {} ms ago is before the room started, {} ms ago\n", id, ms, now_ms));
+                    };
+                    let (position, result) = game.position_at(addr, target_ms, now_ms)?;
+                    Some(describe_rewind(id, ms, position, result))
+                })
+                .unwrap_or_else(|| format!("No player {} in any room\n", id))
+        }
+        ["dump"] => debug_dump(rooms),
+        ["profile"] => profile_report(rooms),
+        ["help"] => "Commands: history <id>, rewind <id> <ms>, dump, profile, help\n".to_string(),
+        _ => format!("Unknown command {}, use history <id>, rewind <id> <ms>, dump, profile or help\n", line.trim()),
+    }
+}
+
+/// Where lag compensation puts a player ms ago, saying whether the history could answer
+fn describe_rewind(id: Uuid, ms: u64, position: Position, result: LagCompResult) -> String {
+    let at = format!("({}, {})", position.x, position.y);
+    match result {
+        LagCompResult::Exact | LagCompResult::ClampedToNewest => format!("Player {} was at {} {} ms ago ({})\n", id, at, ms, result.describe()),
+        LagCompResult::ClampedToOldest { .. } | LagCompResult::NoHistory | LagCompResult::Refused { .. } => {
+            format!("Player {} {} ms ago is unknown, lag compensation uses {} ({})\n", id, ms, at, result.describe())
+        }
+    }
+}
+
+/// Every room with its phase and players, and the whole position history of each player, for debugging
+fn debug_dump(rooms: &RoomRegistry) -> String {
+    let mut dump = format!("Debug dump of {} rooms\n", rooms.len());
+    for (name, game) in rooms.games() {
+        let mut addrs = game.active_player_addrs();
+        addrs.sort();
+        dump.push_str(&format!(
+            "Room {}: {} mode, match {}, {:?}, {} players\n",
+            name, game.mode_name(), game.match_number(), game.phase().to_match_phase(Instant::now()), addrs.len()
+        ));
+        for addr in addrs {
+            dump.push_str(&game.history_report(addr, 0).unwrap_or_default());
+        }
+    }
+    dump
+}
+
+/// Where the recent ticks of every room spent their time under the rooms lock, by profiled scope
+fn profile_report(rooms: &RoomRegistry) -> String {
+    let mut report = String::new();
+    for (name, _) in rooms.games() {
+        let profile = rooms.tick_profile(name).unwrap_or_default();
+        report.push_str(&format!("Room {}: last {} ticks\n", name, profile.frames()));
+        for scope in profile.stats() {
+            report.push_str(&format!("  {:<12} {:.3} ms avg, {:.3} ms max, {:.0}% of the tick\n", scope.name, scope.avg_ms, scope.max_ms, scope.frame_percent));
+        }
+    }
+    report
+}
+
+/// Inputs waiting for the simulated input processing delay, with the time they were received
+type InputQueue = Mutex<DelayQueue<(SocketAddr, PlayerInput, Instant)>>;
+
+/// What the broadcast task of every room shares with the rest of the server
+#[derive(Clone)]
+struct RoomTaskShared {
+    rooms: Arc<Mutex<RoomRegistry>>,
+    socket: Arc<Transport>,
+    input_queue: Arc<InputQueue>,
+    delays: Arc<std::sync::Mutex<BroadcastDelays>>, // Taken by the stats line
+    match_duration: Option<Duration>,
+    processing_delay: ProcessingDelay,
+    tuning: ServerTuning,
+}
+
+/// Delays the room tasks measured since the last stats line, of every room together
+#[derive(Debug, Default)]
+struct BroadcastDelays {
+    tick: DelayStats,      // Simulated processing delay added before sending
+    input: DelayStats,     // Simulated delay of the applied inputs
+    lock_hold: DelayStats, // How long each tick held the rooms lock
+}
+
+/// Broadcast task of one room: ticks the room at its own rate and sends what the tick built. Every room
+/// encodes and sends on its own task, so a room that is slow to serialize doesn't hold up the others.
+/// Ends when the room is gone, the registry aborts it when it removes the room.
+async fn run_room(start: RoomTaskStart, shared: RoomTaskShared) {
+    let RoomTaskStart { name, tick_interval, timings } = start;
+    let mut interval = time::interval(tick_interval);
+    let mut last_server_ping = Instant::now();
+
+    loop {
+        interval.tick().await;
+        let tick_started = Instant::now();
+        let ping_due = last_server_ping.elapsed() >= shared.tuning.server_ping_interval;
+        if ping_due {
+            last_server_ping = Instant::now();
+        }
+
+        // Only the game of the room is touched under the lock, encoding and sending happen after it is released
+        let (mut output, input_delays, lock_held, profile) = {
+            let mut rooms = shared.rooms.lock().await;
+            let locked_at = Instant::now();
+
+            // Inputs of the room's players whose simulated processing delay has passed
+            let ready_inputs = shared.input_queue.lock().await
+                .pop_ready_where(locked_at, |(addr, _, _)| rooms.room_of(addr) == Some(name.as_str()));
+            let Some(game) = rooms.game_mut(&name) else {
+                return;
+            };
+            let input_delays: Vec<Duration> = ready_inputs.iter().map(|(_, _, received_at)| received_at.elapsed()).collect();
+            let (output, profile) = profiler::capture(|| {
+                {
+                    profile_scope!("inputs");
+                    for (addr, input, received_at) in ready_inputs {
+                        game.handle_input_received_at(addr, input, received_at);
+                    }
+                }
+                let mut output = TickOutput::default();
+                run_room_tick(&name, game, shared.match_duration, ping_due, locked_at, &mut output);
+                output
+            });
+            forget_removed(&mut rooms, &output);
+            (output, input_delays, locked_at.elapsed(), profile)
+        };
+
+        for line in &output.log {
+            println!("{}", line);
+        }
+        for (players, reset_msg) in &output.resets {
+            broadcast_message_to_selected(&shared.socket, players, reset_msg).await;
+        }
+
+        // Players that were removed learn why, instead of only dropping out of the snapshots
+        for (addr, removal) in &output.removals {
+            broadcast_message_to_selected(&shared.socket, &[*addr], removal).await;
+        }
+
+        // Simulate a slow server by holding the snapshots for a while before sending
+        let built_at = Instant::now();
+        let tick_delay = shared.processing_delay.sample_tick_delay(&mut rand::rng());
+        if !tick_delay.is_zero() {
+            time::sleep(tick_delay).await;
+        }
+
+        // Snapshots carry the time of their tick, move it on to when they are actually sent
+        let held = built_at.elapsed();
+        for (_, game_state) in output.snapshots.iter_mut() {
+            game_state.server_timestamp += held.as_millis() as u64;
+        }
+
+        // Send the snapshot only to the active players of the room
+        for (active_players, game_state) in &output.snapshots {
+            broadcast_snapshot_to_selected(&shared.socket, active_players, game_state).await;
+        }
+
+        // Ping every player so the server can measure their round trip time
+        for (addr, nonce) in &output.pings {
+            broadcast_message_to_selected(&shared.socket, &[*addr], &ClientMessage::ServerPing(*nonce)).await;
+        }
+
+        {
+            let mut delays = shared.delays.lock().unwrap();
+            for delay in input_delays {
+                delays.input.record(delay);
+            }
+            delays.lock_hold.record(lock_held);
+            if !tick_delay.is_zero() {
+                delays.tick.record(held);
+            }
+        }
+        let mut timings = timings.lock().unwrap();
+        timings.record(tick_started.elapsed());
+        timings.record_profile(profile);
+    }
+}
+
+/// Game state changes and messages of one broadcast tick, sent once the rooms lock is released
+#[derive(Debug, Default)]
+pub(crate) struct TickOutput {
+    pub(crate) resets: Vec<(Vec<SocketAddr>, ClientMessage)>, // Match reset messages with the players of their room
+    pub(crate) snapshots: Vec<(Vec<SocketAddr>, GameState)>,  // Snapshot of every room with its active players
+    pub(crate) pings: Vec<(SocketAddr, u32)>,                 // Server pings with their nonces, empty unless they were due
+    pub(crate) removals: Vec<(SocketAddr, ClientMessage)>,    // Why each player removed since the last tick was removed
+    pub(crate) log: Vec<String>,                              // Lines to print
+}
+
+/// Runs one tick of every room at once: applies the inputs whose simulated delay has passed, moves the match
+/// phases on and builds the snapshots. Only touches game state. The offline mode ticks its rooms this way,
+/// the server ticks each room on its own task with run_room_tick.
+pub(crate) fn run_tick(
+    rooms: &mut RoomRegistry,
+    ready_inputs: Vec<(SocketAddr, PlayerInput, Instant)>,
+    match_duration: Option<Duration>,
+    ping_due: bool,
+    now: Instant,
+) -> TickOutput {
+    let mut output = TickOutput::default();
+    for (addr, input, received_at) in ready_inputs {
+        if let Some(game) = rooms.game_of_mut(&addr) {
+            game.handle_input_received_at(addr, input, received_at);
+        }
+    }
+
+    // Every room runs its own match and gets its own snapshot
+    for (name, game) in rooms.games_mut() {
+        run_room_tick(name, game, match_duration, ping_due, now, &mut output);
+    }
+    forget_removed(rooms, &output);
+    output
+}
+
+/// Forgets the rooms of the players a tick removed, so their addresses are unknown to the server again
+fn forget_removed(rooms: &mut RoomRegistry, output: &TickOutput) {
+    for (addr, _) in &output.removals {
+        rooms.forget(addr);
+    }
+}
+
+/// Runs one tick of a room: moves its match phase on and builds its snapshot, adding what it sends to the output
+pub(crate) fn run_room_tick(name: &str, game: &mut Game, match_duration: Option<Duration>, ping_due: bool, now: Instant, output: &mut TickOutput) {
+    {
+        profile_scope!("phase");
+        game.update_server_dropped();
+        for (addr, id, reason) in game.take_removed() {
+            output.removals.push((addr, ClientMessage::Disconnected { id, reason }));
+        }
+
+        // End the match when the configured duration has passed, then count down to the next one
+        if let Some(duration) = match_duration {
+            if let Some(positions) = game.update_phase(now, duration) {
+                let reset_msg = ClientMessage::MatchReset {
+                    match_number: game.match_number(),
+                    positions,
+                };
+                output.resets.push((game.active_player_addrs(), reset_msg));
+                output.log.push(format!("Match {} counting down in room {}", game.match_number(), name));
+            }
+        }
+    }
+
+    {
+        profile_scope!("simulation");
+        game.advance_tick();
+        game.apply_knockback();
+    }
+    {
+        profile_scope!("snapshot");
+        output.snapshots.push((game.active_player_addrs(), game.build_snapshot()));
+    }
+
+    // Respawns are announced with the match reset, debug teleports and refused moves are worth a line
+    for event in game.take_events() {
+        if !matches!(event, GameEvent::Teleported { reason: PositionChange::Respawn, .. }) {
+            output.log.push(format!("{} in room {}", event.describe(), name));
+        }
+    }
+
+    if ping_due {
+        profile_scope!("pings");
+        output.pings.extend(game.start_server_pings(now));
+    }
+}
+
+/// What the periodic stats line needs from the rooms, taken under the lock and written after it
+#[derive(Debug, Default)]
+struct StatsSample {
+    reorder_distances: Histogram,
+    lag_comp: LagCompCounters,    // Rewinds of every room per result
+    resumable_sessions: usize,    // Timed out sessions every room still keeps for resuming
+    players: usize,               // Active players of every room
+    metrics_rows: Option<String>, // Rows for the metrics CSV, None when it isn't written
+    violations: Vec<String>,      // Broken invariants, with the room they were found in
+}
+
+/// Takes the stats of every room, resetting the reorder distances
+fn sample_stats(rooms: &mut RoomRegistry, with_metrics: bool) -> StatsSample {
+    let mut reorder_distances = Histogram::new();
+    let mut lag_comp = LagCompCounters::default();
+    let (mut resumable_sessions, mut players) = (0, 0);
+    for (_, game) in rooms.games_mut() {
+        reorder_distances.merge(&game.take_reorder_distances());
+        lag_comp.merge(&game.take_lag_comp_counters());
+        resumable_sessions += game.resumable_sessions().0;
+        players += game.active_player_addrs().len();
+    }
+    let metrics_rows = with_metrics.then(|| {
+        let unix_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        rooms.games()
+            .map(|(_, game)| format_metrics_rows(unix_ms, &game.build_snapshot(), &game.instances()))
+            .collect()
+    });
+
+    // Debug builds check after every change, release builds only check here
+    let violations = rooms.games()
+        .flat_map(|(name, game)| game.check_invariants().into_iter().map(move |violation| format!("invariant violated in room {}: {}", name, violation)))
+        .collect();
+    StatsSample { reorder_distances, lag_comp, resumable_sessions, players, metrics_rows, violations }
+}
+
+/// Collects the records of every known player in every room for the leaderboard file
+fn collect_leaderboard(rooms: &RoomRegistry) -> Leaderboard {
+    let mut leaderboard = Leaderboard::default();
+    for (_, game) in rooms.games() {
+        for (token, record) in game.player_records() {
+            leaderboard.record(token, record);
+        }
+    }
+    leaderboard
+}
+
+/// Writes the leaderboard file
+fn save_leaderboard(leaderboard: &Leaderboard, path: &Path) {
+    if let Err(e) = leaderboard.save(path) {
+        eprintln!("Failed to save leaderboard {}: {}", path.display(), e);
+    }
+}
+
+/// Handles a single message received from a client and sends what it answers with
+async fn handle_client_message(socket: &Transport, rooms: &mut RoomRegistry, addr: SocketAddr, msg: ClientMessage) {
+    let mut replies = Vec::new();
+    handle_message(rooms, addr, msg, &mut replies);
+    send_replies(socket, replies).await;
+}
+
+/// Handles a single message received from a client, collecting what it answers with in replies.
+/// Only touches game state, so the server sends the replies over its socket and the offline mode
+/// of the client hands them to its loopback link.
+pub(crate) fn handle_message(rooms: &mut RoomRegistry, addr: SocketAddr, msg: ClientMessage, replies: &mut Vec<(SocketAddr, Reply)>) {
+    match accept_session(rooms, addr, msg) {
+        ClientMessage::Connect => {
+            let room = rooms.default_room().to_string();
+            let Some(game) = join_room(replies, rooms, addr, &room, None) else {
+                return;
+            };
+            let id = game.connect_player(addr);
+            send_welcome(replies, game, addr, false);
+            println!("Player {} connected from {} to room {}", id, addr, room);
+        }
+        ClientMessage::ConnectWithProfile { name, shape, instance, room, password } => {
+            let room = rooms.resolve_name(&room);
+            if let Err(reason) = check_profile(&name, password.as_deref()) {
+                reject(replies, addr, &room, reason);
+                return;
+            }
+            let Some(game) = join_room(replies, rooms, addr, &room, password.as_deref()) else {
+                return;
+            };
+            let id = game.connect_player(addr);
+            game.set_profile(&addr, &name, PlayerShape::from_wire(shape));
+            game.set_instance(&addr, instance);
+            // A returning player without its old token gets its best score back by name
+            let restored = if game.restore_saved_record_by_name(&addr) { " (best score restored)" } else { "" };
+            send_welcome(replies, game, addr, false);
+            println!("Player {} ({}) connected from {} to room {}, instance {}{}", id, game.player_name(&addr).unwrap_or_default(), addr, room, format_instance(instance), restored);
+        }
+        ClientMessage::Reconnect { session_token } => {
+            // Resume the session in its room if the token is still valid, otherwise connect to the default room as a new player
+            if let Some((room, id)) = rooms.reconnect(addr, session_token) {
+                if let Some(game) = rooms.game_mut(&room) {
+                    send_welcome(replies, game, addr, true);
+                }
+                println!("Player {} reconnected from {} to room {}", id, addr, room);
+                return;
+            }
+            let room = rooms.default_room().to_string();
+            let Some(game) = join_room(replies, rooms, addr, &room, None) else {
+                return;
+            };
+            let id = game.connect_player(addr);
+            // A player from an earlier server run gets its best score back
+            let restored = game.restore_saved_record(&addr, session_token);
+            send_welcome(replies, game, addr, false);
+            if restored {
+                println!("Player {} connected from {} (best score restored)", id, addr);
+            } else {
+                println!("Player {} connected from {} (session expired)", id, addr);
+            }
+        }
+        ClientMessage::Input(input) => {
+            if let Some(game) = rooms.game_of_mut(&addr) {
+                game.handle_input(addr, input);
+                game.update_server_dropped();
+            }
+        }
+        ClientMessage::InputBatch(inputs) => {
+            // Applied oldest first, the same as if they had arrived one by one
+            if let Some(game) = rooms.game_of_mut(&addr) {
+                for input in inputs {
+                    game.handle_input(addr, input);
+                }
+                game.update_server_dropped();
+            }
+        }
+        ClientMessage::Ping(timestamp) => {
+            // Echo back the timestamp as a pong
+            replies.push((addr, Reply::Message(ClientMessage::Pong(timestamp))));
+
+            // Update player's last active time
+            if let Some(player) = rooms.game_of_mut(&addr).and_then(|game| game.get_players_mut().get_mut(&addr)) {
+                player.last_active = Instant::now();
+            }
+        }
+        ClientMessage::ServerPong(nonce) => {
+            if let Some(game) = rooms.game_of_mut(&addr) {
+                game.handle_server_pong(addr, nonce, Instant::now());
+            }
+        }
+        ClientMessage::ListRooms => {
+            replies.push((addr, Reply::Message(ClientMessage::RoomList(rooms.listing()))));
+        }
+        ClientMessage::DebugSetPosition(position) => {
+            // Refused teleports are reported with the other game events
+            if let Some(game) = rooms.game_of_mut(&addr) {
+                let _ = game.debug_teleport(addr, position);
+            }
+        }
+        ClientMessage::Leave => {
+            if let Some((room, id)) = rooms.leave(addr) {
+                println!("Player {} left room {}", id, room);
+            }
+        }
+        ClientMessage::Pong(_)
+        | ClientMessage::ServerPing(_)
+        | ClientMessage::PlayerId(_)
+        | ClientMessage::MatchReset { .. }
+        | ClientMessage::Welcome(_)
+        | ClientMessage::RoomList(_)
+        | ClientMessage::ConnectRejected { .. }
+        | ClientMessage::Disconnected { .. } => {
+            // Ignore server-to-client messages sent by clients
+        }
+        ClientMessage::WithSession { .. } => {
+            // Ignore a session header inside another one
+        }
+    }
+}
+
+/// Takes the message out of its session header. A header from an address that isn't in a room moves
+/// the session to that address first, so a client behind a NAT that changed its port keeps playing.
+pub(crate) fn accept_session(rooms: &mut RoomRegistry, addr: SocketAddr, msg: ClientMessage) -> ClientMessage {
+    let ClientMessage::WithSession { session_token, message } = msg else {
+        return msg;
+    };
+    if let Some((room, id, old_addr)) = rooms.rebind(addr, session_token) {
+        println!("Player {} moved from {} to {} in room {}", id, old_addr, addr, room);
+    }
+    *message
+}
+
+/// Puts a player in a room, or tells the client why it wasn't let in
+fn join_room<'a>(replies: &mut Vec<(SocketAddr, Reply)>, rooms: &'a mut RoomRegistry, addr: SocketAddr, room: &str, password: Option<&str>) -> Option<&'a mut Game> {
+    match rooms.join(addr, room, password) {
+        Ok(game) => Some(game),
+        Err(reason) => {
+            reject(replies, addr, room, reason);
+            None
+        }
+    }
+}
+
+/// Tells the client why it wasn't let in the room
+fn reject(replies: &mut Vec<(SocketAddr, Reply)>, addr: SocketAddr, room: &str, reason: RejectReason) {
+    println!("Rejected {} joining room {}: {}", addr, room, reason.describe());
+    replies.push((addr, Reply::Message(ClientMessage::ConnectRejected { reason })));
+}
+
+/// Refuses a name over MAX_NAME_CHARS or a password over MAX_PASSWORD_CHARS instead of cutting them,
+/// a cut password would be checked as a different one
+fn check_profile(name: &str, password: Option<&str>) -> Result<(), RejectReason> {
+    if name.trim().chars().count() > MAX_NAME_CHARS {
+        return Err(RejectReason::NameTooLong);
+    }
+    if password.is_some_and(|password| password.chars().count() > MAX_PASSWORD_CHARS) {
+        return Err(RejectReason::PasswordTooLong);
+    }
+    Ok(())
+}
+
+/// Answers a player with the Welcome message followed by the initial game state
+fn send_welcome(replies: &mut Vec<(SocketAddr, Reply)>, game: &Game, addr: SocketAddr, resumed: bool) {
+    if let Some(welcome) = game.build_welcome(&addr, resumed) {
+        replies.push((addr, Reply::Message(ClientMessage::Welcome(welcome))));
+    }
+
+    // Send initial game state to the player
+    replies.push((addr, Reply::Snapshot(Box::new(game.build_snapshot()))));
+}
+
+/// A datagram the server answers a client with, before it is encoded in the client's protocol
+#[derive(Debug)]
+pub(crate) enum Reply {
+    Message(ClientMessage),
+    Snapshot(Box<GameState>),
+}
+
+/// Sends the replies in order, each in the protocol its client speaks
+async fn send_replies(socket: &Transport, replies: Vec<(SocketAddr, Reply)>) {
+    for (addr, reply) in replies {
+        match reply {
+            Reply::Message(message) => broadcast_message_to_selected(socket, &[addr], &message).await,
+            Reply::Snapshot(snapshot) => broadcast_snapshot_to_selected(socket, &[addr], &snapshot).await,
+        }
+    }
+}
+
+/// Broadcasts the game state snapshot to all active players, each in the protocol it speaks
+async fn broadcast_snapshot_to_selected(
+    socket: &Transport,
+    active_players: &[SocketAddr],
+    snapshot: &GameState,
+) {
+    socket.send_snapshot(active_players, snapshot).await;
+}
+
+/// Sends a message to all active players, each in the protocol it speaks
+async fn broadcast_message_to_selected(
+    socket: &Transport,
+    active_players: &[SocketAddr],
+    message: &ClientMessage,
+) {
+    socket.send_message(active_players, message).await;
+}
+
+
+/// Simulated server processing delay, to compare slow servers with network latency.
+/// In the server config file the delays are milliseconds, like the flags.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessingDelay {
+    #[serde(rename = "tick_delay_ms", with = "millis")]
+    pub tick_delay: Duration,  // Added to every broadcast tick before sending
+    #[serde(rename = "tick_jitter_ms", with = "millis")]
+    pub tick_jitter: Duration, // Random variation of the tick delay (±)
+    #[serde(rename = "input_delay_ms", with = "millis")]
+    pub input_delay: Duration, // How long inputs wait before they are applied
+}
+
+/// Implementation of the ProcessingDelay
+impl ProcessingDelay {
+    /// Checks if any processing delay is simulated
+    pub fn is_enabled(&self) -> bool {
+        !self.tick_delay.is_zero() || !self.tick_jitter.is_zero() || !self.input_delay.is_zero()
+    }
+
+    /// Draws the delay for one broadcast tick, uniformly within the jitter and never negative
+    fn sample_tick_delay(&self, rng: &mut impl Rng) -> Duration {
+        let base = self.tick_delay.as_millis() as i64;
+        let jitter = self.tick_jitter.as_millis() as i64;
+        let offset = if jitter > 0 { rng.random_range(-jitter..=jitter) } else { 0 };
+        Duration::from_millis((base + offset).max(0) as u64)
+    }
+
+    /// Describes the configured delay
+    pub fn describe(&self) -> String {
+        format!(
+            "tick {}±{} ms, input {} ms",
+            self.tick_delay.as_millis(),
+            self.tick_jitter.as_millis(),
+            self.input_delay.as_millis()
+        )
+    }
+}
+
+/// Tracks the delay that was actually added, which can be more than configured due to timer resolution
+#[derive(Debug, Default)]
+struct DelayStats {
+    count: u32,
+    total: Duration,
+    max: Duration,
+}
+
+/// Implementation of the DelayStats
+impl DelayStats {
+    /// Records one added delay
+    fn record(&mut self, delay: Duration) {
+        self.count += 1;
+        self.total += delay;
+        self.max = self.max.max(delay);
+    }
+
+    /// Average added delay, zero if nothing was recorded
+    fn average(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.count
+        }
+    }
+}
+
+/// Header of the metrics CSV
+const METRICS_CSV_HEADER: &str = "unix_ms,instance,player_id,rtt_ms,score,input_reorders\n";
+
+/// Formats one metrics CSV row for every player that sent its hashed instance id, sorted by instance
+fn format_metrics_rows(unix_ms: u128, snapshot: &GameState, instances: &HashMap<Uuid, u64>) -> String {
+    let mut players: Vec<(u64, Uuid)> = instances.iter().map(|(id, instance)| (*instance, *id)).collect();
+    players.sort();
+    players.iter()
+        .map(|(instance, id)| {
+            let rtt = snapshot.ping_ms.get(id).map(u32::to_string).unwrap_or_default();
+            format!(
+                "{},{},{},{},{},{}\n",
+                unix_ms,
+                format_instance(*instance),
+                id,
+                rtt,
+                snapshot.scores.get(id).copied().unwrap_or(0),
+                snapshot.input_reorders.get(id).copied().unwrap_or(0),
+            )
+        })
+        .collect()
+}
+
+/// Appends rows to the metrics CSV, writing the build of the server and the header first if the file is new
+fn append_metrics_csv(path: &Path, build: &BuildInfo, rows: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        file.write_all(BuildInfo::csv_comment("server", Some(build)).as_bytes())?;
+        file.write_all(METRICS_CSV_HEADER.as_bytes())?;
+    }
+    file.write_all(rows.as_bytes())
+}
+
+/// Formats the periodic stats line
+fn format_stats_line(
+    player_count: usize,
+    stats: &StatsSample,
+    processing_delay: &ProcessingDelay,
+    tick_stats: &DelayStats,
+    input_stats: &DelayStats,
+    lock_stats: &DelayStats,
+) -> String {
+    let mut line = format!("[stats] players: {}", player_count);
+    if stats.resumable_sessions > 0 {
+        line.push_str(&format!(" | resumable sessions: {}", stats.resumable_sessions));
+    }
+    if lock_stats.count > 0 {
+        line.push_str(&format!(
+            " | rooms lock per tick: avg {:.2} ms, max {:.2} ms",
+            lock_stats.average().as_secs_f64() * 1000.0,
+            lock_stats.max.as_secs_f64() * 1000.0,
+        ));
+    }
+    if processing_delay.is_enabled() {
+        line.push_str(&format!(
+            " | tick delay: configured {}±{} ms, actual avg {:.1} ms, max {:.1} ms",
+            processing_delay.tick_delay.as_millis(),
+            processing_delay.tick_jitter.as_millis(),
+            tick_stats.average().as_secs_f64() * 1000.0,
+            tick_stats.max.as_secs_f64() * 1000.0,
+        ));
+        line.push_str(&format!(
+            " | input delay: configured {} ms, actual avg {:.1} ms, max {:.1} ms",
+            processing_delay.input_delay.as_millis(),
+            input_stats.average().as_secs_f64() * 1000.0,
+            input_stats.max.as_secs_f64() * 1000.0,
+        ));
+    }
+    if !stats.reorder_distances.is_empty() {
+        line.push_str(&format!(
+            " | reordered inputs: {}, distance avg {:.1}, max {} ({})",
+            stats.reorder_distances.count(),
+            stats.reorder_distances.mean(),
+            stats.reorder_distances.max(),
+            stats.reorder_distances.describe(),
+        ));
+    }
+    if stats.lag_comp.total() > 0 {
+        line.push_str(&format!(" | rewinds: {}", stats.lag_comp.describe()));
+    }
+    line
+}
+
+
+/// Tests for the server loop and message handling
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decode, encode, CodecKind, Incoming};
+    use crate::constants::BROADCAST_INTERVAL;
+    use crate::snapshot::decode_snapshot;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use tokio::time::sleep;
+    use uuid::Uuid;
+    use crate::constants::DEFAULT_ROOM;
+    use crate::rooms::RoomSettings;
+    use crate::types::{DisconnectReason, MoveOrigin, Position, RejectReason, RoomInfo};
+    use crate::constants::STATS_QUERY_INTERVAL;
+
+    #[tokio::test]
+    async fn test_broadcast_snapshot_to_selected() {
+        // Create a mock socket using a real UDP socket bound to a temporary port
+        let socket = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let server_addr = socket.socket().local_addr().unwrap();
+
+        // Create client sockets to receive the broadcast
+        let client1 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client1_addr = client1.local_addr().unwrap();
+        let client2 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client2_addr = client2.local_addr().unwrap();
+
+        // Connect clients to the server (for UDP this just sets the default destination)
+        client1.connect(server_addr).await.unwrap();
+        client2.connect(server_addr).await.unwrap();
+
+        // Create a test game state
+        let player_id1 = Uuid::new_v4();
+        let player_id2 = Uuid::new_v4();
+
+        // Create player data for the game state
+        let mut players = Vec::new();
+        let mut last_processed = std::collections::HashMap::new();
+
+        // Add players to the vector (using the expected (Uuid, Position, u32) format)
+        players.push((player_id1, Position { x: 100, y: 100 }, 0));
+        players.push((player_id2, Position { x: 200, y: 200 }, 0));
+
+        last_processed.insert(player_id1, 5);
+        last_processed.insert(player_id2, 10);
+
+        let game_state = GameState {
+            players,
+            last_processed,
+            pellets: Vec::new(),
+            scores: std::collections::HashMap::new(),
+            best_scores: std::collections::HashMap::new(),
+            teams: std::collections::HashMap::new(),
+            input_reorders: std::collections::HashMap::new(),
+            shapes: std::collections::HashMap::new(),
+            impulses: std::collections::HashMap::new(),
+            input_timings: std::collections::HashMap::new(),
+            match_phase: Default::default(),
+            server_timestamp: 123456,
+            match_number: 0,
+            ping_ms: std::collections::HashMap::new(),
+            snapshot_id: 1,
+            tick_interval_ms: 16,
+            tagged: None,
+            movement_stats: std::collections::HashMap::new(),
+        };
+
+        // Broadcast to the client addresses
+        broadcast_snapshot_to_selected(&socket, &[client1_addr, client2_addr], &game_state).await;
+
+        // Now check that both clients received the broadcast
+        let mut buf = [0u8; 1024];
+
+        // Set a timeout for receiving
+        tokio::select! {
+            res = client1.recv(&mut buf) => {
+                let size = res.unwrap();
+                let received = decode_snapshot(&buf[..size]).unwrap();
+                assert_eq!(received.server_timestamp, 123456);
+                assert_eq!(received.players.len(), 2);
+            }
+            _ = sleep(Duration::from_millis(100)) => {
+                panic!("Timeout waiting for broadcast to client 1");
+            }
+        }
+
+        tokio::select! {
+            res = client2.recv(&mut buf) => {
+                let size = res.unwrap();
+                let received = decode_snapshot(&buf[..size]).unwrap();
+                assert_eq!(received.server_timestamp, 123456);
+                assert_eq!(received.players.len(), 2);
+            }
+            _ = sleep(Duration::from_millis(100)) => {
+                panic!("Timeout waiting for broadcast to client 2");
+            }
+        }
+    }
+
+
+
+
+    #[test]
+    fn test_metrics_csv() {
+        let mut game = Game::new();
+        let with_instance = game.connect_player("127.0.0.1:8080".parse().unwrap());
+        game.connect_player("127.0.0.1:8081".parse().unwrap()); // An older client without an instance id
+        game.set_instance(&"127.0.0.1:8080".parse().unwrap(), 0xabc);
+
+        let rows = format_metrics_rows(1_700_000_000_000, &game.build_snapshot(), &game.instances());
+        assert_eq!(rows, format!("1700000000000,0000000000000abc,{},,0,0\n", with_instance));
+
+        // The build and the header are written once, when the file is created
+        let path = std::env::temp_dir().join(format!("metrics-test-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let build = BuildInfo::current(Some(12));
+        append_metrics_csv(&path, &build, &rows).unwrap();
+        append_metrics_csv(&path, &build, &rows).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let (comment, rest) = csv.split_once('\n').unwrap();
+        assert_eq!(BuildInfo::parse_csv_comment(comment), Some(("server".to_string(), Some(build))));
+        assert_eq!(rest, format!("{}{}{}", METRICS_CSV_HEADER, rows, rows));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+
+    #[test]
+    fn test_sample_tick_delay() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+
+        // Without jitter the delay is exact
+        let fixed = ProcessingDelay { tick_delay: Duration::from_millis(20), ..Default::default() };
+        assert_eq!(fixed.sample_tick_delay(&mut rng), Duration::from_millis(20));
+
+        // With jitter the delay stays within bounds and actually varies
+        let jittered = ProcessingDelay {
+            tick_delay: Duration::from_millis(20),
+            tick_jitter: Duration::from_millis(5),
+            ..Default::default()
+        };
+        let samples: Vec<Duration> = (0..200).map(|_| jittered.sample_tick_delay(&mut rng)).collect();
+        assert!(samples.iter().all(|d| *d >= Duration::from_millis(15) && *d <= Duration::from_millis(25)));
+        assert!(samples.iter().any(|d| *d != samples[0]));
+
+        // Jitter larger than the delay never produces a negative delay
+        let wide = ProcessingDelay { tick_jitter: Duration::from_millis(10), ..Default::default() };
+        for _ in 0..100 {
+            assert!(wide.sample_tick_delay(&mut rng) <= Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_delay_stats() {
+        let mut stats = DelayStats::default();
+        assert_eq!(stats.average(), Duration::ZERO);
+
+        stats.record(Duration::from_millis(10));
+        stats.record(Duration::from_millis(30));
+        assert_eq!(stats.average(), Duration::from_millis(20));
+        assert_eq!(stats.max, Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_format_stats_line() {
+        let disabled = format_stats_line(3, &StatsSample::default(), &ProcessingDelay::default(), &DelayStats::default(), &DelayStats::default(), &DelayStats::default());
+        assert_eq!(disabled, "[stats] players: 3");
+
+        let delay = ProcessingDelay {
+            tick_delay: Duration::from_millis(20),
+            tick_jitter: Duration::from_millis(5),
+            input_delay: Duration::from_millis(50),
+        };
+        let mut tick_stats = DelayStats::default();
+        tick_stats.record(Duration::from_millis(21));
+        let mut input_stats = DelayStats::default();
+        input_stats.record(Duration::from_millis(58));
+
+        let line = format_stats_line(2, &StatsSample::default(), &delay, &tick_stats, &input_stats, &DelayStats::default());
+        assert!(line.contains("players: 2"));
+        assert!(line.contains("tick delay: configured 20±5 ms, actual avg 21.0 ms"));
+        assert!(line.contains("input delay: configured 50 ms, actual avg 58.0 ms"));
+        assert!(!line.contains("reordered"));
+
+        let mut reorders = StatsSample::default();
+        for distance in [1, 1, 3] {
+            reorders.reorder_distances.record(distance);
+        }
+        let line = format_stats_line(2, &reorders, &ProcessingDelay::default(), &DelayStats::default(), &DelayStats::default(), &DelayStats::default());
+        assert!(line.ends_with(" | reordered inputs: 3, distance avg 1.7, max 3 (1: 2, 2-3: 1)"), "{}", line);
+
+        let mut lock_stats = DelayStats::default();
+        lock_stats.record(Duration::from_micros(500));
+        lock_stats.record(Duration::from_micros(1500));
+        let line = format_stats_line(2, &StatsSample::default(), &ProcessingDelay::default(), &DelayStats::default(), &DelayStats::default(), &lock_stats);
+        assert_eq!(line, "[stats] players: 2 | rooms lock per tick: avg 1.00 ms, max 1.50 ms");
+
+        let line = format_stats_line(2, &StatsSample { resumable_sessions: 4, ..Default::default() }, &ProcessingDelay::default(), &DelayStats::default(), &DelayStats::default(), &DelayStats::default());
+        assert_eq!(line, "[stats] players: 2 | resumable sessions: 4");
+
+        let mut rewinds = StatsSample::default();
+        rewinds.lag_comp.record(LagCompResult::Exact);
+        rewinds.lag_comp.record(LagCompResult::Refused { age_ms: 1500 });
+        let line = format_stats_line(2, &rewinds, &ProcessingDelay::default(), &DelayStats::default(), &DelayStats::default(), &DelayStats::default());
+        assert_eq!(line, "[stats] players: 2 | rewinds: 1 exact, 0 after newest, 0 clamped to oldest, 0 without history, 1 refused");
+    }
+
+    #[test]
+    fn test_run_tick_applies_ready_inputs_and_pings_when_due() {
+        use crate::types::{Direction, PlayerInput};
+
+        let mut rooms = default_rooms();
+        let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        rooms.join(addr, DEFAULT_ROOM, None).unwrap().connect_player(addr);
+        let input = PlayerInput { dir: Direction::Right, sequence: 1, timestamp: 0 };
+
+        let output = run_tick(&mut rooms, vec![(addr, input, Instant::now())], None, false, Instant::now());
+        assert!(output.pings.is_empty());
+        assert!(output.resets.is_empty());
+        let (active_players, snapshot) = &output.snapshots[0];
+        assert_eq!(active_players, &vec![addr]);
+        assert_eq!(snapshot.last_processed.values().copied().collect::<Vec<_>>(), vec![1]);
+
+        let output = run_tick(&mut rooms, Vec::new(), None, true, Instant::now());
+        assert_eq!(output.pings.iter().map(|(ping_addr, _)| *ping_addr).collect::<Vec<_>>(), vec![addr]);
+        assert!(output.removals.is_empty());
+    }
+
+    #[test]
+    fn test_run_tick_tells_timed_out_players_why() {
+        let mut rooms = default_rooms();
+        let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let id = rooms.join(addr, DEFAULT_ROOM, None).unwrap().connect_player(addr);
+        lobby(&mut rooms).get_players_mut().get_mut(&addr).unwrap().last_active = Instant::now() - TIMEOUT;
+
+        let output = run_tick(&mut rooms, Vec::new(), None, false, Instant::now());
+        assert_eq!(output.removals, vec![(addr, ClientMessage::Disconnected { id, reason: DisconnectReason::TimedOut })]);
+        assert!(output.snapshots[0].0.is_empty());
+
+        // The address is forgotten, its next datagram is treated like any unknown one
+        assert_eq!(rooms.room_of(&addr), None);
+        assert!(run_tick(&mut rooms, Vec::new(), None, false, Instant::now()).removals.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_match_reset() {
+        let socket = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        let player_id = Uuid::new_v4();
+        let reset_msg = ClientMessage::MatchReset {
+            match_number: 2,
+            positions: vec![(player_id, Position { x: 50, y: 60 })],
+        };
+        broadcast_message_to_selected(&socket, &[client_addr], &reset_msg).await;
+
+        let mut buf = [0u8; 1024];
+        tokio::select! {
+            res = client.recv(&mut buf) => {
+                let size = res.unwrap();
+                match decode(&buf[..size]).unwrap() {
+                    ClientMessage::MatchReset { match_number, positions } => {
+                        assert_eq!(match_number, 2);
+                        assert_eq!(positions, vec![(player_id, Position { x: 50, y: 60 })]);
+                    }
+                    other => panic!("Expected MatchReset, got {:?}", other),
+                }
+            }
+            _ = sleep(Duration::from_millis(100)) => {
+                panic!("Timeout waiting for MatchReset");
+            }
+        }
+    }
+
+    // The second test can be kept as is
+    #[tokio::test]
+    async fn test_server_connect_handler() {
+        // Start a server on a random port
+        let server_socket = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let server_addr = server_socket.socket().local_addr().unwrap();
+
+        let rooms = Arc::new(Mutex::new(default_rooms()));
+        let socket_arc = Arc::new(server_socket);
+
+        // Spawn the server handler task
+        let socket_clone = Arc::clone(&socket_arc);
+        let rooms_clone = Arc::clone(&rooms);
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+
+            // Just handle one message for the test
+            if let Ok((size, addr)) = socket_clone.socket().recv_from(&mut buf).await {
+                if let Some(msg) = socket_clone.decode(addr, &buf[..size]) {
+                    let mut rooms = rooms_clone.lock().await;
+                    handle_client_message(&socket_clone, &mut rooms, addr, msg).await;
+                }
+            }
+        });
+
+        // Create a client and connect to the server
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server_addr).await.unwrap();
+
+        // Send a Connect message
+        let connect_msg = ClientMessage::Connect;
+        let connect_payload = encode(&connect_msg);
+        client.send(&connect_payload).await.unwrap();
+
+        // Wait for the response - should be a Welcome message
+        let mut buf = [0u8; 1024];
+
+        tokio::select! {
+            res = client.recv(&mut buf) => {
+                let size = res.unwrap();
+                if let Ok(ClientMessage::Welcome(welcome)) = decode(&buf[..size]) {
+                    assert!(!welcome.id.to_string().is_empty());
+                    assert_eq!(welcome.last_processed, None);
+                } else {
+                    panic!("Expected Welcome message");
+                }
+            }
+            _ = sleep(Duration::from_millis(100)) => {
+                panic!("Timeout waiting for Welcome response");
+            }
+        }
+
+        // Wait for the GameState message
+        tokio::select! {
+            res = client.recv(&mut buf) => {
+                let size = res.unwrap();
+                let game_state = decode_snapshot(&buf[..size]).unwrap();
+                assert_eq!(game_state.players.len(), 1);
+            }
+            _ = sleep(Duration::from_millis(100)) => {
+                panic!("Timeout waiting for GameState");
+            }
+        }
+    }
+
+    // Helper function to create the rooms of a server started without a room config
+    fn default_rooms() -> RoomRegistry {
+        RoomRegistry::new(RoomConfig::default(), Game::new)
+    }
+
+    // Helper function to get the game of the default room
+    fn lobby(rooms: &mut RoomRegistry) -> &mut Game {
+        rooms.game_mut(DEFAULT_ROOM).unwrap()
+    }
+
+    // Helper function to wait for the next Welcome message on a socket
+    async fn recv_welcome(client: &UdpSocket) -> crate::types::Welcome {
+        let mut buf = [0u8; 1024];
+        loop {
+            tokio::select! {
+                res = client.recv(&mut buf) => {
+                    let size = res.unwrap();
+                    if let Ok(ClientMessage::Welcome(welcome)) = decode(&buf[..size]) {
+                        return welcome;
+                    }
+                }
+                _ = sleep(Duration::from_millis(100)) => {
+                    panic!("Timeout waiting for Welcome");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_reconnect_after_timeout() {
+        use crate::constants::TIMEOUT;
+        use crate::positions::AuthoritativePos;
+        use crate::prediction::PredictionState;
+        use crate::types::{Direction, PlayerInput};
+
+        let server = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let mut rooms = default_rooms();
+
+        // Connect and send some inputs
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        handle_client_message(&server, &mut rooms, client_addr, ClientMessage::Connect).await;
+        let welcome = recv_welcome(&client).await;
+        let mut prediction = PredictionState::new(AuthoritativePos::new(welcome.position));
+        prediction.resume(AuthoritativePos::new(welcome.position), welcome.last_processed);
+
+        for _ in 0..20 {
+            let input = PlayerInput { dir: Direction::Down, sequence: prediction.next_sequence, timestamp: 0 };
+            prediction.next_sequence += 1;
+            handle_client_message(&server, &mut rooms, client_addr, ClientMessage::Input(input)).await;
+        }
+
+        // The player times out
+        lobby(&mut rooms).get_players_mut().get_mut(&client_addr).unwrap().last_active = Instant::now() - TIMEOUT;
+        lobby(&mut rooms).update_server_dropped();
+        assert!(lobby(&mut rooms).active_player_addrs().is_empty());
+
+        // Reconnect from a new socket with the session token
+        let new_client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let new_addr = new_client.local_addr().unwrap();
+        let reconnect = ClientMessage::Reconnect { session_token: welcome.session_token };
+        handle_client_message(&server, &mut rooms, new_addr, reconnect).await;
+        let resumed = recv_welcome(&new_client).await;
+        assert_eq!(resumed.id, welcome.id);
+        assert_eq!(resumed.last_processed, Some(19));
+
+        // The next input is applied immediately
+        let mut my_pos = AuthoritativePos::new(resumed.position);
+        prediction.resume(my_pos, resumed.last_processed);
+        let input = PlayerInput { dir: Direction::Right, sequence: prediction.next_sequence, timestamp: 0 };
+        prediction.apply_prediction(input, &mut my_pos);
+        handle_client_message(&server, &mut rooms, new_addr, ClientMessage::Input(input)).await;
+
+        let snapshot = lobby(&mut rooms).build_snapshot();
+        assert_eq!(snapshot.last_processed.get(&welcome.id), Some(&20));
+        assert_eq!(lobby(&mut rooms).get_players_mut().get(&new_addr).unwrap().position, my_pos.position());
+    }
+
+    #[tokio::test]
+    async fn test_port_change_mid_movement_keeps_the_session() {
+        use crate::constants::PLAYER_SPEED;
+        use crate::types::{Direction, PlayerInput};
+
+        let server = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let mut rooms = default_rooms();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        handle_client_message(&server, &mut rooms, client_addr, ClientMessage::Connect).await;
+        let welcome = recv_welcome(&client).await;
+        let with_session = |message| ClientMessage::WithSession { session_token: welcome.session_token, message: Box::new(message) };
+        // Away from the board edges so the moves aren't clamped
+        lobby(&mut rooms).set_position_validated(client_addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+
+        for sequence in 0..5 {
+            let input = PlayerInput { dir: Direction::Down, sequence, timestamp: 0 };
+            handle_client_message(&server, &mut rooms, client_addr, with_session(ClientMessage::Input(input))).await;
+        }
+        let before = lobby(&mut rooms).get_players_mut()[&client_addr].position;
+
+        // The NAT gives the client a new port, its next input arrives from an unknown address
+        let moved = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let moved_addr = moved.local_addr().unwrap();
+        let input = PlayerInput { dir: Direction::Down, sequence: 5, timestamp: 0 };
+        handle_client_message(&server, &mut rooms, moved_addr, with_session(ClientMessage::Input(input))).await;
+
+        let game = lobby(&mut rooms);
+        assert_eq!(game.active_player_addrs(), vec![moved_addr]);
+        assert_eq!(game.build_snapshot().last_processed.get(&welcome.id), Some(&5));
+        let after = game.get_players_mut()[&moved_addr].position;
+        assert_eq!((after.x, after.y), (before.x, before.y + PLAYER_SPEED));
+        assert_eq!(rooms.room_of(&moved_addr), Some(DEFAULT_ROOM));
+        assert_eq!(rooms.room_of(&client_addr), None);
+
+        // Pings are answered at the new address
+        handle_client_message(&server, &mut rooms, moved_addr, with_session(ClientMessage::Ping(77))).await;
+        let mut buf = [0u8; 1024];
+        let size = tokio::time::timeout(Duration::from_millis(100), moved.recv(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(decode::<ClientMessage>(&buf[..size]).unwrap(), ClientMessage::Pong(77));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_tick_sends_nothing_under_the_lock_with_many_players() {
+        use crate::constants::MAX_DATAGRAM_SIZE;
+        use crate::types::{Direction, PlayerInput};
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // Every player is a socket bound by the test, so nothing is sent to ports owned by someone else
+        let socket = Arc::new(Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None));
+        let mut receivers = Vec::new();
+        for _ in 0..200 {
+            receivers.push(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        }
+        let addrs: Vec<SocketAddr> = receivers.iter().map(|receiver| receiver.local_addr().unwrap()).collect();
+        let mut registry = default_rooms();
+        for addr in &addrs {
+            registry.join(*addr, DEFAULT_ROOM, None).unwrap().connect_player(*addr);
+        }
+        let rooms = Arc::new(Mutex::new(registry));
+
+        // Inputs keep arriving from every player while the ticks run, they are never answered
+        let stop = Arc::new(AtomicBool::new(false));
+        let input_task = {
+            let (socket, rooms, stop, addrs) = (Arc::clone(&socket), Arc::clone(&rooms), Arc::clone(&stop), addrs.clone());
+            tokio::spawn(async move {
+                let mut applied = 0u32;
+                while !stop.load(Ordering::Relaxed) {
+                    for addr in &addrs {
+                        let input = PlayerInput { dir: Direction::Right, sequence: applied / addrs.len() as u32, timestamp: 0 };
+                        handle_client_message(&socket, &mut *rooms.lock().await, *addr, ClientMessage::Input(input)).await;
+                        applied += 1;
+                    }
+                    tokio::task::yield_now().await;
+                }
+                applied
+            })
+        };
+
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        let mut processed = Vec::new();
+        for tick in 0..30 {
+            let output = {
+                let mut rooms = rooms.lock().await;
+                let output = run_tick(&mut rooms, Vec::new(), None, tick % 10 == 0, Instant::now());
+                // The tick only builds the snapshot, nothing is encoded or sent while the lock is held
+                for receiver in &receivers {
+                    assert!(receiver.try_recv(&mut buf).is_err(), "a datagram was sent under the lock on tick {}", tick);
+                }
+                output
+            };
+            processed.push(output.snapshots[0].1.last_processed.values().map(|sequence| *sequence as u64).sum::<u64>());
+
+            // Sending happens after the lock is released, one snapshot per player
+            assert_eq!(output.snapshots.len(), 1);
+            assert_eq!(output.snapshots[0].0.len(), addrs.len());
+            for (active_players, game_state) in &output.snapshots {
+                broadcast_snapshot_to_selected(&socket, active_players, game_state).await;
+            }
+            let mut sizes = Vec::new();
+            for receiver in &receivers {
+                sizes.push(tokio::time::timeout(Duration::from_secs(1), receiver.recv(&mut buf)).await.unwrap().unwrap());
+                assert!(receiver.try_recv(&mut buf).is_err());
+            }
+            // Everyone gets the same payload, the last one received is decoded
+            assert!(sizes.iter().all(|size| *size == sizes[0]));
+            assert!(decode_snapshot(&buf[..sizes[0]]).is_some());
+        }
+        stop.store(true, Ordering::Relaxed);
+        let applied = input_task.await.unwrap();
+
+        assert!(applied as usize > addrs.len(), "only {} inputs were applied", applied);
+        assert!(processed.last() > processed.first(), "no inputs were applied between the ticks");
+    }
+
+    #[tokio::test]
+    async fn test_input_batch_is_applied_like_single_inputs() {
+        use crate::positions::AuthoritativePos;
+        use crate::prediction::PredictionState;
+        use crate::types::{Direction, PlayerInput};
+
+        let server = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let mut rooms = default_rooms();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        handle_client_message(&server, &mut rooms, client_addr, ClientMessage::Connect).await;
+        let welcome = recv_welcome(&client).await;
+
+        // The server ends up where the client predicts the same inputs sent one by one
+        let inputs: Vec<PlayerInput> = [Direction::Right, Direction::Right, Direction::Down].iter().enumerate()
+            .map(|(sequence, dir)| PlayerInput { dir: *dir, sequence: sequence as u32, timestamp: 0 })
+            .collect();
+        let mut predicted = AuthoritativePos::new(welcome.position);
+        let mut prediction = PredictionState::new(predicted);
+        for input in &inputs {
+            prediction.apply_prediction(*input, &mut predicted);
+        }
+        handle_client_message(&server, &mut rooms, client_addr, ClientMessage::InputBatch(inputs)).await;
+
+        assert_ne!(predicted.position(), welcome.position);
+        assert_eq!(lobby(&mut rooms).get_players_mut()[&client_addr].position, predicted.position());
+        assert_eq!(lobby(&mut rooms).build_snapshot().last_processed.get(&welcome.id), Some(&2));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_with_unknown_token_connects_fresh() {
+        let server = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let mut rooms = default_rooms();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        let reconnect = ClientMessage::Reconnect { session_token: Uuid::new_v4() };
+        handle_client_message(&server, &mut rooms, client_addr, reconnect).await;
+
+        // A new session is created and the client starts counting from zero
+        let welcome = recv_welcome(&client).await;
+        assert_eq!(welcome.last_processed, None);
+        assert_eq!(lobby(&mut rooms).active_player_addrs(), vec![client_addr]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_profile() {
+        let server = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let mut rooms = default_rooms();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        let connect = ClientMessage::ConnectWithProfile {
+            name: "Kari".to_string(),
+            shape: PlayerShape::Circle.to_wire(),
+            instance: 7,
+            room: String::new(),
+            password: None,
+        };
+        handle_client_message(&server, &mut rooms, client_addr, connect).await;
+        let welcome = recv_welcome(&client).await;
+
+        assert_eq!(lobby(&mut rooms).player_name(&client_addr), Some("Kari".to_string()));
+        assert_eq!(lobby(&mut rooms).instances().get(&welcome.id), Some(&7));
+        assert_eq!(lobby(&mut rooms).build_snapshot().shapes.get(&welcome.id), Some(&PlayerShape::Circle));
+
+        // A shape value this server doesn't know is drawn as a square
+        let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let other_addr = other.local_addr().unwrap();
+        let connect = ClientMessage::ConnectWithProfile { name: String::new(), shape: 42, instance: 8, room: String::new(), password: None };
+        handle_client_message(&server, &mut rooms, other_addr, connect).await;
+        let welcome = recv_welcome(&other).await;
+        assert_eq!(lobby(&mut rooms).build_snapshot().shapes.get(&welcome.id), Some(&PlayerShape::Square));
+    }
+
+    #[tokio::test]
+    async fn test_server_pong_updates_ping() {
+        let server = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let mut rooms = default_rooms();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        handle_client_message(&server, &mut rooms, client_addr, ClientMessage::Connect).await;
+        let welcome = recv_welcome(&client).await;
+
+        // Echo the nonce the server sent
+        let (_, nonce) = lobby(&mut rooms).start_server_pings(Instant::now())[0];
+        handle_client_message(&server, &mut rooms, client_addr, ClientMessage::ServerPong(nonce)).await;
+
+        assert!(lobby(&mut rooms).build_snapshot().ping_ms.contains_key(&welcome.id));
+    }
+
+    // Helper function to wait for the next message on a socket that isn't a snapshot
+    async fn recv_message(client: &UdpSocket) -> ClientMessage {
+        let mut buf = [0u8; 1024];
+        loop {
+            tokio::select! {
+                res = client.recv(&mut buf) => {
+                    let size = res.unwrap();
+                    if decode_snapshot(&buf[..size]).is_none() {
+                        return decode(&buf[..size]).unwrap();
+                    }
+                }
+                _ = sleep(Duration::from_millis(100)) => {
+                    panic!("Timeout waiting for a message");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_password_protected_rooms() {
+        let server = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let config = RoomConfig {
+            rooms: vec![RoomSettings { name: "vault".to_string(), password: Some("hunter2".to_string()), tick_rate: None }],
+            fixed: false,
+        };
+        let mut rooms = RoomRegistry::new(config, Game::new);
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        let connect = |password: Option<&str>| ClientMessage::ConnectWithProfile {
+            name: "Kari".to_string(),
+            shape: 0,
+            instance: 7,
+            room: "vault".to_string(),
+            password: password.map(str::to_string),
+        };
+
+        // A wrong password is refused without joining
+        handle_client_message(&server, &mut rooms, client_addr, connect(Some("hunter3"))).await;
+        assert_eq!(recv_message(&client).await, ClientMessage::ConnectRejected { reason: RejectReason::WrongPassword });
+        assert_eq!(rooms.room_of(&client_addr), None);
+
+        handle_client_message(&server, &mut rooms, client_addr, connect(Some("hunter2"))).await;
+        let welcome = recv_welcome(&client).await;
+        assert!(rooms.game_mut("vault").unwrap().build_snapshot().players.iter().any(|(id, _, _)| *id == welcome.id));
+        assert!(lobby(&mut rooms).active_player_addrs().is_empty());
+
+        // The room list shows the player count and the lock
+        let browser = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        handle_client_message(&server, &mut rooms, browser.local_addr().unwrap(), ClientMessage::ListRooms).await;
+        assert_eq!(recv_message(&browser).await, ClientMessage::RoomList(vec![
+            RoomInfo { name: DEFAULT_ROOM.to_string(), players: 0, password_required: false },
+            RoomInfo { name: "vault".to_string(), players: 1, password_required: true },
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_long_names_and_passwords_are_refused_not_dropped() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let (config, tuning) = (ServerConfig::default(), ServerTuning::default());
+        let rooms = create_rooms(&config, RoomConfig::default(), HashMap::new(), tuning);
+        tokio::spawn(run_server(Transport::new(socket, None), rooms, config, tuning, std::future::pending()));
+        let connect = |name: &str, password: Option<String>| ClientMessage::ConnectWithProfile {
+            name: name.to_string(),
+            shape: 0,
+            instance: 7,
+            room: "vault".to_string(),
+            password,
+        };
+
+        // A JSON connect with a long password is much bigger than the old receive buffer, it is still read whole and answered
+        let json = CodecKind::Json.codec();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server_addr).await.unwrap();
+        let datagram = json.encode_message(&connect("Kari", Some("x".repeat(2_000))));
+        assert!(datagram.len() > 2_000);
+        client.send(&datagram).await.unwrap();
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        let size = time::timeout(Duration::from_millis(500), client.recv(&mut buf)).await.unwrap().unwrap();
+        let Incoming::Message(reply) = json.decode_incoming(&buf[..size]).unwrap() else {
+            panic!("expected the rejection");
+        };
+        assert_eq!(reply, ClientMessage::ConnectRejected { reason: RejectReason::PasswordTooLong });
+
+        // Names are checked the same way, the limits themselves are let in
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server_addr).await.unwrap();
+        client.send(&encode(&connect(&"K".repeat(MAX_NAME_CHARS + 1), None))).await.unwrap();
+        assert_eq!(recv_message(&client).await, ClientMessage::ConnectRejected { reason: RejectReason::NameTooLong });
+        client.send(&encode(&connect(&"K".repeat(MAX_NAME_CHARS), Some("x".repeat(MAX_PASSWORD_CHARS))))).await.unwrap();
+        recv_welcome(&client).await;
+    }
+
+    #[tokio::test]
+    async fn test_mixed_protocol_clients_on_one_server() {
+        let server = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), None);
+        let mut rooms = default_rooms();
+        let binary = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let text = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (binary_addr, text_addr) = (binary.local_addr().unwrap(), text.local_addr().unwrap());
+
+        // Each client connects in its own protocol, the server tells them apart by the first byte
+        for (addr, codec) in [(binary_addr, CodecKind::Bincode), (text_addr, CodecKind::Json)] {
+            let msg = server.decode(addr, &codec.codec().encode_message(&ClientMessage::Connect)).unwrap();
+            handle_client_message(&server, &mut rooms, addr, msg).await;
+            assert_eq!(server.codec_of(&addr), codec);
+        }
+
+        // Both get every reply and the broadcast snapshot in their own protocol
+        broadcast_snapshot_to_selected(&server, &lobby(&mut rooms).active_player_addrs(), &lobby(&mut rooms).build_snapshot()).await;
+        let mut buf = [0u8; 2048];
+        for (client, codec) in [(&binary, CodecKind::Bincode), (&text, CodecKind::Json)] {
+            let mut snapshots = 0;
+            let mut welcomed = false;
+            while snapshots < 2 {
+                tokio::select! {
+                    res = client.recv(&mut buf) => {
+                        let size = res.unwrap();
+                        assert_eq!(CodecKind::sniff(&buf[..size]), codec);
+                        match codec.codec().decode_incoming(&buf[..size]).unwrap() {
+                            Incoming::Snapshot(_) => snapshots += 1,
+                            Incoming::Message(message) => welcomed |= matches!(message, ClientMessage::Welcome(_)),
+                        }
+                    }
+                    _ = sleep(Duration::from_millis(100)) => {
+                        panic!("Timeout waiting for the {} client", codec.name());
+                    }
+                }
+            }
+            assert!(welcomed);
+        }
+        assert_eq!(lobby(&mut rooms).active_player_addrs().len(), 2);
+
+        // Clients that left are forgotten and fall back to the default
+        server.retain(|addr| *addr == binary_addr);
+        assert_eq!(server.codec_of(&text_addr), CodecKind::Bincode);
+    }
+
+    #[tokio::test]
+    async fn test_server_speaking_one_protocol() {
+        // A JSON-only server ignores bincode datagrams and answers unknown clients in JSON
+        let server = Transport::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), Some(CodecKind::Json));
+        let addr = SocketAddr::from(([127, 0, 0, 1], 4000));
+        assert!(server.decode(addr, &CodecKind::Bincode.codec().encode_message(&ClientMessage::ListRooms)).is_none());
+        assert!(server.decode(addr, br#"{"Message":"ListRooms"}"#).is_some());
+        assert_eq!(server.codec_of(&SocketAddr::from(([127, 0, 0, 1], 4001))), CodecKind::Json);
+    }
+
+    // Helper function to wait briefly for a stats reply, None if nothing arrives
+    async fn recv_stats(client: &UdpSocket) -> Option<ServerStats> {
+        let mut buf = [0u8; 2048];
+        let size = time::timeout(Duration::from_millis(300), client.recv(&mut buf)).await.ok()?.unwrap();
+        assert!(size <= STATS_REPLY_MAX_BYTES, "{} bytes", size);
+        Some(serde_json::from_slice(&buf[..size]).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_stats_query_on_the_game_port() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let (config, tuning) = (ServerConfig::default(), ServerTuning::default());
+        let rooms = create_rooms(&config, RoomConfig::default(), HashMap::new(), tuning);
+        tokio::spawn(run_server(Transport::new(socket, None), rooms, config, tuning, std::future::pending()));
+
+        let player = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        player.connect(server_addr).await.unwrap();
+        player.send(&encode(&ClientMessage::Connect)).await.unwrap();
+        recv_welcome(&player).await;
+        sleep(BROADCAST_INTERVAL * 4).await;
+
+        let dashboard = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        dashboard.connect(server_addr).await.unwrap();
+        dashboard.send(b"STAT\n").await.unwrap();
+        let stats = recv_stats(&dashboard).await.expect("no stats reply");
+        assert_eq!((stats.players, stats.room_count, stats.tick_rate), (1, 1, 60));
+        assert_eq!(stats.rooms.len(), 1);
+        let room = &stats.rooms[0];
+        assert_eq!((room.name.as_str(), room.mode.as_str(), room.players, room.match_number, room.tick_rate), (DEFAULT_ROOM, "pellets", 1, 0, 60));
+        assert!(room.tick.samples > 0 && room.tick.p50_ms <= room.tick.max_ms, "{:?}", room.tick);
+        assert!(stats.tick.samples > 0 && stats.tick.p50_ms <= stats.tick.max_ms, "{:?}", stats.tick);
+        assert_eq!(stats.memory.known_clients, 1);
+        assert_eq!(stats.queries.answered, 1);
+        assert_eq!((stats.receive.handled, stats.receive.shed, stats.receive.rate_limited, stats.memory.receive_sources), (1, 0, 0, 1));
+
+        // A second query right away is dropped, the player is still served
+        dashboard.send(b"STAT").await.unwrap();
+        assert_eq!(recv_stats(&dashboard).await, None);
+        player.send(&encode(&ClientMessage::ListRooms)).await.unwrap();
+        while !matches!(recv_message(&player).await, ClientMessage::RoomList(_)) {}
+    }
+
+    // Helper function to collect the tick interval of every snapshot a socket receives for a while
+    async fn recv_snapshot_intervals(client: &UdpSocket, duration: Duration) -> Vec<u32> {
+        let mut buf = [0u8; 2048];
+        let mut intervals = Vec::new();
+        let deadline = tokio::time::Instant::now() + duration;
+        while let Ok(Ok(size)) = tokio::time::timeout_at(deadline, client.recv(&mut buf)).await {
+            if let Some(Incoming::Snapshot(snapshot)) = CodecKind::Bincode.codec().decode_incoming(&buf[..size]) {
+                intervals.push(snapshot.tick_interval_ms);
+            }
+        }
+        intervals
+    }
+
+    #[tokio::test]
+    async fn test_rooms_broadcast_at_their_own_rate() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let room_config = RoomConfig {
+            rooms: [("casual", 20), ("testing", 60)].iter()
+                .map(|(name, tick_rate)| RoomSettings { name: name.to_string(), password: None, tick_rate: Some(*tick_rate) })
+                .collect(),
+            fixed: false,
+        };
+        let (config, tuning) = (ServerConfig::default(), ServerTuning::default());
+        let rooms = create_rooms(&config, room_config, HashMap::new(), tuning);
+        tokio::spawn(run_server(Transport::new(socket, None), rooms, config, tuning, std::future::pending()));
+
+        let mut players = Vec::new();
+        for room in ["casual", "testing"] {
+            let player = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            player.connect(server_addr).await.unwrap();
+            let connect = ClientMessage::ConnectWithProfile { name: room.to_string(), shape: 0, instance: 1, room: room.to_string(), password: None };
+            player.send(&encode(&connect)).await.unwrap();
+            recv_welcome(&player).await;
+            players.push(player);
+        }
+
+        // Over the same half second each room sends at its own rate, stamped with its own tick interval
+        let (casual, testing) = tokio::join!(
+            recv_snapshot_intervals(&players[0], Duration::from_millis(500)),
+            recv_snapshot_intervals(&players[1], Duration::from_millis(500)),
+        );
+        assert!(!casual.is_empty() && casual.iter().all(|interval| *interval == 50), "{:?}", casual);
+        assert!(testing.iter().all(|interval| *interval == 16), "{:?}", testing);
+        assert!(testing.len() > casual.len() * 2, "{} snapshots at 60 Hz against {} at 20 Hz", testing.len(), casual.len());
+    }
+
+    #[tokio::test]
+    async fn test_stats_query_on_its_own_port_needs_the_token() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let stats_port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = ServerConfig { stats_port: Some(stats_port), stats_token: Some("s3cret".to_string()), ..ServerConfig::default() };
+        let rooms = create_rooms(&config, RoomConfig::default(), HashMap::new(), ServerTuning::default());
+        tokio::spawn(run_server(Transport::new(socket, None), rooms, config, ServerTuning::default(), std::future::pending()));
+        sleep(BROADCAST_INTERVAL * 2).await;
+
+        // The game port leaves stats queries unanswered once they have a port of their own
+        let on_game_port = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        on_game_port.connect(server_addr).await.unwrap();
+        on_game_port.send(b"STAT s3cret").await.unwrap();
+        assert_eq!(recv_stats(&on_game_port).await, None);
+
+        let guesser = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        guesser.connect(("127.0.0.1", stats_port)).await.unwrap();
+        guesser.send(b"STAT guess").await.unwrap();
+        assert_eq!(recv_stats(&guesser).await, None);
+
+        // Loopback is one source, so the next query waits out the rate limit of the guess
+        sleep(STATS_QUERY_INTERVAL).await;
+        guesser.send(b"STAT s3cret").await.unwrap();
+        let stats = recv_stats(&guesser).await.expect("no stats reply");
+        assert_eq!((stats.players, stats.queries.wrong_token, stats.queries.answered), (0, 1, 1));
+    }
+
+    #[test]
+    fn test_room_tick_is_profiled_by_stage() {
+        let mut rooms = default_rooms();
+        lobby(&mut rooms).connect_player(SocketAddr::from(([127, 0, 0, 1], 4000)));
+        let mut output = TickOutput::default();
+        let ((), profile) = profiler::capture(|| run_room_tick(DEFAULT_ROOM, lobby(&mut rooms), None, true, Instant::now(), &mut output));
+        let names: Vec<&str> = profile.scopes.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["phase", "simulation", "snapshot", "pings"]);
+        assert!(profile.scopes.iter().map(|(_, ms)| ms).sum::<f64>() <= profile.frame_ms);
+        assert_eq!(output.snapshots.len(), 1);
+
+        // Rooms without a task have no ticks to show
+        assert_eq!(console_command(&rooms, "profile"), format!("Room {}: last 0 ticks\n", DEFAULT_ROOM));
+    }
+
+    #[test]
+    fn test_console_commands() {
+        let mut rooms = default_rooms();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 4000));
+        let id = lobby(&mut rooms).connect_player(addr);
+        lobby(&mut rooms).set_position_validated(addr, Position { x: 200, y: 200 }, MoveOrigin::Teleport).unwrap();
+
+        let history = console_command(&rooms, &format!("history {}", id));
+        assert!(history.starts_with(&format!("Position history of player {}", id)), "{}", history);
+        assert!(history.contains("(200, 200)") && history.trim_end().ends_with("teleport"), "{}", history);
+
+        // Rewinding just now is answered from the teleport on, further back than the maximum is refused
+        lobby(&mut rooms).set_max_rewind(Duration::from_millis(10));
+        while lobby(&mut rooms).tick_time_ms(Instant::now()) < 100 {
+            lobby(&mut rooms).advance_tick();
+        }
+        let rewound = console_command(&rooms, &format!("rewind {} 0", id));
+        assert!(rewound.starts_with(&format!("Player {} was at (200, 200) 0 ms ago", id)), "{}", rewound);
+        let refused = console_command(&rooms, &format!("rewind {} 50", id));
+        assert!(refused.contains("is unknown, lag compensation uses (200, 200)") && refused.contains("beyond the maximum rewind"), "{}", refused);
+        let early = console_command(&rooms, &format!("rewind {} 60000000", id));
+        assert!(early.contains("before the room started"), "{}", early);
+        assert_eq!(console_command(&rooms, &format!("rewind {} soon", id)), format!("Use rewind <player id> <milliseconds ago>, not rewind {} soon\n", id));
+
+        let dump = console_command(&rooms, "  dump ");
+        assert!(dump.starts_with("Debug dump of 1 rooms\n"), "{}", dump);
+        assert!(dump.contains(&format!("Room {}: ", DEFAULT_ROOM)) && dump.contains("spawn") && dump.contains("teleport"), "{}", dump);
+
+        assert_eq!(console_command(&rooms, ""), "");
+        assert_eq!(console_command(&rooms, "history nobody"), "nobody is not a player id\n");
+        assert_eq!(console_command(&rooms, &format!("history {}", Uuid::nil())), format!("No player {} in any room\n", Uuid::nil()));
+        assert_eq!(console_command(&rooms, "kick all"), "Unknown command kick all, use history <id>, rewind <id> <ms>, dump, profile or help\n");
+    }
+}
+
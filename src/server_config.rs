@@ -0,0 +1,333 @@
+use crate::codec::CodecKind;
+use crate::constants::{DEFAULT_BIND_ADDR, DEFAULT_TICK_RATE, EMPTY_ROOM_GRACE, MAX_RESUMABLE_SESSIONS, MAX_TEAMS, MAX_TICK_RATE, MIN_TICK_RATE, RECONNECT_GRACE, LAG_COMP_MAX_REWIND};
+use crate::game_mode::ModeKind;
+use crate::server::ProcessingDelay;
+
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Configuration of the server. Defaults are overridden by a TOML file, whose keys are the
+/// command line flags with underscores, and the flags override the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,   // Address and port the socket is bound to
+    pub tick_rate: u32, // Broadcast ticks per second
+    #[serde(rename = "match_minutes", with = "minutes")]
+    pub match_duration: Option<Duration>, // Matches reset after this long, 0 minutes in the file never resets
+    #[serde(rename = "teams")]
+    pub team_count: u8, // 0 when team mode is off
+    pub mode: ModeKind, // Rules every room is created with
+    #[serde(rename = "proto")]
+    pub codec: Option<CodecKind>, // Only protocol spoken, None speaks every protocol
+    #[serde(rename = "leaderboard")]
+    pub leaderboard_path: Option<PathBuf>,
+    pub metrics_csv: Option<PathBuf>, // Per-player metrics are appended here with every stats line
+    #[serde(rename = "rooms")]
+    pub rooms_config: Option<PathBuf>, // JSON file listing rooms and their passwords
+    pub debug_teleports: bool, // Clients may place their own player anywhere, for demos and debugging
+    pub coalesce_inputs: bool, // Inputs are applied at the rate of real time, smoothing bursts at the cost of latency
+    pub console: bool, // Commands such as history <id> are read from standard input
+    #[serde(rename = "reconnect_grace_ms", with = "millis")]
+    pub reconnect_grace: Duration, // How long a timed out player can resume its session
+    pub max_resumable_sessions: usize, // Most timed out sessions a room keeps, the one that timed out longest ago is forgotten first
+    #[serde(rename = "max_rewind_ms", with = "millis")]
+    pub max_rewind: Duration, // Furthest back lag compensation rewinds a player, older times use its current position
+    pub spawn_seed: Option<u64>, // Seeds the spawns of the players, room n gets this plus n, None seeds every room randomly
+    pub stats_port: Option<u16>, // Stats queries are answered on this port of the bind address, None answers them on the game port
+    pub stats_token: Option<String>, // Token every stats query has to send, None answers anyone
+    pub processing_delay: ProcessingDelay, // Last, a table has to follow the plain values in TOML
+}
+
+/// Default implementation for the ServerConfig
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: DEFAULT_BIND_ADDR.to_string(),
+            tick_rate: DEFAULT_TICK_RATE,
+            match_duration: None,
+            team_count: 0,
+            mode: ModeKind::default(),
+            codec: None,
+            leaderboard_path: None,
+            metrics_csv: None,
+            rooms_config: None,
+            debug_teleports: false,
+            coalesce_inputs: false,
+            console: false,
+            reconnect_grace: RECONNECT_GRACE,
+            max_resumable_sessions: MAX_RESUMABLE_SESSIONS,
+            max_rewind: LAG_COMP_MAX_REWIND,
+            spawn_seed: None,
+            stats_port: None,
+            stats_token: None,
+            processing_delay: ProcessingDelay::default(),
+        }
+    }
+}
+
+/// Implementation of the ServerConfig
+impl ServerConfig {
+    /// Merges the configuration from the defaults, the file given with --config and the other flags, in
+    /// that order, and validates the result. Errors say which file, flag or value to fix.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut config = match flag_value(args, "--config")? {
+            Some(path) => Self::load(Path::new(path))?,
+            None => Self::default(),
+        };
+        config.apply_args(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads the configuration from a TOML file, missing keys keep their defaults
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::from_toml(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Parses the configuration from TOML text. Unknown keys are an error, a misspelled key would
+    /// otherwise silently keep its default.
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string().trim_end().to_string())
+    }
+
+    /// The configuration as TOML, in the format the file is read in
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("the server config always serializes")
+    }
+
+    /// Overrides the configuration with the flags of the command line. Every flag takes a value,
+    /// an unknown flag or a value that doesn't parse is an error.
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), String> {
+        let mut rest = args.iter();
+        while let Some(flag) = rest.next() {
+            let value = rest.next().ok_or_else(|| format!("{} needs a value", flag))?;
+            match flag.as_str() {
+                "--config" => {} // Read before the other flags
+                "--bind" => self.bind = value.clone(),
+                "--tick-rate" => self.tick_rate = parse_value(flag, value)?,
+                "--match-minutes" => self.match_duration = minutes_to_duration(parse_value(flag, value)?).map_err(|e| format!("{} {}", flag, e))?,
+                "--teams" => self.team_count = parse_value(flag, value)?,
+                "--mode" => self.mode = ModeKind::parse(value).ok_or_else(|| format!("{} {} is unknown, use free, pellets or tag", flag, value))?,
+                "--proto" => self.codec = Some(CodecKind::parse(value).ok_or_else(|| format!("{} {} is unknown, use bincode or json", flag, value))?),
+                "--leaderboard" => self.leaderboard_path = Some(PathBuf::from(value)),
+                "--metrics-csv" => self.metrics_csv = Some(PathBuf::from(value)),
+                "--rooms" => self.rooms_config = Some(PathBuf::from(value)),
+                "--debug-teleports" => self.debug_teleports = value.parse().map_err(|_| format!("{} {} is not true or false", flag, value))?,
+                "--coalesce-inputs" => self.coalesce_inputs = value.parse().map_err(|_| format!("{} {} is not true or false", flag, value))?,
+                "--console" => self.console = value.parse().map_err(|_| format!("{} {} is not true or false", flag, value))?,
+                "--reconnect-grace-ms" => self.reconnect_grace = Duration::from_millis(parse_value(flag, value)?),
+                "--max-resumable-sessions" => self.max_resumable_sessions = parse_value(flag, value)?,
+                "--max-rewind-ms" => self.max_rewind = Duration::from_millis(parse_value(flag, value)?),
+                "--spawn-seed" => self.spawn_seed = Some(parse_value(flag, value)?),
+                "--stats-port" => self.stats_port = Some(parse_value(flag, value)?),
+                "--stats-token" => self.stats_token = Some(value.clone()),
+                "--sim-processing-delay" => self.processing_delay.tick_delay = Duration::from_millis(parse_value(flag, value)?),
+                "--sim-processing-jitter" => self.processing_delay.tick_jitter = Duration::from_millis(parse_value(flag, value)?),
+                "--sim-input-delay" => self.processing_delay.input_delay = Duration::from_millis(parse_value(flag, value)?),
+                _ => return Err(format!("unknown flag {}, see server help", flag)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the values the types allow but the server can't run with
+    pub fn validate(&self) -> Result<(), String> {
+        let Ok(bind) = self.bind.parse::<SocketAddr>() else {
+            return Err(format!("bind {} is not an address and port such as {}", self.bind, DEFAULT_BIND_ADDR));
+        };
+        if self.stats_port.is_some_and(|port| port != 0 && port == bind.port()) {
+            return Err(format!("stats_port {} is the game port, leave it out to answer stats queries on the game port", bind.port()));
+        }
+        if !(MIN_TICK_RATE..=MAX_TICK_RATE).contains(&self.tick_rate) {
+            return Err(format!("tick_rate {} is out of range, use {} to {} ticks per second", self.tick_rate, MIN_TICK_RATE, MAX_TICK_RATE));
+        }
+        if self.team_count == 1 || self.team_count > MAX_TEAMS {
+            return Err(format!("teams {} is out of range, use 2 to {} teams or 0 to turn team mode off", self.team_count, MAX_TEAMS));
+        }
+        if self.reconnect_grace > EMPTY_ROOM_GRACE {
+            // An empty room would be removed with sessions that could still be resumed in it
+            return Err(format!("reconnect_grace_ms {} is too long, use at most {} ms", self.reconnect_grace.as_millis(), EMPTY_ROOM_GRACE.as_millis()));
+        }
+        Ok(())
+    }
+
+    /// Time between two broadcast ticks, in whole milliseconds like the tick interval of the snapshots
+    pub fn tick_interval(&self) -> Duration {
+        tick_interval_of(self.tick_rate)
+    }
+}
+
+/// Time between two broadcast ticks at a tick rate, in whole milliseconds like the tick interval of the snapshots
+pub fn tick_interval_of(tick_rate: u32) -> Duration {
+    Duration::from_millis(1000 / tick_rate.max(1) as u64)
+}
+
+/// Parses the value of a flag, naming the flag if it doesn't parse
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} {} is not a valid number", flag, value))
+}
+
+/// Finds the value of a flag, an error if the flag is the last argument
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Result<Option<&'a str>, String> {
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) => args.get(index + 1).map(|value| Some(value.as_str())).ok_or_else(|| format!("{} needs a value", flag)),
+        None => Ok(None),
+    }
+}
+
+/// Converts a match length in minutes, where 0 means matches never reset
+fn minutes_to_duration(minutes: f64) -> Result<Option<Duration>, String> {
+    if !minutes.is_finite() || minutes < 0.0 {
+        return Err(format!("{} is not a match length, use minutes or 0 to never reset", minutes));
+    }
+    Ok((minutes > 0.0).then(|| Duration::from_secs_f64(minutes * 60.0)))
+}
+
+/// Serializes an optional match length as minutes, 0 when matches never reset
+mod minutes {
+    use super::minutes_to_duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    /// Writes the match length in minutes
+    pub fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.map_or(0.0, |duration| duration.as_secs_f64() / 60.0))
+    }
+
+    /// Reads the match length in minutes
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        minutes_to_duration(f64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serializes a duration as whole milliseconds
+pub(crate) mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    /// Writes the duration in milliseconds
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    /// Reads the duration in milliseconds
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// Tests for the ServerConfig
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::BROADCAST_INTERVAL;
+
+    // Helper function to turn a list of strings into command line arguments
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_defaults_tick_at_the_broadcast_interval() {
+        let config = ServerConfig::default();
+        assert_eq!(config.tick_interval(), BROADCAST_INTERVAL);
+        assert_eq!(config.bind, DEFAULT_BIND_ADDR);
+        assert!(!config.processing_delay.is_enabled());
+        assert_eq!(ServerConfig::from_args(&[]), Ok(config));
+    }
+
+    #[test]
+    fn test_flags_override_the_file_and_the_file_overrides_the_defaults() {
+        let mut config = ServerConfig::from_toml(r#"
+            tick_rate = 30
+            teams = 2
+            match_minutes = 5
+            mode = "tag"
+
+            [processing_delay]
+            tick_delay_ms = 20
+        "#).unwrap();
+        assert_eq!(config.tick_rate, 30);
+        assert_eq!(config.match_duration, Some(Duration::from_secs(300)));
+        assert_eq!(config.bind, DEFAULT_BIND_ADDR);
+
+        config.apply_args(&args(&["--teams", "3", "--match-minutes", "0", "--sim-input-delay", "50", "--proto", "json"])).unwrap();
+        assert_eq!(config.team_count, 3);
+        assert_eq!(config.match_duration, None);
+        assert_eq!(config.mode, ModeKind::Tag);
+        assert_eq!(config.codec, Some(CodecKind::Json));
+        assert_eq!(config.processing_delay.tick_delay, Duration::from_millis(20));
+        assert_eq!(config.processing_delay.input_delay, Duration::from_millis(50));
+        assert_eq!(config.tick_rate, 30);
+    }
+
+    #[test]
+    fn test_config_file_is_merged_before_the_flags() {
+        let path = std::env::temp_dir().join(format!("server_config_{}.toml", std::process::id()));
+        std::fs::write(&path, "bind = \"127.0.0.1:9100\"\ntick_rate = 30\n").unwrap();
+        let config = ServerConfig::from_args(&args(&["--tick-rate", "20", "--config", path.to_str().unwrap()]));
+        std::fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.bind, "127.0.0.1:9100");
+        assert_eq!(config.tick_rate, 20);
+        assert!(ServerConfig::from_args(&args(&["--config", "missing.toml"])).unwrap_err().starts_with("failed to read missing.toml"));
+    }
+
+    #[test]
+    fn test_dumped_config_reads_back_the_same() {
+        let mut config = ServerConfig::default();
+        config.apply_args(&args(&["--match-minutes", "0.5", "--mode", "free", "--rooms", "rooms.json", "--sim-processing-jitter", "5", "--debug-teleports", "true", "--coalesce-inputs", "true", "--console", "true", "--spawn-seed", "99"])).unwrap();
+        let text = config.to_toml();
+        assert!(text.contains("match_minutes = 0.5"), "{}", text);
+        assert!(text.contains("[processing_delay]"), "{}", text);
+        assert!(text.contains("debug_teleports = true"), "{}", text);
+        assert!(text.contains("coalesce_inputs = true"), "{}", text);
+        assert!(text.contains("console = true"), "{}", text);
+        assert!(text.contains("spawn_seed = 99"), "{}", text);
+        assert_eq!(ServerConfig::from_toml(&text), Ok(config));
+    }
+
+    #[test]
+    fn test_errors_name_what_to_fix() {
+        let unknown = ServerConfig::from_toml("tick_rat = 30").unwrap_err();
+        assert!(unknown.contains("unknown field `tick_rat`"), "{}", unknown);
+        let nested = ServerConfig::from_toml("[processing_delay]\ntick_ms = 5").unwrap_err();
+        assert!(nested.contains("unknown field `tick_ms`"), "{}", nested);
+        let negative = ServerConfig::from_toml("match_minutes = -1").unwrap_err();
+        assert!(negative.contains("use minutes or 0 to never reset"), "{}", negative);
+
+        let flags = |list: &[&str]| ServerConfig::default().apply_args(&args(list)).unwrap_err();
+        assert_eq!(flags(&["--tick-rat", "30"]), "unknown flag --tick-rat, see server help");
+        assert_eq!(flags(&["--teams"]), "--teams needs a value");
+        assert_eq!(flags(&["--teams", "red"]), "--teams red is not a valid number");
+        assert_eq!(flags(&["--mode", "golf"]), "--mode golf is unknown, use free, pellets or tag");
+        assert_eq!(flags(&["--proto", "xml"]), "--proto xml is unknown, use bincode or json");
+        assert_eq!(flags(&["--debug-teleports", "yes"]), "--debug-teleports yes is not true or false");
+        assert_eq!(flags(&["--coalesce-inputs", "on"]), "--coalesce-inputs on is not true or false");
+    }
+
+    #[test]
+    fn test_validation_rejects_out_of_range_values() {
+        let validate = |list: &[&str]| ServerConfig::from_args(&args(list));
+        assert_eq!(validate(&["--tick-rate", "500"]).unwrap_err(), "tick_rate 500 is out of range, use 10 to 120 ticks per second");
+        assert!(validate(&["--tick-rate", "0"]).is_err());
+        assert!(validate(&["--tick-rate", "120"]).is_ok());
+        assert_eq!(validate(&["--teams", "1"]).unwrap_err(), format!("teams 1 is out of range, use 2 to {} teams or 0 to turn team mode off", MAX_TEAMS));
+        assert!(validate(&["--teams", &(MAX_TEAMS + 1).to_string()]).is_err());
+        assert!(validate(&["--bind", "9000"]).unwrap_err().starts_with("bind 9000 is not an address"));
+        assert_eq!(validate(&["--tick-rate", "30"]).unwrap().tick_interval(), Duration::from_millis(33));
+        assert_eq!(validate(&["--reconnect-grace-ms", "90000"]).unwrap_err(), "reconnect_grace_ms 90000 is too long, use at most 60000 ms");
+        let config = validate(&["--reconnect-grace-ms", "10000", "--max-resumable-sessions", "8", "--max-rewind-ms", "250"]).unwrap();
+        assert_eq!((config.reconnect_grace, config.max_resumable_sessions, config.max_rewind), (Duration::from_secs(10), 8, Duration::from_millis(250)));
+        assert_eq!(validate(&["--stats-port", "9000"]).unwrap_err(), "stats_port 9000 is the game port, leave it out to answer stats queries on the game port");
+        let config = validate(&["--stats-port", "9001", "--stats-token", "s3cret"]).unwrap();
+        assert_eq!((config.stats_port, config.stats_token.as_deref()), (Some(9001), Some("s3cret")));
+    }
+}
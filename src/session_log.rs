@@ -0,0 +1,423 @@
+use crate::client_events::{ClientEvent, ConnectionState};
+use crate::constants::{SESSION_LOG_FLUSH_INTERVAL_MS, SESSION_LOG_FLUSH_LINES};
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// How much an entry of the session log matters, the log keeps the entries at or above its filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Severity {
+    Debug,   // Every snapshot and score change, for a close look at a short session
+    #[default]
+    Info,    // What happened to the connection, the players and the network conditions
+    Warning, // Corrections, gaps and anything else that shows on screen as the netcode going wrong
+    Error,   // The session could not go on as it was
+}
+
+/// Implementation of the Severity
+impl Severity {
+    /// Every severity, the least important first
+    pub const ALL: [Severity; 4] = [Severity::Debug, Severity::Info, Severity::Warning, Severity::Error];
+
+    /// Name in the log and in the settings
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Debug => "debug",
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    /// Parses a severity by its name, ignoring case and surrounding whitespace
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim().to_lowercase();
+        Self::ALL.into_iter().find(|severity| severity.name() == text)
+    }
+}
+
+/// Something the app did to the session that the client events don't cover
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    ConditionsChanged { delay_ms: i32, jitter_ms: i32, packet_loss: i32, duplicate_percent: i32, preset: Option<String> }, // Simulated network, with the name of the test condition that set it
+    TestsStarted,  // The performance test sweep started
+    TestsAborted,  // The sweep was stopped before its last condition
+    TestsFinished, // Every condition of the sweep was tested
+}
+
+/// One line of the log before it is timed: the type it is counted as, how much it matters and what happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub kind: &'static str,
+    pub severity: Severity,
+    pub text: String,
+}
+
+/// Implementation of the LogEntry
+impl LogEntry {
+    fn new(kind: &'static str, severity: Severity, text: impl Into<String>) -> Self {
+        Self { kind, severity, text: text.into() }
+    }
+}
+
+/// Formats one line of the log: the wall clock time in seconds since the Unix epoch, the seconds since
+/// the session started, the severity and what happened
+pub fn format_line(unix_ms: u64, elapsed: f64, entry: &LogEntry) -> String {
+    format!("[{:.3}] {:.1}s: {} {}\n", unix_ms as f64 / 1000.0, elapsed, entry.severity.name(), entry.text)
+}
+
+/// File the log of a session started at the unix time in milliseconds is written to
+pub fn session_log_path(dir: &Path, started_ms: u64) -> PathBuf {
+    dir.join(format!("session-{}.log", started_ms))
+}
+
+/// A plain text timeline of a session for reviewing a demo afterwards, built from the client events and
+/// the changes the app made to the session. Lines are buffered and appended to the file every few
+/// seconds, and the totals per event type are appended when the session ends. After a failed write
+/// the log gives up, so a missing directory costs one error message instead of one per entry.
+#[derive(Debug)]
+pub struct SessionLog {
+    path: PathBuf,
+    filter: Severity,                         // Lowest severity written, every event is counted regardless
+    started_ms: u64,                          // Unix time in milliseconds the session started at
+    buffer: String,                           // Lines not written to the file yet
+    buffered: usize,                          // Lines in the buffer
+    last_flush_ms: u64,                       // Unix time in milliseconds of the last write
+    counts: BTreeMap<&'static str, u64>,      // Events per type
+    last_id: Option<Uuid>,                    // Id the server gave us last, to tell a new id from a resumed session
+    conditions: Option<(i32, i32, i32, i32)>, // Network conditions logged last
+    finished: bool,                           // The summary was written, nothing is logged after it
+    failed: bool,                             // A write failed, nothing is buffered or written after it
+}
+
+/// Implementation of the SessionLog
+impl SessionLog {
+    /// Starts the log of a session started at the unix time in milliseconds, keeping the entries at or above
+    /// the filter. The file is created in the directory by the first write.
+    pub fn new(dir: &Path, started_ms: u64, filter: Severity) -> Self {
+        let mut log = Self {
+            path: session_log_path(dir, started_ms),
+            filter,
+            started_ms,
+            buffer: String::new(),
+            buffered: 0,
+            last_flush_ms: started_ms,
+            counts: BTreeMap::new(),
+            last_id: None,
+            conditions: None,
+            finished: false,
+            failed: false,
+        };
+        log.buffer.push_str(&format!("# Session started at {:.3}, {} and above\n", started_ms as f64 / 1000.0, filter.name()));
+        log
+    }
+
+    /// File the log is written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Logs a client event at the unix time in milliseconds. Events without anything to review, such as
+    /// room lists, are left out.
+    pub fn record_client_event(&mut self, unix_ms: u64, event: &ClientEvent) {
+        if let Some(entry) = self.describe_client_event(event) {
+            self.record(unix_ms, entry);
+        }
+    }
+
+    /// Logs something the app did at the unix time in milliseconds. Network conditions are only logged
+    /// when they differ from the ones logged last, so they can be passed every frame.
+    pub fn record_session_event(&mut self, unix_ms: u64, event: SessionEvent) {
+        let entry = match event {
+            SessionEvent::ConditionsChanged { delay_ms, jitter_ms, packet_loss, duplicate_percent, preset } => {
+                let conditions = (delay_ms, jitter_ms, packet_loss, duplicate_percent);
+                let last = self.conditions.replace(conditions);
+                if last == Some(conditions) {
+                    return;
+                }
+                let values = format!("{} ms delay, {} ms jitter, {}% loss, {}% duplicates", delay_ms, jitter_ms, packet_loss, duplicate_percent);
+                let text = match (preset, last) {
+                    (Some(name), _) => format!("latency preset changed to {} ({})", name, values),
+                    (None, Some(_)) => format!("network conditions changed to {}", values),
+                    (None, None) => format!("network conditions at {}", values),
+                };
+                LogEntry::new("conditions", Severity::Info, text)
+            }
+            SessionEvent::TestsStarted => LogEntry::new("test sweep", Severity::Info, "test sweep started"),
+            SessionEvent::TestsAborted => LogEntry::new("test sweep", Severity::Info, "test sweep stopped"),
+            SessionEvent::TestsFinished => LogEntry::new("test sweep", Severity::Info, "test sweep finished"),
+        };
+        self.record(unix_ms, entry);
+    }
+
+    /// Counts the entry and buffers its line if it passes the filter, writing the buffer out once it is
+    /// full or old enough
+    pub fn record(&mut self, unix_ms: u64, entry: LogEntry) {
+        if self.finished {
+            return;
+        }
+        *self.counts.entry(entry.kind).or_default() += 1;
+        if entry.severity >= self.filter && !self.failed {
+            self.buffer.push_str(&format_line(unix_ms, self.elapsed(unix_ms), &entry));
+            self.buffered += 1;
+        }
+        self.flush_if_due(unix_ms);
+    }
+
+    /// Writes the buffer out once it is full or old enough. Called every frame, so lines logged before a
+    /// quiet stretch don't wait for the next entry. The first failed write is reported, later ones can't happen.
+    pub fn flush_if_due(&mut self, unix_ms: u64) {
+        let due = self.buffered >= SESSION_LOG_FLUSH_LINES || unix_ms.saturating_sub(self.last_flush_ms) >= SESSION_LOG_FLUSH_INTERVAL_MS;
+        if !due || self.buffered == 0 || self.failed {
+            return;
+        }
+        if let Err(e) = self.flush(unix_ms) {
+            eprintln!("Failed to write the session log {}, not logging the rest of the session: {}", self.path.display(), e);
+        }
+    }
+
+    /// Appends the buffered lines to the file. If they couldn't be written they are dropped and the log
+    /// gives up, every later flush fails without trying.
+    pub fn flush(&mut self, unix_ms: u64) -> io::Result<()> {
+        self.last_flush_ms = unix_ms;
+        if self.failed {
+            return Err(io::Error::other("an earlier write failed"));
+        }
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let written = self.write_buffer();
+        self.failed = written.is_err();
+        self.buffer.clear();
+        self.buffered = 0;
+        written
+    }
+
+    /// The summary section: how long the session ran and the events of each type, filtered ones included
+    pub fn summary(&self, unix_ms: u64) -> String {
+        let mut summary = format!("# Summary after {:.1}s\n", self.elapsed(unix_ms));
+        let width = self.counts.keys().map(|kind| kind.len()).max().unwrap_or(0);
+        for (kind, count) in &self.counts {
+            summary.push_str(&format!("#   {:<width$}  {}\n", kind, count, width = width));
+        }
+        summary
+    }
+
+    /// Ends the session: writes what is still buffered and the summary. Only the first call writes anything.
+    pub fn finish(&mut self, unix_ms: u64) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let summary = self.summary(unix_ms);
+        self.buffer.push_str(&summary);
+        self.flush(unix_ms)
+    }
+
+    /// Appends the buffer to the file, creating its directory first
+    fn write_buffer(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(self.buffer.as_bytes())
+    }
+
+    /// Seconds from the start of the session to the unix time in milliseconds
+    fn elapsed(&self, unix_ms: u64) -> f64 {
+        unix_ms.saturating_sub(self.started_ms) as f64 / 1000.0
+    }
+
+    /// Type, severity and sentence of a client event, None for the events that aren't logged
+    fn describe_client_event(&mut self, event: &ClientEvent) -> Option<LogEntry> {
+        let entry = match event {
+            ClientEvent::Joined { id, .. } => {
+                let text = match self.last_id.replace(*id) {
+                    None => format!("joined with id {}", id),
+                    Some(last) if last == *id => format!("resumed the session as {}", id),
+                    Some(_) => format!("reconnected with new id {}", id),
+                };
+                LogEntry::new("joined", Severity::Info, text)
+            }
+            ClientEvent::ConnectionStateChanged(state) => {
+                let state = match state {
+                    ConnectionState::Disconnected => "disconnected",
+                    ConnectionState::Connecting => "connecting",
+                    ConnectionState::Connected => "connected",
+                };
+                LogEntry::new("connection", Severity::Info, format!("connection {}", state))
+            }
+            ClientEvent::MatchStarted { match_number } => LogEntry::new("match started", Severity::Info, format!("match {} started", match_number)),
+            ClientEvent::Rejected(reason) => LogEntry::new("rejected", Severity::Error, format!("connect refused: {}", reason.describe())),
+            ClientEvent::Incompatible { server_digest } => {
+                LogEntry::new("incompatible", Severity::Error, format!("server movement tunables {:016x} differ from ours", server_digest))
+            }
+            ClientEvent::TunablesAdopted(tunables) => LogEntry::new("tunables", Severity::Info, format!("adopted the movement tunables of the server: {:?}", tunables)),
+            ClientEvent::Removed(Some(reason)) => LogEntry::new("removed", Severity::Error, format!("removed by the server: {}", reason.describe())),
+            ClientEvent::Removed(None) => LogEntry::new("removed", Severity::Error, "the server no longer lists the local player"),
+            ClientEvent::BurstCovered(burst) => {
+                LogEntry::new("snapshot burst", Severity::Warning, format!("{} snapshots in one frame, skipped {}", burst.size, burst.skipped))
+            }
+            ClientEvent::PlayerJoined { id } => LogEntry::new("player joined", Severity::Info, format!("player {} joined", id)),
+            ClientEvent::PlayerLeft { id } => LogEntry::new("player left", Severity::Info, format!("player {} left", id)),
+            ClientEvent::ScoreChanged { score, previous, .. } => LogEntry::new("score", Severity::Debug, format!("score {} -> {}", previous, score)),
+            ClientEvent::SnapCorrection { magnitude } => LogEntry::new("snap correction", Severity::Warning, format!("snap correction {:.0}px", magnitude)),
+            ClientEvent::SnapshotGap { count } => LogEntry::new("snapshot gap", Severity::Warning, format!("{} snapshots missing", count)),
+            ClientEvent::SnapshotDiscarded { snapshot_id, anomalies, players } => LogEntry::new(
+                "snapshot discarded",
+                Severity::Warning,
+                format!("discarded snapshot {}, {} of its {} players off the board", snapshot_id, anomalies, players),
+            ),
+            ClientEvent::SpikeDropped { id, total } => {
+                LogEntry::new("position spike", Severity::Warning, format!("dropped a position spike of player {} ({} so far)", id, total))
+            }
+            ClientEvent::Snapshot(outcome) => {
+                let error = outcome.prediction_error.map_or(String::new(), |error| format!(", prediction error {:.1}px", error));
+                LogEntry::new("snapshot", Severity::Debug, format!("snapshot applied{}", error))
+            }
+            ClientEvent::Warning(change) => LogEntry::new("metric warning", Severity::Warning, change.describe()),
+            ClientEvent::PredictionDisabled => LogEntry::new("prediction disabled", Severity::Warning, "prediction turned off, it kept going wrong"),
+            ClientEvent::Resynced { paused } => LogEntry::new("resync", Severity::Warning, format!("resynced after {:.1}s without updates", paused)),
+            ClientEvent::Message(_) => return None,
+        };
+        Some(entry)
+    }
+}
+
+/// Tests for the SessionLog
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_world::SnapshotOutcome;
+
+    // Helper function to get a directory for a test, removed with everything in it first
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("netcode-session-log-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // Helper function to set the network conditions with only a delay
+    fn delay(delay_ms: i32, preset: Option<&str>) -> SessionEvent {
+        SessionEvent::ConditionsChanged { delay_ms, jitter_ms: 0, packet_loss: 0, duplicate_percent: 0, preset: preset.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_lines_have_wall_clock_and_session_time() {
+        let entry = LogEntry::new("snap correction", Severity::Warning, "snap correction 22px");
+        assert_eq!(format_line(1_760_000_014_100, 14.1, &entry), "[1760000014.100] 14.1s: warning snap correction 22px\n");
+        assert_eq!(session_log_path(Path::new("sessions"), 1_760_000_000_000), PathBuf::from("sessions/session-1760000000000.log"));
+        assert_eq!(Severity::parse(" Warning\n"), Some(Severity::Warning));
+        assert_eq!(Severity::parse("loud"), None);
+
+        // A new id after a reconnect is told apart from the first join and from a resumed session
+        let mut log = SessionLog::new(Path::new("unused"), 0, Severity::Debug);
+        let (a, b) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let text = |log: &mut SessionLog, id| log.describe_client_event(&ClientEvent::Joined { id, session_token: None }).unwrap().text;
+        assert!(text(&mut log, a).starts_with("joined with id"));
+        assert!(text(&mut log, a).starts_with("resumed the session"));
+        assert!(text(&mut log, b).starts_with("reconnected with new id"));
+    }
+
+    #[test]
+    fn test_filter_and_unchanged_conditions_keep_lines_out() {
+        let mut log = SessionLog::new(Path::new("unused"), 10_000, Severity::Info);
+        log.record_client_event(11_000, &ClientEvent::Snapshot(SnapshotOutcome::default()));
+        log.record_session_event(10_000, delay(0, None));
+        log.record_session_event(12_300, delay(200, Some("Poor")));
+        log.record_session_event(12_316, delay(200, None));
+        log.record_client_event(14_100, &ClientEvent::SnapCorrection { magnitude: 22.4 });
+        log.record_session_event(14_200, delay(210, None));
+
+        // The snapshot is below the filter and the conditions passed every frame are only logged when they change
+        let lines: Vec<&str> = log.buffer.lines().collect();
+        assert_eq!(lines, [
+            "# Session started at 10.000, info and above",
+            "[10.000] 0.0s: info network conditions at 0 ms delay, 0 ms jitter, 0% loss, 0% duplicates",
+            "[12.300] 2.3s: info latency preset changed to Poor (200 ms delay, 0 ms jitter, 0% loss, 0% duplicates)",
+            "[14.100] 4.1s: warning snap correction 22px",
+            "[14.200] 4.2s: info network conditions changed to 210 ms delay, 0 ms jitter, 0% loss, 0% duplicates",
+        ]);
+    }
+
+    #[test]
+    fn test_summary_counts_every_event_by_type() {
+        let mut log = SessionLog::new(Path::new("unused"), 0, Severity::Error);
+        for magnitude in [10.0, 20.0, 30.0] {
+            log.record_client_event(100, &ClientEvent::SnapCorrection { magnitude });
+        }
+        log.record_session_event(200, SessionEvent::TestsStarted);
+        log.record_session_event(300, SessionEvent::TestsFinished);
+        log.record_client_event(300, &ClientEvent::Message(crate::types::ClientMessage::Leave));
+
+        // Filtered events still count, events that aren't logged don't
+        assert_eq!(log.summary(35_200), "# Summary after 35.2s\n#   snap correction  3\n#   test sweep       2\n");
+        assert_eq!(SessionLog::new(Path::new("unused"), 0, Severity::Info).summary(0), "# Summary after 0.0s\n");
+    }
+
+    #[test]
+    fn test_lines_are_buffered_until_due_and_flushed_on_finish() {
+        let dir = temp_dir("flush");
+        let mut log = SessionLog::new(&dir, 0, Severity::Info);
+        log.record_session_event(1_000, SessionEvent::TestsStarted);
+        assert!(!log.path().exists());
+
+        // The interval passed, the buffer is written out
+        log.record_session_event(SESSION_LOG_FLUSH_INTERVAL_MS, SessionEvent::TestsAborted);
+        let written = std::fs::read_to_string(log.path()).unwrap();
+        assert_eq!(written.lines().count(), 3);
+
+        // A full buffer is written before the interval
+        for _ in 0..SESSION_LOG_FLUSH_LINES {
+            log.record_client_event(SESSION_LOG_FLUSH_INTERVAL_MS + 1, &ClientEvent::SnapCorrection { magnitude: 5.0 });
+        }
+        assert_eq!(std::fs::read_to_string(log.path()).unwrap().lines().count(), 3 + SESSION_LOG_FLUSH_LINES);
+
+        // Finishing writes what is left with the summary, once
+        log.record_client_event(SESSION_LOG_FLUSH_INTERVAL_MS + 2, &ClientEvent::PredictionDisabled);
+        log.finish(20_000).unwrap();
+        log.finish(30_000).unwrap();
+        log.record_session_event(30_000, SessionEvent::TestsStarted);
+        let written = std::fs::read_to_string(log.path()).unwrap();
+        assert!(written.contains("warning prediction turned off"), "{}", written);
+        assert!(written.ends_with("# Summary after 20.0s\n#   prediction disabled  1\n#   snap correction      64\n#   test sweep           2\n"), "{}", written);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_frames_flush_the_lines_of_a_quiet_session() {
+        let dir = temp_dir("quiet");
+        let mut log = SessionLog::new(&dir, 0, Severity::Info);
+        log.record_session_event(1_000, SessionEvent::TestsStarted);
+        log.flush_if_due(SESSION_LOG_FLUSH_INTERVAL_MS - 1);
+        assert!(!log.path().exists());
+
+        // Nothing else is logged, the frame after the interval writes the line anyway
+        log.flush_if_due(SESSION_LOG_FLUSH_INTERVAL_MS);
+        assert_eq!(std::fs::read_to_string(log.path()).unwrap().lines().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_gives_up_on_the_log() {
+        // The log directory can't be created, a file is in its way
+        let blocker = temp_dir("blocked");
+        std::fs::write(&blocker, "").unwrap();
+        let mut log = SessionLog::new(&blocker.join("sessions"), 0, Severity::Info);
+        for _ in 0..SESSION_LOG_FLUSH_LINES {
+            log.record_client_event(100, &ClientEvent::SnapCorrection { magnitude: 5.0 });
+        }
+        assert!(log.failed);
+
+        // Later entries are still counted but neither buffered nor written
+        for time in 0..SESSION_LOG_FLUSH_LINES as u64 * 2 {
+            log.record_client_event(200 + time * SESSION_LOG_FLUSH_INTERVAL_MS, &ClientEvent::SnapCorrection { magnitude: 5.0 });
+        }
+        assert!(log.buffer.is_empty());
+        assert_eq!(log.counts["snap correction"], SESSION_LOG_FLUSH_LINES as u64 * 3);
+        assert!(log.finish(1_000_000).is_err());
+        std::fs::remove_file(&blocker).unwrap();
+    }
+}
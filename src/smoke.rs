@@ -0,0 +1,169 @@
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(5); // How often wait_for asks again
+
+/// Outcome of one named check of a smoke test scenario
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,     // What was seen, or why the check failed
+    pub elapsed: Duration,  // How long the check took
+}
+
+/// A run of a smoke test scenario. Every step is a named check with a pass or fail and its timing,
+/// later steps can still run after a failure so the report lists every broken check at once.
+#[derive(Debug)]
+pub struct SmokeRun {
+    name: String,
+    started: Instant,
+    results: Vec<CheckResult>,
+}
+
+/// Implementation of the SmokeRun
+impl SmokeRun {
+    /// Starts a run of the named scenario
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), started: Instant::now(), results: Vec::new() }
+    }
+
+    /// Runs a check and records it. The check returns what it saw when it passes and why it failed otherwise.
+    /// Returns whether it passed.
+    pub fn check(&mut self, name: &str, check: impl FnOnce() -> Result<String, String>) -> bool {
+        let started = Instant::now();
+        let outcome = check();
+        self.record(name, outcome, started.elapsed())
+    }
+
+    /// Asks poll again until it returns something or timeout has passed, and records a check that
+    /// passes if it returned something in time
+    pub fn wait_for<T>(&mut self, name: &str, timeout: Duration, mut poll: impl FnMut() -> Option<T>) -> Option<T> {
+        let started = Instant::now();
+        loop {
+            if let Some(value) = poll() {
+                let elapsed = started.elapsed();
+                self.record(name, Ok(format!("after {} ms", elapsed.as_millis())), elapsed);
+                return Some(value);
+            }
+            if started.elapsed() >= timeout {
+                self.record(name, Err(format!("nothing within {} ms", timeout.as_millis())), started.elapsed());
+                return None;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Records the outcome of a step checked some other way
+    pub fn record(&mut self, name: &str, outcome: Result<String, String>, elapsed: Duration) -> bool {
+        let passed = outcome.is_ok();
+        let detail = outcome.unwrap_or_else(|reason| reason);
+        self.results.push(CheckResult { name: name.to_string(), passed, detail, elapsed });
+        passed
+    }
+
+    /// Every check recorded so far, in order
+    pub fn results(&self) -> &[CheckResult] {
+        &self.results
+    }
+
+    /// The checks that failed
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.results.iter().filter(|result| !result.passed)
+    }
+
+    /// Whether every check passed. A run without checks didn't verify anything and doesn't pass.
+    pub fn passed(&self) -> bool {
+        !self.results.is_empty() && self.failures().next().is_none()
+    }
+
+    /// Process exit code for the run, 0 if it passed and 1 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.passed() { 0 } else { 1 }
+    }
+
+    /// One line per check followed by a summary that names the failed checks
+    pub fn report(&self) -> String {
+        let mut report = format!("Smoke test: {}\n", self.name);
+        for result in &self.results {
+            report.push_str(&format!(
+                "  [{}] {} ({} ms): {}\n",
+                if result.passed { "pass" } else { "FAIL" },
+                result.name,
+                result.elapsed.as_millis(),
+                result.detail,
+            ));
+        }
+        let failed: Vec<&str> = self.failures().map(|result| result.name.as_str()).collect();
+        let total = self.started.elapsed().as_secs_f64();
+        if self.passed() {
+            report.push_str(&format!("All {} checks passed in {:.1} s\n", self.results.len(), total));
+        } else if failed.is_empty() {
+            report.push_str("No checks ran\n");
+        } else {
+            report.push_str(&format!("{} of {} checks failed in {:.1} s: {}\n", failed.len(), self.results.len(), total, failed.join(", ")));
+        }
+        report
+    }
+}
+
+/// Passes if actual is at most tolerance away from expected
+pub fn expect_near(what: &str, actual: f64, expected: f64, tolerance: f64) -> Result<String, String> {
+    if (actual - expected).abs() <= tolerance {
+        Ok(format!("{} {} (expected {} ± {})", what, actual, expected, tolerance))
+    } else {
+        Err(format!("{} {}, expected {} ± {}", what, actual, expected, tolerance))
+    }
+}
+
+/// Tests for the smoke test framework
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_reports_failed_checks() {
+        let mut run = SmokeRun::new("scripted");
+        assert!(!run.passed());
+        assert_eq!(run.exit_code(), 1);
+
+        assert!(run.check("first", || Ok("fine".to_string())));
+        assert!(run.passed());
+        assert!(!run.check("second", || Err("broken".to_string())));
+        assert!(run.check("third", || expect_near("x", 101.0, 100.0, 2.0)));
+
+        assert_eq!(run.exit_code(), 1);
+        assert_eq!(run.failures().map(|result| result.name.as_str()).collect::<Vec<_>>(), vec!["second"]);
+        assert_eq!(run.results()[1].detail, "broken");
+
+        let report = run.report();
+        assert!(report.contains("[pass] first"));
+        assert!(report.contains("[FAIL] second"));
+        assert!(report.contains("1 of 3 checks failed"));
+        assert!(report.trim_end().ends_with(": second"));
+    }
+
+    #[test]
+    fn test_wait_for_polls_until_ready_or_timeout() {
+        let mut run = SmokeRun::new("waiting");
+        let mut polls = 0;
+        let value = run.wait_for("third poll", Duration::from_secs(1), || {
+            polls += 1;
+            (polls == 3).then_some(polls)
+        });
+        assert_eq!(value, Some(3));
+        assert!(run.passed());
+
+        let started = Instant::now();
+        assert_eq!(run.wait_for("never", Duration::from_millis(30), || None::<()>), None);
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert!(!run.results()[1].passed);
+        assert!(run.results()[1].elapsed >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_expect_near() {
+        assert!(expect_near("dx", 250.0, 250.0, 0.0).is_ok());
+        assert!(expect_near("dx", 240.0, 250.0, 10.0).is_ok());
+        assert_eq!(expect_near("dx", 200.0, 250.0, 10.0), Err("dx 200, expected 250 ± 10".to_string()));
+    }
+}
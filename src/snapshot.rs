@@ -0,0 +1,645 @@
+use crate::codec::{decode, encode};
+use crate::types::{GameState, MatchPhase, PlayerShape, Position};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const LENGTH_SIZE: usize = 4; // Size of a length prefix in bytes
+const HEADER_SIZE: usize = 1 + LENGTH_SIZE; // Size of an extension block header in bytes
+
+/// Kinds of extension blocks, the values are part of the protocol and must never be reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionKind {
+    MatchNumber = 1,
+    Pings = 2,
+    Pellets = 3,
+    Scores = 4,
+    BestScores = 5,
+    Teams = 6,
+    InputReorders = 7,
+    Shapes = 8,
+    MatchPhase = 9,
+    Impulses = 10,
+    InputTimings = 11,
+    Tagged = 12,
+    MovementStats = 13,
+}
+
+/// Implementation of the ExtensionKind
+impl ExtensionKind {
+    /// Looks up the kind for a tag byte, returns None for kinds this version doesn't know
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(ExtensionKind::MatchNumber),
+            2 => Some(ExtensionKind::Pings),
+            3 => Some(ExtensionKind::Pellets),
+            4 => Some(ExtensionKind::Scores),
+            5 => Some(ExtensionKind::BestScores),
+            6 => Some(ExtensionKind::Teams),
+            7 => Some(ExtensionKind::InputReorders),
+            8 => Some(ExtensionKind::Shapes),
+            9 => Some(ExtensionKind::MatchPhase),
+            10 => Some(ExtensionKind::Impulses),
+            11 => Some(ExtensionKind::InputTimings),
+            12 => Some(ExtensionKind::Tagged),
+            13 => Some(ExtensionKind::MovementStats),
+            _ => None,
+        }
+    }
+}
+
+/// The part of a snapshot every client must understand. Players are sorted by id, and their fields are
+/// stored as columns: the nth entry of every column belongs to the nth id, so a player can be referred to
+/// by its index and the same players always encode to the same bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotCore {
+    pub ids: Vec<Uuid>,                   // Player ids in ascending order, each once
+    pub positions: Vec<Position>,         // Position of each player
+    pub colors: Vec<u32>,                 // Color of each player
+    pub last_processed: Vec<Option<u32>>, // Newest input the server applied of each player, None before its first
+    pub snapshot_id: u64,
+    pub tick_interval_ms: u32,
+    pub server_timestamp: u64,
+}
+
+/// Implementation of the SnapshotCore
+impl SnapshotCore {
+    /// Core of a snapshot, with the players sorted by id whatever order the state lists them in
+    pub fn from_state(state: &GameState) -> Self {
+        let mut players = state.players.clone();
+        players.sort_by_key(|(id, _, _)| *id);
+        Self {
+            ids: players.iter().map(|(id, _, _)| *id).collect(),
+            positions: players.iter().map(|(_, position, _)| *position).collect(),
+            colors: players.iter().map(|(_, _, color)| *color).collect(),
+            last_processed: players.iter().map(|(id, _, _)| state.last_processed.get(id).copied()).collect(),
+            snapshot_id: state.snapshot_id,
+            tick_interval_ms: state.tick_interval_ms,
+            server_timestamp: state.server_timestamp,
+        }
+    }
+
+    /// The players as id, position and color in the order of the ids. None if the columns differ in length
+    /// or the ids aren't strictly ascending, since indexes into such a core would be ambiguous.
+    pub fn players(&self) -> Option<Vec<(Uuid, Position, u32)>> {
+        let count = self.ids.len();
+        if self.positions.len() != count || self.colors.len() != count || self.last_processed.len() != count {
+            return None;
+        }
+        if self.ids.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return None;
+        }
+        Some(self.ids.iter().zip(&self.positions).zip(&self.colors).map(|((id, position), color)| (*id, *position, *color)).collect())
+    }
+
+    /// Newest applied input of each player that has one
+    pub fn last_processed_map(&self) -> HashMap<Uuid, u32> {
+        self.ids.iter().zip(&self.last_processed)
+            .filter_map(|(id, sequence)| Some((*id, (*sequence)?)))
+            .collect()
+    }
+}
+
+/// A raw extension block as found on the wire
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionBlock {
+    pub tag: u8,
+    pub data: Vec<u8>,
+}
+
+/// Encodes a snapshot as a length-prefixed core section followed by extension blocks:
+///
+/// ```text
+/// [core length: u32 LE][core: bincode SnapshotCore]
+/// [kind: u8][length: u32 LE][block: bincode] ...
+/// ```
+///
+/// Clients always need the core. Extension blocks with an unknown kind are skipped, so older
+/// clients keep working when newer servers add data.
+///
+/// Per player blocks refer to a player by its index in the core ids instead of repeating its id.
+/// The encoding is deterministic: players are sorted by id and per player blocks are written in the order
+/// of the core, so two snapshots of the same state are the same bytes however their maps were built.
+pub fn encode_snapshot(state: &GameState) -> Vec<u8> {
+    let core = SnapshotCore::from_state(state);
+    let ids = &core.ids;
+    let mut data = Vec::new();
+    write_section(&mut data, &encode(&core));
+    write_extension(&mut data, ExtensionKind::MatchNumber as u8, &encode(&state.match_number));
+    write_extension(&mut data, ExtensionKind::Pings as u8, &encode(&indexed(&state.ping_ms, ids)));
+    write_extension(&mut data, ExtensionKind::Pellets as u8, &encode(&state.pellets));
+    write_extension(&mut data, ExtensionKind::Scores as u8, &encode(&indexed(&state.scores, ids)));
+    write_extension(&mut data, ExtensionKind::BestScores as u8, &encode(&indexed(&state.best_scores, ids)));
+    write_extension(&mut data, ExtensionKind::MatchPhase as u8, &encode(&state.match_phase));
+
+    write_extension(&mut data, ExtensionKind::InputReorders as u8, &encode(&indexed(&state.input_reorders, ids)));
+
+    // Shapes go as wire values, so a shape added later decodes as a square instead of breaking the block
+    let shapes: HashMap<Uuid, u8> = state.shapes.iter().map(|(id, shape)| (*id, shape.to_wire())).collect();
+    write_extension(&mut data, ExtensionKind::Shapes as u8, &encode(&indexed(&shapes, ids)));
+
+    // Only sent in team mode, so snapshots without teams are unchanged
+    if !state.teams.is_empty() {
+        write_extension(&mut data, ExtensionKind::Teams as u8, &encode(&indexed(&state.teams, ids)));
+    }
+
+    // Only sent while someone was pushed recently
+    if !state.impulses.is_empty() {
+        write_extension(&mut data, ExtensionKind::Impulses as u8, &encode(&indexed(&state.impulses, ids)));
+    }
+
+    // Missing until someone's input was applied
+    if !state.input_timings.is_empty() {
+        write_extension(&mut data, ExtensionKind::InputTimings as u8, &encode(&indexed(&state.input_timings, ids)));
+    }
+
+    // Only sent in tag mode while someone is "it"
+    if let Some(tagged) = state.tagged {
+        write_extension(&mut data, ExtensionKind::Tagged as u8, &encode(&tagged));
+    }
+
+    // Only sent every MOVEMENT_STATS_TICKS ticks
+    if !state.movement_stats.is_empty() {
+        write_extension(&mut data, ExtensionKind::MovementStats as u8, &encode(&indexed(&state.movement_stats, ids)));
+    }
+    data
+}
+
+/// A per player map as pairs of the player's index in the core ids and its value, in the order of the ids.
+/// An index takes 2 bytes where an id takes 24, which keeps the snapshots of full rooms small.
+/// Players missing from the core are left out, a client couldn't place them anyway.
+fn indexed<V: Clone>(map: &HashMap<Uuid, V>, ids: &[Uuid]) -> Vec<(u16, V)> {
+    ids.iter().enumerate()
+        .filter_map(|(index, id)| Some((u16::try_from(index).ok()?, map.get(id)?.clone())))
+        .collect()
+}
+
+/// Decodes an indexed per player block back into a map by id. None if the block is broken,
+/// pairs whose index is past the core ids are skipped.
+fn by_id<V: DeserializeOwned>(data: &[u8], ids: &[Uuid]) -> Option<HashMap<Uuid, V>> {
+    let pairs: Vec<(u16, V)> = decode(data).ok()?;
+    Some(pairs.into_iter().filter_map(|(index, value)| Some((*ids.get(index as usize)?, value))).collect())
+}
+
+/// Decodes a snapshot. Returns None if the core section is missing or broken, extension
+/// blocks that are unknown, broken or truncated are skipped and their fields keep defaults.
+pub fn decode_snapshot(data: &[u8]) -> Option<GameState> {
+    let (core, rest) = read_section(data)?;
+    let core: SnapshotCore = decode(core).ok()?;
+
+    let mut state = GameState {
+        players: core.players()?,
+        last_processed: core.last_processed_map(),
+        pellets: Vec::new(),
+        scores: HashMap::new(),
+        best_scores: HashMap::new(),
+        teams: HashMap::new(),
+        input_reorders: HashMap::new(),
+        shapes: HashMap::new(),
+        impulses: HashMap::new(),
+        input_timings: HashMap::new(),
+        match_phase: MatchPhase::default(),
+        server_timestamp: core.server_timestamp,
+        match_number: 0,
+        ping_ms: HashMap::new(),
+        snapshot_id: core.snapshot_id,
+        tick_interval_ms: core.tick_interval_ms,
+        tagged: None,
+        movement_stats: HashMap::new(),
+    };
+
+    for block in read_extensions(rest) {
+        apply_extension(&mut state, &block);
+    }
+    Some(state)
+}
+
+/// Fills in the fields of an extension block. Per player blocks refer to the players by their index in
+/// the players of the state, which are sorted by id like the core. Unknown and broken blocks leave the state as it is.
+pub fn apply_extension(state: &mut GameState, block: &ExtensionBlock) {
+    let ids: Vec<Uuid> = state.players.iter().map(|(id, _, _)| *id).collect();
+    match ExtensionKind::from_tag(block.tag) {
+        Some(ExtensionKind::MatchNumber) => {
+            if let Ok(match_number) = decode(&block.data) {
+                state.match_number = match_number;
+            }
+        }
+        Some(ExtensionKind::Pings) => {
+            if let Some(ping_ms) = by_id(&block.data, &ids) {
+                state.ping_ms = ping_ms;
+            }
+        }
+        Some(ExtensionKind::Pellets) => {
+            if let Ok(pellets) = decode(&block.data) {
+                state.pellets = pellets;
+            }
+        }
+        Some(ExtensionKind::Scores) => {
+            if let Some(scores) = by_id(&block.data, &ids) {
+                state.scores = scores;
+            }
+        }
+        Some(ExtensionKind::BestScores) => {
+            if let Some(best_scores) = by_id(&block.data, &ids) {
+                state.best_scores = best_scores;
+            }
+        }
+        Some(ExtensionKind::InputReorders) => {
+            if let Some(input_reorders) = by_id(&block.data, &ids) {
+                state.input_reorders = input_reorders;
+            }
+        }
+        Some(ExtensionKind::Shapes) => {
+            if let Some(shapes) = by_id::<u8>(&block.data, &ids) {
+                state.shapes = shapes.into_iter().map(|(id, shape)| (id, PlayerShape::from_wire(shape))).collect();
+            }
+        }
+        Some(ExtensionKind::MatchPhase) => {
+            // A phase added by a newer server fails to decode and leaves players free to move
+            if let Ok(match_phase) = decode(&block.data) {
+                state.match_phase = match_phase;
+            }
+        }
+        Some(ExtensionKind::Teams) => {
+            if let Some(teams) = by_id(&block.data, &ids) {
+                state.teams = teams;
+            }
+        }
+        Some(ExtensionKind::Impulses) => {
+            if let Some(impulses) = by_id(&block.data, &ids) {
+                state.impulses = impulses;
+            }
+        }
+        Some(ExtensionKind::InputTimings) => {
+            if let Some(input_timings) = by_id(&block.data, &ids) {
+                state.input_timings = input_timings;
+            }
+        }
+        Some(ExtensionKind::Tagged) => {
+            if let Ok(tagged) = decode(&block.data) {
+                state.tagged = Some(tagged);
+            }
+        }
+        Some(ExtensionKind::MovementStats) => {
+            if let Some(movement_stats) = by_id(&block.data, &ids) {
+                state.movement_stats = movement_stats;
+            }
+        }
+        None => {
+            // Added by a newer server, skip it
+        }
+    }
+}
+
+/// Reads the extension blocks after the core section, stopping at the first truncated block
+pub fn read_extensions(mut data: &[u8]) -> Vec<ExtensionBlock> {
+    let mut blocks = Vec::new();
+    while data.len() >= HEADER_SIZE {
+        let tag = data[0];
+        let Some((block, rest)) = read_section(&data[1..]) else {
+            break;
+        };
+        blocks.push(ExtensionBlock { tag, data: block.to_vec() });
+        data = rest;
+    }
+    blocks
+}
+
+/// Appends an extension block with the given tag
+pub fn write_extension(data: &mut Vec<u8>, tag: u8, block: &[u8]) {
+    data.push(tag);
+    write_section(data, block);
+}
+
+/// Appends a length-prefixed section
+fn write_section(data: &mut Vec<u8>, section: &[u8]) {
+    data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+    data.extend_from_slice(section);
+}
+
+/// Splits a length-prefixed section from the front of the data, returns None if it is truncated
+fn read_section(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let length_bytes: [u8; LENGTH_SIZE] = data.get(..LENGTH_SIZE)?.try_into().ok()?;
+    let length = u32::from_le_bytes(length_bytes) as usize;
+    let rest = &data[LENGTH_SIZE..];
+    if rest.len() < length {
+        return None;
+    }
+    Some(rest.split_at(length))
+}
+
+/// Tests for the snapshot encoding
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AppliedImpulse, InputTiming, MovementStats};
+
+    // Helper function to create a snapshot with every field set
+    fn test_state() -> GameState {
+        let id = Uuid::new_v4();
+        GameState {
+            players: vec![(id, Position { x: 10, y: 20 }, 0xff1717)],
+            last_processed: HashMap::from([(id, 42)]),
+            pellets: vec![Position { x: 30, y: 40 }],
+            scores: HashMap::from([(id, 4)]),
+            best_scores: HashMap::from([(id, 9)]),
+            teams: HashMap::from([(id, 1)]),
+            input_reorders: HashMap::from([(id, 6)]),
+            shapes: HashMap::from([(id, PlayerShape::Circle)]),
+            impulses: HashMap::from([(id, AppliedImpulse { dx: -3, dy: 0, after_sequence: 41, tick: 98 })]),
+            input_timings: HashMap::from([(id, InputTiming { sequence: 42, client_timestamp: 51_200, received_ms: 1_540, applied_ms: 1_550 })]),
+            match_phase: MatchPhase::Countdown { remaining_ms: 4200 },
+            server_timestamp: 1234,
+            match_number: 3,
+            ping_ms: HashMap::from([(id, 35)]),
+            snapshot_id: 99,
+            tick_interval_ms: 16,
+            tagged: None,
+            movement_stats: HashMap::from([(id, MovementStats { distance: 2_400, inputs: 480, connected_ms: 9_000 })]),
+        }
+    }
+
+    // Helper function to get the ids of the players in the order of the core
+    fn player_ids(state: &GameState) -> Vec<Uuid> {
+        SnapshotCore::from_state(state).ids
+    }
+
+    // Helper function to encode only the core section, like a server without extensions
+    fn encode_core_only(state: &GameState) -> Vec<u8> {
+        let encoded = encode_snapshot(state);
+        let (core, _) = read_section(&encoded).unwrap();
+        let mut data = Vec::new();
+        write_section(&mut data, core);
+        data
+    }
+
+    // Helper function to decode only the core section, like a client that knows no extensions
+    fn decode_core_only(data: &[u8]) -> Option<SnapshotCore> {
+        let (core, _) = read_section(data)?;
+        decode(core).ok()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let state = test_state();
+        let decoded = decode_snapshot(&encode_snapshot(&state)).unwrap();
+
+        assert_eq!(decoded.players, state.players);
+        assert_eq!(decoded.last_processed, state.last_processed);
+        assert_eq!(decoded.server_timestamp, state.server_timestamp);
+        assert_eq!(decoded.match_number, state.match_number);
+        assert_eq!(decoded.ping_ms, state.ping_ms);
+        assert_eq!(decoded.pellets, state.pellets);
+        assert_eq!(decoded.scores, state.scores);
+        assert_eq!(decoded.best_scores, state.best_scores);
+        assert_eq!(decoded.teams, state.teams);
+        assert_eq!(decoded.input_reorders, state.input_reorders);
+        assert_eq!(decoded.shapes, state.shapes);
+        assert_eq!(decoded.impulses, state.impulses);
+        assert_eq!(decoded.input_timings, state.input_timings);
+        assert_eq!(decoded.movement_stats, state.movement_stats);
+        assert_eq!(decoded.match_phase, state.match_phase);
+        assert_eq!(decoded.snapshot_id, state.snapshot_id);
+        assert_eq!(decoded.tick_interval_ms, state.tick_interval_ms);
+    }
+
+    #[test]
+    fn test_new_server_old_client() {
+        // A newer server adds an extension this version doesn't know about
+        let state = test_state();
+        let mut data = encode_snapshot(&state);
+        write_extension(&mut data, 200, &[1, 2, 3, 4]);
+
+        // A client that only knows the core decodes it
+        let core = decode_core_only(&data).unwrap();
+        assert_eq!(core.players(), Some(state.players.clone()));
+        assert_eq!(core.snapshot_id, state.snapshot_id);
+
+        // The current client skips the unknown block and keeps the known ones
+        let decoded = decode_snapshot(&data).unwrap();
+        assert_eq!(decoded.players, state.players);
+        assert_eq!(decoded.match_number, 3);
+        assert_eq!(decoded.ping_ms, state.ping_ms);
+    }
+
+    #[test]
+    fn test_old_server_new_client() {
+        // An older server sends only the core
+        let state = test_state();
+        let decoded = decode_snapshot(&encode_core_only(&state)).unwrap();
+
+        assert_eq!(decoded.players, state.players);
+        assert_eq!(decoded.last_processed, state.last_processed);
+        assert_eq!(decoded.snapshot_id, state.snapshot_id);
+
+        // Extension fields fall back to their defaults
+        assert_eq!(decoded.match_number, 0);
+        assert!(decoded.ping_ms.is_empty());
+        assert_eq!(decoded.match_phase, MatchPhase::Warmup);
+    }
+
+    #[test]
+    fn test_unknown_block_between_known_blocks() {
+        let state = test_state();
+        let mut data = encode_core_only(&state);
+        write_extension(&mut data, ExtensionKind::MatchNumber as u8, &encode(&7_u32));
+        write_extension(&mut data, 99, &[0xff; 16]);
+        write_extension(&mut data, ExtensionKind::Pings as u8, &encode(&indexed(&state.ping_ms, &player_ids(&state))));
+
+        let decoded = decode_snapshot(&data).unwrap();
+        assert_eq!(decoded.match_number, 7);
+        assert_eq!(decoded.ping_ms, state.ping_ms);
+    }
+
+    #[test]
+    fn test_broken_known_block_is_ignored() {
+        let state = test_state();
+        let mut data = encode_core_only(&state);
+        write_extension(&mut data, ExtensionKind::MatchNumber as u8, &[1]); // Too short for a u32
+        write_extension(&mut data, ExtensionKind::Pings as u8, &encode(&indexed(&state.ping_ms, &player_ids(&state))));
+
+        let decoded = decode_snapshot(&data).unwrap();
+        assert_eq!(decoded.match_number, 0);
+        assert_eq!(decoded.ping_ms, state.ping_ms);
+    }
+
+    #[test]
+    fn test_truncated_block_keeps_core_and_earlier_blocks() {
+        let state = test_state();
+        let data = encode_snapshot(&state);
+
+        // Cut the last byte of the last block
+        let decoded = decode_snapshot(&data[..data.len() - 1]).unwrap();
+        assert_eq!(decoded.players, state.players);
+        assert_eq!(decoded.match_number, 3);
+        assert_eq!(decoded.best_scores, state.best_scores);
+        assert_eq!(decoded.teams, state.teams);
+        assert_eq!(decoded.impulses, state.impulses);
+        assert_eq!(decoded.input_timings, state.input_timings);
+        assert!(decoded.movement_stats.is_empty());
+
+        // Cut inside a block header
+        let core_length = encode_core_only(&state).len();
+        let decoded = decode_snapshot(&data[..core_length + 2]).unwrap();
+        assert_eq!(decoded.players, state.players);
+        assert_eq!(decoded.match_number, 0);
+    }
+
+    #[test]
+    fn test_unknown_shape_decodes_as_square() {
+        let state = test_state();
+        let id = state.players[0].0;
+        let mut data = encode_core_only(&state);
+        write_extension(&mut data, ExtensionKind::Shapes as u8, &encode(&vec![(0_u16, 9_u8)]));
+
+        let decoded = decode_snapshot(&data).unwrap();
+        assert_eq!(decoded.shapes.get(&id), Some(&PlayerShape::Square));
+    }
+
+    #[test]
+    fn test_per_player_blocks_refer_to_players_by_index() {
+        let (first, second) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let mut state = test_state();
+        state.players = vec![(second, Position { x: 50, y: 60 }, 0x1717ff), (first, Position { x: 10, y: 20 }, 0xff1717)];
+        state.scores = HashMap::from([(first, 4), (second, 7)]);
+        let ids = player_ids(&state);
+        assert_eq!(ids, vec![first, second]);
+        assert_eq!(indexed(&state.scores, &ids), vec![(0, 4), (1, 7)]);
+
+        // Pairs past the players of the core are skipped, the rest of the block still counts
+        let mut data = encode_core_only(&state);
+        write_extension(&mut data, ExtensionKind::Scores as u8, &encode(&vec![(1_u16, 7_u32), (2, 9)]));
+        let decoded = decode_snapshot(&data).unwrap();
+        assert_eq!(decoded.scores, HashMap::from([(second, 7)]));
+    }
+
+    #[test]
+    fn test_teams_block_only_in_team_mode() {
+        let mut state = test_state();
+        let tags = |state: &GameState| -> Vec<u8> {
+            let encoded = encode_snapshot(state);
+            let (_, rest) = read_section(&encoded).unwrap();
+            read_extensions(rest).iter().map(|block| block.tag).collect()
+        };
+        assert!(tags(&state).contains(&(ExtensionKind::Teams as u8)));
+
+        // Without teams the snapshot is encoded exactly as before team mode existed
+        state.teams.clear();
+        assert!(!tags(&state).contains(&(ExtensionKind::Teams as u8)));
+        assert!(decode_snapshot(&encode_snapshot(&state)).unwrap().teams.is_empty());
+
+        // Same for impulses, which are only there while someone was pushed
+        assert!(tags(&state).contains(&(ExtensionKind::Impulses as u8)));
+        state.impulses.clear();
+        assert!(!tags(&state).contains(&(ExtensionKind::Impulses as u8)));
+
+        // And for the tagged player, which only tag mode has
+        assert!(!tags(&state).contains(&(ExtensionKind::Tagged as u8)));
+        state.tagged = Some(state.players[0].0);
+        assert!(tags(&state).contains(&(ExtensionKind::Tagged as u8)));
+        assert_eq!(decode_snapshot(&encode_snapshot(&state)).unwrap().tagged, state.tagged);
+    }
+
+    #[test]
+    fn test_truncated_core_fails() {
+        let data = encode_snapshot(&test_state());
+
+        assert!(decode_snapshot(&[]).is_none());
+        assert!(decode_snapshot(&data[..2]).is_none());
+        assert!(decode_snapshot(&data[..10]).is_none());
+    }
+
+    #[test]
+    fn test_read_extensions() {
+        let mut data = Vec::new();
+        write_extension(&mut data, 1, &[]);
+        write_extension(&mut data, 5, &[9, 8, 7]);
+
+        let blocks = read_extensions(&data);
+        assert_eq!(blocks, vec![
+            ExtensionBlock { tag: 1, data: vec![] },
+            ExtensionBlock { tag: 5, data: vec![9, 8, 7] },
+        ]);
+
+        // A length larger than the remaining data stops reading
+        let mut truncated = data.clone();
+        write_extension(&mut truncated, 6, &[1, 2, 3]);
+        truncated.truncate(truncated.len() - 2);
+        assert_eq!(read_extensions(&truncated).len(), 2);
+    }
+
+    #[test]
+    fn test_client_message_is_not_a_snapshot() {
+        // Messages arrive on the same socket, they must not decode as snapshots
+        let message = encode(&crate::types::ClientMessage::Pong(12345));
+        assert!(decode_snapshot(&message).is_none());
+    }
+
+    // Helper function to create a snapshot of three fixed players with a few maps holding an entry for each of them
+    fn three_player_state() -> GameState {
+        let mut state = GameState {
+            best_scores: HashMap::new(),
+            teams: HashMap::new(),
+            input_reorders: HashMap::new(),
+            impulses: HashMap::new(),
+            input_timings: HashMap::new(),
+            movement_stats: HashMap::new(),
+            ..test_state()
+        };
+        let ids = [Uuid::from_u128(3), Uuid::from_u128(1), Uuid::from_u128(2)];
+        state.players = ids.iter().enumerate().map(|(index, id)| (*id, Position { x: index as i32 * 50, y: 20 }, 0x1717ff)).collect();
+        state.last_processed = ids.iter().map(|id| (*id, 7)).collect();
+        state.ping_ms = ids.iter().map(|id| (*id, 30)).collect();
+        state.scores = ids.iter().map(|id| (*id, 2)).collect();
+        state.shapes = ids.iter().map(|id| (*id, PlayerShape::Triangle)).collect();
+        state
+    }
+
+    #[test]
+    fn test_identical_states_encode_to_identical_bytes() {
+        // The same state built again, with the players in another order and the maps filled the other way round
+        let reversed = |map: &HashMap<Uuid, u32>| -> HashMap<Uuid, u32> {
+            let mut entries: Vec<(Uuid, u32)> = map.iter().map(|(id, value)| (*id, *value)).collect();
+            entries.reverse();
+            entries.into_iter().collect()
+        };
+        let state = three_player_state();
+        let mut rebuilt = three_player_state();
+        rebuilt.players.reverse();
+        rebuilt.last_processed = reversed(&rebuilt.last_processed);
+        rebuilt.ping_ms = reversed(&rebuilt.ping_ms);
+        rebuilt.scores = reversed(&rebuilt.scores);
+        assert_eq!(encode_snapshot(&state), encode_snapshot(&rebuilt));
+
+        // Players decode sorted by id, and their last processed inputs stay with them
+        let decoded = decode_snapshot(&encode_snapshot(&state)).unwrap();
+        let ids: Vec<Uuid> = decoded.players.iter().map(|(id, _, _)| *id).collect();
+        assert_eq!(ids, vec![Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3)]);
+        assert_eq!(decoded.players[2].1, Position { x: 0, y: 20 });
+        assert_eq!(decoded.last_processed, state.last_processed);
+    }
+
+    #[test]
+    fn test_core_with_unsorted_or_uneven_columns_fails() {
+        let state = three_player_state();
+        let encode_core = |core: &SnapshotCore| {
+            let mut data = Vec::new();
+            write_section(&mut data, &encode(core));
+            data
+        };
+        let core = SnapshotCore::from_state(&state);
+        assert!(decode_snapshot(&encode_core(&core)).is_some());
+
+        let mut unsorted = core.clone();
+        unsorted.ids.swap(0, 1);
+        assert!(decode_snapshot(&encode_core(&unsorted)).is_none());
+        let mut duplicated = core.clone();
+        duplicated.ids[1] = duplicated.ids[0];
+        assert!(decode_snapshot(&encode_core(&duplicated)).is_none());
+        let mut uneven = core;
+        uneven.colors.pop();
+        assert!(decode_snapshot(&encode_core(&uneven)).is_none());
+    }
+}
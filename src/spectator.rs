@@ -0,0 +1,262 @@
+use crate::constants::{SPECTATOR_PAN_SPEED, SPECTATOR_ZOOM};
+use crate::coords::{self, window_size};
+use crate::positions::DisplayPos;
+use crate::types::{Direction, Position};
+
+use uuid::Uuid;
+
+/// Pan and zoom over the board: the board point shown in the middle of the screen and how many
+/// screen pixels one board pixel takes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    pub center: (f32, f32),
+    pub zoom: f32,
+}
+
+/// Implementation of the View
+impl View {
+    /// The whole board at its own size, what is drawn without a view
+    pub fn whole_board() -> Self {
+        let (width, height) = window_size();
+        Self { center: (width / 2.0, height / 2.0), zoom: 1.0 }
+    }
+
+    /// View centered on a board position at the zoom, moved as little as needed to stay on the board
+    pub fn centered_on(position: Position, zoom: f32, screen: (f32, f32)) -> Self {
+        let mut view = Self { center: (position.x as f32, position.y as f32), zoom };
+        view.clamp(screen);
+        view
+    }
+
+    /// Size of the board area shown on a screen of the given size
+    pub fn visible_size(&self, screen: (f32, f32)) -> (f32, f32) {
+        (screen.0 / self.zoom, screen.1 / self.zoom)
+    }
+
+    /// Board area shown on the screen as x, y, width and height
+    pub fn visible_rect(&self, screen: (f32, f32)) -> (f32, f32, f32, f32) {
+        let (width, height) = self.visible_size(screen);
+        (self.center.0 - width / 2.0, self.center.1 - height / 2.0, width, height)
+    }
+
+    /// Screen position of a board position
+    pub fn to_screen(&self, board: (f32, f32), screen: (f32, f32)) -> (f32, f32) {
+        coords::to_screen(self, board, screen)
+    }
+
+    /// Moves the view by screen pixels, staying on the board
+    pub fn pan(&mut self, dx: f32, dy: f32, screen: (f32, f32)) {
+        self.center.0 += dx / self.zoom;
+        self.center.1 += dy / self.zoom;
+        self.clamp(screen);
+    }
+
+    /// Keeps the view on the board. Along an axis where the whole board fits it is centered.
+    pub fn clamp(&mut self, screen: (f32, f32)) {
+        let (width, height) = self.visible_size(screen);
+        let (window_width, window_height) = window_size();
+        self.center.0 = clamp_axis(self.center.0, width, window_width);
+        self.center.1 = clamp_axis(self.center.1, height, window_height);
+    }
+}
+
+/// Default implementation for the View
+impl Default for View {
+    fn default() -> Self {
+        Self::whole_board()
+    }
+}
+
+/// Center on one axis that keeps a visible span within the board length
+fn clamp_axis(center: f32, visible: f32, board: f32) -> f32 {
+    if visible >= board {
+        board / 2.0
+    } else {
+        center.clamp(visible / 2.0, board - visible / 2.0)
+    }
+}
+
+/// The player to follow after current: the next one by id, wrapping around to the first.
+/// A current player that left is skipped the same way, so the order doesn't jump around.
+pub fn next_follow_target(current: Option<Uuid>, players: &[Uuid]) -> Option<Uuid> {
+    let first = players.iter().min().copied();
+    let Some(current) = current else {
+        return first;
+    };
+    players.iter().filter(|id| **id > current).min().copied().or(first)
+}
+
+/// Direction to pan in for the held directions as a unit vector, zero when none or opposite ones are held
+pub fn pan_vector(held: &[Direction]) -> (f32, f32) {
+    let (mut x, mut y) = (0.0f32, 0.0f32);
+    for dir in held {
+        match dir {
+            Direction::Up => y -= 1.0,
+            Direction::Down => y += 1.0,
+            Direction::Left => x -= 1.0,
+            Direction::Right => x += 1.0,
+        }
+    }
+    let (x, y) = (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0));
+    let length = (x * x + y * y).sqrt();
+    if length > 0.0 { (x / length, y / length) } else { (0.0, 0.0) }
+}
+
+/// Camera of a spectator: follows one player, cycled through in a stable order, or pans freely
+#[derive(Debug, Clone)]
+pub struct Spectator {
+    following: Option<Uuid>,
+    free_camera: bool,
+    view: View,
+}
+
+/// Implementation of the Spectator
+impl Spectator {
+    /// Creates a camera following nobody yet, zoomed in on the middle of the board
+    pub fn new() -> Self {
+        Self { following: None, free_camera: false, view: View { zoom: SPECTATOR_ZOOM, ..View::whole_board() } }
+    }
+
+    /// The followed player, None while nobody can be followed
+    pub fn following(&self) -> Option<Uuid> {
+        self.following
+    }
+
+    /// Checks if the camera pans freely instead of following
+    pub fn is_free_camera(&self) -> bool {
+        self.free_camera
+    }
+
+    /// The current view
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    /// Follows the next player, which also ends the free camera
+    pub fn cycle(&mut self, players: &[Uuid]) {
+        self.following = next_follow_target(self.following, players);
+        self.free_camera = false;
+    }
+
+    /// Switches between following and the free camera. The free camera starts where the view is.
+    pub fn toggle_free_camera(&mut self) {
+        self.free_camera = !self.free_camera;
+    }
+
+    /// Pans the free camera for a frame in the held directions, ignored while following
+    pub fn pan(&mut self, held: &[Direction], dt: f32, screen: (f32, f32)) {
+        if !self.free_camera {
+            return;
+        }
+        let (x, y) = pan_vector(held);
+        self.view.pan(x * SPECTATOR_PAN_SPEED * dt, y * SPECTATOR_PAN_SPEED * dt, screen);
+    }
+
+    /// Follows the next player if the followed one left or nobody is followed yet,
+    /// then centers the view on the followed player unless the camera pans freely
+    pub fn update(&mut self, players: &[(Uuid, DisplayPos)], screen: (f32, f32)) {
+        let ids: Vec<Uuid> = players.iter().map(|(id, _)| *id).collect();
+        if self.following.is_none_or(|id| !ids.contains(&id)) {
+            self.following = next_follow_target(self.following, &ids);
+        }
+        if self.free_camera {
+            self.view.clamp(screen);
+            return;
+        }
+        if let Some((_, position)) = players.iter().find(|(id, _)| Some(*id) == self.following) {
+            self.view = View::centered_on(position.layout_position(), self.view.zoom, screen);
+        }
+    }
+}
+
+/// Default implementation for the Spectator
+impl Default for Spectator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tests for spectating
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH};
+
+    const SCREEN: (f32, f32) = (BOARD_WIDTH as f32, BOARD_HEIGHT as f32);
+
+    // Helper function to create ids that sort in the given order
+    fn ids(count: u128) -> Vec<Uuid> {
+        (1..=count).map(Uuid::from_u128).collect()
+    }
+
+    #[test]
+    fn test_follow_order_is_stable_by_id() {
+        let players = ids(3);
+        let shuffled = vec![players[2], players[0], players[1]];
+        assert_eq!(next_follow_target(None, &shuffled), Some(players[0]));
+        assert_eq!(next_follow_target(Some(players[0]), &shuffled), Some(players[1]));
+        assert_eq!(next_follow_target(Some(players[2]), &shuffled), Some(players[0]));
+
+        // A departed player is skipped, the next one by id is followed
+        let without_second = vec![players[2], players[0]];
+        assert_eq!(next_follow_target(Some(players[1]), &without_second), Some(players[2]));
+        assert_eq!(next_follow_target(Some(players[0]), &[]), None);
+    }
+
+    #[test]
+    fn test_view_centers_and_stays_on_the_board() {
+        let zoom = 2.0;
+        let view = View::centered_on(Position { x: 500, y: 400 }, zoom, SCREEN);
+        assert_eq!(view.center, (500.0, 400.0));
+        assert_eq!(view.visible_rect(SCREEN), (244.0, 208.0, 512.0, 384.0));
+        assert_eq!(view.to_screen((500.0, 400.0), SCREEN), (SCREEN.0 / 2.0, SCREEN.1 / 2.0));
+
+        // Near a corner the view stops at the board edges
+        let corner = View::centered_on(Position { x: 10, y: 760 }, zoom, SCREEN);
+        assert_eq!(corner.visible_rect(SCREEN), (0.0, BOARD_HEIGHT as f32 - 384.0, 512.0, 384.0));
+
+        // Zoomed out past the board it is centered
+        let far = View::centered_on(Position { x: 10, y: 10 }, 0.5, SCREEN);
+        assert_eq!(far.center, View::whole_board().center);
+        assert_eq!(View::whole_board().visible_rect(SCREEN), (0.0, 0.0, SCREEN.0, SCREEN.1));
+    }
+
+    #[test]
+    fn test_pan_vector() {
+        assert_eq!(pan_vector(&[]), (0.0, 0.0));
+        assert_eq!(pan_vector(&[Direction::Left]), (-1.0, 0.0));
+        assert_eq!(pan_vector(&[Direction::Up, Direction::Down]), (0.0, 0.0));
+        let (x, y) = pan_vector(&[Direction::Down, Direction::Right]);
+        assert!((x - y).abs() < 1e-6 && (x * x + y * y - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_spectator_follows_and_pans() {
+        let players = ids(2);
+        let positions = vec![(players[1], DisplayPos::smoothed(Position { x: 600, y: 300 })), (players[0], DisplayPos::smoothed(Position { x: 400, y: 300 }))];
+        let mut spectator = Spectator::new();
+
+        // Starts following the first player by id
+        spectator.update(&positions, SCREEN);
+        assert_eq!(spectator.following(), Some(players[0]));
+        assert_eq!(spectator.view().center, (400.0, 300.0));
+        spectator.cycle(&players);
+        spectator.update(&positions, SCREEN);
+        assert_eq!(spectator.view().center, (600.0, 300.0));
+
+        // Following ignores the pan keys, the free camera pans at the pan speed from where the view is
+        spectator.pan(&[Direction::Right], 0.1, SCREEN);
+        assert_eq!(spectator.view().center, (600.0, 300.0));
+        spectator.toggle_free_camera();
+        spectator.pan(&[Direction::Right], 0.1, SCREEN);
+        spectator.update(&positions, SCREEN);
+        assert_eq!(spectator.view().center, (600.0 + SPECTATOR_PAN_SPEED * 0.1 / SPECTATOR_ZOOM, 300.0));
+
+        // The followed player leaves, cycling ends the free camera and wraps around
+        spectator.update(&positions[1..], SCREEN);
+        assert_eq!(spectator.following(), Some(players[0]));
+        spectator.cycle(&[players[0]]);
+        assert!(!spectator.is_free_camera());
+        assert_eq!(spectator.following(), Some(players[0]));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::constants::TOOL_BAR_HEIGHT;
+use crate::coords::play_area_size;
+use crate::minimap::ScreenRect;
+use crate::spectator::View;
+
+/// How the board is laid out in the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ViewLayout {
+    #[default]
+    Single, // The client view over the whole window
+    Dual,   // The client view on the left and the raw snapshots on the right, side by side
+}
+
+/// Implementation of the ViewLayout
+impl ViewLayout {
+    /// The other layout
+    pub fn toggled(self) -> Self {
+        match self {
+            ViewLayout::Single => ViewLayout::Dual,
+            ViewLayout::Dual => ViewLayout::Single,
+        }
+    }
+
+    /// Viewports of the layout on a screen of the given size, left to right. The toolbar keeps the full width
+    /// beneath them. A single view draws the board at its own size, a dual view fits the whole board into
+    /// each half.
+    pub fn viewports(self, screen: (f32, f32)) -> Vec<Viewport> {
+        let (screen_width, screen_height) = screen;
+        let area_height = (screen_height - TOOL_BAR_HEIGHT as f32).max(0.0);
+        match self {
+            ViewLayout::Single => vec![Viewport { rect: (0.0, 0.0, screen_width, screen_height), view: View::whole_board(), source: PositionSource::Smoothed }],
+            ViewLayout::Dual => {
+                let half = screen_width / 2.0;
+                vec![
+                    Viewport::fitting((0.0, 0.0, half, area_height), PositionSource::Smoothed),
+                    Viewport::fitting((half, 0.0, half, area_height), PositionSource::RawSnapshot),
+                ]
+            }
+        }
+    }
+}
+
+/// Which positions a viewport draws the players at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionSource {
+    Smoothed,    // The client view: the local player predicted, remote players interpolated or frozen while fading out
+    RawSnapshot, // Every player where the latest snapshot has it, without prediction or interpolation
+}
+
+/// Implementation of the PositionSource
+impl PositionSource {
+    /// Caption drawn over a viewport in the dual view
+    pub fn label(self) -> &'static str {
+        match self {
+            PositionSource::Smoothed => "Client view (prediction + interpolation)",
+            PositionSource::RawSnapshot => "Server state (latest snapshot)",
+        }
+    }
+}
+
+/// Part of the screen the board is drawn into, with the view of the board it shows there
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub rect: ScreenRect,       // Screen area as x, y, width and height
+    pub view: View,             // Pan and zoom of the board within the area, its screen is the area's size
+    pub source: PositionSource, // Positions the players are drawn at
+}
+
+/// Implementation of the Viewport
+impl Viewport {
+    /// Viewport showing the whole board above the toolbar as large as fits in the area, keeping its aspect
+    /// and centered in the area
+    pub fn fitting(area: ScreenRect, source: PositionSource) -> Self {
+        let (x, y, width, height) = area;
+        let (board_width, board_height) = play_area_size();
+        let zoom = (width / board_width).min(height / board_height).max(0.0);
+        let (fitted_width, fitted_height) = (board_width * zoom, board_height * zoom);
+        Self {
+            rect: (x + (width - fitted_width) / 2.0, y + (height - fitted_height) / 2.0, fitted_width, fitted_height),
+            view: View { center: (board_width / 2.0, board_height / 2.0), zoom },
+            source,
+        }
+    }
+
+    /// Size of the viewport, the screen its view is laid out on
+    pub fn size(&self) -> (f32, f32) {
+        (self.rect.2, self.rect.3)
+    }
+
+    /// Board area the viewport shows as x, y, width and height
+    pub fn visible_rect(&self) -> ScreenRect {
+        self.view.visible_rect(self.size())
+    }
+
+    /// Screen position of a board position
+    pub fn to_screen(&self, board: (f32, f32)) -> (f32, f32) {
+        let (x, y) = self.view.to_screen(board, self.size());
+        (self.rect.0 + x, self.rect.1 + y)
+    }
+}
+
+/// Tests for the split view layout
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{BOARD_HEIGHT, BOARD_WIDTH};
+
+    #[test]
+    fn test_single_view_draws_the_board_as_before() {
+        let screen = (BOARD_WIDTH as f32, BOARD_HEIGHT as f32);
+        let viewports = ViewLayout::Single.viewports(screen);
+        assert_eq!(viewports.len(), 1);
+        assert_eq!(viewports[0].source, PositionSource::Smoothed);
+        assert_eq!(viewports[0].visible_rect(), (0.0, 0.0, screen.0, screen.1));
+        assert_eq!(viewports[0].to_screen((120.0, 80.0)), (120.0, 80.0));
+        assert_eq!(ViewLayout::default().toggled(), ViewLayout::Dual);
+    }
+
+    #[test]
+    fn test_dual_view_fits_the_board_into_each_half() {
+        let screen = (BOARD_WIDTH as f32, BOARD_HEIGHT as f32);
+        let board_height = (BOARD_HEIGHT - TOOL_BAR_HEIGHT) as f32;
+        let [left, right] = ViewLayout::Dual.viewports(screen)[..] else {
+            panic!("the dual view has two viewports");
+        };
+        assert_eq!((left.source, right.source), (PositionSource::Smoothed, PositionSource::RawSnapshot));
+
+        // Both show the whole board at half the width, one beside the other and above the toolbar
+        let zoom = 0.5;
+        assert_eq!(left.view, right.view);
+        assert_eq!(left.view.zoom, zoom);
+        assert_eq!(left.visible_rect(), (0.0, 0.0, BOARD_WIDTH as f32, board_height));
+        assert_eq!(right.rect.0, screen.0 / 2.0);
+        assert!(left.rect.1 + left.rect.3 <= screen.1 - TOOL_BAR_HEIGHT as f32);
+
+        // The same board position lands at the same spot in each half
+        let (left_x, left_y) = left.to_screen((BOARD_WIDTH as f32, board_height));
+        let (right_x, right_y) = right.to_screen((BOARD_WIDTH as f32, board_height));
+        assert_eq!((left_x, right_x - left_x, left_y, right_y), (screen.0 / 2.0, screen.0 / 2.0, left_y, left_y));
+        assert_eq!(left.to_screen((0.0, 0.0)), (left.rect.0, left.rect.1));
+    }
+
+    #[test]
+    fn test_fitting_keeps_the_board_aspect_in_a_tall_area() {
+        let board_height = (BOARD_HEIGHT - TOOL_BAR_HEIGHT) as f32;
+        let viewport = Viewport::fitting((10.0, 0.0, 200.0, 1000.0), PositionSource::RawSnapshot);
+        let (x, y, width, height) = viewport.rect;
+        assert_eq!((x, width), (10.0, 200.0));
+        assert!((height / width - board_height / BOARD_WIDTH as f32).abs() < 1e-4);
+        assert!((y - (1000.0 - height) / 2.0).abs() < 1e-3);
+
+        // An area with no room shows nothing instead of flipping the board
+        assert_eq!(Viewport::fitting((0.0, 0.0, 0.0, 100.0), PositionSource::Smoothed).view.zoom, 0.0);
+    }
+}
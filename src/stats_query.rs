@@ -0,0 +1,395 @@
+use crate::constants::{STATS_QUERY_INTERVAL, STATS_QUERY_MAGIC, STATS_QUERY_MAX_BYTES, STATS_QUERY_MAX_SOURCES, STATS_TICK_WINDOW};
+use crate::load_shedding::ShedCounters;
+use crate::profiler::{FrameProfile, ProfileTotals, ProfileWindow};
+use crate::warnings::percentile;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// A stats query from an external dashboard: the magic, then the token after a space if one is sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsRequest {
+    pub token: Option<String>,
+}
+
+/// Parses a datagram as a stats query, None if it is anything else. Surrounding whitespace is ignored,
+/// so `echo STAT | nc -u` works. Game messages never start with the magic.
+pub fn parse_request(data: &[u8]) -> Option<StatsRequest> {
+    if data.len() > STATS_QUERY_MAX_BYTES {
+        return None;
+    }
+    let rest = data.strip_prefix(STATS_QUERY_MAGIC)?;
+    if rest.first().is_some_and(|byte| !byte.is_ascii_whitespace()) {
+        return None;
+    }
+    let token = std::str::from_utf8(rest).ok()?.trim();
+    Some(StatsRequest { token: (!token.is_empty()).then(|| token.to_string()) })
+}
+
+/// True if the query may be answered: any query when the server has no token, otherwise only one with it
+pub fn is_authorized(request: &StatsRequest, token: Option<&str>) -> bool {
+    token.is_none_or(|token| request.token.as_deref() == Some(token))
+}
+
+/// Why a stats query got no reply. Refused queries are dropped without an answer, so they can't be
+/// used to make the server send anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsRefusal {
+    RateLimited, // The source was answered less than the interval ago
+    WrongToken,  // The server has a token and the query didn't send it
+}
+
+/// Answers each source address at most once per interval, so a spoofed source can't turn the server
+/// into an amplifier. Sources are remembered up to a limit, past it queries from new sources are dropped
+/// until the remembered ones are old enough to forget.
+#[derive(Debug, Clone)]
+pub struct StatsRateLimiter {
+    interval: Duration,
+    max_sources: usize,
+    last_allowed: HashMap<IpAddr, Instant>, // When each source was last let through
+}
+
+/// Implementation of the StatsRateLimiter
+impl StatsRateLimiter {
+    /// Lets a source through at most once per interval, remembering at most max_sources of them
+    pub fn new(interval: Duration, max_sources: usize) -> Self {
+        Self { interval, max_sources, last_allowed: HashMap::new() }
+    }
+
+    /// True if the source may be answered now, which counts as its answer for the interval
+    pub fn allow(&mut self, source: IpAddr, now: Instant) -> bool {
+        if let Some(last) = self.last_allowed.get(&source) {
+            if now.saturating_duration_since(*last) < self.interval {
+                return false;
+            }
+        } else if self.last_allowed.len() >= self.max_sources {
+            let interval = self.interval;
+            self.last_allowed.retain(|_, last| now.saturating_duration_since(*last) < interval);
+            if self.last_allowed.len() >= self.max_sources {
+                return false;
+            }
+        }
+        self.last_allowed.insert(source, now);
+        true
+    }
+
+    /// Number of sources remembered
+    pub fn len(&self) -> usize {
+        self.last_allowed.len()
+    }
+
+    /// True if no source is remembered
+    pub fn is_empty(&self) -> bool {
+        self.last_allowed.is_empty()
+    }
+}
+
+/// How many stats queries were answered and refused since the server started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryCounters {
+    pub answered: u64,
+    pub rate_limited: u64,
+    pub wrong_token: u64,
+}
+
+/// Decides which stats queries are answered: the rate limit comes first, so guessing the token is as slow
+/// as asking for the stats
+#[derive(Debug, Clone)]
+pub struct StatsResponder {
+    token: Option<String>, // Token every query has to send, None answers anyone
+    limiter: StatsRateLimiter,
+    counters: QueryCounters,
+}
+
+/// Implementation of the StatsResponder
+impl StatsResponder {
+    /// Answers queries with the token, or every query without one, at the default rate
+    pub fn new(token: Option<String>) -> Self {
+        Self { token, limiter: StatsRateLimiter::new(STATS_QUERY_INTERVAL, STATS_QUERY_MAX_SOURCES), counters: QueryCounters::default() }
+    }
+
+    /// Checks a query from the source, Ok if it is to be answered
+    pub fn check(&mut self, request: &StatsRequest, source: IpAddr, now: Instant) -> Result<(), StatsRefusal> {
+        if !self.limiter.allow(source, now) {
+            self.counters.rate_limited += 1;
+            return Err(StatsRefusal::RateLimited);
+        }
+        if !is_authorized(request, self.token.as_deref()) {
+            self.counters.wrong_token += 1;
+            return Err(StatsRefusal::WrongToken);
+        }
+        self.counters.answered += 1;
+        Ok(())
+    }
+
+    /// The queries answered and refused so far
+    pub fn counters(&self) -> QueryCounters {
+        self.counters
+    }
+
+    /// Number of sources the rate limiter remembers
+    pub fn tracked_sources(&self) -> usize {
+        self.limiter.len()
+    }
+}
+
+/// Durations of the most recent ticks, for the timing percentiles of the stats reply, and where their time went
+#[derive(Debug, Clone)]
+pub struct TickTimings {
+    window: usize,
+    durations_ms: VecDeque<f32>, // Oldest first, at most window of them
+    profile: ProfileWindow,      // Profiled scopes of the last window ticks
+}
+
+/// Default implementation for the TickTimings
+impl Default for TickTimings {
+    fn default() -> Self {
+        Self::new(STATS_TICK_WINDOW)
+    }
+}
+
+/// Implementation of the TickTimings
+impl TickTimings {
+    /// Keeps the durations of the last window ticks
+    pub fn new(window: usize) -> Self {
+        Self { window: window.max(1), durations_ms: VecDeque::new(), profile: ProfileWindow::new(window) }
+    }
+
+    /// Records how long a tick took, forgetting the oldest one past the window
+    pub fn record(&mut self, duration: Duration) {
+        if self.durations_ms.len() == self.window {
+            self.durations_ms.pop_front();
+        }
+        self.durations_ms.push_back(duration.as_micros() as f32 / 1000.0);
+    }
+
+    /// Records the profiled scopes of a tick, forgetting the oldest tick past the window
+    pub fn record_profile(&mut self, profile: FrameProfile) {
+        self.profile.push(profile);
+    }
+
+    /// Time of the profiled scopes over the ticks in the window
+    pub fn profile(&self) -> ProfileTotals {
+        self.profile.totals()
+    }
+
+    /// Percentiles of the ticks in the window, all zero before the first tick
+    pub fn percentiles(&self) -> TickPercentiles {
+        Self::combined([self])
+    }
+
+    /// Percentiles of the ticks in the windows of several rooms taken together, all zero without any tick
+    pub fn combined<'a>(timings: impl IntoIterator<Item = &'a TickTimings>) -> TickPercentiles {
+        let mut durations: Vec<f32> = timings.into_iter().flat_map(|timings| timings.durations_ms.iter().copied()).collect();
+        let samples = durations.len();
+        let mut at = |fraction| percentile(&mut durations, fraction).unwrap_or_default();
+        TickPercentiles { p50_ms: at(0.5), p95_ms: at(0.95), p99_ms: at(0.99), max_ms: at(1.0), samples }
+    }
+}
+
+/// Tick timing percentiles in milliseconds over the last ticks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TickPercentiles {
+    pub samples: usize,
+    pub p50_ms: f32,
+    pub p95_ms: f32,
+    pub p99_ms: f32,
+    pub max_ms: f32,
+}
+
+/// What the server holds in memory, as far as it can tell cheaply
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryStats {
+    pub resident_bytes: Option<u64>, // Resident set size from the OS, None where it can't be read
+    pub known_clients: usize,        // Clients whose protocol the transport remembers
+    pub resumable_sessions: usize,   // Timed out sessions every room keeps for resuming
+    pub queued_inputs: usize,        // Inputs waiting for the simulated processing delay
+    pub stats_sources: usize,        // Sources the stats rate limiter remembers
+    #[serde(default)]
+    pub receive_sources: usize,      // Sources the receive loop has a token bucket for
+}
+
+/// Players and match of one room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomStats {
+    pub name: String,
+    pub mode: String,
+    pub players: usize,
+    pub match_number: u32,
+    #[serde(default)]
+    pub tick_rate: u32,       // Broadcast ticks per second of the room
+    #[serde(default)]
+    pub tick: TickPercentiles, // Durations of the room's own ticks
+}
+
+/// Snapshot of the server sent to a stats query, serialized as JSON
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerStats {
+    pub version: String,
+    pub uptime_secs: u64,
+    pub tick_rate: u32,
+    pub players: usize,       // Active players of every room
+    pub room_count: usize,
+    pub rooms: Vec<RoomStats>,
+    pub rooms_left_out: usize, // Rooms missing from rooms to keep the reply under the size cap
+    pub tick: TickPercentiles,
+    pub memory: MemoryStats,
+    pub queries: QueryCounters,
+    #[serde(default)]
+    pub receive: ShedCounters,  // Datagrams of the game port handled and dropped by the load shedding
+}
+
+/// The stats as compact JSON of at most max_bytes. Rooms that don't fit are left out from the end and
+/// counted, everything else is small enough to always fit.
+pub fn encode_reply(mut stats: ServerStats, max_bytes: usize) -> Vec<u8> {
+    let rooms = std::mem::take(&mut stats.rooms);
+    stats.rooms_left_out = rooms.len();
+    let mut size = serde_json::to_vec(&stats).map_or(0, |json| json.len());
+    let mut kept = 0;
+    for room in &rooms {
+        // Every room after the first adds a comma, and rooms_left_out never gets longer
+        let room_size = serde_json::to_vec(room).map_or(0, |json| json.len()) + usize::from(kept > 0);
+        if size + room_size > max_bytes {
+            break;
+        }
+        size += room_size;
+        kept += 1;
+    }
+    stats.rooms = rooms.into_iter().take(kept).collect();
+    stats.rooms_left_out -= kept;
+    serde_json::to_vec(&stats).expect("the server stats always serialize")
+}
+
+/// Resident set size of this process, read from /proc on Linux and None elsewhere
+pub fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Tests for the stats query
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    // Helper function to create a source address
+    fn source(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    // Helper function to create stats with the given number of rooms
+    fn stats_with_rooms(count: usize) -> ServerStats {
+        let rooms: Vec<RoomStats> = (0..count)
+            .map(|index| RoomStats { name: format!("room-{}", index), mode: "pellets".to_string(), players: index, match_number: 1, tick_rate: 60, tick: TickPercentiles::default() })
+            .collect();
+        ServerStats { version: "0.1.0".to_string(), tick_rate: 60, room_count: rooms.len(), rooms, ..ServerStats::default() }
+    }
+
+    #[test]
+    fn test_requests_are_the_magic_with_an_optional_token() {
+        assert_eq!(parse_request(b"STAT"), Some(StatsRequest { token: None }));
+        assert_eq!(parse_request(b"STAT\n"), Some(StatsRequest { token: None }));
+        assert_eq!(parse_request(b"STAT s3cret\r\n"), Some(StatsRequest { token: Some("s3cret".to_string()) }));
+
+        // Other datagrams are left for the game protocol
+        assert_eq!(parse_request(b"STATS"), None);
+        assert_eq!(parse_request(b"stat"), None);
+        assert_eq!(parse_request(b"{\"Connect\":null}"), None);
+        assert_eq!(parse_request(&[0, 0, 0, 0]), None);
+        assert_eq!(parse_request(b"STAT \xff\xfe"), None);
+        assert_eq!(parse_request(format!("STAT {}", "x".repeat(STATS_QUERY_MAX_BYTES)).as_bytes()), None);
+    }
+
+    #[test]
+    fn test_token_is_only_checked_when_configured() {
+        let without = StatsRequest { token: None };
+        let with = StatsRequest { token: Some("s3cret".to_string()) };
+        assert!(is_authorized(&without, None) && is_authorized(&with, None));
+        assert!(is_authorized(&with, Some("s3cret")));
+        assert!(!is_authorized(&without, Some("s3cret")));
+        assert!(!is_authorized(&StatsRequest { token: Some("guess".to_string()) }, Some("s3cret")));
+    }
+
+    #[test]
+    fn test_rate_limiter_answers_a_source_once_per_interval() {
+        let mut limiter = StatsRateLimiter::new(Duration::from_secs(1), 2);
+        let start = Instant::now();
+        assert!(limiter.allow(source(1), start));
+        assert!(!limiter.allow(source(1), start + Duration::from_millis(999)));
+        assert!(limiter.allow(source(1), start + Duration::from_secs(1)));
+
+        // Another source has its own budget, a third one doesn't fit while both are recent
+        assert!(limiter.allow(source(2), start));
+        assert!(!limiter.allow(source(3), start + Duration::from_millis(500)));
+        assert_eq!(limiter.len(), 2);
+
+        // Once the first two are old enough they are forgotten to make room
+        assert!(limiter.allow(source(3), start + Duration::from_secs(3)));
+        assert_eq!(limiter.len(), 1);
+    }
+
+    #[test]
+    fn test_responder_limits_before_checking_the_token() {
+        let mut responder = StatsResponder::new(Some("s3cret".to_string()));
+        let now = Instant::now();
+        let guess = StatsRequest { token: Some("guess".to_string()) };
+        let right = StatsRequest { token: Some("s3cret".to_string()) };
+        assert_eq!(responder.check(&guess, source(1), now), Err(StatsRefusal::WrongToken));
+        assert_eq!(responder.check(&right, source(1), now), Err(StatsRefusal::RateLimited));
+        assert_eq!(responder.check(&right, source(2), now), Ok(()));
+        assert_eq!(responder.counters(), QueryCounters { answered: 1, rate_limited: 1, wrong_token: 1 });
+        assert_eq!(responder.tracked_sources(), 2);
+    }
+
+    #[test]
+    fn test_tick_percentiles_over_the_window() {
+        let mut timings = TickTimings::new(100);
+        assert_eq!(timings.percentiles(), TickPercentiles::default());
+        for ms in 1..=200 {
+            timings.record(Duration::from_millis(ms));
+        }
+
+        // Only the last 100 ticks, 101 to 200 ms, are kept
+        let percentiles = timings.percentiles();
+        assert_eq!(percentiles.samples, 100);
+        assert_eq!((percentiles.p50_ms, percentiles.p95_ms, percentiles.p99_ms, percentiles.max_ms), (150.0, 195.0, 199.0, 200.0));
+
+        // Rooms taken together count every tick of each, a slow room shows in the tail
+        let mut fast = TickTimings::new(100);
+        let mut slow = TickTimings::new(100);
+        for _ in 0..95 {
+            fast.record(Duration::from_millis(1));
+        }
+        for _ in 0..5 {
+            slow.record(Duration::from_millis(40));
+        }
+        let combined = TickTimings::combined([&fast, &slow]);
+        assert_eq!((combined.samples, combined.p50_ms, combined.max_ms), (100, 1.0, 40.0));
+        assert_eq!(TickTimings::combined([]), TickPercentiles::default());
+    }
+
+    #[test]
+    fn test_reply_is_json_under_the_size_cap() {
+        let reply = encode_reply(stats_with_rooms(3), 1200);
+        let parsed: ServerStats = serde_json::from_slice(&reply).unwrap();
+        assert_eq!(parsed, stats_with_rooms(3));
+
+        // Rooms that don't fit are left out from the end and counted
+        let reply = encode_reply(stats_with_rooms(100), 1200);
+        assert!(reply.len() <= 1200, "{} bytes", reply.len());
+        let parsed: ServerStats = serde_json::from_slice(&reply).unwrap();
+        assert!(!parsed.rooms.is_empty() && parsed.rooms.len() < 100);
+        assert_eq!(parsed.rooms.len() + parsed.rooms_left_out, 100);
+        assert_eq!(parsed.room_count, 100);
+        assert_eq!(parsed.rooms[..], stats_with_rooms(100).rooms[..parsed.rooms.len()]);
+
+        // Exactly at the cap nothing more is cut than needed
+        let fitting = encode_reply(stats_with_rooms(5), usize::MAX);
+        assert_eq!(encode_reply(stats_with_rooms(5), fitting.len()), fitting);
+    }
+}
@@ -0,0 +1,466 @@
+use crate::analysis::TimedSample;
+use crate::constants::{ANALYSIS_HISTOGRAM_BUCKETS, ANALYSIS_RESERVOIR_SIZE};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Exact aggregates of a stream of values in constant memory, with the variance by Welford's method
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunningStats {
+    count: u64,
+    mean: f64,
+    m2: f64, // Sum of squared differences from the mean
+    min: f64,
+    max: f64,
+}
+
+/// Implementation of the RunningStats
+impl RunningStats {
+    /// Creates empty aggregates
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a value
+    pub fn record(&mut self, value: f64) {
+        self.count += 1;
+        if self.count == 1 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Average of the recorded values, None if nothing was recorded
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.mean)
+    }
+
+    /// Sample variance of the recorded values, None for fewer than two values
+    pub fn variance(&self) -> Option<f64> {
+        (self.count > 1).then(|| self.m2 / (self.count - 1) as f64)
+    }
+
+    /// Sample standard deviation of the recorded values, None for fewer than two values
+    pub fn std_dev(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    /// Smallest recorded value
+    pub fn min(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    /// Largest recorded value
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+}
+
+/// Counts of values in equal buckets from 0 up to a maximum, values above it are counted as overflow.
+/// Percentiles are estimated from it to within one bucket width.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedHistogram {
+    bucket_width: f64,
+    buckets: Vec<u64>,
+    overflow: u64, // Values at or above the top of the last bucket
+    count: u64,
+}
+
+/// Implementation of the FixedHistogram
+impl FixedHistogram {
+    /// Creates a histogram of bucket_count buckets covering 0 to max, negative values go in the first bucket
+    pub fn new(max: f64, bucket_count: usize) -> Self {
+        Self {
+            bucket_width: max / bucket_count as f64,
+            buckets: vec![0; bucket_count],
+            overflow: 0,
+            count: 0,
+        }
+    }
+
+    /// Adds a value to its bucket
+    pub fn record(&mut self, value: f64) {
+        self.count += 1;
+        let index = (value.max(0.0) / self.bucket_width) as usize;
+        match self.buckets.get_mut(index) {
+            Some(bucket) => *bucket += 1,
+            None => self.overflow += 1,
+        }
+    }
+
+    /// Number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Values above the range of the buckets
+    pub fn overflow(&self) -> u64 {
+        self.overflow
+    }
+
+    /// Estimates the value below which the fraction (0 to 1) of the values lie, spreading the values of
+    /// a bucket evenly over its width. A percentile in the overflow is reported as the top of the range.
+    /// None if nothing was recorded.
+    pub fn percentile(&self, fraction: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (fraction.clamp(0.0, 1.0) * self.count as f64).max(1.0);
+        let mut below = 0.0;
+        for (index, &count) in self.buckets.iter().enumerate() {
+            let count = count as f64;
+            if count > 0.0 && below + count >= rank {
+                return Some((index as f64 + (rank - below) / count) * self.bucket_width);
+            }
+            below += count;
+        }
+        Some(self.buckets.len() as f64 * self.bucket_width)
+    }
+}
+
+/// A uniform random sample of fixed size from a stream of any length (Vitter's algorithm R):
+/// after n offers every one of them is kept with the same probability capacity / n.
+#[derive(Debug, Clone)]
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: u64,
+    items: Vec<T>,
+    rng: StdRng,
+}
+
+/// Implementation of the Reservoir
+impl<T> Reservoir<T> {
+    /// Creates an empty reservoir that keeps at most capacity items, the seed makes the sample reproducible
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        Self {
+            capacity,
+            seen: 0,
+            items: Vec::with_capacity(capacity),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Offers an item, it replaces a random kept one with probability capacity / seen once the reservoir is full
+    pub fn offer(&mut self, item: T) {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+            return;
+        }
+        let index = self.rng.random_range(0..self.seen);
+        if index < self.capacity as u64 {
+            self.items[index as usize] = item;
+        }
+    }
+
+    /// The kept items, in no particular order once items were replaced
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Number of items offered so far
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+}
+
+/// How many samples of a series are kept next to the aggregates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    Full,            // Every sample, memory grows with the length of the run. Meant for short tests.
+    Sampled(usize),  // A representative sample of at most this many, memory stays the same however long the run
+}
+
+/// Default implementation for the Capture
+impl Default for Capture {
+    fn default() -> Self {
+        Capture::Sampled(ANALYSIS_RESERVOIR_SIZE)
+    }
+}
+
+/// The samples kept for exports and plots
+#[derive(Debug, Clone)]
+enum KeptSamples {
+    All(Vec<TimedSample>),
+    Reservoir(Box<Reservoir<TimedSample>>), // Boxed, its random generator makes it much larger than a Vec
+}
+
+/// Statistics of one series: exact aggregates and a histogram of the counted samples, and the kept samples
+#[derive(Debug, Clone)]
+pub struct StreamingStats {
+    stats: RunningStats,
+    histogram: FixedHistogram,
+    kept: KeptSamples,
+}
+
+/// Implementation of the StreamingStats
+impl StreamingStats {
+    /// Creates empty statistics with a histogram from 0 to histogram_max
+    pub fn new(capture: Capture, histogram_max: f64) -> Self {
+        let kept = match capture {
+            Capture::Full => KeptSamples::All(Vec::new()),
+            Capture::Sampled(capacity) => KeptSamples::Reservoir(Box::new(Reservoir::new(capacity, rand::random()))),
+        };
+        Self {
+            stats: RunningStats::new(),
+            histogram: FixedHistogram::new(histogram_max, ANALYSIS_HISTOGRAM_BUCKETS),
+            kept,
+        }
+    }
+
+    /// Records a sample in the aggregates and offers it to the kept samples
+    pub fn record(&mut self, sample: TimedSample) {
+        self.stats.record(sample.value as f64);
+        self.histogram.record(sample.value as f64);
+        self.keep(sample);
+    }
+
+    /// Offers a sample to the kept samples only, for samples that don't count such as the warmup
+    pub fn record_uncounted(&mut self, sample: TimedSample) {
+        self.keep(sample);
+    }
+
+    /// Offers a sample to the kept samples
+    fn keep(&mut self, sample: TimedSample) {
+        match &mut self.kept {
+            KeptSamples::All(samples) => samples.push(sample),
+            KeptSamples::Reservoir(reservoir) => reservoir.offer(sample),
+        }
+    }
+
+    /// Exact aggregates of the counted samples
+    pub fn stats(&self) -> &RunningStats {
+        &self.stats
+    }
+
+    /// Estimated percentile (fraction from 0 to 1) of the counted samples, kept within the exact smallest
+    /// and largest value so spreading a bucket doesn't report a percentile above the maximum
+    pub fn percentile(&self, fraction: f64) -> Option<f64> {
+        let estimate = self.histogram.percentile(fraction)?;
+        Some(estimate.clamp(self.stats.min()?, self.stats.max()?))
+    }
+
+    /// The kept samples in time order
+    pub fn samples(&self) -> Vec<TimedSample> {
+        let mut samples = match &self.kept {
+            KeptSamples::All(samples) => samples.clone(),
+            KeptSamples::Reservoir(reservoir) => reservoir.items().to_vec(),
+        };
+        samples.sort_by_key(|sample| sample.elapsed_ms);
+        samples
+    }
+}
+
+/// Tests for the streaming statistics
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to draw normally distributed values with the Box-Muller transform
+    fn normal_values(count: usize, mean: f64, std_dev: f64, seed: u64) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| {
+                let (u1, u2): (f64, f64) = (rng.random_range(f64::EPSILON..1.0), rng.random());
+                mean + std_dev * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_welford_matches_the_two_pass_formulas() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        let mut stats = RunningStats::new();
+        assert_eq!(stats.mean(), None);
+        assert_eq!(stats.variance(), None);
+        for value in values {
+            stats.record(value);
+        }
+
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+        assert_eq!(stats.count(), 8);
+        assert_eq!(stats.mean(), Some(5.0));
+        assert!((stats.variance().unwrap() - variance).abs() < 1e-12);
+        assert_eq!((stats.min(), stats.max()), (Some(2.0), Some(9.0)));
+
+        // A single value has a mean but no sample variance
+        let mut single = RunningStats::new();
+        single.record(-3.0);
+        assert_eq!((single.mean(), single.variance(), single.min()), (Some(-3.0), None, Some(-3.0)));
+    }
+
+    #[test]
+    fn test_welford_stays_accurate_with_a_large_offset() {
+        // The naive sum of squares loses every digit of the variance here
+        let mut stats = RunningStats::new();
+        for i in 0..100_000 {
+            stats.record(1e9 + (i % 2) as f64);
+        }
+        assert!((stats.mean().unwrap() - (1e9 + 0.5)).abs() < 1e-6);
+        assert!((stats.variance().unwrap() - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_welford_on_a_normal_distribution() {
+        let mut stats = RunningStats::new();
+        for value in normal_values(50_000, 40.0, 6.0, 7) {
+            stats.record(value);
+        }
+        assert!((stats.mean().unwrap() - 40.0).abs() < 0.2);
+        assert!((stats.std_dev().unwrap() - 6.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_histogram_percentiles_of_a_uniform_distribution() {
+        let mut histogram = FixedHistogram::new(100.0, 100);
+        assert_eq!(histogram.percentile(0.5), None);
+        for i in 0..10_000 {
+            histogram.record(i as f64 / 100.0);
+        }
+
+        // Within one bucket width of the exact percentiles
+        for (fraction, expected) in [(0.5, 50.0), (0.95, 95.0), (0.99, 99.0), (0.01, 1.0)] {
+            let estimate = histogram.percentile(fraction).unwrap();
+            assert!((estimate - expected).abs() <= 1.0, "p{} was {}", fraction * 100.0, estimate);
+        }
+    }
+
+    #[test]
+    fn test_histogram_percentiles_of_a_normal_distribution() {
+        let mut histogram = FixedHistogram::new(200.0, 400);
+        let mut values = normal_values(50_000, 100.0, 15.0, 11);
+        for value in &values {
+            histogram.record(*value);
+        }
+
+        // The estimates agree with the exact percentiles of the drawn values to within a bucket,
+        // which are themselves close to the theoretical ones
+        values.sort_by(f64::total_cmp);
+        for (fraction, theoretical) in [(0.5, 100.0), (0.95, 100.0 + 1.645 * 15.0)] {
+            let exact = values[(fraction * values.len() as f64) as usize - 1];
+            let estimate = histogram.percentile(fraction).unwrap();
+            assert!((estimate - exact).abs() <= 0.5, "p{}: estimate {} exact {}", fraction * 100.0, estimate, exact);
+            assert!((estimate - theoretical).abs() <= 1.0, "p{}: estimate {} theoretical {}", fraction * 100.0, estimate, theoretical);
+        }
+    }
+
+    #[test]
+    fn test_histogram_clamps_values_outside_the_range() {
+        let mut histogram = FixedHistogram::new(10.0, 10);
+        histogram.record(-5.0);
+        histogram.record(3.5);
+        histogram.record(25.0);
+        histogram.record(40.0);
+
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.overflow(), 2);
+        assert_eq!(histogram.percentile(0.25), Some(1.0));
+        assert_eq!(histogram.percentile(0.5), Some(4.0));
+        assert_eq!(histogram.percentile(1.0), Some(10.0));
+    }
+
+    #[test]
+    fn test_reservoir_keeps_everything_until_full() {
+        let mut reservoir = Reservoir::new(5, 1);
+        for i in 0..3 {
+            reservoir.offer(i);
+        }
+        assert_eq!(reservoir.items(), &[0, 1, 2]);
+
+        for i in 3..1000 {
+            reservoir.offer(i);
+        }
+        assert_eq!(reservoir.items().len(), 5);
+        assert_eq!(reservoir.seen(), 1000);
+    }
+
+    #[test]
+    fn test_reservoir_keeps_every_item_with_equal_probability() {
+        // Ten items into a reservoir of three, each should be kept in about 30% of the runs
+        let runs = 20_000;
+        let mut kept = [0u32; 10];
+        for seed in 0..runs {
+            let mut reservoir = Reservoir::new(3, seed);
+            for i in 0..10 {
+                reservoir.offer(i);
+            }
+            for &i in reservoir.items() {
+                kept[i] += 1;
+            }
+        }
+        // Binomial standard deviation is about 65 runs, allow five of them
+        for (item, count) in kept.iter().enumerate() {
+            assert!((*count as i64 - 6_000).abs() < 325, "item {} kept {} times", item, count);
+        }
+    }
+
+    #[test]
+    fn test_reservoir_represents_a_long_stream() {
+        let mut reservoir = Reservoir::new(1_000, 3);
+        for i in 0..100_000u32 {
+            reservoir.offer(i);
+        }
+
+        // Every tenth of the stream is about a tenth of the sample, early items aren't favoured
+        let mut deciles = [0u32; 10];
+        for &i in reservoir.items() {
+            deciles[(i / 10_000) as usize] += 1;
+        }
+        for (decile, count) in deciles.iter().enumerate() {
+            assert!((60..=140).contains(count), "decile {} has {} items", decile, count);
+        }
+        let mean = reservoir.items().iter().map(|&i| i as f64).sum::<f64>() / 1_000.0;
+        assert!((mean - 50_000.0).abs() < 4_000.0);
+    }
+
+    #[test]
+    fn test_streaming_stats_memory_stays_bounded() {
+        let mut stats = StreamingStats::new(Capture::Sampled(100), 50.0);
+        for i in 0..10_000u64 {
+            stats.record(TimedSample { elapsed_ms: i, value: (i % 50) as f32 });
+        }
+        stats.record_uncounted(TimedSample { elapsed_ms: 10_000, value: 1000.0 });
+
+        // The aggregates are exact, only the kept samples are a sample
+        assert_eq!(stats.stats().count(), 10_000);
+        assert_eq!(stats.stats().max(), Some(49.0));
+        assert!((stats.stats().mean().unwrap() - 24.5).abs() < 1e-9);
+        let samples = stats.samples();
+        assert_eq!(samples.len(), 100);
+        assert!(samples.windows(2).all(|pair| pair[0].elapsed_ms <= pair[1].elapsed_ms));
+
+        // Full capture keeps every sample, counted or not
+        let mut full = StreamingStats::new(Capture::Full, 50.0);
+        full.record_uncounted(TimedSample { elapsed_ms: 0, value: 1.0 });
+        full.record(TimedSample { elapsed_ms: 5, value: 2.0 });
+        assert_eq!(full.samples().len(), 2);
+        assert_eq!(full.stats().count(), 1);
+    }
+
+    #[test]
+    fn test_percentiles_stay_within_the_recorded_values() {
+        // Only zeros, spreading the first bucket would report more than the maximum
+        let mut zeros = StreamingStats::new(Capture::Full, 200.0);
+        for i in 0..100 {
+            zeros.record(TimedSample { elapsed_ms: i, value: 0.0 });
+        }
+        assert_eq!(zeros.percentile(0.95), Some(0.0));
+
+        let mut constant = StreamingStats::new(Capture::Full, 200.0);
+        constant.record(TimedSample { elapsed_ms: 0, value: 25.0 });
+        assert_eq!(constant.percentile(0.5), Some(25.0));
+        assert_eq!(StreamingStats::new(Capture::Full, 200.0).percentile(0.5), None);
+    }
+}
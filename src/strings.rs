@@ -0,0 +1,304 @@
+use std::fmt::Display;
+
+/// Language the user interface is shown in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    English,
+    Norwegian, // Bokmål
+}
+
+/// Implementation of the Language
+impl Language {
+    /// Every language, in the order the language key cycles through them
+    pub const ALL: [Language; 2] = [Language::English, Language::Norwegian];
+
+    /// Code of the language, for the --lang flag and the settings file
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Norwegian => "nb",
+        }
+    }
+
+    /// Name of the language in itself
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Norwegian => "Norsk",
+        }
+    }
+
+    /// Parses a language code or name, ignoring case and surrounding whitespace
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "en" | "english" => Some(Language::English),
+            "nb" | "no" | "norwegian" | "norsk" => Some(Language::Norwegian),
+            _ => None,
+        }
+    }
+
+    /// The language after this one in ALL, the last one goes back to the first
+    pub fn next(self) -> Self {
+        let index = Language::ALL.iter().position(|language| *language == self).unwrap_or(0);
+        Language::ALL[(index + 1) % Language::ALL.len()]
+    }
+}
+
+/// Every user-facing string of the toolbar, the notices and banners, the menu and the results screen.
+/// A {} in a string is filled in by Strings::format, in order, so every language has as many as English.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Text {
+    Movement,          // Movement keys in the toolbar
+    NetworkConditions, // Simulated network conditions with the keys that adjust them
+    DropConnection,
+    Reconnect,
+    Test,
+    LinkStatus,        // Input send rate and expected prediction error
+    EveryInput,        // Send rate when every input is sent right away
+    ReducedEffects,    // Note while optional layers are shed
+    NamePrompt,
+    PasswordPrompt,
+    LookingForRooms,
+    Rooms,
+    PlayerCountOne,
+    PlayerCountMany,
+    Connecting,
+    Disconnected,
+    DisconnectedKeys,
+    Crashed,
+    CrashedKeys,
+    Spectating,
+    FreeCamera,
+    Following,
+    MatchOver,
+    SlowMotion,
+    ResultsKeys,
+    WarningRtt,
+    WarningLoss,
+    WarningPredictionError,
+    WarningStarvation,
+    PredictionOff,     // Banner heading while the watchdog has turned the prediction off
+    PredictionOffKeys,
+}
+
+/// Implementation of the Text
+impl Text {
+    /// Every string, for checking the tables
+    pub const ALL: [Text; 31] = [
+        Text::Movement,
+        Text::NetworkConditions,
+        Text::DropConnection,
+        Text::Reconnect,
+        Text::Test,
+        Text::LinkStatus,
+        Text::EveryInput,
+        Text::ReducedEffects,
+        Text::NamePrompt,
+        Text::PasswordPrompt,
+        Text::LookingForRooms,
+        Text::Rooms,
+        Text::PlayerCountOne,
+        Text::PlayerCountMany,
+        Text::Connecting,
+        Text::Disconnected,
+        Text::DisconnectedKeys,
+        Text::Crashed,
+        Text::CrashedKeys,
+        Text::Spectating,
+        Text::FreeCamera,
+        Text::Following,
+        Text::MatchOver,
+        Text::SlowMotion,
+        Text::ResultsKeys,
+        Text::WarningRtt,
+        Text::WarningLoss,
+        Text::WarningPredictionError,
+        Text::WarningStarvation,
+        Text::PredictionOff,
+        Text::PredictionOffKeys,
+    ];
+
+    /// The string in the language
+    pub fn in_language(self, language: Language) -> &'static str {
+        match language {
+            Language::English => self.english(),
+            Language::Norwegian => self.norwegian(),
+        }
+    }
+
+    /// The English string, the default
+    fn english(self) -> &'static str {
+        match self {
+            Text::Movement => "Movement {}",
+            Text::NetworkConditions => "Delay: {} ms {}   Jitter: {} ms {}   Packet Loss: {}% {}   Duplicates: {}% {}",
+            Text::DropConnection => "Drop connection {}",
+            Text::Reconnect => "Reconnect {}",
+            Text::Test => "Test {}",
+            Text::LinkStatus => "Inputs: {}   Expected error: {} px",
+            Text::EveryInput => "every input",
+            Text::ReducedEffects => "Reduced effects",
+            Text::NamePrompt => "Enter a name and press Enter to connect",
+            Text::PasswordPrompt => "{} needs a password, press Enter to join or Esc to go back",
+            Text::LookingForRooms => "Looking for rooms...",
+            Text::Rooms => "Rooms [Up/Down]",
+            Text::PlayerCountOne => "{} player",
+            Text::PlayerCountMany => "{} players",
+            Text::Connecting => "Connecting...   [Esc] Menu",
+            Text::Disconnected => "Disconnected",
+            Text::DisconnectedKeys => "[R] Reconnect   [Esc] Menu",
+            Text::Crashed => "The client crashed",
+            Text::CrashedKeys => "[R] Reset and reconnect   [Esc] Quit",
+            Text::Spectating => "Spectating, {}   [Tab] Next player   [F] Free camera   [G] Play",
+            Text::FreeCamera => "Free camera",
+            Text::Following => "Following",
+            Text::MatchOver => "Match over",
+            Text::SlowMotion => "Slow motion [F6]   {} s behind",
+            Text::ResultsKeys => "[Esc] Back   [T] Run again",
+            Text::WarningRtt => "High round trip time",
+            Text::WarningLoss => "Packet loss",
+            Text::WarningPredictionError => "Prediction error",
+            Text::WarningStarvation => "Interpolation starving",
+            Text::PredictionOff => "Prediction off",
+            Text::PredictionOffKeys => "It kept going wrong, your player follows the server   [P] Turn it back on",
+        }
+    }
+
+    /// The Norwegian string
+    fn norwegian(self) -> &'static str {
+        match self {
+            Text::Movement => "Bevegelse {}",
+            Text::NetworkConditions => "Forsinkelse: {} ms {}   Jitter: {} ms {}   Pakketap: {}% {}   Duplikater: {}% {}",
+            Text::DropConnection => "Bryt forbindelsen {}",
+            Text::Reconnect => "Koble til igjen {}",
+            Text::Test => "Test {}",
+            Text::LinkStatus => "Inndata: {}   Forventet avvik: {} px",
+            Text::EveryInput => "hver inndata",
+            Text::ReducedEffects => "Reduserte effekter",
+            Text::NamePrompt => "Skriv inn et navn og trykk Enter for å koble til",
+            Text::PasswordPrompt => "{} krever passord, trykk Enter for å bli med eller Esc for å gå tilbake",
+            Text::LookingForRooms => "Leter etter rom...",
+            Text::Rooms => "Rom [Opp/Ned]",
+            Text::PlayerCountOne => "{} spiller",
+            Text::PlayerCountMany => "{} spillere",
+            Text::Connecting => "Kobler til...   [Esc] Meny",
+            Text::Disconnected => "Frakoblet",
+            Text::DisconnectedKeys => "[R] Koble til igjen   [Esc] Meny",
+            Text::Crashed => "Klienten krasjet",
+            Text::CrashedKeys => "[R] Tilbakestill og koble til igjen   [Esc] Avslutt",
+            Text::Spectating => "Ser på, {}   [Tab] Neste spiller   [F] Fritt kamera   [G] Spill",
+            Text::FreeCamera => "fritt kamera",
+            Text::Following => "følger",
+            Text::MatchOver => "Kampen er over",
+            Text::SlowMotion => "Sakte film [F6]   {} s bak",
+            Text::ResultsKeys => "[Esc] Tilbake   [T] Kjør igjen",
+            Text::WarningRtt => "Høy rundetid",
+            Text::WarningLoss => "Pakketap",
+            Text::WarningPredictionError => "Prediksjonsavvik",
+            Text::WarningStarvation => "Interpolasjonen går tom",
+            Text::PredictionOff => "Prediksjon av",
+            Text::PredictionOffKeys => "Den bommet gang på gang, spilleren din følger serveren   [P] Slå den på igjen",
+        }
+    }
+}
+
+/// The strings of the language the interface is shown in. The renderer keeps one, so switching the
+/// language shows up in the next frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Strings {
+    language: Language,
+}
+
+/// Implementation of the Strings
+impl Strings {
+    /// Strings of the language
+    pub fn new(language: Language) -> Self {
+        Self { language }
+    }
+
+    /// The language the strings are in
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Switches to another language
+    pub fn set_language(&mut self, language: Language) {
+        self.language = language;
+    }
+
+    /// A string without placeholders
+    pub fn get(&self, text: Text) -> &'static str {
+        text.in_language(self.language)
+    }
+
+    /// A string with its placeholders filled in by the arguments, in order. Placeholders without an
+    /// argument are left empty.
+    pub fn format(&self, text: Text, args: &[&dyn Display]) -> String {
+        let mut parts = self.get(text).split("{}");
+        let mut formatted = parts.next().unwrap_or_default().to_string();
+        for (index, part) in parts.enumerate() {
+            if let Some(arg) = args.get(index) {
+                formatted.push_str(&arg.to_string());
+            }
+            formatted.push_str(part);
+        }
+        formatted
+    }
+
+    /// A count with the singular string for one and the plural one otherwise, like "1 player" and "3 players"
+    pub fn count(&self, count: u64, one: Text, many: Text) -> String {
+        self.format(if count == 1 { one } else { many }, &[&count])
+    }
+}
+
+/// Number of {} placeholders in a string
+pub fn placeholders(text: &str) -> usize {
+    text.matches("{}").count()
+}
+
+/// Tests for the string tables
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_string_exists_with_the_same_placeholders_in_every_language() {
+        for text in Text::ALL {
+            let english = text.in_language(Language::English);
+            for language in Language::ALL {
+                let translated = text.in_language(language);
+                assert!(!translated.trim().is_empty(), "{:?} is empty in {:?}", text, language);
+                assert_eq!(placeholders(translated), placeholders(english), "{:?} in {:?}: {}", text, language, translated);
+                assert!(!translated.replace("{}", "").contains(['{', '}']), "{:?} in {:?} has a stray brace", text, language);
+            }
+        }
+    }
+
+    #[test]
+    fn test_placeholders_are_filled_in_order() {
+        let strings = Strings::default();
+        assert_eq!(strings.format(Text::LinkStatus, &[&"20 Hz", &3]), "Inputs: 20 Hz   Expected error: 3 px");
+        assert_eq!(strings.format(Text::Movement, &[]), "Movement ");
+        assert_eq!(strings.get(Text::MatchOver), "Match over");
+    }
+
+    #[test]
+    fn test_counts_pick_the_plural() {
+        let mut strings = Strings::new(Language::English);
+        let players = |strings: &Strings, count| strings.count(count, Text::PlayerCountOne, Text::PlayerCountMany);
+        assert_eq!([0, 1, 2].map(|count| players(&strings, count)), ["0 players", "1 player", "2 players"]);
+        strings.set_language(Language::Norwegian);
+        assert_eq!([0, 1, 2].map(|count| players(&strings, count)), ["0 spillere", "1 spiller", "2 spillere"]);
+    }
+
+    #[test]
+    fn test_languages_parse_and_cycle() {
+        for language in Language::ALL {
+            assert_eq!(Language::parse(language.code()), Some(language));
+        }
+        assert_eq!(Language::parse(" Norsk\n"), Some(Language::Norwegian));
+        assert_eq!(Language::parse("de"), None);
+        assert_eq!(Language::English.next(), Language::Norwegian);
+        assert_eq!(Language::Norwegian.next(), Language::English);
+    }
+}
@@ -0,0 +1,271 @@
+/// Editing keys a text field reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextFieldKey {
+    Left,
+    Right,
+    Home,
+    End,
+    Backspace,
+    Delete,
+    Submit, // Enter
+    Cancel, // Escape
+}
+
+/// What happened to the text field after a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextFieldEvent {
+    Submitted(String), // The text that was entered
+    Cancelled,         // The edit was undone
+}
+
+/// Editing state of a single line text field, without any rendering.
+/// The caret and the length cap count chars, so multi-byte chars are never split.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextFieldState {
+    text: String,
+    caret: usize, // Caret position in chars, 0 is before the first char
+    max_chars: usize,
+    focused: bool,
+    committed: String, // Text of the last submit, restored on cancel
+}
+
+/// Implementation of the TextFieldState
+impl TextFieldState {
+    /// Creates an empty, unfocused text field that holds at most max_chars chars
+    pub fn new(max_chars: usize) -> Self {
+        Self {
+            text: String::new(),
+            caret: 0,
+            max_chars,
+            focused: false,
+            committed: String::new(),
+        }
+    }
+
+    /// Creates a text field holding the given text, cut to max_chars, with the caret at the end
+    pub fn with_text(text: &str, max_chars: usize) -> Self {
+        let mut field = Self::new(max_chars);
+        field.set_text(text);
+        field.committed = field.text.clone();
+        field
+    }
+
+    /// The current text
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Caret position in chars
+    pub fn caret(&self) -> usize {
+        self.caret
+    }
+
+    /// Number of chars in the text
+    pub fn char_count(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    /// Maximum number of chars the field holds
+    pub fn max_chars(&self) -> usize {
+        self.max_chars
+    }
+
+    /// Checks if the field receives typed chars and keys
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    /// Gives the field focus, so it receives typed chars and keys
+    pub fn focus(&mut self) {
+        self.focused = true;
+    }
+
+    /// Takes the focus away from the field
+    pub fn blur(&mut self) {
+        self.focused = false;
+    }
+
+    /// Replaces the text, cut to max_chars, and moves the caret to the end
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.chars().take(self.max_chars).collect();
+        self.caret = self.char_count();
+    }
+
+    /// Empties the field
+    pub fn clear(&mut self) {
+        self.set_text("");
+    }
+
+    /// The text before the caret, used to place the caret when drawing
+    pub fn text_before_caret(&self) -> &str {
+        &self.text[..self.byte_index(self.caret)]
+    }
+
+    /// Inserts a typed char at the caret. Control chars and chars past the length cap are ignored.
+    /// Returns whether the char was inserted.
+    pub fn insert_char(&mut self, c: char) -> bool {
+        if !self.focused || c.is_control() || self.char_count() >= self.max_chars {
+            return false;
+        }
+        let index = self.byte_index(self.caret);
+        self.text.insert(index, c);
+        self.caret += 1;
+        true
+    }
+
+    /// Handles an editing key, returning an event for submit and cancel
+    pub fn handle_key(&mut self, key: TextFieldKey) -> Option<TextFieldEvent> {
+        if !self.focused {
+            return None;
+        }
+        match key {
+            TextFieldKey::Left => self.caret = self.caret.saturating_sub(1),
+            TextFieldKey::Right => self.caret = (self.caret + 1).min(self.char_count()),
+            TextFieldKey::Home => self.caret = 0,
+            TextFieldKey::End => self.caret = self.char_count(),
+            TextFieldKey::Backspace => {
+                if self.caret > 0 {
+                    self.caret -= 1;
+                    self.remove_at_caret();
+                }
+            }
+            TextFieldKey::Delete => self.remove_at_caret(),
+            TextFieldKey::Submit => {
+                self.committed = self.text.clone();
+                return Some(TextFieldEvent::Submitted(self.text.clone()));
+            }
+            TextFieldKey::Cancel => {
+                let committed = self.committed.clone();
+                self.set_text(&committed);
+                return Some(TextFieldEvent::Cancelled);
+            }
+        }
+        None
+    }
+
+    /// Removes the char right after the caret, if there is one
+    fn remove_at_caret(&mut self) {
+        if self.caret < self.char_count() {
+            self.text.remove(self.byte_index(self.caret));
+        }
+    }
+
+    /// Byte offset of a char position in the text
+    fn byte_index(&self, char_index: usize) -> usize {
+        self.text.char_indices().nth(char_index).map_or(self.text.len(), |(index, _)| index)
+    }
+}
+
+/// Tests for the text field editing
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to type a string into a field
+    fn type_text(field: &mut TextFieldState, text: &str) {
+        for c in text.chars() {
+            field.insert_char(c);
+        }
+    }
+
+    // Helper function to create a focused, empty field
+    fn focused(max_chars: usize) -> TextFieldState {
+        let mut field = TextFieldState::new(max_chars);
+        field.focus();
+        field
+    }
+
+    #[test]
+    fn test_typing_and_caret_movement() {
+        let mut field = focused(20);
+        type_text(&mut field, "helo");
+        field.handle_key(TextFieldKey::Left);
+        type_text(&mut field, "l");
+        assert_eq!(field.text(), "hello");
+        assert_eq!(field.caret(), 4);
+
+        field.handle_key(TextFieldKey::Home);
+        type_text(&mut field, ">");
+        field.handle_key(TextFieldKey::End);
+        field.handle_key(TextFieldKey::Right); // Stays at the end
+        type_text(&mut field, "!");
+        assert_eq!(field.text(), ">hello!");
+        assert_eq!(field.text_before_caret(), ">hello!");
+    }
+
+    #[test]
+    fn test_backspace_and_delete() {
+        let mut field = focused(20);
+        type_text(&mut field, "abcd");
+
+        field.handle_key(TextFieldKey::Backspace);
+        assert_eq!(field.text(), "abc");
+
+        field.handle_key(TextFieldKey::Home);
+        field.handle_key(TextFieldKey::Backspace); // Nothing before the caret
+        field.handle_key(TextFieldKey::Delete);
+        assert_eq!(field.text(), "bc");
+        assert_eq!(field.caret(), 0);
+
+        field.handle_key(TextFieldKey::End);
+        field.handle_key(TextFieldKey::Delete); // Nothing after the caret
+        assert_eq!(field.text(), "bc");
+    }
+
+    #[test]
+    fn test_multi_byte_chars_are_never_split() {
+        let mut field = focused(20);
+        type_text(&mut field, "aé漢🎮");
+        assert_eq!(field.char_count(), 4);
+
+        field.handle_key(TextFieldKey::Left);
+        field.handle_key(TextFieldKey::Left);
+        assert_eq!(field.text_before_caret(), "aé");
+
+        field.handle_key(TextFieldKey::Backspace);
+        assert_eq!(field.text(), "a漢🎮");
+        field.handle_key(TextFieldKey::Delete);
+        assert_eq!(field.text(), "a🎮");
+
+        type_text(&mut field, "ø");
+        assert_eq!(field.text(), "aø🎮");
+    }
+
+    #[test]
+    fn test_length_cap_counts_chars() {
+        let mut field = focused(3);
+        type_text(&mut field, "🎮🎮🎮🎮");
+        assert_eq!(field.text(), "🎮🎮🎮"); // 12 bytes, but only 3 chars
+
+        let long = TextFieldState::with_text("ééééé", 4);
+        assert_eq!(long.text(), "éééé");
+        assert_eq!(long.caret(), 4);
+    }
+
+    #[test]
+    fn test_control_chars_and_unfocused_input_are_ignored() {
+        let mut field = TextFieldState::new(10);
+        assert!(!field.insert_char('a'));
+        assert_eq!(field.handle_key(TextFieldKey::Submit), None);
+
+        field.focus();
+        assert!(!field.insert_char('\n'));
+        assert!(!field.insert_char('\u{8}'));
+        assert!(field.insert_char('a'));
+        assert_eq!(field.text(), "a");
+    }
+
+    #[test]
+    fn test_submit_and_cancel() {
+        let mut field = TextFieldState::with_text("Ann", 10);
+        field.focus();
+        type_text(&mut field, "a");
+        assert_eq!(field.handle_key(TextFieldKey::Submit), Some(TextFieldEvent::Submitted("Anna".to_string())));
+
+        // Cancel goes back to the last submitted text
+        type_text(&mut field, "bel");
+        assert_eq!(field.handle_key(TextFieldKey::Cancel), Some(TextFieldEvent::Cancelled));
+        assert_eq!(field.text(), "Anna");
+        assert_eq!(field.caret(), 4);
+    }
+}
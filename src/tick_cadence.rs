@@ -0,0 +1,185 @@
+use crate::constants::{TICK_CADENCE_MIN_TICKS, TICK_CADENCE_TOLERANCE, TICK_CADENCE_WINDOW_MS};
+
+use std::collections::VecDeque;
+
+/// Observed time per server tick compared with the tick interval the server reports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CadenceCheck {
+    pub reported_ms: f64, // Tick interval the snapshots carry
+    pub observed_ms: f64, // Local time between snapshots per tick they advanced
+}
+
+/// Implementation of the CadenceCheck
+impl CadenceCheck {
+    /// Observed interval as a share of the reported one, 1.0 when the server keeps its rate
+    pub fn ratio(&self) -> f64 {
+        self.observed_ms / self.reported_ms
+    }
+
+    /// How the observed interval compares with the reported one
+    pub fn status(&self) -> CadenceStatus {
+        CadenceStatus::of_ratio(self.ratio())
+    }
+
+    /// True if the observed interval is off the reported one by more than TICK_CADENCE_TOLERANCE in either
+    /// direction, so the tick compensation of the input lag is off too
+    pub fn disagrees(&self) -> bool {
+        self.status() != CadenceStatus::Steady
+    }
+
+    /// True if ticks take longer than reported by more than the tolerance, the server can't keep its rate
+    pub fn overloaded(&self) -> bool {
+        self.status() == CadenceStatus::Overloaded
+    }
+}
+
+/// Verdict on the observed tick interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CadenceStatus {
+    Steady,     // Within TICK_CADENCE_TOLERANCE of the reported interval
+    Overloaded, // Slower than reported, the server can't tick at its rate
+    Faster,     // Faster than reported, the reported interval is wrong
+}
+
+/// Implementation of the CadenceStatus
+impl CadenceStatus {
+    /// Verdict for an observed interval given as a share of the reported one
+    pub fn of_ratio(ratio: f64) -> Self {
+        if ratio > 1.0 + TICK_CADENCE_TOLERANCE {
+            CadenceStatus::Overloaded
+        } else if ratio < 1.0 - TICK_CADENCE_TOLERANCE {
+            CadenceStatus::Faster
+        } else {
+            CadenceStatus::Steady
+        }
+    }
+
+    /// Describes the verdict for reports
+    pub fn describe(self) -> &'static str {
+        match self {
+            CadenceStatus::Steady => "steady",
+            CadenceStatus::Overloaded => "server overloaded",
+            CadenceStatus::Faster => "faster than reported",
+        }
+    }
+}
+
+/// Measures how fast the server really ticks from when snapshots arrive. Time is divided by the ticks
+/// the snapshot ids advanced, so lost snapshots don't count as slow ticks, and the window spans
+/// TICK_CADENCE_WINDOW_MS so the network jitter of its first and last snapshot is spread thin.
+#[derive(Debug, Clone, Default)]
+pub struct TickCadence {
+    arrivals: VecDeque<(u64, f64)>, // Snapshot id and local arrival time in milliseconds, oldest first
+    reported_ms: Option<u32>,       // Tick interval of the snapshots in the window
+}
+
+/// Implementation of the TickCadence
+impl TickCadence {
+    /// Creates a monitor that hasn't seen a snapshot yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a snapshot that arrived at the given local time. Snapshots no newer than the newest one are
+    /// skipped, a changed tick interval starts over since the ids belong to another timeline.
+    pub fn observe(&mut self, snapshot_id: u64, tick_interval_ms: u32, received_at_ms: f64) {
+        if self.reported_ms != Some(tick_interval_ms) {
+            self.arrivals.clear();
+            self.reported_ms = Some(tick_interval_ms);
+        }
+        if self.arrivals.back().is_some_and(|(newest, _)| snapshot_id <= *newest) {
+            return;
+        }
+        self.arrivals.push_back((snapshot_id, received_at_ms));
+        while self.arrivals.front().is_some_and(|(_, arrived)| received_at_ms - arrived > TICK_CADENCE_WINDOW_MS) {
+            self.arrivals.pop_front();
+        }
+    }
+
+    /// The cadence over the window, None until it spans TICK_CADENCE_MIN_TICKS or without a reported interval
+    pub fn check(&self) -> Option<CadenceCheck> {
+        let reported_ms = self.reported_ms.filter(|interval| *interval > 0)?;
+        let ((first_id, first_at), (last_id, last_at)) = (self.arrivals.front()?, self.arrivals.back()?);
+        let ticks = last_id - first_id;
+        if ticks < TICK_CADENCE_MIN_TICKS {
+            return None;
+        }
+        Some(CadenceCheck { reported_ms: reported_ms as f64, observed_ms: (last_at - first_at) / ticks as f64 })
+    }
+
+    /// Forgets the snapshots, for a room whose snapshot ids start over
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Tests for the tick cadence
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to feed snapshots of every tick at the given real tick length, with the delay of each one
+    fn feed(cadence: &mut TickCadence, ticks: u64, tick_ms: f64, reported_ms: u32, delay_ms: impl Fn(u64) -> f64) {
+        for id in 1..=ticks {
+            cadence.observe(id, reported_ms, id as f64 * tick_ms + delay_ms(id));
+        }
+    }
+
+    #[test]
+    fn test_steady_server_agrees_despite_jitter_and_loss() {
+        // 20 Hz with up to 40 ms of jitter either way and every third snapshot lost
+        let mut cadence = TickCadence::new();
+        for id in (1..=100).filter(|id| id % 3 != 0) {
+            let jitter = if id % 2 == 0 { 40.0 } else { -40.0 };
+            cadence.observe(id, 50, id as f64 * 50.0 + 100.0 + jitter);
+        }
+        let check = cadence.check().unwrap();
+        assert_eq!(check.reported_ms, 50.0);
+        assert!((check.ratio() - 1.0).abs() < 0.05, "{:?}", check);
+        assert!(!check.disagrees() && !check.overloaded());
+    }
+
+    #[test]
+    fn test_overloaded_server_is_flagged() {
+        // The server says 60 Hz but its ticks take 25 ms
+        let mut cadence = TickCadence::new();
+        feed(&mut cadence, 200, 25.0, 16, |id| (id % 5) as f64 * 3.0);
+        let check = cadence.check().unwrap();
+        assert!((check.observed_ms - 25.0).abs() < 0.5, "{:?}", check);
+        assert!(check.disagrees() && check.overloaded());
+
+        // Ticks arriving faster than reported disagree, but aren't an overload
+        let fast = CadenceCheck { reported_ms: 50.0, observed_ms: 35.0 };
+        assert!(fast.disagrees() && !fast.overloaded());
+        assert_eq!(fast.status().describe(), "faster than reported");
+        assert!(!CadenceCheck { reported_ms: 50.0, observed_ms: 59.0 }.disagrees());
+    }
+
+    #[test]
+    fn test_window_follows_the_recent_cadence() {
+        // A server that was slow for a while and recovered is judged by the last window only
+        let mut cadence = TickCadence::new();
+        feed(&mut cadence, 100, 40.0, 16, |_| 0.0);
+        assert!(cadence.check().unwrap().overloaded());
+        let resumed_at = 100.0 * 40.0;
+        for id in 101..=400 {
+            cadence.observe(id, 16, resumed_at + (id - 100) as f64 * 16.0);
+        }
+        assert!(!cadence.check().unwrap().disagrees());
+    }
+
+    #[test]
+    fn test_too_few_ticks_or_a_new_interval_give_no_check() {
+        let mut cadence = TickCadence::new();
+        feed(&mut cadence, TICK_CADENCE_MIN_TICKS, 16.0, 16, |_| 0.0);
+        assert_eq!(cadence.check(), None);
+        cadence.observe(TICK_CADENCE_MIN_TICKS + 1, 16, (TICK_CADENCE_MIN_TICKS + 1) as f64 * 16.0);
+        assert!(cadence.check().is_some());
+
+        // Duplicates and late snapshots don't count, a new tick interval starts over
+        cadence.observe(3, 16, 5_000.0);
+        assert_eq!(cadence.check().unwrap().ratio(), 1.0);
+        cadence.observe(TICK_CADENCE_MIN_TICKS + 2, 50, 400.0);
+        assert_eq!(cadence.check(), None);
+    }
+}
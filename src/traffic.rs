@@ -0,0 +1,231 @@
+use crate::codec::Incoming;
+use crate::constants::TRAFFIC_RATE_WINDOW;
+use crate::types::ClientMessage;
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// What a datagram carries, to tell which kind of message uses the bandwidth
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficCategory {
+    Snapshot, // World state from the server
+    Input,    // Player inputs, single or batched
+    Ping,     // Pings and their echoes, in both directions
+    Control,  // Connects, welcomes, room lists and everything else
+}
+
+/// Implementation of the TrafficCategory
+impl TrafficCategory {
+    pub const ALL: [TrafficCategory; 4] = [TrafficCategory::Snapshot, TrafficCategory::Input, TrafficCategory::Ping, TrafficCategory::Control];
+
+    /// Name of the category in the overlay and the report
+    pub fn name(self) -> &'static str {
+        match self {
+            TrafficCategory::Snapshot => "Snapshot",
+            TrafficCategory::Input => "Input",
+            TrafficCategory::Ping => "Ping",
+            TrafficCategory::Control => "Control",
+        }
+    }
+
+    /// Category of a message, a message sent with the session token counts as the message it wraps
+    pub fn of_message(message: &ClientMessage) -> Self {
+        match message {
+            ClientMessage::Input(_) | ClientMessage::InputBatch(_) => TrafficCategory::Input,
+            ClientMessage::Ping(_) | ClientMessage::Pong(_) | ClientMessage::ServerPing(_) | ClientMessage::ServerPong(_) => TrafficCategory::Ping,
+            ClientMessage::WithSession { message, .. } => Self::of_message(message),
+            _ => TrafficCategory::Control,
+        }
+    }
+
+    /// Category of a decoded datagram from the server
+    pub fn of_incoming(incoming: &Incoming) -> Self {
+        match incoming {
+            Incoming::Snapshot(_) => TrafficCategory::Snapshot,
+            Incoming::Message(message) => Self::of_message(message),
+        }
+    }
+}
+
+/// Direction of the traffic as seen from the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficDirection {
+    Up,   // Sent to the server
+    Down, // Received from the server
+}
+
+/// Bytes and datagrams of one category in one direction
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrafficCount {
+    pub bytes: u64,
+    pub packets: u64,
+}
+
+/// Counts of every category in both directions, indexed by direction and category
+type TrafficCounts = [[TrafficCount; TrafficCategory::ALL.len()]; 2];
+
+/// Bytes and datagrams per second of one category, averaged over the rate window
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrafficRate {
+    pub category: TrafficCategory,
+    pub up_bytes_per_s: f64,
+    pub down_bytes_per_s: f64,
+    pub up_packets_per_s: f64,
+    pub down_packets_per_s: f64,
+}
+
+/// Implementation of the TrafficRate
+impl TrafficRate {
+    /// A category without any traffic
+    fn idle(category: TrafficCategory) -> Self {
+        Self { category, up_bytes_per_s: 0.0, down_bytes_per_s: 0.0, up_packets_per_s: 0.0, down_packets_per_s: 0.0 }
+    }
+
+    /// Bytes per second in both directions together
+    pub fn total_bytes_per_s(&self) -> f64 {
+        self.up_bytes_per_s + self.down_bytes_per_s
+    }
+}
+
+/// Traffic per message category in both directions: totals since the first datagram, and a ring of
+/// per second buckets for the rates over the last TRAFFIC_RATE_WINDOW seconds
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrafficMeter {
+    started: Option<Instant>, // Time of the first recorded datagram, seconds are counted from here
+    totals: TrafficCounts,
+    buckets: VecDeque<(u64, TrafficCounts)>, // (second, counts of that second), oldest first
+}
+
+/// Implementation of the TrafficMeter
+impl TrafficMeter {
+    /// Counts a datagram of the category that was sent or received at now
+    pub fn record(&mut self, direction: TrafficDirection, category: TrafficCategory, bytes: usize, now: Instant) {
+        let second = now.saturating_duration_since(*self.started.get_or_insert(now)).as_secs();
+        if self.buckets.back().is_none_or(|(newest, _)| *newest < second) {
+            self.buckets.push_back((second, TrafficCounts::default()));
+        }
+        while self.buckets.front().is_some_and(|(oldest, _)| oldest + TRAFFIC_RATE_WINDOW <= second) {
+            self.buckets.pop_front();
+        }
+
+        let (direction, category) = (direction as usize, category as usize);
+        let bucket = &mut self.buckets.back_mut().unwrap().1;
+        for count in [&mut self.totals[direction][category], &mut bucket[direction][category]] {
+            count.bytes += bytes as u64;
+            count.packets += 1;
+        }
+    }
+
+    /// Everything of the category counted in the direction so far
+    pub fn total(&self, direction: TrafficDirection, category: TrafficCategory) -> TrafficCount {
+        self.totals[direction as usize][category as usize]
+    }
+
+    /// Rates of every category over the last TRAFFIC_RATE_WINDOW seconds up to now. Shortly after the
+    /// first datagram the rates are over the time since then, at least a second, so they don't start low.
+    pub fn rates(&self, now: Instant) -> Vec<TrafficRate> {
+        let Some(started) = self.started else {
+            return TrafficCategory::ALL.iter().map(|category| TrafficRate::idle(*category)).collect();
+        };
+        let elapsed = now.saturating_duration_since(started);
+        let second = elapsed.as_secs();
+        let span = elapsed.as_secs_f64().clamp(1.0, TRAFFIC_RATE_WINDOW as f64);
+
+        let mut sums = TrafficCounts::default();
+        for (_, counts) in self.buckets.iter().filter(|(bucket, _)| bucket + TRAFFIC_RATE_WINDOW > second) {
+            for (sum, count) in sums.iter_mut().flatten().zip(counts.iter().flatten()) {
+                sum.bytes += count.bytes;
+                sum.packets += count.packets;
+            }
+        }
+        let per_second = |count: TrafficCount| (count.bytes as f64 / span, count.packets as f64 / span);
+        TrafficCategory::ALL.iter()
+            .map(|category| {
+                let (up_bytes_per_s, up_packets_per_s) = per_second(sums[TrafficDirection::Up as usize][*category as usize]);
+                let (down_bytes_per_s, down_packets_per_s) = per_second(sums[TrafficDirection::Down as usize][*category as usize]);
+                TrafficRate { category: *category, up_bytes_per_s, down_bytes_per_s, up_packets_per_s, down_packets_per_s }
+            })
+            .collect()
+    }
+}
+
+/// Text bar for a value, as many of width characters as its share of the largest value
+pub fn text_bar(value: f64, max: f64, width: usize) -> String {
+    let filled = if max > 0.0 { ((value / max).clamp(0.0, 1.0) * width as f64).round() as usize } else { 0 };
+    "#".repeat(filled)
+}
+
+/// Tests for the traffic meter
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Direction, PlayerInput};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    // Helper function to get the rate of a category
+    fn rate_of(rates: &[TrafficRate], category: TrafficCategory) -> TrafficRate {
+        *rates.iter().find(|rate| rate.category == category).unwrap()
+    }
+
+    #[test]
+    fn test_messages_are_attributed_to_their_category() {
+        let input = PlayerInput { dir: Direction::Up, sequence: 1, timestamp: 0 };
+        let wrapped = |message| ClientMessage::WithSession { session_token: Uuid::nil(), message: Box::new(message) };
+        assert_eq!(TrafficCategory::of_message(&ClientMessage::Input(input)), TrafficCategory::Input);
+        assert_eq!(TrafficCategory::of_message(&wrapped(ClientMessage::InputBatch(vec![input]))), TrafficCategory::Input);
+        assert_eq!(TrafficCategory::of_message(&wrapped(ClientMessage::ServerPong(3))), TrafficCategory::Ping);
+        assert_eq!(TrafficCategory::of_message(&ClientMessage::Pong(3)), TrafficCategory::Ping);
+        assert_eq!(TrafficCategory::of_message(&ClientMessage::ListRooms), TrafficCategory::Control);
+    }
+
+    #[test]
+    fn test_rates_cover_the_last_seconds() {
+        let mut meter = TrafficMeter::default();
+        let start = Instant::now();
+        assert_eq!(meter.rates(start)[0], TrafficRate::idle(TrafficCategory::Snapshot));
+
+        // One 100 byte snapshot down and a 10 byte input up every second for 8 seconds
+        for second in 0..8 {
+            let now = start + Duration::from_secs(second);
+            meter.record(TrafficDirection::Down, TrafficCategory::Snapshot, 100, now);
+            meter.record(TrafficDirection::Up, TrafficCategory::Input, 10, now);
+        }
+        let rates = meter.rates(start + Duration::from_millis(7500));
+        assert_eq!(rate_of(&rates, TrafficCategory::Snapshot).down_bytes_per_s, 100.0);
+        assert_eq!(rate_of(&rates, TrafficCategory::Snapshot).up_bytes_per_s, 0.0);
+        assert_eq!(rate_of(&rates, TrafficCategory::Input).up_packets_per_s, 1.0);
+        assert_eq!(meter.total(TrafficDirection::Down, TrafficCategory::Snapshot), TrafficCount { bytes: 800, packets: 8 });
+        assert_eq!(meter.buckets.len(), TRAFFIC_RATE_WINDOW as usize);
+
+        // Seconds without traffic bring the rates down until the window has passed
+        let rates = meter.rates(start + Duration::from_secs(10));
+        assert_eq!(rate_of(&rates, TrafficCategory::Snapshot).down_bytes_per_s, 40.0);
+        let rates = meter.rates(start + Duration::from_secs(12));
+        assert_eq!(rate_of(&rates, TrafficCategory::Snapshot).down_bytes_per_s, 0.0);
+    }
+
+    #[test]
+    fn test_early_rates_are_over_the_time_so_far() {
+        let mut meter = TrafficMeter::default();
+        let start = Instant::now();
+        meter.record(TrafficDirection::Down, TrafficCategory::Ping, 40, start);
+        meter.record(TrafficDirection::Down, TrafficCategory::Ping, 40, start + Duration::from_millis(1500));
+
+        // Two seconds in, not five, and never less than a second
+        let rates = meter.rates(start + Duration::from_secs(2));
+        assert_eq!(rate_of(&rates, TrafficCategory::Ping).down_bytes_per_s, 40.0);
+        let mut meter = TrafficMeter::default();
+        meter.record(TrafficDirection::Down, TrafficCategory::Ping, 40, start);
+        assert_eq!(rate_of(&meter.rates(start + Duration::from_millis(200)), TrafficCategory::Ping).down_bytes_per_s, 40.0);
+    }
+
+    #[test]
+    fn test_text_bar() {
+        assert_eq!(text_bar(50.0, 100.0, 10), "#####");
+        assert_eq!(text_bar(100.0, 100.0, 4), "####");
+        assert_eq!(text_bar(1.0, 0.0, 4), "");
+    }
+}
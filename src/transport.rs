@@ -0,0 +1,78 @@
+use crate::codec::CodecKind;
+use crate::types::{ClientMessage, GameState};
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+
+use tokio::net::UdpSocket;
+
+/// The server socket and the protocol each client speaks. Every datagram is sniffed,
+/// so clients speaking different protocols can play on the same server.
+pub struct Transport {
+    socket: UdpSocket,
+    only: Option<CodecKind>,                      // Set by --proto, datagrams in other protocols are ignored
+    codecs: RwLock<HashMap<SocketAddr, CodecKind>>, // Protocol of the last datagram each client sent
+}
+
+/// Implementation of the Transport
+impl Transport {
+    /// Wraps the socket, speaking only one protocol if one is given and every protocol otherwise
+    pub fn new(socket: UdpSocket, only: Option<CodecKind>) -> Self {
+        Self { socket, only, codecs: RwLock::new(HashMap::new()) }
+    }
+
+    /// The underlying socket
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Decodes a datagram in the protocol its first byte announces and remembers the protocol for the sender.
+    /// Returns None for datagrams that don't decode or are in a protocol this server doesn't speak.
+    pub fn decode(&self, addr: SocketAddr, data: &[u8]) -> Option<ClientMessage> {
+        let kind = CodecKind::sniff(data);
+        if self.only.is_some_and(|only| only != kind) {
+            return None;
+        }
+        let message = kind.codec().decode_message(data)?;
+        if self.codecs.read().unwrap().get(&addr) != Some(&kind) {
+            self.codecs.write().unwrap().insert(addr, kind);
+        }
+        Some(message)
+    }
+
+    /// Protocol a client is answered in, the server's own one if nothing was received from it yet
+    pub fn codec_of(&self, addr: &SocketAddr) -> CodecKind {
+        self.codecs.read().unwrap().get(addr).copied().unwrap_or(self.only.unwrap_or_default())
+    }
+
+    /// Number of clients whose protocol is remembered
+    pub fn client_count(&self) -> usize {
+        self.codecs.read().unwrap().len()
+    }
+
+    /// Forgets the protocol of clients that are gone
+    pub fn retain(&self, keep: impl Fn(&SocketAddr) -> bool) {
+        self.codecs.write().unwrap().retain(|addr, _| keep(addr));
+    }
+
+    /// Sends a message to each client in its protocol, encoding it once per protocol
+    pub async fn send_message(&self, addrs: &[SocketAddr], message: &ClientMessage) {
+        self.send_encoded(addrs, |kind| kind.codec().encode_message(message)).await;
+    }
+
+    /// Sends a snapshot to each client in its protocol, encoding it once per protocol
+    pub async fn send_snapshot(&self, addrs: &[SocketAddr], state: &GameState) {
+        self.send_encoded(addrs, |kind| kind.codec().encode_snapshot(state)).await;
+    }
+
+    /// Sends what encode produces for each client's protocol
+    async fn send_encoded(&self, addrs: &[SocketAddr], encode: impl Fn(CodecKind) -> Vec<u8>) {
+        let mut payloads: HashMap<CodecKind, Vec<u8>> = HashMap::new();
+        for addr in addrs {
+            let kind = self.codec_of(addr);
+            let payload = payloads.entry(kind).or_insert_with(|| encode(kind));
+            let _ = self.socket.send_to(payload, addr).await;
+        }
+    }
+}
@@ -1,27 +1,197 @@
+use crate::build_info::BuildInfo;
+use crate::movement::MovementTunables;
+
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
 /// Represents messages sent from the server to the client
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum ClientMessage {
     Connect,
     PlayerId(Uuid),
     Input(PlayerInput),
     Ping(u64),  // Client sends timestamp
     Pong(u64),  // Server echoes timestamp
+    MatchReset { match_number: u32, positions: Vec<(Uuid, Position)> }, // Server starts a new match
+    Welcome(Welcome), // Server accepts a Connect or Reconnect
+    Reconnect { session_token: Uuid }, // Client resumes a session after a brief disconnect
+    ServerPing(u32), // Server sends a nonce to measure the round trip time
+    ServerPong(u32), // Client echoes the nonce
+    ConnectWithProfile { name: String, shape: u8, instance: u64, room: String, password: Option<String> }, // Connect with a display name, PlayerShape wire value and hashed client instance id to a room, empty for the default room
+    ListRooms, // Client asks for the rooms of the server
+    RoomList(Vec<RoomInfo>), // Server answers ListRooms
+    ConnectRejected { reason: RejectReason }, // Server refuses a connect
+    InputBatch(Vec<PlayerInput>), // Several inputs in one datagram, oldest first, applied like single inputs
+    WithSession { session_token: Uuid, message: Box<ClientMessage> }, // Client message with its session token, so the server can follow the client to a new address
+    Disconnected { id: Uuid, reason: DisconnectReason }, // Server removed the player with the id from the game
+    DebugSetPosition(Position), // Client asks to be placed at the position, only servers started with debug teleports do so
+    Leave, // Client quits, the server removes the player right away instead of waiting for the timeout
 }
 
-/// Represents a network condition for simulating latency and packet loss
-#[derive(Clone)]
+/// A room as listed by the server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoomInfo {
+    pub name: String,
+    pub players: u32,
+    pub password_required: bool,
+}
+
+/// Why the server refused a connect
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    WrongPassword,   // The room needs a password and a different one or none was sent
+    UnknownRoom,     // The server has a fixed room list without this room
+    TooManyRooms,    // The room doesn't exist and the server already has MAX_ROOMS rooms
+    NameTooLong,     // The player name has more than MAX_NAME_CHARS chars
+    PasswordTooLong, // The password has more than MAX_PASSWORD_CHARS chars
+}
+
+/// Implementation of the RejectReason
+impl RejectReason {
+    /// Sentence shown to the player
+    pub fn describe(&self) -> &'static str {
+        match self {
+            RejectReason::WrongPassword => "Wrong password",
+            RejectReason::UnknownRoom => "The room doesn't exist",
+            RejectReason::TooManyRooms => "The server can't open more rooms, join an existing one",
+            RejectReason::NameTooLong => "The name is too long",
+            RejectReason::PasswordTooLong => "The password is too long",
+        }
+    }
+}
+
+/// Why the server removed a player from the game
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    TimedOut, // Nothing arrived from the player for the timeout, the session can still be resumed for a while
+    Kicked,   // Removed by the server, connecting again is allowed
+}
+
+/// Implementation of the DisconnectReason
+impl DisconnectReason {
+    /// Sentence shown to the player
+    pub fn describe(&self) -> &'static str {
+        match self {
+            DisconnectReason::TimedOut => "The server timed out the connection",
+            DisconnectReason::Kicked => "Kicked by the server",
+        }
+    }
+}
+
+/// Sent by the server in response to Connect or Reconnect
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Welcome {
+    pub id: Uuid,
+    pub session_token: Uuid, // Secret the client uses to resume this session
+    pub position: Position,
+    pub last_processed: Option<u32>, // Last applied input sequence when a session was resumed
+    pub tunables_digest: u64, // Digest of the movement tunables of the server, the client refuses to play with other ones
+    pub tunables: Option<MovementTunables>, // The tunables themselves, so a client with other ones can adopt them
+    pub build: Option<BuildInfo>, // Build of the server and spawn seed of the room, for bug reports
+    pub color: u32, // Packed color of the player, so it is drawn right before the first snapshot arrives
+    pub shape: PlayerShape,
+}
+
+/// Implementation of the Welcome
+impl Welcome {
+    /// Creates a Welcome with the movement tunables and the build of this binary, without a seed, for a
+    /// square player without a color
+    pub fn new(id: Uuid, session_token: Uuid, position: Position, last_processed: Option<u32>) -> Self {
+        let tunables = MovementTunables::default();
+        let build = Some(BuildInfo::current(None));
+        Self {
+            id,
+            session_token,
+            position,
+            last_processed,
+            tunables_digest: tunables.digest(),
+            tunables: Some(tunables),
+            build,
+            color: 0,
+            shape: PlayerShape::default(),
+        }
+    }
+}
+
+/// Represents a network condition for simulating latency, jitter, packet loss and duplication
+#[derive(Debug, Clone)]
 pub struct NetworkCondition {
     pub latency_ms: i32,
+    pub jitter_ms: i32,
     pub packet_loss_percent: i32,
+    pub duplicate_percent: i32,
     pub name: String,
 }
 
+/// Shape a player is drawn as. Every shape fills the same square bounding box,
+/// so collisions and clamping don't depend on it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PlayerShape {
+    #[default]
+    Square,
+    Circle,
+    Triangle,
+}
+
+/// Implementation of the PlayerShape
+impl PlayerShape {
+    /// Every shape, in wire value order
+    pub const ALL: [PlayerShape; 3] = [PlayerShape::Square, PlayerShape::Circle, PlayerShape::Triangle];
+
+    /// The value sent over the network
+    pub fn to_wire(self) -> u8 {
+        self as u8
+    }
+
+    /// Reads a value sent over the network, unknown values from newer versions fall back to Square
+    pub fn from_wire(value: u8) -> Self {
+        Self::ALL.get(value as usize).copied().unwrap_or_default()
+    }
+
+    /// Lower case name, as used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            PlayerShape::Square => "square",
+            PlayerShape::Circle => "circle",
+            PlayerShape::Triangle => "triangle",
+        }
+    }
+
+    /// Looks up a shape by its name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|shape| shape.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Phase of the current match as sent in snapshots, players only move while it allows movement
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchPhase {
+    #[default]
+    Warmup,                          // Before the first match ends, players move freely
+    Countdown { remaining_ms: u32 }, // Players are frozen at their spawn until the countdown ends
+    Active,                          // The match is running
+    Ended,                           // The match is over, players are frozen until the next countdown
+}
+
+/// Implementation of the MatchPhase
+impl MatchPhase {
+    /// Checks if inputs move players in this phase
+    pub fn allows_movement(&self) -> bool {
+        matches!(self, MatchPhase::Warmup | MatchPhase::Active)
+    }
+
+    /// Whole seconds left of the countdown, rounded up so it shows 5, 4, 3, 2, 1
+    pub fn countdown_seconds(&self) -> Option<u32> {
+        match self {
+            MatchPhase::Countdown { remaining_ms } => Some(remaining_ms.div_ceil(1000)),
+            _ => None,
+        }
+    }
+}
+
 /// Represents directions for player movement
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum Direction {
     Up,
     Down,
@@ -30,7 +200,7 @@ pub enum Direction {
 }
 
 /// Represents player input with direction, sequence number, and timestamp
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub struct PlayerInput {
     pub dir: Direction,
     pub sequence: u32,
@@ -44,19 +214,93 @@ pub struct Position {
     pub y: i32,
 }
 
+/// Displacement the server applied to a player without an input from that player, such as a knockback.
+/// Pushes between two inputs add up, so a lost snapshot doesn't lose part of the displacement.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppliedImpulse {
+    pub dx: i32,             // Total horizontal push since the input after_sequence was applied
+    pub dy: i32,             // Total vertical push since the input after_sequence was applied
+    pub after_sequence: u32, // Newest input of the player the server had applied when it was pushed
+    pub tick: u64,           // Server tick of the latest push, repeated in the snapshots that follow
+}
+
+/// When the newest input of a player the server applied was sent, received and applied, to break down where latency comes from.
+/// Server times are milliseconds on the tick timeline, tick n starts at n * tick_interval_ms.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputTiming {
+    pub sequence: u32,
+    pub client_timestamp: u64, // Timestamp the client put in the input, in its own milliseconds
+    pub received_ms: u64,      // Server time the input arrived
+    pub applied_ms: u64,       // Server time the input was applied, later than received_ms when the server queues inputs
+}
+
+/// How much a player moved since it joined, kept while its session waits to be resumed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MovementStats {
+    pub distance: u64,     // Pixels the inputs actually moved the player, what the board edges stopped isn't counted
+    pub inputs: u32,       // Inputs the server applied
+    pub connected_ms: u64, // Time connected, without the time the session was gone
+}
+
+/// Implementation of the MovementStats
+impl MovementStats {
+    /// Average inputs per second while connected, None before the first second
+    pub fn inputs_per_second(&self) -> Option<f64> {
+        (self.connected_ms >= 1000).then(|| self.inputs as f64 * 1000.0 / self.connected_ms as f64)
+    }
+}
+
 /// Represents a snapshot of a player's position at a specific timestamp
 #[derive(Clone)]
 pub struct PositionSnapshot {
     pub position: Position,
-    pub timestamp: u64,
+    pub timestamp: u64, // Server time in milliseconds on the tick timeline
+    pub teleport: bool, // The player was placed here, nothing should be interpolated from the entry before
+    pub origin: MoveOrigin,
+}
+
+/// What moved a player to a position in the server's position history, to track down desyncs afterwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveOrigin {
+    Spawn,                            // Placed when the player joined
+    Input { sequence: u32 },          // An input applied when it arrived
+    CoalescedInput { sequence: u32 }, // An input that waited in the coalescing queue before it was applied
+    LateInput { sequence: u32 },      // An input applied after one with a higher sequence
+    Knockback,                        // Pushed apart from the players it ran into
+    Teleport,                         // Placed by a debug teleport
+    MatchReset,                       // Respawned for a new match
+}
+
+/// Implementation of the MoveOrigin
+impl MoveOrigin {
+    /// Sequence of the input that made the move, None for moves without an input
+    pub fn sequence(self) -> Option<u32> {
+        match self {
+            MoveOrigin::Input { sequence } | MoveOrigin::CoalescedInput { sequence } | MoveOrigin::LateInput { sequence } => Some(sequence),
+            MoveOrigin::Spawn | MoveOrigin::Knockback | MoveOrigin::Teleport | MoveOrigin::MatchReset => None,
+        }
+    }
+
+    /// Describes the origin for the history report
+    pub fn describe(self) -> String {
+        match self {
+            MoveOrigin::Spawn => "spawn".to_string(),
+            MoveOrigin::Input { sequence } => format!("input #{}", sequence),
+            MoveOrigin::CoalescedInput { sequence } => format!("coalesced input #{}", sequence),
+            MoveOrigin::LateInput { sequence } => format!("late input #{}", sequence),
+            MoveOrigin::Knockback => "knockback".to_string(),
+            MoveOrigin::Teleport => "teleport".to_string(),
+            MoveOrigin::MatchReset => "match reset".to_string(),
+        }
+    }
 }
 
-/// Represents a position with an associated timestamp and sequence number for interpolation
+/// Represents a position at a server tick for interpolation
 #[derive(Debug, Clone)]
 pub struct InterpolatedPosition {
     pub position: Position,
-    pub timestamp: f32,
-    pub sequence: u32,
+    pub timestamp: f64, // Server time of the tick in seconds
+    pub snapshot_id: u64,
 }
 
 /// Represents the dimensions of the game board
@@ -67,18 +311,33 @@ pub struct Board {
 }
 
 /// Represents the state of the game, including players and their positions and sequences
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct GameState {
-    pub players: Vec<(Uuid, Position, u32)>, // id, pos, color
+    pub players: Vec<(Uuid, Position, u32)>, // id, pos, color, sorted by id
     pub last_processed: HashMap<Uuid, u32>, // Track inputs
-    pub server_timestamp: u64,
+    pub pellets: Vec<Position>,
+    pub scores: HashMap<Uuid, u32>, // Score of each player in the current match
+    pub best_scores: HashMap<Uuid, u32>, // Best score of each player, kept across matches and restarts
+    pub teams: HashMap<Uuid, u8>, // Team of each player in team mode, empty otherwise
+    pub input_reorders: HashMap<Uuid, u32>, // Inputs of each player the server applied out of order in the last minute
+    pub shapes: HashMap<Uuid, PlayerShape>, // Shape each player chose, players missing from it are squares
+    pub impulses: HashMap<Uuid, AppliedImpulse>, // Latest impulse of each player that was pushed in the last IMPULSE_RESEND_TICKS
+    pub input_timings: HashMap<Uuid, InputTiming>, // Timing of the newest applied input of each player
+    pub match_phase: MatchPhase,
+    pub server_timestamp: u64, // Server time the snapshot was sent, in milliseconds on the tick timeline
+    pub match_number: u32, // Incremented every time the server resets the match
+    pub ping_ms: HashMap<Uuid, u32>, // Round trip time of each player measured by the server
+    pub snapshot_id: u64, // Server tick the snapshot was built on
+    pub tick_interval_ms: u32, // Time between two server ticks
+    pub tagged: Option<Uuid>, // The player that is "it" in tag mode, None in other modes
+    pub movement_stats: HashMap<Uuid, MovementStats>, // How far and how long each player moved since it joined
 }
 
 /// Tests for the types
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bincode;
+    use crate::codec::{decode, encode};
 
     #[test]
     fn test_client_message_serialization() {
@@ -93,12 +352,38 @@ mod tests {
             }),
             ClientMessage::Ping(54321),
             ClientMessage::Pong(98765),
+            ClientMessage::MatchReset {
+                match_number: 3,
+                positions: vec![(Uuid::new_v4(), Position { x: 40, y: 60 })],
+            },
+            ClientMessage::Welcome(Welcome::new(Uuid::new_v4(), Uuid::new_v4(), Position { x: 70, y: 80 }, Some(500))),
+            ClientMessage::Reconnect { session_token: Uuid::new_v4() },
+            ClientMessage::ServerPing(7),
+            ClientMessage::ServerPong(7),
+            ClientMessage::ConnectWithProfile {
+                name: "Kari".to_string(),
+                shape: PlayerShape::Circle.to_wire(),
+                instance: 0x0123_4567_89ab_cdef,
+                room: "arena".to_string(),
+                password: Some("secret".to_string()),
+            },
+            ClientMessage::ListRooms,
+            ClientMessage::RoomList(vec![RoomInfo { name: "lobby".to_string(), players: 3, password_required: false }]),
+            ClientMessage::InputBatch(vec![
+                PlayerInput { dir: Direction::Left, sequence: 7, timestamp: 100 },
+                PlayerInput { dir: Direction::Down, sequence: 8, timestamp: 116 },
+            ]),
+            ClientMessage::ConnectRejected { reason: RejectReason::WrongPassword },
+            ClientMessage::WithSession {
+                session_token: Uuid::new_v4(),
+                message: Box::new(ClientMessage::Input(PlayerInput { dir: Direction::Up, sequence: 9, timestamp: 150 })),
+            },
         ];
 
         for message in messages {
             // Serialize and deserialize
-            let serialized = bincode::serialize(&message).unwrap();
-            let deserialized: ClientMessage = bincode::deserialize(&serialized).unwrap();
+            let serialized = encode(&message);
+            let deserialized: ClientMessage = decode(&serialized).unwrap();
 
             // Compare the debug output since ClientMessage doesn't implement PartialEq
             assert_eq!(format!("{:?}", message), format!("{:?}", deserialized));
@@ -109,15 +394,33 @@ mod tests {
     fn test_network_condition_creation() {
         let condition = NetworkCondition {
             latency_ms: 100,
+            jitter_ms: 10,
             packet_loss_percent: 5,
+            duplicate_percent: 0,
             name: "Test Network".to_string(),
         };
 
         assert_eq!(condition.latency_ms, 100);
+        assert_eq!(condition.jitter_ms, 10);
         assert_eq!(condition.packet_loss_percent, 5);
         assert_eq!(condition.name, "Test Network");
     }
 
+    #[test]
+    fn test_player_shape_wire_values() {
+        for shape in PlayerShape::ALL {
+            assert_eq!(PlayerShape::from_wire(shape.to_wire()), shape);
+            assert_eq!(PlayerShape::from_name(shape.name()), Some(shape));
+        }
+        assert_eq!(PlayerShape::Triangle.to_wire(), 2); // Part of the protocol, must not change
+
+        // Unknown values from a newer client fall back to a square
+        assert_eq!(PlayerShape::from_wire(3), PlayerShape::Square);
+        assert_eq!(PlayerShape::from_wire(255), PlayerShape::Square);
+        assert_eq!(PlayerShape::from_name("CIRCLE"), Some(PlayerShape::Circle));
+        assert_eq!(PlayerShape::from_name("hexagon"), None);
+    }
+
     #[test]
     fn test_direction_serialization() {
         let directions = vec![
@@ -128,8 +431,8 @@ mod tests {
         ];
 
         for dir in directions {
-            let serialized = bincode::serialize(&dir).unwrap();
-            let deserialized: Direction = bincode::deserialize(&serialized).unwrap();
+            let serialized = encode(&dir);
+            let deserialized: Direction = decode(&serialized).unwrap();
             assert_eq!(dir as u8, deserialized as u8); // Compare enum variants
         }
     }
@@ -142,8 +445,8 @@ mod tests {
             timestamp: 456789,
         };
 
-        let serialized = bincode::serialize(&input).unwrap();
-        let deserialized: PlayerInput = bincode::deserialize(&serialized).unwrap();
+        let serialized = encode(&input);
+        let deserialized: PlayerInput = decode(&serialized).unwrap();
 
         assert_eq!(deserialized.dir as u8, Direction::Right as u8);
         assert_eq!(deserialized.sequence, 123);
@@ -154,8 +457,8 @@ mod tests {
     fn test_position_serialization() {
         let pos = Position { x: 10, y: 20 };
 
-        let serialized = bincode::serialize(&pos).unwrap();
-        let deserialized: Position = bincode::deserialize(&serialized).unwrap();
+        let serialized = encode(&pos);
+        let deserialized: Position = decode(&serialized).unwrap();
 
         assert_eq!(deserialized.x, 10);
         assert_eq!(deserialized.y, 20);
@@ -169,11 +472,15 @@ mod tests {
         let snapshot = PositionSnapshot {
             position: pos,
             timestamp,
+            teleport: false,
+            origin: MoveOrigin::Input { sequence: 4 },
         };
 
         assert_eq!(snapshot.position.x, 15);
         assert_eq!(snapshot.position.y, 25);
         assert_eq!(snapshot.timestamp, 123456789);
+        assert_eq!(snapshot.origin.sequence(), Some(4));
+        assert_eq!(snapshot.origin.describe(), "input #4");
     }
 
     #[test]
@@ -183,13 +490,13 @@ mod tests {
         let interpol = InterpolatedPosition {
             position: pos,
             timestamp: 12.34,
-            sequence: 42,
+            snapshot_id: 42,
         };
 
         assert_eq!(interpol.position.x, 30);
         assert_eq!(interpol.position.y, 40);
         assert_eq!(interpol.timestamp, 12.34);
-        assert_eq!(interpol.sequence, 42);
+        assert_eq!(interpol.snapshot_id, 42);
     }
 
     #[test]
@@ -199,8 +506,8 @@ mod tests {
             height: 200,
         };
 
-        let serialized = bincode::serialize(&board).unwrap();
-        let deserialized: Board = bincode::deserialize(&serialized).unwrap();
+        let serialized = encode(&board);
+        let deserialized: Board = decode(&serialized).unwrap();
 
         assert_eq!(deserialized.width, 100);
         assert_eq!(deserialized.height, 200);
@@ -215,11 +522,26 @@ mod tests {
         let game_state = GameState {
             players: vec![(player_id, Position { x: 5, y: 10 }, 2)],
             last_processed,
+            pellets: Vec::new(),
+            scores: HashMap::new(),
+            best_scores: HashMap::new(),
+            teams: HashMap::from([(player_id, 1)]),
+            input_reorders: HashMap::from([(player_id, 2)]),
+            shapes: HashMap::from([(player_id, PlayerShape::Triangle)]),
+            impulses: HashMap::new(),
+            input_timings: HashMap::new(),
+            match_phase: MatchPhase::Countdown { remaining_ms: 2500 },
             server_timestamp: 98765,
+            match_number: 7,
+            ping_ms: HashMap::from([(player_id, 35)]),
+            snapshot_id: 1200,
+            tick_interval_ms: 16,
+            tagged: None,
+            movement_stats: HashMap::new(),
         };
 
-        let serialized = bincode::serialize(&game_state).unwrap();
-        let deserialized: GameState = bincode::deserialize(&serialized).unwrap();
+        let serialized = encode(&game_state);
+        let deserialized: GameState = decode(&serialized).unwrap();
 
         assert_eq!(deserialized.players.len(), 1);
         assert_eq!(deserialized.players[0].0, player_id);
@@ -228,5 +550,13 @@ mod tests {
         assert_eq!(deserialized.players[0].2, 2);
         assert_eq!(deserialized.last_processed.get(&player_id), Some(&42));
         assert_eq!(deserialized.server_timestamp, 98765);
+        assert_eq!(deserialized.match_number, 7);
+        assert_eq!(deserialized.ping_ms.get(&player_id), Some(&35));
+        assert_eq!(deserialized.teams.get(&player_id), Some(&1));
+        assert_eq!(deserialized.match_phase, MatchPhase::Countdown { remaining_ms: 2500 });
+        assert_eq!(deserialized.input_reorders.get(&player_id), Some(&2));
+        assert_eq!(deserialized.shapes.get(&player_id), Some(&PlayerShape::Triangle));
+        assert_eq!(deserialized.snapshot_id, 1200);
+        assert_eq!(deserialized.tick_interval_ms, 16);
     }
 }
\ No newline at end of file
@@ -0,0 +1,560 @@
+use std::collections::VecDeque;
+use std::ops::Index;
+use std::time::Instant;
+
+/// Queue of items that become available at a given time, used to simulate delays.
+/// Items are kept sorted by their ready time, items with the same ready time keep insertion order.
+pub struct DelayQueue<T> {
+    items: VecDeque<(Instant, T)>,
+}
+
+/// Implementation of the DelayQueue
+impl<T> DelayQueue<T> {
+    /// Creates an empty DelayQueue
+    pub fn new() -> Self {
+        Self { items: VecDeque::new() }
+    }
+
+    /// Adds an item that becomes available at ready_at
+    pub fn push(&mut self, item: T, ready_at: Instant) {
+        // Insert after all items that are ready at the same time or earlier
+        let index = self.items.partition_point(|(time, _)| *time <= ready_at);
+        self.items.insert(index, (ready_at, item));
+    }
+
+    /// Removes and returns all items that are ready at the given time, in ready order
+    pub fn pop_ready(&mut self, now: Instant) -> Vec<T> {
+        let count = self.items.partition_point(|(time, _)| *time <= now);
+        self.items.drain(..count).map(|(_, item)| item).collect()
+    }
+
+    /// Removes and returns the items that are ready at the given time and match, in ready order.
+    /// The items that don't match stay queued in their order.
+    pub fn pop_ready_where(&mut self, now: Instant, matches: impl Fn(&T) -> bool) -> Vec<T> {
+        let count = self.items.partition_point(|(time, _)| *time <= now);
+        let mut ready = Vec::new();
+        let mut kept = VecDeque::with_capacity(self.items.len());
+        for (index, (time, item)) in self.items.drain(..).enumerate() {
+            if index < count && matches(&item) {
+                ready.push(item);
+            } else {
+                kept.push_back((time, item));
+            }
+        }
+        self.items = kept;
+        ready
+    }
+
+    /// Removes and returns every item in ready order, ready or not
+    pub fn drain_all(&mut self) -> Vec<T> {
+        self.items.drain(..).map(|(_, item)| item).collect()
+    }
+
+    /// Time when the next item becomes ready
+    pub fn next_ready_at(&self) -> Option<Instant> {
+        self.items.front().map(|(time, _)| *time)
+    }
+
+    /// Number of items waiting in the queue
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Checks if the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// Default implementation for the DelayQueue
+impl<T> Default for DelayQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fixed size history of items ordered by a key such as a timestamp or sequence number.
+/// When full, adding an item drops the oldest one. Iteration goes from oldest to newest.
+#[derive(Debug, Clone)]
+pub struct RingHistory<T, K> {
+    items: VecDeque<T>, // Ring buffer that never grows past the capacity
+    capacity: usize,
+    key: fn(&T) -> K,
+}
+
+/// Implementation of the RingHistory
+impl<T, K: Ord> RingHistory<T, K> {
+    /// Creates an empty history holding at most capacity items, ordered by the given key
+    pub fn new(capacity: usize, key: fn(&T) -> K) -> Self {
+        assert!(capacity > 0, "RingHistory needs room for at least one item");
+        Self {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+            key,
+        }
+    }
+
+    /// Adds an item as the newest one, its key should not be lower than the newest key.
+    /// Returns the oldest item if it was dropped to make room.
+    pub fn push(&mut self, item: T) -> Option<T> {
+        let dropped = if self.items.len() == self.capacity { self.items.pop_front() } else { None };
+        self.items.push_back(item);
+        dropped
+    }
+
+    /// Adds an item at its place by key, for items that may arrive out of order.
+    /// Items with a key that is already stored, or older than everything in a full history, are skipped.
+    /// Returns whether the item was added.
+    pub fn insert(&mut self, item: T) -> bool {
+        let key = (self.key)(&item);
+        let index = self.lower_bound(&key);
+
+        if self.items.get(index).is_some_and(|existing| (self.key)(existing) == key) {
+            return false;
+        }
+        if index == 0 && self.is_full() {
+            return false;
+        }
+
+        if self.is_full() {
+            self.items.pop_front();
+            self.items.insert(index - 1, item);
+        } else {
+            self.items.insert(index, item);
+        }
+        true
+    }
+
+    /// Item with exactly the given key
+    pub fn find(&self, key: &K) -> Option<&T> {
+        self.items.get(self.lower_bound(key)).filter(|item| (self.key)(item) == *key)
+    }
+
+    /// Newest item with a key at or before the given key
+    pub fn latest_at_or_before(&self, key: &K) -> Option<&T> {
+        self.upper_bound(key).checked_sub(1).and_then(|index| self.items.get(index))
+    }
+
+    /// Index of the first item with a key at or after the given key
+    pub fn lower_bound(&self, key: &K) -> usize {
+        self.items.partition_point(|item| (self.key)(item) < *key)
+    }
+
+    /// Index of the first item with a key after the given key
+    pub fn upper_bound(&self, key: &K) -> usize {
+        self.items.partition_point(|item| (self.key)(item) <= *key)
+    }
+
+    /// Index of the first item the predicate is false for, the predicate must be true for a prefix
+    pub fn partition_point(&self, pred: impl FnMut(&T) -> bool) -> usize {
+        self.items.partition_point(pred)
+    }
+
+    /// Removes every item with a key at or before the given key
+    pub fn remove_through(&mut self, key: &K) {
+        let count = self.upper_bound(key);
+        self.items.drain(..count);
+    }
+
+    /// Item at an index, 0 is the oldest
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.items.get(index)
+    }
+
+    /// Oldest item
+    pub fn oldest(&self) -> Option<&T> {
+        self.items.front()
+    }
+
+    /// Newest item
+    pub fn newest(&self) -> Option<&T> {
+        self.items.back()
+    }
+
+    /// Iterates from the oldest to the newest item
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + ExactSizeIterator {
+        self.items.iter()
+    }
+
+    /// Removes all items
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Number of items in the history
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Checks if the history is empty
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Checks if the next push drops the oldest item
+    pub fn is_full(&self) -> bool {
+        self.items.len() == self.capacity
+    }
+
+    /// Maximum number of items
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Index access to the RingHistory, 0 is the oldest item
+impl<T, K> Index<usize> for RingHistory<T, K> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        &self.items[index]
+    }
+}
+
+/// Counts of values in power-of-two buckets: 0, 1, 2-3, 4-7, 8-15 and so on.
+/// Buckets are added as larger values are recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: Vec<u64>, // Number of values in each bucket
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+/// Implementation of the Histogram
+impl Histogram {
+    /// Creates an empty histogram
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a value to its bucket
+    pub fn record(&mut self, value: u64) {
+        let index = Self::bucket_index(value);
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, 0);
+        }
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    /// Index of the bucket a value falls in
+    pub fn bucket_index(value: u64) -> usize {
+        (u64::BITS - value.leading_zeros()) as usize
+    }
+
+    /// Smallest and largest value of a bucket, both inclusive
+    pub fn bucket_range(index: usize) -> (u64, u64) {
+        if index == 0 {
+            return (0, 0);
+        }
+        let low = 1u64 << (index - 1);
+        (low, low + (low - 1))
+    }
+
+    /// Number of values in each bucket, indexed like bucket_index
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// Number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of the recorded values
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Largest recorded value, 0 if nothing was recorded
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Average of the recorded values, 0 if nothing was recorded
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// Checks if nothing was recorded
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Adds every value recorded in another histogram
+    pub fn merge(&mut self, other: &Histogram) {
+        if self.buckets.len() < other.buckets.len() {
+            self.buckets.resize(other.buckets.len(), 0);
+        }
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    /// Describes the non-empty buckets, like "1: 5, 2-3: 2"
+    pub fn describe(&self) -> String {
+        self.buckets.iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| match Self::bucket_range(index) {
+                (low, high) if low == high => format!("{}: {}", low, count),
+                (low, high) => format!("{}-{}: {}", low, high, count),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Formats a hashed client instance id for logs and CSV files
+pub fn format_instance(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+/// Tests for the utilities
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_new_delay_queue() {
+        let queue: DelayQueue<u32> = DelayQueue::new();
+        assert!(queue.is_empty());
+        assert_eq!(queue.len(), 0);
+        assert_eq!(queue.next_ready_at(), None);
+    }
+
+    #[test]
+    fn test_pop_ready_respects_time() {
+        // Fake clock: all times are offsets from a fixed start
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+
+        let mut queue = DelayQueue::new();
+        queue.push("a", ms(10));
+        queue.push("b", ms(20));
+
+        assert!(queue.pop_ready(ms(5)).is_empty());
+        assert_eq!(queue.pop_ready(ms(10)), vec!["a"]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pop_ready(ms(100)), vec!["b"]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_pop_ready_where_leaves_the_rest_queued() {
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+
+        let mut queue = DelayQueue::new();
+        for (item, at) in [(1, 10), (2, 10), (3, 20), (4, 50)] {
+            queue.push(item, ms(at));
+        }
+
+        // Only ready items that match, even numbers are left for another caller
+        assert_eq!(queue.pop_ready_where(ms(20), |item| item % 2 == 1), vec![1, 3]);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.next_ready_at(), Some(ms(10)));
+        assert_eq!(queue.pop_ready_where(ms(100), |item| item % 2 == 0), vec![2, 4]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_items_are_sorted_by_ready_time() {
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+
+        let mut queue = DelayQueue::new();
+        queue.push(3, ms(30));
+        queue.push(1, ms(10));
+        queue.push(2, ms(20));
+
+        assert_eq!(queue.next_ready_at(), Some(ms(10)));
+        assert_eq!(queue.pop_ready(ms(30)), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_equal_ready_times_keep_insertion_order() {
+        let start = Instant::now();
+
+        let mut queue = DelayQueue::new();
+        for i in 0..5 {
+            queue.push(i, start);
+        }
+
+        assert_eq!(queue.pop_ready(start), vec![0, 1, 2, 3, 4]);
+    }
+
+    // Helper function to create a history of (key, value) pairs ordered by key
+    fn history(capacity: usize) -> RingHistory<(u32, &'static str), u32> {
+        RingHistory::new(capacity, |item| item.0)
+    }
+
+    // Helper function to get the keys from oldest to newest
+    fn keys(history: &RingHistory<(u32, &'static str), u32>) -> Vec<u32> {
+        history.iter().map(|item| item.0).collect()
+    }
+
+    #[test]
+    fn test_push_overwrites_oldest() {
+        let mut history = history(3);
+        assert!(history.is_empty());
+
+        for key in 1..=3 {
+            assert_eq!(history.push((key, "")), None);
+        }
+        assert!(history.is_full());
+
+        // Wraps around, dropping the oldest items in order
+        assert_eq!(history.push((4, "")), Some((1, "")));
+        assert_eq!(history.push((5, "")), Some((2, "")));
+        assert_eq!(keys(&history), vec![3, 4, 5]);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.capacity(), 3);
+    }
+
+    #[test]
+    fn test_iteration_order_after_many_wraps() {
+        let mut history = history(4);
+        for key in 0..103 {
+            history.push((key, ""));
+        }
+
+        assert_eq!(keys(&history), vec![99, 100, 101, 102]);
+        assert_eq!(history.iter().rev().map(|item| item.0).collect::<Vec<_>>(), vec![102, 101, 100, 99]);
+        assert_eq!(history.oldest().map(|item| item.0), Some(99));
+        assert_eq!(history.newest().map(|item| item.0), Some(102));
+        assert_eq!(history[1].0, 100);
+        assert_eq!(history.get(4), None);
+    }
+
+    #[test]
+    fn test_find_at_boundaries() {
+        let mut history = history(3);
+        assert_eq!(history.find(&1), None);
+        assert_eq!(history.latest_at_or_before(&1), None);
+
+        for (key, value) in [(10, "a"), (20, "b"), (30, "c"), (40, "d")] {
+            history.push((key, value));
+        }
+
+        // The oldest item was dropped by the wrap
+        assert_eq!(history.find(&10), None);
+        assert_eq!(history.find(&20), Some(&(20, "b")));
+        assert_eq!(history.find(&40), Some(&(40, "d")));
+        assert_eq!(history.find(&25), None);
+
+        assert_eq!(history.latest_at_or_before(&19), None);
+        assert_eq!(history.latest_at_or_before(&20), Some(&(20, "b")));
+        assert_eq!(history.latest_at_or_before(&39), Some(&(30, "c")));
+        assert_eq!(history.latest_at_or_before(&1000), Some(&(40, "d")));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut history = history(5);
+        for key in [2, 4, 4, 6] {
+            history.push((key, ""));
+        }
+
+        assert_eq!(history.lower_bound(&1), 0);
+        assert_eq!(history.lower_bound(&4), 1);
+        assert_eq!(history.upper_bound(&4), 3);
+        assert_eq!(history.upper_bound(&6), 4);
+        assert_eq!(history.partition_point(|item| item.0 < 5), 3);
+    }
+
+    #[test]
+    fn test_insert_keeps_order() {
+        let mut history = history(3);
+        assert!(history.insert((20, "")));
+        assert!(history.insert((10, "")));
+        assert!(history.insert((30, "")));
+        assert_eq!(keys(&history), vec![10, 20, 30]);
+
+        // Duplicates and items older than a full history are skipped
+        assert!(!history.insert((20, "")));
+        assert!(!history.insert((5, "")));
+
+        // An item in the middle of a full history drops the oldest
+        assert!(history.insert((25, "")));
+        assert_eq!(keys(&history), vec![20, 25, 30]);
+        assert!(history.insert((40, "")));
+        assert_eq!(keys(&history), vec![25, 30, 40]);
+    }
+
+    #[test]
+    fn test_remove_through() {
+        let mut history = history(5);
+        for key in 1..=5 {
+            history.push((key, ""));
+        }
+
+        history.remove_through(&0);
+        assert_eq!(history.len(), 5);
+        history.remove_through(&3);
+        assert_eq!(keys(&history), vec![4, 5]);
+        history.remove_through(&10);
+        assert!(history.is_empty());
+
+        // Still usable after being emptied
+        history.push((6, ""));
+        history.clear();
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = Histogram::new();
+        for value in [0, 1, 2, 3, 4, 7, 8, 100] {
+            histogram.record(value);
+        }
+
+        assert_eq!(histogram.buckets(), &[1, 1, 2, 2, 1, 0, 0, 1]);
+        assert_eq!(histogram.count(), 8);
+        assert_eq!(histogram.sum(), 125);
+        assert_eq!(histogram.max(), 100);
+        assert_eq!(histogram.describe(), "0: 1, 1: 1, 2-3: 2, 4-7: 2, 8-15: 1, 64-127: 1");
+
+        // Every value falls in the range of its own bucket
+        for value in [0, 1, 5, 64, 1000, u64::MAX] {
+            let (low, high) = Histogram::bucket_range(Histogram::bucket_index(value));
+            assert!((low..=high).contains(&value), "value {}", value);
+        }
+    }
+
+    #[test]
+    fn test_histogram_merge() {
+        let mut merged = Histogram::new();
+        let mut other = Histogram::new();
+        merged.record(1);
+        for value in [2, 100] {
+            other.record(value);
+        }
+        merged.merge(&other);
+
+        let mut expected = Histogram::new();
+        for value in [1, 2, 100] {
+            expected.record(value);
+        }
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn test_empty_histogram() {
+        let histogram = Histogram::new();
+        assert!(histogram.is_empty());
+        assert_eq!(histogram.mean(), 0.0);
+        assert_eq!(histogram.describe(), "");
+    }
+}
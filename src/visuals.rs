@@ -0,0 +1,359 @@
+use crate::colors::{palette, Rgb};
+use crate::constants::{COLOR_BLEND_TIME, PLAYER_FADE_TIME};
+use crate::positions::DisplayPos;
+
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Where a player is in its visual lifecycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VisualState {
+    FadingIn,
+    Visible,
+    FadingOut,
+    Gone, // Faded out, ready to be removed
+}
+
+/// How a player is drawn at a moment in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Appearance {
+    pub color: Rgb,
+    pub alpha: f32,
+}
+
+/// Visual state of one player, separate from the authoritative state received from the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerVisual {
+    spawned_at: f64,
+    despawn_deadline: Option<f64>, // When the fade out completes
+    fade_out_from: f64,            // Opacity when the fade out started
+    color: Rgb,
+    previous_color: Rgb, // Color the current color blends from
+    color_changed_at: f64,
+    frozen_position: Option<DisplayPos>, // Where the fade out is drawn, captured when the player left
+}
+
+/// Implementation of the PlayerVisual
+impl PlayerVisual {
+    /// Creates the visual of a player that just appeared
+    pub fn new(color: Rgb, now: f64) -> Self {
+        Self {
+            spawned_at: now,
+            despawn_deadline: None,
+            fade_out_from: 1.0,
+            color,
+            previous_color: color,
+            color_changed_at: now,
+            frozen_position: None,
+        }
+    }
+
+    /// Lifecycle state at the given time
+    pub fn state(&self, now: f64) -> VisualState {
+        match self.despawn_deadline {
+            Some(deadline) if now >= deadline => VisualState::Gone,
+            Some(_) => VisualState::FadingOut,
+            None if now - self.spawned_at < PLAYER_FADE_TIME => VisualState::FadingIn,
+            None => VisualState::Visible,
+        }
+    }
+
+    /// Opacity at the given time, from 0 (invisible) to 1 (opaque)
+    pub fn alpha(&self, now: f64) -> f32 {
+        let alpha = match self.despawn_deadline {
+            Some(deadline) => self.fade_out_from * fade_progress(deadline - now, PLAYER_FADE_TIME),
+            None => fade_progress(now - self.spawned_at, PLAYER_FADE_TIME),
+        };
+        alpha as f32
+    }
+
+    /// Color at the given time, blending from the previous color after a change
+    pub fn color(&self, now: f64) -> Rgb {
+        let t = fade_progress(now - self.color_changed_at, COLOR_BLEND_TIME);
+        blend(self.previous_color, self.color, t)
+    }
+
+    /// How the player is drawn at the given time
+    pub fn appearance(&self, now: f64) -> Appearance {
+        Appearance {
+            color: self.color(now),
+            alpha: self.alpha(now),
+        }
+    }
+
+    /// Starts blending to a new color from whatever is shown right now
+    pub fn set_color(&mut self, color: Rgb, now: f64) {
+        if color != self.color {
+            self.previous_color = self.color(now);
+            self.color = color;
+            self.color_changed_at = now;
+        }
+    }
+
+    /// Starts the fade out, unless it already started. Returns whether it started now.
+    pub fn despawn(&mut self, now: f64) -> bool {
+        if self.despawn_deadline.is_some() {
+            return false;
+        }
+        self.fade_out_from = self.alpha(now) as f64;
+        self.despawn_deadline = Some(now + PLAYER_FADE_TIME);
+        true
+    }
+
+    /// Pins a fading out player to where it was drawn when it left. Only the first position counts,
+    /// and a player that isn't fading out isn't pinned.
+    pub fn freeze_at(&mut self, position: DisplayPos) {
+        if self.despawn_deadline.is_some() && self.frozen_position.is_none() {
+            self.frozen_position = Some(position);
+        }
+    }
+
+    /// Where the fade out is drawn, None unless the player is fading out at a frozen position
+    pub fn frozen_position(&self) -> Option<DisplayPos> {
+        self.frozen_position
+    }
+
+    /// Cancels a fade out for a player that came back, fading in again from the current opacity.
+    /// It moves with its snapshots again.
+    pub fn respawn(&mut self, now: f64) {
+        if self.despawn_deadline.is_some() {
+            let alpha = self.alpha(now) as f64;
+            self.despawn_deadline = None;
+            self.spawned_at = now - fade_elapsed(alpha) * PLAYER_FADE_TIME;
+            self.frozen_position = None;
+        }
+    }
+}
+
+/// Visual state of every player that is shown, including players that are fading out
+#[derive(Debug, Default)]
+pub struct PlayerVisuals {
+    players: HashMap<Uuid, PlayerVisual>,
+}
+
+/// Implementation of the PlayerVisuals
+impl PlayerVisuals {
+    /// Creates an empty set of player visuals
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compares the players of a snapshot with the shown ones: new players fade in,
+    /// missing players fade out and changed colors start blending. Returns the players
+    /// whose fade out started, for the caller to freeze where they were drawn.
+    pub fn sync(&mut self, players: impl IntoIterator<Item = (Uuid, u32)>, now: f64) -> Vec<Uuid> {
+        let mut present = Vec::new();
+        for (id, packed_color) in players {
+            let color = palette::unpack(packed_color);
+            match self.players.get_mut(&id) {
+                Some(visual) => {
+                    visual.respawn(now);
+                    visual.set_color(color, now);
+                }
+                None => {
+                    self.players.insert(id, PlayerVisual::new(color, now));
+                }
+            }
+            present.push(id);
+        }
+
+        let mut left = Vec::new();
+        for (id, visual) in self.players.iter_mut() {
+            if !present.contains(id) && visual.despawn(now) {
+                left.push(*id);
+            }
+        }
+        left
+    }
+
+    /// Starts the fade out of a player that left
+    pub fn despawn(&mut self, id: &Uuid, now: f64) {
+        if let Some(visual) = self.players.get_mut(id) {
+            visual.despawn(now);
+        }
+    }
+
+    /// Pins a fading out player to the position, see PlayerVisual::freeze_at
+    pub fn freeze_at(&mut self, id: &Uuid, position: DisplayPos) {
+        if let Some(visual) = self.players.get_mut(id) {
+            visual.freeze_at(position);
+        }
+    }
+
+    /// Where a fading out player is drawn, None if it isn't frozen
+    pub fn frozen_position(&self, id: &Uuid) -> Option<DisplayPos> {
+        self.players.get(id).and_then(PlayerVisual::frozen_position)
+    }
+
+    /// Removes the players that finished fading out and returns their ids,
+    /// so they can be removed from the authoritative state as well
+    pub fn update(&mut self, now: f64) -> Vec<Uuid> {
+        let gone: Vec<Uuid> = self.players.iter()
+            .filter(|(_, visual)| visual.state(now) == VisualState::Gone)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &gone {
+            self.players.remove(id);
+        }
+        gone
+    }
+
+    /// How a player is drawn at the given time
+    pub fn appearance(&self, id: &Uuid, now: f64) -> Option<Appearance> {
+        self.players.get(id).map(|visual| visual.appearance(now))
+    }
+
+    /// Lifecycle state of a player at the given time
+    pub fn state(&self, id: &Uuid, now: f64) -> Option<VisualState> {
+        self.players.get(id).map(|visual| visual.state(now))
+    }
+}
+
+/// Progress of a fade that has run for elapsed seconds, from 0 to 1 with an ease out curve
+fn fade_progress(elapsed: f64, duration: f64) -> f64 {
+    let t = (elapsed / duration).clamp(0.0, 1.0);
+    1.0 - (1.0 - t) * (1.0 - t)
+}
+
+/// Inverse of the fade curve: the share of the fade duration that gives the progress
+fn fade_elapsed(progress: f64) -> f64 {
+    1.0 - (1.0 - progress.clamp(0.0, 1.0)).sqrt()
+}
+
+/// Linear blend between two colors
+fn blend(from: Rgb, to: Rgb, t: f64) -> Rgb {
+    let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+    (channel(from.0, to.0), channel(from.1, to.1), channel(from.2, to.2))
+}
+
+/// Tests for the player visuals
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Position;
+
+    const RED: Rgb = (255, 0, 0);
+    const BLUE: Rgb = (0, 0, 255);
+
+    #[test]
+    fn test_fade_in() {
+        let visual = PlayerVisual::new(RED, 10.0);
+
+        assert_eq!(visual.state(10.0), VisualState::FadingIn);
+        assert_eq!(visual.alpha(10.0), 0.0);
+
+        let halfway = visual.alpha(10.0 + PLAYER_FADE_TIME / 2.0);
+        assert!(halfway > 0.5 && halfway < 1.0); // Eases out
+
+        assert_eq!(visual.state(10.0 + PLAYER_FADE_TIME), VisualState::Visible);
+        assert_eq!(visual.alpha(10.0 + PLAYER_FADE_TIME), 1.0);
+    }
+
+    #[test]
+    fn test_fade_out() {
+        let mut visual = PlayerVisual::new(RED, 0.0);
+        visual.despawn(5.0);
+
+        assert_eq!(visual.state(5.0), VisualState::FadingOut);
+        assert_eq!(visual.alpha(5.0), 1.0);
+        assert!(visual.alpha(5.0 + PLAYER_FADE_TIME / 2.0) < 1.0);
+        assert_eq!(visual.state(5.0 + PLAYER_FADE_TIME), VisualState::Gone);
+        assert_eq!(visual.alpha(5.0 + PLAYER_FADE_TIME), 0.0);
+
+        // Despawning again doesn't restart the fade
+        visual.despawn(5.1);
+        assert_eq!(visual.state(5.0 + PLAYER_FADE_TIME), VisualState::Gone);
+    }
+
+    #[test]
+    fn test_leaving_while_fading_in_never_gets_brighter() {
+        let mut visual = PlayerVisual::new(RED, 0.0);
+        let start = PLAYER_FADE_TIME / 4.0;
+        let alpha_at_leave = visual.alpha(start);
+        visual.despawn(start);
+
+        assert!(visual.alpha(start) <= alpha_at_leave);
+        assert!(visual.alpha(start + 0.05) <= alpha_at_leave);
+    }
+
+    #[test]
+    fn test_respawn_continues_from_current_alpha() {
+        let mut visual = PlayerVisual::new(RED, 0.0);
+        visual.despawn(1.0);
+        let now = 1.0 + PLAYER_FADE_TIME / 2.0;
+        let alpha = visual.alpha(now);
+
+        visual.respawn(now);
+
+        assert_eq!(visual.state(now), VisualState::FadingIn);
+        assert!((visual.alpha(now) - alpha).abs() < 0.01);
+        assert_eq!(visual.alpha(now + PLAYER_FADE_TIME), 1.0);
+    }
+
+    #[test]
+    fn test_color_blends() {
+        let mut visual = PlayerVisual::new(RED, 0.0);
+        visual.set_color(BLUE, 1.0);
+
+        assert_eq!(visual.color(1.0), RED);
+        let middle = visual.color(1.0 + COLOR_BLEND_TIME / 2.0);
+        assert!(middle.0 < 255 && middle.2 > 0);
+        assert_eq!(visual.color(1.0 + COLOR_BLEND_TIME), BLUE);
+
+        // Setting the same color again doesn't restart the blend
+        visual.set_color(BLUE, 5.0);
+        assert_eq!(visual.color(5.0), BLUE);
+    }
+
+    #[test]
+    fn test_sync_detects_joins_and_leaves() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let mut visuals = PlayerVisuals::new();
+
+        visuals.sync([(a, palette::pack(RED)), (b, palette::pack(BLUE))], 0.0);
+        assert_eq!(visuals.state(&a, 0.0), Some(VisualState::FadingIn));
+
+        // b is missing from the next snapshot, its fade out starts only once
+        assert_eq!(visuals.sync([(a, palette::pack(RED))], 1.0), vec![b]);
+        assert!(visuals.sync([(a, palette::pack(RED))], 1.1).is_empty());
+        assert_eq!(visuals.state(&a, 1.0), Some(VisualState::Visible));
+        assert_eq!(visuals.state(&b, 1.0), Some(VisualState::FadingOut));
+
+        // b is kept until its fade out completes
+        assert!(visuals.update(1.0 + PLAYER_FADE_TIME / 2.0).is_empty());
+        assert_eq!(visuals.update(1.0 + PLAYER_FADE_TIME), vec![b]);
+        assert_eq!(visuals.appearance(&b, 2.0), None);
+        assert_eq!(visuals.appearance(&a, 2.0), Some(Appearance { color: RED, alpha: 1.0 }));
+    }
+
+    #[test]
+    fn test_despawn_by_id() {
+        let a = Uuid::new_v4();
+        let mut visuals = PlayerVisuals::new();
+        visuals.sync([(a, palette::pack(RED))], 0.0);
+
+        visuals.despawn(&a, 1.0);
+        visuals.despawn(&Uuid::new_v4(), 1.0); // Unknown players are ignored
+
+        assert_eq!(visuals.state(&a, 1.0), Some(VisualState::FadingOut));
+    }
+
+    #[test]
+    fn test_fade_out_is_frozen_at_the_first_position() {
+        let mut visual = PlayerVisual::new(RED, 0.0);
+
+        // A player that is still there isn't pinned
+        visual.freeze_at(DisplayPos::smoothed(Position { x: 1, y: 1 }));
+        assert_eq!(visual.frozen_position(), None);
+
+        assert!(visual.despawn(1.0));
+        visual.freeze_at(DisplayPos::smoothed(Position { x: 120, y: 80 }));
+        visual.freeze_at(DisplayPos::smoothed(Position { x: 130, y: 80 }));
+        assert_eq!(visual.frozen_position(), Some(DisplayPos::smoothed(Position { x: 120, y: 80 })));
+
+        // Coming back moves with the snapshots again
+        visual.respawn(1.1);
+        assert_eq!(visual.frozen_position(), None);
+    }
+}
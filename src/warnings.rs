@@ -0,0 +1,455 @@
+use crate::constants::{
+    WARNING_BREACH_EVALUATIONS, WARNING_CRITICAL_FACTOR, WARNING_LOSS, WARNING_PREDICTION_ERROR_P95, WARNING_RECOVERY_EVALUATIONS,
+    WARNING_RTT_MS, WARNING_STARVATION,
+};
+use crate::strings::{Language, Strings, Text};
+
+use serde::{Deserialize, Serialize};
+
+/// Live metrics the client warns about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    Rtt,             // Round trip time measured by the server, in milliseconds
+    Loss,            // Estimated packet loss, from 0 to 1
+    PredictionError, // 95th percentile of the prediction error since the last evaluation, in pixels
+    Starvation,      // Average share of remote players whose interpolation buffer ran dry
+}
+
+/// Implementation of the Metric
+impl Metric {
+    pub const ALL: [Metric; 4] = [Metric::Rtt, Metric::Loss, Metric::PredictionError, Metric::Starvation];
+
+    /// Name of the metric in the warning log
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Rtt => "rtt_ms",
+            Metric::Loss => "loss",
+            Metric::PredictionError => "prediction_error_p95",
+            Metric::Starvation => "starvation",
+        }
+    }
+
+    /// Name of the metric on the warning banner, in the string tables
+    pub fn text(self) -> Text {
+        match self {
+            Metric::Rtt => Text::WarningRtt,
+            Metric::Loss => Text::WarningLoss,
+            Metric::PredictionError => Text::WarningPredictionError,
+            Metric::Starvation => Text::WarningStarvation,
+        }
+    }
+
+    /// Name of the metric in English, for the console
+    pub fn label(self) -> &'static str {
+        self.text().in_language(Language::English)
+    }
+
+    /// A value of the metric with its unit
+    pub fn format_value(self, value: f64) -> String {
+        match self {
+            Metric::Rtt => format!("{:.0} ms", value),
+            Metric::Loss | Metric::Starvation => format!("{:.0}%", value * 100.0),
+            Metric::PredictionError => format!("{:.0} px (p95)", value),
+        }
+    }
+
+    /// Index of the metric in ALL
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Values above which a metric is warned about, read from the settings file. Missing fields keep their default.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WarningThresholds {
+    pub rtt_ms: f64,
+    pub loss: f64,
+    pub prediction_error_p95: f64,
+    pub starvation: f64,
+}
+
+/// Default implementation for the WarningThresholds
+impl Default for WarningThresholds {
+    fn default() -> Self {
+        Self {
+            rtt_ms: WARNING_RTT_MS,
+            loss: WARNING_LOSS,
+            prediction_error_p95: WARNING_PREDICTION_ERROR_P95,
+            starvation: WARNING_STARVATION,
+        }
+    }
+}
+
+/// Implementation of the WarningThresholds
+impl WarningThresholds {
+    /// Threshold of a metric
+    pub fn limit(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::Rtt => self.rtt_ms,
+            Metric::Loss => self.loss,
+            Metric::PredictionError => self.prediction_error_p95,
+            Metric::Starvation => self.starvation,
+        }
+    }
+}
+
+/// Values of the metrics at one evaluation, None for metrics that weren't measured since the last one
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MetricValues {
+    pub rtt_ms: Option<f64>,
+    pub loss: Option<f64>,
+    pub prediction_error_p95: Option<f64>,
+    pub starvation: Option<f64>,
+}
+
+/// Implementation of the MetricValues
+impl MetricValues {
+    /// Value of a metric
+    pub fn value(&self, metric: Metric) -> Option<f64> {
+        match metric {
+            Metric::Rtt => self.rtt_ms,
+            Metric::Loss => self.loss,
+            Metric::PredictionError => self.prediction_error_p95,
+            Metric::Starvation => self.starvation,
+        }
+    }
+}
+
+/// How bad a breached metric is, shown in orange or red
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningLevel {
+    Warning,  // Over the threshold
+    Critical, // At least WARNING_CRITICAL_FACTOR times over it
+}
+
+/// A warning that is shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveWarning {
+    pub metric: Metric,
+    pub value: f64, // Newest measured value
+    pub level: WarningLevel,
+}
+
+/// Implementation of the ActiveWarning
+impl ActiveWarning {
+    /// Text of the warning on the banner, in the language of the strings
+    pub fn describe(&self, strings: &Strings) -> String {
+        format!("{}: {}", strings.get(self.metric.text()), self.metric.format_value(self.value))
+    }
+}
+
+/// A warning that started or ended at an evaluation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WarningChange {
+    Raised { metric: Metric, value: f64, threshold: f64 },
+    Cleared { metric: Metric, value: f64, threshold: f64 },
+}
+
+/// Implementation of the WarningChange
+impl WarningChange {
+    /// One line for the console
+    pub fn describe(&self) -> String {
+        match self {
+            WarningChange::Raised { metric, value, threshold } => {
+                format!("Warning: {} at {} (threshold {})", metric.label(), metric.format_value(*value), metric.format_value(*threshold))
+            }
+            WarningChange::Cleared { metric, value, .. } => {
+                format!("Warning cleared: {} back to {}", metric.label(), metric.format_value(*value))
+            }
+        }
+    }
+}
+
+/// Hysteresis state of one metric
+#[derive(Debug, Clone, Copy, Default)]
+struct MetricState {
+    breaches: u32,   // Evaluations in a row over the threshold
+    recoveries: u32, // Evaluations in a row under the threshold
+    active: bool,
+    value: f64, // Newest measured value
+}
+
+/// Checks the live metrics against their thresholds. A metric is warned about once it was over its threshold
+/// WARNING_BREACH_EVALUATIONS evaluations in a row, and the warning clears once it was under it
+/// WARNING_RECOVERY_EVALUATIONS evaluations in a row, so a metric close to its threshold doesn't flicker.
+#[derive(Debug, Clone)]
+pub struct WarningEngine {
+    thresholds: WarningThresholds,
+    states: [MetricState; Metric::ALL.len()],
+}
+
+/// Implementation of the WarningEngine
+impl WarningEngine {
+    /// Creates an engine without any warning
+    pub fn new(thresholds: WarningThresholds) -> Self {
+        Self {
+            thresholds,
+            states: Default::default(),
+        }
+    }
+
+    /// The thresholds the metrics are checked against
+    pub fn thresholds(&self) -> &WarningThresholds {
+        &self.thresholds
+    }
+
+    /// Checks the values of one evaluation. A metric without a value breaks both streaks and keeps its warning as it is.
+    /// Returns the warnings that started or ended.
+    pub fn evaluate(&mut self, values: &MetricValues) -> Vec<WarningChange> {
+        let mut changes = Vec::new();
+        for metric in Metric::ALL {
+            let threshold = self.thresholds.limit(metric);
+            let state = &mut self.states[metric.index()];
+            let Some(value) = values.value(metric) else {
+                state.breaches = 0;
+                state.recoveries = 0;
+                continue;
+            };
+            state.value = value;
+
+            if value > threshold {
+                state.breaches += 1;
+                state.recoveries = 0;
+            } else {
+                state.recoveries += 1;
+                state.breaches = 0;
+            }
+
+            if !state.active && state.breaches >= WARNING_BREACH_EVALUATIONS {
+                state.active = true;
+                changes.push(WarningChange::Raised { metric, value, threshold });
+            } else if state.active && state.recoveries >= WARNING_RECOVERY_EVALUATIONS {
+                state.active = false;
+                changes.push(WarningChange::Cleared { metric, value, threshold });
+            }
+        }
+        changes
+    }
+
+    /// The warnings to show, in the order of Metric::ALL
+    pub fn active(&self) -> Vec<ActiveWarning> {
+        Metric::ALL.iter()
+            .filter(|metric| self.states[metric.index()].active)
+            .map(|metric| {
+                let value = self.states[metric.index()].value;
+                let level = if value >= self.thresholds.limit(*metric) * WARNING_CRITICAL_FACTOR {
+                    WarningLevel::Critical
+                } else {
+                    WarningLevel::Warning
+                };
+                ActiveWarning { metric: *metric, value, level }
+            })
+            .collect()
+    }
+
+    /// Drops every warning and streak, for a new session
+    pub fn reset(&mut self) {
+        self.states = Default::default();
+    }
+}
+
+/// Samples of the metrics measured more often than they are evaluated, collected until the next evaluation
+#[derive(Debug, Clone, Default)]
+pub struct MetricWindow {
+    prediction_errors: Vec<f32>,
+    starvation: Vec<f32>,
+}
+
+/// Implementation of the MetricWindow
+impl MetricWindow {
+    /// Creates an empty window
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the prediction error of a reconciliation
+    pub fn record_prediction_error(&mut self, error: f32) {
+        self.prediction_errors.push(error);
+    }
+
+    /// Adds the share of starved remote players of a frame
+    pub fn record_starvation(&mut self, share: f32) {
+        self.starvation.push(share);
+    }
+
+    /// Values for an evaluation with the given round trip time and loss, the window starts over afterwards
+    pub fn take(&mut self, rtt_ms: Option<f64>, loss: Option<f64>) -> MetricValues {
+        let values = MetricValues {
+            rtt_ms,
+            loss,
+            prediction_error_p95: percentile(&mut self.prediction_errors, 0.95).map(f64::from),
+            starvation: (!self.starvation.is_empty()).then(|| {
+                self.starvation.iter().map(|share| *share as f64).sum::<f64>() / self.starvation.len() as f64
+            }),
+        };
+        self.prediction_errors.clear();
+        self.starvation.clear();
+        values
+    }
+}
+
+/// The nearest-rank percentile of the values (0.95 for p95), None if there are none. Sorts the values.
+pub fn percentile(values: &mut [f32], fraction: f64) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f32::total_cmp);
+    let rank = (fraction * values.len() as f64).ceil() as usize;
+    Some(values[rank.clamp(1, values.len()) - 1])
+}
+
+/// Warnings that started or ended, with the wall clock time, for the analysis export
+#[derive(Debug, Clone, Default)]
+pub struct WarningLog {
+    entries: Vec<(u64, WarningChange)>, // Unix time in milliseconds and the change
+}
+
+/// Implementation of the WarningLog
+impl WarningLog {
+    /// Creates an empty log
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the changes of one evaluation at the unix time in milliseconds
+    pub fn record(&mut self, unix_ms: u64, changes: &[WarningChange]) {
+        self.entries.extend(changes.iter().map(|change| (unix_ms, *change)));
+    }
+
+    /// Checks if nothing was logged
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Exports the log as CSV, one row per change. Every row carries the hashed client instance,
+    /// like the rows of the analysis samples.
+    pub fn to_csv(&self, instance: &str) -> String {
+        let mut csv = "instance,timestamp_ms,metric,event,value,threshold\n".to_string();
+        for (unix_ms, change) in &self.entries {
+            let (event, metric, value, threshold) = match change {
+                WarningChange::Raised { metric, value, threshold } => ("raised", metric, value, threshold),
+                WarningChange::Cleared { metric, value, threshold } => ("cleared", metric, value, threshold),
+            };
+            csv.push_str(&format!("{},{},{},{},{},{}\n", instance, unix_ms, metric.name(), event, value, threshold));
+        }
+        csv
+    }
+}
+
+/// Tests for the metric warnings
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function for values with only the round trip time measured
+    fn rtt(ms: f64) -> MetricValues {
+        MetricValues { rtt_ms: Some(ms), ..MetricValues::default() }
+    }
+
+    #[test]
+    fn test_warning_needs_consecutive_breaches() {
+        let mut engine = WarningEngine::new(WarningThresholds::default());
+
+        // Two breaches, a recovery and two more never reach three in a row
+        for ms in [300.0, 300.0, 100.0, 300.0, 300.0] {
+            assert!(engine.evaluate(&rtt(ms)).is_empty());
+        }
+        let changes = engine.evaluate(&rtt(320.0));
+        assert_eq!(changes, vec![WarningChange::Raised { metric: Metric::Rtt, value: 320.0, threshold: WARNING_RTT_MS }]);
+        assert_eq!(engine.active(), vec![ActiveWarning { metric: Metric::Rtt, value: 320.0, level: WarningLevel::Warning }]);
+
+        // Further breaches don't raise it again
+        assert!(engine.evaluate(&rtt(320.0)).is_empty());
+    }
+
+    #[test]
+    fn test_warning_needs_consecutive_recoveries() {
+        let mut engine = WarningEngine::new(WarningThresholds::default());
+        for _ in 0..WARNING_BREACH_EVALUATIONS {
+            engine.evaluate(&rtt(400.0));
+        }
+
+        // Four recoveries and a breach keep the warning, five recoveries in a row clear it
+        for ms in [100.0, 100.0, 100.0, 100.0, 400.0, 100.0, 100.0, 100.0, 100.0] {
+            assert!(engine.evaluate(&rtt(ms)).is_empty());
+            assert_eq!(engine.active().len(), 1);
+        }
+        let changes = engine.evaluate(&rtt(90.0));
+        assert_eq!(changes, vec![WarningChange::Cleared { metric: Metric::Rtt, value: 90.0, threshold: WARNING_RTT_MS }]);
+        assert!(engine.active().is_empty());
+    }
+
+    #[test]
+    fn test_missing_values_break_streaks() {
+        let mut engine = WarningEngine::new(WarningThresholds::default());
+        engine.evaluate(&rtt(300.0));
+        engine.evaluate(&rtt(300.0));
+        engine.evaluate(&MetricValues::default());
+        assert!(engine.evaluate(&rtt(300.0)).is_empty());
+
+        // An active warning stays while nothing is measured
+        engine.evaluate(&rtt(300.0));
+        engine.evaluate(&rtt(300.0));
+        assert_eq!(engine.active().len(), 1);
+        for _ in 0..10 {
+            assert!(engine.evaluate(&MetricValues::default()).is_empty());
+        }
+        assert_eq!(engine.active().len(), 1);
+
+        engine.reset();
+        assert!(engine.active().is_empty());
+    }
+
+    #[test]
+    fn test_metrics_are_independent_and_levels() {
+        let mut engine = WarningEngine::new(WarningThresholds { starvation: 0.2, ..WarningThresholds::default() });
+        let values = MetricValues { rtt_ms: Some(600.0), loss: Some(0.09), prediction_error_p95: Some(5.0), starvation: Some(0.15) };
+        for _ in 0..WARNING_BREACH_EVALUATIONS {
+            engine.evaluate(&values);
+        }
+
+        // Starvation is under its configured threshold, the round trip time is more than twice over it
+        let active = engine.active();
+        assert_eq!(active.iter().map(|warning| warning.metric).collect::<Vec<_>>(), vec![Metric::Rtt, Metric::Loss]);
+        assert_eq!(active[0].level, WarningLevel::Critical);
+        assert_eq!(active[1].level, WarningLevel::Warning);
+        assert_eq!(active[1].describe(&Strings::default()), "Packet loss: 9%");
+        assert_eq!(active[1].describe(&Strings::new(Language::Norwegian)), "Pakketap: 9%");
+    }
+
+    #[test]
+    fn test_thresholds_from_partial_settings() {
+        let thresholds: WarningThresholds = serde_json::from_str(r#"{ "rtt_ms": 150 }"#).unwrap();
+        assert_eq!(thresholds.limit(Metric::Rtt), 150.0);
+        assert_eq!(thresholds.limit(Metric::Loss), WARNING_LOSS);
+        assert_eq!(thresholds.limit(Metric::PredictionError), WARNING_PREDICTION_ERROR_P95);
+        assert_eq!(thresholds.limit(Metric::Starvation), WARNING_STARVATION);
+    }
+
+    #[test]
+    fn test_window_percentile_and_average() {
+        let mut window = MetricWindow::new();
+        for error in 1..=100 {
+            window.record_prediction_error(error as f32);
+        }
+        window.record_starvation(0.0);
+        window.record_starvation(0.5);
+
+        let values = window.take(Some(80.0), Some(0.01));
+        assert_eq!(values, MetricValues { rtt_ms: Some(80.0), loss: Some(0.01), prediction_error_p95: Some(95.0), starvation: Some(0.25) });
+
+        // The window starts over
+        assert_eq!(window.take(None, None), MetricValues::default());
+        assert_eq!(percentile(&mut [3.0], 0.95), Some(3.0));
+    }
+
+    #[test]
+    fn test_log_csv() {
+        let mut log = WarningLog::new();
+        assert!(log.is_empty());
+        log.record(1_000, &[WarningChange::Raised { metric: Metric::Loss, value: 0.1, threshold: 0.08 }]);
+        log.record(9_000, &[WarningChange::Cleared { metric: Metric::Loss, value: 0.02, threshold: 0.08 }]);
+        assert_eq!(log.to_csv("00ff"), "instance,timestamp_ms,metric,event,value,threshold\n00ff,1000,loss,raised,0.1,0.08\n00ff,9000,loss,cleared,0.02,0.08\n");
+    }
+}
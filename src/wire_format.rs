@@ -0,0 +1,369 @@
+//! Golden byte tests for everything sent over the network. Each case encodes a representative
+//! value and compares it with the bytes checked into tests/golden/, so a reordered field or an
+//! inserted enum variant fails a test instead of silently breaking older clients.
+//!
+//! After an intentional protocol change, regenerate the goldens and bump PROTOCOL_VERSION:
+//!
+//! ```text
+//! UPDATE_GOLDEN=1 cargo test --lib wire_format
+//! ```
+//!
+//! The hash test then prints the hash to record for the new version in GOLDEN_HASHES.
+//! Messages encode maps in iteration order, so every map in a golden message holds at most one entry.
+//! Snapshots sort their players by id and refer to them by index, so golden snapshots can hold several.
+
+use crate::build_info::BuildInfo;
+use crate::codec::{decode, encode, CodecKind};
+use crate::constants::{MAX_DATAGRAM_SIZE, PROTOCOL_VERSION};
+use crate::movement::MovementTunables;
+use crate::snapshot::{decode_snapshot, encode_snapshot};
+use crate::types::{AppliedImpulse, ClientMessage, Direction, DisconnectReason, GameState, InputTiming, MatchPhase, MovementStats, PlayerInput, PlayerShape, Position, RejectReason, RoomInfo, Welcome};
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+const UPDATE_ENV: &str = "UPDATE_GOLDEN"; // Set to 1 to write the goldens instead of checking them
+const SIZES_FILE: &str = "SIZES.md"; // Generated table of golden payload sizes
+const FULL_ROOM_PLAYERS: u128 = 32; // Players of the full room snapshot
+const FULL_ROOM_SNAPSHOT_BUDGET: usize = 5_000; // Bytes a binary full room snapshot may take, ids in every per player block would more than double it
+
+/// Hash of the golden set recorded for each protocol version, a changed golden set needs a new version
+const GOLDEN_HASHES: &[(u32, u64)] = &[
+    (1, 0x9b5dfc067759f21d),
+    (2, 0xb24538d27141f1b5),
+    (3, 0x4f0eee086f8bcc4a),
+    (4, 0x8ce305d82a81f97b),
+    (5, 0xcb7810e566bc177b),
+    (6, 0x189b981371e29248),
+    (7, 0x2f1e1a73157474c1),
+    (8, 0x2dfaef21894481ef),
+    (9, 0xbf3a27614e253b39),
+    (10, 0xbcdbbf51c8dc3494),
+    (11, 0xf0d298b715326012),
+    (12, 0x45e50ab476daeefb),
+    (13, 0xd41b59d517054b3b),
+    (14, 0x3c568e961f8f06af),
+    (15, 0xbf90931f81823da0),
+    (16, 0xa6737dce636127f4),
+    (17, 0x75cc7a423449ef53),
+    (18, 0x540ce47537f8c37b),
+    (19, 0x3015b3788a5bab0a),
+];
+
+/// Checks that golden bytes decode to the value of a case
+type DecodeCheck = Box<dyn Fn(&[u8]) -> bool>;
+
+/// One golden: the bytes the current code produces for a value, and a check that the golden decodes to it
+struct GoldenCase {
+    name: String,
+    bytes: Vec<u8>,
+    decodes_to_value: DecodeCheck,
+}
+
+// Helper function to create a golden case from a value and its encoding
+fn case<T: PartialEq + 'static>(name: &str, value: T, encode: fn(&T) -> Vec<u8>, decode: fn(&[u8]) -> Option<T>) -> GoldenCase {
+    GoldenCase {
+        name: name.to_string(),
+        bytes: encode(&value),
+        decodes_to_value: Box::new(move |data| decode(data).as_ref() == Some(&value)),
+    }
+}
+
+// Helper function to create a golden case for a value in the binary protocol, so the goldens pin its bincode configuration
+fn bincode_case<T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + 'static>(name: &str, value: T) -> GoldenCase {
+    case(name, value, encode, |data| decode(data).ok())
+}
+
+// Helper function for fixed ids, so the goldens don't change between runs
+fn id(n: u128) -> Uuid {
+    Uuid::from_u128(0x0123_4567_89ab_cdef_0000_0000_0000_0000 + n)
+}
+
+// Helper function to create a snapshot with every field set
+fn full_snapshot() -> GameState {
+    let player = id(1);
+    GameState {
+        players: vec![(player, Position { x: 120, y: 340 }, 0xff1717), (id(2), Position { x: 600, y: 80 }, 0x1717ff)],
+        last_processed: HashMap::from([(player, 512)]),
+        pellets: vec![Position { x: 30, y: 40 }, Position { x: 500, y: 300 }],
+        scores: HashMap::from([(player, 4)]),
+        best_scores: HashMap::from([(player, 9)]),
+        teams: HashMap::from([(player, 1)]),
+        input_reorders: HashMap::from([(player, 2)]),
+        shapes: HashMap::from([(player, PlayerShape::Triangle)]),
+        impulses: HashMap::from([(player, AppliedImpulse { dx: -3, dy: 6, after_sequence: 510, tick: 8_990 })]),
+        input_timings: HashMap::from([(player, InputTiming { sequence: 512, client_timestamp: 98_765, received_ms: 143_980, applied_ms: 144_000 })]),
+        match_phase: MatchPhase::Countdown { remaining_ms: 4200 },
+        server_timestamp: 123_456,
+        match_number: 3,
+        ping_ms: HashMap::from([(player, 35), (id(2), 80)]),
+        snapshot_id: 9_001,
+        tick_interval_ms: 16,
+        tagged: Some(id(2)),
+        movement_stats: HashMap::from([(player, MovementStats { distance: 12_345, inputs: 2_048, connected_ms: 61_000 })]),
+    }
+}
+
+// Helper function to create a snapshot of a full room, every player with every per player field set
+fn full_room_snapshot() -> GameState {
+    let mut state = empty_snapshot();
+    state.match_phase = MatchPhase::Active;
+    for n in 0..FULL_ROOM_PLAYERS {
+        let player = id(100 + n);
+        state.players.push((player, Position { x: 20 + 30 * n as i32, y: 300 }, 0x17ff17));
+        state.last_processed.insert(player, 4_096);
+        state.scores.insert(player, 12);
+        state.best_scores.insert(player, 40);
+        state.teams.insert(player, (n % 2) as u8);
+        state.input_reorders.insert(player, 3);
+        state.shapes.insert(player, PlayerShape::Circle);
+        state.impulses.insert(player, AppliedImpulse { dx: 3, dy: -3, after_sequence: 4_095, tick: 118 });
+        state.input_timings.insert(player, InputTiming { sequence: 4_096, client_timestamp: 81_920, received_ms: 1_900, applied_ms: 1_916 });
+        state.ping_ms.insert(player, 180);
+        state.movement_stats.insert(player, MovementStats { distance: 20_480, inputs: 4_096, connected_ms: 600_000 });
+    }
+    state
+}
+
+// Helper function to create a snapshot of an empty server, without the optional team, impulse, input timing, tagged and movement stats blocks
+fn empty_snapshot() -> GameState {
+    GameState {
+        players: Vec::new(),
+        last_processed: HashMap::new(),
+        pellets: Vec::new(),
+        scores: HashMap::new(),
+        best_scores: HashMap::new(),
+        teams: HashMap::new(),
+        input_reorders: HashMap::new(),
+        shapes: HashMap::new(),
+        impulses: HashMap::new(),
+        input_timings: HashMap::new(),
+        match_phase: MatchPhase::Warmup,
+        server_timestamp: 0,
+        match_number: 0,
+        ping_ms: HashMap::new(),
+        snapshot_id: 1,
+        tick_interval_ms: 16,
+        tagged: None,
+        movement_stats: HashMap::new(),
+    }
+}
+
+// Helper function to list a representative value of every message, snapshot and enum variant on the wire
+fn golden_cases() -> Vec<GoldenCase> {
+    let welcome = Welcome {
+        id: id(1),
+        session_token: id(100),
+        position: Position { x: 320, y: 240 },
+        last_processed: Some(77),
+        tunables_digest: 0xaac2_0380_cc26_735e,
+        tunables: Some(MovementTunables { board_width: 800, board_height: 600, toolbar_height: 40, player_size: 20, player_speed: 2.5 }),
+        build: Some(BuildInfo { version: "0.1.0".to_string(), git_hash: "0123456789ab".to_string(), profile: "release".to_string(), seed: Some(31) }),
+        color: 0xff3366cc,
+        shape: PlayerShape::Triangle,
+    };
+    let mut cases = vec![
+        bincode_case("message_connect", ClientMessage::Connect),
+        bincode_case("message_player_id", ClientMessage::PlayerId(id(1))),
+        bincode_case("message_ping", ClientMessage::Ping(1_700_000_000_123)),
+        bincode_case("message_pong", ClientMessage::Pong(1_700_000_000_123)),
+        bincode_case("message_match_reset", ClientMessage::MatchReset {
+            match_number: 4,
+            positions: vec![(id(1), Position { x: 50, y: 60 }), (id(2), Position { x: 700, y: 500 })],
+        }),
+        bincode_case("message_welcome", ClientMessage::Welcome(welcome.clone())),
+        bincode_case("message_welcome_new_session", ClientMessage::Welcome(Welcome { last_processed: None, ..welcome.clone() })),
+        bincode_case("message_welcome_without_tunables", ClientMessage::Welcome(Welcome { tunables: None, ..welcome.clone() })),
+        bincode_case("message_welcome_without_build", ClientMessage::Welcome(Welcome { build: None, ..welcome })),
+        bincode_case("message_reconnect", ClientMessage::Reconnect { session_token: id(100) }),
+        bincode_case("message_server_ping", ClientMessage::ServerPing(0xdead_beef)),
+        bincode_case("message_server_pong", ClientMessage::ServerPong(0xdead_beef)),
+        bincode_case("message_connect_with_profile", ClientMessage::ConnectWithProfile {
+            name: "Kari".to_string(),
+            shape: PlayerShape::Circle.to_wire(),
+            instance: 0x0123_4567_89ab_cdef,
+            room: "arena".to_string(),
+            password: Some("hunter2".to_string()),
+        }),
+        bincode_case("message_list_rooms", ClientMessage::ListRooms),
+        bincode_case("message_room_list", ClientMessage::RoomList(vec![
+            RoomInfo { name: "lobby".to_string(), players: 3, password_required: false },
+            RoomInfo { name: "vault".to_string(), players: 0, password_required: true },
+        ])),
+        bincode_case("message_connect_rejected_wrong_password", ClientMessage::ConnectRejected { reason: RejectReason::WrongPassword }),
+        bincode_case("message_connect_rejected_unknown_room", ClientMessage::ConnectRejected { reason: RejectReason::UnknownRoom }),
+        bincode_case("message_connect_rejected_too_many_rooms", ClientMessage::ConnectRejected { reason: RejectReason::TooManyRooms }),
+        bincode_case("message_connect_rejected_name_too_long", ClientMessage::ConnectRejected { reason: RejectReason::NameTooLong }),
+        bincode_case("message_connect_rejected_password_too_long", ClientMessage::ConnectRejected { reason: RejectReason::PasswordTooLong }),
+        bincode_case("message_input_batch", ClientMessage::InputBatch(vec![
+            PlayerInput { dir: Direction::Left, sequence: 42, timestamp: 1_700_000_000 },
+            PlayerInput { dir: Direction::Up, sequence: 43, timestamp: 1_700_000_033 },
+        ])),
+        bincode_case("message_with_session_input", ClientMessage::WithSession {
+            session_token: id(100),
+            message: Box::new(ClientMessage::Input(PlayerInput { dir: Direction::Right, sequence: 42, timestamp: 1_700_000_000 })),
+        }),
+        bincode_case("message_with_session_ping", ClientMessage::WithSession {
+            session_token: id(100),
+            message: Box::new(ClientMessage::Ping(1_700_000_000_123)),
+        }),
+        bincode_case("message_disconnected_timed_out", ClientMessage::Disconnected { id: id(1), reason: DisconnectReason::TimedOut }),
+        bincode_case("message_disconnected_kicked", ClientMessage::Disconnected { id: id(1), reason: DisconnectReason::Kicked }),
+        bincode_case("message_debug_set_position", ClientMessage::DebugSetPosition(Position { x: 400, y: 300 })),
+        bincode_case("message_leave", ClientMessage::Leave),
+        case("snapshot_full", full_snapshot(), encode_snapshot, decode_snapshot),
+        case("snapshot_empty", empty_snapshot(), encode_snapshot, decode_snapshot),
+        case("snapshot_full_room", full_room_snapshot(), encode_snapshot, decode_snapshot),
+    ];
+
+    // Every direction, so inserting a variant shows up
+    for (name, dir) in [("up", Direction::Up), ("down", Direction::Down), ("left", Direction::Left), ("right", Direction::Right)] {
+        let input = PlayerInput { dir, sequence: 42, timestamp: 1_700_000_000 };
+        cases.push(bincode_case(&format!("message_input_{}", name), ClientMessage::Input(input)));
+    }
+
+    // Every match phase, as found in the snapshot extension block
+    let phases = [
+        ("warmup", MatchPhase::Warmup),
+        ("countdown", MatchPhase::Countdown { remaining_ms: 3000 }),
+        ("active", MatchPhase::Active),
+        ("ended", MatchPhase::Ended),
+    ];
+    for (name, phase) in phases {
+        cases.push(bincode_case(&format!("match_phase_{}", name), phase));
+    }
+    cases
+}
+
+// Helper function to get the directory the goldens are checked into
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+// Helper function to check if the goldens should be written instead of checked
+fn updating() -> bool {
+    std::env::var(UPDATE_ENV).is_ok_and(|value| value == "1")
+}
+
+// Helper function to read every golden file, sorted by name
+fn read_goldens() -> Vec<(String, Vec<u8>)> {
+    let mut goldens: Vec<(String, Vec<u8>)> = std::fs::read_dir(golden_dir())
+        .expect("tests/golden is missing")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "bin"))
+        .map(|path| (path.file_stem().unwrap().to_string_lossy().into_owned(), std::fs::read(&path).unwrap()))
+        .collect();
+    goldens.sort();
+    goldens
+}
+
+// Helper function to hash the golden set with FNV-1a, which unlike the std hasher is stable across releases
+fn hash_goldens(goldens: &[(String, Vec<u8>)]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (name, bytes) in goldens {
+        let length = (bytes.len() as u64).to_le_bytes();
+        for byte in name.as_bytes().iter().chain(&length).chain(bytes) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+// Helper function to list the payload size of every golden, checked in as documentation
+fn sizes_table(cases: &[GoldenCase]) -> String {
+    let mut table = format!("# Wire format payload sizes (protocol version {})\n\n", PROTOCOL_VERSION);
+    table.push_str("Generated by the golden tests in src/wire_format.rs, do not edit.\n\n");
+    table.push_str("| Golden | Bytes |\n|---|---|\n");
+    let mut rows: Vec<(&str, usize)> = cases.iter().map(|case| (case.name.as_str(), case.bytes.len())).collect();
+    rows.sort();
+    for (name, size) in rows {
+        table.push_str(&format!("| {} | {} |\n", name, size));
+    }
+    table
+}
+
+/// Tests for the wire format
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings_match_goldens() {
+        for case in golden_cases() {
+            let path = golden_dir().join(format!("{}.bin", case.name));
+            if updating() {
+                std::fs::write(&path, &case.bytes).unwrap();
+                continue;
+            }
+            let golden = std::fs::read(&path)
+                .unwrap_or_else(|_| panic!("{} is missing, run with {}=1 to create it", path.display(), UPDATE_ENV));
+            assert_eq!(case.bytes, golden, "{} encodes differently than its golden", case.name);
+            assert!((case.decodes_to_value)(&golden), "{} golden doesn't decode to the value", case.name);
+        }
+    }
+
+    #[test]
+    fn test_golden_set_matches_cases() {
+        let cases = golden_cases();
+        let sizes_path = golden_dir().join(SIZES_FILE);
+        if updating() {
+            std::fs::write(&sizes_path, sizes_table(&cases)).unwrap();
+            return;
+        }
+
+        // No stale goldens are left behind and no case lacks one
+        let mut names: Vec<String> = cases.iter().map(|case| case.name.clone()).collect();
+        names.sort();
+        let goldens: Vec<String> = read_goldens().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(goldens, names);
+
+        let sizes = std::fs::read_to_string(&sizes_path).unwrap_or_default();
+        assert_eq!(sizes, sizes_table(&cases), "{} is out of date, run with {}=1", SIZES_FILE, UPDATE_ENV);
+    }
+
+    #[test]
+    fn test_golden_hash_is_recorded_for_protocol_version() {
+        if updating() {
+            return;
+        }
+        let hash = hash_goldens(&read_goldens());
+        let recorded = GOLDEN_HASHES.iter().find(|(version, _)| *version == PROTOCOL_VERSION).map(|(_, hash)| *hash);
+        assert_eq!(
+            recorded,
+            Some(hash),
+            "the goldens changed: bump PROTOCOL_VERSION and record ({}, {:#018x}) in GOLDEN_HASHES",
+            PROTOCOL_VERSION + u32::from(recorded.is_some()),
+            hash,
+        );
+
+        // Versions only go up, and each one changed the golden set
+        for pair in GOLDEN_HASHES.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "{:?} is out of order", pair);
+            assert_ne!(pair[0].1, pair[1].1, "{:?} didn't change the goldens", pair);
+        }
+    }
+
+    #[test]
+    fn test_full_room_snapshot_fits_the_client_buffer() {
+        // Per player blocks refer to players by index, a block keyed by ids would blow the budget
+        let binary = encode_snapshot(&full_room_snapshot());
+        assert!(binary.len() <= FULL_ROOM_SNAPSHOT_BUDGET, "{} bytes for {} players", binary.len(), FULL_ROOM_PLAYERS);
+
+        // JSON repeats the ids in every map, but still fits the datagram the client reads
+        let json = CodecKind::Json.codec().encode_snapshot(&full_room_snapshot());
+        assert!(json.len() <= MAX_DATAGRAM_SIZE, "{} bytes of JSON for {} players", json.len(), FULL_ROOM_PLAYERS);
+    }
+
+    #[test]
+    fn test_golden_hash_changes_with_any_byte() {
+        let goldens = vec![("a".to_string(), vec![1, 2, 3])];
+        let changed_byte = vec![("a".to_string(), vec![1, 2, 4])];
+        let renamed = vec![("b".to_string(), vec![1, 2, 3])];
+        let moved_boundary = vec![("a".to_string(), vec![1, 2]), ("b".to_string(), vec![3])];
+        let hash = hash_goldens(&goldens);
+        assert_ne!(hash, hash_goldens(&changed_byte));
+        assert_ne!(hash, hash_goldens(&renamed));
+        assert_ne!(hash, hash_goldens(&moved_boundary));
+    }
+}